@@ -0,0 +1,183 @@
+//! Conversion of a resolved [`Config`] to the settings of a
+//! [`russh::client`] session.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use russh::client::known_hosts::{KnownHosts, KnownHostsVerifier, UnknownHostPolicy};
+use russh::client::proxy::jump::{parse_hops, Hop};
+use russh::client::{self, Handle, Handler};
+use russh::config_file::algorithm_list;
+use russh::keys::Algorithm;
+use russh::{cipher, compression, kex, mac, Preferred};
+
+use crate::{Config, Error};
+
+impl Config {
+    /// A [`client::Config`] with the keepalive, algorithm and compression
+    /// settings of this host.
+    pub fn client_config(&self) -> Result<client::Config, Error> {
+        let mut preferred = Preferred::default();
+        if let Some(ref spec) = self.kex_algorithms {
+            let mut kex = algorithm_list(&preferred.kex, spec, |n| kex::Name::try_from(n).ok())?;
+            // The extension pseudo-algorithms are not part of the user's
+            // list, but must still be advertised.
+            for ext in [
+                kex::EXTENSION_SUPPORT_AS_CLIENT,
+                kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT,
+            ] {
+                if !kex.contains(&ext) {
+                    kex.push(ext)
+                }
+            }
+            preferred.kex = Cow::Owned(kex);
+        }
+        if let Some(ref spec) = self.host_key_algorithms {
+            preferred.key = Cow::Owned(algorithm_list(&preferred.key, spec, |n| {
+                Algorithm::new(n).ok()
+            })?);
+        }
+        if let Some(ref spec) = self.ciphers {
+            preferred.cipher = Cow::Owned(algorithm_list(&preferred.cipher, spec, |n| {
+                cipher::Name::try_from(n).ok()
+            })?);
+        }
+        if let Some(ref spec) = self.macs {
+            preferred.mac = Cow::Owned(algorithm_list(&preferred.mac, spec, |n| {
+                mac::Name::try_from(n).ok()
+            })?);
+        }
+        if self.compression {
+            // Compression is preferred, but "none" stays acceptable.
+            let mut compression = preferred.compression.to_vec();
+            compression.retain(|c| *c != compression::NONE);
+            compression.push(compression::NONE);
+            preferred.compression = Cow::Owned(compression);
+        }
+        Ok(client::Config {
+            keepalive_interval: self.server_alive_interval,
+            keepalive_max: self.server_alive_count_max,
+            preferred,
+            ..Default::default()
+        })
+    }
+
+    /// The hops of `ProxyJump`, in order. Empty if there is none.
+    pub fn proxy_jump_hops(&self) -> Result<Vec<Hop>, Error> {
+        match self.proxy_jump {
+            Some(ref spec) => {
+                let spec = spec
+                    .split(',')
+                    .map(|hop| hop.trim().trim_start_matches("ssh://"))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                Ok(parse_hops(&spec)?)
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The `UserKnownHostsFile`, or the default one, checked according to
+    /// `StrictHostKeyChecking` and `HashKnownHosts`.
+    pub fn known_hosts_verifier(&self) -> Result<KnownHostsVerifier, Error> {
+        let known_hosts = match self.user_known_hosts_file {
+            Some(ref path) => KnownHosts::from_path(path)?,
+            None => KnownHosts::from_default_path()?,
+        };
+        let policy = if self.strict_host_key_checking {
+            UnknownHostPolicy::Reject
+        } else {
+            UnknownHostPolicy::AcceptNew
+        };
+        Ok(
+            KnownHostsVerifier::new(known_hosts, self.host_name.as_str(), self.port)
+                .policy(policy)
+                .hash_hostnames(self.hash_known_hosts),
+        )
+    }
+
+    /// Connect to the host with [`Config::stream`], within
+    /// `ConnectTimeout`. Hosts with a `ProxyJump` must be connected to
+    /// with [`JumpHost`](russh::client::proxy::jump::JumpHost), using
+    /// [`Config::proxy_jump_hops`], since each hop needs to be
+    /// authenticated.
+    pub async fn connect<H: Handler + Send + 'static>(
+        &self,
+        config: Arc<client::Config>,
+        handler: H,
+    ) -> Result<Handle<H>, H::Error> {
+        if self.proxy_jump.is_some() {
+            return Err(russh::Error::InvalidConfig(format!(
+                "{}: ProxyJump must be set up with russh::client::proxy::jump",
+                self.host
+            ))
+            .into());
+        }
+        let connecting = async {
+            let stream = self.stream().await.map_err(russh::Error::from)?;
+            client::connect_stream(config, stream, handler).await
+        };
+        match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connecting)
+                .await
+                .map_err(|_| russh::Error::ConnectionTimeout)?,
+            None => connecting.await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::expect_used)]
+    use std::time::Duration;
+
+    use russh::client::proxy::jump::Hop;
+    use russh::{cipher, kex, mac, Preferred};
+
+    use crate::parse;
+    use crate::tests::CONFIG;
+
+    #[test]
+    fn client_config() {
+        let db = parse(CONFIG, "db.internal").expect("parse");
+        let client = db.client_config().expect("client_config");
+        assert_eq!(Some(Duration::from_secs(15)), client.keepalive_interval);
+        assert_eq!(5, client.keepalive_max);
+        assert_eq!(Some(&cipher::AES_128_CTR), client.preferred.cipher.first());
+        assert_eq!(
+            Preferred::DEFAULT.cipher.len(),
+            client.preferred.cipher.len()
+        );
+        assert!(!client.preferred.mac.contains(&mac::HMAC_SHA1));
+        assert!(!client.preferred.mac.contains(&mac::HMAC_SHA1_ETM));
+
+        let host = parse("KexAlgorithms curve25519-sha256,unknown\n", "x").expect("parse");
+        let kex = host.client_config().expect("client_config").preferred.kex;
+        assert_eq!(
+            &[
+                kex::CURVE25519,
+                kex::EXTENSION_SUPPORT_AS_CLIENT,
+                kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT
+            ],
+            &kex[..]
+        );
+
+        let host = parse("Ciphers unknown\n", "x").expect("parse");
+        assert!(host.client_config().is_err());
+    }
+
+    #[test]
+    fn proxy_jump_hops() {
+        let web = parse(CONFIG, "web.internal").expect("parse");
+        assert_eq!(
+            vec![
+                Hop {
+                    user: Some("admin".into()),
+                    ..Hop::new("bastion", 2222)
+                },
+                Hop::new("other", 22),
+            ],
+            web.proxy_jump_hops().expect("proxy_jump_hops")
+        );
+    }
+}
@@ -4,12 +4,38 @@
     clippy::indexing_slicing,
     clippy::panic
 )]
+//! Parsing of OpenSSH `ssh_config` files.
+//!
+//! [`parse`] computes the settings for a host alias the way `ssh(1)`
+//! does: `Host` and `Match` blocks apply in order, `Include` directives
+//! are followed, and the first value obtained for each option wins. The
+//! resulting [`Config`] converts to a [`russh::client::Config`] with
+//! [`Config::client_config`]:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # async fn run() -> Result<(), russh::Error> {
+//! let host = russh_config::parse_home("myalias")?;
+//! let config = Arc::new(host.client_config()?);
+//! let verifier = host.known_hosts_verifier()?;
+//! let session = host.connect(config, verifier).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `Match exec` is not supported and never matches; `Match canonical`
+//! and `Match final` always match since host names are not
+//! canonicalized.
+
+use std::collections::HashSet;
 use std::io::Read;
 use std::net::ToSocketAddrs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use globset::Glob;
-use log::debug;
+use log::{debug, warn};
+use russh::config_file::{match_patterns, parse_bool, split_args, split_line, MAX_INCLUDE_DEPTH};
 use thiserror::*;
 
 #[derive(Debug, Error)]
@@ -21,38 +47,85 @@ pub enum Error {
     NoHome,
     #[error("Cannot resolve the address")]
     NotResolvable,
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
     #[error("{}", 0)]
     Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Keys(#[from] russh::keys::Error),
+    #[error(transparent)]
+    Russh(#[from] russh::Error),
+}
+
+impl From<Error> for russh::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => russh::Error::IO(e),
+            Error::NoHome => russh::Error::NoHomeDir,
+            Error::Keys(e) => russh::Error::Keys(e),
+            Error::Russh(e) => e,
+            e => russh::Error::InvalidConfig(e.to_string()),
+        }
+    }
 }
 
+mod client;
 mod proxy;
 pub use proxy::*;
 
 #[derive(Debug)]
 pub struct Config {
+    /// The alias this was resolved for.
+    pub host: String,
     pub user: String,
     pub host_name: String,
     pub port: u16,
     pub identity_file: Option<String>,
+    pub identities_only: bool,
     pub proxy_command: Option<String>,
     pub proxy_jump: Option<String>,
     pub add_keys_to_agent: AddKeysToAgent,
+    pub forward_agent: bool,
     pub user_known_hosts_file: Option<String>,
     pub strict_host_key_checking: bool,
+    pub hash_known_hosts: bool,
+    pub connect_timeout: Option<Duration>,
+    pub server_alive_interval: Option<Duration>,
+    pub server_alive_count_max: usize,
+    pub compression: bool,
+    /// `Ciphers`, `MACs`, `KexAlgorithms` and `HostKeyAlgorithms`, as
+    /// written in the file. They are applied by
+    /// [`Config::client_config`].
+    pub ciphers: Option<String>,
+    pub macs: Option<String>,
+    pub kex_algorithms: Option<String>,
+    pub host_key_algorithms: Option<String>,
 }
 
 impl Config {
     pub fn default(host_name: &str) -> Self {
         Config {
+            host: host_name.to_string(),
             user: whoami::username(),
             host_name: host_name.to_string(),
             port: 22,
             identity_file: None,
+            identities_only: false,
             proxy_command: None,
             proxy_jump: None,
             add_keys_to_agent: AddKeysToAgent::default(),
+            forward_agent: false,
             user_known_hosts_file: None,
             strict_host_key_checking: true,
+            hash_known_hosts: false,
+            connect_timeout: None,
+            server_alive_interval: None,
+            server_alive_count_max: 3,
+            compression: false,
+            ciphers: None,
+            macs: None,
+            kex_algorithms: None,
+            host_key_algorithms: None,
         }
     }
 }
@@ -63,20 +136,37 @@ impl Config {
     // can be employed late/lazy eg just before establishing a stream using ProxyCommand
     // but also can be used to modify Hostname as config parse time
     fn expand_tokens(&self, original: &str) -> String {
-        let mut string = original.to_string();
-        string = string.replace("%u", &self.user);
-        string = string.replace("%h", &self.host_name); // remote hostname (from context "host")
-        string = string.replace("%H", &self.host_name); // remote hostname (from context "host")
-        string = string.replace("%p", &format!("{}", self.port)); // original typed hostname (from context "host")
-        string = string.replace("%%", "%");
+        let mut string = String::with_capacity(original.len());
+        let mut chars = original.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                string.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('%') => string.push('%'),
+                Some('h') | Some('H') => string.push_str(&self.host_name), // remote hostname
+                Some('n') => string.push_str(&self.host), // original typed hostname
+                Some('p') => string.push_str(&self.port.to_string()),
+                Some('r') | Some('u') => string.push_str(&self.user),
+                Some('d') => {
+                    if let Some(home) = home::home_dir() {
+                        string.push_str(&home.to_string_lossy())
+                    }
+                }
+                Some(other) => {
+                    string.push('%');
+                    string.push(other);
+                }
+                None => string.push('%'),
+            }
+        }
         string
     }
 
     pub async fn stream(&self) -> Result<Stream, Error> {
         if let Some(ref proxy_command) = self.proxy_command {
-            let proxy_command = self.expand_tokens(proxy_command);
-            let cmd: Vec<&str> = proxy_command.split(' ').collect();
-            Stream::proxy_command(cmd.first().unwrap_or(&""), cmd.get(1..).unwrap_or(&[]))
+            Stream::proxy_command_shell(&self.expand_tokens(proxy_command))
                 .await
                 .map_err(Into::into)
         } else {
@@ -87,6 +177,80 @@ impl Config {
             Stream::tcp_connect(&address).await.map_err(Into::into)
         }
     }
+
+    fn apply(&mut self, directive: &Directive, seen: &mut HashSet<String>) -> Result<(), Error> {
+        // The first obtained value wins.
+        if !seen.insert(directive.keyword.clone()) {
+            return Ok(());
+        }
+        let invalid = || {
+            Error::InvalidConfig(format!(
+                "invalid {} at line {}: {:?}",
+                directive.keyword, directive.line, directive.value
+            ))
+        };
+        let value = directive.value.as_str();
+        let first = directive.args.first().ok_or_else(invalid)?.as_str();
+        let none = first.eq_ignore_ascii_case("none");
+        match directive.keyword.as_str() {
+            "user" => self.user = first.to_string(),
+            "hostname" => self.host_name = self.expand_tokens(first),
+            "port" => self.port = first.parse().map_err(|_| invalid())?,
+            "identityfile" => self.identity_file = Some(value.strip_quotes().to_string()),
+            "identitiesonly" => self.identities_only = parse_bool(first).ok_or_else(invalid)?,
+            "proxycommand" => self.proxy_command = (!none).then(|| value.to_string()),
+            "proxyjump" => self.proxy_jump = (!none).then(|| first.to_string()),
+            "addkeystoagent" => match first.to_lowercase().as_str() {
+                "yes" => self.add_keys_to_agent = AddKeysToAgent::Yes,
+                "confirm" => self.add_keys_to_agent = AddKeysToAgent::Confirm,
+                "ask" => self.add_keys_to_agent = AddKeysToAgent::Ask,
+                _ => self.add_keys_to_agent = AddKeysToAgent::No,
+            },
+            "forwardagent" => self.forward_agent = parse_bool(first).ok_or_else(invalid)?,
+            "userknownhostsfile" => {
+                if !none {
+                    self.user_known_hosts_file = Some(first.strip_quotes().to_string())
+                }
+            }
+            "stricthostkeychecking" => match first.to_lowercase().as_str() {
+                "no" => self.strict_host_key_checking = false,
+                _ => self.strict_host_key_checking = true,
+            },
+            "hashknownhosts" => self.hash_known_hosts = parse_bool(first).ok_or_else(invalid)?,
+            "connecttimeout" => {
+                let seconds: u64 = first.parse().map_err(|_| invalid())?;
+                self.connect_timeout = (seconds > 0).then(|| Duration::from_secs(seconds));
+            }
+            "serveraliveinterval" => {
+                let seconds: u64 = first.parse().map_err(|_| invalid())?;
+                self.server_alive_interval = (seconds > 0).then(|| Duration::from_secs(seconds));
+            }
+            "serveralivecountmax" => {
+                self.server_alive_count_max = first.parse().map_err(|_| invalid())?
+            }
+            "compression" => self.compression = parse_bool(first).ok_or_else(invalid)?,
+            "ciphers" => self.ciphers = Some(first.to_string()),
+            "macs" => self.macs = Some(first.to_string()),
+            "kexalgorithms" => self.kex_algorithms = Some(first.to_string()),
+            "hostkeyalgorithms" => self.host_key_algorithms = Some(first.to_string()),
+            key => {
+                debug!("{:?}", key);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Self, Error> {
+        if let Some(ref identity_file) = self.identity_file {
+            self.identity_file = Some(self.expand_tokens(identity_file).as_str().expand_home()?);
+        }
+        if let Some(ref known_hosts) = self.user_known_hosts_file {
+            self.user_known_hosts_file =
+                Some(self.expand_tokens(known_hosts).as_str().expand_home()?);
+        }
+        self.proxy_jump = self.proxy_jump.as_ref().map(|j| self.expand_tokens(j));
+        Ok(self)
+    }
 }
 
 pub fn parse_home(host: &str) -> Result<Config, Error> {
@@ -116,59 +280,193 @@ pub enum AddKeysToAgent {
     No,
 }
 
+#[derive(Debug, Clone)]
+enum Criterion {
+    All,
+    Canonical,
+    Final,
+    Exec(String),
+    Host(String),
+    OriginalHost(String),
+    User(String),
+    LocalUser(String),
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Host(String),
+    Match(Vec<(bool, Criterion)>),
+}
+
+#[derive(Debug, Clone)]
+struct Directive {
+    line: usize,
+    keyword: String,
+    /// The arguments, split on whitespace outside of double quotes.
+    args: Vec<String>,
+    /// The whole line after the keyword, for the options taking a
+    /// command line.
+    value: String,
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    /// All of these must hold for the block to apply. There is more than
+    /// one condition for blocks included from inside another block.
+    conditions: Vec<Condition>,
+    directives: Vec<Directive>,
+}
+
+/// Compute the settings of `host` from the contents of a configuration
+/// file. Relative `Include` paths are looked up in `~/.ssh`.
 pub fn parse(file: &str, host: &str) -> Result<Config, Error> {
+    let mut blocks = Vec::new();
+    parse_blocks(file, &[], 0, &mut blocks)?;
     let mut config = Config::default(host);
-    let mut matches_current = false;
-    for line in file.lines() {
-        let tokens = line.trim().splitn(2, ' ').collect::<Vec<&str>>();
-        if tokens.len() == 2 {
-            let (key, value) = (tokens.first().unwrap_or(&""), tokens.get(1).unwrap_or(&""));
-            let lower = key.to_lowercase();
-            if lower.as_str() == "host" {
-                matches_current = value
-                    .split_whitespace()
-                    .any(|x| check_host_against_glob_pattern(host, x));
-            }
-            if matches_current {
-                match lower.as_str() {
-                    "user" => {
-                        config.user.clear();
-                        config.user.push_str(value.trim_start());
-                    }
-                    "hostname" => config.host_name = config.expand_tokens(value.trim_start()),
-                    "port" => {
-                        if let Ok(port) = value.trim_start().parse() {
-                            config.port = port
-                        }
-                    }
-                    "identityfile" => {
-                        config.identity_file =
-                            Some(value.trim_start().strip_quotes().expand_home()?);
-                    }
-                    "proxycommand" => config.proxy_command = Some(value.trim_start().to_string()),
-                    "proxyjump" => config.proxy_jump = Some(value.trim_start().to_string()),
-                    "addkeystoagent" => match value.to_lowercase().as_str() {
-                        "yes" => config.add_keys_to_agent = AddKeysToAgent::Yes,
-                        "confirm" => config.add_keys_to_agent = AddKeysToAgent::Confirm,
-                        "ask" => config.add_keys_to_agent = AddKeysToAgent::Ask,
-                        _ => config.add_keys_to_agent = AddKeysToAgent::No,
-                    },
-                    "userknownhostsfile" => {
-                        config.user_known_hosts_file =
-                            Some(value.trim_start().strip_quotes().expand_home()?);
+    let mut seen = HashSet::new();
+    for block in &blocks {
+        if !block.conditions.iter().all(|c| c.matches(&config)) {
+            continue;
+        }
+        for directive in &block.directives {
+            config.apply(directive, &mut seen)?;
+        }
+    }
+    config.finish()
+}
+
+fn parse_blocks(
+    file: &str,
+    outer: &[Condition],
+    depth: usize,
+    blocks: &mut Vec<Block>,
+) -> Result<(), Error> {
+    let mut block = Block {
+        conditions: outer.to_vec(),
+        directives: Vec::new(),
+    };
+    for (n, line) in file.lines().enumerate() {
+        let line_number = n + 1;
+        let Some((keyword, value)) = split_line(line) else {
+            continue;
+        };
+        let args = split_args(value);
+        let invalid =
+            |what: &str| Error::InvalidConfig(format!("invalid {what} at line {line_number}"));
+        match keyword.as_str() {
+            "host" | "match" => {
+                let condition = if keyword == "host" {
+                    if args.is_empty() {
+                        return Err(invalid("Host"));
                     }
-                    "stricthostkeychecking" => match value.to_lowercase().as_str() {
-                        "no" => config.strict_host_key_checking = false,
-                        _ => config.strict_host_key_checking = true,
-                    },
-                    key => {
-                        debug!("{:?}", key);
+                    Condition::Host(args.join(","))
+                } else {
+                    Condition::Match(parse_match(&args).ok_or_else(|| invalid("Match"))?)
+                };
+                blocks.push(block);
+                let mut conditions = outer.to_vec();
+                conditions.push(condition);
+                block = Block {
+                    conditions,
+                    directives: Vec::new(),
+                };
+            }
+            "include" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(invalid("Include (too deeply nested)"));
+                }
+                // The included blocks only apply where this one does.
+                let conditions = block.conditions.clone();
+                blocks.push(block);
+                for path in &args {
+                    for path in include_paths(path)? {
+                        debug!("including {path:?}");
+                        let contents = std::fs::read_to_string(&path)?;
+                        parse_blocks(&contents, &conditions, depth + 1, blocks)?;
                     }
                 }
+                block = Block {
+                    conditions,
+                    directives: Vec::new(),
+                };
+            }
+            _ => block.directives.push(Directive {
+                line: line_number,
+                keyword,
+                args,
+                value: value.to_string(),
+            }),
+        }
+    }
+    blocks.push(block);
+    Ok(())
+}
+
+impl Condition {
+    fn matches(&self, config: &Config) -> bool {
+        match self {
+            Condition::Host(patterns) => match_globs(&config.host, patterns),
+            Condition::Match(criteria) => criteria
+                .iter()
+                .all(|(negated, criterion)| criterion.matches(config) != *negated),
+        }
+    }
+}
+
+impl Criterion {
+    fn matches(&self, config: &Config) -> bool {
+        match self {
+            Criterion::All | Criterion::Canonical | Criterion::Final => true,
+            Criterion::Exec(command) => {
+                warn!("Match exec {command:?} is not supported");
+                false
             }
+            Criterion::Host(patterns) => match_globs(&config.host_name, patterns),
+            Criterion::OriginalHost(patterns) => match_globs(&config.host, patterns),
+            Criterion::User(patterns) => match_globs(&config.user, patterns),
+            Criterion::LocalUser(patterns) => match_globs(&whoami::username(), patterns),
         }
     }
-    Ok(config)
+}
+
+fn parse_match(args: &[String]) -> Option<Vec<(bool, Criterion)>> {
+    let mut criteria = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let lower = arg.to_lowercase();
+        let (negated, name) = match lower.strip_prefix('!') {
+            Some(name) => (true, name),
+            None => (false, lower.as_str()),
+        };
+        let criterion = match name {
+            "all" => Criterion::All,
+            "canonical" => Criterion::Canonical,
+            "final" => Criterion::Final,
+            _ => {
+                let value = args.next()?.clone();
+                match name {
+                    "exec" => Criterion::Exec(value),
+                    "host" => Criterion::Host(value),
+                    "originalhost" => Criterion::OriginalHost(value),
+                    "user" => Criterion::User(value),
+                    "localuser" => Criterion::LocalUser(value),
+                    _ => return None,
+                }
+            }
+        };
+        criteria.push((negated, criterion));
+    }
+    if criteria.is_empty() {
+        return None;
+    }
+    Some(criteria)
+}
+
+/// [`match_patterns`] with the glob patterns of `ssh_config`.
+fn match_globs(candidate: &str, patterns: &str) -> bool {
+    match_patterns(patterns, |pattern| {
+        check_host_against_glob_pattern(candidate, pattern)
+    })
 }
 
 fn check_host_against_glob_pattern(candidate: &str, glob_pattern: &str) -> bool {
@@ -178,6 +476,40 @@ fn check_host_against_glob_pattern(candidate: &str, glob_pattern: &str) -> bool
     }
 }
 
+/// The files named by an `Include` argument, sorted, when its file name
+/// contains wildcards.
+fn include_paths(path: &str) -> Result<Vec<PathBuf>, Error> {
+    let path = PathBuf::from(path.expand_home()?);
+    let path = if path.is_relative() {
+        home::home_dir()
+            .ok_or(Error::NoHome)?
+            .join(".ssh")
+            .join(path)
+    } else {
+        path
+    };
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(vec![path]);
+    };
+    let name = name.to_string_lossy();
+    if !name.contains(['*', '?']) {
+        return Ok(vec![path]);
+    }
+    // Only the file name may contain wildcards.
+    let matcher = Glob::new(&name)
+        .map_err(|e| Error::InvalidConfig(e.to_string()))?
+        .compile_matcher();
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if matcher.is_match(entry.file_name()) {
+            paths.push(entry.path())
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
 trait SshConfigStrExt {
     fn strip_quotes(&self) -> Self;
     fn expand_home(&self) -> Result<String, Error>;
@@ -221,6 +553,8 @@ impl SshConfigStrExt for &str {
 #[cfg(test)]
 mod tests {
     #![allow(clippy::expect_used)]
+    use std::time::Duration;
+
     use crate::{parse, AddKeysToAgent, Config, SshConfigStrExt};
 
     #[test]
@@ -302,4 +636,96 @@ Host test_host
         );
         assert!(!config.strict_host_key_checking);
     }
+
+    pub(crate) const CONFIG: &str = r#"
+# Global options are read first.
+User everyone
+Port 2200
+
+Host bastion
+    HostName bastion.example.com
+    Port 22
+
+Host *.internal !db.internal
+    ProxyJump ssh://admin@bastion:2222,other
+    IdentityFile ~/.ssh/id_%n
+    ForwardAgent yes
+
+Match originalhost db.internal user everyone
+    HostName 10.0.0.5
+    IdentityFile "/keys/db key"
+
+Match host 10.0.0.5
+    ServerAliveInterval 15
+    ServerAliveCountMax=5
+    Ciphers ^aes128-ctr
+    MACs -hmac-sha1,hmac-sha1-etm@openssh.com
+    ProxyCommand ssh -W %h:%p bastion
+
+Host *
+    User ignored
+    ForwardAgent no
+    ConnectTimeout 10
+"#;
+
+    #[test]
+    fn match_blocks() {
+        let home = home::home_dir().expect("homedir");
+
+        let bastion = parse(CONFIG, "bastion").expect("parse");
+        assert_eq!("bastion.example.com", bastion.host_name);
+        assert_eq!(2200, bastion.port);
+        assert_eq!("everyone", bastion.user);
+        assert!(!bastion.forward_agent);
+        assert_eq!(Some(Duration::from_secs(10)), bastion.connect_timeout);
+
+        let web = parse(CONFIG, "web.internal").expect("parse");
+        assert_eq!("web.internal", web.host_name);
+        assert!(web.forward_agent);
+        assert_eq!(
+            Some(home.join(".ssh/id_web.internal").to_str().expect("to_str")),
+            web.identity_file.as_deref()
+        );
+        assert_eq!(
+            Some("ssh://admin@bastion:2222,other"),
+            web.proxy_jump.as_deref()
+        );
+
+        let db = parse(CONFIG, "db.internal").expect("parse");
+        assert_eq!("10.0.0.5", db.host_name);
+        assert_eq!(None, db.proxy_jump);
+        assert_eq!(Some("/keys/db key"), db.identity_file.as_deref());
+        assert_eq!(Some(Duration::from_secs(15)), db.server_alive_interval);
+        assert_eq!(5, db.server_alive_count_max);
+        assert_eq!(Some("ssh -W %h:%p bastion"), db.proxy_command.as_deref());
+        assert_eq!(
+            "ssh -W 10.0.0.5:2200 bastion",
+            db.expand_tokens("ssh -W %h:%p bastion")
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse("Match\n", "x").is_err());
+        assert!(parse("Match host\n", "x").is_err());
+        assert!(parse("Host\n", "x").is_err());
+        assert!(parse("Port ssh\n", "x").is_err());
+    }
+
+    #[test]
+    fn include() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        std::fs::write(dir.path().join("10-a.conf"), "Port 1000\n").expect("write");
+        std::fs::write(dir.path().join("20-b.conf"), "Port 2000\nUser b\n").expect("write");
+        let config = format!(
+            "Host a\n  Include {}/*.conf\nUser outer\n",
+            dir.path().display()
+        );
+        let a = parse(&config, "a").expect("parse");
+        assert_eq!(1000, a.port);
+        assert_eq!("b", a.user);
+        let other = parse(&config, "other").expect("parse");
+        assert_eq!(22, other.port);
+        assert_eq!(whoami::username(), other.user);
+    }
 }
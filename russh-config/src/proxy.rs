@@ -21,13 +21,37 @@ impl Stream {
     pub async fn tcp_connect(addr: &SocketAddr) -> Result<Stream, std::io::Error> {
         Ok(Stream::Tcp(tokio::net::TcpStream::connect(addr).await?))
     }
-    /// Connect through a proxy command.
+    /// Connect through a proxy command. The command is killed when the
+    /// stream is dropped, and its standard error is inherited so that its
+    /// diagnostics reach the user.
     pub async fn proxy_command(cmd: &str, args: &[&str]) -> Result<Stream, std::io::Error> {
+        Self::spawn(Command::new(cmd).args(args))
+    }
+
+    /// Connect through a command line run by the system shell (`sh -c`
+    /// on Unix, `cmd /C` on Windows), as OpenSSH runs `ProxyCommand`.
+    pub async fn proxy_command_shell(command: &str) -> Result<Stream, std::io::Error> {
+        #[cfg(windows)]
+        let mut cmd = {
+            let mut cmd = Command::new("cmd");
+            cmd.arg("/C").arg(command);
+            cmd
+        };
+        #[cfg(not(windows))]
+        let mut cmd = {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(command);
+            cmd
+        };
+        Self::spawn(&mut cmd)
+    }
+
+    fn spawn(command: &mut Command) -> Result<Stream, std::io::Error> {
         Ok(Stream::Child(
-            Command::new(cmd)
+            command
                 .stdin(Stdio::piped())
                 .stdout(Stdio::piped())
-                .args(args)
+                .kill_on_drop(true)
                 .spawn()?,
         ))
     }
@@ -58,7 +82,7 @@ impl tokio::io::AsyncWrite for Stream {
         match *self {
             Stream::Child(ref mut c) => match c.stdin.as_mut() {
                 Some(ref mut stdin) => Pin::new(stdin).poll_write(cx, buf),
-                None => Poll::Ready(Ok(0)),
+                None => Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into())),
             },
             Stream::Tcp(ref mut t) => Pin::new(t).poll_write(cx, buf),
         }
@@ -90,3 +114,23 @@ impl tokio::io::AsyncWrite for Stream {
         }
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    #![allow(clippy::expect_used)]
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn proxy_command_shell() {
+        let mut stream = Stream::proxy_command_shell("cat | cat")
+            .await
+            .expect("spawn");
+        stream.write_all(b"SSH-2.0-test\r\n").await.expect("write");
+        stream.shutdown().await.expect("shutdown");
+        let mut output = Vec::new();
+        stream.read_to_end(&mut output).await.expect("read");
+        assert_eq!(b"SSH-2.0-test\r\n", &output[..]);
+    }
+}
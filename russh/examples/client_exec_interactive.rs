@@ -142,17 +142,11 @@ impl Session {
         let (w, h) = termion::terminal_size()?;
 
         // Request an interactive PTY from the server
-        channel
-            .request_pty(
-                false,
-                &env::var("TERM").unwrap_or("xterm".into()),
-                w as u32,
-                h as u32,
-                0,
-                0,
-                &[], // ideally you want to pass the actual terminal modes here
-            )
-            .await?;
+        // The local terminal is in raw mode, the remote one handles line editing
+        let request = PtyRequest::new(env::var("TERM").unwrap_or("xterm".into()))
+            .size(w as u32, h as u32)
+            .modes(TerminalModes::cooked());
+        channel.request_pty_with(false, &request).await?;
         channel.exec(true, command).await?;
 
         let code;
@@ -33,6 +33,8 @@ pub enum MethodKind {
     PublicKey,
     HostBased,
     KeyboardInteractive,
+    #[cfg(feature = "gssapi")]
+    GssapiWithMic,
 }
 
 impl From<&MethodKind> for &'static str {
@@ -43,6 +45,8 @@ impl From<&MethodKind> for &'static str {
             MethodKind::PublicKey => "publickey",
             MethodKind::HostBased => "hostbased",
             MethodKind::KeyboardInteractive => "keyboard-interactive",
+            #[cfg(feature = "gssapi")]
+            MethodKind::GssapiWithMic => "gssapi-with-mic",
         }
     }
 }
@@ -55,6 +59,8 @@ impl FromStr for MethodKind {
             "publickey" => Ok(MethodKind::PublicKey),
             "hostbased" => Ok(MethodKind::HostBased),
             "keyboard-interactive" => Ok(MethodKind::KeyboardInteractive),
+            #[cfg(feature = "gssapi")]
+            "gssapi-with-mic" => Ok(MethodKind::GssapiWithMic),
             _ => Err(()),
         }
     }
@@ -145,12 +151,41 @@ pub enum AuthResult {
         /// further authentication is required
         partial_success: bool,
     },
+    /// The password has expired and must be changed with
+    /// [`Handle::authenticate_password_change`](crate::client::Handle::authenticate_password_change).
+    PasswordChangeRequired {
+        /// The server's explanation, to be shown to the user.
+        prompt: String,
+    },
 }
 
 impl AuthResult {
     pub fn success(&self) -> bool {
         matches!(self, AuthResult::Success)
     }
+
+    /// The methods that can continue, if the attempt failed. Methods
+    /// unknown to russh are left out.
+    pub fn remaining_methods(&self) -> Option<&MethodSet> {
+        match self {
+            AuthResult::Failure {
+                remaining_methods, ..
+            } => Some(remaining_methods),
+            _ => None,
+        }
+    }
+
+    /// Whether the attempt succeeded, but the server requires more
+    /// authentication, with one of [`AuthResult::remaining_methods`].
+    pub fn partial_success(&self) -> bool {
+        matches!(
+            self,
+            AuthResult::Failure {
+                partial_success: true,
+                ..
+            }
+        )
+    }
 }
 
 #[cfg_attr(feature = "async-trait", async_trait::async_trait)]
@@ -171,6 +206,8 @@ pub enum AgentAuthError {
     Send(#[from] crate::SendError),
     #[error(transparent)]
     Key(#[from] crate::keys::Error),
+    #[error(transparent)]
+    Session(#[from] crate::Error),
 }
 
 #[cfg_attr(feature = "async-trait", async_trait::async_trait)]
@@ -200,6 +237,11 @@ pub enum Method {
     Password {
         password: String,
     },
+    /// A new password, in reply to a password change request.
+    PasswordChange {
+        old_password: String,
+        new_password: String,
+    },
     PublicKey {
         key: PrivateKeyWithHashAlg,
     },
@@ -211,10 +253,20 @@ pub enum Method {
         key: ssh_key::PublicKey,
         hash_alg: Option<HashAlg>,
     },
+    /// A certificate whose key is held by a [`Signer`].
+    FutureCertificate {
+        cert: Certificate,
+    },
     KeyboardInteractive {
         submethods: String,
     },
-    // Hostbased,
+    HostBased {
+        key: PrivateKeyWithHashAlg,
+        client_host: String,
+        client_user: String,
+    },
+    #[cfg(feature = "gssapi")]
+    GssapiWithMic(crate::client::gssapi::GssapiAuth),
 }
 
 #[doc(hidden)]
@@ -226,6 +278,15 @@ pub struct AuthRequest {
     pub current: Option<CurrentRequest>,
     #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
     pub rejection_count: usize,
+    /// The lists of methods of which the server requires one to be
+    /// completed, in order, or empty if any method is enough.
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub required_methods: Vec<Vec<MethodKind>>,
+    /// The methods completed so far by `completed_user`.
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub completed_methods: Vec<MethodKind>,
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub completed_user: String,
 }
 
 #[doc(hidden)]
@@ -242,7 +303,13 @@ pub enum CurrentRequest {
     KeyboardInteractive {
         #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
         submethods: String,
+        /// The number of prompts of the last info request of the server,
+        /// which the responses must answer.
+        #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+        prompts: usize,
     },
+    #[cfg(feature = "gssapi")]
+    GssapiWithMic(crate::server::gssapi::GssapiAcceptance),
 }
 
 impl AuthRequest {
@@ -253,14 +320,21 @@ impl AuthRequest {
                 partial_success: false,
                 current: Some(CurrentRequest::KeyboardInteractive {
                     submethods: submethods.to_string(),
+                    prompts: 0,
                 }),
                 rejection_count: 0,
+                required_methods: Vec::new(),
+                completed_methods: Vec::new(),
+                completed_user: String::new(),
             },
             _ => Self {
                 methods: MethodSet::all(),
                 partial_success: false,
                 current: None,
                 rejection_count: 0,
+                required_methods: Vec::new(),
+                completed_methods: Vec::new(),
+                completed_user: String::new(),
             },
         }
     }
@@ -6,6 +6,10 @@ pub use rx::ChannelRx;
 mod tx;
 pub use tx::ChannelTx;
 
+mod split;
+pub(crate) use split::split;
+pub use split::{ChannelOutput, ChannelStreams};
+
 /// An enum with the ability to hold either an owned [`Channel`]
 /// or a `&mut` ref to it.
 #[derive(Debug)]
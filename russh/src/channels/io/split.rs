@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use tokio::io::AsyncRead;
+
+use super::{ChannelMsg, ChannelTx};
+use crate::channels::ChannelReadHalf;
+use crate::{ChannelId, CryptoVec};
+
+const STDOUT: usize = 0;
+const STDERR: usize = 1;
+
+/// The standard streams of a channel, returned by
+/// [`Channel::split_streams`](crate::Channel::split_streams).
+pub struct ChannelStreams<S>
+where
+    S: From<(ChannelId, ChannelMsg)> + 'static,
+{
+    /// Reads [`ChannelMsg::Data`].
+    pub stdout: ChannelOutput<S>,
+    /// Reads [`ChannelMsg::ExtendedData`] with `ext == 1`.
+    pub stderr: ChannelOutput<S>,
+    /// Writes [`ChannelMsg::Data`]. Shutting it down sends EOF.
+    pub stdin: ChannelTx<S>,
+}
+
+/// One of the output streams of a channel, see [`ChannelStreams`].
+///
+/// Both streams are read from the same channel: data received for the
+/// other stream is buffered until it is read. Both streams end at EOF.
+/// Other messages (such as the exit status) are discarded. The channel
+/// is closed once both streams are dropped.
+pub struct ChannelOutput<S>
+where
+    S: From<(ChannelId, ChannelMsg)> + 'static,
+{
+    demux: Arc<Mutex<Demux<S>>>,
+    stream: usize,
+}
+
+struct Demux<S: From<(ChannelId, ChannelMsg)>> {
+    read_half: ChannelReadHalf,
+    sender: tokio::sync::mpsc::Sender<S>,
+    id: ChannelId,
+    buffers: [VecDeque<(CryptoVec, usize)>; 2],
+    wakers: [Option<Waker>; 2],
+    eof: bool,
+}
+
+pub(crate) fn split<S>(
+    read_half: ChannelReadHalf,
+    sender: tokio::sync::mpsc::Sender<S>,
+    id: ChannelId,
+    stdin: ChannelTx<S>,
+) -> ChannelStreams<S>
+where
+    S: From<(ChannelId, ChannelMsg)> + 'static,
+{
+    let demux = Arc::new(Mutex::new(Demux {
+        read_half,
+        sender,
+        id,
+        buffers: Default::default(),
+        wakers: Default::default(),
+        eof: false,
+    }));
+    ChannelStreams {
+        stdout: ChannelOutput {
+            demux: demux.clone(),
+            stream: STDOUT,
+        },
+        stderr: ChannelOutput {
+            demux,
+            stream: STDERR,
+        },
+        stdin,
+    }
+}
+
+impl<S: From<(ChannelId, ChannelMsg)>> Demux<S> {
+    fn push(&mut self, stream: usize, data: CryptoVec) {
+        if let Some(buffer) = self.buffers.get_mut(stream) {
+            buffer.push_back((data, 0));
+        }
+        self.wake(stream);
+    }
+
+    fn wake(&mut self, stream: usize) {
+        if let Some(waker) = self.wakers.get_mut(stream).and_then(Option::take) {
+            waker.wake();
+        }
+    }
+
+    /// Copy buffered data of `stream` into `buf`, returning `false` if
+    /// there was none.
+    fn read_buffered(&mut self, stream: usize, buf: &mut tokio::io::ReadBuf<'_>) -> bool {
+        let Some(buffer) = self.buffers.get_mut(stream) else {
+            return false;
+        };
+        let Some((data, idx)) = buffer.front_mut() else {
+            return false;
+        };
+        let readable = buf.remaining().min(data.len() - *idx);
+        // Clamped to maximum `buf.remaining()` and `data.len() - idx` with `.min`
+        #[allow(clippy::indexing_slicing)]
+        buf.put_slice(&data[*idx..*idx + readable]);
+        *idx += readable;
+        if *idx == data.len() {
+            buffer.pop_front();
+        }
+        true
+    }
+}
+
+impl<S> ChannelOutput<S>
+where
+    S: From<(ChannelId, ChannelMsg)>,
+{
+    fn lock(&self) -> MutexGuard<'_, Demux<S>> {
+        self.demux
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<S> AsyncRead for ChannelOutput<S>
+where
+    S: From<(ChannelId, ChannelMsg)>,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let stream = self.stream;
+        let other = 1 - stream;
+        let mut demux = self.lock();
+        loop {
+            if demux.read_buffered(stream, buf) {
+                // Only the last reader to poll the channel is woken up
+                // when a message arrives, let the other one register again.
+                demux.wake(other);
+                return Poll::Ready(Ok(()));
+            }
+            if demux.eof {
+                return Poll::Ready(Ok(()));
+            }
+            let msg = match demux.read_half.poll_recv(cx) {
+                Poll::Ready(msg) => msg,
+                Poll::Pending => {
+                    if let Some(waker) = demux.wakers.get_mut(stream) {
+                        *waker = Some(cx.waker().clone());
+                    }
+                    return Poll::Pending;
+                }
+            };
+            match msg {
+                Some(ChannelMsg::Data { data }) => demux.push(STDOUT, data),
+                Some(ChannelMsg::ExtendedData { data, ext: 1 }) => demux.push(STDERR, data),
+                Some(ChannelMsg::Eof) | None => {
+                    demux.eof = true;
+                    demux.read_half.receiver.close();
+                    demux.wake(other);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+impl<S: From<(ChannelId, ChannelMsg)>> Drop for Demux<S> {
+    fn drop(&mut self) {
+        let _ = self.sender.try_send((self.id, ChannelMsg::Close).into());
+    }
+}
@@ -14,6 +14,7 @@ use tokio::sync::mpsc::{self, OwnedPermit};
 use tokio::sync::{Mutex, Notify, OwnedMutexGuard};
 
 use super::ChannelMsg;
+use crate::channels::{Limiters, Throttle};
 use crate::{ChannelId, CryptoVec};
 
 type BoxedThreadsafeFuture<T> = Pin<Box<dyn Sync + Send + std::future::Future<Output = T>>>;
@@ -50,19 +51,21 @@ pub struct ChannelTx<S> {
     window_size_notication: WatchNotification,
     max_packet_size: u32,
     ext: Option<u32>,
+    throttle: Throttle,
 }
 
 impl<S> ChannelTx<S>
 where
     S: From<(ChannelId, ChannelMsg)> + 'static + Send,
 {
-    pub fn new(
+    pub(crate) fn new(
         sender: mpsc::Sender<S>,
         id: ChannelId,
         window_size: Arc<Mutex<u32>>,
         window_size_notification: Arc<Notify>,
         max_packet_size: u32,
         ext: Option<u32>,
+        limiters: Limiters,
     ) -> Self {
         Self {
             sender,
@@ -74,6 +77,7 @@ where
             window_size_fut: None,
             max_packet_size,
             ext,
+            throttle: Throttle::new(limiters),
         }
     }
 
@@ -169,11 +173,16 @@ where
         let send_fut = if let Some(x) = self.send_fut.as_mut() {
             x
         } else {
+            ready!(self.throttle.poll_ready(cx));
             let (msg, writable) = ready!(self.poll_mk_msg(cx, buf));
             self.activate(msg, writable.into())
         };
         let r = ready!(send_fut.as_mut().poll_unpin(cx));
-        Poll::Ready(self.handle_write_result(r))
+        let r = self.handle_write_result(r);
+        if let Ok(n) = r {
+            self.throttle.consume(n);
+        }
+        Poll::Ready(r)
     }
 
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
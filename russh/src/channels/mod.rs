@@ -1,10 +1,11 @@
 use std::sync::Arc;
+use std::task::{ready, Context, Poll};
 
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::sync::{Mutex, Notify};
 
-use crate::{ChannelId, ChannelOpenFailure, CryptoVec, Error, Pty, Sig};
+use crate::{ChannelId, ChannelOpenFailure, CryptoVec, Error, Pty, PtyRequest, Sig};
 
 pub mod io;
 
@@ -14,6 +15,12 @@ pub use channel_ref::ChannelRef;
 mod channel_stream;
 pub use channel_stream::ChannelStream;
 
+mod rate_limit;
+pub(crate) use rate_limit::{ConnectionLimiters, Limiters, Throttle};
+pub use rate_limit::{RateLimit, RateLimiter};
+
+pub mod tun;
+
 #[derive(Debug)]
 #[non_exhaustive]
 /// Possible messages that [Channel::wait] can receive.
@@ -56,6 +63,11 @@ pub enum ChannelMsg {
         signal: Sig,
     },
     /// (client only)
+    Break {
+        want_reply: bool,
+        length_ms: u32,
+    },
+    /// (client only)
     RequestSubsystem {
         want_reply: bool,
         name: String,
@@ -142,12 +154,35 @@ impl WindowSizeRef {
 /// Allows you to read from a channel without borrowing the session
 pub struct ChannelReadHalf {
     pub(crate) receiver: Receiver<ChannelMsg>,
+    pub(crate) throttle: Throttle,
 }
 
 impl ChannelReadHalf {
+    pub(crate) fn new(receiver: Receiver<ChannelMsg>, limiters: Limiters) -> Self {
+        ChannelReadHalf {
+            receiver,
+            throttle: Throttle::new(limiters),
+        }
+    }
+
     /// Awaits an incoming [`ChannelMsg`], this method returns [`None`] if the channel has been closed.
     pub async fn wait(&mut self) -> Option<ChannelMsg> {
-        self.receiver.recv().await
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub(crate) fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<ChannelMsg>> {
+        ready!(self.throttle.poll_ready(cx));
+        let msg = ready!(self.receiver.poll_recv(cx));
+        if let Some(ChannelMsg::Data { data } | ChannelMsg::ExtendedData { data, .. }) = &msg {
+            self.throttle.consume(data.len());
+        }
+        Poll::Ready(msg)
+    }
+
+    /// Limit the rate at which data is received on this channel, in
+    /// addition to the limit of the connection, if any.
+    pub fn set_rate_limit(&mut self, limiter: Option<RateLimiter>) {
+        self.throttle.limiters.channel = limiter;
     }
 }
 
@@ -159,6 +194,7 @@ pub struct ChannelWriteHalf<Send: From<(ChannelId, ChannelMsg)>> {
     pub(crate) sender: Sender<Send>,
     pub(crate) max_packet_size: u32,
     pub(crate) window_size: WindowSizeRef,
+    pub(crate) send_limiters: Limiters,
 }
 
 impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> ChannelWriteHalf<S> {
@@ -197,6 +233,24 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> ChannelWriteHalf<
         .await
     }
 
+    /// Request a pseudo-terminal described by a [`PtyRequest`].
+    pub async fn request_pty_with(
+        &self,
+        want_reply: bool,
+        request: &PtyRequest,
+    ) -> Result<(), Error> {
+        self.request_pty(
+            want_reply,
+            &request.term,
+            request.col_width,
+            request.row_height,
+            request.pix_width,
+            request.pix_height,
+            &request.modes,
+        )
+        .await
+    }
+
     /// Request a remote shell.
     pub async fn request_shell(&self, want_reply: bool) -> Result<(), Error> {
         self.send_msg(ChannelMsg::RequestShell { want_reply }).await
@@ -218,6 +272,16 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> ChannelWriteHalf<
         self.send_msg(ChannelMsg::Signal { signal }).await
     }
 
+    /// Send a break of `length_ms` milliseconds to the remote terminal,
+    /// see [RFC4335](https://tools.ietf.org/html/rfc4335).
+    pub async fn send_break(&self, want_reply: bool, length_ms: u32) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::Break {
+            want_reply,
+            length_ms,
+        })
+        .await
+    }
+
     /// Request the start of a subsystem with the given name.
     pub async fn request_subsystem<A: Into<String>>(
         &self,
@@ -268,6 +332,19 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> ChannelWriteHalf<
         .await
     }
 
+    /// Set several environment variables, with one request each.
+    pub async fn set_envs<I, A, B>(&self, want_reply: bool, variables: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (A, B)>,
+        A: Into<String>,
+        B: Into<String>,
+    {
+        for (name, value) in variables {
+            self.set_env(want_reply, name, value).await?;
+        }
+        Ok(())
+    }
+
     /// Inform the server that our window size has changed.
     pub async fn window_change(
         &self,
@@ -285,6 +362,12 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> ChannelWriteHalf<
         .await
     }
 
+    /// Inform the server that our window now has `col_width` columns and
+    /// `row_height` rows, without a size in pixels.
+    pub async fn resize(&self, col_width: u32, row_height: u32) -> Result<(), Error> {
+        self.window_change(col_width, row_height, 0, 0).await
+    }
+
     /// Inform the server that we will accept agent forwarding channels
     pub async fn agent_forward(&self, want_reply: bool) -> Result<(), Error> {
         self.send_msg(ChannelMsg::AgentForward { want_reply }).await
@@ -326,12 +409,28 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> ChannelWriteHalf<
         self.send_msg(ChannelMsg::ExitStatus { exit_status }).await
     }
 
+    /// Tell the client that the command was terminated by `signal`.
+    pub async fn exit_signal<A: Into<String>>(
+        &self,
+        signal: Sig,
+        core_dumped: bool,
+        error_message: A,
+    ) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::ExitSignal {
+            signal_name: signal,
+            core_dumped,
+            error_message: error_message.into(),
+            lang_tag: String::new(),
+        })
+        .await
+    }
+
     /// Request that the channel be closed.
     pub async fn close(&self) -> Result<(), Error> {
         self.send_msg(ChannelMsg::Close).await
     }
 
-    async fn send_msg(&self, msg: ChannelMsg) -> Result<(), Error> {
+    pub(crate) async fn send_msg(&self, msg: ChannelMsg) -> Result<(), Error> {
         self.sender
             .send((self.id, msg).into())
             .await
@@ -354,8 +453,16 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> ChannelWriteHalf<
             self.window_size.subscribe(),
             self.max_packet_size,
             ext,
+            self.send_limiters.clone(),
         )
     }
+
+    /// Limit the rate at which data is sent on this channel, in addition
+    /// to the limit of the connection, if any. This applies to the writers
+    /// created afterwards.
+    pub fn set_rate_limit(&mut self, limiter: Option<RateLimiter>) {
+        self.send_limiters.channel = limiter;
+    }
 }
 
 /// A handle to a session channel.
@@ -381,15 +488,17 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> Channel<S> {
         max_packet_size: u32,
         window_size: u32,
         channel_buffer_size: usize,
+        limiters: ConnectionLimiters,
     ) -> (Self, ChannelRef) {
         let (tx, rx) = tokio::sync::mpsc::channel(channel_buffer_size);
         let window_size = WindowSizeRef::new(window_size);
-        let read_half = ChannelReadHalf { receiver: rx };
+        let read_half = ChannelReadHalf::new(rx, limiters.receive);
         let write_half = ChannelWriteHalf {
             id,
             sender,
             max_packet_size,
             window_size: window_size.clone(),
+            send_limiters: limiters.send,
         };
 
         (
@@ -420,6 +529,16 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> Channel<S> {
         (self.read_half, self.write_half)
     }
 
+    /// Limit the rate at which data is sent and received on this channel,
+    /// in addition to the limits of the connection (see
+    /// `send_rate_limit` and `receive_rate_limit` in the client and
+    /// server configurations). Sharing a [`RateLimiter`] between channels
+    /// limits their total bandwidth.
+    pub fn set_rate_limit(&mut self, send: Option<RateLimiter>, receive: Option<RateLimiter>) {
+        self.write_half.set_rate_limit(send);
+        self.read_half.set_rate_limit(receive);
+    }
+
     /// Request a pseudo-terminal with the given characteristics.
     #[allow(clippy::too_many_arguments)] // length checked
     pub async fn request_pty(
@@ -445,6 +564,27 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> Channel<S> {
             .await
     }
 
+    /// Request a pseudo-terminal described by a [`PtyRequest`].
+    ///
+    /// ```no_run
+    /// # async fn f(channel: russh::Channel<russh::client::Msg>) -> Result<(), russh::Error> {
+    /// use russh::{PtyRequest, TerminalModes};
+    /// let request = PtyRequest::new("xterm").size(120, 40).modes(TerminalModes::raw());
+    /// channel.request_pty_with(true, &request).await?;
+    /// channel.request_shell(true).await?;
+    /// // Later, when the local terminal is resized:
+    /// channel.resize(100, 30).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn request_pty_with(
+        &self,
+        want_reply: bool,
+        request: &PtyRequest,
+    ) -> Result<(), Error> {
+        self.write_half.request_pty_with(want_reply, request).await
+    }
+
     /// Request a remote shell.
     pub async fn request_shell(&self, want_reply: bool) -> Result<(), Error> {
         self.write_half.request_shell(want_reply).await
@@ -462,6 +602,13 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> Channel<S> {
         self.write_half.signal(signal).await
     }
 
+    /// Send a break of `length_ms` milliseconds to the remote terminal,
+    /// see [RFC4335](https://tools.ietf.org/html/rfc4335). The server
+    /// replies with [`ChannelMsg::Success`] if the break was performed.
+    pub async fn send_break(&self, want_reply: bool, length_ms: u32) -> Result<(), Error> {
+        self.write_half.send_break(want_reply, length_ms).await
+    }
+
     /// Request the start of a subsystem with the given name.
     pub async fn request_subsystem<A: Into<String>>(
         &self,
@@ -506,6 +653,26 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> Channel<S> {
             .await
     }
 
+    /// Set several environment variables, with one request each. If
+    /// `want_reply` is true, a [`ChannelMsg::Success`] or
+    /// [`ChannelMsg::Failure`] is received for each of them, in order.
+    ///
+    /// ```no_run
+    /// # async fn f(channel: russh::Channel<russh::client::Msg>) -> Result<(), russh::Error> {
+    /// let vars = std::env::vars().filter(|(name, _)| name == "LANG" || name.starts_with("LC_"));
+    /// channel.set_envs(false, vars).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_envs<I, A, B>(&self, want_reply: bool, variables: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (A, B)>,
+        A: Into<String>,
+        B: Into<String>,
+    {
+        self.write_half.set_envs(want_reply, variables).await
+    }
+
     /// Inform the server that our window size has changed.
     pub async fn window_change(
         &self,
@@ -519,6 +686,12 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> Channel<S> {
             .await
     }
 
+    /// Inform the server that our window now has `col_width` columns and
+    /// `row_height` rows, without a size in pixels.
+    pub async fn resize(&self, col_width: u32, row_height: u32) -> Result<(), Error> {
+        self.write_half.resize(col_width, row_height).await
+    }
+
     /// Inform the server that we will accept agent forwarding channels
     pub async fn agent_forward(&self, want_reply: bool) -> Result<(), Error> {
         self.write_half.agent_forward(want_reply).await
@@ -548,6 +721,18 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> Channel<S> {
         self.write_half.exit_status(exit_status).await
     }
 
+    /// Tell the client that the command was terminated by `signal`.
+    pub async fn exit_signal<A: Into<String>>(
+        &self,
+        signal: Sig,
+        core_dumped: bool,
+        error_message: A,
+    ) -> Result<(), Error> {
+        self.write_half
+            .exit_signal(signal, core_dumped, error_message)
+            .await
+    }
+
     /// Request that the channel be closed.
     pub async fn close(&self) -> Result<(), Error> {
         self.write_half.close().await
@@ -569,11 +754,33 @@ impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> Channel<S> {
                 self.write_half.window_size.subscribe(),
                 self.write_half.max_packet_size,
                 None,
+                self.write_half.send_limiters.clone(),
             ),
             io::ChannelRx::new(self, None),
         )
     }
 
+    /// Split the [`Channel`] into separate readers for its standard output
+    /// ([`ChannelMsg::Data`]) and standard error ([`ChannelMsg::ExtendedData`]
+    /// with `ext == 1`), and a writer for its standard input.
+    pub fn split_streams(self) -> io::ChannelStreams<S> {
+        let stdin = io::ChannelTx::new(
+            self.write_half.sender.clone(),
+            self.write_half.id,
+            self.write_half.window_size.value.clone(),
+            self.write_half.window_size.subscribe(),
+            self.write_half.max_packet_size,
+            None,
+            self.write_half.send_limiters.clone(),
+        );
+        io::split(
+            self.read_half,
+            self.write_half.sender,
+            self.write_half.id,
+            stdin,
+        )
+    }
+
     /// Make a reader for the [`Channel`] to receive [`ChannelMsg::Data`]
     /// through the `AsyncRead` trait.
     pub fn make_reader(&mut self) -> impl AsyncRead + '_ {
@@ -0,0 +1,173 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::time::{Instant, Sleep};
+
+/// A bandwidth limit, in bytes per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub bytes_per_second: u64,
+    /// The number of bytes that can be transferred at once after an idle
+    /// period.
+    pub burst: u64,
+}
+
+impl RateLimit {
+    /// A limit of `bytes_per_second`, allowing bursts of one second's
+    /// worth of data.
+    pub fn new(bytes_per_second: u64) -> Self {
+        RateLimit {
+            bytes_per_second,
+            burst: bytes_per_second,
+        }
+    }
+}
+
+/// A token bucket enforcing a [`RateLimit`]. Clones share the same
+/// bucket, so a single limiter can be set on several channels (see
+/// [`Channel::set_rate_limit`](crate::Channel::set_rate_limit)) to limit
+/// them together.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            limit,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: limit.burst as f64,
+                last: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Take `n` bytes from the bucket, and return how long to wait until
+    /// it is no longer in debt.
+    fn reserve(&self, n: usize) -> Duration {
+        let Ok(mut bucket) = self.bucket.lock() else {
+            return Duration::ZERO;
+        };
+        let rate = self.limit.bytes_per_second.max(1) as f64;
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.last).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.limit.burst as f64);
+        bucket.last = now;
+        bucket.tokens -= n as f64;
+        if bucket.tokens >= 0. {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+/// The limiters that apply to one direction of a channel: the one of the
+/// connection, from the configuration, and the one of the channel.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Limiters {
+    pub(crate) connection: Option<RateLimiter>,
+    pub(crate) channel: Option<RateLimiter>,
+}
+
+impl Limiters {
+    pub(crate) fn new(connection: Option<RateLimit>) -> Self {
+        Limiters {
+            connection: connection.map(RateLimiter::new),
+            channel: None,
+        }
+    }
+}
+
+/// The outgoing and incoming limiters of a connection.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionLimiters {
+    pub(crate) send: Limiters,
+    pub(crate) receive: Limiters,
+}
+
+impl ConnectionLimiters {
+    pub(crate) fn new(send: Option<RateLimit>, receive: Option<RateLimit>) -> Self {
+        ConnectionLimiters {
+            send: Limiters::new(send),
+            receive: Limiters::new(receive),
+        }
+    }
+}
+
+/// Delays transfers according to [`Limiters`]. Bytes are accounted for
+/// after they are transferred, and the next transfer waits until the
+/// buckets are refilled.
+#[derive(Default)]
+pub(crate) struct Throttle {
+    pub(crate) limiters: Limiters,
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl std::fmt::Debug for Throttle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Throttle")
+            .field("limiters", &self.limiters)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Throttle {
+    pub(crate) fn new(limiters: Limiters) -> Self {
+        Throttle {
+            limiters,
+            delay: None,
+        }
+    }
+
+    pub(crate) fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(delay) = self.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        Poll::Ready(())
+    }
+
+    pub(crate) fn consume(&mut self, n: usize) {
+        let wait = [&self.limiters.connection, &self.limiters.channel]
+            .into_iter()
+            .flatten()
+            .map(|limiter| limiter.reserve(n))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            self.delay = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bucket() {
+        let limiter = RateLimiter::new(RateLimit::new(1000));
+        assert_eq!(limiter.reserve(1000), Duration::ZERO);
+        let wait = limiter.reserve(500);
+        assert!(wait > Duration::from_millis(490) && wait <= Duration::from_millis(500));
+        // Clones share the bucket.
+        let wait = limiter.clone().reserve(500);
+        assert!(wait > Duration::from_millis(990) && wait <= Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,136 @@
+//! Layer 2 and layer 3 tunnels over `tun@openssh.com` channels, as used
+//! by `ssh -w` (see OpenSSH's
+//! [PROTOCOL](https://cvsweb.openbsd.org/src/usr.bin/ssh/PROTOCOL?annotate=HEAD),
+//! section 2.3).
+//!
+//! Each packet is sent as an SSH string in the channel data. In
+//! point-to-point mode, packets are prefixed with their address family;
+//! [`TunChannel`] adds and removes this header, so that it can be
+//! connected directly to a TUN or TAP interface.
+
+use std::io;
+
+use log::warn;
+
+use super::{Channel, ChannelMsg};
+use crate::{ChannelId, Error};
+
+/// Request the next available tunnel device on the other side.
+pub const TUN_UNIT_ANY: u32 = 0x7fff_ffff;
+
+const SSH_TUN_AF_INET: u32 = 2;
+const SSH_TUN_AF_INET6: u32 = 24;
+
+/// The type of tunnel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TunMode {
+    /// Layer 3 (IP) packets, for a TUN interface.
+    PointToPoint = 1,
+    /// Layer 2 (Ethernet) frames, for a TAP interface.
+    Ethernet = 2,
+}
+
+impl TunMode {
+    pub(crate) fn from_u32(x: u32) -> Option<TunMode> {
+        match x {
+            1 => Some(TunMode::PointToPoint),
+            2 => Some(TunMode::Ethernet),
+            _ => None,
+        }
+    }
+}
+
+/// A packet-oriented wrapper around a `tun@openssh.com` channel.
+pub struct TunChannel<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> {
+    channel: Channel<S>,
+    mode: TunMode,
+    buffer: Vec<u8>,
+}
+
+impl<S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static> TunChannel<S> {
+    pub fn new(channel: Channel<S>, mode: TunMode) -> Self {
+        TunChannel {
+            channel,
+            mode,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> TunMode {
+        self.mode
+    }
+
+    pub fn into_inner(self) -> Channel<S> {
+        self.channel
+    }
+
+    /// Receive the next packet: an IP packet in point-to-point mode, or
+    /// an Ethernet frame. Returns `None` once the channel is closed.
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        loop {
+            if let Some(packet) = self.next_packet() {
+                if self.mode == TunMode::Ethernet {
+                    return Some(packet);
+                }
+                if packet.len() >= 4 {
+                    return Some(packet.split_at(4).1.to_vec());
+                }
+                warn!("tunnel packet without address family");
+                continue;
+            }
+            match self.channel.wait().await? {
+                ChannelMsg::Data { data } => self.buffer.extend_from_slice(&data),
+                ChannelMsg::Eof | ChannelMsg::Close => return None,
+                _ => {}
+            }
+        }
+    }
+
+    fn next_packet(&mut self) -> Option<Vec<u8>> {
+        let [a, b, c, d, ref rest @ ..] = self.buffer[..] else {
+            return None;
+        };
+        let len = u32::from_be_bytes([a, b, c, d]) as usize;
+        if rest.len() < len {
+            return None;
+        }
+        let packet = rest.split_at(len).0.to_vec();
+        self.buffer.drain(..4 + len);
+        Some(packet)
+    }
+
+    /// Send a packet: an IPv4 or IPv6 packet in point-to-point mode, or
+    /// an Ethernet frame.
+    pub async fn send(&self, packet: &[u8]) -> Result<(), Error> {
+        let mut len = packet.len();
+        let family = match self.mode {
+            TunMode::Ethernet => None,
+            TunMode::PointToPoint => {
+                len += 4;
+                match packet.first().map(|b| b >> 4) {
+                    Some(4) => Some(SSH_TUN_AF_INET),
+                    Some(6) => Some(SSH_TUN_AF_INET6),
+                    _ => {
+                        return Err(Error::IO(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "not an IP packet",
+                        )))
+                    }
+                }
+            }
+        };
+        let len = u32::try_from(len).map_err(|_| {
+            Error::IO(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packet too long",
+            ))
+        })?;
+        let mut frame = Vec::with_capacity(packet.len() + 8);
+        frame.extend(len.to_be_bytes());
+        if let Some(family) = family {
+            frame.extend(family.to_be_bytes());
+        }
+        frame.extend(packet);
+        self.channel.data(&frame[..]).await
+    }
+}
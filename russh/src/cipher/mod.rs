@@ -72,8 +72,10 @@ pub(crate) trait Cipher {
 
 /// `clear`
 pub const CLEAR: Name = Name("clear");
-/// `3des-cbc`
-#[cfg(feature = "des")]
+/// `3des-cbc`, with the `legacy-ciphers` feature. It is never negotiated
+/// unless listed in [`Preferred::cipher`](crate::Preferred::cipher), and
+/// its 64-bit blocks make sessions rekey every GiB.
+#[cfg(feature = "legacy-ciphers")]
 pub const TRIPLE_DES_CBC: Name = Name("3des-cbc");
 /// `aes128-ctr`
 pub const AES_128_CTR: Name = Name("aes128-ctr");
@@ -95,7 +97,7 @@ pub const CHACHA20_POLY1305: Name = Name("chacha20-poly1305@openssh.com");
 pub const NONE: Name = Name("none");
 
 static _CLEAR: Clear = Clear {};
-#[cfg(feature = "des")]
+#[cfg(feature = "legacy-ciphers")]
 static _3DES_CBC: SshBlockCipher<CbcWrapper<des::TdesEde3>> = SshBlockCipher(PhantomData);
 static _AES_128_CTR: SshBlockCipher<Ctr128BE<Aes128>> = SshBlockCipher(PhantomData);
 static _AES_192_CTR: SshBlockCipher<Ctr128BE<Aes192>> = SshBlockCipher(PhantomData);
@@ -109,7 +111,7 @@ static _CHACHA20_POLY1305: SshChacha20Poly1305Cipher = SshChacha20Poly1305Cipher
 pub static ALL_CIPHERS: &[&Name] = &[
     &CLEAR,
     &NONE,
-    #[cfg(feature = "des")]
+    #[cfg(feature = "legacy-ciphers")]
     &TRIPLE_DES_CBC,
     &AES_128_CTR,
     &AES_192_CTR,
@@ -126,7 +128,7 @@ pub(crate) static CIPHERS: Lazy<HashMap<&'static Name, &(dyn Cipher + Send + Syn
         let mut h: HashMap<&'static Name, &(dyn Cipher + Send + Sync)> = HashMap::new();
         h.insert(&CLEAR, &_CLEAR);
         h.insert(&NONE, &_CLEAR);
-        #[cfg(feature = "des")]
+        #[cfg(feature = "legacy-ciphers")]
         h.insert(&TRIPLE_DES_CBC, &_3DES_CBC);
         h.insert(&AES_128_CTR, &_AES_128_CTR);
         h.insert(&AES_192_CTR, &_AES_192_CTR);
@@ -161,6 +163,20 @@ impl Borrow<str> for &Name {
     }
 }
 
+impl Name {
+    /// The number of bytes that can be encrypted before keys must be
+    /// re-exchanged: 2^32 blocks for 128-bit block ciphers
+    /// ([RFC 4344](https://tools.ietf.org/html/rfc4344#section-3.2)), and
+    /// 1 GiB for the others, like OpenSSH.
+    pub(crate) fn rekey_bytes(&self) -> usize {
+        match *self {
+            AES_128_CTR | AES_192_CTR | AES_256_CTR | AES_256_GCM | AES_128_CBC | AES_192_CBC
+            | AES_256_CBC => usize::try_from(1u64 << 36).unwrap_or(usize::MAX),
+            _ => 1 << 30,
+        }
+    }
+}
+
 impl TryFrom<&str> for Name {
     type Error = ();
     fn try_from(s: &str) -> Result<Name, ()> {
@@ -237,6 +253,7 @@ pub(crate) trait SealingKey {
         self.seal(buffer.seqn.0, plaintext, tag);
 
         buffer.bytes += payload.len();
+        buffer.packets += 1;
         // Sequence numbers are on 32 bits and wrap.
         // https://tools.ietf.org/html/rfc4253#section-6.4
         buffer.seqn += Wrapping(1);
@@ -247,6 +264,7 @@ pub(crate) async fn read<R: AsyncRead + Unpin>(
     stream: &mut R,
     buffer: &mut SSHBuffer,
     cipher: &mut (dyn OpeningKey + Send),
+    maximum_packet_len: usize,
 ) -> Result<usize, Error> {
     if buffer.len == 0 {
         let mut len = vec![0; cipher.packet_length_to_read_for_block_length()];
@@ -261,7 +279,7 @@ pub(crate) async fn read<R: AsyncRead + Unpin>(
             let len = cipher.decrypt_packet_length(seqn, &len);
             let len = BigEndian::read_u32(&len) as usize;
 
-            if len > MAXIMUM_PACKET_LEN {
+            if len > maximum_packet_len {
                 return Err(Error::PacketSize(len));
             }
 
@@ -305,6 +323,6 @@ pub(crate) async fn read<R: AsyncRead + Unpin>(
 pub(crate) const PACKET_LENGTH_LEN: usize = 4;
 
 const MINIMUM_PACKET_LEN: usize = 16;
-const MAXIMUM_PACKET_LEN: usize = 256 * 1024;
+pub(crate) const MAXIMUM_PACKET_LEN: usize = 256 * 1024;
 
 const PADDING_LENGTH_LEN: usize = 1;
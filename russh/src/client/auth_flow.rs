@@ -0,0 +1,356 @@
+//! Trying several authentication methods in one call, in the manner of
+//! the OpenSSH client.
+
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+
+use log::{debug, warn};
+use ssh_key::{Certificate, Fingerprint, PrivateKey};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::{AuthResult, Handle, Handler, KeyboardInteractiveAuthResponse, Prompt};
+use crate::auth::AgentAuthError;
+use crate::keys::agent::client::AgentClient;
+use crate::keys::agent::AgentIdentity;
+use crate::keys::PrivateKeyWithHashAlg;
+use crate::{MethodKind, MethodSet};
+
+/// A private key, and the certificate to authenticate with it, if any.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub key: Arc<PrivateKey>,
+    pub certificate: Option<Certificate>,
+}
+
+impl Identity {
+    pub fn new(key: Arc<PrivateKey>) -> Self {
+        Identity {
+            key,
+            certificate: None,
+        }
+    }
+
+    pub fn with_certificate(key: Arc<PrivateKey>, certificate: Certificate) -> Self {
+        Identity {
+            key,
+            certificate: Some(certificate),
+        }
+    }
+
+    /// Load the key at `path`, like `id_ed25519`, and the certificate
+    /// next to it, like `id_ed25519-cert.pub`, if there is one. As in
+    /// OpenSSH, a certificate that cannot be read, or that is not for
+    /// this key, is ignored.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        password: Option<&str>,
+    ) -> Result<Self, crate::keys::Error> {
+        let path = path.as_ref();
+        let key = crate::keys::load_secret_key(path, password)?;
+        let mut cert_path = path.as_os_str().to_owned();
+        cert_path.push("-cert.pub");
+        let cert_path = Path::new(&cert_path);
+        let certificate = if cert_path.exists() {
+            match crate::keys::load_openssh_certificate(cert_path) {
+                Ok(cert) if cert.public_key() == key.public_key().key_data() => Some(cert),
+                Ok(_) => {
+                    warn!("{cert_path:?} is not a certificate of {path:?}");
+                    None
+                }
+                Err(e) => {
+                    warn!("could not load {cert_path:?}: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        Ok(Identity {
+            key: Arc::new(key),
+            certificate,
+        })
+    }
+}
+
+/// The identities of an agent that [`Handle::authenticate_with_agent`]
+/// tries. The default filter accepts all of them.
+#[derive(Debug, Clone, Default)]
+pub struct AgentFilter {
+    /// Only try the identities with this comment.
+    pub comment: Option<String>,
+    /// Only try the identity whose key has this fingerprint.
+    pub fingerprint: Option<Fingerprint>,
+    /// Only try certificates if `true`, or plain keys if `false`.
+    pub certificates: Option<bool>,
+}
+
+impl AgentFilter {
+    pub fn matches(&self, identity: &AgentIdentity) -> bool {
+        if let Some(ref comment) = self.comment {
+            if identity.comment() != comment {
+                return false;
+            }
+        }
+        if let Some(ref fingerprint) = self.fingerprint {
+            if identity.key_data().fingerprint(fingerprint.algorithm()) != *fingerprint {
+                return false;
+            }
+        }
+        if let Some(certificates) = self.certificates {
+            if matches!(identity, AgentIdentity::Certificate { .. }) != certificates {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The credentials used by [`Handle::authenticate`]. Every method has a
+/// default implementation that skips the corresponding authentication
+/// method.
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+pub trait Authenticator: Send {
+    /// The keys to try with `publickey` authentication, in order. This
+    /// is called once, the first time the server accepts `publickey`.
+    fn keys(&mut self) -> impl Future<Output = Vec<PrivateKeyWithHashAlg>> + Send {
+        async { Vec::new() }
+    }
+
+    /// Answer a round of `keyboard-interactive` authentication, or
+    /// return `None` to give up on the method, which answers the
+    /// prompts with empty strings.
+    #[allow(unused_variables)]
+    fn keyboard_interactive(
+        &mut self,
+        name: &str,
+        instructions: &str,
+        prompts: &[Prompt],
+    ) -> impl Future<Output = Option<Vec<String>>> + Send {
+        async { None }
+    }
+
+    /// The password to try, or `None` to give up on `password`
+    /// authentication. This is called again after each wrong password.
+    fn password(&mut self) -> impl Future<Output = Option<String>> + Send {
+        async { None }
+    }
+}
+
+impl<H: Handler> Handle<H> {
+    /// Authenticate as `user` with the methods the server lets us
+    /// continue with, preferring them like OpenSSH: `none` first, then
+    /// `publickey` with each of the keys, `keyboard-interactive`, and
+    /// finally `password`.
+    ///
+    /// After a partial success, the methods are tried again among the
+    /// ones the server still requires, except for the keys that were
+    /// already used. This returns the last result, which is a failure
+    /// once every method is exhausted.
+    pub async fn authenticate<U: Into<String>, A: Authenticator>(
+        &mut self,
+        user: U,
+        authenticator: &mut A,
+    ) -> Result<AuthResult, crate::Error> {
+        let user = user.into();
+        let mut result = self.authenticate_none(&user).await?;
+        let mut keys: Option<std::vec::IntoIter<PrivateKeyWithHashAlg>> = None;
+        let mut tried_keyboard_interactive = false;
+
+        loop {
+            let AuthResult::Failure {
+                remaining_methods,
+                partial_success,
+            } = &result
+            else {
+                return Ok(result);
+            };
+            if *partial_success {
+                tried_keyboard_interactive = false;
+            }
+            let remaining_methods = remaining_methods.clone();
+
+            if remaining_methods.contains(&MethodKind::PublicKey) {
+                if keys.is_none() {
+                    keys = Some(authenticator.keys().await.into_iter());
+                }
+                if let Some(key) = keys.as_mut().and_then(|k| k.next()) {
+                    result = self.authenticate_publickey(&user, key).await?;
+                    continue;
+                }
+            }
+
+            if remaining_methods.contains(&MethodKind::KeyboardInteractive)
+                && !tried_keyboard_interactive
+            {
+                tried_keyboard_interactive = true;
+                result = self
+                    .authenticate_with_keyboard_interactive(&user, authenticator)
+                    .await?;
+                continue;
+            }
+
+            if remaining_methods.contains(&MethodKind::Password) {
+                if let Some(password) = authenticator.password().await {
+                    result = self.authenticate_password(&user, password).await?;
+                    continue;
+                }
+            }
+
+            return Ok(result);
+        }
+    }
+
+    /// Try `identities` in order, with their certificate if they have
+    /// one, until the server accepts one. Keys are first offered without
+    /// a signature, so the server is only sent signatures from the keys
+    /// it accepts. RSA keys are used with the best hash the server
+    /// supports.
+    ///
+    /// This stops at the first success or partial success, or once the
+    /// server no longer accepts `publickey` authentication. If
+    /// `identities` is empty, this fails without contacting the server.
+    pub async fn authenticate_identities<U, I>(
+        &mut self,
+        user: U,
+        identities: I,
+    ) -> Result<AuthResult, crate::Error>
+    where
+        U: Into<String>,
+        I: IntoIterator<Item = Identity>,
+    {
+        let user = user.into();
+        let mut result = AuthResult::Failure {
+            remaining_methods: MethodSet::from(&[MethodKind::PublicKey][..]),
+            partial_success: false,
+        };
+        for identity in identities {
+            debug!("trying identity {:?}", identity.key.algorithm());
+            result = match identity.certificate {
+                Some(cert) => {
+                    self.authenticate_openssh_cert(&user, identity.key, cert)
+                        .await?
+                }
+                None => {
+                    let hash_alg = if identity.key.algorithm().is_rsa() {
+                        self.best_supported_rsa_hash().await?.flatten()
+                    } else {
+                        None
+                    };
+                    self.authenticate_publickey(
+                        &user,
+                        PrivateKeyWithHashAlg::new(identity.key, hash_alg),
+                    )
+                    .await?
+                }
+            };
+            let more_keys = result
+                .remaining_methods()
+                .is_some_and(|m| m.contains(&MethodKind::PublicKey));
+            if !more_keys || result.partial_success() {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Like [`Handle::authenticate_identities`], with the identities of
+    /// `agent` that match `filter`, in the order of the agent. The agent
+    /// signs, and RSA keys are used with the best hash the server
+    /// supports.
+    ///
+    /// The agent is first bound to this connection with
+    /// [`AgentClient::bind_session`], so that it can enforce the
+    /// destination constraints of its keys. Agents that do not support
+    /// it are used all the same.
+    pub async fn authenticate_with_agent<U, S>(
+        &mut self,
+        user: U,
+        agent: &mut AgentClient<S>,
+        filter: &AgentFilter,
+    ) -> Result<AuthResult, AgentAuthError>
+    where
+        U: Into<String>,
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let user = user.into();
+        let mut result = AuthResult::Failure {
+            remaining_methods: MethodSet::from(&[MethodKind::PublicKey][..]),
+            partial_success: false,
+        };
+        let bind = self.session_bind().await?;
+        if let Err(e) = agent.bind_session(&bind, false).await {
+            debug!("could not bind the agent to the session: {e}");
+        }
+        let identities = agent.request_identities_and_certificates().await?;
+        for identity in identities {
+            if !filter.matches(&identity) {
+                continue;
+            }
+            debug!("trying agent identity {:?}", identity.comment());
+            result = match identity {
+                AgentIdentity::Certificate { certificate, .. } => {
+                    self.authenticate_certificate_with(&user, certificate, agent)
+                        .await?
+                }
+                AgentIdentity::PublicKey { key, .. } => {
+                    let hash_alg = if key.algorithm().is_rsa() {
+                        self.best_supported_rsa_hash().await?.flatten()
+                    } else {
+                        None
+                    };
+                    self.authenticate_publickey_with(&user, key, hash_alg, agent)
+                        .await?
+                }
+            };
+            let more_keys = result
+                .remaining_methods()
+                .is_some_and(|m| m.contains(&MethodKind::PublicKey));
+            if !more_keys || result.partial_success() {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Run `keyboard-interactive` authentication with the answers of
+    /// `authenticator`.
+    async fn authenticate_with_keyboard_interactive<A: Authenticator>(
+        &mut self,
+        user: &str,
+        authenticator: &mut A,
+    ) -> Result<AuthResult, crate::Error> {
+        let mut reply = self
+            .authenticate_keyboard_interactive_start(user, None)
+            .await?;
+        loop {
+            match reply {
+                KeyboardInteractiveAuthResponse::Success => return Ok(AuthResult::Success),
+                KeyboardInteractiveAuthResponse::Failure {
+                    remaining_methods,
+                    partial_success,
+                } => {
+                    return Ok(AuthResult::Failure {
+                        remaining_methods,
+                        partial_success,
+                    })
+                }
+                KeyboardInteractiveAuthResponse::InfoRequest {
+                    name,
+                    instructions,
+                    prompts,
+                } => {
+                    // Servers expect as many answers as prompts, even
+                    // when giving up.
+                    let responses = authenticator
+                        .keyboard_interactive(&name, &instructions, &prompts)
+                        .await
+                        .unwrap_or_else(|| vec![String::new(); prompts.len()]);
+                    reply = self
+                        .authenticate_keyboard_interactive_respond(responses)
+                        .await?;
+                }
+            }
+        }
+    }
+}
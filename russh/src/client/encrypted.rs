@@ -15,23 +15,30 @@
 use std::cell::RefCell;
 use std::convert::TryInto;
 use std::ops::Deref;
-use std::str::FromStr;
 
 use bytes::Bytes;
 use log::{debug, error, info, trace, warn};
+use signature::Verifier;
 use ssh_encoding::{Decode, Encode, Reader};
-use ssh_key::Algorithm;
+use ssh_key::{Algorithm, PublicKey, Signature};
 
 use super::IncomingSshPacket;
 use crate::auth::AuthRequest;
 use crate::cert::PublicKeyOrCertificate;
-use crate::client::{Handler, Msg, Prompt, Reply, Session};
-use crate::helpers::{map_err, sign_with_hash_alg, AlgorithmExt, EncodedExt, NameList};
+use crate::client::{
+    AuthBanner, ForwardedChannelSender, Handler, Msg, OriginatorInfo, Prompt, Reply, Session,
+};
+use crate::ext_info::write_ext_info;
+use crate::helpers::{
+    host_key_proof_data, map_err, sign_with_hash_alg, AlgorithmExt, EncodedExt, NameList,
+};
+use crate::kex::EXTENSION_SUPPORT_AS_SERVER;
 use crate::keys::key::parse_public_key;
+use crate::negotiation::kex_init_lists;
 use crate::parsing::{ChannelOpenConfirmation, ChannelType, OpenChannelMessage};
-use crate::session::{Encrypted, EncryptedState, GlobalRequestResponse};
+use crate::session::{push_global_request_reply, Encrypted, EncryptedState, GlobalRequestResponse};
 use crate::{
-    auth, msg, Channel, ChannelId, ChannelMsg, ChannelOpenFailure, ChannelParams, CryptoVec, Error,
+    auth, msg, Channel, ChannelId, ChannelMsg, ChannelOpenFailure, ChannelParams, CryptoVec,
     MethodSet, Sig,
 };
 
@@ -92,7 +99,7 @@ impl Session {
                             }
                         }
                         Some((&msg::EXT_INFO, mut r)) => {
-                            return self.handle_ext_info(&mut r).map_err(Into::into);
+                            return self.handle_ext_info(client, &mut r).await;
                         }
                         other => {
                             debug!("unknown message: {other:?}");
@@ -113,8 +120,17 @@ impl Session {
                             return Ok(());
                         }
                         Some((&msg::USERAUTH_BANNER, mut r)) => {
-                            let banner = map_err!(String::decode(&mut r))?;
-                            client.auth_banner(&banner, self).await?;
+                            let message = map_err!(String::decode(&mut r))?;
+                            let language_tag = if r.is_finished() {
+                                String::new()
+                            } else {
+                                map_err!(String::decode(&mut r))?
+                            };
+                            client.auth_banner(&message, &language_tag, self).await?;
+                            let _ = self.sender.send(Reply::AuthBanner(AuthBanner {
+                                message,
+                                language_tag,
+                            }));
                             return Ok(());
                         }
                         Some((&msg::USERAUTH_FAILURE, mut r)) => {
@@ -141,6 +157,18 @@ impl Session {
                             }
                         }
                         Some((&msg::USERAUTH_INFO_REQUEST_OR_USERAUTH_PK_OK, mut r)) => {
+                            // Message 60 is SSH_MSG_USERAUTH_GSSAPI_RESPONSE in
+                            // reply to a gssapi-with-mic request.
+                            #[cfg(feature = "gssapi")]
+                            if let Some(auth::Method::GssapiWithMic(ref mut gssapi)) =
+                                self.common.auth_method
+                            {
+                                let mechanism = map_err!(Bytes::decode(&mut r))?;
+                                debug!("gssapi mechanism selected: {mechanism:?}");
+                                gssapi.mechanism = Some(mechanism.to_vec());
+                                enc.client_gssapi_step(&self.common.auth_user, gssapi, None)?;
+                                return Ok(());
+                            }
                             if let Some(auth::CurrentRequest::PublicKey {
                                 ref mut sent_pk_ok,
                                 ..
@@ -196,17 +224,32 @@ impl Session {
                                 // write responses
                                 enc.client_send_auth_response(&responses)?;
                                 return Ok(());
+                            } else if let Some(
+                                auth::Method::Password { .. } | auth::Method::PasswordChange { .. },
+                            ) = self.common.auth_method
+                            {
+                                // Message 60 is SSH_MSG_USERAUTH_PASSWD_CHANGEREQ in
+                                // reply to a password request.
+                                debug!("userauth_passwd_changereq");
+                                let prompt = map_err!(String::decode(&mut r))?;
+                                let _lang = map_err!(String::decode(&mut r))?;
+                                self.common.auth_method = None;
+                                self.sender
+                                    .send(Reply::AuthPasswordChangeRequest { prompt })
+                                    .map_err(|_| crate::Error::SendError)?;
+                                return Ok(());
                             }
 
                             // continue with userauth_pk_ok
-                            match self.common.auth_method.take() {
+                            let future_key = match self.common.auth_method.take() {
                                 Some(auth_method @ auth::Method::PublicKey { .. }) => {
                                     self.common.buffer.clear();
                                     enc.client_send_signature(
                                         &self.common.auth_user,
                                         &auth_method,
                                         &mut self.common.buffer,
-                                    )?
+                                    )?;
+                                    None
                                 }
                                 Some(auth_method @ auth::Method::OpenSshCertificate { .. }) => {
                                     self.common.buffer.clear();
@@ -214,47 +257,80 @@ impl Session {
                                         &self.common.auth_user,
                                         &auth_method,
                                         &mut self.common.buffer,
-                                    )?
-                                }
-                                Some(auth::Method::FuturePublicKey { key, hash_alg }) => {
-                                    debug!("public key");
-                                    self.common.buffer.clear();
-                                    let i = enc.client_make_to_sign(
-                                        &self.common.auth_user,
-                                        &PublicKeyOrCertificate::PublicKey {
-                                            key: key.clone(),
-                                            hash_alg,
-                                        },
-                                        &mut self.common.buffer,
                                     )?;
-                                    let len = self.common.buffer.len();
-                                    let buf = std::mem::replace(
-                                        &mut self.common.buffer,
-                                        CryptoVec::new(),
-                                    );
-
-                                    self.sender
-                                        .send(Reply::SignRequest { key, data: buf })
-                                        .map_err(|_| crate::Error::SendError)?;
-                                    self.common.buffer = loop {
-                                        match self.receiver.recv().await {
-                                            Some(Msg::Signed { data }) => break data,
-                                            _ => {}
-                                        }
-                                    };
-                                    if self.common.buffer.len() != len {
-                                        // The buffer was modified.
-                                        push_packet!(enc.write, {
-                                            #[allow(clippy::indexing_slicing)] // length checked
-                                            enc.write.extend(&self.common.buffer[i..]);
-                                        })
+                                    None
+                                }
+                                Some(auth::Method::FuturePublicKey { key, hash_alg }) => Some((
+                                    PublicKeyOrCertificate::PublicKey {
+                                        key: key.clone(),
+                                        hash_alg,
+                                    },
+                                    key,
+                                )),
+                                Some(auth::Method::FutureCertificate { cert }) => {
+                                    // The signer only needs the key inside
+                                    // the certificate.
+                                    let key = ssh_key::PublicKey::from(cert.public_key().clone());
+                                    Some((PublicKeyOrCertificate::Certificate(cert), key))
+                                }
+                                _ => None,
+                            };
+                            if let Some((to_sign, key)) = future_key {
+                                debug!("public key");
+                                self.common.buffer.clear();
+                                let i = enc.client_make_to_sign(
+                                    &self.common.auth_user,
+                                    &to_sign,
+                                    &mut self.common.buffer,
+                                )?;
+                                let len = self.common.buffer.len();
+                                let buf =
+                                    std::mem::replace(&mut self.common.buffer, CryptoVec::new());
+
+                                self.sender
+                                    .send(Reply::SignRequest { key, data: buf })
+                                    .map_err(|_| crate::Error::SendError)?;
+                                self.common.buffer = loop {
+                                    match self.receiver.recv().await {
+                                        Some(Msg::Signed { data }) => break data,
+                                        _ => {}
                                     }
+                                };
+                                if self.common.buffer.len() != len {
+                                    // The buffer was modified.
+                                    push_packet!(enc.write, {
+                                        #[allow(clippy::indexing_slicing)] // length checked
+                                        enc.write.extend(&self.common.buffer[i..]);
+                                    })
                                 }
-                                _ => {}
                             }
                         }
+                        #[cfg(feature = "gssapi")]
+                        Some((&msg::USERAUTH_GSSAPI_TOKEN, mut r)) => {
+                            let Some(auth::Method::GssapiWithMic(ref mut gssapi)) =
+                                self.common.auth_method
+                            else {
+                                return Err(crate::Error::Inconsistent.into());
+                            };
+                            let token = map_err!(Bytes::decode(&mut r))?;
+                            enc.client_gssapi_step(&self.common.auth_user, gssapi, Some(&token))?;
+                            return Ok(());
+                        }
+                        #[cfg(feature = "gssapi")]
+                        Some((&msg::USERAUTH_GSSAPI_ERROR, mut r)) => {
+                            let major = map_err!(u32::decode(&mut r))?;
+                            let minor = map_err!(u32::decode(&mut r))?;
+                            let message = map_err!(String::decode(&mut r))?;
+                            warn!("gssapi error from server ({major}, {minor}): {message}");
+                            return Ok(());
+                        }
+                        #[cfg(feature = "gssapi")]
+                        Some((&msg::USERAUTH_GSSAPI_ERRTOK, _)) => {
+                            warn!("gssapi error token from server");
+                            return Ok(());
+                        }
                         Some((&msg::EXT_INFO, mut r)) => {
-                            return self.handle_ext_info(&mut r).map_err(Into::into);
+                            return self.handle_ext_info(client, &mut r).await;
                         }
                         other => {
                             debug!("unknown message: {other:?}");
@@ -273,17 +349,19 @@ impl Session {
         }
     }
 
-    fn handle_ext_info(&mut self, r: &mut impl Reader) -> Result<(), Error> {
-        let n_extensions = u32::decode(r)? as usize;
+    async fn handle_ext_info<H: Handler>(
+        &mut self,
+        client: &mut H,
+        r: &mut &[u8],
+    ) -> Result<(), H::Error> {
+        let n_extensions = map_err!(u32::decode(r))? as usize;
         debug!("Received EXT_INFO, {n_extensions:?} extensions");
         for _ in 0..n_extensions {
-            let name = String::decode(r)?;
-            if name == "server-sig-algs" {
-                self.handle_server_sig_algs_ext(r)?;
-            } else {
-                let data = Vec::<u8>::decode(r)?;
-                debug!("* {name:?} (unknown, data: {data:?})");
-            }
+            let name = map_err!(String::decode(r))?;
+            let data = map_err!(Vec::<u8>::decode(r))?;
+            debug!("* {name:?} ({:?})", String::from_utf8_lossy(&data));
+            client.extension_received(&name, &data, self).await?;
+            self.extension_info.insert(name.clone(), data);
             if let Some(ref mut enc) = self.common.encrypted {
                 enc.received_extensions.push(name.clone());
                 if let Some(mut senders) = enc.extension_info_awaiters.remove(&name) {
@@ -296,18 +374,24 @@ impl Session {
         Ok(())
     }
 
-    fn handle_server_sig_algs_ext(&mut self, r: &mut impl Reader) -> Result<(), Error> {
-        let algs = NameList::decode(r)?;
-        debug!("* server-sig-algs");
-        self.server_sig_algs = Some(
-            algs.0
-                .iter()
-                .filter_map(|x| Algorithm::from_str(x).ok())
-                .inspect(|x| {
-                    debug!("  * {x:?}");
-                })
-                .collect::<Vec<_>>(),
-        );
+    /// Send [`Config::extensions`](super::Config::extensions) if the
+    /// server supports extension negotiation. This must be the first
+    /// packet after the first `NEWKEYS`.
+    pub(crate) fn maybe_send_ext_info(&mut self) -> Result<(), crate::Error> {
+        let extensions = &self.common.config.extensions;
+        if let Some(ref mut enc) = self.common.encrypted {
+            let key_extension_server = enc
+                .exchange
+                .as_ref()
+                .is_some_and(|e| kex_init_lists(&e.server_kex_init, &EXTENSION_SUPPORT_AS_SERVER));
+            if extensions.is_empty() || !key_extension_server {
+                return Ok(());
+            }
+            write_ext_info(
+                &mut enc.write,
+                extensions.iter().map(|(n, v)| (n.as_str(), v.as_slice())),
+            )?;
+        }
         Ok(())
     }
 
@@ -558,13 +642,11 @@ impl Session {
                 let req = map_err!(String::decode(&mut r))?;
                 let wants_reply = map_err!(u8::decode(&mut r))?;
                 if let Some(ref mut enc) = self.common.encrypted {
-                    if req.starts_with("keepalive") {
+                    if let Some(&success) = self.common.config.global_request_replies.get(&req) {
+                        trace!("global request {req:?}, replying {success:?}");
                         if wants_reply == 1 {
-                            trace!("Received keep alive message: {req:?}",);
                             self.common.wants_reply = false;
-                            push_packet!(enc.write, enc.write.push(msg::REQUEST_SUCCESS));
-                        } else {
-                            warn!("Received keepalive without reply request!");
+                            push_global_request_reply(&mut enc.write, success.then(Vec::new))?;
                         }
                     } else if req == "hostkeys-00@openssh.com" {
                         let mut keys = vec![];
@@ -587,11 +669,22 @@ impl Session {
                                 }
                             }
                         }
+                        if self.common.config.update_host_keys && !keys.is_empty() {
+                            self.prove_host_keys(keys.clone())?;
+                        }
                         return client.openssh_ext_host_keys_announced(keys, self).await;
                     } else {
-                        warn!("Unhandled global request: {req:?} {wants_reply:?}",);
+                        debug!("handler.global_request {req:?} {wants_reply:?}");
                         self.common.wants_reply = false;
-                        push_packet!(enc.write, enc.write.push(msg::REQUEST_FAILURE))
+                        let reply = client
+                            .global_request(&req, wants_reply != 0, r, self)
+                            .await?;
+                        if wants_reply != 0 {
+                            if let Some(ref mut enc) = self.common.encrypted {
+                                push_global_request_reply(&mut enc.write, reply)?;
+                            }
+                        }
+                        return Ok(());
                     }
                 }
                 self.common.received_data = false;
@@ -668,52 +761,100 @@ impl Session {
                         } => {
                             confirm()?;
                             let channel = self.accept_server_initiated_channel(id, &msg);
-                            client
-                                .server_channel_open_x11(
-                                    channel,
-                                    originator_address,
-                                    *originator_port,
-                                    self,
-                                )
-                                .await?
+                            if let Some(sender) = self.x11_forward.clone() {
+                                if let Err(e) = sender.send(channel).await {
+                                    warn!("could not deliver X11 channel: {e:?}");
+                                    self.close(id)?;
+                                }
+                            } else {
+                                client
+                                    .server_channel_open_x11(
+                                        channel,
+                                        originator_address,
+                                        *originator_port,
+                                        self,
+                                    )
+                                    .await?
+                            }
                         }
                         ChannelType::ForwardedTcpIp(d) => {
                             confirm()?;
                             let channel = self.accept_server_initiated_channel(id, &msg);
-                            client
-                                .server_channel_open_forwarded_tcpip(
-                                    channel,
-                                    &d.host_to_connect,
-                                    d.port_to_connect,
-                                    &d.originator_address,
-                                    d.originator_port,
-                                    self,
-                                )
-                                .await?
+                            if let Some(sender) =
+                                self.remote_forward_sender(&d.host_to_connect, d.port_to_connect)
+                            {
+                                let originator = OriginatorInfo {
+                                    address: d.originator_address.clone(),
+                                    port: d.originator_port,
+                                };
+                                // Like channel data, wait for the receiver to
+                                // make room rather than dropping the channel.
+                                if let Err(e) = sender.send((channel, originator)).await {
+                                    warn!("could not deliver forwarded channel: {e:?}");
+                                    self.close(id)?;
+                                }
+                            } else {
+                                client
+                                    .server_channel_open_forwarded_tcpip(
+                                        channel,
+                                        &d.host_to_connect,
+                                        d.port_to_connect,
+                                        &d.originator_address,
+                                        d.originator_port,
+                                        self,
+                                    )
+                                    .await?
+                            }
                         }
                         ChannelType::ForwardedStreamLocal(d) => {
                             confirm()?;
                             let channel = self.accept_server_initiated_channel(id, &msg);
-                            client
-                                .server_channel_open_forwarded_streamlocal(
-                                    channel,
-                                    &d.socket_path,
-                                    self,
-                                )
-                                .await?;
+                            if let Some(sender) =
+                                self.remote_unix_forwards.get(&d.socket_path).cloned()
+                            {
+                                if let Err(e) = sender.send(channel).await {
+                                    warn!("could not deliver forwarded channel: {e:?}");
+                                    self.close(id)?;
+                                }
+                            } else {
+                                client
+                                    .server_channel_open_forwarded_streamlocal(
+                                        channel,
+                                        &d.socket_path,
+                                        self,
+                                    )
+                                    .await?;
+                            }
                         }
                         ChannelType::AgentForward => {
                             confirm()?;
                             let channel = self.accept_server_initiated_channel(id, &msg);
-                            client
-                                .server_channel_open_agent_forward(channel, self)
-                                .await?
+                            if let Some(sender) = self.agent_forward.clone() {
+                                if let Err(e) = sender.send(channel).await {
+                                    warn!("could not deliver agent channel: {e:?}");
+                                    self.close(id)?;
+                                }
+                            } else {
+                                client
+                                    .server_channel_open_agent_forward(channel, self)
+                                    .await?
+                            }
+                        }
+                        ChannelType::Tun { .. } => {
+                            debug!("rejecting tunnel channel opened by the server");
+                            msg.unknown_type(&mut enc.write)?;
                         }
-                        ChannelType::Unknown { typ } => {
+                        ChannelType::DirectStreamLocal(_) => {
+                            debug!("rejecting direct-streamlocal channel opened by the server");
+                            msg.unknown_type(&mut enc.write)?;
+                        }
+                        ChannelType::Unknown { typ, data } => {
                             if client.should_accept_unknown_server_channel(id, typ).await {
                                 confirm()?;
                                 let channel = self.accept_server_initiated_channel(id, &msg);
-                                client.server_channel_open_unknown(channel, self).await?;
+                                client
+                                    .server_channel_open_unknown(channel, typ, data, self)
+                                    .await?;
                             } else {
                                 debug!("unknown channel type: {typ}");
                                 msg.unknown_type(&mut enc.write)?;
@@ -755,6 +896,17 @@ impl Session {
                     Some(GlobalRequestResponse::CancelStreamLocalForward(return_channel)) => {
                         let _ = return_channel.send(true);
                     }
+                    Some(GlobalRequestResponse::Generic(return_channel)) => {
+                        let _ = return_channel.send(Some(r.to_vec()));
+                    }
+                    Some(GlobalRequestResponse::HostKeysProve(keys)) => {
+                        match self.check_host_key_proofs(keys, &mut r) {
+                            Ok(keys) => {
+                                return client.openssh_ext_host_keys_proven(keys, self).await
+                            }
+                            Err(e) => warn!("invalid host key proofs: {e:?}"),
+                        }
+                    }
                     None => {
                         error!("Received global request failure for unknown request!")
                     }
@@ -779,12 +931,28 @@ impl Session {
                     Some(GlobalRequestResponse::CancelStreamLocalForward(return_channel)) => {
                         let _ = return_channel.send(false);
                     }
+                    Some(GlobalRequestResponse::HostKeysProve(_)) => {
+                        debug!("the server refused to prove its host keys")
+                    }
+                    Some(GlobalRequestResponse::Generic(return_channel)) => {
+                        let _ = return_channel.send(None);
+                    }
                     None => {
                         error!("Received global request failure for unknown request!")
                     }
                 }
                 Ok(())
             }
+            Some((&msg::PONG, _)) => {
+                trace!("pong");
+                match self.open_pings.pop_front() {
+                    Some(reply_channel) => {
+                        let _ = reply_channel.send(Ok(()));
+                    }
+                    None => debug!("unsolicited pong"),
+                }
+                Ok(())
+            }
             m => {
                 debug!("unknown message received: {:?}", m);
                 Ok(())
@@ -792,6 +960,60 @@ impl Session {
         }
     }
 
+    /// Ask the server to sign each of `keys` with a
+    /// `hostkeys-prove-00@openssh.com` request.
+    fn prove_host_keys(&mut self, keys: Vec<PublicKey>) -> Result<(), crate::Error> {
+        if let Some(ref mut enc) = self.common.encrypted {
+            push_packet!(enc.write, {
+                msg::GLOBAL_REQUEST.encode(&mut enc.write)?;
+                "hostkeys-prove-00@openssh.com".encode(&mut enc.write)?;
+                1u8.encode(&mut enc.write)?;
+                for key in &keys {
+                    key.to_bytes()?.encode(&mut enc.write)?;
+                }
+            });
+            self.open_global_requests
+                .push_back(GlobalRequestResponse::HostKeysProve(keys));
+        }
+        Ok(())
+    }
+
+    /// Check the signatures of a reply to `hostkeys-prove-00@openssh.com`.
+    /// As in OpenSSH, a single invalid signature invalidates them all.
+    fn check_host_key_proofs(
+        &self,
+        keys: Vec<PublicKey>,
+        r: &mut &[u8],
+    ) -> Result<Vec<PublicKey>, crate::Error> {
+        let Some(ref enc) = self.common.encrypted else {
+            return Err(crate::Error::Inconsistent);
+        };
+        for key in &keys {
+            let signature = Bytes::decode(r)?;
+            let signature = Signature::decode(&mut &signature[..])?;
+            // SHA-1 RSA signatures are not accepted for proofs.
+            if signature.algorithm() == (Algorithm::Rsa { hash: None }) {
+                return Err(crate::Error::WrongServerSig);
+            }
+            let data = host_key_proof_data(&enc.session_id, &key.to_bytes()?)?;
+            if Verifier::verify(key, &data, &signature).is_err() {
+                return Err(crate::Error::WrongServerSig);
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Find the remote forward registered for a `forwarded-tcpip`
+    /// channel: the one for its exact address and port or, while the
+    /// reply to a request for a server-allocated port is pending, the one
+    /// registered for port 0 on that address.
+    fn remote_forward_sender(&self, address: &str, port: u32) -> Option<ForwardedChannelSender> {
+        self.remote_forwards
+            .get(&(address.to_string(), port))
+            .or_else(|| self.remote_forwards.get(&(address.to_string(), 0)))
+            .cloned()
+    }
+
     fn accept_server_initiated_channel(
         &mut self,
         id: ChannelId,
@@ -803,6 +1025,7 @@ impl Session {
             msg.recipient_maximum_packet_size,
             msg.recipient_window_size,
             self.common.config.channel_buffer_size,
+            self.limiters.clone(),
         );
 
         self.channels.insert(id, channel_ref);
@@ -878,6 +1101,18 @@ impl Encrypted {
                     password.encode(&mut self.write)?;
                     true
                 }
+                auth::Method::PasswordChange {
+                    ref old_password,
+                    ref new_password,
+                } => {
+                    user.encode(&mut self.write)?;
+                    "ssh-connection".encode(&mut self.write)?;
+                    "password".encode(&mut self.write)?;
+                    1u8.encode(&mut self.write)?;
+                    old_password.encode(&mut self.write)?;
+                    new_password.encode(&mut self.write)?;
+                    true
+                }
                 auth::Method::PublicKey { ref key } => {
                     user.encode(&mut self.write)?;
                     "ssh-connection".encode(&mut self.write)?;
@@ -916,6 +1151,55 @@ impl Encrypted {
                     key.to_bytes()?.as_slice().encode(&mut self.write)?;
                     true
                 }
+                auth::Method::FutureCertificate { ref cert } => {
+                    user.as_bytes().encode(&mut self.write)?;
+                    "ssh-connection".encode(&mut self.write)?;
+                    "publickey".encode(&mut self.write)?;
+                    self.write.push(0); // This is a probe
+
+                    cert.algorithm()
+                        .to_certificate_type()
+                        .encode(&mut self.write)?;
+                    cert.to_bytes()?.as_slice().encode(&mut self.write)?;
+                    true
+                }
+                auth::Method::HostBased {
+                    ref key,
+                    ref client_host,
+                    ref client_user,
+                } => {
+                    // The signature covers the session identifier and the
+                    // whole request up to this point.
+                    let mut to_sign = CryptoVec::new();
+                    self.session_id.as_ref().encode(&mut to_sign)?;
+                    let i0 = to_sign.len();
+                    to_sign.push(msg::USERAUTH_REQUEST);
+                    user.encode(&mut to_sign)?;
+                    "ssh-connection".encode(&mut to_sign)?;
+                    "hostbased".encode(&mut to_sign)?;
+                    key.algorithm().as_str().encode(&mut to_sign)?;
+                    key.public_key().to_bytes()?.encode(&mut to_sign)?;
+                    client_host.encode(&mut to_sign)?;
+                    client_user.encode(&mut to_sign)?;
+
+                    debug!("write_auth_request: hostbased - {:?}", key.algorithm());
+                    #[allow(clippy::indexing_slicing)] // length checked
+                    self.write.extend(&to_sign[i0 + 1..]);
+                    sign_with_hash_alg(key, &to_sign)?.encode(&mut self.write)?;
+                    true
+                }
+                #[cfg(feature = "gssapi")]
+                auth::Method::GssapiWithMic(ref gssapi) => {
+                    user.encode(&mut self.write)?;
+                    "ssh-connection".encode(&mut self.write)?;
+                    "gssapi-with-mic".encode(&mut self.write)?;
+                    let mechanisms = gssapi.context.mechanisms();
+                    (mechanisms.len() as u32).encode(&mut self.write)?;
+                    for mechanism in mechanisms {
+                        mechanism.as_slice().encode(&mut self.write)?;
+                    }
+                    true
+                }
                 auth::Method::KeyboardInteractive { ref submethods } => {
                     debug!("Keyboard interactive");
                     user.as_bytes().encode(&mut self.write)?;
@@ -999,6 +1283,41 @@ impl Encrypted {
         Ok(())
     }
 
+    /// Advance the GSSAPI context, sending the token it produced, then the
+    /// MIC once the context is established.
+    #[cfg(feature = "gssapi")]
+    fn client_gssapi_step(
+        &mut self,
+        user: &str,
+        gssapi: &mut crate::client::gssapi::GssapiAuth,
+        input: Option<&[u8]>,
+    ) -> Result<(), crate::Error> {
+        let Some(ref mechanism) = gssapi.mechanism else {
+            return Err(crate::Error::Inconsistent);
+        };
+        let step = gssapi.context.step(mechanism, input)?;
+        if let Some(token) = step.token {
+            push_packet!(self.write, {
+                self.write.push(msg::USERAUTH_GSSAPI_TOKEN);
+                token.as_slice().encode(&mut self.write)?;
+            })
+        }
+        if step.complete {
+            let mut data = CryptoVec::new();
+            self.session_id.as_ref().encode(&mut data)?;
+            data.push(msg::USERAUTH_REQUEST);
+            user.encode(&mut data)?;
+            "ssh-connection".encode(&mut data)?;
+            "gssapi-with-mic".encode(&mut data)?;
+            let mic = gssapi.context.get_mic(&data)?;
+            push_packet!(self.write, {
+                self.write.push(msg::USERAUTH_GSSAPI_MIC);
+                mic.as_slice().encode(&mut self.write)?;
+            })
+        }
+        Ok(())
+    }
+
     fn client_send_auth_response(&mut self, responses: &[String]) -> Result<(), crate::Error> {
         push_packet!(self.write, {
             msg::USERAUTH_INFO_RESPONSE.encode(&mut self.write)?;
@@ -0,0 +1,134 @@
+//! Running a command and collecting its output in one call.
+
+use std::time::Duration;
+
+use super::{Handle, Handler, Msg};
+use crate::{Channel, ChannelMsg, Sig};
+
+/// Options of [`Handle::exec_collect_with`].
+#[derive(Debug, Clone, Default)]
+pub struct ExecOptions {
+    /// Give up, and close the channel, if the command has not exited
+    /// after this long.
+    pub timeout: Option<Duration>,
+    /// Give up, and close the channel, once stdout or stderr exceeds
+    /// this many bytes.
+    pub max_output: Option<usize>,
+}
+
+/// How a remote command exited.
+#[derive(Debug, Clone)]
+pub enum ExitStatus {
+    Code(u32),
+    Signal {
+        signal_name: Sig,
+        core_dumped: bool,
+        error_message: String,
+    },
+    /// The channel was closed without an exit status or signal.
+    Unknown,
+}
+
+impl ExitStatus {
+    /// Whether the command exited with code 0.
+    pub fn success(&self) -> bool {
+        matches!(self, ExitStatus::Code(0))
+    }
+}
+
+/// The output of a command run with [`Handle::exec_collect`].
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit: ExitStatus,
+}
+
+impl<H: Handler> Handle<H> {
+    /// Run `command` on a new session channel, and wait for it to exit
+    /// and close the channel.
+    pub async fn exec_collect<A: Into<Vec<u8>>>(
+        &self,
+        command: A,
+    ) -> Result<CommandOutput, crate::Error> {
+        self.exec_collect_with(command, ExecOptions::default())
+            .await
+    }
+
+    /// Like [`Handle::exec_collect`], with a timeout and a limit on the
+    /// size of the output. If either is exceeded, the channel is closed
+    /// and [`Error::Elapsed`](crate::Error::Elapsed) or
+    /// [`Error::OutputLimitExceeded`](crate::Error::OutputLimitExceeded)
+    /// is returned.
+    pub async fn exec_collect_with<A: Into<Vec<u8>>>(
+        &self,
+        command: A,
+        options: ExecOptions,
+    ) -> Result<CommandOutput, crate::Error> {
+        let mut channel = self.channel_open_session().await?;
+        let result = match options.timeout {
+            Some(timeout) => {
+                tokio::time::timeout(timeout, collect(&mut channel, command, options.max_output))
+                    .await
+                    .map_err(crate::Error::from)
+                    .and_then(|r| r)
+            }
+            None => collect(&mut channel, command, options.max_output).await,
+        };
+        if result.is_err() {
+            let _ = channel.close().await;
+        }
+        result
+    }
+}
+
+async fn collect<A: Into<Vec<u8>>>(
+    channel: &mut Channel<Msg>,
+    command: A,
+    max_output: Option<usize>,
+) -> Result<CommandOutput, crate::Error> {
+    channel.exec(true, command).await?;
+    let mut output = CommandOutput {
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        exit: ExitStatus::Unknown,
+    };
+    let check = |buf: &Vec<u8>| match max_output {
+        Some(max) if buf.len() > max => Err(crate::Error::OutputLimitExceeded),
+        _ => Ok(()),
+    };
+    // The exit status can be sent before or after EOF, and data can still
+    // arrive after the exit status: only the channel closing ends the
+    // command.
+    while let Some(msg) = channel.wait().await {
+        match msg {
+            ChannelMsg::Data { data } => {
+                output.stdout.extend_from_slice(&data);
+                check(&output.stdout)?;
+            }
+            ChannelMsg::ExtendedData { data, ext: 1 } => {
+                output.stderr.extend_from_slice(&data);
+                check(&output.stderr)?;
+            }
+            ChannelMsg::ExitStatus { exit_status } => {
+                output.exit = ExitStatus::Code(exit_status);
+            }
+            ChannelMsg::ExitSignal {
+                signal_name,
+                core_dumped,
+                error_message,
+                ..
+            } => {
+                output.exit = ExitStatus::Signal {
+                    signal_name,
+                    core_dumped,
+                    error_message,
+                };
+            }
+            ChannelMsg::Failure => return Err(crate::Error::RequestDenied),
+            ChannelMsg::Close => break,
+            _ => {}
+        }
+    }
+    Ok(output)
+}
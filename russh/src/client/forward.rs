@@ -0,0 +1,654 @@
+//! Managed port, Unix socket and agent forwarding on top of [`Handle`].
+
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{Future, Stream};
+use log::{debug, error, warn};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::oneshot;
+
+#[cfg(unix)]
+use super::channel_open_direct_streamlocal;
+use super::{channel_open_direct_tcpip, Handle, Handler, Msg, OriginatorInfo};
+use crate::channels::ConnectionLimiters;
+use crate::keys::agent::client::AgentClient;
+use crate::keys::agent::SessionBind;
+use crate::Channel;
+
+/// A local port forward started by [`Handle::forward_local`].
+///
+/// The listener stays open for as long as this guard is alive. Dropping
+/// it stops accepting new connections; connections that are already
+/// being forwarded run to completion.
+#[derive(Debug)]
+pub struct LocalForward {
+    pub(super) local_addr: SocketAddr,
+    pub(super) task: tokio::task::JoinHandle<()>,
+}
+
+impl LocalForward {
+    /// The address the listener is bound to. This is useful to find out
+    /// which port was allocated when binding to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Returns true if the listener has stopped, either because the
+    /// connection was closed or because accepting failed.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl Drop for LocalForward {
+    fn drop(&mut self) {
+        debug!("stopping local forward on {}", self.local_addr);
+        self.task.abort()
+    }
+}
+
+impl<H: Handler> Handle<H> {
+    /// Listen on `local_addr` and forward every accepted TCP connection
+    /// to `remote_host:remote_port` through a `direct-tcpip` channel, like
+    /// OpenSSH's `-L`. The forward is cancelled when the returned guard
+    /// is dropped.
+    pub async fn forward_local<A: ToSocketAddrs, B: Into<String>>(
+        &self,
+        local_addr: A,
+        remote_host: B,
+        remote_port: u32,
+    ) -> Result<LocalForward, crate::Error> {
+        let listener = TcpListener::bind(local_addr).await?;
+        let local_addr = listener.local_addr()?;
+        let task = tokio::spawn(accept_local(
+            listener,
+            self.sender.clone(),
+            self.channel_buffer_size,
+            self.limiters.clone(),
+            remote_host.into(),
+            remote_port,
+        ));
+        Ok(LocalForward { local_addr, task })
+    }
+
+    /// Ask the server to listen on `address:port` (like OpenSSH's `-R`)
+    /// and return the `forwarded-tcpip` channels it opens for that
+    /// listener as a [`Stream`]. Channels for registered forwards are
+    /// not passed to [`Handler::server_channel_open_forwarded_tcpip`].
+    ///
+    /// If `port` is 0, the server picks a port, which is available from
+    /// [`RemoteForward::port`]. The forward is cancelled with
+    /// `cancel-tcpip-forward` when the returned value is dropped.
+    pub async fn forward_remote<A: Into<String>>(
+        &self,
+        address: A,
+        port: u32,
+    ) -> Result<RemoteForward, crate::Error> {
+        let address = address.into();
+        let (channel_sender, receiver) = channel(self.channel_buffer_size);
+        // Register before sending the request so that no channel can be
+        // opened before we are ready to receive it. When the server picks
+        // the port, this entry stands in until the reply is known.
+        self.send_msg(Msg::RegisterRemoteForward {
+            address: address.clone(),
+            port,
+            channel_sender: channel_sender.clone(),
+        })
+        .await?;
+
+        let (reply_send, reply_recv) = oneshot::channel();
+        self.send_msg(Msg::TcpIpForward {
+            reply_channel: Some(reply_send),
+            address: address.clone(),
+            port,
+        })
+        .await?;
+
+        let allocated = match reply_recv.await {
+            Ok(Some(allocated)) => allocated,
+            Ok(None) => {
+                self.send_msg(Msg::UnregisterRemoteForward { address, port })
+                    .await?;
+                return Err(crate::Error::RequestDenied);
+            }
+            Err(e) => {
+                error!("Unable to receive TcpIpForward result: {e:?}");
+                return Err(crate::Error::Disconnect);
+            }
+        };
+        let port = if port == 0 && allocated != 0 {
+            self.send_msg(Msg::RegisterRemoteForward {
+                address: address.clone(),
+                port: allocated,
+                channel_sender,
+            })
+            .await?;
+            self.send_msg(Msg::UnregisterRemoteForward {
+                address: address.clone(),
+                port: 0,
+            })
+            .await?;
+            allocated
+        } else {
+            port
+        };
+
+        Ok(RemoteForward {
+            address,
+            port,
+            receiver,
+            sender: self.sender.clone(),
+        })
+    }
+
+    /// Listen on the Unix socket `local_path` and forward every accepted
+    /// connection to the socket `remote_socket_path` of the server through
+    /// a `direct-streamlocal@openssh.com` channel, like OpenSSH's
+    /// `-L /local:/remote`. The forward is cancelled, and the socket file
+    /// removed, when the returned guard is dropped.
+    #[cfg(unix)]
+    pub async fn forward_local_unix<P: Into<PathBuf>, S: Into<String>>(
+        &self,
+        local_path: P,
+        remote_socket_path: S,
+    ) -> Result<LocalUnixForward, crate::Error> {
+        let local_path = local_path.into();
+        let listener = tokio::net::UnixListener::bind(&local_path)?;
+        let task = tokio::spawn(accept_local_unix(
+            listener,
+            self.sender.clone(),
+            self.channel_buffer_size,
+            self.limiters.clone(),
+            remote_socket_path.into(),
+        ));
+        Ok(LocalUnixForward { local_path, task })
+    }
+
+    /// Ask the server to listen on the Unix socket `socket_path` (like
+    /// OpenSSH's `-R /remote:/local`) and return the
+    /// `forwarded-streamlocal@openssh.com` channels it opens for that
+    /// socket. Channels for registered forwards are not passed to
+    /// [`Handler::server_channel_open_forwarded_streamlocal`].
+    ///
+    /// The forward is cancelled with `cancel-streamlocal-forward@openssh.com`
+    /// when the returned value is dropped.
+    #[cfg(unix)]
+    pub async fn forward_remote_unix<S: Into<String>>(
+        &self,
+        socket_path: S,
+    ) -> Result<RemoteUnixForward, crate::Error> {
+        let socket_path = socket_path.into();
+        let (channel_sender, receiver) = channel(self.channel_buffer_size);
+        self.send_msg(Msg::RegisterRemoteUnixForward {
+            socket_path: socket_path.clone(),
+            channel_sender,
+        })
+        .await?;
+
+        let (reply_send, reply_recv) = oneshot::channel();
+        self.send_msg(Msg::StreamLocalForward {
+            reply_channel: Some(reply_send),
+            socket_path: socket_path.clone(),
+        })
+        .await?;
+        match reply_recv.await {
+            Ok(true) => {}
+            Ok(false) => {
+                self.send_msg(Msg::UnregisterRemoteUnixForward { socket_path })
+                    .await?;
+                return Err(crate::Error::RequestDenied);
+            }
+            Err(e) => {
+                error!("Unable to receive StreamLocalForward result: {e:?}");
+                return Err(crate::Error::Disconnect);
+            }
+        }
+
+        Ok(RemoteUnixForward {
+            socket_path,
+            receiver,
+            sender: self.sender.clone(),
+        })
+    }
+
+    /// Request agent forwarding on the session channel `channel`
+    /// (`auth-agent-req@openssh.com`, like OpenSSH's `-A`), and bridge
+    /// the agent channels opened by the server to the local agent, found
+    /// with `SSH_AUTH_SOCK`.
+    ///
+    /// Agent channels are not passed to
+    /// [`Handler::server_channel_open_agent_forward`] while the returned
+    /// guard is alive.
+    #[cfg(unix)]
+    pub async fn forward_agent(
+        &self,
+        channel: &Channel<Msg>,
+    ) -> Result<AgentForward, crate::Error> {
+        let agent_path = std::env::var("SSH_AUTH_SOCK")
+            .map_err(|_| crate::keys::Error::EnvVar("SSH_AUTH_SOCK"))?;
+        self.forward_agent_to(channel, agent_path).await
+    }
+
+    /// Same as [`Handle::forward_agent`], with the local agent listening
+    /// on `agent_path`.
+    #[cfg(unix)]
+    pub async fn forward_agent_to<P: Into<PathBuf>>(
+        &self,
+        channel: &Channel<Msg>,
+        agent_path: P,
+    ) -> Result<AgentForward, crate::Error> {
+        let agent_path = agent_path.into();
+        self.forward_agent_with(channel, move || {
+            tokio::net::UnixStream::connect(agent_path.clone())
+        })
+        .await
+    }
+
+    /// Same as [`Handle::forward_agent`], with a connection to the
+    /// agent opened by `connect` for each agent channel. The agent can
+    /// be reached over any stream, for instance a TCP connection to a
+    /// proxied agent socket.
+    pub async fn forward_agent_with<F, Fut, S>(
+        &self,
+        channel: &Channel<Msg>,
+        connect: F,
+    ) -> Result<AgentForward, crate::Error>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = std::io::Result<S>> + Send + 'static,
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (channel_sender, receiver) = tokio::sync::mpsc::channel(self.channel_buffer_size);
+        self.send_msg(Msg::RegisterAgentForward { channel_sender })
+            .await?;
+        let bind = self.session_bind().await?;
+        channel.agent_forward(false).await?;
+        let task = tokio::spawn(accept_agent(receiver, connect, bind));
+        Ok(AgentForward {
+            task,
+            sender: self.sender.clone(),
+        })
+    }
+
+    async fn send_msg(&self, msg: Msg) -> Result<(), crate::Error> {
+        self.sender
+            .send(msg)
+            .await
+            .map_err(|_| crate::Error::SendError)
+    }
+}
+
+/// A remote port forward started by [`Handle::forward_remote`], yielding
+/// the channels opened by the server for each connection it accepts.
+#[derive(Debug)]
+pub struct RemoteForward {
+    address: String,
+    port: u32,
+    receiver: Receiver<(Channel<Msg>, OriginatorInfo)>,
+    sender: Sender<Msg>,
+}
+
+impl RemoteForward {
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The port the server is listening on.
+    pub fn port(&self) -> u32 {
+        self.port
+    }
+
+    /// Wait for the next forwarded connection. Returns `None` once the
+    /// session is closed.
+    pub async fn accept(&mut self) -> Option<(Channel<Msg>, OriginatorInfo)> {
+        self.receiver.recv().await
+    }
+
+    /// Connect every forwarded connection to `local_addr` and copy data
+    /// in both directions, until the session is closed.
+    pub async fn forward_to<A: ToSocketAddrs + Clone + Send + 'static>(mut self, local_addr: A) {
+        while let Some((channel, originator)) = self.accept().await {
+            let local_addr = local_addr.clone();
+            tokio::spawn(async move {
+                let mut stream = match TcpStream::connect(local_addr).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("remote forward: could not connect for {originator:?}: {e:?}");
+                        channel.close().await.unwrap_or(());
+                        return;
+                    }
+                };
+                let mut channel_stream = channel.into_stream();
+                if let Err(e) =
+                    tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await
+                {
+                    debug!("remote forward: connection from {originator:?} ended: {e:?}");
+                }
+            });
+        }
+    }
+}
+
+impl Stream for RemoteForward {
+    type Item = (Channel<Msg>, OriginatorInfo);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for RemoteForward {
+    fn drop(&mut self) {
+        debug!(
+            "cancelling remote forward on {}:{}",
+            self.address, self.port
+        );
+        let address = std::mem::take(&mut self.address);
+        let port = self.port;
+        let sender = self.sender.clone();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = sender
+                    .send(Msg::UnregisterRemoteForward {
+                        address: address.clone(),
+                        port,
+                    })
+                    .await;
+                let _ = sender
+                    .send(Msg::CancelTcpIpForward {
+                        reply_channel: None,
+                        address,
+                        port,
+                    })
+                    .await;
+            });
+        }
+    }
+}
+
+/// A local Unix socket forward started by [`Handle::forward_local_unix`].
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct LocalUnixForward {
+    local_path: PathBuf,
+    task: tokio::task::JoinHandle<()>,
+}
+
+#[cfg(unix)]
+impl LocalUnixForward {
+    pub fn local_path(&self) -> &std::path::Path {
+        &self.local_path
+    }
+
+    /// Returns true if the listener has stopped, either because the
+    /// connection was closed or because accepting failed.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+#[cfg(unix)]
+impl Drop for LocalUnixForward {
+    fn drop(&mut self) {
+        debug!("stopping local forward on {:?}", self.local_path);
+        self.task.abort();
+        let _ = std::fs::remove_file(&self.local_path);
+    }
+}
+
+/// A remote Unix socket forward started by
+/// [`Handle::forward_remote_unix`], yielding the channels opened by the
+/// server for each connection it accepts.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct RemoteUnixForward {
+    socket_path: String,
+    receiver: Receiver<Channel<Msg>>,
+    sender: Sender<Msg>,
+}
+
+#[cfg(unix)]
+impl RemoteUnixForward {
+    /// The socket path on the server.
+    pub fn socket_path(&self) -> &str {
+        &self.socket_path
+    }
+
+    /// Wait for the next forwarded connection. Returns `None` once the
+    /// session is closed.
+    pub async fn accept(&mut self) -> Option<Channel<Msg>> {
+        self.receiver.recv().await
+    }
+
+    /// Connect every forwarded connection to the Unix socket `local_path`
+    /// and copy data in both directions, until the session is closed.
+    pub async fn forward_to<P: Into<PathBuf>>(mut self, local_path: P) {
+        let local_path = local_path.into();
+        while let Some(channel) = self.accept().await {
+            let local_path = local_path.clone();
+            tokio::spawn(async move {
+                let mut stream = match tokio::net::UnixStream::connect(&local_path).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("remote forward: could not connect to {local_path:?}: {e:?}");
+                        channel.close().await.unwrap_or(());
+                        return;
+                    }
+                };
+                let mut channel_stream = channel.into_stream();
+                if let Err(e) =
+                    tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await
+                {
+                    debug!("remote forward: connection to {local_path:?} ended: {e:?}");
+                }
+            });
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Stream for RemoteUnixForward {
+    type Item = Channel<Msg>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RemoteUnixForward {
+    fn drop(&mut self) {
+        debug!("cancelling remote forward on {}", self.socket_path);
+        let socket_path = std::mem::take(&mut self.socket_path);
+        let sender = self.sender.clone();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = sender
+                    .send(Msg::UnregisterRemoteUnixForward {
+                        socket_path: socket_path.clone(),
+                    })
+                    .await;
+                let _ = sender
+                    .send(Msg::CancelStreamLocalForward {
+                        reply_channel: None,
+                        socket_path,
+                    })
+                    .await;
+            });
+        }
+    }
+}
+
+/// Agent forwarding started by [`Handle::forward_agent`]. Agent channels
+/// opened by the server are bridged to the local agent for as long as
+/// this guard is alive.
+#[derive(Debug)]
+pub struct AgentForward {
+    task: tokio::task::JoinHandle<()>,
+    sender: Sender<Msg>,
+}
+
+impl Drop for AgentForward {
+    fn drop(&mut self) {
+        debug!("stopping agent forward");
+        self.task.abort();
+        let sender = self.sender.clone();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = sender.send(Msg::UnregisterAgentForward).await;
+            });
+        }
+    }
+}
+
+async fn accept_agent<F, Fut, S>(
+    mut receiver: Receiver<Channel<Msg>>,
+    connect: F,
+    bind: SessionBind,
+) where
+    F: Fn() -> Fut,
+    Fut: Future<Output = std::io::Result<S>> + Send + 'static,
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    while let Some(channel) = receiver.recv().await {
+        let stream = connect();
+        let bind = bind.clone();
+        tokio::spawn(async move {
+            let stream = match stream.await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("agent forward: could not connect to the agent: {e:?}");
+                    channel.close().await.unwrap_or(());
+                    return;
+                }
+            };
+            // Tell the agent it is forwarded over this connection, as
+            // OpenSSH does, so it can enforce destination constraints.
+            let mut agent = AgentClient::connect(stream);
+            if let Err(e) = agent.bind_session(&bind, true).await {
+                debug!("agent forward: could not bind the agent to the session: {e}");
+            }
+            let mut stream = agent.into_inner();
+            let mut channel_stream = channel.into_stream();
+            if let Err(e) = tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await {
+                debug!("agent forward: connection ended: {e:?}");
+            }
+        });
+    }
+}
+
+async fn accept_local(
+    listener: TcpListener,
+    sender: Sender<Msg>,
+    channel_buffer_size: usize,
+    limiters: ConnectionLimiters,
+    remote_host: String,
+    remote_port: u32,
+) {
+    loop {
+        let (stream, originator) = match listener.accept().await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("local forward: accept failed: {e:?}");
+                return;
+            }
+        };
+        if sender.is_closed() {
+            debug!("local forward: session closed");
+            return;
+        }
+        tokio::spawn(bridge_local(
+            stream,
+            originator,
+            sender.clone(),
+            channel_buffer_size,
+            limiters.clone(),
+            remote_host.clone(),
+            remote_port,
+        ));
+    }
+}
+
+async fn bridge_local(
+    mut stream: TcpStream,
+    originator: SocketAddr,
+    sender: Sender<Msg>,
+    channel_buffer_size: usize,
+    limiters: ConnectionLimiters,
+    remote_host: String,
+    remote_port: u32,
+) {
+    let channel = match channel_open_direct_tcpip(
+        sender,
+        channel_buffer_size,
+        limiters.clone(),
+        remote_host,
+        remote_port,
+        originator.ip().to_string(),
+        originator.port().into(),
+    )
+    .await
+    {
+        Ok(channel) => channel,
+        Err(e) => {
+            warn!("local forward: could not open channel for {originator}: {e:?}");
+            return;
+        }
+    };
+    let mut channel_stream = channel.into_stream();
+    if let Err(e) = tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await {
+        debug!("local forward: connection from {originator} ended: {e:?}");
+    }
+}
+
+#[cfg(unix)]
+async fn accept_local_unix(
+    listener: tokio::net::UnixListener,
+    sender: Sender<Msg>,
+    channel_buffer_size: usize,
+    limiters: ConnectionLimiters,
+    remote_socket_path: String,
+) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("local forward: accept failed: {e:?}");
+                return;
+            }
+        };
+        if sender.is_closed() {
+            debug!("local forward: session closed");
+            return;
+        }
+        let sender = sender.clone();
+        let limiters = limiters.clone();
+        let remote_socket_path = remote_socket_path.clone();
+        tokio::spawn(async move {
+            let channel = match channel_open_direct_streamlocal(
+                sender,
+                channel_buffer_size,
+                limiters,
+                remote_socket_path.clone(),
+            )
+            .await
+            {
+                Ok(channel) => channel,
+                Err(e) => {
+                    warn!("local forward: could not open channel to {remote_socket_path}: {e:?}");
+                    return;
+                }
+            };
+            let mut channel_stream = channel.into_stream();
+            if let Err(e) = tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await {
+                debug!("local forward: connection to {remote_socket_path} ended: {e:?}");
+            }
+        });
+    }
+}
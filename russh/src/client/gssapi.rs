@@ -0,0 +1,54 @@
+//! `gssapi-with-mic` authentication ([RFC 4462](https://tools.ietf.org/html/rfc4462)).
+//!
+//! Russh does not link to a GSSAPI library itself. Instead, the security
+//! context is provided by an implementation of [`GssapiContext`], usually
+//! a thin wrapper around a binding such as `libgssapi` (MIT Kerberos or
+//! Heimdal) or `sspi` on Windows.
+
+use std::fmt;
+
+/// DER encoding of the Kerberos V5 mechanism OID, 1.2.840.113554.1.2.2.
+pub const KRB5_MECHANISM: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02,
+];
+
+/// What a call to [`GssapiContext::step`] produced.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GssapiStep {
+    /// A token to send to the server, if any.
+    pub token: Option<Vec<u8>>,
+    /// Whether the security context is now established.
+    pub complete: bool,
+}
+
+/// A client-side GSSAPI security context, initiating the exchange with
+/// the server (`gss_init_sec_context`).
+pub trait GssapiContext: Send + Sync {
+    /// The mechanisms to offer, as DER-encoded OIDs (including the tag and
+    /// length bytes), in order of preference.
+    fn mechanisms(&self) -> Vec<Vec<u8>>;
+
+    /// Advance the context for `mechanism`, the one selected by the
+    /// server. `input` is `None` on the first call, and the token received
+    /// from the server afterwards.
+    fn step(&mut self, mechanism: &[u8], input: Option<&[u8]>) -> Result<GssapiStep, crate::Error>;
+
+    /// Compute the integrity code of `message` (`gss_get_mic`), once the
+    /// context is established.
+    fn get_mic(&mut self, message: &[u8]) -> Result<Vec<u8>, crate::Error>;
+}
+
+/// The state of an ongoing `gssapi-with-mic` authentication.
+pub struct GssapiAuth {
+    pub(crate) context: Box<dyn GssapiContext>,
+    /// The mechanism selected by the server.
+    pub(crate) mechanism: Option<Vec<u8>>,
+}
+
+impl fmt::Debug for GssapiAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GssapiAuth")
+            .field("mechanism", &self.mechanism)
+            .finish_non_exhaustive()
+    }
+}
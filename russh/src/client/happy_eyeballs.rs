@@ -0,0 +1,129 @@
+//! Connection racing across the resolved addresses of a host, following
+//! "Happy Eyeballs" ([RFC 8305](https://tools.ietf.org/html/rfc8305)).
+//!
+//! Addresses are sorted by alternating address families, and a new
+//! attempt starts whenever the previous one fails or has not completed
+//! after the connection attempt delay. The first connection to succeed
+//! wins; the others are dropped.
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::debug;
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs};
+
+/// Connect to the first reachable address of `addrs`. If `attempt_delay`
+/// is `None`, addresses are tried one after the other.
+pub(crate) async fn connect<A: ToSocketAddrs>(
+    addrs: A,
+    attempt_delay: Option<Duration>,
+) -> io::Result<TcpStream> {
+    let Some(attempt_delay) = attempt_delay else {
+        return TcpStream::connect(addrs).await;
+    };
+    let mut addrs = interleave(lookup_host(addrs).await?.collect()).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut last_error = None;
+    if let Some(addr) = addrs.next() {
+        attempts.push(attempt(addr));
+    }
+    loop {
+        tokio::select! {
+            Some((addr, result)) = attempts.next() => match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    debug!("connection to {addr} failed: {e:?}");
+                    last_error = Some(e);
+                    if let Some(addr) = addrs.next() {
+                        attempts.push(attempt(addr));
+                    }
+                }
+            },
+            () = tokio::time::sleep(attempt_delay), if !addrs.as_slice().is_empty() => {
+                if let Some(addr) = addrs.next() {
+                    attempts.push(attempt(addr));
+                }
+            }
+            else => break,
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any address",
+        )
+    }))
+}
+
+async fn attempt(addr: SocketAddr) -> (SocketAddr, io::Result<TcpStream>) {
+    debug!("connecting to {addr}");
+    (addr, TcpStream::connect(addr).await)
+}
+
+/// Reorder `addrs` so that address families alternate, starting with the
+/// family of the first address (RFC 8305, section 4). The order within
+/// each family is preserved.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = addrs.first().is_some_and(|a| a.is_ipv6());
+    let (first, second): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|a| a.is_ipv6() == first_is_ipv6);
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    let mut result = Vec::with_capacity(first.len() + second.len());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return result,
+            (a, b) => result.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn interleave_families() {
+        let addrs: Vec<SocketAddr> = [
+            "[::1]:22",
+            "[::2]:22",
+            "[::3]:22",
+            "1.1.1.1:22",
+            "2.2.2.2:22",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        let expected: Vec<SocketAddr> = [
+            "[::1]:22",
+            "1.1.1.1:22",
+            "[::2]:22",
+            "2.2.2.2:22",
+            "[::3]:22",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        assert_eq!(interleave(addrs), expected);
+    }
+
+    #[tokio::test]
+    async fn skips_unreachable_address() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        // Nothing listens on the port of this socket once it is dropped.
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let addrs = [closed, listener.local_addr().unwrap()];
+        let stream = connect(&addrs[..], Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    }
+}
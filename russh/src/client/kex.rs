@@ -38,6 +38,7 @@ enum ClientKexState {
     },
     WaitingForNewKeys {
         server_host_key: PublicKey,
+        host_key_signature: Signature,
         newkeys: NewKeys,
     },
 }
@@ -149,6 +150,7 @@ impl ClientKex {
                     return Ok(KexProgress::Done {
                         newkeys,
                         server_host_key: None,
+                        host_key_signature: None,
                     });
                 }
 
@@ -301,10 +303,11 @@ impl ClientKex {
                     Ok(())
                 })?;
 
-                let reset_seqn = newkeys.names.strict_kex;
+                let reset_seqn = self.cause.is_strict_kex(&newkeys.names);
 
                 self.state = ClientKexState::WaitingForNewKeys {
                     server_host_key,
+                    host_key_signature: signature,
                     newkeys,
                 };
 
@@ -315,6 +318,7 @@ impl ClientKex {
             }
             ClientKexState::WaitingForNewKeys {
                 server_host_key,
+                host_key_signature,
                 newkeys,
             } => {
                 // At this point the exchange is complete
@@ -334,6 +338,7 @@ impl ClientKex {
                 Ok(KexProgress::Done {
                     newkeys,
                     server_host_key: Some(server_host_key),
+                    host_key_signature: Some(host_key_signature),
                 })
             }
         }
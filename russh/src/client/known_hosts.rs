@@ -0,0 +1,582 @@
+//! Verification of server keys against OpenSSH `known_hosts` files.
+//!
+//! Unlike the lower-level functions in [`crate::keys::known_hosts`], this
+//! module understands the whole file format described in `sshd(8)`:
+//! hashed host names, wildcard and negated patterns, port-qualified
+//! entries (`[host]:port`), and the `@cert-authority` and `@revoked`
+//! markers.
+//!
+//! [`KnownHostsVerifier`] provides a ready-made
+//! [`Handler::check_server_key`] implementation:
+//!
+//! ```no_run
+//! # use russh::client::known_hosts::{KnownHosts, KnownHostsVerifier, UnknownHostPolicy};
+//! # async fn run() -> Result<(), russh::Error> {
+//! let known_hosts = KnownHosts::from_default_path()?;
+//! let verifier = KnownHostsVerifier::new(known_hosts, "example.com", 22)
+//!     .policy(UnknownHostPolicy::AcceptNew);
+//! let config = std::sync::Arc::new(russh::client::Config::default());
+//! let session = russh::client::connect(config, ("example.com", 22), verifier).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use data_encoding::{BASE64, BASE64_MIME};
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use rand::RngCore;
+use sha1::Sha1;
+use ssh_key::{Certificate, PublicKey};
+
+use super::Handler;
+use crate::config_file;
+use crate::helpers::wildcard_match;
+use crate::keys::known_hosts::learn_known_hosts_path;
+use crate::keys::{parse_public_key_base64, Error};
+
+/// A marker at the start of a `known_hosts` line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Marker {
+    /// `@cert-authority`: the key is a CA trusted to sign host
+    /// certificates for the matching hosts.
+    CertAuthority,
+    /// `@revoked`: the key must never be accepted.
+    Revoked,
+}
+
+/// A line of a `known_hosts` file.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Line number in the file, starting at 1.
+    pub line: usize,
+    pub marker: Option<Marker>,
+    /// The comma-separated host patterns, as written in the file.
+    pub hosts: String,
+    pub key: PublicKey,
+}
+
+impl Entry {
+    /// Whether this entry applies to `host:port`.
+    pub fn matches(&self, host: &str, port: u16) -> bool {
+        match_patterns(&host_port(host, port), &self.hosts)
+    }
+}
+
+/// The result of looking up a server key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyStatus {
+    /// The key is recorded for this host, at `line`.
+    Known { line: usize },
+    /// No key of this type is recorded for this host.
+    Unknown,
+    /// A different key of the same type is recorded at `line`.
+    Changed { line: usize },
+    /// The key is marked `@revoked` at `line`.
+    Revoked { line: usize },
+}
+
+/// The contents of a `known_hosts` file.
+#[derive(Debug, Clone, Default)]
+pub struct KnownHosts {
+    path: Option<PathBuf>,
+    entries: Vec<Entry>,
+    /// The number of lines of the file, including those which are not
+    /// entries.
+    lines: usize,
+}
+
+impl KnownHosts {
+    /// Parse the contents of a `known_hosts` file. Lines that cannot be
+    /// parsed are skipped, as OpenSSH does.
+    pub fn parse(contents: &str) -> Self {
+        let mut known_hosts = KnownHosts::default();
+        for line in contents.lines() {
+            known_hosts.lines += 1;
+            known_hosts.parse_line(known_hosts.lines, line);
+        }
+        known_hosts
+    }
+
+    /// Load the file at `path`. A missing file is treated as empty, and
+    /// [`KnownHosts::learn`] will create it.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut known_hosts = KnownHosts {
+            path: Some(path.to_path_buf()),
+            ..Default::default()
+        };
+        let f = match File::open(path) {
+            Ok(f) => BufReader::new(f),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(known_hosts),
+            Err(e) => return Err(e.into()),
+        };
+        for line in f.lines() {
+            known_hosts.lines += 1;
+            known_hosts.parse_line(known_hosts.lines, &line?);
+        }
+        Ok(known_hosts)
+    }
+
+    /// Load the user's `known_hosts` file, from its standard location.
+    pub fn from_default_path() -> Result<Self, Error> {
+        Self::from_path(default_path()?)
+    }
+
+    /// The file these entries were loaded from, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    fn parse_line(&mut self, line: usize, contents: &str) {
+        let contents = contents.trim();
+        if contents.is_empty() || contents.starts_with('#') {
+            return;
+        }
+        let mut fields = contents.split_whitespace();
+        let marker = match fields.clone().next() {
+            Some("@cert-authority") => Some(Marker::CertAuthority),
+            Some("@revoked") => Some(Marker::Revoked),
+            Some(m) if m.starts_with('@') => {
+                debug!("known_hosts:{line}: unknown marker {m:?}");
+                return;
+            }
+            _ => None,
+        };
+        if marker.is_some() {
+            fields.next();
+        }
+        let (Some(hosts), Some(_algorithm), Some(key)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            debug!("known_hosts:{line}: missing fields");
+            return;
+        };
+        match parse_public_key_base64(key) {
+            Ok(key) => self.entries.push(Entry {
+                line,
+                marker,
+                hosts: hosts.to_string(),
+                key,
+            }),
+            Err(e) => debug!("known_hosts:{line}: {e:?}"),
+        }
+    }
+
+    /// Look up the key presented by `host:port`.
+    ///
+    /// A revoked key is reported as such whatever the other entries say.
+    /// Otherwise, as in OpenSSH, a key is only considered changed if a
+    /// different key of the same algorithm is recorded.
+    pub fn check(&self, host: &str, port: u16, key: &PublicKey) -> HostKeyStatus {
+        let host_port = host_port(host, port);
+        let mut status = HostKeyStatus::Unknown;
+        for entry in &self.entries {
+            match entry.marker {
+                Some(Marker::Revoked) => {
+                    if entry.key.key_data() == key.key_data()
+                        && match_patterns(&host_port, &entry.hosts)
+                    {
+                        return HostKeyStatus::Revoked { line: entry.line };
+                    }
+                }
+                Some(Marker::CertAuthority) => {}
+                None => {
+                    if !match_patterns(&host_port, &entry.hosts) {
+                        continue;
+                    }
+                    if entry.key.key_data() == key.key_data() {
+                        status = HostKeyStatus::Known { line: entry.line };
+                    } else if entry.key.algorithm() == key.algorithm()
+                        && status == HostKeyStatus::Unknown
+                    {
+                        status = HostKeyStatus::Changed { line: entry.line };
+                    }
+                }
+            }
+        }
+        status
+    }
+
+    /// Check a host certificate presented by `host:port`: it must be
+    /// signed by a matching `@cert-authority` key, be currently valid, and
+    /// name `host` among its principals. Certificates whose key or CA is
+    /// revoked are reported as such.
+    pub fn check_certificate(
+        &self,
+        host: &str,
+        port: u16,
+        certificate: &Certificate,
+    ) -> HostKeyStatus {
+        let host_port = host_port(host, port);
+        let mut status = HostKeyStatus::Unknown;
+        for entry in &self.entries {
+            match entry.marker {
+                Some(Marker::Revoked) => {
+                    if (entry.key.key_data() == certificate.public_key()
+                        || entry.key.key_data() == certificate.signature_key())
+                        && match_patterns(&host_port, &entry.hosts)
+                    {
+                        return HostKeyStatus::Revoked { line: entry.line };
+                    }
+                }
+                Some(Marker::CertAuthority) => {
+                    if status == HostKeyStatus::Unknown
+                        && entry.key.key_data() == certificate.signature_key()
+                        && match_patterns(&host_port, &entry.hosts)
+                        && is_valid_host_certificate(certificate, host)
+                    {
+                        status = HostKeyStatus::Known { line: entry.line };
+                    }
+                }
+                None => {}
+            }
+        }
+        status
+    }
+
+    /// Record `key` for `host:port`, both in memory and at the end of the
+    /// file this was loaded from (if any). With `hash`, the host name is
+    /// written hashed, like OpenSSH's `HashKnownHosts`.
+    pub fn learn(
+        &mut self,
+        host: &str,
+        port: u16,
+        key: &PublicKey,
+        hash: bool,
+    ) -> Result<(), Error> {
+        let hosts = if hash {
+            hash_hostname(&host_port(host, port))
+        } else {
+            host_port(host, port).into_owned()
+        };
+        self.lines += 1;
+        let line = self.lines;
+        if let Some(ref path) = self.path {
+            // `learn_known_hosts_path` writes the port-qualified name
+            // itself, so it is only used for the plain form.
+            if hash {
+                append_line(path, &hosts, key)?;
+            } else {
+                learn_known_hosts_path(host, port, key, path)?;
+            }
+        }
+        self.entries.push(Entry {
+            line,
+            marker: None,
+            hosts,
+            key: key.clone(),
+        });
+        Ok(())
+    }
+}
+
+fn is_valid_host_certificate(certificate: &Certificate, host: &str) -> bool {
+    let now = SystemTime::now();
+    if certificate.cert_type() != ssh_key::certificate::CertType::Host {
+        warn!("known_hosts: not a host certificate");
+        return false;
+    }
+    if now < certificate.valid_after_time() || now > certificate.valid_before_time() {
+        warn!("known_hosts: certificate is expired or not yet valid");
+        return false;
+    }
+    if certificate.verify_signature().is_err() {
+        warn!("known_hosts: certificate signature is invalid");
+        return false;
+    }
+    let principals = certificate.valid_principals();
+    if !principals.is_empty() && !principals.iter().any(|p| p == host) {
+        warn!("known_hosts: certificate is not valid for {host:?}");
+        return false;
+    }
+    true
+}
+
+/// What [`KnownHostsVerifier`] does with hosts that have no recorded key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownHostPolicy {
+    /// Refuse the key (`StrictHostKeyChecking yes`).
+    #[default]
+    Reject,
+    /// Accept the key and record it (`StrictHostKeyChecking accept-new`).
+    AcceptNew,
+}
+
+/// Checks server keys for one destination against a [`KnownHosts`].
+///
+/// This can be used directly as the [`Handler`] of simple clients, or be
+/// called from the `check_server_key` method of a custom handler.
+#[derive(Debug)]
+pub struct KnownHostsVerifier {
+    known_hosts: KnownHosts,
+    host: String,
+    port: u16,
+    policy: UnknownHostPolicy,
+    hash: bool,
+}
+
+impl KnownHostsVerifier {
+    pub fn new<A: Into<String>>(known_hosts: KnownHosts, host: A, port: u16) -> Self {
+        Self {
+            known_hosts,
+            host: host.into(),
+            port,
+            policy: UnknownHostPolicy::default(),
+            hash: false,
+        }
+    }
+
+    pub fn policy(mut self, policy: UnknownHostPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Hash the host name of newly learned keys.
+    pub fn hash_hostnames(mut self, hash: bool) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    pub fn known_hosts(&self) -> &KnownHosts {
+        &self.known_hosts
+    }
+
+    /// Returns `Ok(true)` if the key is known or was just learned, and
+    /// `Ok(false)` if it is unknown and the policy rejects it. Changed and
+    /// revoked keys are errors, so that they are never silently accepted.
+    pub fn check_server_key(&mut self, key: &PublicKey) -> Result<bool, Error> {
+        match self.known_hosts.check(&self.host, self.port, key) {
+            HostKeyStatus::Known { .. } => Ok(true),
+            HostKeyStatus::Changed { line } => Err(Error::KeyChanged { line }),
+            HostKeyStatus::Revoked { line } => Err(Error::KeyRevoked { line }),
+            HostKeyStatus::Unknown => match self.policy {
+                UnknownHostPolicy::Reject => Ok(false),
+                UnknownHostPolicy::AcceptNew => {
+                    debug!("learning new host key for {}:{}", self.host, self.port);
+                    self.known_hosts
+                        .learn(&self.host, self.port, key, self.hash)?;
+                    Ok(true)
+                }
+            },
+        }
+    }
+}
+
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+impl Handler for KnownHostsVerifier {
+    type Error = crate::Error;
+
+    #[allow(clippy::manual_async_fn)]
+    fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKey,
+    ) -> impl std::future::Future<Output = Result<bool, Self::Error>> + Send {
+        async move {
+            Ok(KnownHostsVerifier::check_server_key(
+                self,
+                server_public_key,
+            )?)
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn default_path() -> Result<PathBuf, Error> {
+    let home_dir = home::home_dir().ok_or(Error::NoHomeDir)?;
+    Ok(home_dir.join("ssh").join("known_hosts"))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn default_path() -> Result<PathBuf, Error> {
+    let home_dir = home::home_dir().ok_or(Error::NoHomeDir)?;
+    Ok(home_dir.join(".ssh").join("known_hosts"))
+}
+
+fn host_port(host: &str, port: u16) -> Cow<'_, str> {
+    if port == 22 {
+        Cow::Borrowed(host)
+    } else {
+        Cow::Owned(format!("[{host}]:{port}"))
+    }
+}
+
+fn hash_hostname(host_port: &str) -> String {
+    let mut salt = [0; 20];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let hash = match Hmac::<Sha1>::new_from_slice(&salt) {
+        Ok(hmac) => hmac.chain_update(host_port).finalize().into_bytes(),
+        // HMAC accepts keys of any length.
+        Err(_) => return host_port.to_string(),
+    };
+    format!("|1|{}|{}", BASE64.encode(&salt), BASE64.encode(&hash))
+}
+
+fn append_line(path: &Path, hosts: &str, key: &PublicKey) -> Result<(), Error> {
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    let mut last = [0; 1];
+    let ends_in_newline = if file.seek(SeekFrom::End(-1)).is_ok() {
+        file.read_exact(&mut last)?;
+        last == *b"\n"
+    } else {
+        true
+    };
+    let mut line = String::new();
+    if !ends_in_newline {
+        line.push('\n');
+    }
+    line.push_str(hosts);
+    line.push(' ');
+    line.push_str(&key.to_openssh()?);
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Match `host` against the patterns of a `known_hosts` line, which may
+/// be hashed.
+pub(super) fn match_patterns(host: &str, patterns: &str) -> bool {
+    config_file::match_patterns(patterns, |pattern| match_pattern(host, pattern))
+}
+
+fn match_pattern(host: &str, pattern: &str) -> bool {
+    if let Some(hashed) = pattern.strip_prefix("|1|") {
+        let Some((salt, hash)) = hashed.split_once('|') else {
+            return false;
+        };
+        let (Ok(salt), Ok(hash)) = (
+            BASE64_MIME.decode(salt.as_bytes()),
+            BASE64_MIME.decode(hash.as_bytes()),
+        ) else {
+            return false;
+        };
+        return Hmac::<Sha1>::new_from_slice(&salt)
+            .map(|hmac| hmac.chain_update(host).verify_slice(&hash).is_ok())
+            .unwrap_or(false);
+    }
+    // Host names are case-insensitive.
+    wildcard_match(
+        host.to_ascii_lowercase().as_bytes(),
+        pattern.to_ascii_lowercase().as_bytes(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used, clippy::indexing_slicing)]
+    use super::*;
+
+    const KEY_A: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIJdD7y3aLq454yWBdwLWbieU1ebz9/cu7/QEXn9OIeZJ";
+    const KEY_B: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIA6rWI3G1sz07DnfFlrouTcysQlj2P+jpNSOEWD9OJ3X";
+    const KEY_C: &str = "AAAAC3NzaC1lZDI1NTE5AAAAILIG2T/B0l0gaqj3puu510tu9N1OkQ4znY3LYuEm5zCF";
+
+    fn key(k: &str) -> PublicKey {
+        parse_public_key_base64(k).unwrap()
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(match_patterns("foo.example.com", "*.example.com"));
+        assert!(match_patterns("web1.example.com", "web?.example.com"));
+        assert!(!match_patterns("web10.example.com", "web?.example.com"));
+        assert!(!match_patterns(
+            "bad.example.com",
+            "*.example.com,!bad.example.com"
+        ));
+        assert!(match_patterns("Example.COM", "example.com"));
+        assert!(match_patterns("[example.com]:2222", "[*.com]:2222"));
+        assert!(!match_patterns("example.com", "[example.com]:2222"));
+    }
+
+    #[test]
+    fn check() {
+        let known_hosts = KnownHosts::parse(&format!(
+            "# comment\n\
+             \n\
+             [localhost]:13265 ssh-ed25519 {KEY_A}\n\
+             pijul.org,37.120.161.53 ssh-ed25519 {KEY_B} comment\n\
+             |1|O33ESRMWPVkMYIwJ1Uw+n877jTo=|nuuC5vEqXlEZ/8BXQR7m619W6Ak= ssh-ed25519 {KEY_C}\n\
+             @revoked * ssh-ed25519 {KEY_C}\n\
+             @cert-authority *.example.com ssh-ed25519 {KEY_A}\n\
+             @unknown * ssh-ed25519 {KEY_A}\n\
+             garbage\n"
+        ));
+        assert_eq!(known_hosts.entries().len(), 5);
+
+        assert_eq!(
+            known_hosts.check("localhost", 13265, &key(KEY_A)),
+            HostKeyStatus::Known { line: 3 }
+        );
+        assert_eq!(
+            known_hosts.check("localhost", 22, &key(KEY_A)),
+            HostKeyStatus::Unknown
+        );
+        assert_eq!(
+            known_hosts.check("37.120.161.53", 22, &key(KEY_B)),
+            HostKeyStatus::Known { line: 4 }
+        );
+        assert_eq!(
+            known_hosts.check("pijul.org", 22, &key(KEY_A)),
+            HostKeyStatus::Changed { line: 4 }
+        );
+        // The hashed entry is for example.com, but its key is revoked.
+        assert_eq!(
+            known_hosts.check("example.com", 22, &key(KEY_C)),
+            HostKeyStatus::Revoked { line: 6 }
+        );
+        // CA keys are not host keys.
+        assert_eq!(
+            known_hosts.check("www.example.com", 22, &key(KEY_A)),
+            HostKeyStatus::Unknown
+        );
+    }
+
+    #[test]
+    fn learn() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        std::fs::write(&path, format!("pijul.org ssh-ed25519 {KEY_B}\n# comment\n")).unwrap();
+
+        let mut verifier =
+            KnownHostsVerifier::new(KnownHosts::from_path(&path).unwrap(), "localhost", 2222);
+        assert!(!verifier.check_server_key(&key(KEY_A)).unwrap());
+
+        let mut verifier = verifier
+            .policy(UnknownHostPolicy::AcceptNew)
+            .hash_hostnames(true);
+        assert!(verifier.check_server_key(&key(KEY_A)).unwrap());
+        assert!(matches!(
+            verifier.check_server_key(&key(KEY_C)),
+            Err(Error::KeyChanged { line: 3 })
+        ));
+
+        let known_hosts = KnownHosts::from_path(&path).unwrap();
+        assert!(known_hosts.entries()[1].hosts.starts_with("|1|"));
+        assert_eq!(
+            known_hosts.check("localhost", 2222, &key(KEY_A)),
+            HostKeyStatus::Known { line: 3 }
+        );
+        assert_eq!(
+            known_hosts.check("pijul.org", 22, &key(KEY_B)),
+            HostKeyStatus::Known { line: 1 }
+        );
+    }
+}
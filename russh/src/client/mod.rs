@@ -35,7 +35,6 @@
 //! [Session]: client::Session
 
 use std::collections::{HashMap, VecDeque};
-use std::convert::TryInto;
 use std::num::Wrapping;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -45,35 +44,66 @@ use futures::Future;
 use kex::ClientKex;
 use log::{debug, error, trace};
 use russh_util::time::Instant;
-use ssh_encoding::Decode;
 use ssh_key::{Algorithm, Certificate, HashAlg, PrivateKey, PublicKey};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::pin;
 use tokio::sync::mpsc::{
     channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender,
 };
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 use tokio::time::Duration;
 
+pub use self::auth_flow::{AgentFilter, Authenticator, Identity};
+pub use self::exec::{CommandOutput, ExecOptions, ExitStatus};
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::forward::{AgentForward, LocalForward, RemoteForward};
+#[cfg(unix)]
+pub use self::forward::{LocalUnixForward, RemoteUnixForward};
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::resilient::{Backoff, ReconnectEvent, ResilientHandle};
 pub use crate::auth::AuthResult;
+use crate::channels::tun::TunMode;
 use crate::channels::{
-    Channel, ChannelMsg, ChannelReadHalf, ChannelRef, ChannelWriteHalf, WindowSizeRef,
+    Channel, ChannelMsg, ChannelReadHalf, ChannelRef, ChannelWriteHalf, ConnectionLimiters,
+    WindowSizeRef,
 };
 use crate::cipher::{self, clear, OpeningKey};
 use crate::kex::{KexCause, KexProgress, SessionKexState};
+use crate::keys::agent::SessionBind;
 use crate::keys::PrivateKeyWithHashAlg;
 use crate::msg::{is_kex_msg, validate_server_msg_strict_kex};
 use crate::session::{CommonSession, EncryptedState, GlobalRequestResponse, NewKeys};
 use crate::ssh_read::SshRead;
 use crate::sshbuffer::{IncomingSshPacket, PacketWriter, SSHBuffer, SshId};
 use crate::{
-    auth, map_err, msg, negotiation, ChannelId, ChannelOpenFailure, CryptoVec, Disconnect, Error,
-    Limits, MethodSet, Sig,
+    auth, map_err, msg, negotiation, ChannelId, ChannelOpenFailure, ConnectionInfo, CryptoVec,
+    Disconnect, Error, Limits, MethodSet, Sig,
 };
+pub use crate::{CloseReason, ExtensionInfo, RemoteDisconnectInfo};
 
+mod auth_flow;
 mod encrypted;
+mod exec;
+#[cfg(not(target_arch = "wasm32"))]
+mod forward;
+#[cfg(feature = "gssapi")]
+pub mod gssapi;
+#[cfg(not(target_arch = "wasm32"))]
+mod happy_eyeballs;
 mod kex;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod known_hosts;
+#[cfg(unix)]
+pub mod mux;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod proxy;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod resilient;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod scp;
 mod session;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod x11;
 
 /// Actual client session's state.
 ///
@@ -93,7 +123,15 @@ pub struct Session {
     inbound_channel_sender: Sender<Msg>,
     inbound_channel_receiver: Receiver<Msg>,
     open_global_requests: VecDeque<GlobalRequestResponse>,
-    server_sig_algs: Option<Vec<Algorithm>>,
+    open_pings: VecDeque<oneshot::Sender<Result<(), crate::Error>>>,
+    extension_info: ExtensionInfo,
+    session_bind: Option<SessionBind>,
+    remote_forwards: HashMap<(String, u32), ForwardedChannelSender>,
+    remote_unix_forwards: HashMap<String, Sender<Channel<Msg>>>,
+    agent_forward: Option<Sender<Channel<Msg>>>,
+    x11_forward: Option<Sender<Channel<Msg>>>,
+    limiters: ConnectionLimiters,
+    closed: watch::Sender<Option<CloseReason>>,
 }
 
 impl Drop for Session {
@@ -120,6 +158,10 @@ enum Reply {
         instructions: String,
         prompts: Vec<Prompt>,
     },
+    AuthPasswordChangeRequest {
+        prompt: String,
+    },
+    AuthBanner(AuthBanner),
 }
 
 #[derive(Debug)]
@@ -149,10 +191,20 @@ pub enum Msg {
         originator_port: u32,
         channel_ref: ChannelRef,
     },
+    ChannelOpenTun {
+        mode: TunMode,
+        remote_unit: u32,
+        channel_ref: ChannelRef,
+    },
     ChannelOpenDirectStreamLocal {
         socket_path: String,
         channel_ref: ChannelRef,
     },
+    ChannelOpenCustom {
+        channel_type: String,
+        data: Vec<u8>,
+        channel_ref: ChannelRef,
+    },
     TcpIpForward {
         /// Provide a channel for the reply result to request a reply from the server
         reply_channel: Option<oneshot::Sender<Option<u32>>>,
@@ -185,6 +237,17 @@ pub enum Msg {
     },
     Channel(ChannelId, ChannelMsg),
     Rekey,
+    RekeyAndNotify {
+        /// Notified when the rekey completes
+        reply_channel: oneshot::Sender<()>,
+    },
+    NoMoreSessions,
+    GlobalRequest {
+        name: String,
+        payload: Vec<u8>,
+        /// Provide a channel for the reply result to request a reply from the other side
+        reply_channel: Option<oneshot::Sender<Option<Vec<u8>>>>,
+    },
     AwaitExtensionInfo {
         extension_name: String,
         reply_channel: oneshot::Sender<()>,
@@ -192,6 +255,52 @@ pub enum Msg {
     GetServerSigAlgs {
         reply_channel: oneshot::Sender<Option<Vec<Algorithm>>>,
     },
+    GetExtensionInfo {
+        reply_channel: oneshot::Sender<ExtensionInfo>,
+    },
+    Ping {
+        reply_channel: oneshot::Sender<Result<(), crate::Error>>,
+    },
+    GetConnectionInfo {
+        reply_channel: oneshot::Sender<Option<ConnectionInfo>>,
+    },
+    GetSessionId {
+        reply_channel: oneshot::Sender<Option<Vec<u8>>>,
+    },
+    GetSessionBind {
+        reply_channel: oneshot::Sender<Option<SessionBind>>,
+    },
+    /// Deliver `forwarded-tcpip` channels for this address and port to
+    /// `channel_sender` instead of the handler.
+    RegisterRemoteForward {
+        address: String,
+        port: u32,
+        channel_sender: Sender<(Channel<Msg>, OriginatorInfo)>,
+    },
+    UnregisterRemoteForward {
+        address: String,
+        port: u32,
+    },
+    /// Deliver `auth-agent@openssh.com` channels to `channel_sender`
+    /// instead of the handler.
+    RegisterAgentForward {
+        channel_sender: Sender<Channel<Msg>>,
+    },
+    UnregisterAgentForward,
+    /// Deliver `forwarded-streamlocal@openssh.com` channels for this
+    /// socket path to `channel_sender` instead of the handler.
+    RegisterRemoteUnixForward {
+        socket_path: String,
+        channel_sender: Sender<Channel<Msg>>,
+    },
+    UnregisterRemoteUnixForward {
+        socket_path: String,
+    },
+    /// Deliver `x11` channels to `channel_sender` instead of the handler.
+    RegisterX11Forward {
+        channel_sender: Sender<Channel<Msg>>,
+    },
+    UnregisterX11Forward,
 }
 
 impl From<(ChannelId, ChannelMsg)> for Msg {
@@ -217,17 +326,28 @@ pub enum KeyboardInteractiveAuthResponse {
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Prompt {
     pub prompt: String,
     pub echo: bool,
 }
 
-#[derive(Debug)]
-pub struct RemoteDisconnectInfo {
-    pub reason_code: crate::Disconnect,
+/// A banner sent by the server during authentication
+/// ([RFC4252](https://tools.ietf.org/html/rfc4252#section-5.4)).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthBanner {
     pub message: String,
-    pub lang_tag: String,
+    /// The language of the message, as in RFC3066. Often empty.
+    pub language_tag: String,
+}
+
+type ForwardedChannelSender = Sender<(Channel<Msg>, OriginatorInfo)>;
+
+/// The remote end of a `forwarded-tcpip` channel, as reported by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginatorInfo {
+    pub address: String,
+    pub port: u32,
 }
 
 #[derive(Debug)]
@@ -243,6 +363,9 @@ pub struct Handle<H: Handler> {
     receiver: UnboundedReceiver<Reply>,
     join: russh_util::runtime::JoinHandle<Result<(), H::Error>>,
     channel_buffer_size: usize,
+    limiters: ConnectionLimiters,
+    auth_banners: Vec<AuthBanner>,
+    closed: watch::Receiver<Option<CloseReason>>,
 }
 
 impl<H: Handler> Drop for Handle<H> {
@@ -252,12 +375,29 @@ impl<H: Handler> Drop for Handle<H> {
 }
 
 impl<H: Handler> Handle<H> {
+    /// Whether the connection has ended. See [`Handle::closed`].
     pub fn is_closed(&self) -> bool {
-        self.sender.is_closed()
+        self.sender.is_closed() || self.closed.borrow().is_some()
     }
 
-    /// Perform no authentication. This is useful for testing, but should not be
-    /// used in most other circumstances.
+    /// Wait for the connection to end, and return why.
+    pub async fn closed(&self) -> CloseReason {
+        crate::session::wait_closed(self.closed.clone()).await
+    }
+
+    /// The banners received from the server so far during
+    /// authentication, in order. They are also passed to
+    /// [`Handler::auth_banner`] as they arrive.
+    pub fn auth_banners(&self) -> &[AuthBanner] {
+        &self.auth_banners
+    }
+
+    /// Perform no authentication. This is mostly useful to ask the server
+    /// which methods it accepts for `user`, with
+    /// [`AuthResult::remaining_methods`], before prompting for
+    /// credentials: servers, including russh, do not count this as a
+    /// failed attempt. Some servers let users in without credentials,
+    /// in which case this succeeds.
     pub async fn authenticate_none<U: Into<String>>(
         &mut self,
         user: U,
@@ -292,6 +432,28 @@ impl<H: Handler> Handle<H> {
         self.wait_recv_reply().await
     }
 
+    /// Change an expired password, after [`Handle::authenticate_password`]
+    /// returned [`AuthResult::PasswordChangeRequired`]. The server may ask
+    /// again if it does not accept the new password.
+    pub async fn authenticate_password_change<U: Into<String>, P: Into<String>, N: Into<String>>(
+        &mut self,
+        user: U,
+        old_password: P,
+        new_password: N,
+    ) -> Result<AuthResult, crate::Error> {
+        self.sender
+            .send(Msg::Authenticate {
+                user: user.into(),
+                method: auth::Method::PasswordChange {
+                    old_password: old_password.into(),
+                    new_password: new_password.into(),
+                },
+            })
+            .await
+            .map_err(|_| crate::Error::SendError)?;
+        self.wait_recv_reply().await
+    }
+
     /// Initiate Keyboard-Interactive based SSH authentication.
     ///
     /// * `submethods` - Hints to the server the preferred methods to be used for authentication
@@ -332,6 +494,66 @@ impl<H: Handler> Handle<H> {
         self.wait_recv_keyboard_interactive_reply().await
     }
 
+    /// Run a complete keyboard-interactive authentication. `respond` is
+    /// called with the name, instructions and prompts of each info request
+    /// sent by the server, and must return one response per prompt. Servers
+    /// may send any number of rounds (e.g. a password, then a one-time
+    /// code), including rounds without prompts.
+    ///
+    /// ```no_run
+    /// # async fn run<H: russh::client::Handler>(session: &mut russh::client::Handle<H>) -> Result<(), russh::Error> {
+    /// let result = session
+    ///     .authenticate_keyboard_interactive("user", None, |_name, instructions, prompts| async move {
+    ///         println!("{instructions}");
+    ///         Ok::<_, russh::Error>(prompts.iter().map(|p| ask_user(&p.prompt, p.echo)).collect())
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// # fn ask_user(prompt: &str, echo: bool) -> String { unimplemented!() }
+    /// ```
+    pub async fn authenticate_keyboard_interactive<U, S, F, Fut, E>(
+        &mut self,
+        user: U,
+        submethods: S,
+        mut respond: F,
+    ) -> Result<AuthResult, E>
+    where
+        U: Into<String>,
+        S: Into<Option<String>>,
+        F: FnMut(String, String, Vec<Prompt>) -> Fut,
+        Fut: Future<Output = Result<Vec<String>, E>>,
+        E: From<crate::Error>,
+    {
+        let mut reply = self
+            .authenticate_keyboard_interactive_start(user, submethods)
+            .await?;
+        loop {
+            match reply {
+                KeyboardInteractiveAuthResponse::Success => return Ok(AuthResult::Success),
+                KeyboardInteractiveAuthResponse::Failure {
+                    remaining_methods,
+                    partial_success,
+                } => {
+                    return Ok(AuthResult::Failure {
+                        remaining_methods,
+                        partial_success,
+                    })
+                }
+                KeyboardInteractiveAuthResponse::InfoRequest {
+                    name,
+                    instructions,
+                    prompts,
+                } => {
+                    let responses = respond(name, instructions, prompts).await?;
+                    reply = self
+                        .authenticate_keyboard_interactive_respond(responses)
+                        .await?;
+                }
+            }
+        }
+    }
+
     async fn wait_recv_keyboard_interactive_reply(
         &mut self,
     ) -> Result<KeyboardInteractiveAuthResponse, crate::Error> {
@@ -358,6 +580,13 @@ impl<H: Handler> Handle<H> {
                         prompts,
                     });
                 }
+                Some(Reply::AuthBanner(banner)) => self.auth_banners.push(banner),
+                None => {
+                    return Ok(KeyboardInteractiveAuthResponse::Failure {
+                        remaining_methods: MethodSet::empty(),
+                        partial_success: false,
+                    })
+                }
                 _ => {}
             }
         }
@@ -376,6 +605,10 @@ impl<H: Handler> Handle<H> {
                         partial_success,
                     })
                 }
+                Some(Reply::AuthPasswordChangeRequest { prompt }) => {
+                    return Ok(AuthResult::PasswordChangeRequired { prompt })
+                }
+                Some(Reply::AuthBanner(banner)) => self.auth_banners.push(banner),
                 None => {
                     return Ok(AuthResult::Failure {
                         remaining_methods: MethodSet::empty(),
@@ -410,6 +643,55 @@ impl<H: Handler> Handle<H> {
         self.wait_recv_reply().await
     }
 
+    /// Perform host-based authentication ([RFC 4252, section
+    /// 9](https://tools.ietf.org/html/rfc4252#section-9)), signing the
+    /// request with `host_key`, the private key of the client host.
+    ///
+    /// `client_host` is the fully qualified name of the client host, which
+    /// OpenSSH writes with a trailing dot, and `client_user` the name of
+    /// the user on that host.
+    pub async fn authenticate_hostbased<U: Into<String>, C: Into<String>, L: Into<String>>(
+        &mut self,
+        user: U,
+        host_key: PrivateKeyWithHashAlg,
+        client_host: C,
+        client_user: L,
+    ) -> Result<AuthResult, crate::Error> {
+        self.sender
+            .send(Msg::Authenticate {
+                user: user.into(),
+                method: auth::Method::HostBased {
+                    key: host_key,
+                    client_host: client_host.into(),
+                    client_user: client_user.into(),
+                },
+            })
+            .await
+            .map_err(|_| crate::Error::SendError)?;
+        self.wait_recv_reply().await
+    }
+
+    /// Perform `gssapi-with-mic` authentication (e.g. Kerberos), with the
+    /// security context established by `context`.
+    #[cfg(feature = "gssapi")]
+    pub async fn authenticate_gssapi_with_mic<U: Into<String>>(
+        &mut self,
+        user: U,
+        context: Box<dyn gssapi::GssapiContext>,
+    ) -> Result<AuthResult, crate::Error> {
+        self.sender
+            .send(Msg::Authenticate {
+                user: user.into(),
+                method: auth::Method::GssapiWithMic(gssapi::GssapiAuth {
+                    context,
+                    mechanism: None,
+                }),
+            })
+            .await
+            .map_err(|_| crate::Error::SendError)?;
+        self.wait_recv_reply().await
+    }
+
     /// Perform public OpenSSH Certificate-based SSH authentication
     pub async fn authenticate_openssh_cert<U: Into<String>>(
         &mut self,
@@ -450,6 +732,40 @@ impl<H: Handler> Handle<H> {
         {
             return Err((crate::SendError {}).into());
         }
+        self.wait_signed_reply(hash_alg, signer).await
+    }
+
+    /// Authenticate with an OpenSSH certificate whose private key is
+    /// held by a [`Signer`][auth::Signer], such as an
+    /// [SSH agent][crate::keys::agent::client::AgentClient].
+    pub async fn authenticate_certificate_with<U: Into<String>, S: auth::Signer>(
+        &mut self,
+        user: U,
+        cert: Certificate,
+        signer: &mut S,
+    ) -> Result<AuthResult, S::Error> {
+        let user = user.into();
+        if self
+            .sender
+            .send(Msg::Authenticate {
+                user,
+                method: auth::Method::FutureCertificate { cert },
+            })
+            .await
+            .is_err()
+        {
+            return Err((crate::SendError {}).into());
+        }
+        self.wait_signed_reply(None, signer).await
+    }
+
+    /// Wait for the result of a publickey authentication, signing with
+    /// `signer` if the server accepts the key.
+    async fn wait_signed_reply<S: auth::Signer>(
+        &mut self,
+        hash_alg: Option<HashAlg>,
+        signer: &mut S,
+    ) -> Result<AuthResult, S::Error> {
         loop {
             let reply = self.receiver.recv().await;
             match reply {
@@ -473,6 +789,7 @@ impl<H: Handler> Handle<H> {
                         return Err((crate::SendError {}).into());
                     }
                 }
+                Some(Reply::AuthBanner(banner)) => self.auth_banners.push(banner),
                 None => {
                     return Ok(AuthResult::Failure {
                         remaining_methods: MethodSet::empty(),
@@ -487,40 +804,16 @@ impl<H: Handler> Handle<H> {
     /// Wait for confirmation that a channel is open
     async fn wait_channel_confirmation(
         &self,
-        mut receiver: Receiver<ChannelMsg>,
+        receiver: Receiver<ChannelMsg>,
         window_size_ref: WindowSizeRef,
     ) -> Result<Channel<Msg>, crate::Error> {
-        loop {
-            match receiver.recv().await {
-                Some(ChannelMsg::Open {
-                    id,
-                    max_packet_size,
-                    window_size,
-                }) => {
-                    window_size_ref.update(window_size).await;
-
-                    return Ok(Channel {
-                        write_half: ChannelWriteHalf {
-                            id,
-                            sender: self.sender.clone(),
-                            max_packet_size,
-                            window_size: window_size_ref,
-                        },
-                        read_half: ChannelReadHalf { receiver },
-                    });
-                }
-                Some(ChannelMsg::OpenFailure(reason)) => {
-                    return Err(crate::Error::ChannelOpenFailure(reason));
-                }
-                None => {
-                    debug!("channel confirmation sender was dropped");
-                    return Err(crate::Error::Disconnect);
-                }
-                msg => {
-                    debug!("msg = {:?}", msg);
-                }
-            }
-        }
+        wait_channel_confirmation(
+            self.sender.clone(),
+            receiver,
+            window_size_ref,
+            self.limiters.clone(),
+        )
+        .await
     }
 
     /// Returns the best RSA hash algorithm supported by the server,
@@ -574,6 +867,21 @@ impl<H: Handler> Handle<H> {
         Ok(None)
     }
 
+    /// The extensions received from the server so far. Unlike
+    /// [`Handle::best_supported_rsa_hash`], this does not wait for
+    /// them: after authentication, every extension has usually been
+    /// received.
+    pub async fn extension_info(&self) -> Result<ExtensionInfo, Error> {
+        let (sender, receiver) = oneshot::channel();
+        self.sender
+            .send(Msg::GetExtensionInfo {
+                reply_channel: sender,
+            })
+            .await
+            .map_err(|_| Error::SendError)?;
+        receiver.await.map_err(|_| Error::Inconsistent)
+    }
+
     /// Request a session channel (the most basic type of
     /// channel). This function returns `Some(..)` immediately if the
     /// connection is authenticated, but the channel only becomes
@@ -628,17 +936,50 @@ impl<H: Handler> Handle<H> {
         port_to_connect: u32,
         originator_address: B,
         originator_port: u32,
+    ) -> Result<Channel<Msg>, crate::Error> {
+        channel_open_direct_tcpip(
+            self.sender.clone(),
+            self.channel_buffer_size,
+            self.limiters.clone(),
+            host_to_connect.into(),
+            port_to_connect,
+            originator_address.into(),
+            originator_port,
+        )
+        .await
+    }
+
+    pub async fn channel_open_direct_streamlocal<S: Into<String>>(
+        &self,
+        socket_path: S,
+    ) -> Result<Channel<Msg>, crate::Error> {
+        channel_open_direct_streamlocal(
+            self.sender.clone(),
+            self.channel_buffer_size,
+            self.limiters.clone(),
+            socket_path.into(),
+        )
+        .await
+    }
+
+    /// Open a `tun@openssh.com` channel, forwarding packets to the tunnel
+    /// device `remote_unit` of the server, or to any free device with
+    /// [`TUN_UNIT_ANY`](crate::tun::TUN_UNIT_ANY). Wrap the
+    /// channel in a [`TunChannel`](crate::tun::TunChannel) to
+    /// exchange packets.
+    pub async fn channel_open_tun(
+        &self,
+        mode: TunMode,
+        remote_unit: u32,
     ) -> Result<Channel<Msg>, crate::Error> {
         let (sender, receiver) = channel(self.channel_buffer_size);
         let channel_ref = ChannelRef::new(sender);
         let window_size_ref = channel_ref.window_size().clone();
 
         self.sender
-            .send(Msg::ChannelOpenDirectTcpIp {
-                host_to_connect: host_to_connect.into(),
-                port_to_connect,
-                originator_address: originator_address.into(),
-                originator_port,
+            .send(Msg::ChannelOpenTun {
+                mode,
+                remote_unit,
                 channel_ref,
             })
             .await
@@ -647,17 +988,23 @@ impl<H: Handler> Handle<H> {
             .await
     }
 
-    pub async fn channel_open_direct_streamlocal<S: Into<String>>(
+    /// Open a channel of type `channel_type`, which this crate does not
+    /// know about, for protocol extensions. `data` is sent after the
+    /// standard fields of `SSH_MSG_CHANNEL_OPEN`, and must be encoded
+    /// as the channel type requires.
+    pub async fn channel_open_custom<A: Into<String>>(
         &self,
-        socket_path: S,
+        channel_type: A,
+        data: Vec<u8>,
     ) -> Result<Channel<Msg>, crate::Error> {
         let (sender, receiver) = channel(self.channel_buffer_size);
         let channel_ref = ChannelRef::new(sender);
         let window_size_ref = channel_ref.window_size().clone();
 
         self.sender
-            .send(Msg::ChannelOpenDirectStreamLocal {
-                socket_path: socket_path.into(),
+            .send(Msg::ChannelOpenCustom {
+                channel_type: channel_type.into(),
+                data,
                 channel_ref,
             })
             .await
@@ -809,6 +1156,126 @@ impl<H: Handler> Handle<H> {
 
         Ok(())
     }
+
+    /// Re-exchange keys now, and wait for the new keys to be in use.
+    /// Keys are also re-exchanged automatically according to
+    /// [`Config::limits`].
+    pub async fn rekey(&self) -> Result<(), Error> {
+        let (reply_channel, receiver) = oneshot::channel();
+        self.sender
+            .send(Msg::RekeyAndNotify { reply_channel })
+            .await
+            .map_err(|_| Error::SendError)?;
+        receiver.await.map_err(|_| Error::Disconnect)
+    }
+
+    /// The algorithms negotiated with the server in the last key
+    /// exchange, and its identification string.
+    pub async fn connection_info(&self) -> Result<ConnectionInfo, Error> {
+        let (reply_channel, receiver) = oneshot::channel();
+        self.sender
+            .send(Msg::GetConnectionInfo { reply_channel })
+            .await
+            .map_err(|_| Error::SendError)?;
+        receiver
+            .await
+            .map_err(|_| Error::Disconnect)?
+            .ok_or(Error::Inconsistent)
+    }
+
+    /// The session identifier, see [`Session::session_id`].
+    pub async fn session_id(&self) -> Result<Vec<u8>, Error> {
+        let (reply_channel, receiver) = oneshot::channel();
+        self.sender
+            .send(Msg::GetSessionId { reply_channel })
+            .await
+            .map_err(|_| Error::SendError)?;
+        receiver
+            .await
+            .map_err(|_| Error::Disconnect)?
+            .ok_or(Error::Inconsistent)
+    }
+
+    /// The host key, session identifier and signature that bind an SSH
+    /// agent to this connection, see
+    /// [`AgentClient::bind_session`][crate::keys::agent::client::AgentClient::bind_session].
+    pub async fn session_bind(&self) -> Result<SessionBind, Error> {
+        let (reply_channel, receiver) = oneshot::channel();
+        self.sender
+            .send(Msg::GetSessionBind { reply_channel })
+            .await
+            .map_err(|_| Error::SendError)?;
+        receiver
+            .await
+            .map_err(|_| Error::Disconnect)?
+            .ok_or(Error::Inconsistent)
+    }
+
+    /// Measure the round-trip time to the server with a
+    /// `ping@openssh.com` message, which the server answers at the
+    /// transport level. This requires the server to announce the
+    /// extension, and the session to be authenticated.
+    pub async fn ping(&self) -> Result<Duration, Error> {
+        let (reply_channel, receiver) = oneshot::channel();
+        let start = Instant::now();
+        self.sender
+            .send(Msg::Ping { reply_channel })
+            .await
+            .map_err(|_| Error::SendError)?;
+        match receiver.await {
+            Ok(result) => result.map(|()| Instant::now().duration_since(start)),
+            Err(_) => Err(Error::Disconnect),
+        }
+    }
+
+    /// Tell the server that this client will not open any more session
+    /// channels (`no-more-sessions@openssh.com`). Call this once the
+    /// sessions of a connection are open, so that a compromised
+    /// multiplexing client cannot open new ones; the server rejects
+    /// any session channel opened afterwards.
+    pub async fn no_more_sessions(&self) -> Result<(), Error> {
+        self.sender
+            .send(Msg::NoMoreSessions)
+            .await
+            .map_err(|_| Error::SendError)?;
+
+        Ok(())
+    }
+
+    /// Send a global request named `name`, followed by `payload`. This
+    /// is meant for extensions that this crate does not implement.
+    ///
+    /// If `want_reply` is set, this waits for the reply and returns the
+    /// data that follows `SSH_MSG_REQUEST_SUCCESS`, or
+    /// [`Error::RequestDenied`] if the request failed. Otherwise, this
+    /// returns `None` as soon as the request is sent.
+    pub async fn global_request<A: Into<String>>(
+        &self,
+        name: A,
+        want_reply: bool,
+        payload: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (reply_send, reply_recv) = oneshot::channel();
+        self.sender
+            .send(Msg::GlobalRequest {
+                name: name.into(),
+                payload,
+                reply_channel: want_reply.then_some(reply_send),
+            })
+            .await
+            .map_err(|_| Error::SendError)?;
+        if !want_reply {
+            return Ok(None);
+        }
+        match reply_recv.await {
+            Ok(Some(data)) => Ok(Some(data)),
+            Ok(None) => Err(Error::RequestDenied),
+            Err(e) => {
+                error!("Unable to receive GlobalRequest result: {e:?}");
+                Err(Error::Disconnect)
+            }
+        }
+    }
 }
 
 impl<H: Handler> Future for Handle<H> {
@@ -825,6 +1292,94 @@ impl<H: Handler> Future for Handle<H> {
     }
 }
 
+async fn channel_open_direct_tcpip(
+    sender: Sender<Msg>,
+    channel_buffer_size: usize,
+    limiters: ConnectionLimiters,
+    host_to_connect: String,
+    port_to_connect: u32,
+    originator_address: String,
+    originator_port: u32,
+) -> Result<Channel<Msg>, crate::Error> {
+    let (channel_sender, receiver) = channel(channel_buffer_size);
+    let channel_ref = ChannelRef::new(channel_sender);
+    let window_size_ref = channel_ref.window_size().clone();
+
+    sender
+        .send(Msg::ChannelOpenDirectTcpIp {
+            host_to_connect,
+            port_to_connect,
+            originator_address,
+            originator_port,
+            channel_ref,
+        })
+        .await
+        .map_err(|_| crate::Error::SendError)?;
+    wait_channel_confirmation(sender, receiver, window_size_ref, limiters).await
+}
+
+async fn channel_open_direct_streamlocal(
+    sender: Sender<Msg>,
+    channel_buffer_size: usize,
+    limiters: ConnectionLimiters,
+    socket_path: String,
+) -> Result<Channel<Msg>, crate::Error> {
+    let (channel_sender, receiver) = channel(channel_buffer_size);
+    let channel_ref = ChannelRef::new(channel_sender);
+    let window_size_ref = channel_ref.window_size().clone();
+
+    sender
+        .send(Msg::ChannelOpenDirectStreamLocal {
+            socket_path,
+            channel_ref,
+        })
+        .await
+        .map_err(|_| crate::Error::SendError)?;
+    wait_channel_confirmation(sender, receiver, window_size_ref, limiters).await
+}
+
+/// Wait for confirmation that a channel is open
+async fn wait_channel_confirmation(
+    sender: Sender<Msg>,
+    receiver: Receiver<ChannelMsg>,
+    window_size_ref: WindowSizeRef,
+    limiters: ConnectionLimiters,
+) -> Result<Channel<Msg>, crate::Error> {
+    let mut read_half = ChannelReadHalf::new(receiver, limiters.receive);
+    loop {
+        match read_half.receiver.recv().await {
+            Some(ChannelMsg::Open {
+                id,
+                max_packet_size,
+                window_size,
+            }) => {
+                window_size_ref.update(window_size).await;
+
+                return Ok(Channel {
+                    write_half: ChannelWriteHalf {
+                        id,
+                        sender,
+                        max_packet_size,
+                        window_size: window_size_ref,
+                        send_limiters: limiters.send,
+                    },
+                    read_half,
+                });
+            }
+            Some(ChannelMsg::OpenFailure(reason)) => {
+                return Err(crate::Error::ChannelOpenFailure(reason));
+            }
+            None => {
+                debug!("channel confirmation sender was dropped");
+                return Err(crate::Error::Disconnect);
+            }
+            msg => {
+                debug!("msg = {:?}", msg);
+            }
+        }
+    }
+}
+
 /// Connect to a server at the address specified, using the [`Handler`]
 /// (implemented by you) and [`Config`] specified. Returns a future that
 /// resolves to a [`Handle`]. This handle can then be used to create channels,
@@ -838,14 +1393,16 @@ pub async fn connect<H: Handler + Send + 'static, A: tokio::net::ToSocketAddrs>(
     addrs: A,
     handler: H,
 ) -> Result<Handle<H>, H::Error> {
-    let socket = map_err!(tokio::net::TcpStream::connect(addrs).await)?;
+    let socket = map_err!(happy_eyeballs::connect(addrs, config.connection_attempt_delay).await)?;
     connect_stream(config, socket, handler).await
 }
 
-/// Connect a stream to a server. This stream must implement
-/// [`tokio::io::AsyncRead`] and [`tokio::io::AsyncWrite`], as well as [`Unpin`]
-/// and [`Send`]. Typically, you may prefer to use [`connect`], which uses a
-/// [`tokio::net::TcpStream`] and then calls this function under the hood.
+/// Connect a stream to a server. This stream can be any [`Transport`]
+/// (see the [`transport`](crate::transport) module). Typically, you may
+/// prefer to use [`connect`], which uses a [`tokio::net::TcpStream`] and
+/// then calls this function under the hood.
+///
+/// [`Transport`]: crate::transport::Transport
 pub async fn connect_stream<H, R>(
     config: Arc<Config>,
     mut stream: R,
@@ -853,12 +1410,13 @@ pub async fn connect_stream<H, R>(
 ) -> Result<Handle<H>, H::Error>
 where
     H: Handler + Send + 'static,
-    R: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    R: crate::transport::Transport,
 {
     // Writing SSH id.
     let mut write_buffer = SSHBuffer::new();
     write_buffer.send_ssh_id(&config.as_ref().client_id);
     map_err!(stream.write_all(&write_buffer.buffer).await)?;
+    map_err!(stream.flush().await)?;
 
     // Reading SSH id and allocating a session if correct.
     let mut stream = SshRead::new(stream);
@@ -872,6 +1430,7 @@ where
         );
     }
     let channel_buffer_size = config.channel_buffer_size;
+    let limiters = ConnectionLimiters::new(config.send_rate_limit, config.receive_rate_limit);
     let mut session = Session::new(
         config.window_size,
         CommonSession {
@@ -892,8 +1451,10 @@ where
         },
         session_receiver,
         session_sender,
+        limiters.clone(),
     );
     session.begin_rekey()?;
+    let closed = session.closed.subscribe();
     let (kex_done_signal, kex_done_signal_rx) = oneshot::channel();
     let join = russh_util::runtime::spawn(session.run(stream, handler, Some(kex_done_signal)));
 
@@ -902,6 +1463,9 @@ where
         // fails before a succesful key exchange
         debug!("kex_done_signal sender was dropped {err:?}");
         join.await.map_err(crate::Error::Join)??;
+        if let Some(CloseReason::Remote(info)) = closed.borrow().clone() {
+            return Err(H::Error::from(crate::Error::RemoteDisconnect(info)));
+        }
         return Err(H::Error::from(crate::Error::Disconnect));
     }
 
@@ -910,6 +1474,9 @@ where
         receiver: handle_receiver,
         join,
         channel_buffer_size,
+        limiters,
+        auth_banners: Vec::new(),
+        closed,
     })
 }
 
@@ -919,13 +1486,21 @@ async fn start_reading<R: AsyncRead + Unpin>(
     mut cipher: Box<dyn OpeningKey + Send>,
 ) -> Result<(usize, R, SSHBuffer, Box<dyn OpeningKey + Send>), crate::Error> {
     buffer.buffer.clear();
-    let n = cipher::read(&mut stream_read, &mut buffer, &mut *cipher).await?;
+    let n = cipher::read(
+        &mut stream_read,
+        &mut buffer,
+        &mut *cipher,
+        cipher::MAXIMUM_PACKET_LEN,
+    )
+    .await?;
     Ok((n, stream_read, buffer, cipher))
 }
 
 impl Session {
     fn maybe_decompress(&mut self, buffer: &SSHBuffer) -> Result<IncomingSshPacket, Error> {
         if let Some(ref mut enc) = self.common.encrypted {
+            enc.read_bytes += buffer.buffer.len();
+            enc.read_packets += 1;
             let mut decomp = CryptoVec::new();
             Ok(IncomingSshPacket {
                 #[allow(clippy::indexing_slicing)] // length checked
@@ -949,6 +1524,7 @@ impl Session {
         common: CommonSession<Arc<Config>>,
         receiver: Receiver<Msg>,
         sender: UnboundedSender<Reply>,
+        limiters: ConnectionLimiters,
     ) -> Self {
         let (inbound_channel_sender, inbound_channel_receiver) = channel(10);
         Self {
@@ -963,7 +1539,15 @@ impl Session {
             pending_reads: Vec::new(),
             pending_len: 0,
             open_global_requests: VecDeque::new(),
-            server_sig_algs: None,
+            open_pings: VecDeque::new(),
+            extension_info: ExtensionInfo::default(),
+            session_bind: None,
+            remote_forwards: HashMap::new(),
+            remote_unix_forwards: HashMap::new(),
+            agent_forward: None,
+            x11_forward: None,
+            limiters,
+            closed: watch::channel(None).0,
         }
     }
 
@@ -985,7 +1569,14 @@ impl Session {
         trace!("disconnected");
         self.receiver.close();
         self.inbound_channel_receiver.close();
-        map_err!(stream_write.shutdown().await)?;
+        if let Err(e) = stream_write.shutdown().await {
+            debug!("could not shut down the transport: {e:?}");
+        }
+        let _ = self.closed.send(Some(match &result {
+            Ok(v) => CloseReason::Remote(v.clone()),
+            Err(_) if self.common.disconnected => CloseReason::Local,
+            Err(_) => CloseReason::Error,
+        }));
         match result {
             Ok(v) => {
                 handler
@@ -1163,19 +1754,8 @@ impl Session {
         &mut self,
         pkt: &IncomingSshPacket,
     ) -> Result<RemoteDisconnectInfo, Error> {
-        let mut r = &pkt.buffer[..];
-        u8::decode(&mut r)?; // skip message type
         self.common.disconnected = true;
-
-        let reason_code = u32::decode(&mut r)?.try_into()?;
-        let message = String::decode(&mut r)?;
-        let lang_tag = String::decode(&mut r)?;
-
-        Ok(RemoteDisconnectInfo {
-            reason_code,
-            message,
-            lang_tag,
-        })
+        RemoteDisconnectInfo::parse(&pkt.buffer)
     }
 
     fn handle_msg(&mut self, msg: Msg) -> Result<(), crate::Error> {
@@ -1212,6 +1792,14 @@ impl Session {
                 )?;
                 self.channels.insert(id, channel_ref);
             }
+            Msg::ChannelOpenTun {
+                mode,
+                remote_unit,
+                channel_ref,
+            } => {
+                let id = self.channel_open_tun(mode, remote_unit)?;
+                self.channels.insert(id, channel_ref);
+            }
             Msg::ChannelOpenDirectStreamLocal {
                 socket_path,
                 channel_ref,
@@ -1219,6 +1807,14 @@ impl Session {
                 let id = self.channel_open_direct_streamlocal(&socket_path)?;
                 self.channels.insert(id, channel_ref);
             }
+            Msg::ChannelOpenCustom {
+                channel_type,
+                data,
+                channel_ref,
+            } => {
+                let id = self.channel_open_custom(&channel_type, &data)?;
+                self.channels.insert(id, channel_ref);
+            }
             Msg::TcpIpForward {
                 reply_channel,
                 address,
@@ -1315,6 +1911,13 @@ impl Session {
                 },
             ) => self.exec(id, want_reply, &command)?,
             Msg::Channel(id, ChannelMsg::Signal { signal }) => self.signal(id, signal)?,
+            Msg::Channel(
+                id,
+                ChannelMsg::Break {
+                    want_reply,
+                    length_ms,
+                },
+            ) => self.send_break(id, want_reply, length_ms)?,
             Msg::Channel(id, ChannelMsg::RequestSubsystem { want_reply, name }) => {
                 self.request_subsystem(want_reply, id, &name)?
             }
@@ -1323,6 +1926,18 @@ impl Session {
             }
             Msg::Channel(id, ChannelMsg::Close) => self.close(id)?,
             Msg::Rekey => self.initiate_rekey()?,
+            Msg::RekeyAndNotify { reply_channel } => {
+                if let Some(ref mut enc) = self.common.encrypted {
+                    enc.rekey_waiters.push(reply_channel);
+                }
+                self.initiate_rekey()?
+            }
+            Msg::NoMoreSessions => self.no_more_sessions()?,
+            Msg::GlobalRequest {
+                name,
+                payload,
+                reply_channel,
+            } => self.global_request(&name, &payload, reply_channel)?,
             Msg::AwaitExtensionInfo {
                 extension_name,
                 reply_channel,
@@ -1342,7 +1957,57 @@ impl Session {
                 }
             }
             Msg::GetServerSigAlgs { reply_channel } => {
-                let _ = reply_channel.send(self.server_sig_algs.clone());
+                let _ = reply_channel.send(self.extension_info.server_sig_algs.clone());
+            }
+            Msg::GetExtensionInfo { reply_channel } => {
+                let _ = reply_channel.send(self.extension_info.clone());
+            }
+            Msg::GetConnectionInfo { reply_channel } => {
+                let _ = reply_channel.send(self.common.connection_info());
+            }
+            Msg::GetSessionId { reply_channel } => {
+                let _ = reply_channel.send(self.session_id().map(|id| id.to_vec()));
+            }
+            Msg::GetSessionBind { reply_channel } => {
+                let _ = reply_channel.send(self.session_bind.clone());
+            }
+            Msg::Ping { reply_channel } => match self.ping() {
+                Ok(()) => self.open_pings.push_back(reply_channel),
+                Err(e) => {
+                    let _ = reply_channel.send(Err(e));
+                }
+            },
+            Msg::RegisterRemoteForward {
+                address,
+                port,
+                channel_sender,
+            } => {
+                self.remote_forwards.insert((address, port), channel_sender);
+            }
+            Msg::UnregisterRemoteForward { address, port } => {
+                self.remote_forwards.remove(&(address, port));
+            }
+            Msg::RegisterRemoteUnixForward {
+                socket_path,
+                channel_sender,
+            } => {
+                self.remote_unix_forwards
+                    .insert(socket_path, channel_sender);
+            }
+            Msg::UnregisterRemoteUnixForward { socket_path } => {
+                self.remote_unix_forwards.remove(&socket_path);
+            }
+            Msg::RegisterAgentForward { channel_sender } => {
+                self.agent_forward = Some(channel_sender);
+            }
+            Msg::UnregisterAgentForward => {
+                self.agent_forward = None;
+            }
+            Msg::RegisterX11Forward { channel_sender } => {
+                self.x11_forward = Some(channel_sender);
+            }
+            Msg::UnregisterX11Forward => {
+                self.x11_forward = None;
             }
             msg => {
                 // should be unreachable, since the receiver only gets
@@ -1376,12 +2041,17 @@ impl Session {
     /// Flush the temporary cleartext buffer into the encryption
     /// buffer. This does *not* flush to the socket.
     fn flush(&mut self) -> Result<(), crate::Error> {
+        // Only key exchange messages may be sent until the new keys are
+        // in use, the others are sent after the key exchange.
+        if self.kex.active() {
+            return Ok(());
+        }
         if let Some(ref mut enc) = self.common.encrypted {
             if enc.flush(
-                &self.common.config.as_ref().limits,
+                &self.common.config.limits,
+                self.common.config.rekey_packet_limit,
                 &mut self.common.packet_writer,
-            )? && !self.kex.active()
-            {
+            )? {
                 self.begin_rekey()?;
             }
         }
@@ -1444,16 +2114,17 @@ async fn reply<H: Handler>(
                 }
                 KexProgress::Done {
                     server_host_key,
+                    host_key_signature,
                     newkeys,
                 } => {
                     debug!("kex impl has completed");
-                    session.common.strict_kex =
-                        session.common.strict_kex || newkeys.names.strict_kex;
+                    if session.common.encrypted.is_none() {
+                        session.common.strict_kex = newkeys.names.strict_kex;
+                    }
 
                     if let Some(ref mut enc) = session.common.encrypted {
                         // This is a rekey
-                        enc.last_rekey = Instant::now();
-                        session.common.packet_writer.buffer().bytes = 0;
+                        enc.rekey_done(&mut session.common.packet_writer);
                         enc.flush_all_pending()?;
                         let mut pending = std::mem::take(&mut session.pending_reads);
                         for p in pending.drain(..) {
@@ -1470,10 +2141,20 @@ async fn reply<H: Handler>(
                                 return Err(crate::Error::UnknownKey.into());
                             }
                         }
+                        if let (Some(host_key), Some(signature)) =
+                            (server_host_key, host_key_signature)
+                        {
+                            session.session_bind = Some(SessionBind {
+                                host_key,
+                                session_id: newkeys.session_id.to_vec(),
+                                signature,
+                            });
+                        }
 
                         session
                             .common
                             .encrypted(initial_encrypted_state(session), newkeys);
+                        session.maybe_send_ext_info()?;
 
                         if let Some(sender) = kex_done_signal.take() {
                             sender.send(()).unwrap_or(());
@@ -1536,6 +2217,20 @@ impl GexParams {
         Ok(this)
     }
 
+    /// The sizes requested by a client, which may be below the minimum
+    /// this crate requests.
+    pub(crate) fn unchecked(
+        min_group_size: usize,
+        preferred_group_size: usize,
+        max_group_size: usize,
+    ) -> Self {
+        Self {
+            min_group_size,
+            preferred_group_size,
+            max_group_size,
+        }
+    }
+
     pub(crate) fn validate(&self) -> Result<(), Error> {
         if self.min_group_size < 2048 {
             return Err(Error::InvalidConfig(
@@ -1585,6 +2280,9 @@ pub struct Config {
     pub client_id: SshId,
     /// The bytes and time limits before key re-exchange.
     pub limits: Limits,
+    /// The number of packets sent or received before key re-exchange,
+    /// 2^31 by default ([RFC 4344](https://tools.ietf.org/html/rfc4344#section-3.1)).
+    pub rekey_packet_limit: usize,
     /// The initial size of a channel (used for flow control).
     pub window_size: u32,
     /// The maximal size of a single packet.
@@ -1595,14 +2293,46 @@ pub struct Config {
     pub preferred: negotiation::Preferred,
     /// Time after which the connection is garbage-collected.
     pub inactivity_timeout: Option<std::time::Duration>,
-    /// If nothing is received from the server for this amount of time, send a keepalive message.
+    /// If nothing is received from the server for this amount of time, send a keepalive message
+    /// (a `keepalive@openssh.com` global request, like OpenSSH's `ServerAliveInterval`).
     pub keepalive_interval: Option<std::time::Duration>,
-    /// If this many keepalives have been sent without reply, close the connection.
+    /// If this many keepalives have been sent without reply, close the connection with
+    /// [`Error::KeepaliveTimeout`](crate::Error::KeepaliveTimeout), like OpenSSH's
+    /// `ServerAliveCountMax`. 0 means never.
     pub keepalive_max: usize,
     /// Whether to expect and wait for an authentication call.
     pub anonymous: bool,
     /// DH dynamic group exchange parameters.
     pub gex: GexParams,
+    /// Limit the total rate of data sent on the channels of a connection.
+    /// Individual channels can be limited with
+    /// [`Channel::set_rate_limit`].
+    pub send_rate_limit: Option<crate::RateLimit>,
+    /// Limit the total rate of data received on the channels of a
+    /// connection.
+    pub receive_rate_limit: Option<crate::RateLimit>,
+    /// When the host resolves to several addresses, [`connect`] starts a
+    /// connection to the next one if the previous attempt has not
+    /// succeeded after this delay, as in
+    /// [RFC 8305](https://tools.ietf.org/html/rfc8305). `None` tries the
+    /// addresses one after the other. Defaults to 250 ms, the value
+    /// recommended by the RFC.
+    pub connection_attempt_delay: Option<std::time::Duration>,
+    /// Ask the server to prove that it holds the host keys it announces
+    /// with `hostkeys-00@openssh.com`, and pass the proven keys to
+    /// [`Handler::openssh_ext_host_keys_proven`], like OpenSSH's
+    /// `UpdateHostKeys`.
+    pub update_host_keys: bool,
+    /// Extensions sent to servers that support extension negotiation
+    /// ([RFC 8308](https://tools.ietf.org/html/rfc8308)) after the first
+    /// key exchange, as names and raw values.
+    pub extensions: Vec<(String, Vec<u8>)>,
+    /// Replies to the global requests with these names, `true` for a
+    /// success and `false` for a failure, sent without calling
+    /// [`Handler::global_request`]. By default, the
+    /// `keepalive@openssh.com` requests sent by servers such as OpenSSH
+    /// with `ClientAliveInterval` are accepted right away.
+    pub global_request_replies: HashMap<String, bool>,
 }
 
 impl Default for Config {
@@ -1614,6 +2344,7 @@ impl Default for Config {
                 env!("CARGO_PKG_VERSION")
             )),
             limits: Limits::default(),
+            rekey_packet_limit: 1 << 31,
             window_size: 2097152,
             maximum_packet_size: 32768,
             channel_buffer_size: 100,
@@ -1623,6 +2354,12 @@ impl Default for Config {
             keepalive_max: 3,
             anonymous: false,
             gex: Default::default(),
+            send_rate_limit: None,
+            receive_rate_limit: None,
+            connection_attempt_delay: Some(std::time::Duration::from_millis(250)),
+            update_host_keys: false,
+            extensions: Vec::new(),
+            global_request_replies: [("keepalive@openssh.com".to_string(), true)].into(),
         }
     }
 }
@@ -1642,11 +2379,26 @@ pub trait Handler: Sized + Send {
     /// Called when the server sends us an authentication banner. This
     /// is usually meant to be shown to the user, see
     /// [RFC4252](https://tools.ietf.org/html/rfc4252#section-5.4) for
-    /// more details.
+    /// more details. The banners are also kept in
+    /// [`Handle::auth_banners`].
     #[allow(unused_variables)]
     fn auth_banner(
         &mut self,
         banner: &str,
+        language_tag: &str,
+        session: &mut Session,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Called for each extension received from the server in
+    /// `SSH_MSG_EXT_INFO` ([RFC 8308](https://tools.ietf.org/html/rfc8308)).
+    /// All of them are also kept in [`Handle::extension_info`].
+    #[allow(unused_variables)]
+    fn extension_received(
+        &mut self,
+        name: &str,
+        value: &[u8],
         session: &mut Session,
     ) -> impl Future<Output = Result<(), Self::Error>> + Send {
         async { Ok(()) }
@@ -1778,11 +2530,14 @@ pub trait Handler: Sized + Send {
         async { false }
     }
 
-    /// Called when the server opens an unknown channel.
+    /// Called when the server opens an unknown channel, with the
+    /// type-specific data of the request.
     #[allow(unused_variables)]
     fn server_channel_open_unknown(
         &mut self,
         channel: Channel<Msg>,
+        channel_type: &str,
+        data: &[u8],
         session: &mut Session,
     ) -> impl Future<Output = Result<(), Self::Error>> + Send {
         async { Ok(()) }
@@ -1913,7 +2668,9 @@ pub trait Handler: Sized + Send {
         window
     }
 
-    /// Called when the server signals success.
+    /// Called when the server announces its host keys with
+    /// `hostkeys-00@openssh.com`, usually after authentication. The keys
+    /// are not verified: see [`Config::update_host_keys`].
     #[allow(unused_variables)]
     fn openssh_ext_host_keys_announced(
         &mut self,
@@ -1926,6 +2683,36 @@ pub trait Handler: Sized + Send {
         }
     }
 
+    /// Called with the announced host keys that the server proved to hold,
+    /// if [`Config::update_host_keys`] is set. These keys can be added to
+    /// the known hosts of the server, and its other known keys removed.
+    #[allow(unused_variables)]
+    fn openssh_ext_host_keys_proven(
+        &mut self,
+        keys: Vec<PublicKey>,
+        session: &mut Session,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async move {
+            debug!("openssh_ext_host_keys_proven: {:?}", keys);
+            Ok(())
+        }
+    }
+
+    /// Called for global requests that this crate does not handle, with
+    /// the data that follows their name and `want_reply` flag. Return the
+    /// data of a successful reply, or `None` to reject the request. The
+    /// reply is only sent if `want_reply` is set.
+    #[allow(unused_variables)]
+    fn global_request(
+        &mut self,
+        name: &str,
+        want_reply: bool,
+        payload: &[u8],
+        session: &mut Session,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>, Self::Error>> + Send {
+        async { Ok(None) }
+    }
+
     /// Called when the server sent a disconnect message
     ///
     /// If reason is an Error, this function should re-return the error so the join can also evaluate it
@@ -0,0 +1,645 @@
+//! Connection multiplexing over a Unix control socket, in the spirit of
+//! OpenSSH's `ControlMaster` and `ControlPath`.
+//!
+//! [`Handle::serve_control_socket`] shares an established session with
+//! other processes or tasks, which open their own channels on it with a
+//! [`MuxClient`]:
+//!
+//! ```no_run
+//! # async fn run<H: russh::client::Handler>(session: russh::client::Handle<H>) -> Result<(), russh::Error> {
+//! let _master = session.serve_control_socket("/tmp/ssh-mux.sock").await?;
+//!
+//! // Somewhere else, possibly in another process:
+//! let client = russh::client::mux::MuxClient::new("/tmp/ssh-mux.sock");
+//! let mut channel = client.channel_open_session().await?;
+//! channel.exec(true, "uptime").await?;
+//! while let Some(msg) = channel.wait().await {
+//!     if let russh::ChannelMsg::Data { data } = msg {
+//!         print!("{}", String::from_utf8_lossy(&data));
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Each channel uses its own connection to the control socket, on which
+//! the channel messages are exchanged as length-prefixed frames. This
+//! protocol is specific to russh, and is not compatible with `ssh -S`.
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::{debug, warn};
+use ssh_encoding::{Decode, Encode};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::Mutex;
+
+use super::{
+    channel_open_direct_streamlocal, channel_open_direct_tcpip, wait_channel_confirmation, Handle,
+    Handler, Msg,
+};
+use crate::channels::{ChannelRef, ConnectionLimiters};
+use crate::{map_err, ChannelMsg, ChannelOpenFailure, CryptoVec, Error, Pty, Sig};
+
+const MAX_FRAME_SIZE: usize = 1 << 20;
+/// Data is sent in frames of at most this size.
+const DATA_CHUNK_SIZE: usize = 32768;
+
+const MUX_OPEN_SESSION: u8 = 1;
+const MUX_OPEN_DIRECT_TCPIP: u8 = 2;
+const MUX_OPEN_DIRECT_STREAMLOCAL: u8 = 3;
+const MUX_OPEN_OK: u8 = 4;
+const MUX_OPEN_FAILED: u8 = 5;
+
+const MUX_DATA: u8 = 10;
+const MUX_EXTENDED_DATA: u8 = 11;
+const MUX_EOF: u8 = 12;
+const MUX_CLOSE: u8 = 13;
+const MUX_PTY: u8 = 20;
+const MUX_SHELL: u8 = 21;
+const MUX_EXEC: u8 = 22;
+const MUX_SIGNAL: u8 = 23;
+const MUX_SUBSYSTEM: u8 = 24;
+const MUX_ENV: u8 = 25;
+const MUX_WINDOW_CHANGE: u8 = 26;
+const MUX_XON_XOFF: u8 = 30;
+const MUX_EXIT_STATUS: u8 = 31;
+const MUX_EXIT_SIGNAL: u8 = 32;
+const MUX_SUCCESS: u8 = 33;
+const MUX_FAILURE: u8 = 34;
+
+/// A control socket started by [`Handle::serve_control_socket`]. The
+/// socket is closed and removed when this guard is dropped. Channels
+/// that are already open are not affected.
+#[derive(Debug)]
+pub struct ControlMaster {
+    path: PathBuf,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl ControlMaster {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ControlMaster {
+    fn drop(&mut self) {
+        debug!("closing control socket {:?}", self.path);
+        self.task.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl<H: Handler> Handle<H> {
+    /// Listen on the Unix socket `path`, and let [`MuxClient`]s connecting
+    /// to it open channels on this session. The socket is only accessible
+    /// to its owner.
+    pub async fn serve_control_socket<P: Into<PathBuf>>(
+        &self,
+        path: P,
+    ) -> Result<ControlMaster, Error> {
+        let path = path.into();
+        let listener = UnixListener::bind(&path)?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        let task = tokio::spawn(accept_mux(
+            listener,
+            self.sender.clone(),
+            self.channel_buffer_size,
+            self.limiters.clone(),
+        ));
+        Ok(ControlMaster { path, task })
+    }
+}
+
+async fn accept_mux(
+    listener: UnixListener,
+    sender: Sender<Msg>,
+    channel_buffer_size: usize,
+    limiters: ConnectionLimiters,
+) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("control socket: accept failed: {e:?}");
+                return;
+            }
+        };
+        if sender.is_closed() {
+            debug!("control socket: session closed");
+            return;
+        }
+        let sender = sender.clone();
+        let limiters = limiters.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_mux(stream, sender, channel_buffer_size, limiters).await {
+                debug!("control socket: connection ended: {e:?}");
+            }
+        });
+    }
+}
+
+async fn serve_mux(
+    stream: UnixStream,
+    sender: Sender<Msg>,
+    channel_buffer_size: usize,
+    limiters: ConnectionLimiters,
+) -> Result<(), Error> {
+    let (mut r, mut w) = stream.into_split();
+    let request = read_frame(&mut r).await?;
+    let mut request = &request[..];
+    let channel = match map_err!(u8::decode(&mut request))? {
+        MUX_OPEN_SESSION => {
+            let (channel_sender, receiver) = channel(channel_buffer_size);
+            let channel_ref = ChannelRef::new(channel_sender);
+            let window_size_ref = channel_ref.window_size().clone();
+            sender
+                .send(Msg::ChannelOpenSession { channel_ref })
+                .await
+                .map_err(|_| Error::SendError)?;
+            wait_channel_confirmation(sender, receiver, window_size_ref, limiters).await
+        }
+        MUX_OPEN_DIRECT_TCPIP => {
+            let host = map_err!(String::decode(&mut request))?;
+            let port = map_err!(u32::decode(&mut request))?;
+            let originator_address = map_err!(String::decode(&mut request))?;
+            let originator_port = map_err!(u32::decode(&mut request))?;
+            channel_open_direct_tcpip(
+                sender,
+                channel_buffer_size,
+                limiters.clone(),
+                host,
+                port,
+                originator_address,
+                originator_port,
+            )
+            .await
+        }
+        MUX_OPEN_DIRECT_STREAMLOCAL => {
+            let socket_path = map_err!(String::decode(&mut request))?;
+            channel_open_direct_streamlocal(sender, channel_buffer_size, limiters, socket_path)
+                .await
+        }
+        _ => Err(Error::Inconsistent),
+    };
+    let channel = match channel {
+        Ok(channel) => channel,
+        Err(e) => {
+            debug!("control socket: could not open channel: {e:?}");
+            let reason = match e {
+                Error::ChannelOpenFailure(reason) => reason,
+                _ => ChannelOpenFailure::Unknown,
+            };
+            let mut reply = Vec::new();
+            MUX_OPEN_FAILED.encode(&mut reply)?;
+            (reason as u32).encode(&mut reply)?;
+            write_frame(&mut w, &reply).await?;
+            return Ok(());
+        }
+    };
+    write_frame(&mut w, &[MUX_OPEN_OK]).await?;
+
+    let (mut read_half, write_half) = channel.split();
+    let requests = tokio::spawn(async move {
+        loop {
+            let msg = match read_frame(&mut r).await {
+                Ok(frame) => decode_msg(&frame)?,
+                Err(_) => ChannelMsg::Close,
+            };
+            match msg {
+                ChannelMsg::Data { data } => write_half.data(&data[..]).await?,
+                ChannelMsg::ExtendedData { data, ext } => {
+                    write_half.extended_data(ext, &data[..]).await?
+                }
+                ChannelMsg::Close => return write_half.close().await,
+                msg => write_half.send_msg(msg).await?,
+            }
+        }
+    });
+    while let Some(msg) = read_half.wait().await {
+        let close = matches!(msg, ChannelMsg::Close);
+        if let Some(frame) = encode_msg(&msg)? {
+            write_frame(&mut w, &frame).await?;
+        }
+        if close {
+            break;
+        }
+    }
+    requests.abort();
+    Ok(())
+}
+
+/// Opens channels through a control socket served with
+/// [`Handle::serve_control_socket`].
+#[derive(Debug, Clone)]
+pub struct MuxClient {
+    path: PathBuf,
+}
+
+impl MuxClient {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        MuxClient { path: path.into() }
+    }
+
+    pub async fn channel_open_session(&self) -> Result<MuxChannel, Error> {
+        self.open(&[MUX_OPEN_SESSION]).await
+    }
+
+    pub async fn channel_open_direct_tcpip<A: Into<String>, B: Into<String>>(
+        &self,
+        host_to_connect: A,
+        port_to_connect: u32,
+        originator_address: B,
+        originator_port: u32,
+    ) -> Result<MuxChannel, Error> {
+        let mut request = vec![MUX_OPEN_DIRECT_TCPIP];
+        host_to_connect.into().encode(&mut request)?;
+        port_to_connect.encode(&mut request)?;
+        originator_address.into().encode(&mut request)?;
+        originator_port.encode(&mut request)?;
+        self.open(&request).await
+    }
+
+    pub async fn channel_open_direct_streamlocal<S: Into<String>>(
+        &self,
+        socket_path: S,
+    ) -> Result<MuxChannel, Error> {
+        let mut request = vec![MUX_OPEN_DIRECT_STREAMLOCAL];
+        socket_path.into().encode(&mut request)?;
+        self.open(&request).await
+    }
+
+    async fn open(&self, request: &[u8]) -> Result<MuxChannel, Error> {
+        let (mut reader, mut writer) = UnixStream::connect(&self.path).await?.into_split();
+        write_frame(&mut writer, request).await?;
+        let reply = read_frame(&mut reader).await?;
+        let mut reply = &reply[..];
+        match map_err!(u8::decode(&mut reply))? {
+            MUX_OPEN_OK => Ok(MuxChannel {
+                reader,
+                writer: Arc::new(Mutex::new(writer)),
+            }),
+            MUX_OPEN_FAILED => {
+                let reason = map_err!(u32::decode(&mut reply))?;
+                Err(Error::ChannelOpenFailure(
+                    ChannelOpenFailure::from_u32(reason).unwrap_or(ChannelOpenFailure::Unknown),
+                ))
+            }
+            _ => Err(Error::Inconsistent),
+        }
+    }
+}
+
+/// A channel opened through a control socket. Its methods mirror those
+/// of [`Channel`](crate::Channel).
+pub struct MuxChannel {
+    reader: OwnedReadHalf,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+}
+
+impl std::fmt::Debug for MuxChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MuxChannel").finish_non_exhaustive()
+    }
+}
+
+impl MuxChannel {
+    /// Awaits an incoming [`ChannelMsg`]. Returns `None` once the
+    /// channel is closed.
+    pub async fn wait(&mut self) -> Option<ChannelMsg> {
+        let frame = read_frame(&mut self.reader).await.ok()?;
+        match decode_msg(&frame) {
+            Ok(msg) => Some(msg),
+            Err(e) => {
+                warn!("control socket: invalid message: {e:?}");
+                None
+            }
+        }
+    }
+
+    async fn send_msg(&self, msg: ChannelMsg) -> Result<(), Error> {
+        if let Some(frame) = encode_msg(&msg)? {
+            write_frame(&mut *self.writer.lock().await, &frame).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn data(&self, data: &[u8]) -> Result<(), Error> {
+        for chunk in data.chunks(DATA_CHUNK_SIZE) {
+            self.send_msg(ChannelMsg::Data {
+                data: CryptoVec::from_slice(chunk),
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn extended_data(&self, ext: u32, data: &[u8]) -> Result<(), Error> {
+        for chunk in data.chunks(DATA_CHUNK_SIZE) {
+            self.send_msg(ChannelMsg::ExtendedData {
+                data: CryptoVec::from_slice(chunk),
+                ext,
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn eof(&self) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::Eof).await
+    }
+
+    pub async fn close(&self) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::Close).await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn request_pty(
+        &self,
+        want_reply: bool,
+        term: &str,
+        col_width: u32,
+        row_height: u32,
+        pix_width: u32,
+        pix_height: u32,
+        terminal_modes: &[(Pty, u32)],
+    ) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::RequestPty {
+            want_reply,
+            term: term.to_string(),
+            col_width,
+            row_height,
+            pix_width,
+            pix_height,
+            terminal_modes: terminal_modes.to_vec(),
+        })
+        .await
+    }
+
+    pub async fn request_shell(&self, want_reply: bool) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::RequestShell { want_reply }).await
+    }
+
+    pub async fn exec<A: Into<Vec<u8>>>(&self, want_reply: bool, command: A) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::Exec {
+            want_reply,
+            command: command.into(),
+        })
+        .await
+    }
+
+    pub async fn signal(&self, signal: Sig) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::Signal { signal }).await
+    }
+
+    pub async fn request_subsystem<A: Into<String>>(
+        &self,
+        want_reply: bool,
+        name: A,
+    ) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::RequestSubsystem {
+            want_reply,
+            name: name.into(),
+        })
+        .await
+    }
+
+    pub async fn set_env<A: Into<String>, B: Into<String>>(
+        &self,
+        want_reply: bool,
+        variable_name: A,
+        variable_value: B,
+    ) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::SetEnv {
+            want_reply,
+            variable_name: variable_name.into(),
+            variable_value: variable_value.into(),
+        })
+        .await
+    }
+
+    pub async fn window_change(
+        &self,
+        col_width: u32,
+        row_height: u32,
+        pix_width: u32,
+        pix_height: u32,
+    ) -> Result<(), Error> {
+        self.send_msg(ChannelMsg::WindowChange {
+            col_width,
+            row_height,
+            pix_width,
+            pix_height,
+        })
+        .await
+    }
+}
+
+async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> Result<Vec<u8>, Error> {
+    let len = r.read_u32().await? as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(Error::Inconsistent);
+    }
+    let mut frame = vec![0; len];
+    r.read_exact(&mut frame).await?;
+    Ok(frame)
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, frame: &[u8]) -> Result<(), Error> {
+    w.write_u32(frame.len() as u32).await?;
+    w.write_all(frame).await?;
+    w.flush().await?;
+    Ok(())
+}
+
+/// Encode the messages that can be exchanged on a control connection.
+fn encode_msg(msg: &ChannelMsg) -> Result<Option<Vec<u8>>, Error> {
+    let mut w = Vec::new();
+    match msg {
+        ChannelMsg::Data { data } => {
+            MUX_DATA.encode(&mut w)?;
+            data.encode(&mut w)?;
+        }
+        ChannelMsg::ExtendedData { data, ext } => {
+            MUX_EXTENDED_DATA.encode(&mut w)?;
+            ext.encode(&mut w)?;
+            data.encode(&mut w)?;
+        }
+        ChannelMsg::Eof => MUX_EOF.encode(&mut w)?,
+        ChannelMsg::Close => MUX_CLOSE.encode(&mut w)?,
+        ChannelMsg::RequestPty {
+            want_reply,
+            term,
+            col_width,
+            row_height,
+            pix_width,
+            pix_height,
+            terminal_modes,
+        } => {
+            MUX_PTY.encode(&mut w)?;
+            (*want_reply as u8).encode(&mut w)?;
+            term.encode(&mut w)?;
+            col_width.encode(&mut w)?;
+            row_height.encode(&mut w)?;
+            pix_width.encode(&mut w)?;
+            pix_height.encode(&mut w)?;
+            (terminal_modes.len() as u32).encode(&mut w)?;
+            for &(code, value) in terminal_modes {
+                (code as u8).encode(&mut w)?;
+                value.encode(&mut w)?;
+            }
+        }
+        ChannelMsg::RequestShell { want_reply } => {
+            MUX_SHELL.encode(&mut w)?;
+            (*want_reply as u8).encode(&mut w)?;
+        }
+        ChannelMsg::Exec {
+            want_reply,
+            command,
+        } => {
+            MUX_EXEC.encode(&mut w)?;
+            (*want_reply as u8).encode(&mut w)?;
+            command.encode(&mut w)?;
+        }
+        ChannelMsg::Signal { signal } => {
+            MUX_SIGNAL.encode(&mut w)?;
+            signal.name().encode(&mut w)?;
+        }
+        ChannelMsg::RequestSubsystem { want_reply, name } => {
+            MUX_SUBSYSTEM.encode(&mut w)?;
+            (*want_reply as u8).encode(&mut w)?;
+            name.encode(&mut w)?;
+        }
+        ChannelMsg::SetEnv {
+            want_reply,
+            variable_name,
+            variable_value,
+        } => {
+            MUX_ENV.encode(&mut w)?;
+            (*want_reply as u8).encode(&mut w)?;
+            variable_name.encode(&mut w)?;
+            variable_value.encode(&mut w)?;
+        }
+        ChannelMsg::WindowChange {
+            col_width,
+            row_height,
+            pix_width,
+            pix_height,
+        } => {
+            MUX_WINDOW_CHANGE.encode(&mut w)?;
+            col_width.encode(&mut w)?;
+            row_height.encode(&mut w)?;
+            pix_width.encode(&mut w)?;
+            pix_height.encode(&mut w)?;
+        }
+        ChannelMsg::XonXoff { client_can_do } => {
+            MUX_XON_XOFF.encode(&mut w)?;
+            (*client_can_do as u8).encode(&mut w)?;
+        }
+        ChannelMsg::ExitStatus { exit_status } => {
+            MUX_EXIT_STATUS.encode(&mut w)?;
+            exit_status.encode(&mut w)?;
+        }
+        ChannelMsg::ExitSignal {
+            signal_name,
+            core_dumped,
+            error_message,
+            lang_tag,
+        } => {
+            MUX_EXIT_SIGNAL.encode(&mut w)?;
+            signal_name.name().encode(&mut w)?;
+            (*core_dumped as u8).encode(&mut w)?;
+            error_message.encode(&mut w)?;
+            lang_tag.encode(&mut w)?;
+        }
+        ChannelMsg::Success => MUX_SUCCESS.encode(&mut w)?,
+        ChannelMsg::Failure => MUX_FAILURE.encode(&mut w)?,
+        _ => return Ok(None),
+    }
+    Ok(Some(w))
+}
+
+fn decode_msg(mut r: &[u8]) -> Result<ChannelMsg, Error> {
+    let r = &mut r;
+    Ok(match map_err!(u8::decode(r))? {
+        MUX_DATA => ChannelMsg::Data {
+            data: CryptoVec::from(map_err!(Vec::<u8>::decode(r))?),
+        },
+        MUX_EXTENDED_DATA => ChannelMsg::ExtendedData {
+            ext: map_err!(u32::decode(r))?,
+            data: CryptoVec::from(map_err!(Vec::<u8>::decode(r))?),
+        },
+        MUX_EOF => ChannelMsg::Eof,
+        MUX_CLOSE => ChannelMsg::Close,
+        MUX_PTY => {
+            let want_reply = map_err!(u8::decode(r))? != 0;
+            let term = map_err!(String::decode(r))?;
+            let col_width = map_err!(u32::decode(r))?;
+            let row_height = map_err!(u32::decode(r))?;
+            let pix_width = map_err!(u32::decode(r))?;
+            let pix_height = map_err!(u32::decode(r))?;
+            let mut terminal_modes = Vec::new();
+            for _ in 0..map_err!(u32::decode(r))? {
+                let code = map_err!(u8::decode(r))?;
+                let value = map_err!(u32::decode(r))?;
+                if let Some(code) = Pty::from_u8(code) {
+                    terminal_modes.push((code, value));
+                }
+            }
+            ChannelMsg::RequestPty {
+                want_reply,
+                term,
+                col_width,
+                row_height,
+                pix_width,
+                pix_height,
+                terminal_modes,
+            }
+        }
+        MUX_SHELL => ChannelMsg::RequestShell {
+            want_reply: map_err!(u8::decode(r))? != 0,
+        },
+        MUX_EXEC => ChannelMsg::Exec {
+            want_reply: map_err!(u8::decode(r))? != 0,
+            command: map_err!(Vec::<u8>::decode(r))?,
+        },
+        MUX_SIGNAL => ChannelMsg::Signal {
+            signal: Sig::from_name(&map_err!(String::decode(r))?),
+        },
+        MUX_SUBSYSTEM => ChannelMsg::RequestSubsystem {
+            want_reply: map_err!(u8::decode(r))? != 0,
+            name: map_err!(String::decode(r))?,
+        },
+        MUX_ENV => ChannelMsg::SetEnv {
+            want_reply: map_err!(u8::decode(r))? != 0,
+            variable_name: map_err!(String::decode(r))?,
+            variable_value: map_err!(String::decode(r))?,
+        },
+        MUX_WINDOW_CHANGE => ChannelMsg::WindowChange {
+            col_width: map_err!(u32::decode(r))?,
+            row_height: map_err!(u32::decode(r))?,
+            pix_width: map_err!(u32::decode(r))?,
+            pix_height: map_err!(u32::decode(r))?,
+        },
+        MUX_XON_XOFF => ChannelMsg::XonXoff {
+            client_can_do: map_err!(u8::decode(r))? != 0,
+        },
+        MUX_EXIT_STATUS => ChannelMsg::ExitStatus {
+            exit_status: map_err!(u32::decode(r))?,
+        },
+        MUX_EXIT_SIGNAL => ChannelMsg::ExitSignal {
+            signal_name: Sig::from_name(&map_err!(String::decode(r))?),
+            core_dumped: map_err!(u8::decode(r))? != 0,
+            error_message: map_err!(String::decode(r))?,
+            lang_tag: map_err!(String::decode(r))?,
+        },
+        MUX_SUCCESS => ChannelMsg::Success,
+        MUX_FAILURE => ChannelMsg::Failure,
+        _ => return Err(Error::Inconsistent),
+    })
+}
@@ -0,0 +1,250 @@
+//! Chained connections through jump hosts, equivalent to OpenSSH's
+//! `-J` / `ProxyJump`.
+//!
+//! Each hop is connected to and authenticated in turn, the next one
+//! being reached through a `direct-tcpip` channel opened on the
+//! previous hop. The result is a [`JumpStream`] to the final
+//! destination, on which a regular session can be started with
+//! [`connect_stream`](crate::client::connect_stream):
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use russh::client::{self, proxy::jump::{parse_hops, JumpHost}};
+//! # async fn run<H: client::Handler + Clone + 'static>(handler: H, config: Arc<client::Config>) -> Result<(), H::Error> {
+//! let mut jump = JumpHost::new();
+//! for hop in parse_hops("alice@bastion.example.com,10.0.0.2:2222")? {
+//!     jump = jump.hop(hop, config.clone(), handler.clone());
+//! }
+//! let stream = jump
+//!     .connect("internal.example.com", 22, |hop, mut handle| async move {
+//!         let user = hop.user.unwrap_or_else(|| "root".into());
+//!         handle.authenticate_none(user).await?;
+//!         Ok(handle)
+//!     })
+//!     .await?;
+//! let session = client::connect_stream(config, stream, handler).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use log::debug;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::client::{connect, connect_stream, Config, Handle, Handler, Msg};
+use crate::ChannelStream;
+
+/// The originator reported for channels opened towards the next hop.
+const ORIGINATOR_ADDRESS: &str = "127.0.0.1";
+const ORIGINATOR_PORT: u32 = 0;
+
+/// An intermediate host, as written in a `-J` specification:
+/// `[user@]host[:port]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hop {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Hop {
+    pub fn new<A: Into<String>>(host: A, port: u16) -> Self {
+        Self {
+            user: None,
+            host: host.into(),
+            port,
+        }
+    }
+}
+
+impl FromStr for Hop {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || crate::Error::InvalidConfig(format!("invalid jump host: {s:?}"));
+        let (user, rest) = match s.rsplit_once('@') {
+            Some((user, rest)) if !user.is_empty() => (Some(user.to_string()), rest),
+            Some(_) => return Err(invalid()),
+            None => (None, s),
+        };
+        let (host, port) = if let Some(rest) = rest.strip_prefix('[') {
+            // Bracketed IPv6 address, optionally followed by a port.
+            let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+            match rest.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None if rest.is_empty() => (host, None),
+                None => return Err(invalid()),
+            }
+        } else {
+            match rest.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (rest, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid())?,
+            None => 22,
+        };
+        Ok(Hop {
+            user,
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+/// Parse a comma-separated list of hops, as accepted by OpenSSH's `-J`
+/// option.
+pub fn parse_hops(spec: &str) -> Result<Vec<Hop>, crate::Error> {
+    spec.split(',').map(|hop| hop.trim().parse()).collect()
+}
+
+/// A chain of jump hosts leading to a destination.
+pub struct JumpHost<H: Handler> {
+    hops: Vec<(Hop, Arc<Config>, H)>,
+}
+
+impl<H: Handler> Default for JumpHost<H> {
+    fn default() -> Self {
+        Self { hops: Vec::new() }
+    }
+}
+
+impl<H: Handler + Send + 'static> JumpHost<H> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a hop at the end of the chain, connected to with `config` and
+    /// `handler`.
+    pub fn hop(mut self, hop: Hop, config: Arc<Config>, handler: H) -> Self {
+        self.hops.push((hop, config, handler));
+        self
+    }
+
+    /// Connect through every hop in order, and open a channel from the
+    /// last one to `host:port`.
+    ///
+    /// `authenticate` is called with each hop right after its key
+    /// exchange, and must return the handle once authenticated.
+    pub async fn connect<F, Fut>(
+        self,
+        host: &str,
+        port: u16,
+        mut authenticate: F,
+    ) -> Result<JumpStream<H>, H::Error>
+    where
+        F: FnMut(Hop, Handle<H>) -> Fut,
+        Fut: Future<Output = Result<Handle<H>, H::Error>>,
+    {
+        let mut handles: Vec<Handle<H>> = Vec::with_capacity(self.hops.len());
+        for (hop, config, handler) in self.hops {
+            let handle = match handles.last() {
+                None => {
+                    debug!("connecting to jump host {}:{}", hop.host, hop.port);
+                    connect(config, (hop.host.as_str(), hop.port), handler).await?
+                }
+                Some(previous) => {
+                    debug!("jumping to {}:{}", hop.host, hop.port);
+                    let stream = open_stream(previous, &hop.host, hop.port).await?;
+                    connect_stream(config, stream, handler).await?
+                }
+            };
+            handles.push(authenticate(hop, handle).await?);
+        }
+
+        let stream = match handles.last() {
+            Some(last) => open_stream(last, host, port).await?,
+            None => {
+                return Err(crate::Error::InvalidConfig("no jump host".into()).into());
+            }
+        };
+        Ok(JumpStream {
+            stream,
+            _hops: handles,
+        })
+    }
+}
+
+async fn open_stream<H: Handler>(
+    handle: &Handle<H>,
+    host: &str,
+    port: u16,
+) -> Result<ChannelStream<Msg>, crate::Error> {
+    let channel = handle
+        .channel_open_direct_tcpip(host, port.into(), ORIGINATOR_ADDRESS, ORIGINATOR_PORT)
+        .await?;
+    Ok(channel.into_stream())
+}
+
+/// A stream to the destination of a [`JumpHost`] chain. The sessions to
+/// the intermediate hops are kept open for as long as the stream lives.
+pub struct JumpStream<H: Handler> {
+    stream: ChannelStream<Msg>,
+    _hops: Vec<Handle<H>>,
+}
+
+impl<H: Handler> AsyncRead for JumpStream<H> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<H: Handler> AsyncWrite for JumpStream<H> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn hop_parsing() {
+        assert_eq!(
+            parse_hops("alice@a,b:2222,[::1]:23,bob@[fe80::1]").unwrap(),
+            vec![
+                Hop {
+                    user: Some("alice".into()),
+                    ..Hop::new("a", 22)
+                },
+                Hop::new("b", 2222),
+                Hop::new("::1", 23),
+                Hop {
+                    user: Some("bob".into()),
+                    ..Hop::new("fe80::1", 22)
+                },
+            ]
+        );
+        assert!("a:b".parse::<Hop>().is_err());
+        assert!("@a".parse::<Hop>().is_err());
+        assert!("[::1".parse::<Hop>().is_err());
+    }
+}
@@ -0,0 +1,7 @@
+//! Helpers to proxy connections: to run a session through established
+//! sessions to reach further SSH servers ([`jump`]), or to tunnel local
+//! applications ([`socks`]). Sessions over an external command, like
+//! OpenSSH's `ProxyCommand`, are provided by the `russh-config` crate.
+
+pub mod jump;
+pub mod socks;
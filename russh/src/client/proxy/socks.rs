@@ -0,0 +1,370 @@
+//! SOCKS4/SOCKS5 dynamic port forwarding, equivalent to OpenSSH's `-D`.
+//!
+//! Local applications connect to a listener and issue a SOCKS `CONNECT`
+//! request; each request is fulfilled by opening a `direct-tcpip`
+//! channel on the SSH connection and bridging the two streams.
+//!
+//! ```no_run
+//! # async fn run<H: russh::client::Handler + 'static>(handle: russh::client::Handle<H>) -> Result<(), russh::Error> {
+//! use std::sync::Arc;
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:1080").await?;
+//! russh::client::proxy::socks::serve(Arc::new(handle), listener).await
+//! # }
+//! ```
+//!
+//! Only the `CONNECT` command and the "no authentication" method are
+//! supported. SOCKS4a host names are accepted.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use log::{debug, warn};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::client::{Handle, Handler};
+
+const SOCKS4_VERSION: u8 = 4;
+const SOCKS5_VERSION: u8 = 5;
+const CMD_CONNECT: u8 = 1;
+
+const SOCKS4_GRANTED: u8 = 0x5a;
+const SOCKS4_REJECTED: u8 = 0x5b;
+
+const SOCKS5_NO_AUTH: u8 = 0x00;
+const SOCKS5_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+
+const SOCKS5_SUCCEEDED: u8 = 0x00;
+const SOCKS5_GENERAL_FAILURE: u8 = 0x01;
+const SOCKS5_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const SOCKS5_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Maximum length of the null-terminated strings of SOCKS4(a).
+const MAX_SOCKS4_STRING: usize = 255;
+
+/// The version of the SOCKS protocol spoken by a local client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Socks4,
+    Socks5,
+}
+
+/// The destination requested by a SOCKS client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Addr(SocketAddr),
+    Domain(String, u16),
+}
+
+impl Target {
+    /// The host name or address, suitable for `host_to_connect`.
+    pub fn host(&self) -> String {
+        match self {
+            Target::Addr(addr) => addr.ip().to_string(),
+            Target::Domain(host, _) => host.clone(),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            Target::Addr(addr) => addr.port(),
+            Target::Domain(_, port) => *port,
+        }
+    }
+}
+
+/// A parsed SOCKS `CONNECT` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub version: Version,
+    pub target: Target,
+}
+
+/// Accept connections on `listener` until the connection of `handle`
+/// ends, forwarding each of them through `handle`. Every connection is
+/// served on its own task, and failures of individual connections are
+/// only logged.
+pub async fn serve<H: Handler + 'static>(
+    handle: Arc<Handle<H>>,
+    listener: TcpListener,
+) -> Result<(), crate::Error> {
+    loop {
+        let (stream, originator) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = handle.closed() => return Err(crate::Error::Disconnect),
+        };
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(&handle, stream, originator).await {
+                debug!("socks connection from {originator} failed: {e:?}");
+            }
+        });
+    }
+}
+
+/// Serve a single SOCKS connection: read the request, open a
+/// `direct-tcpip` channel to the requested target and copy data in both
+/// directions until either side closes.
+pub async fn handle_connection<H, S>(
+    handle: &Handle<H>,
+    mut stream: S,
+    originator: SocketAddr,
+) -> Result<(), crate::Error>
+where
+    H: Handler,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = match read_request(&mut stream).await {
+        Ok(request) => request,
+        Err(e) => {
+            stream.shutdown().await.unwrap_or(());
+            return Err(e.into());
+        }
+    };
+    debug!("socks request {request:?} from {originator}");
+
+    let channel = match handle
+        .channel_open_direct_tcpip(
+            request.target.host(),
+            request.target.port().into(),
+            originator.ip().to_string(),
+            originator.port().into(),
+        )
+        .await
+    {
+        Ok(channel) => channel,
+        Err(e) => {
+            warn!("could not open channel to {:?}: {e:?}", request.target);
+            write_reply(&mut stream, request.version, false).await?;
+            return Err(e);
+        }
+    };
+    write_reply(&mut stream, request.version, true).await?;
+
+    let mut channel_stream = channel.into_stream();
+    tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await?;
+    Ok(())
+}
+
+/// Read the handshake and `CONNECT` request of either SOCKS version,
+/// answering the SOCKS5 method negotiation along the way. Unsupported
+/// requests are answered with the appropriate error before returning.
+pub async fn read_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> Result<Request, io::Error> {
+    match stream.read_u8().await? {
+        SOCKS4_VERSION => read_socks4_request(stream).await,
+        SOCKS5_VERSION => read_socks5_request(stream).await,
+        v => Err(invalid_data(format!("unsupported SOCKS version {v}"))),
+    }
+}
+
+async fn read_socks4_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> Result<Request, io::Error> {
+    let command = stream.read_u8().await?;
+    let port = stream.read_u16().await?;
+    let ip = Ipv4Addr::from(stream.read_u32().await?);
+    // The user ID is ignored.
+    read_null_terminated(stream).await?;
+
+    if command != CMD_CONNECT {
+        write_reply(stream, Version::Socks4, false).await?;
+        return Err(invalid_data(format!(
+            "unsupported SOCKS4 command {command}"
+        )));
+    }
+
+    // SOCKS4a: an address of 0.0.0.x (x != 0) means a host name follows.
+    let target = if matches!(ip.octets(), [0, 0, 0, x] if x != 0) {
+        let host = read_null_terminated(stream).await?;
+        let host = String::from_utf8(host).map_err(|_| invalid_data("invalid SOCKS4a host"))?;
+        Target::Domain(host, port)
+    } else {
+        Target::Addr(SocketAddr::new(ip.into(), port))
+    };
+
+    Ok(Request {
+        version: Version::Socks4,
+        target,
+    })
+}
+
+async fn read_socks5_request<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+) -> Result<Request, io::Error> {
+    let n_methods = stream.read_u8().await?;
+    let mut methods = vec![0; n_methods as usize];
+    stream.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS5_NO_AUTH) {
+        stream
+            .write_all(&[SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHOD])
+            .await?;
+        return Err(invalid_data("no acceptable SOCKS5 authentication method"));
+    }
+    stream.write_all(&[SOCKS5_VERSION, SOCKS5_NO_AUTH]).await?;
+
+    let version = stream.read_u8().await?;
+    if version != SOCKS5_VERSION {
+        return Err(invalid_data(format!("unexpected SOCKS version {version}")));
+    }
+    let command = stream.read_u8().await?;
+    let _reserved = stream.read_u8().await?;
+    let target = match stream.read_u8().await? {
+        ATYP_IPV4 => {
+            let ip = Ipv4Addr::from(stream.read_u32().await?);
+            Target::Addr(SocketAddr::new(ip.into(), stream.read_u16().await?))
+        }
+        ATYP_IPV6 => {
+            let ip = Ipv6Addr::from(stream.read_u128().await?);
+            Target::Addr(SocketAddr::new(ip.into(), stream.read_u16().await?))
+        }
+        ATYP_DOMAIN => {
+            let len = stream.read_u8().await?;
+            let mut host = vec![0; len as usize];
+            stream.read_exact(&mut host).await?;
+            let host = String::from_utf8(host).map_err(|_| invalid_data("invalid SOCKS5 host"))?;
+            Target::Domain(host, stream.read_u16().await?)
+        }
+        atyp => {
+            write_socks5_reply(stream, SOCKS5_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Err(invalid_data(format!(
+                "unsupported SOCKS5 address type {atyp}"
+            )));
+        }
+    };
+
+    if command != CMD_CONNECT {
+        write_socks5_reply(stream, SOCKS5_COMMAND_NOT_SUPPORTED).await?;
+        return Err(invalid_data(format!(
+            "unsupported SOCKS5 command {command}"
+        )));
+    }
+
+    Ok(Request {
+        version: Version::Socks5,
+        target,
+    })
+}
+
+async fn read_null_terminated<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>, io::Error> {
+    let mut s = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            0 => return Ok(s),
+            _ if s.len() >= MAX_SOCKS4_STRING => {
+                return Err(invalid_data("SOCKS4 string too long"))
+            }
+            c => s.push(c),
+        }
+    }
+}
+
+async fn write_reply<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    version: Version,
+    success: bool,
+) -> Result<(), io::Error> {
+    match version {
+        Version::Socks4 => {
+            let code = if success {
+                SOCKS4_GRANTED
+            } else {
+                SOCKS4_REJECTED
+            };
+            stream.write_all(&[0, code, 0, 0, 0, 0, 0, 0]).await
+        }
+        Version::Socks5 => {
+            let code = if success {
+                SOCKS5_SUCCEEDED
+            } else {
+                SOCKS5_GENERAL_FAILURE
+            };
+            write_socks5_reply(stream, code).await
+        }
+    }
+}
+
+async fn write_socks5_reply<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    code: u8,
+) -> Result<(), io::Error> {
+    // The bound address is not meaningful for a tunneled connection.
+    stream
+        .write_all(&[SOCKS5_VERSION, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used, clippy::indexing_slicing)]
+    use super::*;
+
+    async fn parse(input: &[u8]) -> (Result<Request, io::Error>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(input).await.unwrap();
+        let request = read_request(&mut server).await;
+        drop(server);
+        let mut output = Vec::new();
+        client.read_to_end(&mut output).await.unwrap();
+        (request, output)
+    }
+
+    #[tokio::test]
+    async fn socks4() {
+        let (request, output) = parse(&[4, 1, 0, 80, 10, 0, 0, 1, b'u', 0]).await;
+        assert_eq!(
+            request.unwrap(),
+            Request {
+                version: Version::Socks4,
+                target: Target::Addr("10.0.0.1:80".parse().unwrap()),
+            }
+        );
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn socks4a() {
+        let (request, _) = parse(b"\x04\x01\x01\xbb\x00\x00\x00\x01\x00example.com\x00").await;
+        assert_eq!(
+            request.unwrap().target,
+            Target::Domain("example.com".into(), 443)
+        );
+    }
+
+    #[tokio::test]
+    async fn socks5_domain() {
+        let (request, output) = parse(b"\x05\x01\x00\x05\x01\x00\x03\x04host\x00\x16").await;
+        assert_eq!(
+            request.unwrap(),
+            Request {
+                version: Version::Socks5,
+                target: Target::Domain("host".into(), 22),
+            }
+        );
+        assert_eq!(output, [SOCKS5_VERSION, SOCKS5_NO_AUTH]);
+    }
+
+    #[tokio::test]
+    async fn socks5_bind_rejected() {
+        let (request, output) = parse(&[5, 1, 0, 5, 2, 0, 1, 127, 0, 0, 1, 0, 80]).await;
+        assert!(request.is_err());
+        assert_eq!(output[2..4], [SOCKS5_VERSION, SOCKS5_COMMAND_NOT_SUPPORTED]);
+    }
+
+    #[tokio::test]
+    async fn socks5_requires_no_auth() {
+        let (request, output) = parse(&[5, 1, 2]).await;
+        assert!(request.is_err());
+        assert_eq!(output, [SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHOD]);
+    }
+}
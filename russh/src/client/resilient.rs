@@ -0,0 +1,409 @@
+//! A session that reconnects by itself.
+//!
+//! [`ResilientHandle`] dials and authenticates through a user-supplied
+//! function, and runs it again, with exponential backoff, whenever the
+//! connection is lost. Port forwards registered through it outlive
+//! reconnections: local listeners stay open and use the current session,
+//! and remote forwards are requested again on every new session.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, warn};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+
+use super::forward::LocalForward;
+use super::{Handle, Handler};
+
+/// How long to wait between connection attempts.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// The delay before the first attempt after a disconnection.
+    pub initial: Duration,
+    /// The maximum delay between two attempts.
+    pub max: Duration,
+    /// The factor by which the delay grows after each failed attempt.
+    pub multiplier: f64,
+    /// Give up after this many failed attempts in a row. `None` retries
+    /// forever.
+    pub max_attempts: Option<usize>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2.,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    fn delay(&self, attempt: usize) -> Duration {
+        let factor = self
+            .multiplier
+            .powi(attempt.saturating_sub(1).min(64) as i32);
+        self.initial.mul_f64(factor).min(self.max)
+    }
+}
+
+/// Reported to the callback set with [`ResilientHandle::on_event`].
+#[derive(Debug)]
+pub enum ReconnectEvent<'a> {
+    /// The session was lost.
+    Disconnected,
+    /// Connection attempt number `attempt` starts after `delay`.
+    Reconnecting { attempt: usize, delay: Duration },
+    /// Connection attempt number `attempt` failed.
+    Failed {
+        attempt: usize,
+        error: &'a crate::Error,
+    },
+    /// A new session is ready.
+    Connected,
+    /// [`Backoff::max_attempts`] was reached, the handle is now closed.
+    GaveUp,
+}
+
+type EventCallback = Box<dyn Fn(&ReconnectEvent) + Send + Sync>;
+
+struct RemoteForwardSpec {
+    address: String,
+    port: u32,
+    local_addr: String,
+}
+
+struct Shared<H: Handler> {
+    current: watch::Sender<Option<Arc<Handle<H>>>>,
+    remote_forwards: Mutex<RemoteForwards>,
+    on_event: std::sync::RwLock<Option<EventCallback>>,
+}
+
+#[derive(Default)]
+struct RemoteForwards {
+    specs: Vec<RemoteForwardSpec>,
+    /// The tasks serving the forwards on the current session.
+    active: Vec<JoinHandle<()>>,
+}
+
+impl<H: Handler> Shared<H> {
+    fn event(&self, event: ReconnectEvent) {
+        if let Ok(on_event) = self.on_event.read() {
+            if let Some(ref on_event) = *on_event {
+                on_event(&event)
+            }
+        }
+    }
+}
+
+/// A session that transparently reconnects. See the [module
+/// documentation](self).
+pub struct ResilientHandle<H: Handler> {
+    shared: Arc<Shared<H>>,
+    closed: watch::Receiver<bool>,
+    supervisor: JoinHandle<()>,
+}
+
+impl<H: Handler> std::fmt::Debug for ResilientHandle<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResilientHandle").finish_non_exhaustive()
+    }
+}
+
+impl<H: Handler + Send + 'static> ResilientHandle<H> {
+    /// `connect` must return a new, authenticated session. It is called
+    /// right away, and again after every disconnection.
+    pub fn new<F, Fut>(connect: F, backoff: Backoff) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Handle<H>, crate::Error>> + Send + 'static,
+    {
+        let (current, _) = watch::channel(None);
+        let shared = Arc::new(Shared {
+            current,
+            remote_forwards: Mutex::new(RemoteForwards::default()),
+            on_event: std::sync::RwLock::new(None),
+        });
+        let (closed_send, closed) = watch::channel(false);
+        let supervisor = tokio::spawn(supervise(shared.clone(), connect, backoff, closed_send));
+        ResilientHandle {
+            shared,
+            closed,
+            supervisor,
+        }
+    }
+
+    /// Call `f` on every connection state change.
+    pub fn on_event<F: Fn(&ReconnectEvent) + Send + Sync + 'static>(&self, f: F) {
+        if let Ok(mut on_event) = self.shared.on_event.write() {
+            *on_event = Some(Box::new(f))
+        }
+    }
+
+    /// The current session, waiting for the connection to be
+    /// re-established if needed. Fails with [`Error::Disconnect`] once
+    /// the handle gave up reconnecting.
+    ///
+    /// [`Error::Disconnect`]: crate::Error::Disconnect
+    pub async fn handle(&self) -> Result<Arc<Handle<H>>, crate::Error> {
+        current_handle(&self.shared, self.closed.clone()).await
+    }
+
+    /// The current session, if connected.
+    pub fn try_handle(&self) -> Option<Arc<Handle<H>>> {
+        self.shared.current.borrow().clone()
+    }
+
+    /// Same as [`Handle::forward_local`], except that the listener stays
+    /// open across reconnections, and connections accepted while
+    /// disconnected wait for the next session.
+    pub async fn forward_local<A: ToSocketAddrs, B: Into<String>>(
+        &self,
+        local_addr: A,
+        remote_host: B,
+        remote_port: u32,
+    ) -> Result<LocalForward, crate::Error> {
+        let listener = TcpListener::bind(local_addr).await?;
+        let local_addr = listener.local_addr()?;
+        let task = tokio::spawn(accept_local(
+            listener,
+            self.shared.clone(),
+            self.closed.clone(),
+            remote_host.into(),
+            remote_port,
+        ));
+        Ok(LocalForward { local_addr, task })
+    }
+
+    /// Ask the server to listen on `address:port` and forward connections
+    /// to `local_addr`, on this session and every future one.
+    pub async fn forward_remote<A: Into<String>, B: Into<String>>(
+        &self,
+        address: A,
+        port: u32,
+        local_addr: B,
+    ) -> Result<(), crate::Error> {
+        let spec = RemoteForwardSpec {
+            address: address.into(),
+            port,
+            local_addr: local_addr.into(),
+        };
+        let mut forwards = self.shared.remote_forwards.lock().await;
+        let current = self.shared.current.borrow().clone();
+        if let Some(handle) = current {
+            forwards
+                .active
+                .push(start_remote_forward(&handle, &spec).await?);
+        }
+        forwards.specs.push(spec);
+        Ok(())
+    }
+
+    /// Stop reconnecting and disconnect the current session.
+    pub async fn disconnect(
+        &self,
+        reason: crate::Disconnect,
+        description: &str,
+        language_tag: &str,
+    ) -> Result<(), crate::Error> {
+        self.supervisor.abort();
+        let current = self.shared.current.send_replace(None);
+        for task in self.shared.remote_forwards.lock().await.active.drain(..) {
+            task.abort()
+        }
+        if let Some(handle) = current {
+            handle.disconnect(reason, description, language_tag).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<H: Handler> Drop for ResilientHandle<H> {
+    fn drop(&mut self) {
+        self.supervisor.abort()
+    }
+}
+
+async fn current_handle<H: Handler>(
+    shared: &Shared<H>,
+    mut closed: watch::Receiver<bool>,
+) -> Result<Arc<Handle<H>>, crate::Error> {
+    let mut current = shared.current.subscribe();
+    loop {
+        if let Some(handle) = current.borrow_and_update().clone() {
+            if !handle.is_closed() {
+                return Ok(handle);
+            }
+        }
+        if *closed.borrow() {
+            return Err(crate::Error::Disconnect);
+        }
+        tokio::select! {
+            r = current.changed() => r.map_err(|_| crate::Error::Disconnect)?,
+            r = closed.changed() => r.map_err(|_| crate::Error::Disconnect)?,
+        }
+    }
+}
+
+async fn supervise<H, F, Fut>(
+    shared: Arc<Shared<H>>,
+    connect: F,
+    backoff: Backoff,
+    closed: watch::Sender<bool>,
+) where
+    H: Handler + Send + 'static,
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Handle<H>, crate::Error>> + Send + 'static,
+{
+    let mut first = true;
+    loop {
+        let mut attempt = 0;
+        let handle = loop {
+            attempt += 1;
+            if !first {
+                let delay = backoff.delay(attempt);
+                shared.event(ReconnectEvent::Reconnecting { attempt, delay });
+                tokio::time::sleep(delay).await;
+            }
+            first = false;
+            match connect().await {
+                Ok(handle) => break Arc::new(handle),
+                Err(error) => {
+                    warn!("connection attempt {attempt} failed: {error:?}");
+                    shared.event(ReconnectEvent::Failed {
+                        attempt,
+                        error: &error,
+                    });
+                    if backoff.max_attempts.is_some_and(|max| attempt >= max) {
+                        shared.event(ReconnectEvent::GaveUp);
+                        let _ = closed.send(true);
+                        return;
+                    }
+                }
+            }
+        };
+
+        {
+            let mut forwards = shared.remote_forwards.lock().await;
+            let RemoteForwards { specs, active } = &mut *forwards;
+            for spec in specs.iter() {
+                match start_remote_forward(&handle, spec).await {
+                    Ok(task) => active.push(task),
+                    Err(e) => warn!(
+                        "could not re-establish remote forward {}:{}: {e:?}",
+                        spec.address, spec.port
+                    ),
+                }
+            }
+            shared.current.send_replace(Some(handle.clone()));
+        }
+        shared.event(ReconnectEvent::Connected);
+
+        handle.sender.closed().await;
+        debug!("session lost");
+        shared.current.send_replace(None);
+        for task in shared.remote_forwards.lock().await.active.drain(..) {
+            task.abort()
+        }
+        shared.event(ReconnectEvent::Disconnected);
+    }
+}
+
+async fn start_remote_forward<H: Handler>(
+    handle: &Handle<H>,
+    spec: &RemoteForwardSpec,
+) -> Result<JoinHandle<()>, crate::Error> {
+    let forward = handle
+        .forward_remote(spec.address.clone(), spec.port)
+        .await?;
+    Ok(tokio::spawn(forward.forward_to(spec.local_addr.clone())))
+}
+
+async fn accept_local<H: Handler + Send + 'static>(
+    listener: TcpListener,
+    shared: Arc<Shared<H>>,
+    closed: watch::Receiver<bool>,
+    remote_host: String,
+    remote_port: u32,
+) {
+    loop {
+        let (stream, originator) = match listener.accept().await {
+            Ok(x) => x,
+            Err(e) => {
+                warn!("local forward: accept failed: {e:?}");
+                return;
+            }
+        };
+        if *closed.borrow() {
+            debug!("local forward: handle closed");
+            return;
+        }
+        tokio::spawn(bridge_local(
+            stream,
+            originator,
+            shared.clone(),
+            closed.clone(),
+            remote_host.clone(),
+            remote_port,
+        ));
+    }
+}
+
+async fn bridge_local<H: Handler>(
+    mut stream: TcpStream,
+    originator: SocketAddr,
+    shared: Arc<Shared<H>>,
+    closed: watch::Receiver<bool>,
+    remote_host: String,
+    remote_port: u32,
+) {
+    let channel = match current_handle(&shared, closed).await {
+        Ok(handle) => {
+            handle
+                .channel_open_direct_tcpip(
+                    remote_host,
+                    remote_port,
+                    originator.ip().to_string(),
+                    originator.port().into(),
+                )
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    let channel = match channel {
+        Ok(channel) => channel,
+        Err(e) => {
+            warn!("local forward: could not open channel for {originator}: {e:?}");
+            return;
+        }
+    };
+    let mut channel_stream = channel.into_stream();
+    if let Err(e) = tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await {
+        debug!("local forward: connection from {originator} ended: {e:?}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let backoff = Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(1),
+            ..Default::default()
+        };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2), Duration::from_millis(200));
+        assert_eq!(backoff.delay(4), Duration::from_millis(800));
+        assert_eq!(backoff.delay(5), Duration::from_secs(1));
+        assert_eq!(backoff.delay(1000), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,427 @@
+//! File transfers with the scp protocol, for servers that do not run an
+//! SFTP subsystem.
+//!
+//! [`Scp::upload`] runs `scp -t` (sink) on the server and sends files to
+//! it, [`Scp::download`] runs `scp -f` (source) and receives them. The
+//! [`Scp::recursive`] and [`Scp::preserve`] options are the `-r` and `-p`
+//! flags of `scp`.
+//!
+//! ```no_run
+//! # async fn run<H: russh::client::Handler>(session: russh::client::Handle<H>) -> Result<(), russh::Error> {
+//! session.scp().upload("notes.txt", "notes.txt", Some(0o600)).await?;
+//! session
+//!     .scp()
+//!     .recursive(true)
+//!     .preserve(true)
+//!     .download("/var/log/app", "logs")
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+
+use super::{Handle, Handler};
+use crate::Error;
+
+/// The longest protocol line accepted, including the file name.
+const MAX_LINE: u64 = 8192;
+
+/// An scp transfer, created by [`Handle::scp`].
+pub struct Scp<'a, H: Handler> {
+    handle: &'a Handle<H>,
+    options: Options,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Options {
+    pub(crate) recursive: bool,
+    pub(crate) preserve: bool,
+}
+
+impl<H: Handler> Handle<H> {
+    /// Transfer files with scp.
+    pub fn scp(&self) -> Scp<'_, H> {
+        Scp {
+            handle: self,
+            options: Options::default(),
+        }
+    }
+}
+
+impl<H: Handler> Scp<'_, H> {
+    /// Transfer directories and their contents (`-r`).
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.options.recursive = recursive;
+        self
+    }
+
+    /// Preserve modification times, access times and modes (`-p`).
+    pub fn preserve(mut self, preserve: bool) -> Self {
+        self.options.preserve = preserve;
+        self
+    }
+
+    /// Upload the file or directory `path` to `remote_path`. `mode` sets
+    /// the permissions of the uploaded file or directory; by default, the
+    /// local permissions are used.
+    pub async fn upload(
+        &self,
+        path: impl AsRef<Path>,
+        remote_path: &str,
+        mode: Option<u32>,
+    ) -> Result<(), Error> {
+        let mut stream = self.exec("-t", remote_path).await?;
+        send(&mut stream, path.as_ref(), mode, self.options).await?;
+        finish(stream).await
+    }
+
+    /// Download the file or directory `remote_path` to `path`. If `path`
+    /// is an existing directory, the remote file is stored in it.
+    pub async fn download(&self, remote_path: &str, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut stream = self.exec("-f", remote_path).await?;
+        receive(&mut stream, path.as_ref(), self.options).await?;
+        finish(stream).await
+    }
+
+    async fn exec(
+        &self,
+        mode: &str,
+        remote_path: &str,
+    ) -> Result<BufReader<crate::ChannelStream<super::Msg>>, Error> {
+        let mut command = format!("scp {mode}");
+        if self.options.recursive {
+            command.push_str(" -r");
+        }
+        if self.options.preserve {
+            command.push_str(" -p");
+        }
+        command.push(' ');
+        command.push_str(&shell_quote(remote_path));
+        debug!("running {command:?}");
+        let channel = self.handle.channel_open_session().await?;
+        channel.exec(false, command).await?;
+        Ok(BufReader::new(channel.into_stream()))
+    }
+}
+
+/// Send EOF, and wait for the remote scp to exit.
+async fn finish<S: AsyncBufRead + AsyncWrite + Unpin>(mut stream: S) -> Result<(), Error> {
+    stream.shutdown().await?;
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).await?;
+    Ok(())
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn protocol_error(message: impl Into<String>) -> Error {
+    Error::Scp(message.into())
+}
+
+async fn read_ack<S: AsyncBufRead + Unpin>(stream: &mut S) -> Result<(), Error> {
+    match stream.read_u8().await? {
+        0 => Ok(()),
+        1 | 2 => Err(protocol_error(read_line(stream).await?)),
+        c => Err(protocol_error(format!("unexpected reply {c:?}"))),
+    }
+}
+
+async fn read_line<S: AsyncBufRead + Unpin>(stream: &mut S) -> Result<String, Error> {
+    let mut line = Vec::new();
+    (&mut *stream)
+        .take(MAX_LINE)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if line.pop() != Some(b'\n') {
+        return Err(protocol_error("truncated line"));
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+async fn write_line<S: AsyncBufRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    line: &str,
+) -> Result<(), Error> {
+    stream.write_all(line.as_bytes()).await?;
+    stream.write_all(b"\n").await?;
+    stream.flush().await?;
+    read_ack(stream).await
+}
+
+async fn write_ack<S: AsyncWrite + Unpin>(stream: &mut S) -> Result<(), Error> {
+    stream.write_all(&[0]).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+fn unix_time(time: std::io::Result<SystemTime>) -> u64 {
+    time.ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs())
+}
+
+fn local_mode(metadata: &std::fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o7777
+    }
+    #[cfg(not(unix))]
+    {
+        if metadata.is_dir() {
+            0o755
+        } else {
+            0o644
+        }
+    }
+}
+
+fn file_name(path: &Path) -> Result<String, Error> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| protocol_error(format!("{} has no file name", path.display())))
+}
+
+/// Run the source side of the protocol: send `path` to a sink.
+pub(crate) async fn send<S: AsyncBufRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    path: &Path,
+    mode: Option<u32>,
+    options: Options,
+) -> Result<(), Error> {
+    read_ack(stream).await?;
+    let metadata = tokio::fs::metadata(path).await?;
+    if metadata.is_dir() && !options.recursive {
+        return Err(protocol_error(format!("{} is a directory", path.display())));
+    }
+    let mode = mode.unwrap_or_else(|| local_mode(&metadata));
+    send_entry(stream, path, &file_name(path)?, mode, &metadata, options).await?;
+    if !metadata.is_dir() {
+        return Ok(());
+    }
+    let mut dirs = vec![tokio::fs::read_dir(path).await?];
+    while let Some(dir) = dirs.last_mut() {
+        let Some(entry) = dir.next_entry().await? else {
+            dirs.pop();
+            write_line(stream, "E").await?;
+            continue;
+        };
+        let path = entry.path();
+        let metadata = tokio::fs::metadata(&path).await?;
+        if !metadata.is_dir() && !metadata.is_file() {
+            debug!("skipping {}", path.display());
+            continue;
+        }
+        let mode = local_mode(&metadata);
+        send_entry(stream, &path, &file_name(&path)?, mode, &metadata, options).await?;
+        if metadata.is_dir() {
+            dirs.push(tokio::fs::read_dir(&path).await?);
+        }
+    }
+    Ok(())
+}
+
+/// Send the header of a file or directory, and the contents of a file.
+async fn send_entry<S: AsyncBufRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    path: &Path,
+    name: &str,
+    mode: u32,
+    metadata: &std::fs::Metadata,
+    options: Options,
+) -> Result<(), Error> {
+    if name.contains('\n') {
+        return Err(protocol_error(format!("invalid file name {name:?}")));
+    }
+    if options.preserve {
+        let mtime = unix_time(metadata.modified());
+        let atime = unix_time(metadata.accessed());
+        write_line(stream, &format!("T{mtime} 0 {atime} 0")).await?;
+    }
+    if metadata.is_dir() {
+        return write_line(stream, &format!("D{:04o} 0 {name}", mode & 0o7777)).await;
+    }
+    let size = metadata.len();
+    write_line(stream, &format!("C{:04o} {size} {name}", mode & 0o7777)).await?;
+    let file = tokio::fs::File::open(path).await?;
+    let copied = tokio::io::copy(&mut file.take(size), stream).await?;
+    if copied != size {
+        return Err(protocol_error(format!(
+            "{} changed during the transfer",
+            path.display()
+        )));
+    }
+    write_ack(stream).await?;
+    read_ack(stream).await
+}
+
+/// The modification and access times of a `T` line, in seconds since
+/// the Unix epoch.
+type Times = (u64, u64);
+
+/// A `C` or `D` header.
+struct Header {
+    mode: u32,
+    size: u64,
+    name: String,
+}
+
+fn parse_header(line: &str) -> Option<Header> {
+    let mut parts = line.get(1..)?.splitn(3, ' ');
+    let mode = u32::from_str_radix(parts.next()?, 8).ok()?;
+    let size = parts.next()?.parse().ok()?;
+    let name = parts.next()?.to_owned();
+    Some(Header { mode, size, name })
+}
+
+fn parse_times(line: &str) -> Option<Times> {
+    let mut parts = line.get(1..)?.split(' ');
+    let mtime = parts.next()?.parse().ok()?;
+    parts.next()?;
+    let atime = parts.next()?.parse().ok()?;
+    Some((mtime, atime))
+}
+
+/// Run the sink side of the protocol: receive files from a source into
+/// `path`.
+pub(crate) async fn receive<S: AsyncBufRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    path: &Path,
+    options: Options,
+) -> Result<(), Error> {
+    let into_dir = tokio::fs::metadata(path)
+        .await
+        .map(|m| m.is_dir())
+        .unwrap_or(false);
+    let mut dirs: Vec<(PathBuf, Option<Times>, u32)> = Vec::new();
+    let mut times = None;
+    write_ack(stream).await?;
+    loop {
+        if stream.fill_buf().await?.is_empty() {
+            if !dirs.is_empty() {
+                return Err(protocol_error("unexpected end of transfer"));
+            }
+            return Ok(());
+        }
+        let line = read_line(stream).await?;
+        let target = |name: &str| -> Result<PathBuf, Error> {
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+                return Err(protocol_error(format!("invalid file name {name:?}")));
+            }
+            Ok(match dirs.last() {
+                Some((dir, _, _)) => dir.join(name),
+                None if into_dir => path.join(name),
+                None => path.to_owned(),
+            })
+        };
+        match line.as_bytes().first() {
+            Some(1 | 2) => return Err(protocol_error(line.get(1..).unwrap_or_default())),
+            Some(b'T') => {
+                times = Some(parse_times(&line).ok_or_else(|| protocol_error(line.clone()))?);
+                write_ack(stream).await?;
+            }
+            Some(b'C') => {
+                let header = parse_header(&line).ok_or_else(|| protocol_error(line.clone()))?;
+                let target = target(&header.name)?;
+                let mut file = tokio::fs::File::create(&target).await?;
+                write_ack(stream).await?;
+                let copied =
+                    tokio::io::copy(&mut (&mut *stream).take(header.size), &mut file).await?;
+                if copied != header.size {
+                    return Err(protocol_error("unexpected end of file"));
+                }
+                read_ack(stream).await?;
+                file.flush().await?;
+                let file = file.into_std().await;
+                if options.preserve {
+                    set_mode(&target, header.mode).await?;
+                    set_times(&file, times.take())?;
+                }
+                write_ack(stream).await?;
+            }
+            Some(b'D') => {
+                if !options.recursive {
+                    return Err(protocol_error("received a directory"));
+                }
+                let header = parse_header(&line).ok_or_else(|| protocol_error(line.clone()))?;
+                let target = target(&header.name)?;
+                if !tokio::fs::metadata(&target)
+                    .await
+                    .map(|m| m.is_dir())
+                    .unwrap_or(false)
+                {
+                    tokio::fs::create_dir(&target).await?;
+                }
+                dirs.push((target, times.take(), header.mode));
+                write_ack(stream).await?;
+            }
+            Some(b'E') => {
+                let Some((dir, times, mode)) = dirs.pop() else {
+                    return Err(protocol_error("unexpected end of directory"));
+                };
+                if options.preserve {
+                    set_mode(&dir, mode).await?;
+                    // Directories cannot be opened on all platforms.
+                    if let Ok(dir) = std::fs::File::open(&dir) {
+                        let _ = set_times(&dir, times);
+                    }
+                }
+                write_ack(stream).await?;
+            }
+            _ => return Err(protocol_error(format!("unexpected line {line:?}"))),
+        }
+    }
+}
+
+async fn set_mode(path: &Path, mode: u32) -> Result<(), Error> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o7777)).await?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+fn set_times(file: &std::fs::File, times: Option<Times>) -> Result<(), Error> {
+    if let Some((mtime, atime)) = times {
+        let time = |t| UNIX_EPOCH + Duration::from_secs(t);
+        file.set_times(
+            std::fs::FileTimes::new()
+                .set_modified(time(mtime))
+                .set_accessed(time(atime)),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn headers() {
+        let header = parse_header("C0644 12 a file").unwrap();
+        assert_eq!(
+            (header.mode, header.size, header.name.as_str()),
+            (0o644, 12, "a file")
+        );
+        assert_eq!(
+            parse_times("T1700000000 0 1700000001 0"),
+            Some((1700000000, 1700000001))
+        );
+        assert!(parse_header("C0644 x a").is_none());
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+}
@@ -2,6 +2,7 @@ use log::error;
 use ssh_encoding::Encode;
 use tokio::sync::oneshot;
 
+use crate::channels::tun::TunMode;
 use crate::client::Session;
 use crate::session::EncryptedState;
 use crate::{map_err, msg, ChannelId, CryptoVec, Disconnect, Pty, Sig};
@@ -87,6 +88,30 @@ impl Session {
         })
     }
 
+    pub fn channel_open_tun(
+        &mut self,
+        mode: TunMode,
+        remote_unit: u32,
+    ) -> Result<ChannelId, crate::Error> {
+        self.channel_open_generic(b"tun@openssh.com", |write| {
+            (mode as u32).encode(write)?;
+            remote_unit.encode(write)?;
+            Ok(())
+        })
+    }
+
+    /// Opens a channel of any type, followed by `data`.
+    pub fn channel_open_custom(
+        &mut self,
+        channel_type: &str,
+        data: &[u8],
+    ) -> Result<ChannelId, crate::Error> {
+        self.channel_open_generic(channel_type.as_bytes(), |write| {
+            write.extend(data);
+            Ok(())
+        })
+    }
+
     pub fn channel_open_direct_streamlocal(
         &mut self,
         socket_path: &str,
@@ -246,6 +271,26 @@ impl Session {
         Ok(())
     }
 
+    pub fn send_break(
+        &mut self,
+        channel: ChannelId,
+        want_reply: bool,
+        length_ms: u32,
+    ) -> Result<(), crate::Error> {
+        if let Some(ref mut enc) = self.common.encrypted {
+            if let Some(channel) = enc.channels.get(&channel) {
+                push_packet!(enc.write, {
+                    msg::CHANNEL_REQUEST.encode(&mut enc.write)?;
+                    channel.recipient_channel.encode(&mut enc.write)?;
+                    "break".encode(&mut enc.write)?;
+                    (want_reply as u8).encode(&mut enc.write)?;
+                    length_ms.encode(&mut enc.write)?;
+                });
+            }
+        }
+        Ok(())
+    }
+
     pub fn request_subsystem(
         &mut self,
         want_reply: bool,
@@ -414,6 +459,62 @@ impl Session {
         Ok(())
     }
 
+    pub(crate) fn ping(&mut self) -> Result<(), crate::Error> {
+        if self.extension_info.ping().is_none() {
+            return Err(crate::Error::Unsupported("ping@openssh.com".into()));
+        }
+        let Some(ref mut enc) = self.common.encrypted else {
+            return Err(crate::Error::Inconsistent);
+        };
+        if !matches!(enc.state, EncryptedState::Authenticated) {
+            return Err(crate::Error::NotAuthenticated);
+        }
+        push_packet!(enc.write, {
+            msg::PING.encode(&mut enc.write)?;
+            "".encode(&mut enc.write)?;
+        });
+        Ok(())
+    }
+
+    /// Send a global request named `name`, followed by `payload`. If
+    /// `reply_channel` is provided, a reply is requested, and its data
+    /// (or `None` if the request failed) is sent on it.
+    pub fn global_request(
+        &mut self,
+        name: &str,
+        payload: &[u8],
+        reply_channel: Option<oneshot::Sender<Option<Vec<u8>>>>,
+    ) -> Result<(), crate::Error> {
+        if let Some(ref mut enc) = self.common.encrypted {
+            let want_reply = reply_channel.is_some();
+            if let Some(reply_channel) = reply_channel {
+                self.open_global_requests.push_back(
+                    crate::session::GlobalRequestResponse::Generic(reply_channel),
+                );
+            }
+            push_packet!(enc.write, {
+                msg::GLOBAL_REQUEST.encode(&mut enc.write)?;
+                name.encode(&mut enc.write)?;
+                (want_reply as u8).encode(&mut enc.write)?;
+                enc.write.extend(payload);
+            });
+        }
+        Ok(())
+    }
+
+    /// Tells the server that no more session channels will be opened
+    /// on this connection (`no-more-sessions@openssh.com`).
+    pub fn no_more_sessions(&mut self) -> Result<(), crate::Error> {
+        if let Some(ref mut enc) = self.common.encrypted {
+            push_packet!(enc.write, {
+                msg::GLOBAL_REQUEST.encode(&mut enc.write)?;
+                "no-more-sessions@openssh.com".encode(&mut enc.write)?;
+                0u8.encode(&mut enc.write)?;
+            });
+        }
+        Ok(())
+    }
+
     pub fn data(&mut self, channel: ChannelId, data: CryptoVec) -> Result<(), crate::Error> {
         if let Some(ref mut enc) = self.common.encrypted {
             enc.data(channel, data, self.kex.active())
@@ -506,4 +607,12 @@ impl Session {
     pub fn remote_sshid(&self) -> &[u8] {
         &self.common.remote_sshid
     }
+
+    /// The session identifier: the exchange hash `H` of the first key
+    /// exchange, which stays the same after rekeys. It is what
+    /// signatures bind to in authentication, and can be used for channel
+    /// binding. `None` before the first key exchange.
+    pub fn session_id(&self) -> Option<&[u8]> {
+        self.common.session_id()
+    }
 }
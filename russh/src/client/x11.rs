@@ -0,0 +1,384 @@
+//! X11 forwarding, equivalent to `ssh -X`.
+//!
+//! [`Handle::forward_x11`] sends an `x11-req` with a random cookie, and
+//! bridges the `x11` channels then opened by the server to the local X
+//! server. The remote clients only ever see the random cookie: it is
+//! checked and replaced with the real one, read from the Xauthority file,
+//! in the connection setup of each channel.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use rand::RngCore;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use super::{Handle, Handler, Msg};
+use crate::Channel;
+
+pub(crate) const MIT_MAGIC_COOKIE: &str = "MIT-MAGIC-COOKIE-1";
+
+/// How to reach an X server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum X11Server {
+    Unix(PathBuf),
+    Tcp(String, u16),
+}
+
+/// A parsed X display name, such as `:0`, `localhost:10.0` or the socket
+/// path used by XQuartz.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct X11Display {
+    pub server: X11Server,
+    pub number: u32,
+    pub screen: u32,
+}
+
+impl X11Display {
+    pub fn parse(display: &str) -> Option<Self> {
+        let (host, rest) = display.rsplit_once(':')?;
+        let (number, screen) = match rest.split_once('.') {
+            Some((number, screen)) => (number.parse().ok()?, screen.parse().ok()?),
+            None => (rest.parse().ok()?, 0),
+        };
+        let server = if host.starts_with('/') {
+            X11Server::Unix(PathBuf::from(display))
+        } else if host.is_empty() || host == "unix" {
+            X11Server::Unix(PathBuf::from(format!("/tmp/.X11-unix/X{number}")))
+        } else {
+            X11Server::Tcp(host.to_string(), u16::try_from(6000 + number).ok()?)
+        };
+        Some(X11Display {
+            server,
+            number,
+            screen,
+        })
+    }
+
+    /// The display named by the `DISPLAY` environment variable.
+    pub fn from_env() -> Result<Self, crate::Error> {
+        let display =
+            std::env::var("DISPLAY").map_err(|_| crate::keys::Error::EnvVar("DISPLAY"))?;
+        Self::parse(&display)
+            .ok_or_else(|| crate::Error::InvalidConfig(format!("invalid display {display:?}")))
+    }
+}
+
+/// Find the `MIT-MAGIC-COOKIE-1` of display `number` in an Xauthority
+/// file.
+pub fn xauthority_cookie(path: &Path, number: u32) -> Result<Option<Vec<u8>>, io::Error> {
+    let data = std::fs::read(path)?;
+    let number = number.to_string();
+    let mut r = &data[..];
+    while !r.is_empty() {
+        let _family = read_u16(&mut r)?;
+        let _address = read_string(&mut r)?;
+        let entry_number = read_string(&mut r)?;
+        let name = read_string(&mut r)?;
+        let cookie = read_string(&mut r)?;
+        if entry_number == number.as_bytes() && name == MIT_MAGIC_COOKIE.as_bytes() {
+            return Ok(Some(cookie.to_vec()));
+        }
+    }
+    Ok(None)
+}
+
+/// The Xauthority file, from `XAUTHORITY` or `~/.Xauthority`.
+fn xauthority_path() -> Option<PathBuf> {
+    match std::env::var_os("XAUTHORITY") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => home::home_dir().map(|home| home.join(".Xauthority")),
+    }
+}
+
+pub(crate) fn read_u16(r: &mut &[u8]) -> Result<u16, io::Error> {
+    let &[a, b, ref rest @ ..] = *r else {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    };
+    *r = rest;
+    Ok(u16::from_be_bytes([a, b]))
+}
+
+pub(crate) fn read_string<'a>(r: &mut &'a [u8]) -> Result<&'a [u8], io::Error> {
+    let len = read_u16(r)? as usize;
+    if r.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (s, rest) = r.split_at(len);
+    *r = rest;
+    Ok(s)
+}
+
+/// X11 forwarding started by [`Handle::forward_x11`]. X11 channels opened
+/// by the server are bridged to the local display for as long as this
+/// guard is alive.
+#[derive(Debug)]
+pub struct X11Forward {
+    task: tokio::task::JoinHandle<()>,
+    sender: Sender<Msg>,
+}
+
+impl Drop for X11Forward {
+    fn drop(&mut self) {
+        debug!("stopping X11 forward");
+        self.task.abort();
+        let sender = self.sender.clone();
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move {
+                let _ = sender.send(Msg::UnregisterX11Forward).await;
+            });
+        }
+    }
+}
+
+impl<H: Handler> Handle<H> {
+    /// Request X11 forwarding on the session channel `channel`, and
+    /// bridge the X11 channels opened by the server to the display named
+    /// by `DISPLAY`, authenticating with the cookie from the Xauthority
+    /// file.
+    ///
+    /// X11 channels are not passed to [`Handler::server_channel_open_x11`]
+    /// while the returned guard is alive.
+    pub async fn forward_x11(&self, channel: &Channel<Msg>) -> Result<X11Forward, crate::Error> {
+        let display = X11Display::from_env()?;
+        let cookie = match xauthority_path() {
+            Some(path) => xauthority_cookie(&path, display.number).unwrap_or_else(|e| {
+                debug!("could not read {path:?}: {e:?}");
+                None
+            }),
+            None => None,
+        };
+        self.forward_x11_to(channel, display, cookie).await
+    }
+
+    /// Same as [`Handle::forward_x11`], with an explicit display and
+    /// `MIT-MAGIC-COOKIE-1` cookie. If `cookie` is `None`, connections
+    /// are made without authentication.
+    pub async fn forward_x11_to(
+        &self,
+        channel: &Channel<Msg>,
+        display: X11Display,
+        cookie: Option<Vec<u8>>,
+    ) -> Result<X11Forward, crate::Error> {
+        let mut fake_cookie = vec![0; 16];
+        rand::thread_rng().fill_bytes(&mut fake_cookie);
+
+        let (channel_sender, receiver) = tokio::sync::mpsc::channel(self.channel_buffer_size);
+        self.sender
+            .send(Msg::RegisterX11Forward { channel_sender })
+            .await
+            .map_err(|_| crate::Error::SendError)?;
+        channel
+            .request_x11(
+                false,
+                false,
+                MIT_MAGIC_COOKIE,
+                data_encoding::HEXLOWER.encode(&fake_cookie),
+                display.screen,
+            )
+            .await?;
+        let task = tokio::spawn(accept_x11(receiver, display.server, fake_cookie, cookie));
+        Ok(X11Forward {
+            task,
+            sender: self.sender.clone(),
+        })
+    }
+}
+
+async fn accept_x11(
+    mut receiver: Receiver<Channel<Msg>>,
+    server: X11Server,
+    fake_cookie: Vec<u8>,
+    cookie: Option<Vec<u8>>,
+) {
+    while let Some(channel) = receiver.recv().await {
+        let server = server.clone();
+        let fake_cookie = fake_cookie.clone();
+        let cookie = cookie.clone();
+        tokio::spawn(async move {
+            let mut channel_stream = channel.into_stream();
+            let setup = match read_setup(&mut channel_stream, &fake_cookie, cookie.as_deref()).await
+            {
+                Ok(setup) => setup,
+                Err(e) => {
+                    warn!("X11 forward: rejecting connection: {e:?}");
+                    return;
+                }
+            };
+            let result = match server {
+                #[cfg(unix)]
+                X11Server::Unix(ref path) => match tokio::net::UnixStream::connect(path).await {
+                    Ok(stream) => bridge(stream, channel_stream, &setup).await,
+                    Err(e) => Err(e),
+                },
+                #[cfg(not(unix))]
+                X11Server::Unix(_) => Err(io::ErrorKind::Unsupported.into()),
+                X11Server::Tcp(ref host, port) => {
+                    match tokio::net::TcpStream::connect((host.as_str(), port)).await {
+                        Ok(stream) => bridge(stream, channel_stream, &setup).await,
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+            if let Err(e) = result {
+                debug!("X11 forward: connection to {server:?} ended: {e:?}");
+            }
+        });
+    }
+}
+
+async fn bridge<S, C>(mut stream: S, mut channel_stream: C, setup: &[u8]) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(setup).await?;
+    tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await?;
+    Ok(())
+}
+
+/// Read the connection setup sent by an X11 client, check that it
+/// authenticates with `fake_cookie`, and return it with `cookie` instead.
+async fn read_setup<R: AsyncRead + Unpin>(
+    r: &mut R,
+    fake_cookie: &[u8],
+    cookie: Option<&[u8]>,
+) -> Result<Vec<u8>, io::Error> {
+    let mut header = [0; 12];
+    r.read_exact(&mut header).await?;
+    let [order, _, major0, major1, minor0, minor1, n0, n1, d0, d1, _, _] = header;
+    let decode = |a, b| match order {
+        b'B' => Ok(u16::from_be_bytes([a, b])),
+        b'l' => Ok(u16::from_le_bytes([a, b])),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid byte order",
+        )),
+    };
+    let encode = |n: u16| match order {
+        b'B' => n.to_be_bytes(),
+        _ => n.to_le_bytes(),
+    };
+    let name_len = decode(n0, n1)? as usize;
+    let data_len = decode(d0, d1)? as usize;
+    let mut name = vec![0; pad4(name_len)];
+    r.read_exact(&mut name).await?;
+    let mut data = vec![0; pad4(data_len)];
+    r.read_exact(&mut data).await?;
+    name.truncate(name_len);
+    data.truncate(data_len);
+    if name != MIT_MAGIC_COOKIE.as_bytes() || data != fake_cookie {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "invalid authentication cookie",
+        ));
+    }
+
+    let (name, data) = match cookie {
+        Some(cookie) => (MIT_MAGIC_COOKIE.as_bytes(), cookie),
+        None => (&b""[..], &b""[..]),
+    };
+    let too_long = |_| io::Error::new(io::ErrorKind::InvalidInput, "cookie too long");
+    let mut setup = vec![order, 0, major0, major1, minor0, minor1];
+    setup.extend(encode(u16::try_from(name.len()).map_err(too_long)?));
+    setup.extend(encode(u16::try_from(data.len()).map_err(too_long)?));
+    setup.extend([0, 0]);
+    for field in [name, data] {
+        setup.extend(field);
+        setup.resize(setup.len() + pad4(field.len()) - field.len(), 0);
+    }
+    Ok(setup)
+}
+
+fn pad4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    fn setup(order: u8, name: &[u8], data: &[u8]) -> Vec<u8> {
+        let encode = |n: usize| match order {
+            b'B' => (n as u16).to_be_bytes(),
+            _ => (n as u16).to_le_bytes(),
+        };
+        let mut setup = vec![order, 0, 0, 11, 0, 0];
+        setup.extend(encode(name.len()));
+        setup.extend(encode(data.len()));
+        setup.extend([0, 0]);
+        for field in [name, data] {
+            setup.extend(field);
+            setup.resize(setup.len() + pad4(field.len()) - field.len(), 0);
+        }
+        setup
+    }
+
+    #[test]
+    fn parse_display() {
+        assert_eq!(
+            X11Display::parse(":0"),
+            Some(X11Display {
+                server: X11Server::Unix("/tmp/.X11-unix/X0".into()),
+                number: 0,
+                screen: 0,
+            })
+        );
+        assert_eq!(
+            X11Display::parse("localhost:10.1"),
+            Some(X11Display {
+                server: X11Server::Tcp("localhost".into(), 6010),
+                number: 10,
+                screen: 1,
+            })
+        );
+        assert_eq!(
+            X11Display::parse("/private/tmp/com.apple.launchd.x/org.xquartz:0").map(|d| d.server),
+            Some(X11Server::Unix(
+                "/private/tmp/com.apple.launchd.x/org.xquartz:0".into()
+            ))
+        );
+        assert_eq!(X11Display::parse("localhost"), None);
+        assert_eq!(X11Display::parse(":x"), None);
+    }
+
+    #[test]
+    fn xauthority() {
+        let mut file = Vec::new();
+        for (number, cookie) in [(&b"1"[..], [1; 16]), (b"0", [2; 16])] {
+            // FamilyLocal
+            file.extend([1, 0]);
+            for field in [&b"host"[..], number, MIT_MAGIC_COOKIE.as_bytes(), &cookie] {
+                file.extend((field.len() as u16).to_be_bytes());
+                file.extend(field);
+            }
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Xauthority");
+        std::fs::write(&path, file).unwrap();
+        assert_eq!(xauthority_cookie(&path, 0).unwrap(), Some(vec![2; 16]));
+        assert_eq!(xauthority_cookie(&path, 1).unwrap(), Some(vec![1; 16]));
+        assert_eq!(xauthority_cookie(&path, 2).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn replace_cookie() {
+        for order in [b'B', b'l'] {
+            let fake = setup(order, MIT_MAGIC_COOKIE.as_bytes(), &[1; 16]);
+            let real = read_setup(&mut &fake[..], &[1; 16], Some(&[2; 16]))
+                .await
+                .unwrap();
+            assert_eq!(real, setup(order, MIT_MAGIC_COOKIE.as_bytes(), &[2; 16]));
+
+            let anonymous = read_setup(&mut &fake[..], &[1; 16], None).await.unwrap();
+            assert_eq!(anonymous, setup(order, b"", b""));
+
+            let err = read_setup(&mut &fake[..], &[3; 16], None)
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        }
+    }
+}
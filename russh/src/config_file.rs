@@ -0,0 +1,195 @@
+//! Parsing of OpenSSH configuration files, shared by
+//! [`server::sshd_config`](crate::server::sshd_config) and the
+//! `russh-config` crate.
+
+use log::debug;
+
+use crate::Error;
+
+/// Maximum nesting of `Include` directives, as in OpenSSH.
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Split a line into its lowercase keyword and the rest of the line.
+/// Returns `None` for blank lines and comments.
+pub fn split_line(line: &str) -> Option<(String, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or(line.len());
+    let (keyword, rest) = line.split_at(end);
+    // The keyword and arguments are separated by whitespace and at most
+    // one `=`.
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim_start();
+    Some((keyword.to_ascii_lowercase(), rest))
+}
+
+/// Split arguments on whitespace, honouring double quotes.
+pub fn split_args(s: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    args
+}
+
+/// Parse a `yes`/`no` (or `true`/`false`) argument.
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match s.to_ascii_lowercase().as_str() {
+        "yes" | "true" => Some(true),
+        "no" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Match a comma-separated list of patterns, each checked with
+/// `matches`. A matching negated pattern (`!pattern`) overrides any
+/// positive match.
+pub fn match_patterns(patterns: &str, matches: impl Fn(&str) -> bool) -> bool {
+    let mut matched = false;
+    for pattern in patterns.split(',') {
+        let pattern = pattern.trim();
+        if let Some(pattern) = pattern.strip_prefix('!') {
+            if matches(pattern) {
+                return false;
+            }
+        } else if matches(pattern) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// Apply an OpenSSH algorithm list to `default`: a leading `+` appends to
+/// it, `-` removes from it, `^` moves to its front, and a plain list
+/// replaces it. Algorithms not supported by russh, and repeated ones,
+/// are ignored.
+pub fn algorithm_list<T: Clone + PartialEq>(
+    default: &[T],
+    spec: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Vec<T>, Error> {
+    let (op, list) = match spec.chars().next() {
+        Some(op @ ('+' | '-' | '^')) => (Some(op), spec.get(1..).unwrap_or("")),
+        _ => (None, spec),
+    };
+    let parsed = list
+        .split(',')
+        .filter_map(|name| {
+            let algorithm = parse(name);
+            if algorithm.is_none() {
+                debug!("ignoring unsupported algorithm {name:?}");
+            }
+            algorithm
+        })
+        .collect::<Vec<_>>();
+    let candidates = match op {
+        Some('+') => {
+            let mut result = default.to_vec();
+            result.extend(parsed);
+            result
+        }
+        Some('-') => default
+            .iter()
+            .filter(|a| !parsed.contains(a))
+            .cloned()
+            .collect(),
+        Some('^') => {
+            let mut result = parsed;
+            result.extend(default.iter().cloned());
+            result
+        }
+        _ => parsed,
+    };
+    // Keep the first occurrence of each algorithm, in order.
+    let mut result = Vec::with_capacity(candidates.len());
+    for algorithm in candidates {
+        if !result.contains(&algorithm) {
+            result.push(algorithm)
+        }
+    }
+    if result.is_empty() {
+        return Err(Error::InvalidConfig(format!(
+            "no supported algorithm in {spec:?}"
+        )));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        assert_eq!(None, split_line("  # comment"));
+        assert_eq!(
+            Some(("ciphers".to_string(), "aes128-ctr")),
+            split_line("Ciphers = aes128-ctr")
+        );
+        assert_eq!(
+            vec!["a b".to_string(), "c".to_string()],
+            split_args(r#""a b"  c"#)
+        );
+    }
+
+    #[test]
+    fn patterns() {
+        let matches = |host: &'static str| {
+            move |pattern: &str| crate::helpers::wildcard_match(host.as_bytes(), pattern.as_bytes())
+        };
+        assert!(match_patterns("*.example.com", matches("a.example.com")));
+        assert!(!match_patterns(
+            "*.example.com, !a.*",
+            matches("a.example.com")
+        ));
+        assert!(!match_patterns("!a.*", matches("b.example.com")));
+    }
+
+    #[test]
+    fn algorithms() {
+        let parse = |n: &str| Some(n.to_string());
+        let default = ["a".to_string(), "b".to_string()];
+        assert_eq!(
+            vec!["a", "b", "c"],
+            algorithm_list(&default, "+c,a", parse).unwrap_or_default()
+        );
+        assert_eq!(
+            vec!["c", "a", "b"],
+            algorithm_list(&default, "^c,a,c", parse).unwrap_or_default()
+        );
+        assert_eq!(
+            vec!["b", "a"],
+            algorithm_list(&default, "b,a,b", parse).unwrap_or_default()
+        );
+        assert_eq!(
+            vec!["b"],
+            algorithm_list(&default, "-a", parse).unwrap_or_default()
+        );
+        assert!(algorithm_list(&default, "-a,b", parse).is_err());
+    }
+}
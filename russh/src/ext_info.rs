@@ -0,0 +1,107 @@
+//! Extensions negotiated with `SSH_MSG_EXT_INFO`
+//! ([RFC 8308](https://tools.ietf.org/html/rfc8308)).
+
+use std::str::FromStr;
+
+use ssh_encoding::Encode;
+use ssh_key::Algorithm;
+
+use crate::{msg, CryptoVec, Error};
+
+/// The extensions received from the other side. Both sides send them
+/// after the first key exchange, and servers may send some of them again
+/// after authentication, in which case the later values replace the
+/// earlier ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtensionInfo {
+    /// The name and raw value of every extension, in the order they
+    /// were first received.
+    pub extensions: Vec<(String, Vec<u8>)>,
+    /// The signature algorithms of `server-sig-algs` that this crate
+    /// knows about, or `None` if it was not received.
+    pub server_sig_algs: Option<Vec<Algorithm>>,
+}
+
+impl ExtensionInfo {
+    pub(crate) fn insert(&mut self, name: String, value: Vec<u8>) {
+        if name == "server-sig-algs" {
+            self.server_sig_algs = Some(
+                String::from_utf8_lossy(&value)
+                    .split(',')
+                    .filter_map(|x| Algorithm::from_str(x).ok())
+                    .collect(),
+            );
+        }
+        match self.extensions.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.extensions.push((name, value)),
+        }
+    }
+
+    /// The raw value of extension `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.extensions
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// The version of `publickey-hostbound@openssh.com` supported by
+    /// the other side, if any.
+    pub fn publickey_hostbound(&self) -> Option<String> {
+        self.version("publickey-hostbound@openssh.com")
+    }
+
+    /// The version of `ping@openssh.com` supported by the other side, if
+    /// any.
+    pub fn ping(&self) -> Option<String> {
+        self.version("ping@openssh.com")
+    }
+
+    fn version(&self, name: &str) -> Option<String> {
+        self.get(name)
+            .map(|v| String::from_utf8_lossy(v).into_owned())
+    }
+}
+
+/// Write an `SSH_MSG_EXT_INFO` packet with `extensions`.
+pub(crate) fn write_ext_info<'a>(
+    write: &mut CryptoVec,
+    extensions: impl ExactSizeIterator<Item = (&'a str, &'a [u8])>,
+) -> Result<(), Error> {
+    push_packet!(write, {
+        msg::EXT_INFO.encode(write)?;
+        (extensions.len() as u32).encode(write)?;
+        for (name, value) in extensions {
+            name.encode(write)?;
+            value.encode(write)?;
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let mut info = ExtensionInfo::default();
+        info.insert(
+            "server-sig-algs".into(),
+            b"ssh-ed25519,unknown,rsa-sha2-512".to_vec(),
+        );
+        info.insert("ping@openssh.com".into(), b"0".to_vec());
+        info.insert("server-sig-algs".into(), b"ssh-ed25519".to_vec());
+        assert_eq!(info.server_sig_algs, Some(vec![Algorithm::Ed25519]));
+        assert_eq!(info.ping().as_deref(), Some("0"));
+        assert_eq!(info.publickey_hostbound(), None);
+        assert_eq!(info.extensions.len(), 2);
+        assert_eq!(info.get("server-sig-algs"), Some(&b"ssh-ed25519"[..]));
+    }
+}
@@ -4,6 +4,30 @@ use ssh_encoding::{Decode, Encode};
 use ssh_key::private::KeypairData;
 use ssh_key::Algorithm;
 
+/// Glob matching with `*` (any sequence) and `?` (any character).
+pub(crate) fn wildcard_match(s: &[u8], pattern: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => {
+            (0..=s.len()).any(|i| s.get(i..).is_some_and(|s| wildcard_match(s, rest)))
+        }
+        Some((&p, rest)) => match s.split_first() {
+            Some((&c, s)) if p == b'?' || p == c => wildcard_match(s, rest),
+            _ => false,
+        },
+    }
+}
+
+/// The data signed by a server to prove that it holds the host key
+/// `key`, in answer to a `hostkeys-prove-00@openssh.com` request.
+pub(crate) fn host_key_proof_data(session_id: &[u8], key: &[u8]) -> ssh_key::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    "hostkeys-prove-00@openssh.com".encode(&mut data)?;
+    session_id.encode(&mut data)?;
+    key.encode(&mut data)?;
+    Ok(data)
+}
+
 #[doc(hidden)]
 pub trait EncodedExt {
     fn encoded(&self) -> ssh_key::Result<Vec<u8>>;
@@ -0,0 +1,205 @@
+use byteorder::{BigEndian, ByteOrder};
+use log::debug;
+use rand_core::{OsRng, RngCore};
+use ssh_encoding::{Encode, Writer};
+use x448::{PublicKey, Secret};
+
+use super::{compute_keys, KexAlgorithm, KexAlgorithmImplementor, KexType};
+use crate::kex::encode_mpint;
+use crate::mac::{self};
+use crate::session::Exchange;
+use crate::{cipher, msg, CryptoVec};
+
+pub struct Curve448KexType {}
+
+impl KexType for Curve448KexType {
+    fn make(&self) -> KexAlgorithm {
+        Curve448Kex {
+            local_secret: None,
+            shared_secret: None,
+        }
+        .into()
+    }
+}
+
+#[doc(hidden)]
+pub struct Curve448Kex {
+    local_secret: Option<Secret>,
+    shared_secret: Option<[u8; 56]>,
+}
+
+impl std::fmt::Debug for Curve448Kex {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Algorithm {{ local_secret: [hidden], shared_secret: [hidden] }}",
+        )
+    }
+}
+
+fn generate_secret() -> Secret {
+    let mut bytes = [0; 56];
+    OsRng.fill_bytes(&mut bytes);
+    Secret::from(bytes)
+}
+
+/// X448 with the peer's public key, failing on the low order points
+/// (RFC 8731, section 3).
+fn diffie_hellman(secret: &Secret, remote_pubkey: &[u8]) -> Result<[u8; 56], crate::Error> {
+    let remote_pubkey = PublicKey::from_bytes(remote_pubkey).ok_or(crate::Error::Kex)?;
+    let shared = secret
+        .as_diffie_hellman(&remote_pubkey)
+        .ok_or(crate::Error::Kex)?;
+    Ok(*shared.as_bytes())
+}
+
+impl KexAlgorithmImplementor for Curve448Kex {
+    fn skip_exchange(&self) -> bool {
+        false
+    }
+
+    #[doc(hidden)]
+    fn server_dh(&mut self, exchange: &mut Exchange, payload: &[u8]) -> Result<(), crate::Error> {
+        debug!("server_dh");
+
+        let client_pubkey = {
+            if payload.first() != Some(&msg::KEX_ECDH_INIT) {
+                return Err(crate::Error::Inconsistent);
+            }
+
+            #[allow(clippy::indexing_slicing)] // length checked
+            let pubkey_len = BigEndian::read_u32(&payload[1..]) as usize;
+
+            if pubkey_len != 56 {
+                return Err(crate::Error::Kex);
+            }
+
+            payload
+                .get(5..5 + pubkey_len)
+                .ok_or(crate::Error::Inconsistent)?
+        };
+
+        let server_secret = generate_secret();
+        let server_pubkey = PublicKey::from(&server_secret);
+
+        // fill exchange.
+        exchange.server_ephemeral.clear();
+        exchange.server_ephemeral.extend(server_pubkey.as_bytes());
+        self.shared_secret = Some(diffie_hellman(&server_secret, client_pubkey)?);
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    fn client_dh(
+        &mut self,
+        client_ephemeral: &mut CryptoVec,
+        writer: &mut impl Writer,
+    ) -> Result<(), crate::Error> {
+        let client_secret = generate_secret();
+        let client_pubkey = PublicKey::from(&client_secret);
+
+        // fill exchange.
+        client_ephemeral.clear();
+        client_ephemeral.extend(client_pubkey.as_bytes());
+
+        msg::KEX_ECDH_INIT.encode(writer)?;
+        client_pubkey.as_bytes().encode(writer)?;
+
+        self.local_secret = Some(client_secret);
+        Ok(())
+    }
+
+    fn compute_shared_secret(&mut self, remote_pubkey_: &[u8]) -> Result<(), crate::Error> {
+        let local_secret = self.local_secret.take().ok_or(crate::Error::KexInit)?;
+        self.shared_secret = Some(diffie_hellman(&local_secret, remote_pubkey_)?);
+        Ok(())
+    }
+
+    fn compute_exchange_hash(
+        &self,
+        key: &CryptoVec,
+        exchange: &Exchange,
+        buffer: &mut CryptoVec,
+    ) -> Result<CryptoVec, crate::Error> {
+        // Computing the exchange hash, see page 7 of RFC 5656.
+        buffer.clear();
+        exchange.client_id.encode(buffer)?;
+        exchange.server_id.encode(buffer)?;
+        exchange.client_kex_init.encode(buffer)?;
+        exchange.server_kex_init.encode(buffer)?;
+
+        buffer.extend(key);
+        exchange.client_ephemeral.encode(buffer)?;
+        exchange.server_ephemeral.encode(buffer)?;
+
+        if let Some(ref shared) = self.shared_secret {
+            encode_mpint(shared, buffer)?;
+        }
+
+        use sha2::Digest;
+        let mut hasher = sha2::Sha512::new();
+        hasher.update(&buffer);
+
+        let mut res = CryptoVec::new();
+        res.extend(hasher.finalize().as_slice());
+        Ok(res)
+    }
+
+    fn compute_keys(
+        &self,
+        session_id: &CryptoVec,
+        exchange_hash: &CryptoVec,
+        cipher: cipher::Name,
+        remote_to_local_mac: mac::Name,
+        local_to_remote_mac: mac::Name,
+        is_server: bool,
+    ) -> Result<super::cipher::CipherPair, crate::Error> {
+        compute_keys::<sha2::Sha512>(
+            self.shared_secret.as_ref().map(|x| x.as_slice()),
+            session_id,
+            exchange_hash,
+            cipher,
+            remote_to_local_mac,
+            local_to_remote_mac,
+            is_server,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn shared_secret() {
+        let mut client = Curve448Kex {
+            local_secret: None,
+            shared_secret: None,
+        };
+        let mut server = Curve448Kex {
+            local_secret: None,
+            shared_secret: None,
+        };
+
+        let mut exchange = Exchange::default();
+        let mut init = CryptoVec::new();
+        client
+            .client_dh(&mut exchange.client_ephemeral, &mut init)
+            .unwrap();
+        server.server_dh(&mut exchange, &init).unwrap();
+        client
+            .compute_shared_secret(&exchange.server_ephemeral)
+            .unwrap();
+        assert!(client.shared_secret.is_some());
+        assert_eq!(client.shared_secret, server.shared_secret);
+
+        // A low order point.
+        assert!(Curve448Kex {
+            local_secret: Some(generate_secret()),
+            shared_secret: None,
+        }
+        .compute_shared_secret(&[0; 56])
+        .is_err());
+    }
+}
@@ -42,6 +42,14 @@ pub struct DhGroup {
 }
 
 impl DhGroup {
+    /// A group from its big-endian prime and generator.
+    pub fn new(prime: Vec<u8>, generator: Vec<u8>) -> Self {
+        DhGroup {
+            prime: prime.into(),
+            generator: generator.into(),
+        }
+    }
+
     pub fn bit_size(&self) -> usize {
         let Some(fsb_idx) = self.prime.deref().iter().position(|&x| x != 0) else {
             return 0;
@@ -1,4 +1,5 @@
 pub mod groups;
+pub mod moduli;
 use std::marker::PhantomData;
 
 use byteorder::{BigEndian, ByteOrder};
@@ -339,7 +340,13 @@ impl Decode for GexParams {
         let min_group_size = u32::decode(reader)? as usize;
         let preferred_group_size = u32::decode(reader)? as usize;
         let max_group_size = u32::decode(reader)? as usize;
-        GexParams::new(min_group_size, preferred_group_size, max_group_size)
+        // Checked against the group sizes a server offers, in
+        // `Moduli::select`.
+        Ok(GexParams::unchecked(
+            min_group_size,
+            preferred_group_size,
+            max_group_size,
+        ))
     }
 
     type Error = Error;
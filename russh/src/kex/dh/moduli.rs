@@ -0,0 +1,190 @@
+use std::path::Path;
+
+use num_bigint::BigUint;
+use rand::Rng;
+
+use super::groups::{DhGroup, BUILTIN_SAFE_DH_GROUPS};
+use crate::client::GexParams;
+use crate::Error;
+
+/// The smallest and largest group sizes a server offers in the group
+/// exchanges, whatever the client asks for, as in OpenSSH.
+const MIN_GROUP_SIZE: usize = 2048;
+const MAX_GROUP_SIZE: usize = 8192;
+
+/// The OpenSSH moduli type of safe primes.
+const TYPE_SAFE: u32 = 2;
+/// The OpenSSH moduli test flag of the numbers found composite.
+const TESTS_COMPOSITE: u32 = 0x01;
+
+/// A set of Diffie-Hellman groups for the servers to choose from in the
+/// `diffie-hellman-group-exchange-*` key exchanges (RFC 4419), such as
+/// OpenSSH's `/etc/ssh/moduli`.
+#[derive(Debug, Clone)]
+pub struct Moduli {
+    groups: Vec<DhGroup>,
+}
+
+impl Moduli {
+    pub fn new(groups: Vec<DhGroup>) -> Self {
+        Moduli { groups }
+    }
+
+    /// The built-in standard groups, used by the default
+    /// [`Handler::lookup_dh_gex_group`](crate::server::Handler::lookup_dh_gex_group).
+    pub fn builtin() -> Self {
+        Self::new(
+            BUILTIN_SAFE_DH_GROUPS
+                .iter()
+                .map(|g| (*g).clone())
+                .collect(),
+        )
+    }
+
+    /// Parse a file in the format of OpenSSH's `/etc/ssh/moduli`, as
+    /// generated by `ssh-keygen -M`. Like `sshd`, this keeps only the
+    /// safe primes which passed a primality test.
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let mut groups = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::InvalidConfig(format!("moduli line {}: {}", n + 1, line));
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [_timestamp, type_, tests, _tries, size, generator, prime] = fields[..] else {
+                return Err(invalid());
+            };
+            let type_: u32 = type_.parse().map_err(|_| invalid())?;
+            let tests: u32 = tests.parse().map_err(|_| invalid())?;
+            // The size is that of the prime minus one.
+            let size: u64 = size.parse().map_err(|_| invalid())?;
+            let generator = BigUint::parse_bytes(generator.as_bytes(), 16).ok_or_else(invalid)?;
+            let prime = BigUint::parse_bytes(prime.as_bytes(), 16).ok_or_else(invalid)?;
+            if prime.bits() != size + 1 {
+                return Err(invalid());
+            }
+            if type_ != TYPE_SAFE || tests & TESTS_COMPOSITE != 0 || tests & !TESTS_COMPOSITE == 0 {
+                continue;
+            }
+            groups.push(DhGroup::new(prime.to_bytes_be(), generator.to_bytes_be()));
+        }
+        Ok(Self::new(groups))
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents)
+    }
+
+    pub fn groups(&self) -> &[DhGroup] {
+        &self.groups
+    }
+
+    /// Choose a group for a client, as `sshd` does: among the groups
+    /// between the minimal and maximal sizes of `params`, one of the
+    /// smallest at least as large as the preferred size, or else one of
+    /// the largest. The sizes asked by the client are first brought
+    /// between 2048 and 8192 bits.
+    pub fn select(&self, params: &GexParams) -> Option<DhGroup> {
+        let min = params.min_group_size().max(MIN_GROUP_SIZE);
+        let max = params.max_group_size().min(MAX_GROUP_SIZE);
+        let preferred = params
+            .preferred_group_size()
+            .clamp(MIN_GROUP_SIZE, MAX_GROUP_SIZE);
+        if max < min || preferred < min || max < preferred {
+            return None;
+        }
+
+        let mut best = 0;
+        for group in &self.groups {
+            let size = group.bit_size();
+            if size < min || size > max {
+                continue;
+            }
+            if (size > preferred && size < best) || (size > best && best < preferred) {
+                best = size;
+            }
+        }
+        let candidates: Vec<&DhGroup> = self
+            .groups
+            .iter()
+            .filter(|g| best > 0 && g.bit_size() == best)
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let i = rand::thread_rng().gen_range(0..candidates.len());
+        candidates.get(i).map(|g| (*g).clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use crate::kex::dh::groups::{DH_GROUP14, DH_GROUP15, DH_GROUP16, DH_GROUP18};
+
+    fn line(group: &DhGroup, type_: u32, tests: u32) -> String {
+        let prime = BigUint::from_bytes_be(&group.prime);
+        format!(
+            "20240101000000 {} {} 100 {} {:X} {:X}\n",
+            type_,
+            tests,
+            prime.bits() - 1,
+            BigUint::from_bytes_be(&group.generator),
+            prime,
+        )
+    }
+
+    #[test]
+    fn parse() {
+        let contents = format!(
+            "# Time Type Tests Tries Size Generator Modulus\n{}{}{}{}",
+            line(&DH_GROUP14, 2, 6),
+            line(&DH_GROUP15, 2, 1),
+            line(&DH_GROUP16, 1, 6),
+            line(&DH_GROUP18, 2, 4),
+        );
+        let moduli = Moduli::parse(&contents).unwrap();
+        let sizes: Vec<usize> = moduli.groups().iter().map(|g| g.bit_size()).collect();
+        assert_eq!(sizes, [2048, 8192]);
+
+        assert!(Moduli::parse("20240101000000 2 6 100 2047 2\n").is_err());
+        assert!(Moduli::parse("20240101000000 2 6 100 1023 2 FF\n").is_err());
+    }
+
+    #[test]
+    fn select() {
+        let moduli = Moduli::new(
+            [&DH_GROUP14, &DH_GROUP15, &DH_GROUP16, &DH_GROUP18]
+                .into_iter()
+                .cloned()
+                .collect(),
+        );
+        let size = |min, preferred, max| {
+            moduli
+                .select(&GexParams::new(min, preferred, max).unwrap())
+                .map(|g| g.bit_size())
+        };
+        assert_eq!(size(2048, 3072, 8192), Some(3072));
+        assert_eq!(size(2048, 3500, 8192), Some(4096));
+        assert_eq!(size(2048, 7680, 8192), Some(8192));
+        assert_eq!(size(2048, 7680, 7680), Some(4096));
+        assert_eq!(size(5000, 6000, 7000), None);
+        // The client sizes are brought between 2048 and 8192 bits.
+        assert_eq!(
+            moduli
+                .select(&GexParams::unchecked(1024, 1024, 2048))
+                .map(|g| g.bit_size()),
+            Some(2048)
+        );
+        assert_eq!(
+            moduli
+                .select(&GexParams::unchecked(1024, 1024, 1024))
+                .map(|g| g.bit_size()),
+            None
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! Hybrid post-quantum key exchanges, combining ML-KEM with a classical
+//! elliptic curve Diffie-Hellman, as in
+//! [draft-ietf-sshm-mlkem-hybrid-kex](https://datatracker.ietf.org/doc/draft-ietf-sshm-mlkem-hybrid-kex/).
+//!
+//! The client sends its ML-KEM encapsulation key followed by its
+//! ephemeral curve public key, and the server replies with the ML-KEM
+//! ciphertext followed by its own ephemeral public key. The shared
+//! secret is the hash of both shared secrets, and is encoded as a
+//! `string` rather than an `mpint`.
+
+use std::marker::PhantomData;
+
+use byteorder::{BigEndian, ByteOrder};
+use curve25519_dalek::constants::ED25519_BASEPOINT_TABLE;
+use curve25519_dalek::montgomery::MontgomeryPoint;
+use curve25519_dalek::scalar::Scalar;
+use digest::Digest;
+use elliptic_curve::ecdh::EphemeralSecret;
+use elliptic_curve::sec1::ToEncodedPoint;
+use log::debug;
+use ml_kem::kem::{Decapsulate, Encapsulate};
+use ml_kem::{Ciphertext, Encoded, EncodedSizeUser, KemCore, MlKem1024, MlKem768};
+use p384::NistP384;
+use sha2::{Sha256, Sha384};
+use ssh_encoding::{Encode, Writer};
+use subtle::ConstantTimeEq;
+
+use super::{compute_keys_with_encoded_secret, KexAlgorithm, KexAlgorithmImplementor, KexType};
+use crate::mac::{self};
+use crate::session::Exchange;
+use crate::{cipher, msg, CryptoVec, Error};
+
+pub struct MlKem768X25519KexType {}
+
+impl KexType for MlKem768X25519KexType {
+    fn make(&self) -> KexAlgorithm {
+        HybridKex::<MlKem768, X25519, Sha256>::default().into()
+    }
+}
+
+pub struct MlKem1024NistP384KexType {}
+
+impl KexType for MlKem1024NistP384KexType {
+    fn make(&self) -> KexAlgorithm {
+        HybridKex::<MlKem1024, NistP384, Sha384>::default().into()
+    }
+}
+
+/// The classical half of a hybrid key exchange.
+pub(crate) trait Ecdh {
+    type Secret;
+
+    /// A new ephemeral secret, and its encoded public key.
+    fn generate() -> (Self::Secret, Vec<u8>);
+
+    /// The length of an encoded public key.
+    fn public_key_len() -> usize;
+
+    /// The shared secret with the encoded public key `remote`.
+    fn shared_secret(secret: Self::Secret, remote: &[u8]) -> Result<CryptoVec, Error>;
+}
+
+pub(crate) struct X25519;
+
+impl Ecdh for X25519 {
+    type Secret = Scalar;
+
+    fn generate() -> (Scalar, Vec<u8>) {
+        let secret = Scalar::from_bytes_mod_order(rand::random::<[u8; 32]>());
+        let public = (ED25519_BASEPOINT_TABLE * &secret).to_montgomery();
+        (secret, public.0.to_vec())
+    }
+
+    fn public_key_len() -> usize {
+        32
+    }
+
+    fn shared_secret(secret: Scalar, remote: &[u8]) -> Result<CryptoVec, Error> {
+        let remote = MontgomeryPoint(remote.try_into().map_err(|_| Error::Kex)?);
+        let shared = secret * remote;
+        // Reject the low order points, as OpenSSH does.
+        if bool::from(shared.0.ct_eq(&[0; 32])) {
+            return Err(Error::Kex);
+        }
+        Ok(CryptoVec::from_slice(&shared.0))
+    }
+}
+
+impl Ecdh for NistP384 {
+    type Secret = EphemeralSecret<NistP384>;
+
+    fn generate() -> (Self::Secret, Vec<u8>) {
+        let secret = EphemeralSecret::<NistP384>::random(&mut rand_core::OsRng);
+        let public = secret.public_key().to_encoded_point(false);
+        (secret, public.as_bytes().to_vec())
+    }
+
+    fn public_key_len() -> usize {
+        // An uncompressed point.
+        97
+    }
+
+    fn shared_secret(secret: Self::Secret, remote: &[u8]) -> Result<CryptoVec, Error> {
+        let remote = elliptic_curve::PublicKey::<NistP384>::from_sec1_bytes(remote)
+            .map_err(|_| Error::Kex)?;
+        let shared = secret.diffie_hellman(&remote);
+        Ok(CryptoVec::from_slice(shared.raw_secret_bytes()))
+    }
+}
+
+#[doc(hidden)]
+pub struct HybridKex<K: KemCore, C: Ecdh, D: Digest> {
+    decapsulation_key: Option<Box<K::DecapsulationKey>>,
+    local_secret: Option<C::Secret>,
+    /// The hash of the ML-KEM and ECDH shared secrets.
+    shared_secret: Option<CryptoVec>,
+    _digest: PhantomData<D>,
+}
+
+impl<K: KemCore, C: Ecdh, D: Digest> Default for HybridKex<K, C, D> {
+    fn default() -> Self {
+        HybridKex {
+            decapsulation_key: None,
+            local_secret: None,
+            shared_secret: None,
+            _digest: PhantomData,
+        }
+    }
+}
+
+impl<K: KemCore, C: Ecdh, D: Digest> std::fmt::Debug for HybridKex<K, C, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Algorithm {{ decapsulation_key: [hidden], local_secret: [hidden], shared_secret: [hidden] }}",
+        )
+    }
+}
+
+fn encoded_len<T: EncodedSizeUser>() -> usize {
+    Encoded::<T>::default().len()
+}
+
+impl<K: KemCore, C: Ecdh, D: Digest> HybridKex<K, C, D> {
+    fn combine(&mut self, kem_shared: &[u8], ecdh_shared: &[u8]) {
+        let mut hasher = D::new();
+        hasher.update(kem_shared);
+        hasher.update(ecdh_shared);
+        self.shared_secret = Some(CryptoVec::from_slice(&hasher.finalize()));
+    }
+}
+
+impl<K: KemCore, C: Ecdh, D: Digest> KexAlgorithmImplementor for HybridKex<K, C, D> {
+    fn skip_exchange(&self) -> bool {
+        false
+    }
+
+    #[doc(hidden)]
+    fn server_dh(&mut self, exchange: &mut Exchange, payload: &[u8]) -> Result<(), Error> {
+        debug!("server_dh");
+
+        if payload.first() != Some(&msg::KEX_ECDH_INIT) {
+            return Err(Error::Inconsistent);
+        }
+        let client_init = payload
+            .get(5..)
+            .filter(|_| payload.len() >= 5)
+            .ok_or(Error::Inconsistent)?;
+        #[allow(clippy::indexing_slicing)] // length checked
+        let init_len = BigEndian::read_u32(&payload[1..5]) as usize;
+        let client_init = client_init.get(..init_len).ok_or(Error::Inconsistent)?;
+
+        let ek_len = encoded_len::<K::EncapsulationKey>();
+        if client_init.len() != ek_len + C::public_key_len() {
+            return Err(Error::Kex);
+        }
+        let (ek, client_pubkey) = client_init.split_at(ek_len);
+        let ek = Encoded::<K::EncapsulationKey>::try_from(ek).map_err(|_| Error::Kex)?;
+        let ek = K::EncapsulationKey::from_bytes(&ek);
+        let (ciphertext, kem_shared) = ek
+            .encapsulate(&mut rand_core::OsRng)
+            .map_err(|_| Error::Kex)?;
+
+        let (server_secret, server_pubkey) = C::generate();
+        let ecdh_shared = C::shared_secret(server_secret, client_pubkey)?;
+
+        // fill exchange.
+        exchange.server_ephemeral.clear();
+        exchange.server_ephemeral.extend(&ciphertext);
+        exchange.server_ephemeral.extend(&server_pubkey);
+        self.combine(&kem_shared, &ecdh_shared);
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    fn client_dh(
+        &mut self,
+        client_ephemeral: &mut CryptoVec,
+        writer: &mut impl Writer,
+    ) -> Result<(), Error> {
+        let (dk, ek) = K::generate(&mut rand_core::OsRng);
+        let (client_secret, client_pubkey) = C::generate();
+
+        // fill exchange.
+        client_ephemeral.clear();
+        client_ephemeral.extend(&ek.as_bytes());
+        client_ephemeral.extend(&client_pubkey);
+
+        msg::KEX_ECDH_INIT.encode(writer)?;
+        client_ephemeral.encode(writer)?;
+
+        self.decapsulation_key = Some(Box::new(dk));
+        self.local_secret = Some(client_secret);
+        Ok(())
+    }
+
+    fn compute_shared_secret(&mut self, remote_pubkey_: &[u8]) -> Result<(), Error> {
+        let dk = self.decapsulation_key.take().ok_or(Error::KexInit)?;
+        let local_secret = self.local_secret.take().ok_or(Error::KexInit)?;
+
+        let ct_len = encoded_len_of_ciphertext::<K>();
+        if remote_pubkey_.len() != ct_len + C::public_key_len() {
+            return Err(Error::Kex);
+        }
+        let (ciphertext, server_pubkey) = remote_pubkey_.split_at(ct_len);
+        let ciphertext = Ciphertext::<K>::try_from(ciphertext).map_err(|_| Error::Kex)?;
+        let kem_shared = dk.decapsulate(&ciphertext).map_err(|_| Error::Kex)?;
+        let ecdh_shared = C::shared_secret(local_secret, server_pubkey)?;
+        self.combine(&kem_shared, &ecdh_shared);
+        Ok(())
+    }
+
+    fn compute_exchange_hash(
+        &self,
+        key: &CryptoVec,
+        exchange: &Exchange,
+        buffer: &mut CryptoVec,
+    ) -> Result<CryptoVec, Error> {
+        buffer.clear();
+        exchange.client_id.encode(buffer)?;
+        exchange.server_id.encode(buffer)?;
+        exchange.client_kex_init.encode(buffer)?;
+        exchange.server_kex_init.encode(buffer)?;
+
+        buffer.extend(key);
+        exchange.client_ephemeral.encode(buffer)?;
+        exchange.server_ephemeral.encode(buffer)?;
+
+        if let Some(ref shared) = self.shared_secret {
+            shared.encode(buffer)?;
+        }
+
+        let mut hasher = D::new();
+        hasher.update(&buffer);
+
+        let mut res = CryptoVec::new();
+        res.extend(&hasher.finalize());
+        Ok(res)
+    }
+
+    fn compute_keys(
+        &self,
+        session_id: &CryptoVec,
+        exchange_hash: &CryptoVec,
+        cipher: cipher::Name,
+        remote_to_local_mac: mac::Name,
+        local_to_remote_mac: mac::Name,
+        is_server: bool,
+    ) -> Result<super::cipher::CipherPair, Error> {
+        let shared_secret = self
+            .shared_secret
+            .as_ref()
+            .map(|shared| {
+                let mut encoded = CryptoVec::new();
+                shared.encode(&mut encoded)?;
+                Ok::<_, Error>(encoded)
+            })
+            .transpose()?;
+        compute_keys_with_encoded_secret::<D>(
+            shared_secret.as_deref(),
+            session_id,
+            exchange_hash,
+            cipher,
+            remote_to_local_mac,
+            local_to_remote_mac,
+            is_server,
+        )
+    }
+}
+
+fn encoded_len_of_ciphertext<K: KemCore>() -> usize {
+    Ciphertext::<K>::default().len()
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    fn exchange<K: KemCore, C: Ecdh, D: Digest>(ek_len: usize, ct_len: usize) {
+        let mut client = HybridKex::<K, C, D>::default();
+        let mut server = HybridKex::<K, C, D>::default();
+
+        let mut exchange = Exchange::default();
+        let mut init = CryptoVec::new();
+        client
+            .client_dh(&mut exchange.client_ephemeral, &mut init)
+            .unwrap();
+        assert_eq!(exchange.client_ephemeral.len(), ek_len);
+
+        server.server_dh(&mut exchange, &init).unwrap();
+        assert_eq!(exchange.server_ephemeral.len(), ct_len);
+        client
+            .compute_shared_secret(&exchange.server_ephemeral)
+            .unwrap();
+        assert!(client.shared_secret.is_some());
+        assert_eq!(
+            client.shared_secret.as_deref(),
+            server.shared_secret.as_deref()
+        );
+
+        let mut init = init.to_vec();
+        init.truncate(init.len() - 1);
+        assert!(HybridKex::<K, C, D>::default()
+            .server_dh(&mut exchange, &init)
+            .is_err());
+    }
+
+    #[test]
+    fn mlkem768x25519() {
+        exchange::<MlKem768, X25519, Sha256>(1184 + 32, 1088 + 32);
+    }
+
+    #[test]
+    fn mlkem1024nistp384() {
+        exchange::<MlKem1024, NistP384, Sha384>(1568 + 97, 1568 + 97);
+    }
+}
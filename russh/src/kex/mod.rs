@@ -16,16 +16,18 @@
 //!
 //! This module exports kex algorithm names for use with [Preferred].
 mod curve25519;
+mod curve448;
 pub mod dh;
 mod ecdh_nistp;
+mod hybrid;
 mod none;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt::Debug;
-use std::ops::DerefMut;
 
 use curve25519::Curve25519KexType;
+use curve448::Curve448KexType;
 use delegate::delegate;
 use dh::groups::DhGroup;
 use dh::{
@@ -36,6 +38,8 @@ use dh::{
 use digest::Digest;
 use ecdh_nistp::{EcdhNistP256KexType, EcdhNistP384KexType, EcdhNistP521KexType};
 use enum_dispatch::enum_dispatch;
+use hybrid::{MlKem1024NistP384KexType, MlKem768X25519KexType, X25519};
+use ml_kem::{MlKem1024, MlKem768};
 use once_cell::sync::Lazy;
 use p256::NistP256;
 use p384::NistP384;
@@ -93,8 +97,13 @@ pub(crate) enum KexCause {
 }
 
 impl KexCause {
+    /// Whether strict kex is in effect. Only the initial key exchange
+    /// negotiates it: the pseudo-algorithms are ignored in later ones.
     pub fn is_strict_kex(&self, names: &Names) -> bool {
-        names.strict_kex || matches!(self, Self::Rekey { strict: true, .. })
+        match self {
+            Self::Initial => names.strict_kex,
+            Self::Rekey { strict, .. } => *strict,
+        }
     }
 
     pub fn is_rekey(&self) -> bool {
@@ -121,6 +130,8 @@ pub(crate) enum KexProgress<T> {
     },
     Done {
         server_host_key: Option<PublicKey>,
+        /// The signature of the exchange hash by `server_host_key`.
+        host_key_signature: Option<ssh_key::Signature>,
         newkeys: NewKeys,
     },
 }
@@ -131,9 +142,12 @@ pub(crate) enum KexAlgorithm {
     DhGroupKexSha256(dh::DhGroupKex<Sha256>),
     DhGroupKexSha512(dh::DhGroupKex<Sha512>),
     Curve25519Kex(curve25519::Curve25519Kex),
+    Curve448Kex(curve448::Curve448Kex),
     EcdhNistP256Kex(ecdh_nistp::EcdhNistPKex<NistP256, Sha256>),
     EcdhNistP384Kex(ecdh_nistp::EcdhNistPKex<NistP384, Sha384>),
     EcdhNistP521Kex(ecdh_nistp::EcdhNistPKex<NistP521, Sha512>),
+    MlKem768X25519Kex(hybrid::HybridKex<MlKem768, X25519, Sha256>),
+    MlKem1024NistP384Kex(hybrid::HybridKex<MlKem1024, NistP384, Sha384>),
     None(none::NoneKexAlgorithm),
 }
 
@@ -223,6 +237,8 @@ impl TryFrom<&str> for Name {
 pub const CURVE25519: Name = Name("curve25519-sha256");
 /// `curve25519-sha256@libssh.org`
 pub const CURVE25519_PRE_RFC_8731: Name = Name("curve25519-sha256@libssh.org");
+/// `curve448-sha512`
+pub const CURVE448: Name = Name("curve448-sha512");
 /// `diffie-hellman-group-exchange-sha1`.
 pub const DH_GEX_SHA1: Name = Name("diffie-hellman-group-exchange-sha1");
 /// `diffie-hellman-group-exchange-sha256`.
@@ -247,6 +263,10 @@ pub const ECDH_SHA2_NISTP256: Name = Name("ecdh-sha2-nistp256");
 pub const ECDH_SHA2_NISTP384: Name = Name("ecdh-sha2-nistp384");
 /// `ecdh-sha2-nistp521`
 pub const ECDH_SHA2_NISTP521: Name = Name("ecdh-sha2-nistp521");
+/// `mlkem768x25519-sha256`
+pub const MLKEM768X25519_SHA256: Name = Name("mlkem768x25519-sha256");
+/// `mlkem1024nistp384-sha384`
+pub const MLKEM1024NISTP384_SHA384: Name = Name("mlkem1024nistp384-sha384");
 /// `none`
 pub const NONE: Name = Name("none");
 /// `ext-info-c`
@@ -259,6 +279,7 @@ pub const EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT: Name = Name("kex-strict-c-v00@
 pub const EXTENSION_OPENSSH_STRICT_KEX_AS_SERVER: Name = Name("kex-strict-s-v00@openssh.com");
 
 const _CURVE25519: Curve25519KexType = Curve25519KexType {};
+const _CURVE448: Curve448KexType = Curve448KexType {};
 const _DH_GEX_SHA1: DhGexSha1KexType = DhGexSha1KexType {};
 const _DH_GEX_SHA256: DhGexSha256KexType = DhGexSha256KexType {};
 const _DH_G1_SHA1: DhGroup1Sha1KexType = DhGroup1Sha1KexType {};
@@ -271,11 +292,14 @@ const _DH_G18_SHA512: DhGroup18Sha512KexType = DhGroup18Sha512KexType {};
 const _ECDH_SHA2_NISTP256: EcdhNistP256KexType = EcdhNistP256KexType {};
 const _ECDH_SHA2_NISTP384: EcdhNistP384KexType = EcdhNistP384KexType {};
 const _ECDH_SHA2_NISTP521: EcdhNistP521KexType = EcdhNistP521KexType {};
+const _MLKEM768X25519_SHA256: MlKem768X25519KexType = MlKem768X25519KexType {};
+const _MLKEM1024NISTP384_SHA384: MlKem1024NistP384KexType = MlKem1024NistP384KexType {};
 const _NONE: none::NoneKexType = none::NoneKexType {};
 
 pub const ALL_KEX_ALGORITHMS: &[&Name] = &[
     &CURVE25519,
     &CURVE25519_PRE_RFC_8731,
+    &CURVE448,
     &DH_GEX_SHA1,
     &DH_GEX_SHA256,
     &DH_G1_SHA1,
@@ -288,6 +312,8 @@ pub const ALL_KEX_ALGORITHMS: &[&Name] = &[
     &ECDH_SHA2_NISTP256,
     &ECDH_SHA2_NISTP384,
     &ECDH_SHA2_NISTP521,
+    &MLKEM768X25519_SHA256,
+    &MLKEM1024NISTP384_SHA384,
     &NONE,
 ];
 
@@ -296,6 +322,7 @@ pub(crate) static KEXES: Lazy<HashMap<&'static Name, &(dyn KexType + Send + Sync
         let mut h: HashMap<&'static Name, &(dyn KexType + Send + Sync)> = HashMap::new();
         h.insert(&CURVE25519, &_CURVE25519);
         h.insert(&CURVE25519_PRE_RFC_8731, &_CURVE25519);
+        h.insert(&CURVE448, &_CURVE448);
         h.insert(&DH_GEX_SHA1, &_DH_GEX_SHA1);
         h.insert(&DH_GEX_SHA256, &_DH_GEX_SHA256);
         h.insert(&DH_G18_SHA512, &_DH_G18_SHA512);
@@ -308,6 +335,8 @@ pub(crate) static KEXES: Lazy<HashMap<&'static Name, &(dyn KexType + Send + Sync
         h.insert(&ECDH_SHA2_NISTP256, &_ECDH_SHA2_NISTP256);
         h.insert(&ECDH_SHA2_NISTP384, &_ECDH_SHA2_NISTP384);
         h.insert(&ECDH_SHA2_NISTP521, &_ECDH_SHA2_NISTP521);
+        h.insert(&MLKEM768X25519_SHA256, &_MLKEM768X25519_SHA256);
+        h.insert(&MLKEM1024NISTP384_SHA384, &_MLKEM1024NISTP384_SHA384);
         h.insert(&NONE, &_NONE);
         assert_eq!(ALL_KEX_ALGORITHMS.len(), h.len());
         h
@@ -328,6 +357,35 @@ pub(crate) fn compute_keys<D: Digest>(
     remote_to_local_mac: mac::Name,
     local_to_remote_mac: mac::Name,
     is_server: bool,
+) -> Result<super::cipher::CipherPair, Error> {
+    let shared_secret = shared_secret
+        .map(|shared| {
+            let mut encoded = CryptoVec::new();
+            encode_mpint(shared, &mut encoded)?;
+            Ok::<_, Error>(encoded)
+        })
+        .transpose()?;
+    compute_keys_with_encoded_secret::<D>(
+        shared_secret.as_deref(),
+        session_id,
+        exchange_hash,
+        cipher,
+        remote_to_local_mac,
+        local_to_remote_mac,
+        is_server,
+    )
+}
+
+/// Derive the keys from the shared secret already encoded, as an
+/// `mpint` by most algorithms, or as a `string` by the hybrid ones.
+pub(crate) fn compute_keys_with_encoded_secret<D: Digest>(
+    shared_secret: Option<&[u8]>,
+    session_id: &CryptoVec,
+    exchange_hash: &CryptoVec,
+    cipher: cipher::Name,
+    remote_to_local_mac: mac::Name,
+    local_to_remote_mac: mac::Name,
+    is_server: bool,
 ) -> Result<super::cipher::CipherPair, Error> {
     let cipher = CIPHERS.get(&cipher).ok_or(Error::UnknownAlgo)?;
     let remote_to_local_mac = MACS.get(&remote_to_local_mac).ok_or(Error::UnknownAlgo)?;
@@ -344,7 +402,7 @@ pub(crate) fn compute_keys<D: Digest>(
                         key.clear();
 
                         if let Some(shared) = shared_secret {
-                            encode_mpint(shared, buffer.deref_mut())?;
+                            buffer.extend(shared);
                         }
 
                         buffer.extend(exchange_hash.as_ref());
@@ -361,7 +419,7 @@ pub(crate) fn compute_keys<D: Digest>(
                             // extend.
                             buffer.clear();
                             if let Some(shared) = shared_secret {
-                                encode_mpint(shared, buffer.deref_mut())?;
+                                buffer.extend(shared);
                             }
                             buffer.extend(exchange_hash.as_ref());
                             buffer.extend(key);
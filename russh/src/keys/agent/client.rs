@@ -4,11 +4,11 @@ use byteorder::{BigEndian, ByteOrder};
 use bytes::Bytes;
 use log::{debug, error};
 use ssh_encoding::{Decode, Encode, Reader};
-use ssh_key::{Algorithm, HashAlg, PrivateKey, PublicKey, Signature};
+use ssh_key::{Algorithm, Certificate, HashAlg, PrivateKey, PublicKey, Signature};
 use tokio;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
-use super::{msg, Constraint};
+use super::{msg, AgentIdentity, Constraint, SessionBind};
 use crate::helpers::EncodedExt;
 use crate::keys::{key, Error};
 use crate::CryptoVec;
@@ -41,7 +41,8 @@ impl<S: AgentStream + Send + Unpin + 'static> AgentClient<S> {
 // https://tools.ietf.org/html/draft-miller-ssh-agent-00#section-4.1
 impl<S: AgentStream + Unpin> AgentClient<S> {
     /// Build a future that connects to an SSH agent via the provided
-    /// stream (on Unix, usually a Unix-domain socket).
+    /// stream (on Unix, usually a Unix-domain socket). Any stream works,
+    /// such as a TCP connection or a WebSocket to a proxied agent.
     pub fn connect(stream: S) -> Self {
         AgentClient {
             stream,
@@ -50,6 +51,18 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl AgentClient<tokio::net::TcpStream> {
+    /// Connect to an SSH agent over TCP, for instance to an agent socket
+    /// proxied out of a container. Any other stream can be used with
+    /// [`AgentClient::connect`].
+    pub async fn connect_tcp<A: tokio::net::ToSocketAddrs>(addr: A) -> Result<Self, Error> {
+        let stream = tokio::net::TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(AgentClient::connect(stream))
+    }
+}
+
 #[cfg(unix)]
 impl AgentClient<tokio::net::UnixStream> {
     /// Connect to an SSH agent via the provided
@@ -82,6 +95,39 @@ impl AgentClient<tokio::net::UnixStream> {
 #[cfg(windows)]
 const ERROR_PIPE_BUSY: u32 = 231u32;
 
+/// The named pipe of the OpenSSH agent shipped with Windows.
+#[cfg(windows)]
+pub const OPENSSH_AGENT_PIPE: &str = r"\\.\pipe\openssh-ssh-agent";
+
+#[cfg(windows)]
+impl AgentClient<Box<dyn AgentStream + Send + Unpin + 'static>> {
+    /// Connect to the SSH agent of the user: the named pipe in the
+    /// SSH_AUTH_SOCK environment variable if it is set, or else a
+    /// running Pageant instance, or else the OpenSSH agent at
+    /// [`OPENSSH_AGENT_PIPE`].
+    pub async fn connect_env() -> Result<Self, Error> {
+        if let Ok(var) = std::env::var("SSH_AUTH_SOCK") {
+            return match AgentClient::connect_named_pipe(var).await {
+                Ok(client) => Ok(client.dynamic()),
+                Err(Error::IO(io_err)) if io_err.kind() == std::io::ErrorKind::NotFound => {
+                    Err(Error::BadAuthSock)
+                }
+                Err(e) => Err(e),
+            };
+        }
+        if pageant::is_pageant_running() {
+            return Ok(AgentClient::connect_pageant().await.dynamic());
+        }
+        match AgentClient::connect_openssh_agent().await {
+            Ok(client) => Ok(client.dynamic()),
+            Err(Error::IO(io_err)) if io_err.kind() == std::io::ErrorKind::NotFound => {
+                Err(Error::EnvVar("SSH_AUTH_SOCK"))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
 #[cfg(windows)]
 impl AgentClient<pageant::PageantStream> {
     /// Connect to a running Pageant instance
@@ -109,6 +155,12 @@ impl AgentClient<tokio::net::windows::named_pipe::NamedPipeClient> {
             buf: CryptoVec::new(),
         })
     }
+
+    /// Connect to the OpenSSH agent service of Windows, at
+    /// [`OPENSSH_AGENT_PIPE`].
+    pub async fn connect_openssh_agent() -> Result<Self, Error> {
+        Self::connect_named_pipe(OPENSSH_AGENT_PIPE).await
+    }
 }
 
 impl<S: AgentStream + Unpin> AgentClient<S> {
@@ -158,7 +210,7 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
         }
 
         key.key_data().encode(&mut self.buf)?;
-        "".encode(&mut self.buf)?; // comment field
+        key.comment().encode(&mut self.buf)?;
 
         if !constraints.is_empty() {
             for cons in constraints {
@@ -204,7 +256,6 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
         id.encode(&mut self.buf)?;
         pin.encode(&mut self.buf)?;
         if !constraints.is_empty() {
-            (constraints.len() as u32).encode(&mut self.buf)?;
             for cons in constraints {
                 match *cons {
                     Constraint::KeyLifetime { seconds } => {
@@ -237,8 +288,7 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
         passphrase.encode(&mut self.buf)?;
         let len = self.buf.len() - 4;
         BigEndian::write_u32(&mut self.buf[..], len as u32);
-        self.read_response().await?;
-        Ok(())
+        self.read_success().await
     }
 
     /// Unlock the agent, allowing it to sign again.
@@ -250,8 +300,7 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
         let len = self.buf.len() - 4;
         #[allow(clippy::indexing_slicing)] // static length
         BigEndian::write_u32(&mut self.buf[..], len as u32);
-        self.read_response().await?;
-        Ok(())
+        self.read_success().await
     }
 
     /// Ask the agent for a list of the currently registered secret
@@ -282,6 +331,43 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
         Ok(keys)
     }
 
+    /// Ask the agent for a list of the currently registered keys, like
+    /// [`AgentClient::request_identities`], including the certificates
+    /// it holds.
+    pub async fn request_identities_and_certificates(
+        &mut self,
+    ) -> Result<Vec<AgentIdentity>, Error> {
+        self.buf.clear();
+        self.buf.resize(4);
+        msg::REQUEST_IDENTITIES.encode(&mut self.buf)?;
+        let len = self.buf.len() - 4;
+        BigEndian::write_u32(&mut self.buf[..], len as u32);
+
+        self.read_response().await?;
+        let mut identities = Vec::new();
+
+        if let Some((&msg::IDENTITIES_ANSWER, mut r)) = self.buf.split_first() {
+            let n = u32::decode(&mut r)?;
+            for _ in 0..n {
+                let key_blob = Bytes::decode(&mut r)?;
+                let comment = String::decode(&mut r)?;
+                let algorithm = String::decode(&mut key_blob.as_ref())?;
+                identities.push(if algorithm.ends_with("-cert-v01@openssh.com") {
+                    AgentIdentity::Certificate {
+                        certificate: Certificate::from_bytes(&key_blob)?,
+                        comment,
+                    }
+                } else {
+                    let mut key = key::parse_public_key(&key_blob)?;
+                    key.set_comment(comment.clone());
+                    AgentIdentity::PublicKey { key, comment }
+                });
+            }
+        }
+
+        Ok(identities)
+    }
+
     /// Ask the agent to sign the supplied piece of data.
     pub async fn sign_request(
         &mut self,
@@ -341,13 +427,13 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
         hash: u32,
         data: &mut CryptoVec,
     ) -> Result<(), Error> {
-        let mut resp = &Bytes::decode(r)?[..];
+        let blob = Bytes::decode(r)?;
+        let mut resp = &blob[..];
         let t = String::decode(&mut resp)?;
         if (hash == 2 && t == "rsa-sha2-256") || (hash == 4 && t == "rsa-sha2-512") || hash == 0 {
-            let sig = Bytes::decode(&mut resp)?;
-            (t.len() + sig.len() + 8).encode(data)?;
-            t.encode(data)?;
-            sig.encode(data)?;
+            // The blob is forwarded whole: security key signatures have
+            // flags and a counter after the signature itself.
+            blob.encode(data)?;
             Ok(())
         } else {
             error!("unexpected agent signature type: {:?}", t);
@@ -436,11 +522,36 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
         self.buf.clear();
         self.buf.resize(4);
         msg::REMOVE_ALL_IDENTITIES.encode(&mut self.buf)?;
-        1u32.encode(&mut self.buf)?;
+        let len = self.buf.len() - 4;
+        BigEndian::write_u32(&mut self.buf[..], len as u32);
         self.read_success().await?;
         Ok(())
     }
 
+    /// Bind this connection to the agent to an SSH connection, with the
+    /// `session-bind@openssh.com` extension. `is_forwarding` tells the
+    /// agent whether it is being forwarded over that connection, rather
+    /// than used to authenticate it. This fails with
+    /// [`Error::AgentFailure`] if the agent does not support the
+    /// extension.
+    pub async fn bind_session(
+        &mut self,
+        bind: &SessionBind,
+        is_forwarding: bool,
+    ) -> Result<(), Error> {
+        self.buf.clear();
+        self.buf.resize(4);
+        msg::EXTENSION.encode(&mut self.buf)?;
+        "session-bind@openssh.com".encode(&mut self.buf)?;
+        bind.host_key.key_data().encoded()?.encode(&mut self.buf)?;
+        bind.session_id.encode(&mut self.buf)?;
+        bind.signature.encoded()?.encode(&mut self.buf)?;
+        (is_forwarding as u8).encode(&mut self.buf)?;
+        let len = self.buf.len() - 4;
+        BigEndian::write_u32(&mut self.buf[..], len as u32);
+        self.read_success().await
+    }
+
     /// Send a custom message to the agent.
     pub async fn extension(&mut self, typ: &[u8], ext: &[u8]) -> Result<(), Error> {
         self.buf.clear();
@@ -449,7 +560,7 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
         typ.encode(&mut self.buf)?;
         ext.encode(&mut self.buf)?;
         let len = self.buf.len() - 4;
-        (len as u32).encode(&mut self.buf)?;
+        BigEndian::write_u32(&mut self.buf[..], len as u32);
         self.read_response().await?;
         Ok(())
     }
@@ -461,7 +572,7 @@ impl<S: AgentStream + Unpin> AgentClient<S> {
         msg::EXTENSION.encode(&mut self.buf)?;
         typ.encode(&mut self.buf)?;
         let len = self.buf.len() - 4;
-        (len as u32).encode(&mut self.buf)?;
+        BigEndian::write_u32(&mut self.buf[..], len as u32);
         self.read_response().await?;
 
         match self.buf.split_first() {
@@ -4,6 +4,56 @@ mod msg;
 /// Write servers for SSH agents.
 pub mod server;
 
+use ssh_encoding::Encode;
+use ssh_key::public::KeyData;
+use ssh_key::{Certificate, PublicKey, Signature};
+
+use crate::helpers::EncodedExt;
+
+/// A key held by an agent, as listed by
+/// [`AgentClient::request_identities_and_certificates`][client::AgentClient::request_identities_and_certificates].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentIdentity {
+    PublicKey {
+        key: PublicKey,
+        comment: String,
+    },
+    Certificate {
+        certificate: Certificate,
+        comment: String,
+    },
+}
+
+impl AgentIdentity {
+    /// The public key, which is the key of the certificate for
+    /// certificates.
+    pub fn key_data(&self) -> &KeyData {
+        match self {
+            AgentIdentity::PublicKey { key, .. } => key.key_data(),
+            AgentIdentity::Certificate { certificate, .. } => certificate.public_key(),
+        }
+    }
+
+    pub fn comment(&self) -> &str {
+        match self {
+            AgentIdentity::PublicKey { comment, .. }
+            | AgentIdentity::Certificate { comment, .. } => comment,
+        }
+    }
+}
+
+/// What identifies an SSH connection to an agent, for the
+/// `session-bind@openssh.com` extension: the host key of the server,
+/// the session identifier, and the signature of the session identifier
+/// by the host key, which the agent checks. Agents use it to enforce
+/// the destination constraints of their keys.
+#[derive(Debug, Clone)]
+pub struct SessionBind {
+    pub host_key: PublicKey,
+    pub session_id: Vec<u8>,
+    pub signature: Signature,
+}
+
 /// Constraints on how keys can be used
 #[derive(Debug, PartialEq, Eq)]
 pub enum Constraint {
@@ -14,3 +64,62 @@ pub enum Constraint {
     /// Custom constraints
     Extensions { name: Vec<u8>, details: Vec<u8> },
 }
+
+impl Constraint {
+    /// Only let the key be used for the `destinations`, with the
+    /// `restrict-destination-v00@openssh.com` extension of OpenSSH's
+    /// agent. The agent enforces them on the connections it is bound to
+    /// with [`client::AgentClient::bind_session`].
+    pub fn restrict_destination(
+        destinations: &[DestinationConstraint],
+    ) -> Result<Self, crate::keys::Error> {
+        let mut details = Vec::new();
+        for destination in destinations {
+            let mut constraint = Vec::new();
+            destination.from.encode(&mut constraint)?;
+            destination.to.encode(&mut constraint)?;
+            "".encode(&mut constraint)?; // reserved
+            constraint.encode(&mut details)?;
+        }
+        Ok(Constraint::Extensions {
+            name: b"restrict-destination-v00@openssh.com".to_vec(),
+            details,
+        })
+    }
+}
+
+/// A hop that a key may be used for: from the `from` host, to
+/// authenticate to the `to` host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DestinationConstraint {
+    /// The host the connection comes from. Its hostname is empty for
+    /// connections from the host of the agent.
+    pub from: DestinationHop,
+    pub to: DestinationHop,
+}
+
+/// A host in a [`DestinationConstraint`], identified by its host keys.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DestinationHop {
+    /// The user to authenticate as, or the empty string for any user.
+    /// This must be empty for the `from` hop.
+    pub user: String,
+    pub hostname: String,
+    /// The host keys of the host, and whether each is a certificate
+    /// authority rather than a key of the host itself.
+    pub host_keys: Vec<(PublicKey, bool)>,
+}
+
+impl DestinationHop {
+    fn encode(&self, w: &mut Vec<u8>) -> Result<(), crate::keys::Error> {
+        let mut hop = Vec::new();
+        self.user.encode(&mut hop)?;
+        self.hostname.encode(&mut hop)?;
+        "".encode(&mut hop)?; // reserved
+        for (key, is_ca) in &self.host_keys {
+            key.key_data().encoded()?.encode(&mut hop)?;
+            (*is_ca as u8).encode(&mut hop)?;
+        }
+        Ok(hop.encode(w)?)
+    }
+}
@@ -1,3 +1,4 @@
+use std;
 use std::collections::HashMap;
 use std::marker::Sync;
 use std::sync::{Arc, RwLock};
@@ -9,9 +10,10 @@ use futures::future::Future;
 use futures::stream::{Stream, StreamExt};
 use ssh_encoding::{Decode, Encode, Reader};
 use ssh_key::PrivateKey;
+use subtle::ConstantTimeEq;
+use tokio;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::time::sleep;
-use {std, tokio};
 
 use super::{msg, Constraint};
 use crate::helpers::{sign_with_hash_alg, EncodedExt};
@@ -23,8 +25,9 @@ use crate::CryptoVec;
 #[allow(clippy::type_complexity)]
 struct KeyStore(Arc<RwLock<HashMap<Vec<u8>, (Arc<PrivateKey>, SystemTime, Vec<Constraint>)>>>);
 
+/// The passphrase of the agent while it is locked.
 #[derive(Clone)]
-struct Lock(Arc<RwLock<CryptoVec>>);
+struct Lock(Arc<RwLock<Option<CryptoVec>>>);
 
 #[allow(missing_docs)]
 #[derive(Debug)]
@@ -45,6 +48,9 @@ pub enum MessageType {
 
 #[cfg_attr(feature = "async-trait", async_trait::async_trait)]
 pub trait Agent: Clone + Send + 'static {
+    /// Called before signing with a key added with
+    /// [`Constraint::Confirm`], for instance to ask the user with a
+    /// dialog. The agent refuses to sign unless this returns `true`.
     fn confirm(
         self,
         _pk: Arc<PrivateKey>,
@@ -52,6 +58,8 @@ pub trait Agent: Clone + Send + 'static {
         Box::new(futures::future::ready((self, true)))
     }
 
+    /// Called for every request, before it is processed. The request
+    /// fails unless this returns `true`.
     fn confirm_request(&self, _msg: MessageType) -> impl Future<Output = bool> + Send {
         async { true }
     }
@@ -64,7 +72,7 @@ where
     A: Agent + Send + Sync + 'static,
 {
     let keys = KeyStore(Arc::new(RwLock::new(HashMap::new())));
-    let lock = Lock(Arc::new(RwLock::new(CryptoVec::new())));
+    let lock = Lock(Arc::new(RwLock::new(None)));
     while let Some(Ok(stream)) = listener.next().await {
         let mut buf = CryptoVec::new();
         buf.resize(4);
@@ -82,6 +90,16 @@ where
     Ok(())
 }
 
+/// Whether the lifetime of a key added at `added` is over.
+fn is_expired(added: SystemTime, constraints: &[Constraint]) -> bool {
+    constraints.iter().any(|c| match c {
+        Constraint::KeyLifetime { seconds } => added
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed >= Duration::from_secs(*seconds as u64)),
+        _ => false,
+    })
+}
+
 impl Agent for () {
     fn confirm(self, _: Arc<PrivateKey>) -> Box<dyn Future<Output = (Self, bool)> + Unpin + Send> {
         Box::new(futures::future::ready((self, true)))
@@ -122,7 +140,7 @@ impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static, A: Agent + Send + Sync
     async fn respond(&mut self, writebuf: &mut CryptoVec) -> Result<(), Error> {
         let is_locked = {
             if let Ok(password) = self.lock.0.read() {
-                !password.is_empty()
+                password.is_some()
             } else {
                 true
             }
@@ -135,17 +153,23 @@ impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static, A: Agent + Send + Sync
                 if !is_locked && agentref.confirm_request(MessageType::RequestKeys).await =>
             {
                 // request identities
-                if let Ok(keys) = self.keys.0.read() {
+                if let Ok(mut keys) = self.keys.0.write() {
+                    keys.retain(|_, (_, added, constraints)| !is_expired(*added, constraints));
                     msg::IDENTITIES_ANSWER.encode(writebuf)?;
                     (keys.len() as u32).encode(writebuf)?;
-                    for (k, _) in keys.iter() {
+                    for (k, (key, _, _)) in keys.iter() {
                         k.encode(writebuf)?;
-                        "".encode(writebuf)?;
+                        key.comment().encode(writebuf)?;
                     }
                 } else {
                     msg::FAILURE.encode(writebuf)?
                 }
             }
+            Some((&11, _)) if is_locked => {
+                // A locked agent has no identities, like OpenSSH's.
+                msg::IDENTITIES_ANSWER.encode(writebuf)?;
+                0u32.encode(writebuf)?;
+            }
             Some((&13, mut r))
                 if !is_locked && agentref.confirm_request(MessageType::Sign).await =>
             {
@@ -232,19 +256,22 @@ impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static, A: Agent + Send + Sync
     fn lock<R: Reader>(&self, r: &mut R) -> Result<(), Error> {
         let password = Bytes::decode(r)?;
         let mut lock = self.lock.0.write().or(Err(Error::AgentFailure))?;
-        lock.extend(&password);
+        let mut passphrase = CryptoVec::new();
+        passphrase.extend(&password);
+        *lock = Some(passphrase);
         Ok(())
     }
 
     fn unlock<R: Reader>(&self, r: &mut R) -> Result<bool, Error> {
         let password = Bytes::decode(r)?;
         let mut lock = self.lock.0.write().or(Err(Error::AgentFailure))?;
-        if lock[..] == password {
-            lock.clear();
-            Ok(true)
-        } else {
-            Ok(false)
+        let matches = lock
+            .as_ref()
+            .is_some_and(|passphrase| bool::from(passphrase[..].ct_eq(&password)));
+        if matches {
+            *lock = None;
         }
+        Ok(matches)
     }
 
     fn remove_identity<R: Reader>(&self, r: &mut R) -> Result<bool, Error> {
@@ -266,13 +293,12 @@ impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static, A: Agent + Send + Sync
         writebuf: &mut CryptoVec,
     ) -> Result<bool, Error> {
         let (blob, key_pair) = {
-            let private_key =
-                ssh_key::private::PrivateKey::new(ssh_key::private::KeypairData::decode(r)?, "")?;
-            let _comment = String::decode(r)?;
+            let key_data = ssh_key::private::KeypairData::decode(r)?;
+            let comment = String::decode(r)?;
+            let private_key = ssh_key::private::PrivateKey::new(key_data, comment)?;
 
             (private_key.public_key().key_data().encoded()?, private_key)
         };
-        writebuf.push(msg::SUCCESS);
         let mut w = self.keys.0.write().or(Err(Error::AgentFailure))?;
         let now = SystemTime::now();
         if constrained {
@@ -306,6 +332,7 @@ impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static, A: Agent + Send + Sync
         } else {
             w.insert(blob, (Arc::new(key_pair), now, Vec::new()));
         }
+        writebuf.push(msg::SUCCESS);
         Ok(true)
     }
 
@@ -317,23 +344,25 @@ impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static, A: Agent + Send + Sync
     ) -> Result<(A, bool), Error> {
         let mut needs_confirm = false;
         let key = {
-            let blob = Bytes::decode(r)?;
-            let k = self.keys.0.read().or(Err(Error::AgentFailure))?;
-            if let Some((key, _, constraints)) = k.get(&blob.to_vec()) {
-                if constraints.iter().any(|c| *c == Constraint::Confirm) {
-                    needs_confirm = true;
+            let blob = Bytes::decode(r)?.to_vec();
+            let mut k = self.keys.0.write().or(Err(Error::AgentFailure))?;
+            match k.get(&blob) {
+                Some((_, added, constraints)) if is_expired(*added, constraints) => {
+                    // The timer removing the key has not fired yet.
+                    k.remove(&blob);
+                    return Ok((agent, false));
                 }
-                key.clone()
-            } else {
-                return Ok((agent, false));
+                Some((key, _, constraints)) => {
+                    if constraints.iter().any(|c| *c == Constraint::Confirm) {
+                        needs_confirm = true;
+                    }
+                    key.clone()
+                }
+                None => return Ok((agent, false)),
             }
         };
         let agent = if needs_confirm {
-            let (agent, ok) = {
-                let _pk = key.clone();
-                Box::new(futures::future::ready((agent, true)))
-            }
-            .await;
+            let (agent, ok) = agent.confirm(key.clone()).await;
             if !ok {
                 return Ok((agent, false));
             }
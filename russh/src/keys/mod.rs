@@ -83,6 +83,8 @@ pub mod agent;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod known_hosts;
+pub mod pkcs11;
+pub mod sk;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub use known_hosts::{check_known_hosts, check_known_hosts_path};
@@ -116,6 +118,9 @@ pub enum Error {
     /// The server key has changed
     #[error("The server key changed at line {}", line)]
     KeyChanged { line: usize },
+    /// The server key is marked as revoked
+    #[error("The server key is revoked at line {}", line)]
+    KeyRevoked { line: usize },
     /// The key uses an unsupported algorithm
     #[error("Unknown key algorithm: {0}")]
     UnknownAlgorithm(::pkcs8::ObjectIdentifier),
@@ -890,6 +895,69 @@ Cog3JMeTrb3LiPHgN6gU2P30MRp6L1j1J/MtlOAr5rux
         test_client_agent(key).await.expect("ssh-agent test failed")
     }
 
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_client_agent_constraints() -> Result<(), Box<dyn std::error::Error>> {
+        env_logger::try_init().unwrap_or(());
+        use std::process::Stdio;
+
+        let dir = tempfile::tempdir()?;
+        let agent_path = dir.path().join("agent");
+        let mut agent = tokio::process::Command::new("ssh-agent")
+            .arg("-a")
+            .arg(&agent_path)
+            .arg("-D")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        // The socket may exist before ssh-agent listens on it.
+        let mut client = loop {
+            match agent::client::AgentClient::connect_uds(&agent_path).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        let mut key = decode_secret_key(ED25519_KEY, Some("blabla"))?;
+        key.set_comment("first");
+        let mut other = decode_secret_key(RSA_KEY, None)?;
+        other.set_comment("second");
+        let host_key = decode_secret_key(PKCS8_ENCRYPTED, Some("blabla"))?;
+        let destination = agent::DestinationConstraint {
+            from: agent::DestinationHop::default(),
+            to: agent::DestinationHop {
+                user: "user".into(),
+                hostname: "example.com".into(),
+                host_keys: vec![(host_key.public_key().clone(), false)],
+            },
+        };
+        client
+            .add_identity(
+                &key,
+                &[
+                    agent::Constraint::KeyLifetime { seconds: 60 },
+                    agent::Constraint::restrict_destination(&[destination])?,
+                ],
+            )
+            .await?;
+        client
+            .add_identity(&other, &[agent::Constraint::Confirm])
+            .await?;
+
+        let identities = client.request_identities().await?;
+        let comments: Vec<_> = identities.iter().map(|k| k.comment()).collect();
+        assert_eq!(comments, ["first", "second"]);
+
+        client.remove_identity(key.public_key()).await?;
+        assert_eq!(client.request_identities().await?.len(), 1);
+        client.add_identity(&key, &[]).await?;
+        client.remove_all_identities().await?;
+        assert!(client.request_identities().await?.is_empty());
+
+        agent.kill().await?;
+        agent.wait().await?;
+        Ok(())
+    }
+
     #[test]
     #[cfg(unix)]
     fn test_agent() {
@@ -945,6 +1013,150 @@ Cog3JMeTrb3LiPHgN6gU2P30MRp6L1j1J/MtlOAr5rux
         })
     }
 
+    #[tokio::test]
+    async fn test_agent_lock() -> Result<(), Box<dyn std::error::Error>> {
+        env_logger::try_init().unwrap_or(());
+        let (client_stream, agent_stream) = tokio::io::duplex(65536);
+        tokio::spawn(agent::server::serve(
+            futures::stream::iter([Ok::<_, std::io::Error>(agent_stream)]),
+            (),
+        ));
+        let mut client = agent::client::AgentClient::connect(client_stream);
+        let mut key = decode_secret_key(ED25519_KEY, Some("blabla"))?;
+        key.set_comment("key");
+        client.add_identity(&key, &[]).await?;
+        let identities = client.request_identities().await?;
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities.first().map(|k| k.comment()), Some("key"));
+
+        client.lock(b"passphrase").await?;
+        assert!(client.lock(b"passphrase").await.is_err());
+        assert!(client.request_identities().await?.is_empty());
+        let data = russh_cryptovec::CryptoVec::from_slice(b"data");
+        assert!(client
+            .sign_request(key.public_key(), None, data)
+            .await
+            .is_err());
+        assert!(client.unlock(b"wrong").await.is_err());
+        client.unlock(b"passphrase").await?;
+        assert!(client.unlock(b"passphrase").await.is_err());
+        assert_eq!(client.request_identities().await?.len(), 1);
+
+        // Unknown messages are answered with a failure.
+        assert!(
+            !client
+                .query_extension(b"query", russh_cryptovec::CryptoVec::new())
+                .await?
+        );
+
+        client.remove_identity(key.public_key()).await?;
+        assert!(client.request_identities().await?.is_empty());
+        let other = decode_secret_key(RSA_KEY, None)?;
+        client.add_identity(&key, &[]).await?;
+        client.add_identity(&other, &[]).await?;
+        assert_eq!(client.request_identities().await?.len(), 2);
+        client.remove_all_identities().await?;
+        assert!(client.request_identities().await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_client_agent_tcp() -> Result<(), Box<dyn std::error::Error>> {
+        env_logger::try_init().unwrap_or(());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(agent::server::serve(
+            tokio_stream::wrappers::TcpListenerStream::new(listener),
+            (),
+        ));
+        let mut client = agent::client::AgentClient::connect_tcp(addr).await?;
+        let key = decode_secret_key(ED25519_KEY, Some("blabla"))?;
+        client.add_identity(&key, &[]).await?;
+        let identities = client.request_identities().await?;
+        assert_eq!(identities.first(), Some(key.public_key()));
+        Ok(())
+    }
+
+    /// Confirms signatures if `accept`, counting the confirmations.
+    #[derive(Clone)]
+    struct Confirmer {
+        accept: bool,
+        asked: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl agent::server::Agent for Confirmer {
+        fn confirm(
+            self,
+            _: std::sync::Arc<PrivateKey>,
+        ) -> Box<dyn futures::Future<Output = (Self, bool)> + Send + Unpin> {
+            self.asked.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let accept = self.accept;
+            Box::new(futures::future::ready((self, accept)))
+        }
+    }
+
+    async fn agent_with_confirmer(
+        accept: bool,
+    ) -> (
+        agent::client::AgentClient<tokio::io::DuplexStream>,
+        std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    ) {
+        let (client_stream, agent_stream) = tokio::io::duplex(65536);
+        let asked = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        tokio::spawn(agent::server::serve(
+            futures::stream::iter([Ok::<_, std::io::Error>(agent_stream)]),
+            Confirmer {
+                accept,
+                asked: asked.clone(),
+            },
+        ));
+        (agent::client::AgentClient::connect(client_stream), asked)
+    }
+
+    #[tokio::test]
+    async fn test_agent_constraints() -> Result<(), Box<dyn std::error::Error>> {
+        env_logger::try_init().unwrap_or(());
+        let key = decode_secret_key(ED25519_KEY, Some("blabla"))?;
+        let data = russh_cryptovec::CryptoVec::from_slice(b"data");
+
+        let (mut client, asked) = agent_with_confirmer(false).await;
+        client.add_identity(&key, &[]).await?;
+        client
+            .sign_request(key.public_key(), None, data.clone())
+            .await?;
+        assert_eq!(asked.load(std::sync::atomic::Ordering::SeqCst), 0);
+        client
+            .add_identity(&key, &[agent::Constraint::Confirm])
+            .await?;
+        assert!(client
+            .sign_request(key.public_key(), None, data.clone())
+            .await
+            .is_err());
+        assert_eq!(asked.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let (mut client, asked) = agent_with_confirmer(true).await;
+        client
+            .add_identity(
+                &key,
+                &[
+                    agent::Constraint::Confirm,
+                    agent::Constraint::KeyLifetime { seconds: 1 },
+                ],
+            )
+            .await?;
+        client
+            .sign_request(key.public_key(), None, data.clone())
+            .await?;
+        assert_eq!(asked.load(std::sync::atomic::Ordering::SeqCst), 1);
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert!(client.request_identities().await?.is_empty());
+        assert!(client
+            .sign_request(key.public_key(), None, data)
+            .await
+            .is_err());
+        Ok(())
+    }
+
     #[cfg(unix)]
     struct Incoming<'a> {
         listener: &'a mut tokio::net::UnixListener,
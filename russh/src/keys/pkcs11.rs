@@ -0,0 +1,281 @@
+//! Authentication with keys stored on a PKCS#11 token (smartcards, HSMs,
+//! YubiKey PIV), equivalent to OpenSSH's `PKCS11Provider`.
+//!
+//! The private keys never leave the token: signatures are computed by the
+//! module with `C_Sign`. Russh does not load the module itself. Instead,
+//! the session with the token is provided by an implementation of
+//! [`Pkcs11Token`], usually a thin wrapper around a binding such as
+//! `cryptoki`, that lists the key objects and signs with one of the raw
+//! [`Mechanism`]s. [`Pkcs11Signer`] takes care of hashing and of the SSH
+//! signature encoding, and implements [`Signer`](crate::auth::Signer):
+//!
+//! ```no_run
+//! # use russh::keys::pkcs11::{Pkcs11Signer, Pkcs11Token};
+//! # async fn run<H: russh::client::Handler, T: Pkcs11Token>(session: &mut russh::client::Handle<H>, token: T) -> Result<(), Box<dyn std::error::Error>> {
+//! let mut signer = Pkcs11Signer::new(token).await?;
+//! for key in signer.keys().to_vec() {
+//!     let hash_alg = session.best_supported_rsa_hash().await?.flatten();
+//!     let result = session
+//!         .authenticate_publickey_with("user", key.public_key, hash_alg, &mut signer)
+//!         .await?;
+//!     if result.success() {
+//!         break;
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use ssh_encoding::Encode;
+use ssh_key::public::{EcdsaPublicKey, KeyData};
+use ssh_key::{Algorithm, HashAlg, Mpint, PublicKey};
+
+use crate::keys::Error;
+use crate::CryptoVec;
+
+/// `DigestInfo` prefixes for `CKM_RSA_PKCS` ([RFC 8017, section 9.2](https://tools.ietf.org/html/rfc8017#section-9.2)).
+const SHA1_DIGEST_INFO: &[u8] = &[
+    0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04, 0x14,
+];
+const SHA256_DIGEST_INFO: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01, 0x05,
+    0x00, 0x04, 0x20,
+];
+const SHA512_DIGEST_INFO: &[u8] = &[
+    0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03, 0x05,
+    0x00, 0x04, 0x40,
+];
+
+/// A private key object of the token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pkcs11Key {
+    /// The `CKA_ID` of the object, used to find it again when signing.
+    pub id: Vec<u8>,
+    /// The `CKA_LABEL` of the object.
+    pub label: String,
+    pub public_key: PublicKey,
+}
+
+/// The raw signing mechanisms used by [`Pkcs11Signer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    /// `CKM_RSA_PKCS`. The data is a DER-encoded `DigestInfo`.
+    RsaPkcs,
+    /// `CKM_ECDSA`. The data is the hash of the message, and the signature
+    /// is `r || s`.
+    Ecdsa,
+    /// `CKM_EDDSA`. The data is the message itself.
+    Eddsa,
+}
+
+/// A logged-in session with a PKCS#11 token.
+///
+/// Note: this is an async trait. The trait functions return `impl Future`,
+/// and you can simply define them as `async fn` instead.
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+pub trait Pkcs11Token: Send {
+    /// List the private keys of the token, with the matching public keys
+    /// (from the public key objects or certificates of the token).
+    fn keys(&mut self) -> impl Future<Output = Result<Vec<Pkcs11Key>, Error>> + Send;
+
+    /// Sign `data` with the private key object `key`.
+    fn sign(
+        &mut self,
+        key: &Pkcs11Key,
+        mechanism: Mechanism,
+        data: &[u8],
+    ) -> impl Future<Output = Result<Vec<u8>, Error>> + Send;
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Pkcs11AuthError {
+    #[error(transparent)]
+    Send(#[from] crate::SendError),
+    #[error(transparent)]
+    Key(#[from] Error),
+}
+
+/// A [`Signer`](crate::auth::Signer) for the keys of a PKCS#11 token.
+pub struct Pkcs11Signer<T: Pkcs11Token> {
+    token: T,
+    keys: Vec<Pkcs11Key>,
+}
+
+impl<T: Pkcs11Token> Pkcs11Signer<T> {
+    /// Load the keys of `token`. Keys of unsupported types are skipped.
+    pub async fn new(mut token: T) -> Result<Self, Error> {
+        let keys = token
+            .keys()
+            .await?
+            .into_iter()
+            .filter(|k| {
+                matches!(
+                    k.public_key.key_data(),
+                    KeyData::Rsa(_) | KeyData::Ecdsa(_) | KeyData::Ed25519(_)
+                )
+            })
+            .collect();
+        Ok(Pkcs11Signer { token, keys })
+    }
+
+    /// The keys available for authentication.
+    pub fn keys(&self) -> &[Pkcs11Key] {
+        &self.keys
+    }
+
+    async fn sign(
+        &mut self,
+        public_key: &PublicKey,
+        hash_alg: Option<HashAlg>,
+        data: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let Some(key) = self
+            .keys
+            .iter()
+            .find(|k| k.public_key.key_data() == public_key.key_data())
+        else {
+            return Err(Error::CouldNotReadKey);
+        };
+        let mut blob = Vec::new();
+        match public_key.key_data() {
+            KeyData::Rsa(_) => {
+                let digest_info = match hash_alg {
+                    Some(HashAlg::Sha256) => [SHA256_DIGEST_INFO, &Sha256::digest(data)].concat(),
+                    Some(HashAlg::Sha512) => [SHA512_DIGEST_INFO, &Sha512::digest(data)].concat(),
+                    None => [SHA1_DIGEST_INFO, &sha1::Sha1::digest(data)].concat(),
+                    Some(_) => return Err(Error::InvalidParameters),
+                };
+                let signature = self
+                    .token
+                    .sign(key, Mechanism::RsaPkcs, &digest_info)
+                    .await?;
+                Algorithm::Rsa { hash: hash_alg }
+                    .as_str()
+                    .encode(&mut blob)?;
+                signature.encode(&mut blob)?;
+            }
+            KeyData::Ecdsa(k) => {
+                let hash = match k {
+                    EcdsaPublicKey::NistP256(_) => Sha256::digest(data).to_vec(),
+                    EcdsaPublicKey::NistP384(_) => Sha384::digest(data).to_vec(),
+                    EcdsaPublicKey::NistP521(_) => Sha512::digest(data).to_vec(),
+                };
+                let signature = self.token.sign(key, Mechanism::Ecdsa, &hash).await?;
+                if signature.is_empty() || signature.len() % 2 != 0 {
+                    return Err(Error::InvalidSignature);
+                }
+                let (r, s) = signature.split_at(signature.len() / 2);
+                public_key.algorithm().as_str().encode(&mut blob)?;
+                let mut inner = Vec::new();
+                Mpint::from_positive_bytes(r)?.encode(&mut inner)?;
+                Mpint::from_positive_bytes(s)?.encode(&mut inner)?;
+                inner.encode(&mut blob)?;
+            }
+            KeyData::Ed25519(_) => {
+                let signature = self.token.sign(key, Mechanism::Eddsa, data).await?;
+                public_key.algorithm().as_str().encode(&mut blob)?;
+                signature.encode(&mut blob)?;
+            }
+            _ => {
+                return Err(Error::UnsupportedKeyType {
+                    key_type_string: public_key.algorithm().as_str().to_string(),
+                    key_type_raw: public_key.algorithm().as_str().as_bytes().to_vec(),
+                })
+            }
+        }
+        Ok(blob)
+    }
+}
+
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+impl<T: Pkcs11Token> crate::auth::Signer for Pkcs11Signer<T> {
+    type Error = Pkcs11AuthError;
+
+    #[allow(clippy::manual_async_fn)]
+    fn auth_publickey_sign(
+        &mut self,
+        key: &PublicKey,
+        hash_alg: Option<HashAlg>,
+        mut to_sign: CryptoVec,
+    ) -> impl Future<Output = Result<CryptoVec, Self::Error>> + Send {
+        async move {
+            let signature = self.sign(key, hash_alg, &to_sign).await?;
+            signature.encode(&mut to_sign).map_err(Error::from)?;
+            Ok(to_sign)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use signature::hazmat::PrehashSigner;
+    use signature::{Signer, Verifier};
+    use ssh_encoding::Decode;
+    use ssh_key::private::{EcdsaKeypair, Ed25519Keypair, KeypairData};
+    use ssh_key::PrivateKey;
+
+    use super::*;
+
+    struct Token {
+        key: PrivateKey,
+    }
+
+    impl Pkcs11Token for Token {
+        async fn keys(&mut self) -> Result<Vec<Pkcs11Key>, Error> {
+            Ok(vec![Pkcs11Key {
+                id: vec![1],
+                label: "test".into(),
+                public_key: self.key.public_key().clone(),
+            }])
+        }
+
+        async fn sign(
+            &mut self,
+            key: &Pkcs11Key,
+            mechanism: Mechanism,
+            data: &[u8],
+        ) -> Result<Vec<u8>, Error> {
+            assert_eq!(key.id, [1]);
+            match self.key.key_data() {
+                KeypairData::Ed25519(k) => {
+                    assert_eq!(mechanism, Mechanism::Eddsa);
+                    let k = ed25519_dalek::SigningKey::from_bytes(&k.private.to_bytes());
+                    Ok(k.sign(data).to_bytes().to_vec())
+                }
+                KeypairData::Ecdsa(EcdsaKeypair::NistP256 { private, .. }) => {
+                    assert_eq!(mechanism, Mechanism::Ecdsa);
+                    let k = p256::ecdsa::SigningKey::from_slice(private.as_slice()).unwrap();
+                    let signature: p256::ecdsa::Signature = k.sign_prehash(data).unwrap();
+                    Ok(signature.to_bytes().to_vec())
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    async fn check(key: PrivateKey) {
+        let public_key = key.public_key().clone();
+        let mut signer = Pkcs11Signer::new(Token { key }).await.unwrap();
+        assert_eq!(signer.keys().len(), 1);
+        let blob = signer.sign(&public_key, None, b"data").await.unwrap();
+        let signature = ssh_key::Signature::decode(&mut &blob[..]).unwrap();
+        Verifier::verify(&public_key, b"data", &signature).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ed25519_signature() {
+        let keypair = Ed25519Keypair::random(&mut rand::thread_rng());
+        check(PrivateKey::new(KeypairData::Ed25519(keypair), "").unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn ecdsa_signature() {
+        let keypair =
+            EcdsaKeypair::random(&mut rand::thread_rng(), ssh_key::EcdsaCurve::NistP256).unwrap();
+        check(PrivateKey::new(KeypairData::Ecdsa(keypair), "").unwrap()).await;
+    }
+}
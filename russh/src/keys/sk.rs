@@ -0,0 +1,258 @@
+//! Authentication with FIDO/U2F security keys, i.e. the
+//! `sk-ssh-ed25519@openssh.com` and `sk-ecdsa-sha2-nistp256@openssh.com`
+//! key types created by `ssh-keygen -t ed25519-sk`.
+//!
+//! The private key file of such a key only holds a handle to a credential
+//! stored on the device. Signing is done by the device itself, through an
+//! implementation of [`SecurityKey`], usually a wrapper around a FIDO2
+//! library such as `ctap-hid-fido2`. [`SkSigner`] then implements
+//! [`Signer`](crate::auth::Signer), for use with
+//! [`Handle::authenticate_publickey_with`](crate::client::Handle::authenticate_publickey_with):
+//!
+//! ```no_run
+//! # use russh::keys::sk::{SecurityKey, SkSigner};
+//! # async fn run<H: russh::client::Handler, D: SecurityKey>(session: &mut russh::client::Handle<H>, device: D) -> Result<(), Box<dyn std::error::Error>> {
+//! let key = russh::keys::load_secret_key("/home/user/.ssh/id_ed25519_sk", None)?;
+//! let public_key = key.public_key().clone();
+//! let mut signer = SkSigner::new(key, device)?;
+//! session
+//!     .authenticate_publickey_with("user", public_key, None, &mut signer)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+
+use sha2::{Digest, Sha256};
+use ssh_encoding::Encode;
+use ssh_key::private::KeypairData;
+use ssh_key::{HashAlg, Mpint, PrivateKey, PublicKey};
+
+use crate::keys::Error;
+use crate::CryptoVec;
+
+/// The device must check that the user is present (touch).
+pub const FLAG_USER_PRESENCE_REQUIRED: u8 = 0x01;
+/// The device must verify the user (PIN or biometrics).
+pub const FLAG_USER_VERIFICATION_REQUIRED: u8 = 0x04;
+
+/// A request to sign with a credential of the device.
+#[derive(Debug, Clone, Copy)]
+pub struct SkSignRequest<'a> {
+    /// The FIDO application (relying party) of the credential, usually
+    /// `ssh:`.
+    pub application: &'a str,
+    pub key_handle: &'a [u8],
+    /// The flags of the key, see [`FLAG_USER_PRESENCE_REQUIRED`] and
+    /// [`FLAG_USER_VERIFICATION_REQUIRED`].
+    pub flags: u8,
+    /// The SHA-256 hash of the data to sign, passed to the device as the
+    /// client data hash.
+    pub challenge: &'a [u8; 32],
+    /// The PIN returned by [`SecurityKey::pin`], if user verification is
+    /// required.
+    pub pin: Option<&'a str>,
+}
+
+/// The assertion returned by the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkAssertion {
+    /// The raw signature: 64 bytes for Ed25519, and a DER-encoded or
+    /// 64-byte `r || s` signature for ECDSA.
+    pub signature: Vec<u8>,
+    /// The flags reported by the authenticator.
+    pub flags: u8,
+    /// The signature counter.
+    pub counter: u32,
+}
+
+/// A FIDO2 authenticator.
+///
+/// Note: this is an async trait. The trait functions return `impl Future`,
+/// and you can simply define them as `async fn` instead.
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+pub trait SecurityKey: Send {
+    /// Sign with the device. This typically blocks until the user touches
+    /// the key.
+    fn sign(
+        &mut self,
+        request: SkSignRequest<'_>,
+    ) -> impl Future<Output = Result<SkAssertion, Error>> + Send;
+
+    /// Called before signing with a key that requires user verification.
+    /// Returns the PIN, or `None` to let the device verify the user by
+    /// other means.
+    fn pin(&mut self) -> impl Future<Output = Option<String>> + Send {
+        async { None }
+    }
+
+    /// Called before signing with a key that requires user presence, for
+    /// instance to ask the user to touch the key.
+    fn user_presence_required(&mut self) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SkAuthError {
+    #[error(transparent)]
+    Send(#[from] crate::SendError),
+    #[error(transparent)]
+    Key(#[from] Error),
+}
+
+/// A [`Signer`](crate::auth::Signer) for a security key.
+pub struct SkSigner<D: SecurityKey> {
+    key: PrivateKey,
+    device: D,
+}
+
+impl<D: SecurityKey> SkSigner<D> {
+    /// `key` is the private key file of the credential, which must be of
+    /// one of the security key types.
+    pub fn new(key: PrivateKey, device: D) -> Result<Self, Error> {
+        match key.key_data() {
+            KeypairData::SkEd25519(_) | KeypairData::SkEcdsaSha2NistP256(_) => {
+                Ok(SkSigner { key, device })
+            }
+            _ => Err(Error::UnsupportedKeyType {
+                key_type_string: key.algorithm().as_str().to_string(),
+                key_type_raw: key.algorithm().as_str().as_bytes().to_vec(),
+            }),
+        }
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        self.key.public_key()
+    }
+
+    async fn sign(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let (application, flags, key_handle) = match self.key.key_data() {
+            KeypairData::SkEd25519(k) => (k.public().application(), k.flags(), k.key_handle()),
+            KeypairData::SkEcdsaSha2NistP256(k) => {
+                (k.public().application(), k.flags(), k.key_handle())
+            }
+            _ => return Err(Error::CouldNotReadKey),
+        };
+        let challenge: [u8; 32] = Sha256::digest(data).into();
+        let pin = if flags & FLAG_USER_VERIFICATION_REQUIRED != 0 {
+            self.device.pin().await
+        } else {
+            None
+        };
+        if flags & FLAG_USER_PRESENCE_REQUIRED != 0 {
+            self.device.user_presence_required().await;
+        }
+        let assertion = self
+            .device
+            .sign(SkSignRequest {
+                application,
+                key_handle,
+                flags,
+                challenge: &challenge,
+                pin: pin.as_deref(),
+            })
+            .await?;
+        encode_signature(&self.key, &assertion)
+    }
+}
+
+/// Encode a security key signature blob, as specified in
+/// [PROTOCOL.u2f](https://cvsweb.openbsd.org/src/usr.bin/ssh/PROTOCOL.u2f?annotate=HEAD).
+fn encode_signature(key: &PrivateKey, assertion: &SkAssertion) -> Result<Vec<u8>, Error> {
+    let mut blob = Vec::new();
+    key.algorithm().as_str().encode(&mut blob)?;
+    match key.key_data() {
+        KeypairData::SkEcdsaSha2NistP256(_) => {
+            let signature = match p256::ecdsa::Signature::from_der(&assertion.signature) {
+                Ok(signature) => signature,
+                Err(_) => p256::ecdsa::Signature::from_slice(&assertion.signature)
+                    .map_err(|_| Error::InvalidSignature)?,
+            };
+            let (r, s) = signature.split_bytes();
+            let mut inner = Vec::new();
+            Mpint::from_positive_bytes(&r)?.encode(&mut inner)?;
+            Mpint::from_positive_bytes(&s)?.encode(&mut inner)?;
+            inner.encode(&mut blob)?;
+        }
+        _ => assertion.signature.encode(&mut blob)?,
+    }
+    assertion.flags.encode(&mut blob)?;
+    assertion.counter.encode(&mut blob)?;
+    Ok(blob)
+}
+
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+impl<D: SecurityKey> crate::auth::Signer for SkSigner<D> {
+    type Error = SkAuthError;
+
+    #[allow(clippy::manual_async_fn)]
+    fn auth_publickey_sign(
+        &mut self,
+        _key: &PublicKey,
+        _hash_alg: Option<HashAlg>,
+        mut to_sign: CryptoVec,
+    ) -> impl Future<Output = Result<CryptoVec, Self::Error>> + Send {
+        async move {
+            let signature = self.sign(&to_sign).await?;
+            signature.encode(&mut to_sign).map_err(Error::from)?;
+            Ok(to_sign)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use ssh_encoding::{Decode, Reader};
+    use ssh_key::public::Ed25519PublicKey;
+
+    use super::*;
+
+    struct Device {
+        touched: bool,
+    }
+
+    impl SecurityKey for Device {
+        async fn sign(&mut self, request: SkSignRequest<'_>) -> Result<SkAssertion, Error> {
+            assert_eq!(request.application, "ssh:");
+            assert_eq!(request.key_handle, b"handle");
+            assert!(self.touched);
+            Ok(SkAssertion {
+                signature: request.challenge.repeat(2),
+                flags: FLAG_USER_PRESENCE_REQUIRED,
+                counter: 7,
+            })
+        }
+
+        async fn user_presence_required(&mut self) {
+            self.touched = true
+        }
+    }
+
+    #[tokio::test]
+    async fn ed25519_signature() {
+        let public = ssh_key::public::SkEd25519::new(Ed25519PublicKey([1; 32]), "ssh:");
+        let keypair = ssh_key::private::SkEd25519::new(
+            public,
+            FLAG_USER_PRESENCE_REQUIRED,
+            b"handle".to_vec(),
+        )
+        .unwrap();
+        let key = PrivateKey::new(KeypairData::SkEd25519(keypair), "").unwrap();
+        let mut signer = SkSigner::new(key, Device { touched: false }).unwrap();
+
+        let blob = signer.sign(b"data").await.unwrap();
+        let mut r = &blob[..];
+        assert_eq!(
+            String::decode(&mut r).unwrap(),
+            "sk-ssh-ed25519@openssh.com"
+        );
+        let signature = Vec::<u8>::decode(&mut r).unwrap();
+        assert_eq!(signature, Sha256::digest(b"data").repeat(2));
+        assert_eq!(u8::decode(&mut r).unwrap(), FLAG_USER_PRESENCE_REQUIRED);
+        assert_eq!(u32::decode(&mut r).unwrap(), 7);
+        assert!(r.is_finished());
+    }
+}
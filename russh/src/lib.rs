@@ -107,6 +107,7 @@ mod cert;
 pub mod cipher;
 /// Compression algorithm names
 pub mod compression;
+pub mod config_file;
 /// Key exchange algorithm names
 pub mod kex;
 /// MAC algorithm names
@@ -118,12 +119,15 @@ mod msg;
 mod negotiation;
 mod ssh_read;
 mod sshbuffer;
+pub mod transport;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
-pub use negotiation::Preferred;
+pub use negotiation::{ConnectionInfo, Preferred};
 
 mod pty;
 
-pub use pty::Pty;
+pub use pty::{Pty, PtyRequest, TerminalModes, WindowSize};
 pub use sshbuffer::SshId;
 
 mod helpers;
@@ -144,11 +148,21 @@ macro_rules! push_packet {
 }
 
 mod channels;
-pub use channels::{Channel, ChannelMsg, ChannelReadHalf, ChannelStream, ChannelWriteHalf};
+mod ext_info;
+pub use channels::io::{ChannelOutput, ChannelStreams, ChannelTx};
+pub use channels::{
+    tun, Channel, ChannelMsg, ChannelReadHalf, ChannelStream, ChannelWriteHalf, RateLimit,
+    RateLimiter,
+};
+pub use ext_info::ExtensionInfo;
 
 mod parsing;
 mod session;
 
+/// SFTP client.
+#[cfg(feature = "sftp")]
+pub mod sftp;
+
 /// Server side of this library.
 #[cfg(not(target_arch = "wasm32"))]
 pub mod server;
@@ -235,6 +249,10 @@ pub enum Error {
     #[error("Disconnected")]
     Disconnect,
 
+    /// The other side sent `SSH_MSG_DISCONNECT`.
+    #[error("Disconnected by the other side ({:?}): {}", .0.reason_code, .0.message)]
+    RemoteDisconnect(RemoteDisconnectInfo),
+
     /// No home directory found when trying to learn new host key.
     #[error("No home directory when saving host key")]
     NoHomeDir,
@@ -252,7 +270,7 @@ pub enum Error {
     #[error("Connection timeout")]
     ConnectionTimeout,
 
-    /// Keepalive timeout.
+    /// Too many keepalives were left unanswered.
     #[error("Keepalive timeout")]
     KeepaliveTimeout,
 
@@ -276,6 +294,20 @@ pub enum Error {
     #[error("The request was rejected by the other party")]
     RequestDenied,
 
+    #[error("The other party does not support {0}")]
+    Unsupported(String),
+
+    #[error("Command output exceeded the size limit")]
+    OutputLimitExceeded,
+
+    /// A client exceeded the limits of
+    /// [`server::Config::preauth_limits`] before authenticating.
+    #[error("Pre-authentication limit exceeded")]
+    PreAuthLimitExceeded,
+
+    #[error("scp: {0}")]
+    Scp(String),
+
     #[error(transparent)]
     Keys(#[from] crate::keys::Error),
 
@@ -316,6 +348,10 @@ pub enum Error {
 
     #[error("Invalid config: {0}")]
     InvalidConfig(String),
+
+    #[cfg(feature = "gssapi")]
+    #[error("GSSAPI: {0}")]
+    Gssapi(String),
 }
 
 pub(crate) fn strict_kex_violation(message_type: u8, sequence_number: usize) -> crate::Error {
@@ -333,20 +369,30 @@ pub(crate) fn strict_kex_violation(message_type: u8, sequence_number: usize) ->
 #[error("Could not reach the event loop")]
 pub struct SendError {}
 
-/// The number of bytes read/written, and the number of seconds before a key
-/// re-exchange is requested.
+/// When to re-exchange keys, in the manner of OpenSSH's `RekeyLimit`:
+/// a rekey is started as soon as any of the limits is reached.
+///
+/// The byte limits are capped by what the negotiated cipher can safely
+/// encrypt under a single key (2^32 blocks, that is 64 GiB, for AES,
+/// and 1 GiB otherwise), so that a large limit cannot lead to nonce
+/// reuse. By default, they are exactly that limit of the cipher. The
+/// number of packets before a rekey is set by the `rekey_packet_limit`
+/// of the client and server configurations.
 #[derive(Debug, Clone)]
 pub struct Limits {
+    /// Bytes sent before a rekey. `usize::MAX` uses the limit of the
+    /// cipher.
     pub rekey_write_limit: usize,
+    /// Bytes received before a rekey. `usize::MAX` uses the limit of the
+    /// cipher.
     pub rekey_read_limit: usize,
+    /// Time before a rekey.
     pub rekey_time_limit: std::time::Duration,
 }
 
 impl Limits {
-    /// Create a new `Limits`, checking that the given bounds cannot lead to
-    /// nonce reuse.
+    /// Create a new `Limits` with the given byte and time limits.
     pub fn new(write_limit: usize, read_limit: usize, time_limit: std::time::Duration) -> Limits {
-        assert!(write_limit <= 1 << 30 && read_limit <= 1 << 30);
         Limits {
             rekey_write_limit: write_limit,
             rekey_read_limit: read_limit,
@@ -359,9 +405,10 @@ impl Default for Limits {
     fn default() -> Self {
         // Following the recommendations of
         // https://tools.ietf.org/html/rfc4253#section-9
+        // and https://tools.ietf.org/html/rfc4344#section-3
         Limits {
-            rekey_write_limit: 1 << 30, // 1 Gb
-            rekey_read_limit: 1 << 30,  // 1 Gb
+            rekey_write_limit: usize::MAX,
+            rekey_read_limit: usize::MAX,
             rekey_time_limit: std::time::Duration::from_secs(3600),
         }
     }
@@ -372,7 +419,7 @@ pub use auth::{AgentAuthError, MethodKind, MethodSet, Signer};
 /// A reason for disconnection.
 #[allow(missing_docs)] // This should be relatively self-explanatory.
 #[allow(clippy::manual_non_exhaustive)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Disconnect {
     HostNotAllowedToConnect = 1,
     ProtocolError = 2,
@@ -417,6 +464,40 @@ impl TryFrom<u32> for Disconnect {
     }
 }
 
+/// The contents of an `SSH_MSG_DISCONNECT` sent by the other side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteDisconnectInfo {
+    pub reason_code: Disconnect,
+    pub message: String,
+    pub lang_tag: String,
+}
+
+impl RemoteDisconnectInfo {
+    /// Parse an `SSH_MSG_DISCONNECT` packet, including its message type.
+    pub(crate) fn parse(mut r: &[u8]) -> Result<Self, Error> {
+        u8::decode(&mut r)?; // skip message type
+        Ok(RemoteDisconnectInfo {
+            reason_code: u32::decode(&mut r)?.try_into()?,
+            message: String::decode(&mut r)?,
+            lang_tag: String::decode(&mut r)?,
+        })
+    }
+}
+
+/// How a connection ended, as reported by `closed()` on the client and
+/// server handles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The other side sent `SSH_MSG_DISCONNECT`.
+    Remote(RemoteDisconnectInfo),
+    /// This side disconnected, or all the handles to the session were
+    /// dropped.
+    Local,
+    /// The connection failed. The error itself is returned by the
+    /// session (or passed to the client's `Handler::disconnected`).
+    Error,
+}
+
 /// The type of signals that can be sent to a remote process. If you
 /// plan to use custom signals, read [the
 /// RFC](https://tools.ietf.org/html/rfc4254#section-6.10) to
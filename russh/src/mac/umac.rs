@@ -0,0 +1,283 @@
+//! UMAC ([RFC 4418](https://datatracker.ietf.org/doc/html/rfc4418)), as
+//! `umac-64@openssh.com` and `umac-128@openssh.com`. The nonce is the
+//! sequence number of the packet, on 64 bits.
+
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use generic_array::GenericArray;
+use subtle::ConstantTimeEq;
+
+use super::{Mac, MacAlgorithm};
+
+/// The size of the blocks hashed by NH, in bytes.
+const L1_BLOCK_LEN: usize = 1024;
+
+const P36: u64 = (1 << 36) - 5;
+const P64: u64 = u64::MAX - 58;
+
+pub struct UmacAlgorithm {
+    pub tag_len: usize,
+    pub etm: bool,
+}
+
+impl MacAlgorithm for UmacAlgorithm {
+    fn key_len(&self) -> usize {
+        16
+    }
+
+    fn make_mac(&self, mac_key: &[u8]) -> Box<dyn Mac + Send> {
+        Box::new(Umac::new(mac_key, self.tag_len, self.etm)) as Box<dyn Mac + Send>
+    }
+}
+
+/// The keys of one of the UHASH iterations.
+struct Iteration {
+    l2_key: u64,
+    l3_key1: [u64; 8],
+    l3_key2: u32,
+}
+
+pub struct Umac {
+    tag_len: usize,
+    etm: bool,
+    /// The NH key, shifted by 4 words for each iteration.
+    l1_key: Vec<u32>,
+    iterations: Vec<Iteration>,
+    pdf: Aes128,
+}
+
+/// The key derivation function of RFC 4418, section 3.2.1.
+fn kdf(cipher: &Aes128, index: u64, len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len.div_ceil(16) * 16);
+    for i in 1..=len.div_ceil(16) as u64 {
+        let mut block = GenericArray::from((u128::from(index) << 64 | u128::from(i)).to_be_bytes());
+        cipher.encrypt_block(&mut block);
+        out.extend_from_slice(&block);
+    }
+    out.truncate(len);
+    out
+}
+
+impl Umac {
+    fn new(key: &[u8], tag_len: usize, etm: bool) -> Self {
+        let cipher = Aes128::new(GenericArray::from_slice(key));
+        let iters = tag_len / 4;
+
+        let l1_key = kdf(&cipher, 1, L1_BLOCK_LEN + (iters - 1) * 16)
+            .chunks_exact(4)
+            .map(BigEndian::read_u32)
+            .collect();
+        let l2_key = kdf(&cipher, 2, iters * 24);
+        let l3_key1 = kdf(&cipher, 3, iters * 64);
+        let l3_key2 = kdf(&cipher, 4, iters * 4);
+        let iterations = l2_key
+            .chunks_exact(24)
+            .zip(l3_key1.chunks_exact(64))
+            .zip(l3_key2.chunks_exact(4))
+            .map(|((l2, l3_1), l3_2)| {
+                let mut l3_key1 = [0; 8];
+                for (k, b) in l3_key1.iter_mut().zip(l3_1.chunks_exact(8)) {
+                    *k = BigEndian::read_u64(b) % P36;
+                }
+                Iteration {
+                    // The last 16 bytes, the key of the 128-bit polynomial, are
+                    // unused.
+                    l2_key: BigEndian::read_u64(l2) & 0x01ff_ffff_01ff_ffff,
+                    l3_key1,
+                    l3_key2: BigEndian::read_u32(l3_2),
+                }
+            })
+            .collect();
+
+        let pdf_key = kdf(&cipher, 0, 16);
+        Umac {
+            tag_len,
+            etm,
+            l1_key,
+            iterations,
+            pdf: Aes128::new(GenericArray::from_slice(&pdf_key)),
+        }
+    }
+
+    /// The pad of RFC 4418, section 3.1, xored into `output`.
+    fn pdf(&self, nonce: u64, output: &mut [u8]) {
+        // UMAC-64 uses either half of the block of two consecutive nonces.
+        let (nonce, index) = if self.tag_len == 8 {
+            (nonce & !1, (nonce & 1) as usize)
+        } else {
+            (nonce, 0)
+        };
+        let mut block = GenericArray::from((u128::from(nonce) << 64).to_be_bytes());
+        self.pdf.encrypt_block(&mut block);
+        for (o, p) in output
+            .iter_mut()
+            .zip(block.iter().skip(index * self.tag_len))
+        {
+            *o ^= p;
+        }
+    }
+
+    fn uhash(&self, message: &[u8], output: &mut [u8]) {
+        for ((i, iteration), out) in self
+            .iterations
+            .iter()
+            .enumerate()
+            .zip(output.chunks_exact_mut(4))
+        {
+            let key = self.l1_key.get(4 * i..).unwrap_or_default();
+            let l1 = l1_hash(key, message);
+            let l2 = match l1.as_slice() {
+                [l1] if message.len() <= L1_BLOCK_LEN => u128::from(*l1),
+                _ => l2_hash(iteration.l2_key, &l1),
+            };
+            BigEndian::write_u32(out, l3_hash(&iteration.l3_key1, iteration.l3_key2, l2));
+        }
+    }
+}
+
+/// NH on the 32-byte blocks of `message`.
+fn nh<'a>(key: &[u32], message: impl Iterator<Item = &'a [u8]>) -> u64 {
+    let mut y = 0u64;
+    for (m, k) in message.zip(key.chunks_exact(8)) {
+        let mut s = [0u32; 8];
+        for ((s, m), k) in s.iter_mut().zip(m.chunks_exact(4)).zip(k) {
+            *s = LittleEndian::read_u32(m).wrapping_add(*k);
+        }
+        let [a0, a1, a2, a3, a4, a5, a6, a7] = s.map(u64::from);
+        y = y
+            .wrapping_add(a0 * a4)
+            .wrapping_add(a1 * a5)
+            .wrapping_add(a2 * a6)
+            .wrapping_add(a3 * a7);
+    }
+    y
+}
+
+fn l1_hash(key: &[u32], message: &[u8]) -> Vec<u64> {
+    let blocks = message.len().div_ceil(L1_BLOCK_LEN).max(1);
+    let (full, last) = message.split_at((blocks - 1) * L1_BLOCK_LEN);
+    let mut out = Vec::with_capacity(blocks);
+    for block in full.chunks_exact(L1_BLOCK_LEN) {
+        out.push(nh(key, block.chunks_exact(32)).wrapping_add(8 * L1_BLOCK_LEN as u64));
+    }
+    // The last block is padded with zeros to a non-zero multiple of 32
+    // bytes.
+    let mut padded = [0; L1_BLOCK_LEN];
+    for (p, m) in padded.iter_mut().zip(last) {
+        *p = *m;
+    }
+    let len = last.len().div_ceil(32).max(1);
+    out.push(nh(key, padded.chunks_exact(32).take(len)).wrapping_add(8 * last.len() as u64));
+    out
+}
+
+fn poly64(k: u64, y: u64, m: u64) -> u64 {
+    let step =
+        |y: u64, m: u64| ((u128::from(k) * u128::from(y) + u128::from(m)) % u128::from(P64)) as u64;
+    if m >= u64::MAX - 0xffff_ffff {
+        step(step(y, P64 - 1), m - (u64::MAX - P64 + 1))
+    } else {
+        step(y, m)
+    }
+}
+
+/// L2-HASH with 64-bit words only: like OpenSSH, this does not switch
+/// to the 128-bit polynomial after 2^17 bytes of L1 output, which is
+/// 16 MiB of message, far above the maximal packet length.
+fn l2_hash(k: u64, l1: &[u64]) -> u128 {
+    u128::from(l1.iter().fold(1, |y, m| poly64(k, y, *m)))
+}
+
+fn l3_hash(key1: &[u64; 8], key2: u32, m: u128) -> u32 {
+    let y = key1
+        .iter()
+        .enumerate()
+        .map(|(i, k)| ((m >> (112 - 16 * i)) as u64 & 0xffff) * k)
+        .sum::<u64>()
+        % P36;
+    (y as u32) ^ key2
+}
+
+impl Umac {
+    fn tag(&self, sequence_number: u32, payload: &[u8], output: &mut [u8]) {
+        self.uhash(payload, output);
+        self.pdf(u64::from(sequence_number), output);
+    }
+}
+
+impl Mac for Umac {
+    fn mac_len(&self) -> usize {
+        self.tag_len
+    }
+
+    fn is_etm(&self) -> bool {
+        self.etm
+    }
+
+    fn compute(&self, sequence_number: u32, payload: &[u8], output: &mut [u8]) {
+        self.tag(sequence_number, payload, output)
+    }
+
+    fn verify(&self, sequence_number: u32, payload: &[u8], mac: &[u8]) -> bool {
+        let mut buf = vec![0; self.tag_len];
+        self.tag(sequence_number, payload, &mut buf);
+        buf.ct_eq(mac).into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn umac(tag_len: usize, nonce: u64, message: &[u8]) -> String {
+        let umac = Umac::new(b"abcdefghijklmnop", tag_len, false);
+        let mut tag = vec![0; tag_len];
+        umac.uhash(message, &mut tag);
+        umac.pdf(nonce, &mut tag);
+        data_encoding::HEXUPPER.encode(&tag)
+    }
+
+    // RFC 4418, appendix, with the nonce "bcdefghi". The first 12 bytes
+    // of UMAC-128 are UMAC-96.
+    #[test]
+    fn test_vectors() {
+        let nonce = u64::from_be_bytes(*b"bcdefghi");
+        for (message, tag64, tag96) in [
+            (vec![], "6E155FAD26900BE1", "32FEDB100C79AD58F07FF764"),
+            (
+                vec![b'a'; 3],
+                "44B5CB542F220104",
+                "185E4FE905CBA7BD85E4C2DC",
+            ),
+            (
+                vec![b'a'; 1 << 10],
+                "26BF2F5D60118BD9",
+                "7A54ABE04AF82D60FB298C3C",
+            ),
+            (
+                vec![b'a'; 1 << 15],
+                "27F8EF643B0D118D",
+                "7B136BD911E4B734286EF2BE",
+            ),
+            (
+                vec![b'a'; 1 << 20],
+                "A4477E87E9F55853",
+                "F8ACFA3AC31CFEEA047F7B11",
+            ),
+            (
+                b"abc".to_vec(),
+                "D4D7B9F6BD4FBFCF",
+                "883C3D4B97A61976FFCF2323",
+            ),
+            (
+                b"abc".repeat(500),
+                "D4CF26DDEFD5C01A",
+                "8824A260C53C66A36C9260A6",
+            ),
+        ] {
+            assert_eq!(umac(8, nonce, &message), tag64);
+            assert!(umac(16, nonce, &message).starts_with(tag96));
+        }
+    }
+}
@@ -52,6 +52,18 @@ pub const USERAUTH_INFO_RESPONSE: u8 = 61;
 // some numbers have same meaning
 pub const USERAUTH_INFO_REQUEST_OR_USERAUTH_PK_OK: u8 = 60;
 
+// https://tools.ietf.org/html/rfc4462#section-3.9
+#[cfg(feature = "gssapi")]
+pub const USERAUTH_GSSAPI_RESPONSE: u8 = 60;
+#[cfg(feature = "gssapi")]
+pub const USERAUTH_GSSAPI_TOKEN: u8 = 61;
+#[cfg(feature = "gssapi")]
+pub const USERAUTH_GSSAPI_ERROR: u8 = 64;
+#[cfg(feature = "gssapi")]
+pub const USERAUTH_GSSAPI_ERRTOK: u8 = 65;
+#[cfg(feature = "gssapi")]
+pub const USERAUTH_GSSAPI_MIC: u8 = 66;
+
 // https://tools.ietf.org/html/rfc4254#section-9
 pub const GLOBAL_REQUEST: u8 = 80;
 pub const REQUEST_SUCCESS: u8 = 81;
@@ -69,6 +81,10 @@ pub const CHANNEL_REQUEST: u8 = 98;
 pub const CHANNEL_SUCCESS: u8 = 99;
 pub const CHANNEL_FAILURE: u8 = 100;
 
+// https://github.com/openssh/openssh-portable/blob/master/PROTOCOL
+pub const PING: u8 = 192;
+pub const PONG: u8 = 193;
+
 #[allow(dead_code)]
 pub const SSH_OPEN_CONNECT_FAILED: u8 = 2;
 pub const SSH_OPEN_UNKNOWN_CHANNEL_TYPE: u8 = 3;
@@ -155,3 +171,26 @@ const ALL_KEX_MESSAGES: &[u8] = &[
 pub(crate) fn is_kex_msg(msg: u8) -> bool {
     ALL_KEX_MESSAGES.contains(&msg)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn strict_kex_order() {
+        for (seqno, msg) in [KEXINIT, KEX_ECDH_REPLY, NEWKEYS].into_iter().enumerate() {
+            assert!(validate_server_msg_strict_kex(msg, seqno).is_ok());
+        }
+        for (seqno, msg) in [KEXINIT, KEX_DH_GEX_REQUEST, KEX_DH_GEX_INIT, NEWKEYS]
+            .into_iter()
+            .enumerate()
+        {
+            assert!(validate_client_msg_strict_kex(msg, seqno).is_ok());
+        }
+        // Messages that are harmless otherwise are rejected during the
+        // initial kex.
+        assert!(validate_server_msg_strict_kex(IGNORE, 1).is_err());
+        assert!(validate_client_msg_strict_kex(DEBUG, 0).is_err());
+        assert!(validate_server_msg_strict_kex(NEWKEYS, 1).is_err());
+    }
+}
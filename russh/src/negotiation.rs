@@ -17,7 +17,7 @@ use std::borrow::Cow;
 use log::debug;
 use rand::RngCore;
 use ssh_encoding::{Decode, Encode};
-use ssh_key::{Algorithm, EcdsaCurve, HashAlg, PrivateKey};
+use ssh_key::{Algorithm, Certificate, EcdsaCurve, HashAlg, PrivateKey};
 
 use crate::cipher::CIPHERS;
 use crate::helpers::NameList;
@@ -31,6 +31,7 @@ use crate::{cipher, compression, kex, mac, msg, AlgorithmKind, CryptoVec, Error}
 /// WASM-only stub
 pub struct Config {
     keys: Vec<PrivateKey>,
+    host_certificates: Vec<Certificate>,
 }
 
 #[derive(Debug, Clone)]
@@ -38,15 +39,57 @@ pub struct Names {
     pub kex: kex::Name,
     #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
     pub key: Algorithm,
+    /// Whether the host key is presented in a certificate.
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    pub key_is_certificate: bool,
     pub cipher: cipher::Name,
     pub client_mac: mac::Name,
     pub server_mac: mac::Name,
-    pub server_compression: compression::Compression,
-    pub client_compression: compression::Compression,
+    pub server_compression: compression::Name,
+    pub client_compression: compression::Name,
     pub ignore_guessed: bool,
     pub strict_kex: bool,
 }
 
+/// The algorithms negotiated in the last key exchange of a connection,
+/// and the version of the other side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub kex: kex::Name,
+    pub host_key: Algorithm,
+    /// Whether the host key was presented in a certificate.
+    pub host_certificate: bool,
+    /// The cipher, used in both directions.
+    pub cipher: cipher::Name,
+    /// The MAC from the client to the server.
+    pub client_mac: mac::Name,
+    /// The MAC from the server to the client.
+    pub server_mac: mac::Name,
+    /// The compression from the client to the server.
+    pub client_compression: compression::Name,
+    /// The compression from the server to the client.
+    pub server_compression: compression::Name,
+    /// The SSH identification string of the other side, such as
+    /// `SSH-2.0-OpenSSH_9.6`.
+    pub remote_sshid: String,
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new(names: &Names, remote_sshid: &[u8]) -> Self {
+        ConnectionInfo {
+            kex: names.kex,
+            host_key: names.key.clone(),
+            host_certificate: names.key_is_certificate,
+            cipher: names.cipher,
+            client_mac: names.client_mac,
+            server_mac: names.server_mac,
+            client_compression: names.client_compression,
+            server_compression: names.server_compression,
+            remote_sshid: String::from_utf8_lossy(remote_sshid).into_owned(),
+        }
+    }
+}
+
 /// Lists of preferred algorithms. This is normally hard-coded into implementations.
 #[derive(Debug, Clone)]
 pub struct Preferred {
@@ -71,26 +114,80 @@ pub(crate) fn is_key_compatible_with_algo(key: &PrivateKey, algo: &Algorithm) ->
     }
 }
 
+/// The certificate of `key` among `certificates`, if any.
+pub(crate) fn certificate_of<'a>(
+    key: &PrivateKey,
+    certificates: &'a [Certificate],
+) -> Option<&'a Certificate> {
+    certificates
+        .iter()
+        .find(|c| c.public_key() == key.public_key().key_data())
+}
+
+/// A host key algorithm, either for a plain key or for a certificate.
+#[derive(Debug, Clone)]
+pub(crate) struct HostKeyAlgorithm {
+    name: String,
+    algorithm: Algorithm,
+    certificate: bool,
+}
+
+impl AsRef<str> for HostKeyAlgorithm {
+    fn as_ref(&self) -> &str {
+        &self.name
+    }
+}
+
+impl HostKeyAlgorithm {
+    fn plain(algorithm: &Algorithm) -> Self {
+        HostKeyAlgorithm {
+            name: algorithm.to_string(),
+            algorithm: algorithm.clone(),
+            certificate: false,
+        }
+    }
+}
+
 impl Preferred {
+    /// The host key algorithms of the keys and certificates available,
+    /// with the ones of certificates first as in OpenSSH.
     pub(crate) fn possible_host_key_algos_for_keys(
         &self,
         available_host_keys: &[PrivateKey],
-    ) -> Vec<Algorithm> {
-        self.key
+        available_host_certificates: &[Certificate],
+    ) -> Vec<HostKeyAlgorithm> {
+        let certified = self
+            .key
+            .iter()
+            .filter(|n| {
+                available_host_keys.iter().any(|k| {
+                    is_key_compatible_with_algo(k, n)
+                        && certificate_of(k, available_host_certificates).is_some()
+                })
+            })
+            .map(|n| HostKeyAlgorithm {
+                name: n.to_certificate_type(),
+                algorithm: n.clone(),
+                certificate: true,
+            });
+        let plain = self
+            .key
             .iter()
             .filter(|n| {
                 available_host_keys
                     .iter()
                     .any(|k| is_key_compatible_with_algo(k, n))
             })
-            .cloned()
-            .collect::<Vec<_>>()
+            .map(HostKeyAlgorithm::plain);
+        certified.chain(plain).collect()
     }
 }
 
 const SAFE_KEX_ORDER: &[kex::Name] = &[
+    kex::MLKEM768X25519_SHA256,
     kex::CURVE25519,
     kex::CURVE25519_PRE_RFC_8731,
+    kex::CURVE448,
     kex::DH_GEX_SHA256,
     kex::DH_G18_SHA512,
     kex::DH_G17_SHA512,
@@ -111,13 +208,17 @@ const CIPHER_ORDER: &[cipher::Name] = &[
     cipher::AES_128_CTR,
 ];
 
-const HMAC_ORDER: &[mac::Name] = &[
+const MAC_ORDER: &[mac::Name] = &[
     mac::HMAC_SHA512_ETM,
     mac::HMAC_SHA256_ETM,
     mac::HMAC_SHA512,
     mac::HMAC_SHA256,
     mac::HMAC_SHA1_ETM,
     mac::HMAC_SHA1,
+    mac::UMAC_128_ETM,
+    mac::UMAC_64_ETM,
+    mac::UMAC_128,
+    mac::UMAC_64,
 ];
 
 const COMPRESSION_ORDER: &[compression::Name] = &[
@@ -151,7 +252,7 @@ impl Preferred {
             Algorithm::Rsa { hash: None },
         ]),
         cipher: Cow::Borrowed(CIPHER_ORDER),
-        mac: Cow::Borrowed(HMAC_ORDER),
+        mac: Cow::Borrowed(MAC_ORDER),
         compression: Cow::Borrowed(COMPRESSION_ORDER),
     };
 
@@ -159,7 +260,7 @@ impl Preferred {
         kex: Cow::Borrowed(SAFE_KEX_ORDER),
         key: Preferred::DEFAULT.key,
         cipher: Cow::Borrowed(CIPHER_ORDER),
-        mac: Cow::Borrowed(HMAC_ORDER),
+        mac: Cow::Borrowed(MAC_ORDER),
         compression: Cow::Borrowed(COMPRESSION_ORDER),
     };
 }
@@ -174,6 +275,32 @@ pub(crate) fn parse_kex_algo_list(list: &str) -> Vec<&str> {
     list.split(',').collect()
 }
 
+/// Whether a `KEXINIT` packet lists `name` among its key exchange
+/// algorithms, as the extension pseudo-algorithms are.
+pub(crate) fn kex_init_lists(kex_init: &[u8], name: &kex::Name) -> bool {
+    // Skip the message type and the cookie.
+    let Some(mut r) = kex_init.get(17..) else {
+        return false;
+    };
+    String::decode(&mut r).is_ok_and(|list| parse_kex_algo_list(&list).contains(&name.as_ref()))
+}
+
+/// The host key algorithms listed by a `KEXINIT` packet, in order of
+/// preference.
+pub(crate) fn kex_init_host_key_algorithms(kex_init: &[u8]) -> Vec<String> {
+    let Some(mut r) = kex_init.get(17..) else {
+        return Vec::new();
+    };
+    let (Ok(_), Ok(list)) = (String::decode(&mut r), String::decode(&mut r)) else {
+        return Vec::new();
+    };
+    parse_kex_algo_list(&list)
+        .into_iter()
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect()
+}
+
 pub(crate) trait Select {
     fn is_server() -> bool;
 
@@ -183,11 +310,13 @@ pub(crate) trait Select {
         kind: AlgorithmKind,
     ) -> Result<(bool, S), Error>;
 
-    /// `available_host_keys`, if present, is used to limit the host key algorithms to the ones we have keys for.
+    /// `available_host_keys`, if present, is used to limit the host key
+    /// algorithms to the ones we have keys for, and add the ones of the
+    /// certificates of these keys.
     fn read_kex(
         buffer: &[u8],
         pref: &Preferred,
-        available_host_keys: Option<&[PrivateKey]>,
+        available_host_keys: Option<(&[PrivateKey], &[Certificate])>,
     ) -> Result<Names, Error> {
         let Some(mut r) = &buffer.get(17..) else {
             return Err(Error::Inconsistent);
@@ -228,8 +357,12 @@ pub(crate) trait Select {
 
         let key_string = String::decode(&mut r)?;
         let possible_host_key_algos = match available_host_keys {
-            Some(available_host_keys) => pref.possible_host_key_algos_for_keys(available_host_keys),
-            None => pref.key.iter().map(ToOwned::to_owned).collect::<Vec<_>>(),
+            Some((keys, certificates)) => pref.possible_host_key_algos_for_keys(keys, certificates),
+            None => pref
+                .key
+                .iter()
+                .map(HostKeyAlgorithm::plain)
+                .collect::<Vec<_>>(),
         };
 
         let (key_both_first, key_algorithm) = Self::select(
@@ -284,31 +417,28 @@ pub(crate) trait Select {
         // Compression
 
         // client-to-server compression.
-        let client_compression = compression::Compression::new(
-            &Self::select(
-                &pref.compression,
-                &parse_kex_algo_list(&String::decode(&mut r)?),
-                AlgorithmKind::Compression,
-            )?
-            .1,
-        );
+        let client_compression = Self::select(
+            &pref.compression,
+            &parse_kex_algo_list(&String::decode(&mut r)?),
+            AlgorithmKind::Compression,
+        )?
+        .1;
 
         // server-to-client compression.
-        let server_compression = compression::Compression::new(
-            &Self::select(
-                &pref.compression,
-                &parse_kex_algo_list(&String::decode(&mut r)?),
-                AlgorithmKind::Compression,
-            )?
-            .1,
-        );
+        let server_compression = Self::select(
+            &pref.compression,
+            &parse_kex_algo_list(&String::decode(&mut r)?),
+            AlgorithmKind::Compression,
+        )?
+        .1;
         String::decode(&mut r)?; // languages client-to-server
         String::decode(&mut r)?; // languages server-to-client
 
         let follows = u8::decode(&mut r)? != 0;
         Ok(Names {
             kex: kex_algorithm,
-            key: key_algorithm,
+            key: key_algorithm.algorithm,
+            key_is_certificate: key_algorithm.certificate,
             cipher,
             client_mac,
             server_mac,
@@ -420,15 +550,12 @@ pub(crate) fn write_kex(
             // Only advertise host key algorithms that we have keys for.
             NameList(
                 prefs
-                    .key
-                    .iter()
-                    .filter(|algo| {
-                        server_config
-                            .keys
-                            .iter()
-                            .any(|k| is_key_compatible_with_algo(k, algo))
-                    })
-                    .map(|x| x.to_string())
+                    .possible_host_key_algos_for_keys(
+                        &server_config.keys,
+                        &server_config.host_certificates,
+                    )
+                    .into_iter()
+                    .map(|x| x.name)
                     .collect(),
             )
             .encode(w)?;
@@ -490,3 +617,80 @@ pub(crate) fn write_kex(
         Ok(())
     })
 }
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use rand_core::OsRng;
+    use ssh_key::certificate::{Builder, CertType};
+
+    use super::*;
+
+    /// A client `KEXINIT` offering `host_key_algorithms`.
+    fn kex_init(host_key_algorithms: &str) -> Vec<u8> {
+        let mut buffer = vec![msg::KEXINIT];
+        buffer.extend([0; 16]);
+        for list in [
+            "curve25519-sha256",
+            host_key_algorithms,
+            "aes256-gcm@openssh.com",
+            "aes256-gcm@openssh.com",
+            "hmac-sha2-256",
+            "hmac-sha2-256",
+            "none",
+            "none",
+            "",
+            "",
+        ] {
+            list.encode(&mut buffer).unwrap();
+        }
+        buffer.push(0); // doesn't follow
+        0u32.encode(&mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn host_certificate() {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let ca = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut builder = Builder::new_with_random_nonce(
+            &mut OsRng,
+            key.public_key().key_data().clone(),
+            0,
+            u64::MAX >> 2,
+        )
+        .unwrap();
+        builder.cert_type(CertType::Host).unwrap();
+        builder.all_principals_valid().unwrap();
+        let certificate = builder.sign(&ca).unwrap();
+        let keys = [key];
+        let certificates = [certificate];
+
+        let algorithms: Vec<_> = Preferred::DEFAULT
+            .possible_host_key_algos_for_keys(&keys, &certificates)
+            .into_iter()
+            .map(|a| a.name)
+            .collect();
+        assert_eq!(
+            algorithms,
+            ["ssh-ed25519-cert-v01@openssh.com", "ssh-ed25519"]
+        );
+
+        let read = |client: &str, certificates: &[Certificate]| {
+            let names = Server::read_kex(
+                &kex_init(client),
+                &Preferred::DEFAULT,
+                Some((&keys, certificates)),
+            )
+            .unwrap();
+            assert_eq!(names.key, Algorithm::Ed25519);
+            names.key_is_certificate
+        };
+        assert!(read(
+            "ssh-ed25519-cert-v01@openssh.com,ssh-ed25519",
+            &certificates
+        ));
+        assert!(!read("ssh-ed25519", &certificates));
+        assert!(!read("ssh-ed25519-cert-v01@openssh.com,ssh-ed25519", &[]));
+    }
+}
@@ -34,8 +34,20 @@ impl OpenChannelMessage {
             "forwarded-streamlocal@openssh.com" => {
                 ChannelType::ForwardedStreamLocal(StreamLocalChannelInfo::decode(r)?)
             }
+            "direct-streamlocal@openssh.com" => {
+                ChannelType::DirectStreamLocal(StreamLocalChannelInfo::decode(r)?)
+            }
             "auth-agent@openssh.com" => ChannelType::AgentForward,
-            _ => ChannelType::Unknown { typ },
+            "tun@openssh.com" => {
+                let mode = map_err!(u32::decode(r))?;
+                let remote_unit = map_err!(u32::decode(r))?;
+                ChannelType::Tun { mode, remote_unit }
+            }
+            _ => {
+                let mut data = vec![0; r.remaining_len()];
+                map_err!(r.read(&mut data))?;
+                ChannelType::Unknown { typ, data }
+            }
         };
 
         Ok(Self {
@@ -101,9 +113,16 @@ pub enum ChannelType {
     DirectTcpip(TcpChannelInfo),
     ForwardedTcpIp(TcpChannelInfo),
     ForwardedStreamLocal(StreamLocalChannelInfo),
+    DirectStreamLocal(StreamLocalChannelInfo),
     AgentForward,
+    Tun {
+        mode: u32,
+        remote_unit: u32,
+    },
     Unknown {
         typ: String,
+        /// The type-specific data that follows the maximum packet size.
+        data: Vec<u8>,
     },
 }
 
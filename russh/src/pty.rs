@@ -132,3 +132,264 @@ impl Pty {
         }
     }
 }
+
+/// A list of terminal modes, as sent in a `pty-req` (RFC 4254, section 8).
+///
+/// ```
+/// use russh::{Pty, TerminalModes};
+/// let modes = TerminalModes::cooked().flag(Pty::ECHO, false).speed(38400);
+/// assert_eq!(modes.get(Pty::ECHO), Some(0));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TerminalModes(Vec<(Pty, u32)>);
+
+impl TerminalModes {
+    /// No modes: the server picks its defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Modes for programs handling every key themselves, like
+    /// `cfmakeraw`: no line editing, echo, signals or output processing.
+    pub fn raw() -> Self {
+        [
+            Pty::IGNPAR,
+            Pty::PARMRK,
+            Pty::ISTRIP,
+            Pty::INLCR,
+            Pty::IGNCR,
+            Pty::ICRNL,
+            Pty::IXON,
+            Pty::ISIG,
+            Pty::ICANON,
+            Pty::ECHO,
+            Pty::ECHOE,
+            Pty::ECHOK,
+            Pty::ECHONL,
+            Pty::IEXTEN,
+            Pty::OPOST,
+            Pty::PARENB,
+        ]
+        .into_iter()
+        .fold(Self::new(), |modes, mode| modes.flag(mode, false))
+        .flag(Pty::CS8, true)
+    }
+
+    /// The usual modes of an interactive terminal: line editing with the
+    /// standard control characters, echo, signals and `\n` to `\r\n`
+    /// translation.
+    pub fn cooked() -> Self {
+        Self::new()
+            .set(Pty::VINTR, 0x03)
+            .set(Pty::VQUIT, 0x1c)
+            .set(Pty::VERASE, 0x7f)
+            .set(Pty::VKILL, 0x15)
+            .set(Pty::VEOF, 0x04)
+            .set(Pty::VSTART, 0x11)
+            .set(Pty::VSTOP, 0x13)
+            .set(Pty::VSUSP, 0x1a)
+            .set(Pty::VREPRINT, 0x12)
+            .set(Pty::VWERASE, 0x17)
+            .set(Pty::VLNEXT, 0x16)
+            .flag(Pty::ICRNL, true)
+            .flag(Pty::IXON, true)
+            .flag(Pty::IUTF8, true)
+            .flag(Pty::ISIG, true)
+            .flag(Pty::ICANON, true)
+            .flag(Pty::ECHO, true)
+            .flag(Pty::ECHOE, true)
+            .flag(Pty::ECHOK, true)
+            .flag(Pty::IEXTEN, true)
+            .flag(Pty::ECHOCTL, true)
+            .flag(Pty::ECHOKE, true)
+            .flag(Pty::OPOST, true)
+            .flag(Pty::ONLCR, true)
+            .flag(Pty::CS8, true)
+    }
+
+    /// Set `mode` to `value`, replacing any previous value.
+    /// [`Pty::TTY_OP_END`] is ignored.
+    pub fn set(mut self, mode: Pty, value: u32) -> Self {
+        if mode == Pty::TTY_OP_END {
+            return self;
+        }
+        match self.0.iter_mut().find(|(m, _)| *m == mode) {
+            Some((_, v)) => *v = value,
+            None => self.0.push((mode, value)),
+        }
+        self
+    }
+
+    /// Enable or disable a boolean mode.
+    pub fn flag(self, mode: Pty, enabled: bool) -> Self {
+        self.set(mode, enabled as u32)
+    }
+
+    /// Set the input and output baud rates.
+    pub fn speed(self, baud: u32) -> Self {
+        self.set(Pty::TTY_OP_ISPEED, baud)
+            .set(Pty::TTY_OP_OSPEED, baud)
+    }
+
+    /// Remove `mode`, letting the server pick its value.
+    pub fn unset(mut self, mode: Pty) -> Self {
+        self.0.retain(|(m, _)| *m != mode);
+        self
+    }
+
+    pub fn get(&self, mode: Pty) -> Option<u32> {
+        self.0.iter().find(|(m, _)| *m == mode).map(|(_, v)| *v)
+    }
+
+    pub fn as_slice(&self) -> &[(Pty, u32)] {
+        &self.0
+    }
+
+    pub fn into_vec(self) -> Vec<(Pty, u32)> {
+        self.0
+    }
+
+    /// Parse the encoded terminal modes of a `pty-req`, skipping unknown
+    /// opcodes.
+    pub(crate) fn parse(mut bytes: &[u8]) -> Self {
+        let mut modes = Self::new();
+        while let [code, rest @ ..] = bytes {
+            // Opcodes 160 to 255 are not defined, and stop parsing.
+            if *code == 0 || *code >= 160 {
+                break;
+            }
+            let Some(value) = rest.get(..4).and_then(|v| <[u8; 4]>::try_from(v).ok()) else {
+                break;
+            };
+            let value = u32::from_be_bytes(value);
+            match Pty::from_u8(*code) {
+                Some(mode) => modes = modes.set(mode, value),
+                None => log::info!("pty-req: unknown pty code {:?}", code),
+            }
+            bytes = rest.get(4..).unwrap_or_default();
+        }
+        modes
+    }
+}
+
+impl std::ops::Deref for TerminalModes {
+    type Target = [(Pty, u32)];
+
+    fn deref(&self) -> &[(Pty, u32)] {
+        &self.0
+    }
+}
+
+impl From<&[(Pty, u32)]> for TerminalModes {
+    fn from(modes: &[(Pty, u32)]) -> Self {
+        modes
+            .iter()
+            .fold(Self::new(), |modes, &(mode, value)| modes.set(mode, value))
+    }
+}
+
+/// The parameters of a pseudo-terminal request, see
+/// [`Channel::request_pty_with`](crate::Channel::request_pty_with).
+///
+/// ```
+/// use russh::{PtyRequest, TerminalModes};
+/// let request = PtyRequest::new("xterm-256color")
+///     .size(120, 40)
+///     .modes(TerminalModes::raw());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PtyRequest {
+    pub term: String,
+    pub col_width: u32,
+    pub row_height: u32,
+    pub pix_width: u32,
+    pub pix_height: u32,
+    pub modes: TerminalModes,
+}
+
+impl PtyRequest {
+    /// A request for an 80x24 terminal of type `term`, with
+    /// [`TerminalModes::cooked`] modes.
+    pub fn new(term: impl Into<String>) -> Self {
+        PtyRequest {
+            term: term.into(),
+            col_width: 80,
+            row_height: 24,
+            pix_width: 0,
+            pix_height: 0,
+            modes: TerminalModes::cooked(),
+        }
+    }
+
+    /// Set the size in characters.
+    pub fn size(mut self, col_width: u32, row_height: u32) -> Self {
+        self.col_width = col_width;
+        self.row_height = row_height;
+        self
+    }
+
+    /// Set the size in pixels.
+    pub fn pixel_size(mut self, pix_width: u32, pix_height: u32) -> Self {
+        self.pix_width = pix_width;
+        self.pix_height = pix_height;
+        self
+    }
+
+    pub fn modes(mut self, modes: TerminalModes) -> Self {
+        self.modes = modes;
+        self
+    }
+}
+
+/// The size of a terminal, as sent by the client in `window-change`
+/// requests, see
+/// [`Handler::window_change`](crate::server::Handler::window_change).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WindowSize {
+    pub col_width: u32,
+    pub row_height: u32,
+    pub pix_width: u32,
+    pub pix_height: u32,
+}
+
+impl From<&PtyRequest> for WindowSize {
+    fn from(request: &PtyRequest) -> Self {
+        WindowSize {
+            col_width: request.col_width,
+            row_height: request.row_height,
+            pix_width: request.pix_width,
+            pix_height: request.pix_height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_terminal_modes() {
+        let modes = TerminalModes::new()
+            .set(Pty::VINTR, 3)
+            .flag(Pty::ECHO, false)
+            .speed(9600);
+        let mut bytes = Vec::new();
+        for &(code, value) in modes.iter() {
+            bytes.push(code as u8);
+            bytes.extend(value.to_be_bytes());
+        }
+        // An unknown opcode is skipped.
+        bytes.extend([20, 0, 0, 0, 1]);
+        bytes.push(Pty::TTY_OP_END as u8);
+        bytes.extend([Pty::ECHO as u8, 0, 0, 0, 1]);
+        assert_eq!(TerminalModes::parse(&bytes), modes);
+    }
+
+    #[test]
+    fn set_replaces() {
+        let modes = TerminalModes::raw().flag(Pty::ECHO, true);
+        assert_eq!(modes.get(Pty::ECHO), Some(1));
+        assert_eq!(modes.iter().filter(|(m, _)| *m == Pty::ECHO).count(), 1);
+        assert_eq!(modes.unset(Pty::ECHO).get(Pty::ECHO), None);
+    }
+}
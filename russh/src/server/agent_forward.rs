@@ -0,0 +1,135 @@
+//! Agent forwarding, as done by `sshd` with `AllowAgentForwarding yes`.
+//!
+//! Once a client sends an `auth-agent-req@openssh.com`, the server can
+//! reach its agent by opening `auth-agent@openssh.com` channels with
+//! [`Handle::channel_open_agent`]. An [`AgentForward`] does so for each
+//! connection to a Unix socket made for the session, which the
+//! processes of the session find with `SSH_AUTH_SOCK`:
+//!
+//! ```no_run
+//! # use russh::server::agent_forward::AgentForward;
+//! # use russh::server::process::Process;
+//! # use russh::server::{Handler, Session};
+//! # use russh::ChannelId;
+//! struct Client {
+//!     process: Process,
+//!     agent: Option<AgentForward>,
+//! }
+//!
+//! impl Handler for Client {
+//!     type Error = russh::Error;
+//!
+//!     async fn agent_request(
+//!         &mut self,
+//!         _: ChannelId,
+//!         session: &mut Session,
+//!     ) -> Result<bool, Self::Error> {
+//!         let agent = AgentForward::listen(session.handle())?;
+//!         self.process = std::mem::take(&mut self.process).env("SSH_AUTH_SOCK", agent.path());
+//!         self.agent = Some(agent);
+//!         Ok(true)
+//!     }
+//! }
+//! ```
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use rand::Rng;
+use tokio::net::UnixListener;
+
+use super::Handle;
+
+/// A Unix socket forwarding its connections to the agent of the client
+/// for as long as this guard is alive. The socket is removed when it is
+/// dropped.
+#[derive(Debug)]
+pub struct AgentForward {
+    path: PathBuf,
+    /// The directory made for the socket by [`AgentForward::listen`].
+    dir: Option<PathBuf>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for AgentForward {
+    fn drop(&mut self) {
+        debug!("stopping agent forward on {:?}", self.path);
+        self.task.abort();
+        let _ = std::fs::remove_file(&self.path);
+        if let Some(ref dir) = self.dir {
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
+
+impl AgentForward {
+    /// Listen on a socket named like the ones of `sshd`, in a new
+    /// directory of the temporary directory only accessible to the
+    /// user of the server.
+    pub fn listen(handle: Handle) -> io::Result<Self> {
+        let name: String = rand::thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(10)
+            .map(char::from)
+            .collect();
+        let dir = std::env::temp_dir().join(format!("ssh-{name}"));
+        let mut builder = std::fs::DirBuilder::new();
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(&dir)?;
+        let path = dir.join(format!("agent.{}", std::process::id()));
+        match Self::listen_at(handle, &path) {
+            Ok(mut forward) => {
+                forward.dir = Some(dir);
+                Ok(forward)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_dir(&dir);
+                Err(e)
+            }
+        }
+    }
+
+    /// Listen on a socket at `path`, which must not exist.
+    pub fn listen_at<P: AsRef<Path>>(handle: Handle, path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path)?;
+        debug!("agent forward on {path:?}");
+        Ok(AgentForward {
+            path,
+            dir: None,
+            task: tokio::spawn(accept(listener, handle)),
+        })
+    }
+
+    /// The path of the socket, for `SSH_AUTH_SOCK`.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+async fn accept(listener: UnixListener, handle: Handle) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                debug!("agent forward: {e:?}");
+                return;
+            }
+        };
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            let channel = match handle.channel_open_agent().await {
+                Ok(channel) => channel,
+                Err(e) => {
+                    debug!("agent forward: could not open a channel: {e:?}");
+                    return;
+                }
+            };
+            let mut channel_stream = channel.into_stream();
+            if let Err(e) = tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await {
+                debug!("agent forward: connection ended: {e:?}");
+            }
+        });
+    }
+}
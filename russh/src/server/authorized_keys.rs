@@ -0,0 +1,1022 @@
+//! Authorization of client keys against OpenSSH `authorized_keys` and
+//! `authorized_principals` files, and of user certificates against
+//! [trusted certificate authorities](TrustedUserCaKeys).
+//!
+//! The options of each line, described in `sshd(8)`, are parsed into a
+//! [`KeyOptions`]. Looking up a key with [`AuthorizedKeys::authorize`]
+//! checks the `from=` and `expiry-time=` options, and returns the
+//! options of the matching line, for the handler to enforce on the
+//! channel requests of the connection:
+//!
+//! ```no_run
+//! # use std::net::IpAddr;
+//! # use russh::server::authorized_keys::{AuthorizedKeys, KeyOptions};
+//! # use russh::server::{Auth, Handler, Session};
+//! # use russh::{ChannelId, Pty};
+//! struct Client {
+//!     authorized_keys: AuthorizedKeys,
+//!     address: Option<IpAddr>,
+//!     options: Option<KeyOptions>,
+//! }
+//!
+//! impl Handler for Client {
+//!     type Error = russh::Error;
+//!
+//!     async fn auth_publickey(
+//!         &mut self,
+//!         _user: &str,
+//!         key: &ssh_key::PublicKey,
+//!     ) -> Result<Auth, Self::Error> {
+//!         self.options = self.authorized_keys.authorize(key, self.address);
+//!         Ok(if self.options.is_some() {
+//!             Auth::Accept
+//!         } else {
+//!             Auth::reject()
+//!         })
+//!     }
+//!
+//!     async fn pty_request(
+//!         &mut self,
+//!         channel: ChannelId,
+//!         _: &str,
+//!         _: u32,
+//!         _: u32,
+//!         _: u32,
+//!         _: u32,
+//!         _: &[(Pty, u32)],
+//!         session: &mut Session,
+//!     ) -> Result<(), Self::Error> {
+//!         if self.options.as_ref().is_some_and(|o| o.pty) {
+//!             session.channel_success(channel)?;
+//!         } else {
+//!             session.channel_failure(channel)?;
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//! ```
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use ssh_key::certificate::CertType;
+use ssh_key::{Algorithm, Certificate, HashAlg, PublicKey};
+
+use super::source_filter::{canonical, IpRange};
+use crate::config_file::match_patterns;
+use crate::helpers::wildcard_match;
+use crate::keys::{parse_public_key_base64, Error};
+
+/// A `host:port` pair of the `permitopen=` and `permitlisten=` options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermittedAddress {
+    /// The host, or `*` for any host.
+    pub host: String,
+    /// The port, or `None` for any port (`*`).
+    pub port: Option<u32>,
+}
+
+impl PermittedAddress {
+    fn parse(s: &str, default_host: Option<&str>) -> Option<Self> {
+        let (host, port) = match s.rsplit_once(':') {
+            Some((host, port)) => (host, port),
+            None => (default_host?, s),
+        };
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        let port = match port {
+            "*" => None,
+            port => Some(port.parse().ok()?),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(PermittedAddress {
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    fn matches(&self, host: &str, port: u32) -> bool {
+        (self.host == "*" || self.host.eq_ignore_ascii_case(host))
+            && self.port.map_or(true, |p| p == port)
+    }
+}
+
+/// The options of an `authorized_keys` or `authorized_principals` line.
+///
+/// The permissions are granted by default, and removed by the `no-*`
+/// options or by `restrict`, which the options after it can grant
+/// again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyOptions {
+    /// `cert-authority`: the key is a CA trusted to sign user
+    /// certificates.
+    pub cert_authority: bool,
+    /// `command=`: the command run instead of the one requested.
+    pub command: Option<String>,
+    /// `environment=`: variables to set for the session, in order.
+    pub environment: Vec<(String, String)>,
+    /// `expiry-time=`: the key is not accepted after this time.
+    pub expiry_time: Option<SystemTime>,
+    /// `from=`: the comma-separated patterns the address of the client
+    /// must match.
+    pub from: Option<String>,
+    /// `permitopen=`: the destinations of local forwardings. If empty,
+    /// every destination is allowed.
+    pub permit_open: Vec<PermittedAddress>,
+    /// `permitlisten=`: the addresses of remote forwardings. If empty,
+    /// every address is allowed.
+    pub permit_listen: Vec<PermittedAddress>,
+    /// `principals=`: the principals accepted in certificates signed by
+    /// a `cert-authority` key.
+    pub principals: Option<Vec<String>>,
+    /// `tunnel=`: the only `tun` device the client may use.
+    pub tunnel: Option<u32>,
+    pub agent_forwarding: bool,
+    pub port_forwarding: bool,
+    pub pty: bool,
+    pub user_rc: bool,
+    pub x11_forwarding: bool,
+    /// Cleared by `no-touch-required`.
+    pub touch_required: bool,
+    /// `verify-required`: FIDO signatures must attest that the user was
+    /// verified.
+    pub verify_required: bool,
+}
+
+impl Default for KeyOptions {
+    fn default() -> Self {
+        KeyOptions {
+            cert_authority: false,
+            command: None,
+            environment: Vec::new(),
+            expiry_time: None,
+            from: None,
+            permit_open: Vec::new(),
+            permit_listen: Vec::new(),
+            principals: None,
+            tunnel: None,
+            agent_forwarding: true,
+            port_forwarding: true,
+            pty: true,
+            user_rc: true,
+            x11_forwarding: true,
+            touch_required: true,
+            verify_required: false,
+        }
+    }
+}
+
+impl KeyOptions {
+    /// Parse a comma-separated list of options, like
+    /// `no-pty,command="uptime"`.
+    pub fn parse(options: &str) -> Result<Self, String> {
+        let mut result = KeyOptions::default();
+        for (name, value) in split_options(options)? {
+            result.set(&name, value)?;
+        }
+        Ok(result)
+    }
+
+    fn set(&mut self, name: &str, value: Option<String>) -> Result<(), String> {
+        let name = name.to_ascii_lowercase();
+        let Some(value) = value else {
+            return self.set_flag(&name);
+        };
+        match name.as_str() {
+            "command" => self.command = Some(value),
+            "environment" => {
+                let Some((var, val)) = value.split_once('=') else {
+                    return Err(format!("invalid environment {value:?}"));
+                };
+                self.environment.push((var.to_string(), val.to_string()))
+            }
+            "expiry-time" => {
+                self.expiry_time = Some(
+                    parse_time(&value).ok_or_else(|| format!("invalid expiry-time {value:?}"))?,
+                )
+            }
+            "from" => self.from = Some(value),
+            "permitopen" => self.permit_open.push(
+                PermittedAddress::parse(&value, None)
+                    .ok_or_else(|| format!("invalid permitopen {value:?}"))?,
+            ),
+            "permitlisten" => self.permit_listen.push(
+                PermittedAddress::parse(&value, Some("localhost"))
+                    .ok_or_else(|| format!("invalid permitlisten {value:?}"))?,
+            ),
+            "principals" => self.principals = Some(value.split(',').map(String::from).collect()),
+            "tunnel" => {
+                self.tunnel = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("invalid tunnel {value:?}"))?,
+                )
+            }
+            _ => return Err(format!("invalid option {name:?}")),
+        }
+        Ok(())
+    }
+
+    fn set_flag(&mut self, name: &str) -> Result<(), String> {
+        let (flag, value) = match name {
+            "restrict" => {
+                self.agent_forwarding = false;
+                self.port_forwarding = false;
+                self.pty = false;
+                self.user_rc = false;
+                self.x11_forwarding = false;
+                return Ok(());
+            }
+            "cert-authority" => (&mut self.cert_authority, true),
+            "agent-forwarding" => (&mut self.agent_forwarding, true),
+            "no-agent-forwarding" => (&mut self.agent_forwarding, false),
+            "port-forwarding" => (&mut self.port_forwarding, true),
+            "no-port-forwarding" => (&mut self.port_forwarding, false),
+            "pty" => (&mut self.pty, true),
+            "no-pty" => (&mut self.pty, false),
+            "user-rc" => (&mut self.user_rc, true),
+            "no-user-rc" => (&mut self.user_rc, false),
+            "x11-forwarding" => (&mut self.x11_forwarding, true),
+            "no-x11-forwarding" => (&mut self.x11_forwarding, false),
+            "no-touch-required" => (&mut self.touch_required, false),
+            "verify-required" => (&mut self.verify_required, true),
+            _ => return Err(format!("invalid option {name:?}")),
+        };
+        *flag = value;
+        Ok(())
+    }
+
+    /// Whether these options allow a client connecting from `client`
+    /// at time `now`. If there is a `from=` option, clients of unknown
+    /// address are refused.
+    pub fn allows_client(&self, client: Option<IpAddr>, now: SystemTime) -> bool {
+        if self.expiry_time.is_some_and(|t| now > t) {
+            return false;
+        }
+        match (&self.from, client) {
+            (None, _) => true,
+            (Some(from), Some(client)) => match_address(client, from),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Whether a `direct-tcpip` channel to `host:port` is allowed.
+    pub fn permits_open(&self, host: &str, port: u32) -> bool {
+        self.port_forwarding
+            && (self.permit_open.is_empty()
+                || self.permit_open.iter().any(|p| p.matches(host, port)))
+    }
+
+    /// Whether a `tcpip-forward` request for `address:port` is allowed.
+    /// Like OpenSSH, `permitlisten` entries without a host only allow
+    /// forwardings on the loopback interface.
+    pub fn permits_listen(&self, address: &str, port: u32) -> bool {
+        if !self.port_forwarding {
+            return false;
+        }
+        if self.permit_listen.is_empty() {
+            return true;
+        }
+        let loopback = matches!(address, "localhost" | "127.0.0.1" | "::1");
+        self.permit_listen.iter().any(|p| {
+            p.matches(address, port)
+                || (loopback && p.host == "localhost" && p.port.map_or(true, |p| p == port))
+        })
+    }
+
+    /// The command to run for an `exec` or `shell` request: the forced
+    /// command if there is one, else the requested command.
+    pub fn command<'a>(&'a self, requested: Option<&'a str>) -> Option<&'a str> {
+        self.command.as_deref().or(requested)
+    }
+
+    /// Restrict these options with the critical options and extensions
+    /// of `certificate`, or return `None` if the certificate does not
+    /// allow `client` or conflicts with these options.
+    fn restrict(mut self, certificate: &Certificate, client: Option<IpAddr>) -> Option<Self> {
+        let extensions = certificate.extensions();
+        self.agent_forwarding &= extensions.contains_key("permit-agent-forwarding");
+        self.port_forwarding &= extensions.contains_key("permit-port-forwarding");
+        self.pty &= extensions.contains_key("permit-pty");
+        self.user_rc &= extensions.contains_key("permit-user-rc");
+        self.x11_forwarding &= extensions.contains_key("permit-X11-forwarding");
+        // Both the line and the certificate must waive user presence.
+        self.touch_required |= !extensions.contains_key("no-touch-required");
+        let critical = certificate.critical_options();
+        if critical.contains_key("verify-required") {
+            self.verify_required = true;
+        }
+        if let Some(command) = critical.get("force-command") {
+            match self.command {
+                Some(ref c) if c != command => {
+                    debug!("certificate and key options force different commands");
+                    return None;
+                }
+                _ => self.command = Some(command.clone()),
+            }
+        }
+        if let Some(source) = critical.get("source-address") {
+            if !client.is_some_and(|client| match_address(client, source)) {
+                debug!("certificate is not valid from {client:?}");
+                return None;
+            }
+        }
+        Some(self)
+    }
+}
+
+/// A line of an `authorized_keys` file.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Line number in the file, starting at 1.
+    pub line: usize,
+    pub options: KeyOptions,
+    pub key: PublicKey,
+}
+
+/// The contents of an `authorized_keys` file.
+#[derive(Debug, Clone, Default)]
+pub struct AuthorizedKeys {
+    entries: Vec<Entry>,
+}
+
+impl AuthorizedKeys {
+    /// Parse the contents of an `authorized_keys` file. Lines that
+    /// cannot be parsed, including lines with unknown options, are
+    /// skipped, as OpenSSH does.
+    pub fn parse(contents: &str) -> Self {
+        let mut authorized_keys = AuthorizedKeys::default();
+        for (n, line) in contents.lines().enumerate() {
+            authorized_keys.parse_line(n + 1, line);
+        }
+        authorized_keys
+    }
+
+    /// Load the file at `path`. A missing file is treated as empty.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut authorized_keys = AuthorizedKeys::default();
+        let f = match File::open(path) {
+            Ok(f) => BufReader::new(f),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(authorized_keys),
+            Err(e) => return Err(e.into()),
+        };
+        for (n, line) in f.lines().enumerate() {
+            authorized_keys.parse_line(n + 1, &line?);
+        }
+        Ok(authorized_keys)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    fn parse_line(&mut self, line: usize, contents: &str) {
+        let contents = contents.trim();
+        if contents.is_empty() || contents.starts_with('#') {
+            return;
+        }
+        let (options, rest) = match contents.split_whitespace().next() {
+            Some(first) if Algorithm::new(first).is_ok() => (KeyOptions::default(), contents),
+            _ => {
+                let (options, rest) = split_first_field(contents);
+                match KeyOptions::parse(options) {
+                    Ok(options) => (options, rest),
+                    Err(e) => {
+                        debug!("authorized_keys:{line}: {e}");
+                        return;
+                    }
+                }
+            }
+        };
+        let mut fields = rest.split_whitespace();
+        let (Some(_algorithm), Some(key)) = (fields.next(), fields.next()) else {
+            debug!("authorized_keys:{line}: missing fields");
+            return;
+        };
+        match parse_public_key_base64(key) {
+            Ok(key) => self.entries.push(Entry { line, options, key }),
+            Err(e) => debug!("authorized_keys:{line}: {e:?}"),
+        }
+    }
+
+    /// The options of the first line authorizing `key` for a client
+    /// connecting from `client`, or `None` if the key is not
+    /// authorized. Keys marked `cert-authority` only authorize
+    /// certificates.
+    pub fn authorize(&self, key: &PublicKey, client: Option<IpAddr>) -> Option<KeyOptions> {
+        let now = SystemTime::now();
+        let entry = self.entries.iter().find(|e| {
+            !e.options.cert_authority
+                && e.key.key_data() == key.key_data()
+                && e.options.allows_client(client, now)
+        })?;
+        Some(entry.options.clone())
+    }
+
+    /// The options authorizing `certificate` for `user`, connecting
+    /// from `client`, or `None` if it is not authorized.
+    ///
+    /// The certificate must be a valid user certificate signed by a key
+    /// marked `cert-authority`, and list one of the `principals=` of
+    /// that line, or `user` if there are none. The permissions of the
+    /// line are restricted by those of the certificate.
+    pub fn authorize_certificate(
+        &self,
+        certificate: &Certificate,
+        user: &str,
+        client: Option<IpAddr>,
+    ) -> Option<KeyOptions> {
+        let now = SystemTime::now();
+        let entry = self.entries.iter().find(|e| {
+            e.options.cert_authority
+                && e.key.key_data() == certificate.signature_key()
+                && e.options.allows_client(client, now)
+        })?;
+        if !is_valid_user_certificate(certificate, &entry.key, now) {
+            return None;
+        }
+        let principals = certificate.valid_principals();
+        let allowed = match entry.options.principals {
+            Some(ref allowed) => allowed.iter().any(|a| principals.contains(a)),
+            None => principals.iter().any(|p| p == user),
+        };
+        if !allowed {
+            debug!("certificate is not valid for {user:?}");
+            return None;
+        }
+        entry.options.clone().restrict(certificate, client)
+    }
+}
+
+/// A line of an `authorized_principals` file.
+#[derive(Debug, Clone)]
+pub struct Principal {
+    /// Line number in the file, starting at 1.
+    pub line: usize,
+    pub options: KeyOptions,
+    pub name: String,
+}
+
+/// The contents of an `authorized_principals` file, which lists the
+/// principals of the certificates accepted for a user, when the
+/// certificate authorities are configured separately.
+#[derive(Debug, Clone, Default)]
+pub struct AuthorizedPrincipals {
+    principals: Vec<Principal>,
+}
+
+impl AuthorizedPrincipals {
+    /// Parse the contents of an `authorized_principals` file. Lines
+    /// that cannot be parsed are skipped.
+    pub fn parse(contents: &str) -> Self {
+        let mut authorized_principals = AuthorizedPrincipals::default();
+        for (n, line) in contents.lines().enumerate() {
+            authorized_principals.parse_line(n + 1, line);
+        }
+        authorized_principals
+    }
+
+    /// Load the file at `path`. A missing file is treated as empty.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut authorized_principals = AuthorizedPrincipals::default();
+        let f = match File::open(path) {
+            Ok(f) => BufReader::new(f),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(authorized_principals),
+            Err(e) => return Err(e.into()),
+        };
+        for (n, line) in f.lines().enumerate() {
+            authorized_principals.parse_line(n + 1, &line?);
+        }
+        Ok(authorized_principals)
+    }
+
+    pub fn principals(&self) -> &[Principal] {
+        &self.principals
+    }
+
+    fn parse_line(&mut self, line: usize, contents: &str) {
+        let contents = contents.trim();
+        if contents.is_empty() || contents.starts_with('#') {
+            return;
+        }
+        let (first, rest) = split_first_field(contents);
+        let (options, name) = if rest.is_empty() {
+            (KeyOptions::default(), first)
+        } else {
+            match KeyOptions::parse(first) {
+                Ok(options) if options.cert_authority || options.principals.is_some() => {
+                    debug!("authorized_principals:{line}: invalid options");
+                    return;
+                }
+                Ok(options) => (options, rest),
+                Err(e) => {
+                    debug!("authorized_principals:{line}: {e}");
+                    return;
+                }
+            }
+        };
+        self.principals.push(Principal {
+            line,
+            options,
+            name: name.to_string(),
+        })
+    }
+
+    /// The options authorizing `certificate`, connecting from `client`,
+    /// or `None` if it is not authorized. The certificate must be a
+    /// valid user certificate signed by one of `trusted_cas`, and list
+    /// one of the principals of this file. The permissions of the line
+    /// are restricted by those of the certificate.
+    pub fn authorize(
+        &self,
+        certificate: &Certificate,
+        trusted_cas: &[PublicKey],
+        client: Option<IpAddr>,
+    ) -> Option<KeyOptions> {
+        let now = SystemTime::now();
+        let ca = trusted_cas
+            .iter()
+            .find(|ca| ca.key_data() == certificate.signature_key())?;
+        if !is_valid_user_certificate(certificate, ca, now) {
+            return None;
+        }
+        let principals = certificate.valid_principals();
+        let principal = self
+            .principals
+            .iter()
+            .find(|p| principals.contains(&p.name) && p.options.allows_client(client, now))?;
+        principal.options.clone().restrict(certificate, client)
+    }
+}
+
+/// The certificate authorities trusted to sign the certificates of all
+/// users, as the `TrustedUserCAKeys` option of `sshd_config`.
+///
+/// A certificate offered by a client, as passed to
+/// [`Handler::auth_openssh_certificate`](super::Handler::auth_openssh_certificate),
+/// is accepted if it is a valid user certificate signed by one of these
+/// authorities, which lists the requested user, or one of the principals
+/// of an [`AuthorizedPrincipals`] file, among its principals:
+///
+/// ```no_run
+/// # use std::net::IpAddr;
+/// # use russh::server::authorized_keys::{KeyOptions, TrustedUserCaKeys};
+/// # use russh::server::{Auth, Handler};
+/// struct Client {
+///     trusted_user_ca_keys: TrustedUserCaKeys,
+///     address: Option<IpAddr>,
+///     options: Option<KeyOptions>,
+/// }
+///
+/// impl Handler for Client {
+///     type Error = russh::Error;
+///
+///     async fn auth_openssh_certificate(
+///         &mut self,
+///         user: &str,
+///         certificate: &ssh_key::Certificate,
+///     ) -> Result<Auth, Self::Error> {
+///         self.options = self
+///             .trusted_user_ca_keys
+///             .authorize(certificate, user, self.address);
+///         Ok(if self.options.is_some() {
+///             Auth::Accept
+///         } else {
+///             Auth::reject()
+///         })
+///     }
+/// }
+/// ```
+///
+/// The options returned carry the restrictions of the certificate, and
+/// the `source-address` critical option is checked against the address
+/// of the client.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedUserCaKeys {
+    keys: Vec<PublicKey>,
+}
+
+impl TrustedUserCaKeys {
+    pub fn new(keys: Vec<PublicKey>) -> Self {
+        TrustedUserCaKeys { keys }
+    }
+
+    /// Parse a list of public keys, one per line, as in
+    /// `authorized_keys` files without options. Lines that cannot be
+    /// parsed are skipped.
+    pub fn parse(contents: &str) -> Self {
+        let mut keys = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(_algorithm), Some(key)) = (fields.next(), fields.next()) else {
+                debug!("trusted CA keys:{}: missing fields", n + 1);
+                continue;
+            };
+            match parse_public_key_base64(key) {
+                Ok(key) => keys.push(key),
+                Err(e) => debug!("trusted CA keys:{}: {e:?}", n + 1),
+            }
+        }
+        TrustedUserCaKeys { keys }
+    }
+
+    /// Load the file at `path`. A missing file is treated as empty.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Ok(Self::parse(&contents)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    /// The options authorizing `certificate` for `user`, connecting
+    /// from `client`, or `None` if it is not authorized. The certificate
+    /// must be a valid user certificate signed by one of these keys, and
+    /// list `user` among its principals.
+    pub fn authorize(
+        &self,
+        certificate: &Certificate,
+        user: &str,
+        client: Option<IpAddr>,
+    ) -> Option<KeyOptions> {
+        let ca = self
+            .keys
+            .iter()
+            .find(|ca| ca.key_data() == certificate.signature_key())?;
+        if !is_valid_user_certificate(certificate, ca, SystemTime::now()) {
+            return None;
+        }
+        if !certificate.valid_principals().iter().any(|p| p == user) {
+            debug!("certificate is not valid for {user:?}");
+            return None;
+        }
+        KeyOptions::default().restrict(certificate, client)
+    }
+
+    /// The options authorizing `certificate`, connecting from `client`,
+    /// with the principals of the `authorized_principals` file of the
+    /// user instead of the name of the user.
+    pub fn authorize_principals(
+        &self,
+        certificate: &Certificate,
+        principals: &AuthorizedPrincipals,
+        client: Option<IpAddr>,
+    ) -> Option<KeyOptions> {
+        principals.authorize(certificate, &self.keys, client)
+    }
+}
+
+fn is_valid_user_certificate(certificate: &Certificate, ca: &PublicKey, now: SystemTime) -> bool {
+    if certificate.cert_type() != CertType::User {
+        debug!("not a user certificate");
+        return false;
+    }
+    let now = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    if let Err(e) = certificate.validate_at(now, &[ca.fingerprint(HashAlg::Sha256)]) {
+        debug!("invalid certificate: {e}");
+        return false;
+    }
+    true
+}
+
+/// Split `s` at the first whitespace outside of double quotes.
+fn split_first_field(s: &str) -> (&str, &str) {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                let (first, rest) = s.split_at(i);
+                return (first, rest.trim_start());
+            }
+            _ => {}
+        }
+    }
+    (s, "")
+}
+
+/// Split comma-separated options into names and unquoted values.
+fn split_options(s: &str) -> Result<Vec<(String, Option<String>)>, String> {
+    let mut options = Vec::new();
+    let mut chars = s.chars().peekable();
+    while chars.peek().is_some() {
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ',') {
+            name.push(c);
+        }
+        if name.is_empty() {
+            return Err(format!("invalid options {s:?}"));
+        }
+        let mut value = None;
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next() != Some('"') {
+                return Err(format!("missing quote for option {name:?}"));
+            }
+            let mut v = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        v.push('"')
+                    }
+                    Some(c) => v.push(c),
+                    None => return Err(format!("missing end quote for option {name:?}")),
+                }
+            }
+            value = Some(v)
+        }
+        match chars.next() {
+            None | Some(',') => {}
+            Some(c) => return Err(format!("unexpected {c:?} after option {name:?}")),
+        }
+        options.push((name, value))
+    }
+    Ok(options)
+}
+
+/// Parse a `YYYYMMDD[HHMM[SS]]` time, in UTC.
+fn parse_time(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix(['Z', 'z']).unwrap_or(s);
+    if !s.bytes().all(|b| b.is_ascii_digit()) || ![8, 12, 14].contains(&s.len()) {
+        return None;
+    }
+    let field = |r: std::ops::Range<usize>| s.get(r).and_then(|f| f.parse::<i64>().ok());
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let hour = field(8..10).unwrap_or(0);
+    let minute = field(10..12).unwrap_or(0);
+    let second = field(12..14).unwrap_or(0);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+    // Days since the epoch, from Howard Hinnant's `days_from_civil`.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Match `address` against a comma-separated list of address patterns
+/// or CIDR ranges. A matching negated pattern (`!pattern`) overrides any
+/// positive match. IPv4 addresses mapped to IPv6, as accepted on
+/// dual-stack sockets, are matched as IPv4 addresses.
+pub(super) fn match_address(address: IpAddr, patterns: &str) -> bool {
+    let address = canonical(address);
+    match_patterns(patterns, |pattern| match_address_pattern(address, pattern))
+}
+
+fn match_address_pattern(address: IpAddr, pattern: &str) -> bool {
+    if pattern.contains('/') {
+        return pattern
+            .parse::<IpRange>()
+            .is_ok_and(|range| range.contains(address));
+    }
+    wildcard_match(address.to_string().as_bytes(), pattern.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used, clippy::indexing_slicing)]
+    use super::*;
+
+    const KEY_A: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIJdD7y3aLq454yWBdwLWbieU1ebz9/cu7/QEXn9OIeZJ";
+    const KEY_B: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIA6rWI3G1sz07DnfFlrouTcysQlj2P+jpNSOEWD9OJ3X";
+    const KEY_C: &str = "AAAAC3NzaC1lZDI1NTE5AAAAILIG2T/B0l0gaqj3puu510tu9N1OkQ4znY3LYuEm5zCF";
+
+    fn key(k: &str) -> PublicKey {
+        parse_public_key_base64(k).unwrap()
+    }
+
+    #[test]
+    fn options() {
+        let options = KeyOptions::parse(
+            r#"restrict,pty,command="echo \"hi\", there",environment="A=b=c",permitopen="[::1]:22",permitopen="*:80",permitlisten="8080",expiry-time="20300101""#,
+        )
+        .unwrap();
+        assert!(options.pty);
+        assert!(!options.port_forwarding && !options.agent_forwarding);
+        assert_eq!(options.command.as_deref(), Some(r#"echo "hi", there"#));
+        assert_eq!(options.environment, [("A".to_string(), "b=c".to_string())]);
+        assert_eq!(
+            options.expiry_time,
+            Some(UNIX_EPOCH + Duration::from_secs(1893456000))
+        );
+        assert_eq!(options.permit_open.len(), 2);
+        assert_eq!(options.permit_listen[0].host, "localhost");
+        assert!(
+            KeyOptions::parse("no-such-option").is_err(),
+            "unknown options are errors"
+        );
+        assert!(KeyOptions::parse(r#"command="unterminated"#).is_err());
+
+        let options = KeyOptions::parse(r#"permitopen="[::1]:22",permitopen="*:80""#).unwrap();
+        assert!(options.permits_open("::1", 22));
+        assert!(options.permits_open("example.com", 80));
+        assert!(!options.permits_open("example.com", 22));
+        let options = KeyOptions::parse(r#"permitlisten="8080",permitlisten="*:9000""#).unwrap();
+        assert!(options.permits_listen("127.0.0.1", 8080));
+        assert!(!options.permits_listen("0.0.0.0", 8080));
+        assert!(options.permits_listen("0.0.0.0", 9000));
+        assert!(!KeyOptions::parse("no-port-forwarding")
+            .unwrap()
+            .permits_open("example.com", 80));
+    }
+
+    #[test]
+    fn addresses() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(match_address(ip("192.168.1.7"), "192.168.0.0/16"));
+        assert!(match_address(ip("192.168.1.7"), "10.0.0.1,192.168.1.*"));
+        assert!(!match_address(
+            ip("192.168.1.7"),
+            "192.168.0.0/16,!192.168.1.0/24"
+        ));
+        assert!(match_address(ip("2001:db8::1"), "2001:db8::/32"));
+        assert!(!match_address(ip("2001:db9::1"), "2001:db8::/32"));
+        assert!(match_address(ip("10.1.2.3"), "0.0.0.0/0"));
+        // Peers of dual-stack listeners.
+        assert!(match_address(ip("::ffff:10.1.2.3"), "10.0.0.0/8"));
+        assert!(match_address(ip("::ffff:10.1.2.3"), "10.1.2.*"));
+        assert!(!match_address(ip("::ffff:203.0.113.5"), "*,!203.0.113.5"));
+    }
+
+    #[test]
+    fn authorize() {
+        let authorized_keys = AuthorizedKeys::parse(&format!(
+            "# comment\n\
+             \n\
+             ssh-ed25519 {KEY_A} alice@example.com\n\
+             from=\"10.0.0.0/8,!10.0.0.1\",no-pty ssh-ed25519 {KEY_B}\n\
+             expiry-time=\"20000101\" ssh-ed25519 {KEY_C}\n\
+             cert-authority ssh-ed25519 {KEY_C}\n\
+             unknown-option ssh-ed25519 {KEY_C}\n\
+             garbage\n"
+        ));
+        assert_eq!(authorized_keys.entries().len(), 4);
+
+        let options = authorized_keys.authorize(&key(KEY_A), None).unwrap();
+        assert_eq!(options, KeyOptions::default());
+
+        let client = "10.2.3.4".parse().ok();
+        let options = authorized_keys.authorize(&key(KEY_B), client).unwrap();
+        assert!(!options.pty);
+        assert!(authorized_keys.authorize(&key(KEY_B), None).is_none());
+        assert!(authorized_keys
+            .authorize(&key(KEY_B), "10.0.0.1".parse().ok())
+            .is_none());
+
+        // Expired, and the CA key does not authorize itself.
+        assert!(authorized_keys.authorize(&key(KEY_C), None).is_none());
+    }
+
+    #[test]
+    fn certificate() {
+        use rand_core::OsRng;
+        use ssh_key::PrivateKey;
+
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let ca = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut builder = ssh_key::certificate::Builder::new_with_random_nonce(
+            &mut OsRng,
+            key.public_key().key_data().clone(),
+            0,
+            u64::MAX >> 2,
+        )
+        .unwrap();
+        builder.cert_type(CertType::User).unwrap();
+        builder.valid_principal("alice").unwrap();
+        builder.extension("permit-pty", "").unwrap();
+        builder.critical_option("force-command", "uptime").unwrap();
+        let cert = builder.sign(&ca).unwrap();
+        let ca = ca.public_key().to_openssh().unwrap();
+
+        let authorized_keys = AuthorizedKeys::parse(&format!("cert-authority {ca}"));
+        let options = authorized_keys
+            .authorize_certificate(&cert, "alice", None)
+            .unwrap();
+        assert!(options.pty && !options.port_forwarding);
+        assert_eq!(options.command.as_deref(), Some("uptime"));
+        assert!(authorized_keys
+            .authorize_certificate(&cert, "bob", None)
+            .is_none());
+        // Plain keys do not authorize certificates.
+        let authorized_keys = AuthorizedKeys::parse(&ca);
+        assert!(authorized_keys
+            .authorize_certificate(&cert, "alice", None)
+            .is_none());
+        let authorized_keys =
+            AuthorizedKeys::parse(&format!("cert-authority,principals=\"bob,alice\" {ca}"));
+        assert!(authorized_keys
+            .authorize_certificate(&cert, "root", None)
+            .is_some());
+        let authorized_keys = AuthorizedKeys::parse(&format!("cert-authority,command=\"ls\" {ca}"));
+        assert!(authorized_keys
+            .authorize_certificate(&cert, "alice", None)
+            .is_none());
+
+        let ca = parse_public_key_base64(ca.split_whitespace().nth(1).unwrap()).unwrap();
+        let authorized_principals = AuthorizedPrincipals::parse("no-pty alice");
+        let options = authorized_principals
+            .authorize(&cert, &[ca.clone()], None)
+            .unwrap();
+        assert!(!options.pty);
+        assert!(AuthorizedPrincipals::parse("bob")
+            .authorize(&cert, &[ca], None)
+            .is_none());
+        assert!(authorized_principals.authorize(&cert, &[], None).is_none());
+    }
+
+    #[test]
+    fn trusted_user_ca_keys() {
+        use rand_core::OsRng;
+        use ssh_key::PrivateKey;
+
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let ca = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let certificate = |principal: &str, source: &str| {
+            let mut builder = ssh_key::certificate::Builder::new_with_random_nonce(
+                &mut OsRng,
+                key.public_key().key_data().clone(),
+                0,
+                u64::MAX >> 2,
+            )
+            .unwrap();
+            builder.cert_type(CertType::User).unwrap();
+            builder.valid_principal(principal).unwrap();
+            builder.critical_option("source-address", source).unwrap();
+            builder.critical_option("force-command", "uptime").unwrap();
+            builder.sign(&ca).unwrap()
+        };
+        let trusted = TrustedUserCaKeys::parse(&format!(
+            "# CA\n{}\nnot a key\n",
+            ca.public_key().to_openssh().unwrap()
+        ));
+        assert_eq!(trusted.keys().len(), 1);
+
+        let cert = certificate("alice", "192.0.2.0/24");
+        let client = "192.0.2.7".parse().ok();
+        let options = trusted.authorize(&cert, "alice", client).unwrap();
+        assert_eq!(options.command.as_deref(), Some("uptime"));
+        assert!(trusted.authorize(&cert, "bob", client).is_none());
+        assert!(trusted.authorize(&cert, "alice", None).is_none());
+        assert!(trusted
+            .authorize(&cert, "alice", "198.51.100.1".parse().ok())
+            .is_none());
+        assert!(TrustedUserCaKeys::default()
+            .authorize(&cert, "alice", client)
+            .is_none());
+
+        // The principal is looked up in the file of the user instead.
+        let cert = certificate("admins", "0.0.0.0/0");
+        assert!(trusted.authorize(&cert, "alice", client).is_none());
+        assert!(trusted
+            .authorize_principals(&cert, &AuthorizedPrincipals::parse("admins"), client)
+            .is_some());
+        assert!(trusted
+            .authorize_principals(&cert, &AuthorizedPrincipals::parse("alice"), client)
+            .is_none());
+    }
+
+    #[test]
+    fn principals() {
+        let authorized_principals =
+            AuthorizedPrincipals::parse("alice\ncommand=\"uptime\" bob\ncert-authority carol\n");
+        let principals = authorized_principals.principals();
+        assert_eq!(principals.len(), 2);
+        assert_eq!(principals[0].name, "alice");
+        assert_eq!(principals[1].name, "bob");
+        assert_eq!(principals[1].options.command.as_deref(), Some("uptime"));
+    }
+}
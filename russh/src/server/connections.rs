@@ -0,0 +1,111 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::metrics::ServerMetrics;
+use super::Config;
+
+/// A limit on the connections open at once in
+/// [`Server::run_on_listener`](super::Server::run_on_listener), whether
+/// they are authenticated or not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxConnections {
+    pub limit: usize,
+    /// What to do with the connections past the limit.
+    pub overflow: Overflow,
+}
+
+impl MaxConnections {
+    /// Close the new connections once there are `limit` of them.
+    pub fn new(limit: usize) -> Self {
+        MaxConnections {
+            limit,
+            overflow: Overflow::Refuse,
+        }
+    }
+}
+
+/// What to do with the connections past [`MaxConnections::limit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Close them as soon as they are accepted, before sending them
+    /// anything.
+    Refuse,
+    /// Stop accepting connections until one of them ends, leaving the
+    /// new ones in the backlog of the listener.
+    Queue,
+}
+
+/// The connections of a server, counted for [`Config::max_connections`]
+/// and [`ServerMetrics::active_connections`].
+pub(crate) struct Connections {
+    count: Arc<AtomicUsize>,
+    slots: Option<(Arc<Semaphore>, Overflow)>,
+    metrics: Option<Arc<dyn ServerMetrics>>,
+}
+
+impl Connections {
+    pub(crate) fn new(config: &Config) -> Self {
+        Connections {
+            count: Arc::new(AtomicUsize::new(0)),
+            slots: config
+                .max_connections
+                .map(|m| (Arc::new(Semaphore::new(m.limit)), m.overflow)),
+            metrics: config.metrics.clone(),
+        }
+    }
+
+    /// Wait until a new connection can be accepted, if the connections
+    /// past the limit are queued.
+    pub(crate) async fn ready(&self) -> Option<OwnedSemaphorePermit> {
+        match self.slots {
+            Some((ref slots, Overflow::Queue)) => slots.clone().acquire_owned().await.ok(),
+            _ => None,
+        }
+    }
+
+    /// Count a new connection until the returned guard is dropped, or
+    /// return `None` if it is past the limit. `permit` is the one
+    /// returned by [`Connections::ready`].
+    pub(crate) fn begin(&self, permit: Option<OwnedSemaphorePermit>) -> Option<Connection> {
+        let permit = match (permit, &self.slots) {
+            (Some(permit), _) => Some(permit),
+            (None, Some((slots, _))) => match slots.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    if let Some(ref metrics) = self.metrics {
+                        metrics.connection_refused();
+                    }
+                    return None;
+                }
+            },
+            (None, None) => None,
+        };
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(ref metrics) = self.metrics {
+            metrics.active_connections(count);
+        }
+        Some(Connection {
+            count: self.count.clone(),
+            metrics: self.metrics.clone(),
+            _permit: permit,
+        })
+    }
+}
+
+/// A connection, counted in [`Connections`] until this is dropped.
+pub(crate) struct Connection {
+    count: Arc<AtomicUsize>,
+    metrics: Option<Arc<dyn ServerMetrics>>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let count = self.count.fetch_sub(1, Ordering::Relaxed) - 1;
+        if let Some(ref metrics) = self.metrics {
+            metrics.active_connections(count);
+        }
+    }
+}
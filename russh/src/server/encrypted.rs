@@ -17,10 +17,9 @@ use std::cell::RefCell;
 use std::time::SystemTime;
 
 use auth::*;
-use byteorder::{BigEndian, ByteOrder};
 use bytes::Bytes;
 use cert::PublicKeyOrCertificate;
-use log::{debug, error, info, trace, warn};
+use log::{debug, error, trace, warn};
 use msg;
 use signature::Verifier;
 use ssh_encoding::{Decode, Encode, Reader};
@@ -28,11 +27,15 @@ use ssh_key::{PublicKey, Signature};
 use tokio::time::Instant;
 
 use super::super::*;
+use super::penalties::Progress;
 use super::*;
-use crate::helpers::NameList;
+use crate::channels::tun::TunMode;
+use crate::helpers::{host_key_proof_data, sign_with_hash_alg, NameList};
+use crate::keys::{HashAlg, PrivateKeyWithHashAlg};
 use crate::map_err;
 use crate::msg::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED;
 use crate::parsing::{ChannelOpenConfirmation, ChannelType, OpenChannelMessage};
+use crate::session::push_global_request_reply;
 
 impl Session {
     /// Returns false iff a request was rejected.
@@ -48,6 +51,71 @@ impl Session {
         &mut self,
         handler: &mut H,
         buf: &[u8],
+    ) -> Result<(), H::Error> {
+        let auth_attempt = self.metrics.as_ref().and_then(|_| self.auth_attempt(buf));
+        let result = self.process_encrypted_packet(handler, buf).await;
+        if let Some((method, failures)) = auth_attempt {
+            self.report_auth_attempt(method, failures);
+        }
+        result
+    }
+
+    /// The method of an authentication message, and the number of
+    /// failures before it.
+    fn auth_attempt(&self, buf: &[u8]) -> Option<(MethodKind, usize)> {
+        let Some(Encrypted {
+            state: EncryptedState::WaitingAuthRequest(ref auth_request),
+            ..
+        }) = self.common.encrypted
+        else {
+            return None;
+        };
+        let method = match buf.split_first()? {
+            (&msg::USERAUTH_REQUEST, mut r) => {
+                let _user = String::decode(&mut r).ok()?;
+                let _service = String::decode(&mut r).ok()?;
+                String::decode(&mut r).ok()?.parse().ok()?
+            }
+            // The messages of both methods share their numbers.
+            #[cfg(feature = "gssapi")]
+            (&(msg::USERAUTH_GSSAPI_TOKEN | msg::USERAUTH_GSSAPI_MIC), _)
+                if matches!(auth_request.current, Some(CurrentRequest::GssapiWithMic(_))) =>
+            {
+                MethodKind::GssapiWithMic
+            }
+            (&msg::USERAUTH_INFO_RESPONSE, _) => MethodKind::KeyboardInteractive,
+            _ => return None,
+        };
+        Some((method, auth_request.rejection_count))
+    }
+
+    /// Report the outcome of an authentication attempt to the metrics.
+    fn report_auth_attempt(&self, method: MethodKind, failures: usize) {
+        let (Some(metrics), Some(enc)) = (&self.metrics, &self.common.encrypted) else {
+            return;
+        };
+        match enc.state {
+            EncryptedState::WaitingAuthRequest(ref auth_request) => {
+                if auth_request.rejection_count > failures {
+                    metrics.auth_failed(method)
+                }
+            }
+            _ => metrics.auth_succeeded(method),
+        }
+    }
+
+    /// Record a request of the client on a session channel, starting
+    /// its recording if needed.
+    fn record_request(&mut self, channel: ChannelId, event: recording::Event<'_>) {
+        if let Some(ref mut recordings) = self.recordings {
+            recordings.request(&self.common.auth_user, channel, event)
+        }
+    }
+
+    async fn process_encrypted_packet<H: Handler + Send>(
+        &mut self,
+        handler: &mut H,
+        buf: &[u8],
     ) -> Result<(), H::Error> {
         let rejection_wait_until =
             tokio::time::Instant::now() + self.common.config.auth_rejection_time;
@@ -62,10 +130,29 @@ impl Session {
             rejection_wait_until
         };
 
+        // https://tools.ietf.org/html/rfc8308#section-2.4
+        let ext_info_expected = std::mem::take(&mut self.ext_info_expected);
+
         #[allow(clippy::unwrap_used)]
         let enc = self.common.encrypted.as_mut().unwrap();
         // If we've successfully read a packet.
         match (&mut enc.state, buf.split_first()) {
+            (_, Some((&msg::EXT_INFO, mut r))) if ext_info_expected => {
+                self.read_ext_info(handler, &mut r).await
+            }
+            (_, Some((&msg::EXT_INFO, _))) => {
+                debug!("ignoring EXT_INFO, not right after the first key exchange");
+                Ok(())
+            }
+            (_, Some((&msg::PING, mut r))) => {
+                let data = map_err!(Bytes::decode(&mut r))?;
+                trace!("ping, {} bytes", data.len());
+                push_packet!(enc.write, {
+                    enc.write.push(msg::PONG);
+                    map_err!(data.encode(&mut enc.write))?;
+                });
+                Ok(())
+            }
             (
                 EncryptedState::WaitingAuthServiceRequest {
                     ref mut accepted, ..
@@ -75,11 +162,18 @@ impl Session {
                 let request = map_err!(String::decode(&mut r))?;
                 debug!("request: {:?}", request);
                 if request == "ssh-userauth" {
-                    let auth_request = server_accept_service(
-                        handler.authentication_banner().await?,
+                    let banner = match handler.authentication_banner().await? {
+                        Some(banner) => Some(banner),
+                        None => self.common.config.auth_banner.clone(),
+                    };
+                    let mut auth_request = server_accept_service(
+                        banner,
                         self.common.config.as_ref().methods.clone(),
                         &mut enc.write,
                     )?;
+                    auth_request
+                        .required_methods
+                        .clone_from(&self.common.config.authentication_methods);
                     *accepted = true;
                     enc.state = EncryptedState::WaitingAuthRequest(auth_request);
                 }
@@ -89,19 +183,57 @@ impl Session {
                 enc.server_read_auth_request(
                     rejection_wait_until,
                     initial_none_rejection_wait_until,
+                    self.common.auth_attempts == 0,
                     handler,
                     buf,
                     &mut r,
                     &mut self.common.auth_user,
+                    &self.common.config,
                 )
                 .await?;
                 self.common.auth_attempts += 1;
                 if let EncryptedState::InitCompression = enc.state {
+                    self.startup = None;
                     enc.client_compression.init_decompress(&mut enc.decompress);
+                    self.advance_auth(Progress::Authenticated);
                     handler.auth_succeeded(self).await?;
                 }
+                self.check_auth_failures()?;
                 Ok(())
             }
+            #[cfg(feature = "gssapi")]
+            (
+                EncryptedState::WaitingAuthRequest(AuthRequest {
+                    current: Some(CurrentRequest::GssapiWithMic(_)),
+                    ..
+                }),
+                Some((
+                    &code @ (msg::USERAUTH_GSSAPI_TOKEN
+                    | msg::USERAUTH_GSSAPI_MIC
+                    | msg::USERAUTH_GSSAPI_ERRTOK),
+                    mut r,
+                )),
+            ) => {
+                let accepted = enc
+                    .server_read_gssapi(
+                        rejection_wait_until,
+                        handler,
+                        code,
+                        &self.common.auth_user,
+                        &mut r,
+                    )
+                    .await?;
+                if accepted {
+                    enc.state = EncryptedState::InitCompression;
+                    self.startup = None;
+                    enc.client_compression.init_decompress(&mut enc.decompress);
+                    self.advance_auth(Progress::Authenticated);
+                    handler.auth_succeeded(self).await
+                } else {
+                    self.check_auth_failures()?;
+                    Ok(())
+                }
+            }
             (
                 EncryptedState::WaitingAuthRequest(ref mut auth),
                 Some((&msg::USERAUTH_INFO_RESPONSE, mut r)),
@@ -117,9 +249,12 @@ impl Session {
                 .await?;
                 if resp {
                     enc.state = EncryptedState::InitCompression;
+                    self.startup = None;
                     enc.client_compression.init_decompress(&mut enc.decompress);
+                    self.advance_auth(Progress::Authenticated);
                     handler.auth_succeeded(self).await
                 } else {
+                    self.check_auth_failures()?;
                     Ok(())
                 }
             }
@@ -135,6 +270,47 @@ impl Session {
             _ => Ok(()),
         }
     }
+
+    async fn read_ext_info<H: Handler + Send>(
+        &mut self,
+        handler: &mut H,
+        r: &mut &[u8],
+    ) -> Result<(), H::Error> {
+        let n_extensions = map_err!(u32::decode(r))?;
+        debug!("Received EXT_INFO, {n_extensions:?} extensions");
+        for _ in 0..n_extensions {
+            let name = map_err!(String::decode(r))?;
+            let value = map_err!(Vec::<u8>::decode(r))?;
+            debug!("* {name:?} ({:?})", String::from_utf8_lossy(&value));
+            handler.extension_received(&name, &value, self).await?;
+            self.extension_info.insert(name, value);
+        }
+        Ok(())
+    }
+
+    /// Disconnect once the client has failed to authenticate
+    /// [`Config::max_auth_attempts`] times.
+    fn check_auth_failures(&mut self) -> Result<(), Error> {
+        let failures = match self.common.encrypted {
+            Some(Encrypted {
+                state: EncryptedState::WaitingAuthRequest(ref auth_request),
+                ..
+            }) => auth_request.rejection_count,
+            _ => return Ok(()),
+        };
+        if failures > 0 {
+            self.advance_auth(Progress::AuthFailed);
+        }
+        if failures >= self.common.config.max_auth_attempts {
+            info!("Too many authentication failures ({failures})");
+            self.common.disconnect(
+                Disconnect::NoMoreAuthMethodsAvailable,
+                "Too many authentication failures",
+                "",
+            )?;
+        }
+        Ok(())
+    }
 }
 
 fn server_accept_service(
@@ -160,19 +336,25 @@ fn server_accept_service(
         partial_success: false, // not used immediately anway.
         current: None,
         rejection_count: 0,
+        required_methods: Vec::new(),
+        completed_methods: Vec::new(),
+        completed_user: String::new(),
     })
 }
 
 impl Encrypted {
     /// Returns false iff the request was rejected.
+    #[allow(clippy::too_many_arguments)]
     async fn server_read_auth_request<H: Handler + Send>(
         &mut self,
         mut until: Instant,
         initial_auth_until: Instant,
+        first_attempt: bool,
         handler: &mut H,
         original_packet: &[u8],
         r: &mut &[u8],
         auth_user: &mut String,
+        config: &Config,
     ) -> Result<(), H::Error> {
         // https://tools.ietf.org/html/rfc4252#section-5
         let user = map_err!(String::decode(r))?;
@@ -194,8 +376,14 @@ impl Encrypted {
                 let password = map_err!(String::decode(r))?;
                 let auth = handler.auth_password(&user, &password).await?;
                 if let Auth::Accept = auth {
-                    server_auth_request_success(&mut self.write);
-                    self.state = EncryptedState::InitCompression;
+                    if accept_auth_request(
+                        &mut self.write,
+                        auth_request,
+                        &user,
+                        MethodKind::Password,
+                    )? {
+                        self.state = EncryptedState::InitCompression;
+                    }
                 } else {
                     auth_user.clear();
                     if let Auth::Reject {
@@ -208,8 +396,7 @@ impl Encrypted {
                     } else {
                         auth_request.methods.remove(MethodKind::Password);
                     }
-                    auth_request.partial_success = false;
-                    reject_auth_request(until, &mut self.write, auth_request).await?;
+                    reject_auth_request(until, &mut self.write, auth_request, false).await?;
                 }
                 Ok(())
             } else if method == "publickey" {
@@ -220,6 +407,19 @@ impl Encrypted {
                     auth_user,
                     &user,
                     r,
+                    config,
+                )
+                .await
+            } else if method == "hostbased" {
+                auth_user.clear();
+                auth_user.push_str(&user);
+                self.server_read_auth_request_hostbased(
+                    until,
+                    handler,
+                    original_packet,
+                    &user,
+                    r,
+                    config,
                 )
                 .await
             } else if method == "none" {
@@ -232,10 +432,14 @@ impl Encrypted {
 
                 until = initial_auth_until;
 
+                auth_user.clear();
+                auth_user.push_str(&user);
                 let auth = handler.auth_none(&user).await?;
                 if let Auth::Accept = auth {
-                    server_auth_request_success(&mut self.write);
-                    self.state = EncryptedState::InitCompression;
+                    if accept_auth_request(&mut self.write, auth_request, &user, MethodKind::None)?
+                    {
+                        self.state = EncryptedState::InitCompression;
+                    }
                 } else {
                     auth_user.clear();
                     if let Auth::Reject {
@@ -248,8 +452,8 @@ impl Encrypted {
                     } else {
                         auth_request.methods.remove(MethodKind::None);
                     }
-                    auth_request.partial_success = false;
-                    reject_auth_request(until, &mut self.write, auth_request).await?;
+                    reject_auth_request(until, &mut self.write, auth_request, first_attempt)
+                        .await?;
                 }
                 Ok(())
             } else if method == "keyboard-interactive" {
@@ -266,15 +470,26 @@ impl Encrypted {
                 debug!("{:?}", submethods);
                 auth_request.current = Some(CurrentRequest::KeyboardInteractive {
                     submethods: submethods.to_string(),
+                    prompts: 0,
                 });
                 let auth = handler
                     .auth_keyboard_interactive(&user, &submethods, None)
                     .await?;
-                if reply_userauth_info_response(until, auth_request, &mut self.write, auth).await? {
+                if reply_userauth_info_response(until, auth_request, &mut self.write, &user, auth)
+                    .await?
+                {
                     self.state = EncryptedState::InitCompression
                 }
                 Ok(())
             } else {
+                #[cfg(feature = "gssapi")]
+                if method == "gssapi-with-mic" {
+                    auth_user.clear();
+                    auth_user.push_str(&user);
+                    return self
+                        .server_read_auth_request_gssapi(until, handler, &user, r)
+                        .await;
+                }
                 // Other methods of the base specification are insecure or optional.
                 let auth_request = if let EncryptedState::WaitingAuthRequest(ref mut a) = self.state
                 {
@@ -282,7 +497,7 @@ impl Encrypted {
                 } else {
                     unreachable!()
                 };
-                reject_auth_request(until, &mut self.write, auth_request).await?;
+                reject_auth_request(until, &mut self.write, auth_request, false).await?;
                 Ok(())
             }
         } else {
@@ -297,6 +512,7 @@ thread_local! {
 }
 
 impl Encrypted {
+    #[allow(clippy::too_many_arguments)]
     async fn server_read_auth_request_pk<H: Handler + Send>(
         &mut self,
         until: Instant,
@@ -305,6 +521,7 @@ impl Encrypted {
         auth_user: &mut String,
         user: &str,
         r: &mut &[u8],
+        config: &Config,
     ) -> Result<(), H::Error> {
         let auth_request = if let EncryptedState::WaitingAuthRequest(ref mut a) = self.state {
             a
@@ -323,6 +540,23 @@ impl Encrypted {
             Ok(pk_or_cert) => {
                 debug!("is_real = {:?}", is_real);
 
+                if let Some(ref revoked_keys) = config.revoked_keys {
+                    let revoked = match pk_or_cert {
+                        PublicKeyOrCertificate::PublicKey { ref key, .. } => {
+                            revoked_keys.is_revoked(key)
+                        }
+                        PublicKeyOrCertificate::Certificate(ref cert) => {
+                            revoked_keys.is_certificate_revoked(cert)
+                        }
+                    };
+                    if revoked {
+                        warn!("Rejecting revoked key");
+                        auth_user.clear();
+                        reject_auth_request(until, &mut self.write, auth_request, false).await?;
+                        return Ok(());
+                    }
+                }
+
                 // Handle certificates specifically
                 let pubkey = match pk_or_cert {
                     PublicKeyOrCertificate::PublicKey { ref key, .. } => key.clone(),
@@ -331,14 +565,16 @@ impl Encrypted {
                         let now = SystemTime::now();
                         if now < cert.valid_after_time() || now > cert.valid_before_time() {
                             warn!("Certificate is expired or not yet valid");
-                            reject_auth_request(until, &mut self.write, auth_request).await?;
+                            reject_auth_request(until, &mut self.write, auth_request, false)
+                                .await?;
                             return Ok(());
                         }
 
                         // Verify the certificate’s signature
                         if cert.verify_signature().is_err() {
                             warn!("Certificate signature is invalid");
-                            reject_auth_request(until, &mut self.write, auth_request).await?;
+                            reject_auth_request(until, &mut self.write, auth_request, false)
+                                .await?;
                             return Ok(());
                         }
 
@@ -403,8 +639,14 @@ impl Encrypted {
                             };
 
                             if auth == Auth::Accept {
-                                server_auth_request_success(&mut self.write);
-                                self.state = EncryptedState::InitCompression;
+                                if accept_auth_request(
+                                    &mut self.write,
+                                    auth_request,
+                                    user,
+                                    MethodKind::PublicKey,
+                                )? {
+                                    self.state = EncryptedState::InitCompression;
+                                }
                             } else {
                                 if let Auth::Reject {
                                     proceed_with_methods: Some(proceed_with_methods),
@@ -414,16 +656,17 @@ impl Encrypted {
                                     auth_request.methods = proceed_with_methods;
                                     auth_request.partial_success = partial_success;
                                 }
-                                auth_request.partial_success = false;
                                 auth_user.clear();
-                                reject_auth_request(until, &mut self.write, auth_request).await?;
+                                reject_auth_request(until, &mut self.write, auth_request, false)
+                                    .await?;
                             }
                         } else {
                             debug!("signature wrong");
-                            reject_auth_request(until, &mut self.write, auth_request).await?;
+                            reject_auth_request(until, &mut self.write, auth_request, false)
+                                .await?;
                         }
                     } else {
-                        reject_auth_request(until, &mut self.write, auth_request).await?;
+                        reject_auth_request(until, &mut self.write, auth_request, false).await?;
                     }
                     Ok(())
                 } else {
@@ -461,7 +704,8 @@ impl Encrypted {
                             }
                             auth_request.partial_success = false;
                             auth_user.clear();
-                            reject_auth_request(until, &mut self.write, auth_request).await?;
+                            reject_auth_request(until, &mut self.write, auth_request, false)
+                                .await?;
                         }
                     }
                     Ok(())
@@ -472,7 +716,7 @@ impl Encrypted {
                 | ssh_key::Error::AlgorithmUnsupported { .. }
                 | ssh_key::Error::CertificateValidation { .. } => {
                     debug!("public key error: {e}");
-                    reject_auth_request(until, &mut self.write, auth_request).await?;
+                    reject_auth_request(until, &mut self.write, auth_request, false).await?;
                     Ok(())
                 }
                 e => Err(crate::Error::from(e).into()),
@@ -481,28 +725,318 @@ impl Encrypted {
     }
 }
 
+impl Encrypted {
+    /// Verify the signature of the client host key over the request,
+    /// before asking the handler whether the host and its user are
+    /// trusted.
+    async fn server_read_auth_request_hostbased<H: Handler + Send>(
+        &mut self,
+        until: Instant,
+        handler: &mut H,
+        original_packet: &[u8],
+        user: &str,
+        r: &mut &[u8],
+        config: &Config,
+    ) -> Result<(), H::Error> {
+        let auth_request = if let EncryptedState::WaitingAuthRequest(ref mut a) = self.state {
+            a
+        } else {
+            unreachable!()
+        };
+        let algo = map_err!(String::decode(r))?;
+        let key_blob = map_err!(Bytes::decode(r))?;
+        let client_host = map_err!(String::decode(r))?;
+        let client_user = map_err!(String::decode(r))?;
+        // The signature covers the whole request before it.
+        #[allow(clippy::indexing_slicing)] // length checked
+        let signed = &original_packet[..original_packet.len() - r.len()];
+        let signature = map_err!(Bytes::decode(r))?;
+
+        let key = match PublicKeyOrCertificate::decode(&algo, &key_blob) {
+            Ok(PublicKeyOrCertificate::PublicKey { key, .. }) => key,
+            Ok(PublicKeyOrCertificate::Certificate(_)) => {
+                debug!("hostbased: host certificates are not supported");
+                reject_auth_request(until, &mut self.write, auth_request, false).await?;
+                return Ok(());
+            }
+            Err(e) => {
+                debug!("hostbased: {e}");
+                reject_auth_request(until, &mut self.write, auth_request, false).await?;
+                return Ok(());
+            }
+        };
+        if config
+            .revoked_keys
+            .as_ref()
+            .is_some_and(|revoked_keys| revoked_keys.is_revoked(&key))
+        {
+            warn!("hostbased: rejecting revoked key");
+            reject_auth_request(until, &mut self.write, auth_request, false).await?;
+            return Ok(());
+        }
+        let Ok(signature) = Signature::decode(&mut &signature[..]) else {
+            debug!("hostbased: invalid signature");
+            reject_auth_request(until, &mut self.write, auth_request, false).await?;
+            return Ok(());
+        };
+        let session_id = self.session_id.as_ref();
+        let is_valid = SIGNATURE_BUFFER.with(|buf| {
+            let mut buf = buf.borrow_mut();
+            buf.clear();
+            map_err!(session_id.encode(&mut *buf))?;
+            buf.extend(signed);
+            Ok(Verifier::verify(&key, &buf, &signature).is_ok())
+        })?;
+        if !is_valid {
+            debug!("hostbased: signature wrong");
+            reject_auth_request(until, &mut self.write, auth_request, false).await?;
+            return Ok(());
+        }
+
+        // Clients send the fully qualified name of their host.
+        let client_host = client_host.strip_suffix('.').unwrap_or(&client_host);
+        let auth = handler
+            .auth_hostbased(user, client_host, &client_user, &key)
+            .await?;
+        if auth == Auth::Accept {
+            if accept_auth_request(&mut self.write, auth_request, user, MethodKind::HostBased)? {
+                self.state = EncryptedState::InitCompression;
+            }
+        } else {
+            if let Auth::Reject {
+                proceed_with_methods: Some(proceed_with_methods),
+                partial_success,
+            } = auth
+            {
+                auth_request.methods = proceed_with_methods;
+                auth_request.partial_success = partial_success;
+            }
+            reject_auth_request(until, &mut self.write, auth_request, false).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gssapi")]
+impl Encrypted {
+    /// Select the first mechanism of the client supported by the
+    /// acceptor of the handler.
+    async fn server_read_auth_request_gssapi<H: Handler + Send>(
+        &mut self,
+        until: Instant,
+        handler: &mut H,
+        user: &str,
+        r: &mut &[u8],
+    ) -> Result<(), H::Error> {
+        let auth_request = if let EncryptedState::WaitingAuthRequest(ref mut a) = self.state {
+            a
+        } else {
+            unreachable!()
+        };
+        let n = map_err!(u32::decode(r))?;
+        let mut mechanisms = Vec::new();
+        for _ in 0..n {
+            mechanisms.push(map_err!(Bytes::decode(r))?);
+        }
+        let Some(acceptor) = handler.gssapi_acceptor(user).await? else {
+            reject_auth_request(until, &mut self.write, auth_request, false).await?;
+            return Ok(());
+        };
+        let supported = acceptor.mechanisms();
+        let Some(mechanism) = mechanisms
+            .iter()
+            .find(|m| supported.iter().any(|s| s[..] == m[..]))
+        else {
+            debug!("no supported gssapi mechanism in {mechanisms:?}");
+            reject_auth_request(until, &mut self.write, auth_request, false).await?;
+            return Ok(());
+        };
+        push_packet!(self.write, {
+            self.write.push(msg::USERAUTH_GSSAPI_RESPONSE);
+            map_err!(mechanism.as_ref().encode(&mut self.write))?;
+        });
+        auth_request.current = Some(CurrentRequest::GssapiWithMic(gssapi::GssapiAcceptance {
+            acceptor,
+            mechanism: mechanism.to_vec(),
+            complete: false,
+        }));
+        Ok(())
+    }
+
+    /// Handle a message of an ongoing `gssapi-with-mic` authentication.
+    /// Returns whether the client is now authenticated.
+    async fn server_read_gssapi<H: Handler + Send>(
+        &mut self,
+        until: Instant,
+        handler: &mut H,
+        code: u8,
+        user: &str,
+        r: &mut &[u8],
+    ) -> Result<bool, H::Error> {
+        let EncryptedState::WaitingAuthRequest(ref mut auth_request) = self.state else {
+            return Err(Error::Inconsistent.into());
+        };
+        let Some(CurrentRequest::GssapiWithMic(ref mut gssapi)) = auth_request.current else {
+            return Err(Error::Inconsistent.into());
+        };
+        match code {
+            msg::USERAUTH_GSSAPI_TOKEN if !gssapi.complete => {
+                let token = map_err!(Bytes::decode(r))?;
+                match gssapi.acceptor.step(&gssapi.mechanism, &token) {
+                    Ok(step) => {
+                        if let Some(token) = step.token {
+                            push_packet!(self.write, {
+                                self.write.push(msg::USERAUTH_GSSAPI_TOKEN);
+                                map_err!(token.as_slice().encode(&mut self.write))?;
+                            })
+                        }
+                        gssapi.complete = step.complete;
+                    }
+                    Err(e) => {
+                        debug!("gssapi context failed: {e}");
+                        reject_auth_request(until, &mut self.write, auth_request, false).await?;
+                    }
+                }
+                Ok(false)
+            }
+            msg::USERAUTH_GSSAPI_MIC if gssapi.complete => {
+                let mic = map_err!(Bytes::decode(r))?;
+                let mut data = CryptoVec::new();
+                map_err!(self.session_id.as_ref().encode(&mut data))?;
+                data.push(msg::USERAUTH_REQUEST);
+                map_err!(user.encode(&mut data))?;
+                map_err!("ssh-connection".encode(&mut data))?;
+                map_err!("gssapi-with-mic".encode(&mut data))?;
+                let verified = gssapi.acceptor.verify_mic(&data, &mic).unwrap_or_else(|e| {
+                    debug!("gssapi mic verification failed: {e}");
+                    false
+                });
+                let auth = match gssapi.acceptor.source_name() {
+                    Some(principal) if verified => {
+                        debug!("gssapi principal {principal:?} for {user:?}");
+                        handler.auth_gssapi_with_mic(user, &principal).await?
+                    }
+                    _ => Auth::reject(),
+                };
+                match auth {
+                    Auth::Accept => {
+                        return Ok(accept_auth_request(
+                            &mut self.write,
+                            auth_request,
+                            user,
+                            MethodKind::GssapiWithMic,
+                        )?);
+                    }
+                    Auth::Reject {
+                        proceed_with_methods,
+                        partial_success,
+                    } => {
+                        if let Some(proceed_with_methods) = proceed_with_methods {
+                            auth_request.methods = proceed_with_methods;
+                        }
+                        auth_request.partial_success = partial_success;
+                    }
+                    _ => {}
+                }
+                reject_auth_request(until, &mut self.write, auth_request, false).await?;
+                Ok(false)
+            }
+            _ => {
+                debug!("unexpected gssapi message {code}");
+                reject_auth_request(until, &mut self.write, auth_request, false).await?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Send a failure, counted towards [`Config::max_auth_attempts`] unless
+/// it answers a `none` request opening the authentication, with which
+/// clients usually probe the methods, as in OpenSSH.
 async fn reject_auth_request(
     until: Instant,
     write: &mut CryptoVec,
     auth_request: &mut AuthRequest,
+    first_none: bool,
 ) -> Result<(), Error> {
     debug!("rejecting {:?}", auth_request);
+    send_auth_failure(write, auth_request)?;
+    if !first_none {
+        auth_request.rejection_count += 1;
+    }
+    debug!("packet pushed");
+    tokio::time::sleep_until(until).await;
+    Ok(())
+}
+
+fn send_auth_failure(write: &mut CryptoVec, auth_request: &mut AuthRequest) -> Result<(), Error> {
+    let methods = if auth_request.required_methods.is_empty() {
+        auth_request.methods.clone()
+    } else {
+        // Only the next methods of the lists are worth trying.
+        let mut next = MethodSet::empty();
+        for method in next_methods(auth_request) {
+            if auth_request.methods.contains(&method) {
+                next.push(method);
+            }
+        }
+        next
+    };
     push_packet!(write, {
         write.push(msg::USERAUTH_FAILURE);
-        NameList::from(&auth_request.methods).encode(write)?;
+        NameList::from(&methods).encode(write)?;
         write.push(auth_request.partial_success as u8);
     });
     auth_request.current = None;
-    auth_request.rejection_count += 1;
-    debug!("packet pushed");
-    tokio::time::sleep_until(until).await;
+    // Partial success only describes this reply.
+    auth_request.partial_success = false;
     Ok(())
 }
 
-fn server_auth_request_success(buffer: &mut CryptoVec) {
-    push_packet!(buffer, {
-        buffer.push(msg::USERAUTH_SUCCESS);
-    })
+/// The methods continuing the required lists after the completed
+/// methods.
+fn next_methods(auth_request: &AuthRequest) -> impl Iterator<Item = MethodKind> + '_ {
+    let completed = &auth_request.completed_methods;
+    auth_request
+        .required_methods
+        .iter()
+        .filter(|list| list.starts_with(completed))
+        .filter_map(|list| list.get(completed.len()).copied())
+}
+
+/// Record that `user` completed `method`, and return whether they are
+/// now authenticated, that is if there are no required methods or if
+/// they completed one of the lists. Otherwise, a partial success is sent.
+fn accept_auth_request(
+    write: &mut CryptoVec,
+    auth_request: &mut AuthRequest,
+    user: &str,
+    method: MethodKind,
+) -> Result<bool, Error> {
+    if !auth_request.required_methods.is_empty() {
+        if auth_request.completed_user != user {
+            auth_request.completed_user = user.to_string();
+            auth_request.completed_methods.clear();
+        }
+        if !next_methods(auth_request).any(|m| m == method) {
+            debug!("{method:?} is not one of the next required methods");
+            send_auth_failure(write, auth_request)?;
+            auth_request.rejection_count += 1;
+            return Ok(false);
+        }
+        auth_request.completed_methods.push(method);
+        let completed = &auth_request.completed_methods;
+        if !auth_request.required_methods.contains(completed) {
+            debug!("partial success after {completed:?}");
+            auth_request.partial_success = true;
+            send_auth_failure(write, auth_request)?;
+            return Ok(false);
+        }
+    }
+    push_packet!(write, {
+        write.push(msg::USERAUTH_SUCCESS);
+    });
+    Ok(true)
 }
 
 async fn read_userauth_info_response<H: Handler + Send, R: Reader>(
@@ -513,10 +1047,19 @@ async fn read_userauth_info_response<H: Handler + Send, R: Reader>(
     user: &str,
     r: &mut R,
 ) -> Result<bool, H::Error> {
-    if let Some(CurrentRequest::KeyboardInteractive { ref submethods }) = auth_request.current {
+    if let Some(CurrentRequest::KeyboardInteractive {
+        ref submethods,
+        prompts,
+    }) = auth_request.current
+    {
         let n = map_err!(u32::decode(r))?;
+        if n as usize != prompts {
+            debug!("{n} responses to {prompts} prompts");
+            reject_auth_request(until, write, auth_request, false).await?;
+            return Ok(false);
+        }
 
-        let mut responses = Vec::with_capacity(n as usize);
+        let mut responses = Vec::with_capacity(prompts);
         for _ in 0..n {
             responses.push(Bytes::decode(r).ok())
         }
@@ -524,12 +1067,12 @@ async fn read_userauth_info_response<H: Handler + Send, R: Reader>(
         let auth = handler
             .auth_keyboard_interactive(user, submethods, Some(Response(&mut responses.into_iter())))
             .await?;
-        let resp = reply_userauth_info_response(until, auth_request, write, auth)
+        let resp = reply_userauth_info_response(until, auth_request, write, user, auth)
             .await
             .map_err(H::Error::from)?;
         Ok(resp)
     } else {
-        reject_auth_request(until, write, auth_request).await?;
+        reject_auth_request(until, write, auth_request, false).await?;
         Ok(false)
     }
 }
@@ -538,12 +1081,12 @@ async fn reply_userauth_info_response(
     until: Instant,
     auth_request: &mut AuthRequest,
     write: &mut CryptoVec,
+    user: &str,
     auth: Auth,
 ) -> Result<bool, Error> {
     match auth {
         Auth::Accept => {
-            server_auth_request_success(write);
-            Ok(true)
+            accept_auth_request(write, auth_request, user, MethodKind::KeyboardInteractive)
         }
         Auth::Reject {
             proceed_with_methods,
@@ -553,7 +1096,7 @@ async fn reply_userauth_info_response(
                 auth_request.methods = proceed_with_methods;
             }
             auth_request.partial_success = partial_success;
-            reject_auth_request(until, write, auth_request).await?;
+            reject_auth_request(until, write, auth_request, false).await?;
             Ok(false)
         }
         Auth::Partial {
@@ -573,6 +1116,13 @@ async fn reply_userauth_info_response(
                 }
                 Ok::<(), crate::Error>(())
             })?;
+            if let Some(CurrentRequest::KeyboardInteractive {
+                prompts: ref mut sent,
+                ..
+            }) = auth_request.current
+            {
+                *sent = prompts.len();
+            }
             Ok(false)
         }
         Auth::UnsupportedMethod => unreachable!(),
@@ -597,6 +1147,12 @@ impl Session {
                     enc.channels.remove(&channel_num);
                 }
                 self.channels.remove(&channel_num);
+                if let Some(ref mut metrics) = self.metrics {
+                    metrics.channel_closed(channel_num);
+                }
+                if let Some(ref mut recordings) = self.recordings {
+                    recordings.close(channel_num);
+                }
                 debug!("handler.channel_close {:?}", channel_num);
                 handler.channel_close(channel_num, self).await
             }
@@ -618,7 +1174,11 @@ impl Session {
                 };
                 trace!("handler.data {:?} {:?}", ext, channel_num);
                 let data = map_err!(Bytes::decode(r))?;
+                self.channel_activity = true;
                 let target = self.target_window_size;
+                if let (None, Some(recordings)) = (ext, &mut self.recordings) {
+                    recordings.record(channel_num, recording::Event::Input(&data));
+                }
 
                 if let Some(ref mut enc) = self.common.encrypted {
                     if enc.adjust_window_size(channel_num, &data, target)? {
@@ -705,6 +1265,9 @@ impl Session {
                 } else {
                     error!("no channel for id {:?}", local_id);
                 }
+                if let Some(ref mut metrics) = self.metrics {
+                    metrics.channel_opened(local_id);
+                }
                 handler
                     .channel_open_confirmation(
                         local_id,
@@ -725,38 +1288,28 @@ impl Session {
                     }
                 }
                 match req_type.as_str() {
+                    "pty-req" | "x11-req" | "shell" | "exec" | "auth-agent-req@openssh.com"
+                        if self.sftp_only =>
+                    {
+                        debug!("refusing {req_type:?}, only SFTP is allowed");
+                        self.channel_failure(channel_num)?;
+                        Ok(())
+                    }
                     "pty-req" => {
                         let term = map_err!(String::decode(r))?;
                         let col_width = map_err!(u32::decode(r))?;
                         let row_height = map_err!(u32::decode(r))?;
                         let pix_width = map_err!(u32::decode(r))?;
                         let pix_height = map_err!(u32::decode(r))?;
-                        let mut modes = [(Pty::TTY_OP_END, 0); 130];
-                        let mut i = 0;
-                        {
-                            let mode_string = map_err!(Bytes::decode(r))?;
-                            while 5 * i < mode_string.len() {
-                                #[allow(clippy::indexing_slicing)] // length checked
-                                let code = mode_string[5 * i];
-                                if code == 0 {
-                                    break;
-                                }
-                                #[allow(clippy::indexing_slicing)] // length checked
-                                let num = BigEndian::read_u32(&mode_string[5 * i + 1..]);
-                                debug!("code = {:?}", code);
-                                if let Some(code) = Pty::from_u8(code) {
-                                    #[allow(clippy::indexing_slicing)] // length checked
-                                    if i < 130 {
-                                        modes[i] = (code, num);
-                                    } else {
-                                        error!("pty-req: too many pty codes");
-                                    }
-                                } else {
-                                    info!("pty-req: unknown pty code {:?}", code);
-                                }
-                                i += 1
-                            }
-                        }
+                        let modes = TerminalModes::parse(&map_err!(Bytes::decode(r))?);
+                        self.record_request(
+                            channel_num,
+                            recording::Event::Pty {
+                                term: &term,
+                                cols: col_width,
+                                rows: row_height,
+                            },
+                        );
 
                         if let Some(chan) = self.channels.get(&channel_num) {
                             let _ = chan
@@ -767,13 +1320,12 @@ impl Session {
                                     row_height,
                                     pix_width,
                                     pix_height,
-                                    terminal_modes: modes.into(),
+                                    terminal_modes: modes.to_vec(),
                                 })
                                 .await;
                         }
 
                         debug!("handler.pty_request {:?}", channel_num);
-                        #[allow(clippy::indexing_slicing)] // `modes` length checked
                         handler
                             .pty_request(
                                 channel_num,
@@ -782,7 +1334,7 @@ impl Session {
                                 row_height,
                                 pix_width,
                                 pix_height,
-                                &modes[0..i],
+                                &modes,
                                 self,
                             )
                             .await
@@ -820,6 +1372,12 @@ impl Session {
                         let env_variable = map_err!(String::decode(r))?;
                         let env_value = map_err!(String::decode(r))?;
 
+                        if !self.common.config.accepts_env(&env_variable) {
+                            debug!("refusing environment variable {env_variable:?}");
+                            self.channel_failure(channel_num)?;
+                            return Ok(());
+                        }
+
                         if let Some(chan) = self.channels.get(&channel_num) {
                             let _ = chan
                                 .send(ChannelMsg::SetEnv {
@@ -836,6 +1394,7 @@ impl Session {
                             .await
                     }
                     "shell" => {
+                        self.record_request(channel_num, recording::Event::Shell);
                         if let Some(chan) = self.channels.get(&channel_num) {
                             let _ = chan
                                 .send(ChannelMsg::RequestShell { want_reply: true })
@@ -852,16 +1411,16 @@ impl Session {
                         }
                         debug!("handler.agent_request {:?}", channel_num);
 
-                        let response = handler.agent_request(channel_num, self).await?;
-                        if response {
-                            self.request_success()
+                        if handler.agent_request(channel_num, self).await? {
+                            self.channel_success(channel_num)?;
                         } else {
-                            self.request_failure()
+                            self.channel_failure(channel_num)?;
                         }
                         Ok(())
                     }
                     "exec" => {
                         let req = map_err!(Bytes::decode(r))?;
+                        self.record_request(channel_num, recording::Event::Exec(&req));
                         if let Some(chan) = self.channels.get(&channel_num) {
                             let _ = chan
                                 .send(ChannelMsg::Exec {
@@ -875,6 +1434,12 @@ impl Session {
                     }
                     "subsystem" => {
                         let name = map_err!(String::decode(r))?;
+                        if self.sftp_only && name != "sftp" {
+                            debug!("refusing subsystem {name:?}, only SFTP is allowed");
+                            self.channel_failure(channel_num)?;
+                            return Ok(());
+                        }
+                        self.record_request(channel_num, recording::Event::Subsystem(&name));
 
                         if let Some(chan) = self.channels.get(&channel_num) {
                             let _ = chan
@@ -892,6 +1457,15 @@ impl Session {
                         let row_height = map_err!(u32::decode(r))?;
                         let pix_width = map_err!(u32::decode(r))?;
                         let pix_height = map_err!(u32::decode(r))?;
+                        if let Some(ref mut recordings) = self.recordings {
+                            recordings.record(
+                                channel_num,
+                                recording::Event::WindowChange {
+                                    cols: col_width,
+                                    rows: row_height,
+                                },
+                            );
+                        }
 
                         if let Some(chan) = self.channels.get(&channel_num) {
                             let _ = chan
@@ -905,16 +1479,13 @@ impl Session {
                         }
 
                         debug!("handler.window_change {:?}", channel_num);
-                        handler
-                            .window_change_request(
-                                channel_num,
-                                col_width,
-                                row_height,
-                                pix_width,
-                                pix_height,
-                                self,
-                            )
-                            .await
+                        let size = WindowSize {
+                            col_width,
+                            row_height,
+                            pix_width,
+                            pix_height,
+                        };
+                        handler.window_change(channel_num, size, self).await
                     }
                     "signal" => {
                         let signal = Sig::from_name(&map_err!(String::decode(r))?);
@@ -928,6 +1499,25 @@ impl Session {
                         debug!("handler.signal {:?} {:?}", channel_num, signal);
                         handler.signal(channel_num, signal, self).await
                     }
+                    "break" => {
+                        let length_ms = map_err!(u32::decode(r))?;
+                        if let Some(chan) = self.channels.get(&channel_num) {
+                            chan.send(ChannelMsg::Break {
+                                want_reply: wants_reply != 0,
+                                length_ms,
+                            })
+                            .await
+                            .unwrap_or(())
+                        }
+                        debug!("handler.break_request {:?} {:?}", channel_num, length_ms);
+                        let length = std::time::Duration::from_millis(length_ms.into());
+                        if handler.break_request(channel_num, length, self).await? {
+                            self.channel_success(channel_num)?;
+                        } else {
+                            self.channel_failure(channel_num)?;
+                        }
+                        Ok(())
+                    }
                     x => {
                         warn!("unknown channel request {x}");
                         self.channel_failure(channel_num)?;
@@ -939,6 +1529,15 @@ impl Session {
                 let req_type = map_err!(String::decode(r))?;
                 self.common.wants_reply = map_err!(u8::decode(r))? != 0;
                 match req_type.as_str() {
+                    "tcpip-forward" | "streamlocal-forward@openssh.com" if self.sftp_only => {
+                        debug!("refusing {req_type:?}, only SFTP is allowed");
+                        if self.common.wants_reply {
+                            if let Some(ref mut enc) = self.common.encrypted {
+                                push_packet!(enc.write, enc.write.push(msg::REQUEST_FAILURE))
+                            }
+                        }
+                        Ok(())
+                    }
                     "tcpip-forward" => {
                         let address = map_err!(String::decode(r))?;
                         let port = map_err!(u32::decode(r))?;
@@ -947,11 +1546,14 @@ impl Session {
                         let result = handler
                             .tcpip_forward(&address, &mut returned_port, self)
                             .await?;
+                        if !self.common.wants_reply {
+                            return Ok(());
+                        }
                         if let Some(ref mut enc) = self.common.encrypted {
                             if result {
                                 push_packet!(enc.write, {
                                     enc.write.push(msg::REQUEST_SUCCESS);
-                                    if self.common.wants_reply && port == 0 && returned_port != 0 {
+                                    if port == 0 && returned_port != 0 {
                                         map_err!(returned_port.encode(&mut enc.write))?;
                                     }
                                 })
@@ -966,6 +1568,9 @@ impl Session {
                         let port = map_err!(u32::decode(r))?;
                         debug!("handler.cancel_tcpip_forward {:?} {:?}", address, port);
                         let result = handler.cancel_tcpip_forward(&address, port, self).await?;
+                        if !self.common.wants_reply {
+                            return Ok(());
+                        }
                         if let Some(ref mut enc) = self.common.encrypted {
                             if result {
                                 push_packet!(enc.write, enc.write.push(msg::REQUEST_SUCCESS))
@@ -981,6 +1586,9 @@ impl Session {
                         let result = handler
                             .streamlocal_forward(&server_socket_path, self)
                             .await?;
+                        if !self.common.wants_reply {
+                            return Ok(());
+                        }
                         if let Some(ref mut enc) = self.common.encrypted {
                             if result {
                                 push_packet!(enc.write, enc.write.push(msg::REQUEST_SUCCESS))
@@ -996,6 +1604,9 @@ impl Session {
                         let result = handler
                             .cancel_streamlocal_forward(&socket_path, self)
                             .await?;
+                        if !self.common.wants_reply {
+                            return Ok(());
+                        }
                         if let Some(ref mut enc) = self.common.encrypted {
                             if result {
                                 push_packet!(enc.write, enc.write.push(msg::REQUEST_SUCCESS))
@@ -1005,11 +1616,63 @@ impl Session {
                         }
                         Ok(())
                     }
-                    _ => {
+                    "hostkeys-prove-00@openssh.com" => {
+                        let mut keys = Vec::new();
+                        while !r.is_finished() {
+                            keys.push(map_err!(Bytes::decode(r))?);
+                        }
+                        let signatures = self.prove_host_keys(&keys)?;
                         if let Some(ref mut enc) = self.common.encrypted {
-                            push_packet!(enc.write, {
-                                enc.write.push(msg::REQUEST_FAILURE);
-                            });
+                            match signatures {
+                                Some(signatures) => push_packet!(enc.write, {
+                                    enc.write.push(msg::REQUEST_SUCCESS);
+                                    for signature in signatures {
+                                        map_err!(signature.encode(&mut enc.write))?;
+                                    }
+                                }),
+                                None => {
+                                    push_packet!(enc.write, enc.write.push(msg::REQUEST_FAILURE))
+                                }
+                            }
+                        }
+                        Ok(())
+                    }
+                    "no-more-sessions@openssh.com" => {
+                        debug!("no more sessions");
+                        self.no_more_sessions = true;
+                        if self.common.wants_reply {
+                            if let Some(ref mut enc) = self.common.encrypted {
+                                push_packet!(enc.write, enc.write.push(msg::REQUEST_SUCCESS))
+                            }
+                        }
+                        Ok(())
+                    }
+                    name if self.common.config.global_request_replies.contains_key(name) => {
+                        let success =
+                            self.common.config.global_request_replies.get(name) == Some(&true);
+                        trace!("global request {name:?}, replying {success:?}");
+                        if self.common.wants_reply {
+                            if let Some(ref mut enc) = self.common.encrypted {
+                                map_err!(push_global_request_reply(
+                                    &mut enc.write,
+                                    success.then(Vec::new)
+                                ))?;
+                            }
+                        }
+                        Ok(())
+                    }
+                    _ => {
+                        let mut payload = vec![0; r.remaining_len()];
+                        map_err!(r.read(&mut payload))?;
+                        debug!("handler.global_request {:?}", req_type);
+                        let want_reply = self.common.wants_reply;
+                        let reply = handler
+                            .global_request(&req_type, want_reply, &payload, self)
+                            .await?;
+                        if want_reply {
+                            if let Some(ref mut enc) = self.common.encrypted {
+                                map_err!(push_global_request_reply(&mut enc.write, reply))?;
+                            }
                         }
                         Ok(())
                     }
@@ -1063,6 +1726,11 @@ impl Session {
                     Some(GlobalRequestResponse::CancelTcpIpForward(return_channel)) => {
                         let _ = return_channel.send(true);
                     }
+                    Some(GlobalRequestResponse::Generic(return_channel)) => {
+                        let mut data = vec![0; r.remaining_len()];
+                        map_err!(r.read(&mut data))?;
+                        let _ = return_channel.send(Some(data));
+                    }
                     _ => {
                         error!("Received global request failure for unknown request!")
                     }
@@ -1081,6 +1749,9 @@ impl Session {
                     Some(GlobalRequestResponse::CancelTcpIpForward(return_channel)) => {
                         let _ = return_channel.send(false);
                     }
+                    Some(GlobalRequestResponse::Generic(return_channel)) => {
+                        let _ = return_channel.send(None);
+                    }
                     _ => {
                         error!("Received global request failure for unknown request!")
                     }
@@ -1094,6 +1765,18 @@ impl Session {
         }
     }
 
+    /// Whether [`Config::max_sessions`] session channels are open.
+    fn too_many_sessions(&mut self) -> bool {
+        let Some(max) = self.common.config.max_sessions else {
+            return false;
+        };
+        if let Some(ref enc) = self.common.encrypted {
+            self.session_channels
+                .retain(|id| enc.channels.contains_key(id));
+        }
+        self.session_channels.len() >= max
+    }
+
     async fn server_handle_channel_open<H: Handler + Send, R: Reader>(
         &mut self,
         handler: &mut H,
@@ -1129,14 +1812,45 @@ impl Session {
             channel_params.recipient_maximum_packet_size,
             channel_params.recipient_window_size,
             self.common.config.channel_buffer_size,
+            self.sender.limiters.clone(),
         );
 
         match &msg.typ {
+            ChannelType::Session if self.no_more_sessions => {
+                warn!("session channel opened after no-more-sessions, rejecting");
+                self.finalize_channel_open(&msg, channel_params, false)?;
+                Ok(false)
+            }
+            typ if self.sftp_only && !matches!(typ, ChannelType::Session) => {
+                debug!("refusing a {typ:?} channel, only SFTP is allowed");
+                if let Some(ref mut enc) = self.common.encrypted {
+                    msg.fail(
+                        &mut enc.write,
+                        msg::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                        b"Only SFTP is allowed",
+                    )?;
+                }
+                Ok(false)
+            }
+            ChannelType::Session if self.too_many_sessions() => {
+                debug!("refusing a session channel past MaxSessions");
+                if let Some(ref mut enc) = self.common.encrypted {
+                    msg.fail(
+                        &mut enc.write,
+                        msg::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                        b"Too many sessions",
+                    )?;
+                }
+                Ok(false)
+            }
             ChannelType::Session => {
                 let mut result = handler.channel_open_session(channel, self).await;
                 if let Ok(allowed) = &mut result {
                     self.channels.insert(sender_channel, reference);
                     self.finalize_channel_open(&msg, channel_params, *allowed)?;
+                    if *allowed {
+                        self.session_channels.insert(sender_channel);
+                    }
                 }
                 result
             }
@@ -1187,6 +1901,16 @@ impl Session {
                 }
                 result
             }
+            ChannelType::DirectStreamLocal(d) => {
+                let mut result = handler
+                    .channel_open_direct_streamlocal(channel, &d.socket_path, self)
+                    .await;
+                if let Ok(allowed) = &mut result {
+                    self.channels.insert(sender_channel, reference);
+                    self.finalize_channel_open(&msg, channel_params, *allowed)?;
+                }
+                result
+            }
             ChannelType::ForwardedStreamLocal(_) => {
                 if let Some(ref mut enc) = self.common.encrypted {
                     msg.fail(
@@ -1207,16 +1931,72 @@ impl Session {
                 }
                 Ok(false)
             }
-            ChannelType::Unknown { typ } => {
-                debug!("unknown channel type: {typ}");
-                if let Some(ref mut enc) = self.common.encrypted {
-                    msg.unknown_type(&mut enc.write)?;
+            ChannelType::Tun { mode, remote_unit } => match TunMode::from_u32(*mode) {
+                Some(mode) => {
+                    let mut result = handler
+                        .channel_open_tun(channel, mode, *remote_unit, self)
+                        .await;
+                    if let Ok(allowed) = &mut result {
+                        self.channels.insert(sender_channel, reference);
+                        self.finalize_channel_open(&msg, channel_params, *allowed)?;
+                    }
+                    result
                 }
-                Ok(false)
+                None => {
+                    if let Some(ref mut enc) = self.common.encrypted {
+                        msg.fail(
+                            &mut enc.write,
+                            msg::SSH_OPEN_ADMINISTRATIVELY_PROHIBITED,
+                            b"Unsupported tunnel mode",
+                        )?;
+                    }
+                    Ok(false)
+                }
+            },
+            ChannelType::Unknown { typ, data } => {
+                let allowed = handler
+                    .channel_open_unknown(channel, typ, data, self)
+                    .await?;
+                if allowed {
+                    self.channels.insert(sender_channel, reference);
+                    self.finalize_channel_open(&msg, channel_params, true)?;
+                } else {
+                    debug!("unknown channel type: {typ}");
+                    if let Some(ref mut enc) = self.common.encrypted {
+                        msg.unknown_type(&mut enc.write)?;
+                    }
+                }
+                Ok(allowed)
             }
         }
     }
 
+    /// Sign each of `keys` with the matching host key, for a
+    /// `hostkeys-prove-00@openssh.com` request. `None` if one of them is
+    /// not a host key of this server.
+    fn prove_host_keys(&self, keys: &[Bytes]) -> Result<Option<Vec<Vec<u8>>>, Error> {
+        let Some(ref enc) = self.common.encrypted else {
+            return Ok(None);
+        };
+        let mut signatures = Vec::new();
+        for blob in keys {
+            let Some(key) = self
+                .common
+                .config
+                .keys
+                .iter()
+                .find(|k| k.public_key().to_bytes().is_ok_and(|b| b == blob.as_ref()))
+            else {
+                debug!("asked to prove an unknown host key");
+                return Ok(None);
+            };
+            let key = PrivateKeyWithHashAlg::new(Arc::new(key.clone()), Some(HashAlg::Sha512));
+            let data = host_key_proof_data(&enc.session_id, blob)?;
+            signatures.push(sign_with_hash_alg(&key, &data)?);
+        }
+        Ok(Some(signatures))
+    }
+
     fn finalize_channel_open(
         &mut self,
         open: &OpenChannelMessage,
@@ -1231,6 +2011,9 @@ impl Session {
                     channel.sender_window_size,
                     channel.sender_maximum_packet_size,
                 )?;
+                if let Some(ref mut metrics) = self.metrics {
+                    metrics.channel_opened(channel.sender_channel);
+                }
                 enc.channels.insert(channel.sender_channel, channel);
             } else {
                 open.fail(
@@ -0,0 +1,285 @@
+//! Remote port forwarding, as done by `sshd` for `ssh -R`.
+//!
+//! [`RemoteForwards`] binds the addresses of the `tcpip-forward`
+//! requests the handler accepts, opens a `forwarded-tcpip` channel to
+//! the client for each connection, and closes the listeners on
+//! `cancel-tcpip-forward`, or when it is dropped with the handler at the
+//! end of the session. Unix sockets are forwarded the same way, with
+//! `streamlocal-forward@openssh.com` requests and
+//! `forwarded-streamlocal@openssh.com` channels:
+//!
+//! ```no_run
+//! # use russh::server::forward::RemoteForwards;
+//! # use russh::server::{Handler, Session};
+//! struct Client {
+//!     forwards: RemoteForwards,
+//! }
+//!
+//! impl Handler for Client {
+//!     type Error = russh::Error;
+//!
+//!     async fn tcpip_forward(
+//!         &mut self,
+//!         address: &str,
+//!         port: &mut u32,
+//!         session: &mut Session,
+//!     ) -> Result<bool, Self::Error> {
+//!         Ok(self.forwards.listen(session.handle(), address, port).await.is_ok())
+//!     }
+//!
+//!     async fn cancel_tcpip_forward(
+//!         &mut self,
+//!         address: &str,
+//!         port: u32,
+//!         _: &mut Session,
+//!     ) -> Result<bool, Self::Error> {
+//!         Ok(self.forwards.cancel(address, port))
+//!     }
+//!
+//!     async fn streamlocal_forward(
+//!         &mut self,
+//!         socket_path: &str,
+//!         session: &mut Session,
+//!     ) -> Result<bool, Self::Error> {
+//!         Ok(self.forwards.listen_unix(session.handle(), socket_path).is_ok())
+//!     }
+//!
+//!     async fn cancel_streamlocal_forward(
+//!         &mut self,
+//!         socket_path: &str,
+//!         _: &mut Session,
+//!     ) -> Result<bool, Self::Error> {
+//!         Ok(self.forwards.cancel_unix(socket_path))
+//!     }
+//! }
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use log::debug;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use super::Handle;
+
+/// The listeners of the forwarding requests of a session, which are
+/// closed when this is dropped.
+#[derive(Debug, Default)]
+pub struct RemoteForwards {
+    gateway_ports: bool,
+    unlink_sockets: bool,
+    listeners: HashMap<(String, u32), Vec<JoinHandle<()>>>,
+    unix_listeners: HashMap<String, JoinHandle<()>>,
+}
+
+impl Drop for RemoteForwards {
+    fn drop(&mut self) {
+        for task in self.listeners.values().flatten() {
+            task.abort();
+        }
+        for (path, task) in self.unix_listeners.drain() {
+            task.abort();
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl RemoteForwards {
+    /// Forwards that only listen on the loopback interface, like
+    /// `GatewayPorts no`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind the addresses requested by the client, like `GatewayPorts
+    /// clientspecified`, instead of the loopback addresses. The empty
+    /// address then listens on all interfaces, `0.0.0.0` and `::` on
+    /// all the interfaces of one protocol, and `localhost` on the
+    /// loopback interface.
+    pub fn gateway_ports(mut self, enabled: bool) -> Self {
+        self.gateway_ports = enabled;
+        self
+    }
+
+    /// Remove an existing file before listening on a Unix socket, like
+    /// `StreamLocalBindUnlink yes`.
+    pub fn unlink_sockets(mut self, enabled: bool) -> Self {
+        self.unlink_sockets = enabled;
+        self
+    }
+
+    /// Listen for a `tcpip-forward` request of `address` and `port`. If
+    /// `port` is 0, it is set to the port allocated by the system.
+    pub async fn listen(
+        &mut self,
+        handle: Handle,
+        address: &str,
+        port: &mut u32,
+    ) -> io::Result<()> {
+        let requested_port = u16::try_from(*port)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid port"))?;
+        let mut bound_port = requested_port;
+        let mut tasks = Vec::new();
+        let mut error = None;
+        for ip in self.bind_addresses(address).await? {
+            let listener = match TcpListener::bind(SocketAddr::new(ip, bound_port)).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    // Some of the addresses of a name or of all
+                    // interfaces may be unavailable, like IPv6.
+                    debug!("tcpip-forward: could not listen on {ip}: {e}");
+                    error = Some(e);
+                    continue;
+                }
+            };
+            // All the listeners of a request share the port allocated
+            // to the first one.
+            bound_port = listener.local_addr()?.port();
+            tasks.push(tokio::spawn(accept(
+                listener,
+                handle.clone(),
+                address.to_string(),
+                bound_port as u32,
+            )));
+        }
+        if tasks.is_empty() {
+            return Err(error.unwrap_or_else(|| io::ErrorKind::AddrNotAvailable.into()));
+        }
+        debug!("tcpip-forward of {address}:{bound_port}");
+        *port = bound_port as u32;
+        if let Some(previous) = self.listeners.insert((address.to_string(), *port), tasks) {
+            previous.iter().for_each(JoinHandle::abort);
+        }
+        Ok(())
+    }
+
+    /// Close the listeners of a `cancel-tcpip-forward` request, and
+    /// return whether there were any.
+    pub fn cancel(&mut self, address: &str, port: u32) -> bool {
+        match self.listeners.remove(&(address.to_string(), port)) {
+            Some(tasks) => {
+                tasks.iter().for_each(JoinHandle::abort);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Listen for a `streamlocal-forward@openssh.com` request of
+    /// `socket_path`. The socket is removed once the forward is
+    /// cancelled.
+    #[cfg(unix)]
+    pub fn listen_unix(&mut self, handle: Handle, socket_path: &str) -> io::Result<()> {
+        if self.unix_listeners.contains_key(socket_path) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        if self.unlink_sockets {
+            match std::fs::remove_file(socket_path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        let listener = tokio::net::UnixListener::bind(socket_path)?;
+        debug!("streamlocal-forward of {socket_path:?}");
+        let task = tokio::spawn(accept_unix(listener, handle, socket_path.to_string()));
+        self.unix_listeners.insert(socket_path.to_string(), task);
+        Ok(())
+    }
+
+    /// Close the listener of a `cancel-streamlocal-forward@openssh.com`
+    /// request, and return whether there was one.
+    pub fn cancel_unix(&mut self, socket_path: &str) -> bool {
+        match self.unix_listeners.remove(socket_path) {
+            Some(task) => {
+                task.abort();
+                let _ = std::fs::remove_file(socket_path);
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn bind_addresses(&self, address: &str) -> io::Result<Vec<IpAddr>> {
+        let loopback = vec![
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ];
+        if !self.gateway_ports {
+            return Ok(loopback);
+        }
+        Ok(match address {
+            "" | "*" => vec![
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            ],
+            "localhost" => loopback,
+            address => match address.parse() {
+                Ok(ip) => vec![ip],
+                Err(_) => tokio::net::lookup_host((address, 0))
+                    .await?
+                    .map(|a| a.ip())
+                    .collect(),
+            },
+        })
+    }
+}
+
+async fn accept(listener: TcpListener, handle: Handle, address: String, port: u32) {
+    loop {
+        let (mut stream, originator) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                debug!("tcpip-forward: {e:?}");
+                return;
+            }
+        };
+        let handle = handle.clone();
+        let address = address.clone();
+        tokio::spawn(async move {
+            let channel = match handle
+                .channel_open_forwarded_tcpip_from(address, port, originator)
+                .await
+            {
+                Ok(channel) => channel,
+                Err(e) => {
+                    debug!("tcpip-forward: could not open a channel: {e:?}");
+                    return;
+                }
+            };
+            let mut channel_stream = channel.into_stream();
+            if let Err(e) = tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await {
+                debug!("tcpip-forward: connection from {originator} ended: {e:?}");
+            }
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn accept_unix(listener: tokio::net::UnixListener, handle: Handle, socket_path: String) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                debug!("streamlocal-forward: {e:?}");
+                return;
+            }
+        };
+        let handle = handle.clone();
+        let socket_path = socket_path.clone();
+        tokio::spawn(async move {
+            let channel = match handle.channel_open_forwarded_streamlocal(socket_path).await {
+                Ok(channel) => channel,
+                Err(e) => {
+                    debug!("streamlocal-forward: could not open a channel: {e:?}");
+                    return;
+                }
+            };
+            let mut channel_stream = channel.into_stream();
+            if let Err(e) = tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await {
+                debug!("streamlocal-forward: connection ended: {e:?}");
+            }
+        });
+    }
+}
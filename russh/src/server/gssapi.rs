@@ -0,0 +1,60 @@
+//! `gssapi-with-mic` authentication on the server ([RFC 4462](https://tools.ietf.org/html/rfc4462)).
+//!
+//! As on the client, Russh does not link to a GSSAPI library itself.
+//! When a client starts the method, [`Handler::gssapi_acceptor`](super::Handler::gssapi_acceptor)
+//! provides a [`GssapiAcceptor`], usually a thin wrapper around
+//! `gss_accept_sec_context` of a binding such as `libgssapi`. Once the
+//! context is established and the integrity code of the client is
+//! verified against the session identifier,
+//! [`Handler::auth_gssapi_with_mic`](super::Handler::auth_gssapi_with_mic)
+//! decides whether the authenticated principal may log in as the
+//! requested user.
+//!
+//! The method is only offered to clients once it is added to
+//! [`Config::methods`](super::Config::methods), with
+//! [`MethodKind::GssapiWithMic`](crate::MethodKind::GssapiWithMic).
+
+use std::fmt;
+
+pub use crate::client::gssapi::{GssapiStep, KRB5_MECHANISM};
+
+/// A server-side GSSAPI security context, accepting the exchange
+/// initiated by the client (`gss_accept_sec_context`).
+pub trait GssapiAcceptor: Send + Sync {
+    /// The mechanisms accepted, as DER-encoded OIDs (including the tag
+    /// and length bytes). The first one offered by the client is used.
+    fn mechanisms(&self) -> Vec<Vec<u8>>;
+
+    /// Advance the context for `mechanism` with `input`, a token
+    /// received from the client. The token produced, if any, is sent
+    /// back to the client.
+    fn step(&mut self, mechanism: &[u8], input: &[u8]) -> Result<GssapiStep, crate::Error>;
+
+    /// Check the integrity code `mic` of `message` (`gss_verify_mic`),
+    /// once the context is established.
+    fn verify_mic(&mut self, message: &[u8], mic: &[u8]) -> Result<bool, crate::Error>;
+
+    /// The name of the authenticated client, such as a Kerberos
+    /// principal, once the context is established.
+    fn source_name(&self) -> Option<String>;
+}
+
+/// The state of an ongoing `gssapi-with-mic` authentication.
+#[doc(hidden)]
+pub struct GssapiAcceptance {
+    pub(crate) acceptor: Box<dyn GssapiAcceptor>,
+    /// The mechanism selected for the client.
+    pub(crate) mechanism: Vec<u8>,
+    /// Whether the security context is established, and the client
+    /// can send its integrity code.
+    pub(crate) complete: bool,
+}
+
+impl fmt::Debug for GssapiAcceptance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GssapiAcceptance")
+            .field("mechanism", &self.mechanism)
+            .field("complete", &self.complete)
+            .finish_non_exhaustive()
+    }
+}
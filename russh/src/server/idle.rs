@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+/// A limit on the time a session can stay without any data sent or
+/// received on its channels, as [`Config::idle_timeout`](super::Config::idle_timeout).
+///
+/// Unlike [`Config::inactivity_timeout`](super::Config::inactivity_timeout),
+/// it is not reset by keepalives or other transport messages, only by
+/// the data of the channels, in either direction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdleTimeout {
+    /// The time without channel data after which [`IdleTimeout::action`]
+    /// is taken.
+    pub duration: Duration,
+    pub action: IdleAction,
+}
+
+impl IdleTimeout {
+    /// Disconnect the sessions idle for `duration`.
+    pub fn new(duration: Duration) -> Self {
+        IdleTimeout {
+            duration,
+            action: IdleAction::Disconnect,
+        }
+    }
+}
+
+/// What to do with an idle session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdleAction {
+    /// Send this message on the standard error of the open channels,
+    /// again after each idle period.
+    Warn(String),
+    /// Close the open channels, keeping the connection.
+    CloseChannels,
+    /// Disconnect the client.
+    Disconnect,
+}
@@ -12,7 +12,9 @@ use crate::helpers::sign_with_hash_alg;
 use crate::kex::dh::biguint_to_mpint;
 use crate::kex::{KexAlgorithm, KexAlgorithmImplementor, KexCause, KEXES};
 use crate::keys::key::PrivateKeyWithHashAlg;
-use crate::negotiation::{is_key_compatible_with_algo, Names, Select};
+use crate::negotiation::{
+    certificate_of, is_key_compatible_with_algo, kex_init_host_key_algorithms, Names, Select,
+};
 use crate::{msg, negotiation};
 
 thread_local! {
@@ -42,6 +44,8 @@ pub(crate) struct ServerKex {
     cause: KexCause,
     state: ServerKexState,
     config: Arc<Config>,
+    /// The algorithms of the connection, usually [`Config::preferred`].
+    preferred: Preferred,
 }
 
 impl Debug for ServerKex {
@@ -69,6 +73,7 @@ impl Debug for ServerKex {
 impl ServerKex {
     pub fn new(
         config: Arc<Config>,
+        preferred: Preferred,
         client_sshid: &[u8],
         server_sshid: &SshId,
         cause: KexCause,
@@ -76,6 +81,7 @@ impl ServerKex {
         let exchange = Exchange::new(client_sshid, server_sshid.as_kex_hash_bytes());
         Self {
             config,
+            preferred,
             exchange,
             cause,
             state: ServerKexState::Created,
@@ -84,7 +90,7 @@ impl ServerKex {
 
     pub fn kexinit(&mut self, output: &mut PacketWriter) -> Result<(), Error> {
         self.exchange.server_kex_init =
-            negotiation::write_kex(&self.config.preferred, output, Some(self.config.as_ref()))?;
+            negotiation::write_kex(&self.preferred, output, Some(self.config.as_ref()))?;
 
         Ok(())
     }
@@ -112,8 +118,8 @@ impl ServerKex {
                     self.exchange.client_kex_init.extend(&input.buffer);
                     negotiation::Server::read_kex(
                         &input.buffer,
-                        &self.config.preferred,
-                        Some(&self.config.keys),
+                        &self.preferred,
+                        Some((&self.config.keys, &self.config.host_certificates)),
                     )?
                 };
                 debug!("negotiated: {names:?}");
@@ -145,6 +151,7 @@ impl ServerKex {
                     return Ok(KexProgress::Done {
                         newkeys,
                         server_host_key: None,
+                        host_key_signature: None,
                     });
                 }
 
@@ -236,33 +243,54 @@ impl ServerKex {
                     .client_ephemeral
                     .extend(&Bytes::decode(&mut r).map_err(Into::into)?);
 
+                let client_algorithms =
+                    kex_init_host_key_algorithms(&self.exchange.client_kex_init);
+                let selected = handler
+                    .select_host_key(&names.key, names.key_is_certificate, &client_algorithms)
+                    .await?;
+
                 let exchange = &mut self.exchange;
                 kex.server_dh(exchange, &input.buffer)?;
 
-                let Some(matching_key_index) = self
-                    .config
-                    .keys
-                    .iter()
-                    .position(|key| is_key_compatible_with_algo(key, &names.key))
-                else {
-                    debug!("we don't have a host key of type {:?}", names.key);
-                    return Err(Error::UnknownKey.into());
+                let is_usable = |key: &PrivateKey| {
+                    is_key_compatible_with_algo(key, &names.key)
+                        && (!names.key_is_certificate
+                            || certificate_of(key, &self.config.host_certificates).is_some())
                 };
 
                 // Look up the key we'll be using to sign the exchange hash
-                #[allow(clippy::indexing_slicing)] // key index checked
-                let key = &self.config.keys[matching_key_index];
+                let key = match selected {
+                    Some(ref key) if is_usable(key) => key,
+                    Some(_) => {
+                        debug!("the selected host key can't be used for {:?}", names.key);
+                        return Err(Error::UnknownKey.into());
+                    }
+                    None => {
+                        let Some(key) = self.config.keys.iter().find(|key| is_usable(key)) else {
+                            debug!("we don't have a host key of type {:?}", names.key);
+                            return Err(Error::UnknownKey.into());
+                        };
+                        key
+                    }
+                };
                 let signature_hash_alg = match &names.key {
                     Algorithm::Rsa { hash } => *hash,
                     _ => None,
                 };
 
+                // The certificate replaces the key, in the exchange hash too.
+                let host_key = match certificate_of(key, &self.config.host_certificates) {
+                    Some(certificate) if names.key_is_certificate => certificate.to_bytes(),
+                    _ => key.public_key().to_bytes(),
+                }
+                .map_err(Error::from)?;
+
                 let hash = HASH_BUF.with(|buffer| {
                     let mut buffer = buffer.borrow_mut();
                     buffer.clear();
 
                     let mut pubkey_vec = CryptoVec::new();
-                    key.public_key().to_bytes()?.encode(&mut pubkey_vec)?;
+                    host_key.encode(&mut pubkey_vec)?;
 
                     let hash = kex.compute_exchange_hash(&pubkey_vec, exchange, &mut buffer)?;
 
@@ -283,7 +311,7 @@ impl ServerKex {
                         false => &msg::KEX_ECDH_REPLY,
                     }
                     .encode(w)?;
-                    key.public_key().to_bytes()?.encode(w)?;
+                    host_key.encode(w)?;
                     exchange.server_ephemeral.encode(w)?;
                     signature.encode(w)?;
                     Ok(())
@@ -328,6 +356,7 @@ impl ServerKex {
                 Ok(KexProgress::Done {
                     newkeys,
                     server_host_key: None,
+                    host_key_signature: None,
                 })
             }
         }
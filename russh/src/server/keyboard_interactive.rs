@@ -0,0 +1,180 @@
+//! Multi-round "keyboard-interactive" authentication, as used for
+//! one-time passwords and other second factors.
+//!
+//! [`Handler::auth_keyboard_interactive`](super::Handler::auth_keyboard_interactive)
+//! is called once when the client starts the method, and then once for
+//! each `SSH_MSG_USERAUTH_INFO_RESPONSE`, answering the prompts of the
+//! last [`InfoRequest`] it returned. [`Rounds`] keeps the answers of the
+//! previous rounds between these calls, so that the handler can decide
+//! on the next one:
+//!
+//! ```
+//! # use russh::server::keyboard_interactive::{InfoRequest, Rounds};
+//! # use russh::server::{Auth, Handler, Response};
+//! struct Client {
+//!     rounds: Rounds,
+//! }
+//!
+//! impl Handler for Client {
+//!     type Error = russh::Error;
+//!
+//!     async fn auth_keyboard_interactive<'a>(
+//!         &'a mut self,
+//!         user: &str,
+//!         _: &str,
+//!         response: Option<Response<'a>>,
+//!     ) -> Result<Auth, Self::Error> {
+//!         Ok(match self.rounds.receive(response) {
+//!             [] => InfoRequest::new().prompt("Password: ", false).into(),
+//!             [password] if password.first().is_some_and(|p| p == "hunter2") => {
+//!                 InfoRequest::new()
+//!                     .instructions("Enter the code of your authenticator")
+//!                     .prompt("Code: ", true)
+//!                     .into()
+//!             }
+//!             [_, code] if code.first().is_some_and(|c| c == "123456") => Auth::Accept,
+//!             _ => Auth::reject(),
+//!         })
+//!     }
+//! }
+//! ```
+//!
+//! The responses of the client are only passed to the handler if there
+//! is one for each prompt of the request, and the other ones are
+//! rejected.
+
+use std::borrow::Cow;
+
+use super::{Auth, Response};
+
+/// A prompt of an [`InfoRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt {
+    pub text: String,
+    /// Whether the client should echo the answer as it is typed.
+    pub echo: bool,
+}
+
+/// An `SSH_MSG_USERAUTH_INFO_REQUEST`, sent to the client by returning
+/// it as an [`Auth::Partial`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InfoRequest {
+    pub name: String,
+    pub instructions: String,
+    pub prompts: Vec<Prompt>,
+}
+
+impl InfoRequest {
+    /// A request without name, instructions or prompts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the name of the request, usually shown as a title.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Set the instructions shown before the prompts.
+    pub fn instructions(mut self, instructions: &str) -> Self {
+        self.instructions = instructions.to_string();
+        self
+    }
+
+    /// Add a prompt, whose answer is echoed if `echo` is set.
+    pub fn prompt(mut self, text: &str, echo: bool) -> Self {
+        self.prompts.push(Prompt {
+            text: text.to_string(),
+            echo,
+        });
+        self
+    }
+}
+
+impl From<InfoRequest> for Auth {
+    fn from(request: InfoRequest) -> Self {
+        Auth::Partial {
+            name: request.name.into(),
+            instructions: request.instructions.into(),
+            prompts: request
+                .prompts
+                .into_iter()
+                .map(|p| (Cow::Owned(p.text), p.echo))
+                .collect::<Vec<_>>()
+                .into(),
+        }
+    }
+}
+
+/// The answers of the rounds of a keyboard-interactive authentication.
+#[derive(Debug, Default)]
+pub struct Rounds {
+    answers: Vec<Vec<String>>,
+}
+
+impl Rounds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the `response` passed to
+    /// [`Handler::auth_keyboard_interactive`](super::Handler::auth_keyboard_interactive),
+    /// and return the answers of all the rounds so far, one vector per
+    /// round. A new authentication, without response, forgets the
+    /// previous rounds.
+    pub fn receive(&mut self, response: Option<Response<'_>>) -> &[Vec<String>] {
+        match response {
+            None => self.answers.clear(),
+            Some(response) => self.answers.push(
+                response
+                    .map(|answer| String::from_utf8_lossy(&answer).into_owned())
+                    .collect(),
+            ),
+        }
+        &self.answers
+    }
+
+    /// The answers of the rounds so far.
+    pub fn answers(&self) -> &[Vec<String>] {
+        &self.answers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn info_request() {
+        let auth: Auth = InfoRequest::new()
+            .instructions("Second factor")
+            .prompt("Code: ", true)
+            .into();
+        assert_eq!(
+            auth,
+            Auth::Partial {
+                name: "".into(),
+                instructions: "Second factor".into(),
+                prompts: vec![("Code: ".into(), true)].into(),
+            }
+        );
+    }
+
+    #[test]
+    fn rounds() {
+        let mut rounds = Rounds::new();
+        assert!(rounds.receive(None).is_empty());
+        let mut answers = vec![Some(Bytes::from("hunter2"))].into_iter();
+        assert_eq!(
+            rounds.receive(Some(Response(&mut answers))),
+            [vec!["hunter2".to_string()]]
+        );
+        let mut answers = vec![Some(Bytes::from("123456")), None].into_iter();
+        rounds.receive(Some(Response(&mut answers)));
+        assert_eq!(rounds.answers().len(), 2);
+        assert!(rounds.receive(None).is_empty());
+    }
+}
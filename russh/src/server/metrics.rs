@@ -0,0 +1,171 @@
+//! Instrumentation of the server, to export metrics such as those of
+//! Prometheus.
+//!
+//! A [`ServerMetrics`] set in [`Config::metrics`](super::Config::metrics)
+//! is called by every session of the server, as connections open and
+//! close, clients authenticate, channels open and close, bytes are sent
+//! and received, and keys are exchanged:
+//!
+//! ```
+//! # use std::sync::atomic::{AtomicU64, Ordering};
+//! # use std::sync::Arc;
+//! # use russh::server::metrics::ServerMetrics;
+//! # use russh::MethodKind;
+//! #[derive(Default)]
+//! struct Counters {
+//!     connections: AtomicU64,
+//!     auth_failures: AtomicU64,
+//! }
+//!
+//! impl ServerMetrics for Counters {
+//!     fn connection_opened(&self) {
+//!         self.connections.fetch_add(1, Ordering::Relaxed);
+//!     }
+//!
+//!     fn connection_closed(&self, _: std::time::Duration) {
+//!         self.connections.fetch_sub(1, Ordering::Relaxed);
+//!     }
+//!
+//!     fn auth_failed(&self, _: MethodKind) {
+//!         self.auth_failures.fetch_add(1, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let config = russh::server::Config {
+//!     metrics: Some(Arc::new(Counters::default())),
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! The methods are called from the session tasks, and should not block.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{kex, ChannelId, MethodKind};
+
+/// Events of the sessions of a server. All the methods do nothing by
+/// default.
+#[allow(unused_variables)]
+pub trait ServerMetrics: Send + Sync {
+    /// A session started, once the SSH identification is sent.
+    fn connection_opened(&self) {}
+
+    /// A session ended, after `duration`.
+    fn connection_closed(&self, duration: Duration) {}
+
+    /// The number of connections open at once in
+    /// [`Server::run_on_listener`](super::Server::run_on_listener)
+    /// changed to `count`, as a gauge.
+    fn active_connections(&self, count: usize) {}
+
+    /// A connection was closed as soon as it was accepted, past
+    /// [`Config::max_connections`](super::Config::max_connections).
+    fn connection_refused(&self) {}
+
+    /// A client authenticated with `method`, the last one of
+    /// [`Config::authentication_methods`](super::Config::authentication_methods)
+    /// if several are required.
+    fn auth_succeeded(&self, method: MethodKind) {}
+
+    /// An authentication attempt with `method` was rejected, including
+    /// the initial "none" probe of most clients.
+    fn auth_failed(&self, method: MethodKind) {}
+
+    /// A channel was opened, by either side.
+    fn channel_opened(&self) {}
+
+    /// A channel was closed, or its session ended.
+    fn channel_closed(&self) {}
+
+    /// `bytes` were received from a client, as encrypted packets.
+    fn bytes_received(&self, bytes: usize) {}
+
+    /// `bytes` were sent to a client, as encrypted packets.
+    fn bytes_sent(&self, bytes: usize) {}
+
+    /// A key exchange with `kex` completed, after `duration`, for the
+    /// first time or to re-exchange keys.
+    fn kex_completed(&self, kex: kex::Name, duration: Duration) {}
+}
+
+/// The metrics of a session, reporting its end when dropped.
+pub(crate) struct SessionMetrics {
+    metrics: Arc<dyn ServerMetrics>,
+    opened: Instant,
+    kex_started: Option<Instant>,
+    channels: HashSet<ChannelId>,
+}
+
+impl std::fmt::Debug for SessionMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionMetrics")
+            .field("opened", &self.opened)
+            .field("channels", &self.channels)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionMetrics {
+    pub(crate) fn new(metrics: Arc<dyn ServerMetrics>) -> Self {
+        metrics.connection_opened();
+        SessionMetrics {
+            metrics,
+            opened: Instant::now(),
+            kex_started: None,
+            channels: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn kex_started(&mut self) {
+        self.kex_started = Some(Instant::now());
+    }
+
+    pub(crate) fn kex_completed(&mut self, kex: kex::Name) {
+        if let Some(started) = self.kex_started.take() {
+            self.metrics.kex_completed(kex, started.elapsed());
+        }
+    }
+
+    pub(crate) fn auth_succeeded(&self, method: MethodKind) {
+        self.metrics.auth_succeeded(method)
+    }
+
+    pub(crate) fn auth_failed(&self, method: MethodKind) {
+        self.metrics.auth_failed(method)
+    }
+
+    pub(crate) fn channel_opened(&mut self, id: ChannelId) {
+        if self.channels.insert(id) {
+            self.metrics.channel_opened();
+        }
+    }
+
+    pub(crate) fn channel_closed(&mut self, id: ChannelId) {
+        if self.channels.remove(&id) {
+            self.metrics.channel_closed();
+        }
+    }
+
+    pub(crate) fn bytes_received(&self, bytes: usize) {
+        if bytes > 0 {
+            self.metrics.bytes_received(bytes)
+        }
+    }
+
+    pub(crate) fn bytes_sent(&self, bytes: usize) {
+        if bytes > 0 {
+            self.metrics.bytes_sent(bytes)
+        }
+    }
+}
+
+impl Drop for SessionMetrics {
+    fn drop(&mut self) {
+        for _ in self.channels.drain() {
+            self.metrics.channel_closed();
+        }
+        self.metrics.connection_closed(self.opened.elapsed());
+    }
+}
@@ -29,7 +29,7 @@
 //! * Serving `ratatui` based TUI app to clients: [per-client](https://github.com/warp-tech/russh/blob/main/russh/examples/ratatui_app.rs), [shared](https://github.com/warp-tech/russh/blob/main/russh/examples/ratatui_shared_app.rs)
 
 use std;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::num::Wrapping;
 use std::pin::Pin;
 use std::sync::Arc;
@@ -41,23 +41,63 @@ use futures::future::Future;
 use log::{debug, error, info, warn};
 use msg::{is_kex_msg, validate_client_msg_strict_kex};
 use russh_util::runtime::JoinHandle;
-use russh_util::time::Instant;
-use ssh_key::{Certificate, PrivateKey};
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use ssh_key::{Algorithm, Certificate, PrivateKey};
+use tokio::io::{AsyncRead, AsyncWriteExt};
 use tokio::net::{TcpListener, ToSocketAddrs};
 use tokio::pin;
 
+use crate::channels::tun::TunMode;
 use crate::cipher::{clear, OpeningKey};
-use crate::kex::dh::groups::{DhGroup, BUILTIN_SAFE_DH_GROUPS, DH_GROUP14};
+use crate::kex::dh::groups::DhGroup;
+use crate::kex::dh::moduli::Moduli;
 use crate::kex::{KexProgress, SessionKexState};
 use crate::session::*;
 use crate::ssh_read::*;
 use crate::sshbuffer::*;
 use crate::{map_err, *};
 
+#[cfg(unix)]
+pub mod agent_forward;
+pub mod authorized_keys;
+mod connections;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod forward;
+#[cfg(feature = "gssapi")]
+pub mod gssapi;
+mod idle;
 mod kex;
+pub mod keyboard_interactive;
+pub mod metrics;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod motd;
+#[cfg(feature = "pam")]
+pub mod pam;
+pub mod penalties;
+mod preauth;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod process;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod proxy_protocol;
+pub mod recording;
+pub mod revoked_keys;
 mod session;
+mod shutdown;
+#[cfg(unix)]
+pub mod socket_activation;
+pub mod source_filter;
+pub mod sshd_config;
+mod startups;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod x11;
+use self::connections::Connections;
+pub use self::connections::{MaxConnections, Overflow};
+pub use self::idle::{IdleAction, IdleTimeout};
+use self::preauth::PreAuthBudget;
+pub use self::preauth::PreAuthLimits;
 pub use self::session::*;
+pub use self::shutdown::Shutdown;
+pub use self::startups::MaxStartups;
+use self::startups::{Startup, Startups};
 mod encrypted;
 
 /// Configuration of a server.
@@ -66,16 +106,39 @@ pub struct Config {
     pub server_id: SshId,
     /// Authentication methods proposed to the client.
     pub methods: auth::MethodSet,
+    /// Lists of methods the client must complete in order, as the
+    /// `AuthenticationMethods` option of `sshd_config`, so that the
+    /// client is only authenticated once it completes one of them, and
+    /// only gets a partial success for the previous methods. For
+    /// instance, `vec![vec![MethodKind::PublicKey, MethodKind::KeyboardInteractive]]`
+    /// requires a second factor after a public key. Any method accepted
+    /// by the handler is enough if this is empty, the default.
+    pub authentication_methods: Vec<Vec<MethodKind>>,
     /// Authentication rejections must happen in constant time for
     /// security reasons. Russh does not handle this by default.
     pub auth_rejection_time: std::time::Duration,
     /// Authentication rejection time override for the initial "none" auth attempt.
     /// OpenSSH clients will send an initial "none" auth to probe for authentication methods.
     pub auth_rejection_time_initial: Option<std::time::Duration>,
+    /// A banner sent to the clients when authentication starts, usually
+    /// a legal notice, unless [`Handler::authentication_banner`]
+    /// returns one.
+    pub auth_banner: Option<String>,
     /// The server's keys. The first key pair in the client's preference order will be chosen.
     pub keys: Vec<PrivateKey>,
+    /// OpenSSH certificates of some of the [`Config::keys`], presented
+    /// instead of the keys to the clients preferring certificate
+    /// algorithms such as `ssh-ed25519-cert-v01@openssh.com`.
+    pub host_certificates: Vec<Certificate>,
+    /// Client keys and certificates rejected for the "publickey" and
+    /// "hostbased" methods, before reaching the handler, as the
+    /// `RevokedKeys` option of `sshd_config`.
+    pub revoked_keys: Option<Arc<revoked_keys::RevokedKeys>>,
     /// The bytes and time limits before key re-exchange.
     pub limits: Limits,
+    /// The number of packets sent or received before key re-exchange,
+    /// 2^31 by default ([RFC 4344](https://tools.ietf.org/html/rfc4344#section-3.1)).
+    pub rekey_packet_limit: usize,
     /// The initial size of a channel (used for flow control).
     pub window_size: u32,
     /// The maximal size of a single packet.
@@ -86,16 +149,98 @@ pub struct Config {
     pub event_buffer_size: usize,
     /// Lists of preferred algorithms.
     pub preferred: Preferred,
-    /// Maximal number of allowed authentication attempts.
+    /// Maximal number of failed authentication attempts, not counting
+    /// the initial "none" probe. The client is disconnected after the
+    /// last one.
     pub max_auth_attempts: usize,
+    /// The client is disconnected if it has not authenticated after
+    /// this time.
+    pub login_grace_time: Option<std::time::Duration>,
+    /// Drop new connections when too many have not authenticated yet,
+    /// in [`Server::run_on_listener`]. Unlimited by default.
+    pub max_startups: Option<MaxStartups>,
+    /// Refuse or queue new connections when too many are open, in
+    /// [`Server::run_on_listener`]. Unlimited by default.
+    pub max_connections: Option<MaxConnections>,
+    /// Disconnect the clients which send too many packets, too large
+    /// packets or too much data before authenticating. Disabled by
+    /// default.
+    pub preauth_limits: Option<PreAuthLimits>,
+    /// Refuse new connections from the addresses of the clients which
+    /// misbehaved recently, in [`Server::run_on_listener`]. Disabled by
+    /// default.
+    pub per_source_penalties: Option<penalties::PerSourcePenalties>,
+    /// Close the connections from the addresses which are not allowed,
+    /// in [`Server::run_on_listener`], before sending them anything.
+    /// Everything is allowed by default.
+    pub source_filter: Option<source_filter::SourceFilter>,
     /// Time after which the connection is garbage-collected.
     pub inactivity_timeout: Option<std::time::Duration>,
+    /// Warn, close the channels or disconnect the client after a time
+    /// without any data on the channels, even if the connection is kept
+    /// alive. Disabled by default.
+    pub idle_timeout: Option<IdleTimeout>,
     /// If nothing is received from the client for this amount of time, send a keepalive message.
     pub keepalive_interval: Option<std::time::Duration>,
     /// If this many keepalives have been sent without reply, close the connection.
     pub keepalive_max: usize,
     /// If active, invoke `set_nodelay(true)` on client sockets; disabled by default (i.e. Nagle's algorithm is active).
     pub nodelay: bool,
+    /// Limit the total rate of data sent on the channels of a connection.
+    /// Individual channels can be limited with
+    /// [`Channel::set_rate_limit`](crate::Channel::set_rate_limit).
+    pub send_rate_limit: Option<crate::RateLimit>,
+    /// Limit the total rate of data received on the channels of a
+    /// connection.
+    pub receive_rate_limit: Option<crate::RateLimit>,
+    /// If set, only the environment variables matching one of these
+    /// patterns, such as `LANG` or `LC_*`, are passed to
+    /// [`Handler::env_request`]. The others are refused. Patterns can
+    /// contain `*` and `?` wildcards, as in the `AcceptEnv` option of
+    /// `sshd_config`.
+    pub accept_env: Option<Vec<String>>,
+    /// Only allow the `sftp` subsystem on session channels, as
+    /// `ForceCommand internal-sftp` in `sshd_config`: terminals,
+    /// shells, commands, other subsystems, agent and X11 forwarding,
+    /// and the other channels and port forwarding are refused before
+    /// reaching the handler. This can also be set for each session with
+    /// [`Session::set_sftp_only`], and the directory served can be the
+    /// [`Policy::chroot_path`](sshd_config::Policy::chroot_path) of the
+    /// user. Disabled by default.
+    pub sftp_only: bool,
+    /// The maximal number of session channels open at once on a
+    /// connection, as the `MaxSessions` option of `sshd_config`. Further
+    /// session channels are refused before reaching the handler.
+    /// Unlimited by default.
+    pub max_sessions: Option<usize>,
+    /// Extensions sent to clients that support extension negotiation
+    /// ([RFC 8308](https://tools.ietf.org/html/rfc8308)), after
+    /// `server-sig-algs`, as names and raw values.
+    pub extensions: Vec<(String, Vec<u8>)>,
+    /// Replies to the global requests with these names, `true` for a
+    /// success and `false` for a failure, sent without calling
+    /// [`Handler::global_request`]. By default, the
+    /// `keepalive@openssh.com` requests sent by clients such as OpenSSH
+    /// with `ServerAliveInterval` are rejected right away, as OpenSSH
+    /// does.
+    pub global_request_replies: HashMap<String, bool>,
+    /// Called by the sessions of the server to report their activity.
+    pub metrics: Option<Arc<dyn metrics::ServerMetrics>>,
+    /// Records the terminal sessions, commands and subsystems of the
+    /// clients, for audit.
+    pub recorder: Option<Arc<dyn recording::Recorder>>,
+}
+
+impl Config {
+    /// Whether the environment variable `name` is accepted by
+    /// [`Config::accept_env`].
+    pub fn accepts_env(&self, name: &str) -> bool {
+        self.accept_env.as_ref().map_or(true, |patterns| {
+            patterns
+                .iter()
+                .any(|p| crate::helpers::wildcard_match(name.as_bytes(), p.as_bytes()))
+        })
+    }
 }
 
 impl Default for Config {
@@ -107,20 +252,41 @@ impl Default for Config {
                 env!("CARGO_PKG_VERSION")
             )),
             methods: auth::MethodSet::all(),
+            authentication_methods: Vec::new(),
             auth_rejection_time: std::time::Duration::from_secs(1),
             auth_rejection_time_initial: None,
+            auth_banner: None,
             keys: Vec::new(),
+            host_certificates: Vec::new(),
+            revoked_keys: None,
             window_size: 2097152,
             maximum_packet_size: 32768,
             channel_buffer_size: 100,
             event_buffer_size: 10,
             limits: Limits::default(),
+            rekey_packet_limit: 1 << 31,
             preferred: Default::default(),
             max_auth_attempts: 10,
+            max_startups: None,
+            max_connections: None,
+            preauth_limits: None,
+            per_source_penalties: None,
+            source_filter: None,
+            login_grace_time: None,
             inactivity_timeout: Some(std::time::Duration::from_secs(600)),
+            idle_timeout: None,
             keepalive_interval: None,
             keepalive_max: 3,
             nodelay: false,
+            send_rate_limit: None,
+            receive_rate_limit: None,
+            accept_env: None,
+            sftp_only: false,
+            max_sessions: None,
+            extensions: Vec::new(),
+            global_request_replies: [("keepalive@openssh.com".to_string(), false)].into(),
+            metrics: None,
+            recorder: None,
         }
     }
 }
@@ -131,22 +297,43 @@ impl Debug for Config {
         f.debug_struct("Config")
             .field("server_id", &self.server_id)
             .field("methods", &self.methods)
+            .field("authentication_methods", &self.authentication_methods)
             .field("auth_rejection_time", &self.auth_rejection_time)
             .field(
                 "auth_rejection_time_initial",
                 &self.auth_rejection_time_initial,
             )
+            .field("auth_banner", &self.auth_banner)
             .field("keys", &"***")
+            .field("host_certificates", &self.host_certificates)
+            .field("revoked_keys", &self.revoked_keys.is_some())
             .field("window_size", &self.window_size)
             .field("maximum_packet_size", &self.maximum_packet_size)
             .field("channel_buffer_size", &self.channel_buffer_size)
             .field("event_buffer_size", &self.event_buffer_size)
             .field("limits", &self.limits)
+            .field("rekey_packet_limit", &self.rekey_packet_limit)
             .field("preferred", &self.preferred)
             .field("max_auth_attempts", &self.max_auth_attempts)
+            .field("max_startups", &self.max_startups)
+            .field("max_connections", &self.max_connections)
+            .field("preauth_limits", &self.preauth_limits)
+            .field("per_source_penalties", &self.per_source_penalties)
+            .field("source_filter", &self.source_filter)
+            .field("login_grace_time", &self.login_grace_time)
             .field("inactivity_timeout", &self.inactivity_timeout)
+            .field("idle_timeout", &self.idle_timeout)
             .field("keepalive_interval", &self.keepalive_interval)
             .field("keepalive_max", &self.keepalive_max)
+            .field("send_rate_limit", &self.send_rate_limit)
+            .field("receive_rate_limit", &self.receive_rate_limit)
+            .field("accept_env", &self.accept_env)
+            .field("sftp_only", &self.sftp_only)
+            .field("max_sessions", &self.max_sessions)
+            .field("extensions", &self.extensions)
+            .field("global_request_replies", &self.global_request_replies)
+            .field("metrics", &self.metrics.is_some())
+            .field("recorder", &self.recorder.is_some())
             .finish()
     }
 }
@@ -261,6 +448,27 @@ pub trait Handler: Sized {
         async { Ok(Auth::reject()) }
     }
 
+    /// Check "hostbased" authentication, once the client has proved to
+    /// own the host key `key` of `client_host`. This should tell whether
+    /// `key` is the key of `client_host`, for instance with
+    /// [`KnownHosts::check`](crate::client::known_hosts::KnownHosts::check)
+    /// on `/etc/ssh/ssh_known_hosts`, whether `client_host` is the host
+    /// the client connects from, and whether `client_user` on that host
+    /// may log in as `user`, as `shosts.equiv` does.
+    /// Russh makes sure rejection happens in time
+    /// `config.auth_rejection_time`, except if this method takes more
+    /// than that.
+    #[allow(unused_variables)]
+    fn auth_hostbased(
+        &mut self,
+        user: &str,
+        client_host: &str,
+        client_user: &str,
+        key: &ssh_key::PublicKey,
+    ) -> impl Future<Output = Result<Auth, Self::Error>> + Send {
+        async { Ok(Auth::reject()) }
+    }
+
     /// Check authentication using an OpenSSH certificate. This method
     /// is called after the signature has been verified and key
     /// ownership has been confirmed.
@@ -280,6 +488,11 @@ pub trait Handler: Sized {
     /// method. Russh makes sure rejection happens in time
     /// `config.auth_rejection_time`, except if this method takes more
     /// than that.
+    ///
+    /// This is called without `response` when the client starts the
+    /// method, and then with the answers to the prompts of each
+    /// [`Auth::Partial`] returned, see [`keyboard_interactive`] to
+    /// follow the rounds.
     #[allow(unused_variables)]
     fn auth_keyboard_interactive<'a>(
         &'a mut self,
@@ -290,6 +503,36 @@ pub trait Handler: Sized {
         async { Ok(Auth::reject()) }
     }
 
+    /// Called when the client starts `gssapi-with-mic` authentication as
+    /// `user`, to get the security context accepting it. The default
+    /// implementation returns `None`, which rejects the method.
+    #[cfg(feature = "gssapi")]
+    #[allow(unused_variables)]
+    fn gssapi_acceptor(
+        &mut self,
+        user: &str,
+    ) -> impl Future<Output = Result<Option<Box<dyn gssapi::GssapiAcceptor>>, Self::Error>> + Send
+    {
+        async { Ok(None) }
+    }
+
+    /// Check `gssapi-with-mic` authentication, once the client has
+    /// established the context of [`Handler::gssapi_acceptor`] as
+    /// `principal`, and proved it with an integrity code of the session.
+    /// This should tell whether `principal` may log in as `user`, as a
+    /// `.k5login` file does. Russh makes sure rejection happens in time
+    /// `config.auth_rejection_time`, except if this method takes more
+    /// than that.
+    #[cfg(feature = "gssapi")]
+    #[allow(unused_variables)]
+    fn auth_gssapi_with_mic(
+        &mut self,
+        user: &str,
+        principal: &str,
+    ) -> impl Future<Output = Result<Auth, Self::Error>> + Send {
+        async { Ok(Auth::reject()) }
+    }
+
     /// Called when authentication succeeds for a session.
     #[allow(unused_variables)]
     fn auth_succeeded(
@@ -299,8 +542,23 @@ pub trait Handler: Sized {
         async { Ok(()) }
     }
 
+    /// Called for each extension received from the client in
+    /// `SSH_MSG_EXT_INFO` ([RFC 8308](https://tools.ietf.org/html/rfc8308)).
+    /// All of them are also kept in [`Session::extension_info`].
+    #[allow(unused_variables)]
+    fn extension_received(
+        &mut self,
+        name: &str,
+        value: &[u8],
+        session: &mut Session,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        async { Ok(()) }
+    }
+
     /// Called when authentication starts but before it is successful.
     /// Return value is an authentication banner, usually a warning message shown to the client.
+    /// It replaces [`Config::auth_banner`], and can depend on the connection, such as on the
+    /// peer address given to [`Server::new_client`].
     #[allow(unused_variables)]
     fn authentication_banner(
         &mut self,
@@ -352,6 +610,37 @@ pub trait Handler: Sized {
         async { Ok(false) }
     }
 
+    /// Called when the client opens a `tun@openssh.com` channel, to
+    /// forward packets to the tunnel device `remote_unit` (which may be
+    /// [`TUN_UNIT_ANY`](crate::tun::TUN_UNIT_ANY)). Use a
+    /// [`TunChannel`](crate::tun::TunChannel) to exchange packets.
+    /// Return value indicates whether the channel request should be granted.
+    #[allow(unused_variables)]
+    fn channel_open_tun(
+        &mut self,
+        channel: Channel<Msg>,
+        mode: TunMode,
+        remote_unit: u32,
+        session: &mut Session,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        async { Ok(false) }
+    }
+
+    /// Called when the client opens a channel of a type that this crate
+    /// does not know about, with the type-specific data of the request.
+    /// Return value indicates whether the channel request should be
+    /// granted; if not, the channel type is reported as unknown.
+    #[allow(unused_variables)]
+    fn channel_open_unknown(
+        &mut self,
+        channel: Channel<Msg>,
+        channel_type: &str,
+        data: &[u8],
+        session: &mut Session,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        async { Ok(false) }
+    }
+
     /// Called when a new TCP/IP is created.
     /// Return value indicates whether the channel request should be granted.
     #[allow(unused_variables)]
@@ -367,6 +656,19 @@ pub trait Handler: Sized {
         async { Ok(false) }
     }
 
+    /// Called when the client opens a connection to the Unix socket
+    /// `socket_path` of the server, like `ssh -L` with a socket. Return
+    /// value indicates whether the channel request should be granted.
+    #[allow(unused_variables)]
+    fn channel_open_direct_streamlocal(
+        &mut self,
+        channel: Channel<Msg>,
+        socket_path: &str,
+        session: &mut Session,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        async { Ok(false) }
+    }
+
     /// Called when a new forwarded connection comes in.
     /// <https://www.rfc-editor.org/rfc/rfc4254#section-7>
     #[allow(unused_variables)]
@@ -480,7 +782,8 @@ pub trait Handler: Sized {
         async { Ok(()) }
     }
 
-    /// The client requests an X11 connection.
+    /// The client requests an X11 connection. See [`x11`] to serve it
+    /// with a fake display.
     ///
     /// **Note:** Success or failure should be communicated to the client by calling
     /// `session.channel_success(channel)` or `session.channel_failure(channel)` respectively. For
@@ -625,7 +928,33 @@ pub trait Handler: Sized {
         async { Ok(()) }
     }
 
-    /// The client's pseudo-terminal window size has changed.
+    /// The client's pseudo-terminal window size has changed. By
+    /// default, this calls [`Handler::window_change_request`] with the
+    /// fields of `size`.
+    ///
+    /// Clients send `window-change` requests without asking for a reply
+    /// ([RFC4254](https://tools.ietf.org/html/rfc4254#section-6.7)), so
+    /// calling `session.channel_success(channel)` or
+    /// `session.channel_failure(channel)` sends nothing.
+    #[allow(unused_variables)]
+    fn window_change(
+        &mut self,
+        channel: ChannelId,
+        size: WindowSize,
+        session: &mut Session,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send {
+        self.window_change_request(
+            channel,
+            size.col_width,
+            size.row_height,
+            size.pix_width,
+            size.pix_height,
+            session,
+        )
+    }
+
+    /// The client's pseudo-terminal window size has changed, called by
+    /// the default [`Handler::window_change`].
     ///
     /// **Note:** Success or failure should be communicated to the client by calling
     /// `session.channel_success(channel)` or `session.channel_failure(channel)` respectively. For
@@ -658,22 +987,10 @@ pub trait Handler: Sized {
         async { Ok(()) }
     }
 
-    /// The client requests OpenSSH agent forwarding
-    ///
-    /// **Note:** Success or failure should be communicated to the client by calling
-    /// `session.channel_success(channel)` or `session.channel_failure(channel)` respectively. For
-    /// instance:
-    ///
-    /// ```ignore
-    /// async fn agent_request(
-    ///     &mut self,
-    ///     channel: ChannelId,
-    ///     session: &mut Session,
-    /// ) -> Result<bool, Self::Error> {
-    ///     session.channel_success(channel);
-    ///     Ok(())
-    /// }
-    /// ```
+    /// The client requests OpenSSH agent forwarding. Return `true` to
+    /// accept it, after which [`Handle::channel_open_agent`] reaches the
+    /// agent of the client. See [`agent_forward`] to bridge these
+    /// channels to a Unix socket.
     #[allow(unused_variables)]
     fn agent_request(
         &mut self,
@@ -684,7 +1001,12 @@ pub trait Handler: Sized {
     }
 
     /// The client is sending a signal (usually to pass to the
-    /// currently running process).
+    /// currently running process). Signals the server does not know
+    /// are passed as [`Sig::Custom`].
+    ///
+    /// Clients send `signal` requests without asking for a reply
+    /// ([RFC4254](https://tools.ietf.org/html/rfc4254#section-6.9)), so
+    /// nothing needs to be sent back.
     #[allow(unused_variables)]
     fn signal(
         &mut self,
@@ -695,9 +1017,25 @@ pub trait Handler: Sized {
         async { Ok(()) }
     }
 
+    /// The client sends a break of `length` to the terminal, see
+    /// [RFC4335](https://tools.ietf.org/html/rfc4335), such as to reach
+    /// the console of a serial device. Return `true` if the break was
+    /// performed, which is replied to the client if it asked for a
+    /// reply.
+    #[allow(unused_variables)]
+    fn break_request(
+        &mut self,
+        channel: ChannelId,
+        length: std::time::Duration,
+        session: &mut Session,
+    ) -> impl Future<Output = Result<bool, Self::Error>> + Send {
+        async { Ok(false) }
+    }
+
     /// Used for reverse-forwarding ports, see
     /// [RFC4254](https://tools.ietf.org/html/rfc4254#section-7).
     /// If `port` is 0, you should set it to the allocated port number.
+    /// See [`forward::RemoteForwards`] to listen for the client.
     #[allow(unused_variables)]
     fn tcpip_forward(
         &mut self,
@@ -720,6 +1058,9 @@ pub trait Handler: Sized {
         async { Ok(false) }
     }
 
+    /// Used for reverse-forwarding Unix sockets, like `ssh -R` with a
+    /// socket path, with `streamlocal-forward@openssh.com`. See
+    /// [`forward::RemoteForwards`] to listen for the client.
     #[allow(unused_variables)]
     fn streamlocal_forward(
         &mut self,
@@ -729,6 +1070,7 @@ pub trait Handler: Sized {
         async { Ok(false) }
     }
 
+    /// Used to stop the reverse-forwarding of a Unix socket.
     #[allow(unused_variables)]
     fn cancel_streamlocal_forward(
         &mut self,
@@ -738,16 +1080,34 @@ pub trait Handler: Sized {
         async { Ok(false) }
     }
 
-    /// Override when enabling the `diffie-hellman-group-exchange-*` key exchange methods.
-    /// Should return a Diffie-Hellman group with a safe prime whose length is
-    /// between `gex_params.min_group_size` and `gex_params.max_group_size` and
-    /// (if possible) over and as close as possible to `gex_params.preferred_group_size`.
-    ///
-    /// OpenSSH uses a pre-generated database of safe primes stored in `/etc/ssh/moduli`
+    /// Called for global requests that this crate does not handle, with
+    /// the data that follows their name and `want_reply` flag. Return the
+    /// data of a successful reply, or `None` to reject the request. The
+    /// reply is only sent if `want_reply` is set.
+    #[allow(unused_variables)]
+    fn global_request(
+        &mut self,
+        name: &str,
+        want_reply: bool,
+        payload: &[u8],
+        session: &mut Session,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>, Self::Error>> + Send {
+        async { Ok(None) }
+    }
+
+    /// Called in the `diffie-hellman-group-exchange-*` key exchanges to
+    /// choose a Diffie-Hellman group with a safe prime whose length is
+    /// between `gex_params.min_group_size` and `gex_params.max_group_size`
+    /// and (if possible) over and as close as possible to
+    /// `gex_params.preferred_group_size`. Returning `None` fails the
+    /// key exchange.
     ///
-    /// The default implementation picks a group from a very short static list
-    /// of built-in standard groups and is not really taking advantage of the security
-    /// offered by these kex methods.
+    /// The default implementation chooses from a very short list of
+    /// built-in standard groups, and does not really take advantage of
+    /// the security offered by these kex methods. Servers can instead
+    /// choose from a set of generated primes, such as OpenSSH's
+    /// `/etc/ssh/moduli` loaded with [`Moduli::from_path`], with
+    /// [`Moduli::select`].
     ///
     /// See https://datatracker.ietf.org/doc/html/rfc4419#section-3
     #[allow(unused_variables)]
@@ -755,29 +1115,52 @@ pub trait Handler: Sized {
         &mut self,
         gex_params: &GexParams,
     ) -> impl Future<Output = Result<Option<DhGroup>, Self::Error>> + Send {
-        async {
-            let mut best_group = &DH_GROUP14;
-
-            // Find _some_ matching group
-            for group in BUILTIN_SAFE_DH_GROUPS.iter() {
-                if group.bit_size() >= gex_params.min_group_size()
-                    && group.bit_size() <= gex_params.max_group_size()
-                {
-                    best_group = *group;
-                    break;
-                }
-            }
+        async { Ok(Moduli::builtin().select(gex_params)) }
+    }
 
-            // Find _closest_ matching group
-            for group in BUILTIN_SAFE_DH_GROUPS.iter() {
-                if group.bit_size() > gex_params.preferred_group_size() {
-                    best_group = *group;
-                    break;
-                }
-            }
+    /// Called during each key exchange, once the host key `algorithm`
+    /// is negotiated, to choose the host key presented to the client,
+    /// for instance that of the virtual host the client connected to.
+    /// `certificate` tells whether the client asked for a certificate,
+    /// which is then looked up in [`Config::host_certificates`], and
+    /// `client_algorithms` lists the host key algorithms of the client,
+    /// in order of preference.
+    ///
+    /// The algorithms offered to the client are those of
+    /// [`Config::keys`], and the returned key must be usable with
+    /// `algorithm`, or the key exchange fails. Since clients check that
+    /// the host key does not change when re-keying, the same key should
+    /// be returned each time. The destination of the connection, which
+    /// is not known to the session, can be kept in the handler, such as
+    /// from [`TcpStream::local_addr`](tokio::net::TcpStream::local_addr)
+    /// before [`run_stream`].
+    ///
+    /// The default implementation returns `None`, to use the first
+    /// matching key of [`Config::keys`].
+    #[allow(unused_variables)]
+    fn select_host_key(
+        &mut self,
+        algorithm: &Algorithm,
+        certificate: bool,
+        client_algorithms: &[String],
+    ) -> impl Future<Output = Result<Option<PrivateKey>, Self::Error>> + Send {
+        async { Ok(None) }
+    }
 
-            Ok(Some(best_group.clone()))
-        }
+    /// Called once per connection, before the version exchange, to
+    /// choose its algorithms instead of [`Config::preferred`]. This can
+    /// depend on the peer address given to [`Server::new_client`], for
+    /// instance to offer legacy ciphers only on a management network,
+    /// or on the listener that accepted the connection.
+    ///
+    /// The default implementation returns `None`, to use
+    /// [`Config::preferred`].
+    #[allow(unused_variables)]
+    fn preferred_algorithms(
+        &mut self,
+        config: &Config,
+    ) -> impl Future<Output = Result<Option<Preferred>, Self::Error>> + Send {
+        async { Ok(None) }
     }
 }
 
@@ -798,6 +1181,39 @@ pub trait Server {
         config: Arc<Config>,
         socket: &TcpListener,
     ) -> impl Future<Output = Result<(), std::io::Error>> + Send
+    where
+        Self: Send,
+    {
+        let listener = TcpAcceptor {
+            listener: socket,
+            nodelay: config.nodelay,
+        };
+        self.run_on_listener(config, listener)
+    }
+
+    /// Run a server on the connections accepted by `listener`, which can
+    /// be any [`Listener`](crate::transport::Listener).
+    fn run_on_listener<L: crate::transport::Listener>(
+        &mut self,
+        config: Arc<Config>,
+        listener: L,
+    ) -> impl Future<Output = Result<(), std::io::Error>> + Send
+    where
+        Self: Send,
+    {
+        self.run_on_listener_with_shutdown(config, listener, Shutdown::new())
+    }
+
+    /// Run a server on the connections accepted by `listener` until
+    /// [`Shutdown::shutdown`] is called on `shutdown` or one of its
+    /// clones. The server then stops accepting connections, and returns
+    /// once its sessions have ended or were closed at the deadline.
+    fn run_on_listener_with_shutdown<L: crate::transport::Listener>(
+        &mut self,
+        config: Arc<Config>,
+        mut listener: L,
+        shutdown: Shutdown,
+    ) -> impl Future<Output = Result<(), std::io::Error>> + Send
     where
         Self: Send,
     {
@@ -810,24 +1226,58 @@ pub trait Server {
             }
 
             let (error_tx, mut error_rx) = tokio::sync::mpsc::unbounded_channel();
+            let startups = Startups::default();
+            let connections = Connections::new(&config);
+            // Held by the session tasks, to wait for them to end.
+            let (sessions_tx, mut sessions_rx) = tokio::sync::mpsc::channel::<()>(1);
+            let mut state = shutdown.subscribe();
 
-            loop {
+            let deadline = loop {
                 tokio::select! {
-                    accept_result = socket.accept() => {
+                    (accept_result, permit) = async {
+                        let permit = connections.ready().await;
+                        (listener.accept().await, permit)
+                    } => {
                         match accept_result {
-                            Ok((socket, _)) => {
+                            Ok((stream, peer_addr)) => {
+                                let source = peer_addr.map(|a| a.ip());
+                                if let (Some(filter), Some(source)) = (&config.source_filter, source) {
+                                    if !filter.allows(source) {
+                                        debug!("Refusing connection from filtered {source}");
+                                        continue;
+                                    }
+                                }
+                                if let (Some(penalties), Some(source)) = (&config.per_source_penalties, source) {
+                                    if penalties.is_refused(source) {
+                                        debug!("Refusing connection from penalized {source}");
+                                        continue;
+                                    }
+                                }
+                                let unauthenticated = startups.count();
+                                if config.max_startups.is_some_and(|m| m.should_drop(unauthenticated)) {
+                                    info!("Dropping connection from {peer_addr:?} past MaxStartups ({unauthenticated} unauthenticated)");
+                                    continue;
+                                }
+                                let Some(connection) = connections.begin(permit) else {
+                                    info!("Refusing connection from {peer_addr:?} past MaxConnections");
+                                    continue;
+                                };
+                                let startup = startups.begin();
                                 let config = config.clone();
-                                let handler = self.new_client(socket.peer_addr().ok());
+                                let handler = self.new_client(peer_addr);
                                 let error_tx = error_tx.clone();
+                                let sessions_tx = sessions_tx.clone();
+                                let state = shutdown.subscribe();
 
                                 russh_util::runtime::spawn(async move {
-                                    if config.nodelay {
-                                        if let Err(e) = socket.set_nodelay(true) {
-                                            warn!("set_nodelay() failed: {e:?}");
-                                        }
-                                    }
-
-                                    let session = match run_stream(config, socket, handler).await {
+                                    let _sessions_tx = sessions_tx;
+                                    let _connection = connection;
+                                    let setup = start_session(config, stream, handler, Some(startup), source, Some(state.clone()));
+                                    let setup = tokio::select! {
+                                        setup = setup => setup,
+                                        () = shutdown::closing(state) => return,
+                                    };
+                                    let session = match setup {
                                         Ok(s) => s,
                                         Err(e) => {
                                             debug!("Connection setup failed");
@@ -846,15 +1296,41 @@ pub trait Server {
                                 });
                             }
 
-                            _ => break,
+                            _ => return Ok(()),
                         }
                     },
 
                     Some(error) = error_rx.recv() => {
                         self.handle_session_error(error);
                     }
+
+                    deadline = shutdown::draining(&mut state) => break deadline,
+                }
+            };
+
+            drop(listener);
+            drop(sessions_tx);
+            info!("Shutting down, waiting {deadline:?} for the sessions to end");
+            let deadline = tokio::time::sleep(deadline);
+            pin!(deadline);
+            loop {
+                tokio::select! {
+                    None = sessions_rx.recv() => break,
+                    Some(error) = error_rx.recv() => {
+                        self.handle_session_error(error);
+                    }
+                    () = &mut deadline => {
+                        debug!("Closing the remaining sessions");
+                        shutdown.close();
+                        // Closed sessions end without an error.
+                        while sessions_rx.recv().await.is_some() {}
+                        break;
+                    }
                 }
             }
+            while let Ok(error) = error_rx.try_recv() {
+                self.handle_session_error(error);
+            }
 
             Ok(())
         }
@@ -876,6 +1352,73 @@ pub trait Server {
             self.run_on_socket(config, &socket).await
         }
     }
+
+    /// Run a server on a Unix domain socket bound at `path`, which must
+    /// not exist yet. Clients have no peer address, and access to the
+    /// server is controlled by the permissions of the socket.
+    #[cfg(unix)]
+    fn run_on_unix_socket<P: AsRef<std::path::Path> + Send>(
+        &mut self,
+        config: Arc<Config>,
+        path: P,
+    ) -> impl Future<Output = Result<(), std::io::Error>> + Send
+    where
+        Self: Send,
+    {
+        async move {
+            let listener = tokio::net::UnixListener::bind(path)?;
+            self.run_on_listener(config, listener).await
+        }
+    }
+
+    /// Run a server on the sockets passed by systemd, with socket
+    /// activation, or fail if there are none. See
+    /// [`socket_activation`].
+    #[cfg(unix)]
+    fn run_on_activated_sockets(
+        &mut self,
+        config: Arc<Config>,
+    ) -> impl Future<Output = Result<(), std::io::Error>> + Send
+    where
+        Self: Send,
+    {
+        async move {
+            let Some(listener) = socket_activation::ActivatedListener::from_env()? else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "no sockets passed by systemd",
+                ));
+            };
+            let listener = listener.with_nodelay(config.nodelay);
+            self.run_on_listener(config, listener).await
+        }
+    }
+}
+
+struct TcpAcceptor<'a> {
+    listener: &'a TcpListener,
+    nodelay: bool,
+}
+
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+impl crate::transport::Listener for TcpAcceptor<'_> {
+    type Stream = tokio::net::TcpStream;
+
+    #[allow(clippy::manual_async_fn)]
+    fn accept(
+        &mut self,
+    ) -> impl Future<Output = std::io::Result<(Self::Stream, Option<std::net::SocketAddr>)>> + Send
+    {
+        async move {
+            let (socket, peer_addr) = self.listener.accept().await?;
+            if self.nodelay {
+                if let Err(e) = socket.set_nodelay(true) {
+                    warn!("set_nodelay() failed: {e:?}");
+                }
+            }
+            Ok((socket, Some(peer_addr)))
+        }
+    }
 }
 
 use std::cell::RefCell;
@@ -888,9 +1431,16 @@ async fn start_reading<R: AsyncRead + Unpin>(
     mut stream_read: R,
     mut buffer: SSHBuffer,
     mut cipher: Box<dyn OpeningKey + Send>,
+    maximum_packet_len: usize,
 ) -> Result<(usize, R, SSHBuffer, Box<dyn OpeningKey + Send>), Error> {
     buffer.buffer.clear();
-    let n = cipher::read(&mut stream_read, &mut buffer, &mut *cipher).await?;
+    let n = cipher::read(
+        &mut stream_read,
+        &mut buffer,
+        &mut *cipher,
+        maximum_packet_len,
+    )
+    .await?;
     Ok((n, stream_read, buffer, cipher))
 }
 
@@ -923,30 +1473,69 @@ impl<H: Handler> Future for RunningSession<H> {
     }
 }
 
-/// Start a single connection in the background.
+/// Start a single connection in the background, over any
+/// [`Transport`](crate::transport::Transport).
 pub async fn run_stream<H, R>(
     config: Arc<Config>,
-    mut stream: R,
+    stream: R,
     handler: H,
 ) -> Result<RunningSession<H>, H::Error>
 where
     H: Handler + Send + 'static,
-    R: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    R: crate::transport::Transport,
 {
+    start_session(config, stream, handler, None, None, None).await
+}
+
+/// Start a connection, counted in `startup` until it authenticates,
+/// penalizing `source` as configured when it ends, and following the
+/// `shutdown` of its server.
+async fn start_session<H, R>(
+    config: Arc<Config>,
+    mut stream: R,
+    mut handler: H,
+    startup: Option<Startup>,
+    source: Option<std::net::IpAddr>,
+    shutdown: Option<tokio::sync::watch::Receiver<shutdown::State>>,
+) -> Result<RunningSession<H>, H::Error>
+where
+    H: Handler + Send + 'static,
+    R: crate::transport::Transport,
+{
+    let penalty = source
+        .filter(|_| config.per_source_penalties.is_some())
+        .map(|source| penalties::SessionPenalty::new(config.clone(), source));
+
+    let preferred = match handler.preferred_algorithms(&config).await? {
+        Some(preferred) => preferred,
+        None => config.preferred.clone(),
+    };
+
     // Writing SSH id.
     let mut write_buffer = SSHBuffer::new();
     write_buffer.send_ssh_id(&config.as_ref().server_id);
     map_err!(stream.write_all(&write_buffer.buffer[..]).await)?;
+    map_err!(stream.flush().await)?;
 
     // Reading SSH id and allocating a session.
     let mut stream = SshRead::new(stream);
     let (sender, receiver) = tokio::sync::mpsc::channel(config.event_buffer_size);
+    let (closed_sender, closed) = tokio::sync::watch::channel(None);
     let handle = server::session::Handle {
         sender,
         channel_buffer_size: config.channel_buffer_size,
+        limiters: crate::channels::ConnectionLimiters::new(
+            config.send_rate_limit,
+            config.receive_rate_limit,
+        ),
+        closed,
     };
 
+    let metrics = config.metrics.clone().map(metrics::SessionMetrics::new);
+    let recordings = config.recorder.clone().map(recording::Recordings::new);
     let common = read_ssh_id(config, &mut stream).await?;
+    let sftp_only = common.config.sftp_only;
+    let preauth = common.config.preauth_limits.map(PreAuthBudget::new);
     let mut session = Session {
         target_window_size: common.config.window_size,
         common,
@@ -957,11 +1546,45 @@ where
         channels: HashMap::new(),
         open_global_requests: VecDeque::new(),
         kex: SessionKexState::Idle,
+        extension_info: ExtensionInfo::default(),
+        ext_info_expected: false,
+        no_more_sessions: false,
+        sftp_only,
+        channel_activity: false,
+        session_channels: HashSet::new(),
+        preauth,
+        startup,
+        auth_progress: penalty.as_ref().map(|p| p.progress()),
+        metrics,
+        recordings,
+        preferred,
     };
 
     session.begin_rekey()?;
 
-    let join = russh_util::runtime::spawn(session.run(stream, handler));
+    let session_handle = handle.clone();
+    let join = russh_util::runtime::spawn(async move {
+        // Penalizes the source once the session ends, or panics.
+        let _penalty = penalty;
+        let result = match shutdown {
+            Some(shutdown) => {
+                tokio::select! {
+                    result = session.run(stream, handler) => result,
+                    () = shutdown::session(shutdown, session_handle) => {
+                        debug!("Closing the session at the end of the shutdown");
+                        Ok(CloseReason::Local)
+                    }
+                }
+            }
+            None => session.run(stream, handler).await,
+        };
+        let reason = match &result {
+            Ok(reason) => reason.clone(),
+            Err(_) => CloseReason::Error,
+        };
+        let _ = closed_sender.send(Some(reason));
+        result.map(|_| ())
+    });
 
     Ok(RunningSession { handle, join })
 }
@@ -1043,13 +1666,16 @@ async fn reply<H: Handler + Send>(
                 }
                 KexProgress::Done { newkeys, .. } => {
                     debug!("kex impl has completed");
-                    session.common.strict_kex =
-                        session.common.strict_kex || newkeys.names.strict_kex;
+                    if let Some(ref mut metrics) = session.metrics {
+                        metrics.kex_completed(newkeys.names.kex);
+                    }
+                    if session.common.encrypted.is_none() {
+                        session.common.strict_kex = newkeys.names.strict_kex;
+                    }
 
                     if let Some(ref mut enc) = session.common.encrypted {
                         // This is a rekey
-                        enc.last_rekey = Instant::now();
-                        session.common.packet_writer.buffer().bytes = 0;
+                        enc.rekey_done(&mut session.common.packet_writer);
                         enc.flush_all_pending()?;
 
                         let mut pending = std::mem::take(&mut session.pending_reads);
@@ -1070,6 +1696,7 @@ async fn reply<H: Handler + Send>(
                             },
                             newkeys,
                         );
+                        session.ext_info_expected = true;
 
                         session.maybe_send_ext_info()?;
                     }
@@ -0,0 +1,251 @@
+//! The messages shown when a shell starts, as the `PrintMotd` and
+//! `PrintLastLog` options of `sshd_config`.
+//!
+//! A [`Motd`] sends the time and origin of the previous login of the
+//! user, from a [`LastLog`], and the message of the day, such as the
+//! contents of `/etc/motd`, to a session channel. This is done once the
+//! client asks for a shell, before the channel is handed to the shell:
+//!
+//! ```no_run
+//! # use std::collections::HashMap;
+//! # use std::sync::Arc;
+//! # use russh::server::motd::{MemoryLastLog, Motd};
+//! # use russh::server::process::Process;
+//! # use russh::server::{Handler, Msg, Session};
+//! # use russh::{Channel, ChannelId};
+//! struct Client {
+//!     user: String,
+//!     address: String,
+//!     motd: Motd,
+//!     channels: HashMap<ChannelId, Channel<Msg>>,
+//! }
+//!
+//! impl Handler for Client {
+//!     type Error = russh::Error;
+//!
+//!     async fn shell_request(
+//!         &mut self,
+//!         channel: ChannelId,
+//!         session: &mut Session,
+//!     ) -> Result<(), Self::Error> {
+//!         let Some(ch) = self.channels.remove(&channel) else {
+//!             session.channel_failure(channel)?;
+//!             return Ok(());
+//!         };
+//!         session.channel_success(channel)?;
+//!         let (motd, user, address) = (self.motd.clone(), self.user.clone(), self.address.clone());
+//!         tokio::spawn(async move {
+//!             motd.send(&ch, &user, &address).await?;
+//!             Process::new().spawn_shell(ch)?.await.ok();
+//!             Ok::<_, russh::Error>(())
+//!         });
+//!         Ok(())
+//!     }
+//! }
+//!
+//! # fn run() -> std::io::Result<()> {
+//! let motd = Motd::new()
+//!     .message_from_path("/etc/motd")?
+//!     .last_log(Arc::new(MemoryLastLog::default()));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Times are shown in UTC.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Channel, ChannelId, ChannelMsg, Error};
+
+/// A login of a user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastLogin {
+    pub time: SystemTime,
+    /// The address or host name the user logged in from.
+    pub from: String,
+}
+
+/// Remembers the logins of the users, as `lastlog` or `wtmp`.
+pub trait LastLog: Send + Sync {
+    /// Record that `user` logs in now from `from`, and return their
+    /// previous login, if any.
+    fn login(&self, user: &str, from: &str) -> Option<LastLogin>;
+}
+
+/// A [`LastLog`] kept in memory, which forgets the logins when the
+/// server stops.
+#[derive(Debug, Default)]
+pub struct MemoryLastLog {
+    logins: Mutex<HashMap<String, LastLogin>>,
+}
+
+impl LastLog for MemoryLastLog {
+    fn login(&self, user: &str, from: &str) -> Option<LastLogin> {
+        let login = LastLogin {
+            time: SystemTime::now(),
+            from: from.to_string(),
+        };
+        self.logins.lock().ok()?.insert(user.to_string(), login)
+    }
+}
+
+/// The message of the day and last login shown to the users when they
+/// start a shell. Nothing is shown by default.
+#[derive(Clone, Default)]
+pub struct Motd {
+    message: Option<String>,
+    last_log: Option<Arc<dyn LastLog>>,
+}
+
+impl std::fmt::Debug for Motd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Motd")
+            .field("message", &self.message)
+            .field("last_log", &self.last_log.is_some())
+            .finish()
+    }
+}
+
+impl Motd {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Show `message`, as `PrintMotd yes`.
+    pub fn message<M: Into<String>>(mut self, message: M) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Show the contents of the file at `path`, such as `/etc/motd`,
+    /// read now. A missing file shows nothing, as in `sshd`.
+    pub fn message_from_path<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+        match std::fs::read(path) {
+            Ok(message) => self.message = Some(String::from_utf8_lossy(&message).into_owned()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.message = None,
+            Err(e) => return Err(e),
+        }
+        Ok(self)
+    }
+
+    /// Show the previous login of the users, and record their logins,
+    /// in `last_log`, as `PrintLastLog yes`.
+    pub fn last_log(mut self, last_log: Arc<dyn LastLog>) -> Self {
+        self.last_log = Some(last_log);
+        self
+    }
+
+    /// The text shown to `user`, logging in from `from`, with the
+    /// `\r\n` line endings of a terminal. This records the login in the
+    /// [`LastLog`].
+    pub fn text(&self, user: &str, from: &str) -> String {
+        let mut text = String::new();
+        if let Some(login) = self
+            .last_log
+            .as_ref()
+            .and_then(|last_log| last_log.login(user, from))
+        {
+            text.push_str(&format!(
+                "Last login: {} from {}\r\n",
+                format_time(login.time),
+                login.from
+            ));
+        }
+        if let Some(ref message) = self.message {
+            for line in message.lines() {
+                text.push_str(line);
+                text.push_str("\r\n");
+            }
+        }
+        text
+    }
+
+    /// Send the [text](Motd::text) shown to `user`, logging in from
+    /// `from`, to `channel`.
+    pub async fn send<S>(&self, channel: &Channel<S>, user: &str, from: &str) -> Result<(), Error>
+    where
+        S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static,
+    {
+        let text = self.text(user, from);
+        if text.is_empty() {
+            return Ok(());
+        }
+        channel.data(text.as_bytes()).await
+    }
+}
+
+/// `time` as `ctime(3)` formats it, in UTC, such as
+/// `Thu Jan  1 00:00:00 1970`.
+fn format_time(time: SystemTime) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // The date, from Howard Hinnant's `civil_from_days`.
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    let weekday = DAYS.get((days % 7) as usize).unwrap_or(&"");
+    let month = MONTHS.get((month - 1) as usize).unwrap_or(&"");
+    format!(
+        "{weekday} {month} {day:>2} {:02}:{:02}:{:02} {year}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn time() {
+        assert_eq!(format_time(UNIX_EPOCH), "Thu Jan  1 00:00:00 1970");
+        assert_eq!(
+            format_time(UNIX_EPOCH + Duration::from_secs(1_000_000_000)),
+            "Sun Sep  9 01:46:40 2001"
+        );
+        assert_eq!(
+            format_time(UNIX_EPOCH + Duration::from_secs(1_709_251_199)),
+            "Thu Feb 29 23:59:59 2024"
+        );
+    }
+
+    #[test]
+    fn text() {
+        let motd = Motd::new()
+            .message("Welcome\nto russh\n")
+            .last_log(Arc::new(MemoryLastLog::default()));
+        assert_eq!(motd.text("alice", "10.0.0.1"), "Welcome\r\nto russh\r\n");
+        let text = motd.text("alice", "10.0.0.2");
+        assert!(text.starts_with("Last login: ") && text.ends_with("\r\nWelcome\r\nto russh\r\n"));
+        assert!(text.contains(" from 10.0.0.1\r\n"));
+        assert_eq!(motd.text("bob", "10.0.0.1"), "Welcome\r\nto russh\r\n");
+
+        assert_eq!(Motd::new().text("alice", "10.0.0.1"), "");
+        let dir = tempfile::tempdir().unwrap();
+        let motd = Motd::new()
+            .message_from_path(dir.path().join("motd"))
+            .unwrap();
+        assert_eq!(motd.text("alice", "10.0.0.1"), "");
+    }
+}
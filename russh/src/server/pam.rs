@@ -0,0 +1,459 @@
+//! PAM authentication, as done by `sshd` with `UsePAM yes`.
+//!
+//! Russh does not link to libpam itself. Instead, a [`PamContext`] wraps
+//! a PAM handle of a binding such as `pam-client` or `pam-sys`, whose
+//! conversation function forwards its messages to a [`Conversation`].
+//! [`Pam`] runs the blocking PAM calls on a thread of their own, and
+//! relays the conversation to the client: as the rounds of
+//! keyboard-interactive authentication, or by answering the prompts
+//! with the password of a "password" request. Once authenticated, it
+//! opens and closes the PAM session:
+//!
+//! ```no_run
+//! # use russh::server::pam::{Conversation, Pam, PamContext};
+//! # use russh::server::{Auth, Handler, Response};
+//! # struct LibPam;
+//! # impl LibPam {
+//! #     fn start(_: &str, _: &str, _: Conversation) -> Result<Self, russh::Error> { Ok(LibPam) }
+//! # }
+//! # impl PamContext for LibPam {
+//! #     fn authenticate(&mut self) -> Result<(), russh::Error> { Ok(()) }
+//! #     fn acct_mgmt(&mut self) -> Result<(), russh::Error> { Ok(()) }
+//! #     fn open_session(&mut self) -> Result<(), russh::Error> { Ok(()) }
+//! #     fn close_session(&mut self) -> Result<(), russh::Error> { Ok(()) }
+//! # }
+//! struct Client {
+//!     pam: Option<Pam>,
+//! }
+//!
+//! impl Handler for Client {
+//!     type Error = russh::Error;
+//!
+//!     async fn auth_keyboard_interactive<'a>(
+//!         &'a mut self,
+//!         user: &str,
+//!         _: &str,
+//!         response: Option<Response<'a>>,
+//!     ) -> Result<Auth, Self::Error> {
+//!         let user = user.to_string();
+//!         let pam = match (response.is_some(), &mut self.pam) {
+//!             (true, Some(pam)) => pam,
+//!             _ => self
+//!                 .pam
+//!                 .insert(Pam::start(move |conversation| LibPam::start("sshd", &user, conversation))),
+//!         };
+//!         Ok(pam.keyboard_interactive(response).await)
+//!     }
+//!
+//!     async fn auth_succeeded(&mut self, _: &mut russh::server::Session) -> Result<(), Self::Error> {
+//!         match self.pam {
+//!             Some(ref mut pam) => pam.open_session().await,
+//!             None => Ok(()),
+//!         }
+//!     }
+//! }
+//! ```
+
+use std::borrow::Cow;
+use std::sync::mpsc as std_mpsc;
+
+use log::debug;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+
+use super::{Auth, Response};
+use crate::Error;
+
+/// A message of the PAM conversation function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PamMessage {
+    /// `PAM_PROMPT_ECHO_ON` or `PAM_PROMPT_ECHO_OFF`.
+    Prompt { text: String, echo: bool },
+    /// `PAM_TEXT_INFO`.
+    Info(String),
+    /// `PAM_ERROR_MSG`.
+    Error(String),
+}
+
+/// The client side of the PAM conversation function, to call from the
+/// conversation function of the PAM handle.
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    events: UnboundedSender<Event>,
+}
+
+impl Conversation {
+    /// Send `messages` to the client, and block until it answers, with a
+    /// response for each message, empty for the ones which are not
+    /// prompts. `None` means that the client did not answer, which
+    /// should be reported to PAM as `PAM_CONV_ERR`.
+    pub fn converse(&self, messages: Vec<PamMessage>) -> Option<Vec<String>> {
+        let (reply, answers) = std_mpsc::channel();
+        self.events.send(Event::Converse(messages, reply)).ok()?;
+        answers.recv().ok().flatten()
+    }
+}
+
+/// A PAM handle, started with the conversation function of a
+/// [`Conversation`].
+pub trait PamContext: Send {
+    /// `pam_authenticate`.
+    fn authenticate(&mut self) -> Result<(), Error>;
+
+    /// `pam_acct_mgmt`, after a successful authentication.
+    fn acct_mgmt(&mut self) -> Result<(), Error>;
+
+    /// `pam_open_session`, once the client is authenticated.
+    fn open_session(&mut self) -> Result<(), Error>;
+
+    /// `pam_close_session`.
+    fn close_session(&mut self) -> Result<(), Error>;
+}
+
+enum Event {
+    Converse(Vec<PamMessage>, std_mpsc::Sender<Option<Vec<String>>>),
+    Authenticated(Result<(), Error>),
+}
+
+enum Command {
+    OpenSession(oneshot::Sender<Result<(), Error>>),
+    CloseSession(oneshot::Sender<Result<(), Error>>),
+}
+
+/// A conversation waiting for the answers of the client.
+struct Pending {
+    messages: Vec<PamMessage>,
+    reply: std_mpsc::Sender<Option<Vec<String>>>,
+}
+
+impl Pending {
+    /// Answer the prompts in order with `answers`, and the other
+    /// messages with empty responses.
+    fn answer<I: Iterator<Item = String>>(self, mut answers: I) {
+        let responses = self
+            .messages
+            .iter()
+            .map(|message| match message {
+                PamMessage::Prompt { .. } => answers.next(),
+                _ => Some(String::new()),
+            })
+            .collect();
+        let _ = self.reply.send(responses);
+    }
+}
+
+/// A PAM handle running on a thread of its own, from authentication to
+/// the end of the session. The session is closed when this is dropped.
+pub struct Pam {
+    events: UnboundedReceiver<Event>,
+    commands: std_mpsc::Sender<Command>,
+    pending: Option<Pending>,
+    authenticated: Option<bool>,
+    session_open: bool,
+}
+
+impl std::fmt::Debug for Pam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pam")
+            .field("authenticated", &self.authenticated)
+            .field("session_open", &self.session_open)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Pam {
+    fn drop(&mut self) {
+        if self.session_open {
+            let (reply, _) = oneshot::channel();
+            let _ = self.commands.send(Command::CloseSession(reply));
+        }
+    }
+}
+
+impl Pam {
+    /// Start a PAM handle with `start`, given the conversation to use
+    /// in its conversation function, and authenticate with it.
+    pub fn start<F, C>(start: F) -> Self
+    where
+        F: FnOnce(Conversation) -> Result<C, Error> + Send + 'static,
+        C: PamContext + 'static,
+    {
+        let (events, events_receiver) = unbounded_channel();
+        let (commands, commands_receiver) = std_mpsc::channel();
+        std::thread::spawn(move || run(start, events, commands_receiver));
+        Pam {
+            events: events_receiver,
+            commands,
+            pending: None,
+            authenticated: None,
+            session_open: false,
+        }
+    }
+
+    /// Continue keyboard-interactive authentication with `response`,
+    /// the answers to the prompts of the last [`Auth::Partial`]. The
+    /// next messages of PAM are returned as another [`Auth::Partial`],
+    /// with its informational messages as instructions.
+    pub async fn keyboard_interactive(&mut self, response: Option<Response<'_>>) -> Auth {
+        if let (Some(pending), Some(response)) = (self.pending.take(), response) {
+            pending.answer(response.map(|answer| String::from_utf8_lossy(&answer).into_owned()));
+        }
+        let mut instructions = String::new();
+        loop {
+            match self.next_event().await {
+                Some(Event::Converse(messages, reply)) => {
+                    let mut prompts = Vec::new();
+                    for message in &messages {
+                        match message {
+                            PamMessage::Prompt { text, echo } => {
+                                prompts.push((Cow::Owned(text.clone()), *echo))
+                            }
+                            PamMessage::Info(text) | PamMessage::Error(text) => {
+                                instructions.push_str(text);
+                                instructions.push('\n');
+                            }
+                        }
+                    }
+                    let pending = Pending { messages, reply };
+                    if prompts.is_empty() {
+                        // Informational messages are shown with the next
+                        // prompts.
+                        pending.answer(std::iter::empty());
+                        continue;
+                    }
+                    self.pending = Some(pending);
+                    return Auth::Partial {
+                        name: Cow::Borrowed(""),
+                        instructions: instructions.into(),
+                        prompts: prompts.into(),
+                    };
+                }
+                Some(Event::Authenticated(result)) => return self.authenticated(result),
+                None => return Auth::reject(),
+            }
+        }
+    }
+
+    /// Authenticate with `password`, used to answer the prompts of PAM
+    /// which are not echoed. Prompts which would be echoed fail the
+    /// conversation.
+    pub async fn password(&mut self, password: &str) -> Auth {
+        loop {
+            match self.next_event().await {
+                Some(Event::Converse(messages, reply)) => {
+                    let responses = messages
+                        .iter()
+                        .map(|message| match message {
+                            PamMessage::Prompt { echo: false, .. } => Some(password.to_string()),
+                            PamMessage::Prompt { echo: true, .. } => None,
+                            _ => Some(String::new()),
+                        })
+                        .collect();
+                    let _ = reply.send(responses);
+                }
+                Some(Event::Authenticated(result)) => return self.authenticated(result),
+                None => return Auth::reject(),
+            }
+        }
+    }
+
+    /// Open the PAM session, once authenticated.
+    pub async fn open_session(&mut self) -> Result<(), Error> {
+        if self.authenticated != Some(true) {
+            return Err(Error::NotAuthenticated);
+        }
+        self.command(Command::OpenSession).await?;
+        self.session_open = true;
+        Ok(())
+    }
+
+    /// Close the PAM session, which is otherwise closed when this is
+    /// dropped.
+    pub async fn close_session(&mut self) -> Result<(), Error> {
+        if !self.session_open {
+            return Ok(());
+        }
+        self.session_open = false;
+        self.command(Command::CloseSession).await
+    }
+
+    async fn next_event(&mut self) -> Option<Event> {
+        if self.authenticated.is_some() {
+            return None;
+        }
+        self.events.recv().await
+    }
+
+    fn authenticated(&mut self, result: Result<(), Error>) -> Auth {
+        if let Err(ref e) = result {
+            debug!("PAM authentication failed: {e}");
+        }
+        self.authenticated = Some(result.is_ok());
+        if result.is_ok() {
+            Auth::Accept
+        } else {
+            Auth::reject()
+        }
+    }
+
+    async fn command<F: FnOnce(oneshot::Sender<Result<(), Error>>) -> Command>(
+        &self,
+        command: F,
+    ) -> Result<(), Error> {
+        let (reply, result) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .map_err(|_| Error::SendError)?;
+        result.await.map_err(|_| Error::SendError)?
+    }
+}
+
+fn run<F, C>(start: F, events: UnboundedSender<Event>, commands: std_mpsc::Receiver<Command>)
+where
+    F: FnOnce(Conversation) -> Result<C, Error>,
+    C: PamContext,
+{
+    let conversation = Conversation {
+        events: events.clone(),
+    };
+    let mut context = match start(conversation) {
+        Ok(context) => context,
+        Err(e) => {
+            let _ = events.send(Event::Authenticated(Err(e)));
+            return;
+        }
+    };
+    let result = context.authenticate().and_then(|()| context.acct_mgmt());
+    let authenticated = result.is_ok();
+    let _ = events.send(Event::Authenticated(result));
+    if !authenticated {
+        return;
+    }
+    while let Ok(command) = commands.recv() {
+        let _ = match command {
+            Command::OpenSession(reply) => reply.send(context.open_session()),
+            Command::CloseSession(reply) => reply.send(context.close_session()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use std::sync::{Arc, Mutex};
+
+    use bytes::Bytes;
+
+    use super::*;
+
+    /// Asks for a password and a code, and records the session calls.
+    struct TestPam {
+        conversation: Conversation,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl PamContext for TestPam {
+        fn authenticate(&mut self) -> Result<(), Error> {
+            let answers = self
+                .conversation
+                .converse(vec![
+                    PamMessage::Info("Welcome".into()),
+                    PamMessage::Prompt {
+                        text: "Password: ".into(),
+                        echo: false,
+                    },
+                ])
+                .ok_or(Error::Inconsistent)?;
+            if answers != ["", "secret"] {
+                return Err(Error::NotAuthenticated);
+            }
+            let answers = self
+                .conversation
+                .converse(vec![PamMessage::Prompt {
+                    text: "Code: ".into(),
+                    echo: true,
+                }])
+                .ok_or(Error::Inconsistent)?;
+            if answers != ["123456"] {
+                return Err(Error::NotAuthenticated);
+            }
+            Ok(())
+        }
+
+        fn acct_mgmt(&mut self) -> Result<(), Error> {
+            self.calls.lock().unwrap().push("acct_mgmt");
+            Ok(())
+        }
+
+        fn open_session(&mut self) -> Result<(), Error> {
+            self.calls.lock().unwrap().push("open_session");
+            Ok(())
+        }
+
+        fn close_session(&mut self) -> Result<(), Error> {
+            self.calls.lock().unwrap().push("close_session");
+            Ok(())
+        }
+    }
+
+    fn start(calls: &Arc<Mutex<Vec<&'static str>>>) -> Pam {
+        let calls = calls.clone();
+        Pam::start(move |conversation| {
+            Ok(TestPam {
+                conversation,
+                calls,
+            })
+        })
+    }
+
+    async fn answer(pam: &mut Pam, answer: &str) -> Auth {
+        let mut answers = vec![Some(Bytes::from(answer.to_string()))].into_iter();
+        pam.keyboard_interactive(Some(Response(&mut answers))).await
+    }
+
+    #[tokio::test]
+    async fn keyboard_interactive() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut pam = start(&calls);
+        assert_eq!(
+            pam.keyboard_interactive(None).await,
+            Auth::Partial {
+                name: "".into(),
+                instructions: "Welcome\n".into(),
+                prompts: vec![("Password: ".into(), false)].into(),
+            }
+        );
+        assert!(matches!(
+            answer(&mut pam, "secret").await,
+            Auth::Partial { .. }
+        ));
+        assert_eq!(answer(&mut pam, "123456").await, Auth::Accept);
+
+        pam.open_session().await.unwrap();
+        drop(pam);
+        // The session is closed by the thread of the handle.
+        for _ in 0..100 {
+            if calls.lock().unwrap().len() == 3 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["acct_mgmt", "open_session", "close_session"]
+        );
+    }
+
+    #[tokio::test]
+    async fn password() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        // The code prompt is echoed, and fails the conversation.
+        let mut pam = start(&calls);
+        assert_eq!(pam.password("secret").await, Auth::reject());
+        assert!(pam.open_session().await.is_err());
+
+        let mut pam = start(&calls);
+        assert_eq!(pam.password("wrong").await, Auth::reject());
+        assert!(calls.lock().unwrap().is_empty());
+    }
+}
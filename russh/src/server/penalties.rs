@@ -0,0 +1,371 @@
+//! Per-source penalties, as the `PerSourcePenalties` option of
+//! `sshd_config`.
+//!
+//! With [`Config::per_source_penalties`](super::Config::per_source_penalties),
+//! [`Server::run_on_listener`](super::Server::run_on_listener) penalizes
+//! the addresses of the clients which disconnect without authenticating,
+//! exceed the [login grace time](super::Config::login_grace_time), or
+//! make their session panic, and refuses new connections from them
+//! while they are penalized. The penalties of successive connections
+//! add up to a maximum, and connections are only refused once the
+//! penalty of their source reaches a minimum, so that a single typo
+//! does not lock a user out.
+//!
+//! The penalties are kept in a [`PenaltyStore`], by default a
+//! [`MemoryPenaltyStore`], which can be replaced to share them between
+//! servers:
+//!
+//! ```
+//! # use std::sync::Arc;
+//! # use std::time::Duration;
+//! # use russh::server::penalties::{MemoryPenaltyStore, PerSourcePenalties};
+//! let config = russh::server::Config {
+//!     per_source_penalties: Some(PerSourcePenalties {
+//!         auth_failure: Duration::from_secs(30),
+//!         store: Arc::new(MemoryPenaltyStore::new(1024)),
+//!         ..Default::default()
+//!     }),
+//!     ..Default::default()
+//! };
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use log::info;
+
+use super::Config;
+
+/// The number of sources remembered by the default store, as the
+/// `max-sources4` and `max-sources6` defaults of `sshd`.
+const DEFAULT_MAX_SOURCES: usize = 65536;
+
+/// The kinds of misbehaviour penalized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Penalty {
+    /// The session panicked.
+    Crash,
+    /// The client disconnected after failing to authenticate.
+    AuthFailure,
+    /// The client disconnected without trying to authenticate.
+    NoAuth,
+    /// The client did not authenticate before the login grace time.
+    GraceExceeded,
+}
+
+/// The penalty of a source, as kept in a [`PenaltyStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourcePenalty {
+    /// When the penalty expires.
+    pub expires: SystemTime,
+    /// Whether the penalty has reached the minimum, and connections from
+    /// the source are refused until it expires.
+    pub active: bool,
+}
+
+/// Storage for the penalties of sources.
+pub trait PenaltyStore: Send + Sync {
+    /// The penalty of `source`, if it has one.
+    fn get(&self, source: IpAddr) -> Option<SourcePenalty>;
+
+    /// Replace the penalty of `source`.
+    fn set(&self, source: IpAddr, penalty: SourcePenalty);
+}
+
+/// The penalties of each kind of misbehaviour, and how they add up. The
+/// defaults are those of `sshd`.
+#[derive(Clone)]
+pub struct PerSourcePenalties {
+    pub crash: Duration,
+    pub auth_failure: Duration,
+    pub no_auth: Duration,
+    pub grace_exceeded: Duration,
+    /// The maximal penalty of a source.
+    pub max: Duration,
+    /// The penalty from which connections are refused.
+    pub min: Duration,
+    pub store: Arc<dyn PenaltyStore>,
+}
+
+impl Default for PerSourcePenalties {
+    fn default() -> Self {
+        PerSourcePenalties {
+            crash: Duration::from_secs(90),
+            auth_failure: Duration::from_secs(5),
+            no_auth: Duration::from_secs(1),
+            grace_exceeded: Duration::from_secs(10),
+            max: Duration::from_secs(600),
+            min: Duration::from_secs(15),
+            store: Arc::new(MemoryPenaltyStore::new(DEFAULT_MAX_SOURCES)),
+        }
+    }
+}
+
+impl std::fmt::Debug for PerSourcePenalties {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PerSourcePenalties")
+            .field("crash", &self.crash)
+            .field("auth_failure", &self.auth_failure)
+            .field("no_auth", &self.no_auth)
+            .field("grace_exceeded", &self.grace_exceeded)
+            .field("max", &self.max)
+            .field("min", &self.min)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PerSourcePenalties {
+    /// The duration of a penalty.
+    pub fn duration(&self, penalty: Penalty) -> Duration {
+        match penalty {
+            Penalty::Crash => self.crash,
+            Penalty::AuthFailure => self.auth_failure,
+            Penalty::NoAuth => self.no_auth,
+            Penalty::GraceExceeded => self.grace_exceeded,
+        }
+    }
+
+    /// Add `penalty` to the penalty of `source`.
+    pub fn penalize(&self, source: IpAddr, penalty: Penalty) {
+        let duration = self.duration(penalty);
+        if duration.is_zero() {
+            return;
+        }
+        let now = SystemTime::now();
+        let previous = self.store.get(source).filter(|p| p.expires > now);
+        // Penalties are counted in whole seconds, as in `sshd`, for the
+        // successive ones to add up exactly.
+        let remaining = previous
+            .and_then(|p| p.expires.duration_since(now).ok())
+            .map(|d| Duration::from_secs(d.as_secs() + u64::from(d.subsec_nanos() > 0)))
+            .unwrap_or_default();
+        let total = remaining.saturating_add(duration).min(self.max);
+        let was_active = previous.is_some_and(|p| p.active);
+        let active = was_active || total >= self.min;
+        if active && !was_active {
+            info!("Refusing connections from {source} for {total:?} after {penalty:?}");
+        }
+        self.store.set(
+            source,
+            SourcePenalty {
+                expires: now + total,
+                active,
+            },
+        );
+    }
+
+    /// Whether connections from `source` are refused.
+    pub fn is_refused(&self, source: IpAddr) -> bool {
+        self.store
+            .get(source)
+            .is_some_and(|p| p.active && p.expires > SystemTime::now())
+    }
+}
+
+/// A store keeping the penalties in memory, forgetting the least
+/// recently penalized sources when it is full.
+#[derive(Debug)]
+pub struct MemoryPenaltyStore {
+    max_sources: usize,
+    sources: Mutex<Sources>,
+}
+
+#[derive(Debug, Default)]
+struct Sources {
+    penalties: HashMap<IpAddr, (SourcePenalty, u64)>,
+    /// The sources by the time they were last penalized.
+    order: BTreeMap<u64, IpAddr>,
+    time: u64,
+}
+
+impl MemoryPenaltyStore {
+    /// A store of the penalties of at most `max_sources` sources.
+    pub fn new(max_sources: usize) -> Self {
+        MemoryPenaltyStore {
+            max_sources,
+            sources: Mutex::new(Sources::default()),
+        }
+    }
+}
+
+impl PenaltyStore for MemoryPenaltyStore {
+    fn get(&self, source: IpAddr) -> Option<SourcePenalty> {
+        let Ok(sources) = self.sources.lock() else {
+            return None;
+        };
+        sources.penalties.get(&source).map(|(penalty, _)| *penalty)
+    }
+
+    fn set(&self, source: IpAddr, penalty: SourcePenalty) {
+        let Ok(mut sources) = self.sources.lock() else {
+            return;
+        };
+        let sources = &mut *sources;
+        if let Some((_, time)) = sources.penalties.remove(&source) {
+            sources.order.remove(&time);
+        }
+        while sources.penalties.len() >= self.max_sources {
+            let Some((_, oldest)) = sources.order.pop_first() else {
+                break;
+            };
+            sources.penalties.remove(&oldest);
+        }
+        if self.max_sources == 0 {
+            return;
+        }
+        sources.time += 1;
+        sources.order.insert(sources.time, source);
+        sources.penalties.insert(source, (penalty, sources.time));
+    }
+}
+
+/// How far a connection went before it ended. A connection only moves
+/// forward, so that its furthest step decides its penalty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub(crate) enum Progress {
+    Connected,
+    AuthFailed,
+    GraceExceeded,
+    Authenticated,
+}
+
+/// The progress of a connection, shared between its session and its
+/// [`SessionPenalty`].
+#[derive(Debug, Clone)]
+pub(crate) struct AuthProgress(Arc<AtomicU8>);
+
+impl AuthProgress {
+    pub(crate) fn advance(&self, progress: Progress) {
+        self.0.fetch_max(progress as u8, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u8 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Penalizes the source of a connection according to its progress when
+/// dropped, with the session.
+#[derive(Debug)]
+pub(crate) struct SessionPenalty {
+    config: Arc<Config>,
+    source: IpAddr,
+    progress: AuthProgress,
+}
+
+impl SessionPenalty {
+    pub(crate) fn new(config: Arc<Config>, source: IpAddr) -> Self {
+        SessionPenalty {
+            config,
+            source,
+            progress: AuthProgress(Arc::new(AtomicU8::new(Progress::Connected as u8))),
+        }
+    }
+
+    pub(crate) fn progress(&self) -> AuthProgress {
+        self.progress.clone()
+    }
+}
+
+impl Drop for SessionPenalty {
+    fn drop(&mut self) {
+        let Some(ref penalties) = self.config.per_source_penalties else {
+            return;
+        };
+        let penalty = if std::thread::panicking() {
+            Penalty::Crash
+        } else {
+            match self.progress.get() {
+                p if p == Progress::Authenticated as u8 => return,
+                p if p == Progress::GraceExceeded as u8 => Penalty::GraceExceeded,
+                p if p == Progress::AuthFailed as u8 => Penalty::AuthFailure,
+                _ => Penalty::NoAuth,
+            }
+        };
+        penalties.penalize(self.source, penalty);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn penalize() {
+        let penalties = PerSourcePenalties::default();
+        let source = "192.0.2.1".parse().unwrap();
+        let other = "2001:db8::1".parse().unwrap();
+        for _ in 0..2 {
+            penalties.penalize(source, Penalty::AuthFailure);
+            assert!(!penalties.is_refused(source));
+        }
+        // The third failure reaches the minimum.
+        penalties.penalize(source, Penalty::AuthFailure);
+        assert!(penalties.is_refused(source));
+        assert!(!penalties.is_refused(other));
+
+        penalties.penalize(other, Penalty::Crash);
+        assert!(penalties.is_refused(other));
+        for _ in 0..10 {
+            penalties.penalize(other, Penalty::Crash);
+        }
+        let remaining = penalties
+            .store
+            .get(other)
+            .unwrap()
+            .expires
+            .duration_since(SystemTime::now())
+            .unwrap();
+        assert!(remaining <= penalties.max);
+    }
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryPenaltyStore::new(2);
+        let penalty = SourcePenalty {
+            expires: SystemTime::now(),
+            active: true,
+        };
+        let first = "192.0.2.1".parse().unwrap();
+        let second = "192.0.2.2".parse().unwrap();
+        let third = "192.0.2.3".parse().unwrap();
+        store.set(first, penalty);
+        store.set(second, penalty);
+        // Penalizing the first source again makes the second one the
+        // least recently penalized.
+        store.set(first, penalty);
+        store.set(third, penalty);
+        assert_eq!(store.get(first), Some(penalty));
+        assert_eq!(store.get(second), None);
+        assert_eq!(store.get(third), Some(penalty));
+    }
+
+    #[test]
+    fn session_penalty() {
+        let config = Arc::new(Config {
+            per_source_penalties: Some(PerSourcePenalties {
+                min: Duration::from_secs(1),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let penalties = config.per_source_penalties.clone().unwrap();
+        let source = "192.0.2.1".parse().unwrap();
+
+        let session = SessionPenalty::new(config.clone(), source);
+        session.progress().advance(Progress::Authenticated);
+        session.progress().advance(Progress::AuthFailed);
+        drop(session);
+        assert!(!penalties.is_refused(source));
+
+        let session = SessionPenalty::new(config, source);
+        session.progress().advance(Progress::AuthFailed);
+        drop(session);
+        assert!(penalties.is_refused(source));
+    }
+}
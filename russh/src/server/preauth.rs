@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+
+/// Limits on what a client can send before it authenticates, to close
+/// the connections of the clients which flood the server or make it
+/// buffer large packets, as [`Config::preauth_limits`](super::Config::preauth_limits).
+/// The client is disconnected as soon as it exceeds one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PreAuthLimits {
+    /// The maximal length of a packet, which is buffered until it is
+    /// complete. Large public keys and certificates must fit in it.
+    pub max_packet_len: usize,
+    /// The maximal number of packets received in a second.
+    pub max_packet_rate: u32,
+    /// The maximal number of bytes received before authenticating,
+    /// which bounds the memory a client can make the server allocate
+    /// before it is known.
+    pub max_bytes: usize,
+}
+
+impl Default for PreAuthLimits {
+    fn default() -> Self {
+        PreAuthLimits {
+            max_packet_len: 32 * 1024,
+            max_packet_rate: 100,
+            max_bytes: 256 * 1024,
+        }
+    }
+}
+
+/// What is left of the [`PreAuthLimits`] of a connection.
+#[derive(Debug)]
+pub(crate) struct PreAuthBudget {
+    limits: PreAuthLimits,
+    bytes: usize,
+    second: Instant,
+    packets: u32,
+}
+
+impl PreAuthBudget {
+    pub(crate) fn new(limits: PreAuthLimits) -> Self {
+        PreAuthBudget {
+            limits,
+            bytes: 0,
+            second: Instant::now(),
+            packets: 0,
+        }
+    }
+
+    pub(crate) fn max_packet_len(&self) -> usize {
+        self.limits.max_packet_len
+    }
+
+    /// Count a packet of `len` bytes, and return the limit it exceeds,
+    /// if any.
+    pub(crate) fn packet(&mut self, len: usize) -> Result<(), &'static str> {
+        self.bytes = self.bytes.saturating_add(len);
+        if self.bytes > self.limits.max_bytes {
+            return Err("Too much data before authentication");
+        }
+        if self.second.elapsed() >= Duration::from_secs(1) {
+            self.second = Instant::now();
+            self.packets = 0;
+        }
+        self.packets += 1;
+        if self.packets > self.limits.max_packet_rate {
+            return Err("Too many packets before authentication");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn budget() {
+        let mut budget = PreAuthBudget::new(PreAuthLimits {
+            max_packet_len: 1024,
+            max_packet_rate: 3,
+            max_bytes: 4096,
+        });
+        for _ in 0..3 {
+            assert!(budget.packet(100).is_ok());
+        }
+        assert!(budget.packet(100).is_err());
+
+        let mut budget = PreAuthBudget::new(PreAuthLimits {
+            max_packet_rate: 100,
+            ..PreAuthLimits::default()
+        });
+        for _ in 0..8 {
+            assert!(budget.packet(32 * 1024).is_ok());
+        }
+        assert!(budget.packet(1).is_err());
+    }
+}
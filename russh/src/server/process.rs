@@ -0,0 +1,797 @@
+//! Running the `exec` and `shell` requests of session channels as local
+//! processes.
+//!
+//! A [`Process`] gathers the `pty-req` and `env` requests received on a
+//! channel. Once the client asks for a command or a shell, the channel
+//! is handed over to a task which pipes the data of the channel to the
+//! standard input of the process, and its standard output and error
+//! back as data and extended data. Without a pseudo-terminal, standard
+//! error is kept separate, as in OpenSSH. The task also forwards the
+//! `signal` and `window-change` requests, and the exit status or signal
+//! of the process, before closing the channel:
+//!
+//! ```no_run
+//! # use std::collections::HashMap;
+//! # use russh::server::process::Process;
+//! # use russh::server::{Handler, Msg, Session};
+//! # use russh::{Channel, ChannelId, Pty, PtyRequest, TerminalModes};
+//! struct Client {
+//!     channels: HashMap<ChannelId, (Channel<Msg>, Process)>,
+//! }
+//!
+//! impl Handler for Client {
+//!     type Error = russh::Error;
+//!
+//!     async fn channel_open_session(
+//!         &mut self,
+//!         channel: Channel<Msg>,
+//!         _: &mut Session,
+//!     ) -> Result<bool, Self::Error> {
+//!         self.channels.insert(channel.id(), (channel, Process::new()));
+//!         Ok(true)
+//!     }
+//!
+//!     async fn pty_request(
+//!         &mut self,
+//!         channel: ChannelId,
+//!         term: &str,
+//!         col_width: u32,
+//!         row_height: u32,
+//!         _: u32,
+//!         _: u32,
+//!         modes: &[(Pty, u32)],
+//!         session: &mut Session,
+//!     ) -> Result<(), Self::Error> {
+//!         if let Some((_, process)) = self.channels.get_mut(&channel) {
+//!             let request = PtyRequest::new(term)
+//!                 .size(col_width, row_height)
+//!                 .modes(TerminalModes::from(modes));
+//!             *process = std::mem::take(process).pty(request);
+//!             session.channel_success(channel)?;
+//!         }
+//!         Ok(())
+//!     }
+//!
+//!     async fn exec_request(
+//!         &mut self,
+//!         channel: ChannelId,
+//!         data: &[u8],
+//!         session: &mut Session,
+//!     ) -> Result<(), Self::Error> {
+//!         match self.channels.remove(&channel) {
+//!             Some((ch, process)) => {
+//!                 process.spawn_exec(ch, data)?;
+//!                 session.channel_success(channel)?;
+//!             }
+//!             None => session.channel_failure(channel)?,
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//! ```
+//!
+//! The processes run as the user of the server, in its environment.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+
+use log::debug;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+
+use crate::{Channel, ChannelId, ChannelMsg, Error, PtyRequest, Sig};
+
+/// The command of a session channel, with its environment and
+/// pseudo-terminal.
+#[derive(Debug, Clone, Default)]
+pub struct Process {
+    shell: Option<PathBuf>,
+    env: Vec<(OsString, OsString)>,
+    current_dir: Option<PathBuf>,
+    pty: Option<PtyRequest>,
+}
+
+impl Process {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The shell that runs the commands, with `-c`, and the `shell`
+    /// requests. The default is `/bin/sh`, or `cmd.exe` on Windows.
+    pub fn shell<P: Into<PathBuf>>(mut self, shell: P) -> Self {
+        self.shell = Some(shell.into());
+        self
+    }
+
+    /// Set an environment variable of the process, usually from an
+    /// `env` request the server accepted.
+    pub fn env<K: Into<OsString>, V: Into<OsString>>(mut self, name: K, value: V) -> Self {
+        self.env.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn current_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Run the process on a pseudo-terminal, which also sets `TERM`.
+    /// This is only supported on Unix.
+    pub fn pty(mut self, request: PtyRequest) -> Self {
+        self.pty = Some(request);
+        self
+    }
+
+    /// Run `command`, the data of an `exec` request, with the shell.
+    /// The returned task ends once the channel is closed, with the exit
+    /// status of the process.
+    pub fn spawn_exec<S>(
+        self,
+        channel: Channel<S>,
+        command: &[u8],
+    ) -> io::Result<JoinHandle<Result<ExitStatus, Error>>>
+    where
+        S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static,
+    {
+        #[cfg(unix)]
+        let command = {
+            use std::os::unix::ffi::OsStrExt;
+            std::ffi::OsStr::from_bytes(command).to_owned()
+        };
+        #[cfg(not(unix))]
+        let command = OsString::from(String::from_utf8_lossy(command).into_owned());
+        self.spawn(channel, Some(command))
+    }
+
+    /// Run the shell, for a `shell` request.
+    pub fn spawn_shell<S>(
+        self,
+        channel: Channel<S>,
+    ) -> io::Result<JoinHandle<Result<ExitStatus, Error>>>
+    where
+        S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static,
+    {
+        self.spawn(channel, None)
+    }
+
+    fn spawn<S>(
+        self,
+        channel: Channel<S>,
+        command: Option<OsString>,
+    ) -> io::Result<JoinHandle<Result<ExitStatus, Error>>>
+    where
+        S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static,
+    {
+        let shell = self
+            .shell
+            .unwrap_or_else(|| PathBuf::from(if cfg!(windows) { "cmd.exe" } else { "/bin/sh" }));
+        let mut cmd = Command::new(shell);
+        if let Some(command) = command {
+            cmd.arg(if cfg!(windows) { "/C" } else { "-c" })
+                .arg(command);
+        }
+        cmd.envs(self.env).kill_on_drop(true);
+        if let Some(dir) = self.current_dir {
+            cmd.current_dir(dir);
+        }
+        let Some(request) = self.pty else {
+            let mut child = cmd
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            let (Some(stdin), Some(stdout), Some(stderr)) =
+                (child.stdin.take(), child.stdout.take(), child.stderr.take())
+            else {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            };
+            return Ok(tokio::spawn(run(
+                channel,
+                child,
+                Box::new(stdin),
+                vec![(None, Box::new(stdout)), (Some(1), Box::new(stderr))],
+                None,
+            )));
+        };
+        #[cfg(unix)]
+        {
+            let (terminal, slave) = terminal::Terminal::open(&request)?;
+            cmd.env("TERM", &request.term)
+                .stdin(slave.try_clone()?)
+                .stdout(slave.try_clone()?)
+                .stderr(slave);
+            terminal::set_controlling(&mut cmd);
+            let child = cmd.spawn()?;
+            // Drop the copies of the slave of the parent, for reads of the
+            // master to fail once the process and its children are gone.
+            drop(cmd);
+            Ok(tokio::spawn(run(
+                channel,
+                child,
+                Box::new(terminal.io()),
+                vec![(None, Box::new(terminal.io()))],
+                Some(terminal),
+            )))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (channel, request);
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "pseudo-terminals are only supported on Unix",
+            ))
+        }
+    }
+}
+
+type Output = (Option<u32>, Box<dyn AsyncRead + Unpin + Send>);
+
+#[cfg(unix)]
+type Terminal = terminal::Terminal;
+#[cfg(not(unix))]
+type Terminal = std::convert::Infallible;
+
+async fn run<S>(
+    channel: Channel<S>,
+    mut child: Child,
+    stdin: Box<dyn AsyncWrite + Unpin + Send>,
+    outputs: Vec<Output>,
+    terminal: Option<Terminal>,
+) -> Result<ExitStatus, Error>
+where
+    S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static,
+{
+    let (mut read_half, write_half) = channel.split();
+    let outputs: Vec<_> = outputs
+        .into_iter()
+        .map(|(ext, mut output)| {
+            let mut writer = write_half.make_writer_ext(ext);
+            tokio::spawn(async move {
+                // Reading a pseudo-terminal fails once the process is
+                // gone, which ends its output like a closed pipe.
+                if let Err(e) = tokio::io::copy(&mut output, &mut writer).await {
+                    debug!("process output: {e}");
+                }
+            })
+        })
+        .collect();
+
+    let mut stdin = Some(stdin);
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status?,
+            msg = read_half.wait() => match msg {
+                Some(ChannelMsg::Data { data }) => {
+                    if let Some(input) = stdin.as_mut() {
+                        if let Err(e) = input.write_all(&data).await {
+                            debug!("process input: {e}");
+                            stdin = None;
+                        }
+                    }
+                }
+                Some(ChannelMsg::Eof) => stdin = None,
+                Some(ChannelMsg::WindowChange {
+                    col_width,
+                    row_height,
+                    pix_width,
+                    pix_height,
+                }) => {
+                    #[cfg(unix)]
+                    if let Some(ref terminal) = terminal {
+                        if let Err(e) = terminal.resize(col_width, row_height, pix_width, pix_height) {
+                            debug!("window change: {e}");
+                        }
+                    }
+                    #[cfg(not(unix))]
+                    let _ = (&terminal, col_width, row_height, pix_width, pix_height);
+                }
+                Some(ChannelMsg::Signal { signal }) => {
+                    if let Err(e) = kill(&mut child, &signal) {
+                        debug!("signal {signal:?}: {e}");
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    // The channel is gone, and so are the client and its
+                    // terminal.
+                    child.start_kill()?;
+                    return Ok(child.wait().await?);
+                }
+            }
+        }
+    };
+    drop(stdin);
+    for output in outputs {
+        output
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    }
+    drop(terminal);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            write_half
+                .exit_signal(signal_name(signal), status.core_dumped(), "")
+                .await?;
+        }
+    }
+    if let Some(code) = status.code() {
+        write_half.exit_status(code as u32).await?;
+    }
+    write_half.eof().await?;
+    write_half.close().await?;
+    Ok(status)
+}
+
+#[cfg(unix)]
+fn kill(child: &mut Child, signal: &Sig) -> io::Result<()> {
+    let number = signal_number(signal).ok_or(io::ErrorKind::Unsupported)?;
+    let pid = child.id().ok_or(io::ErrorKind::NotFound)?;
+    // SAFETY: `kill` has no memory effects.
+    if unsafe { libc::kill(pid as libc::pid_t, number) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child, signal: &Sig) -> io::Result<()> {
+    match signal {
+        Sig::KILL => child.start_kill(),
+        _ => Err(io::ErrorKind::Unsupported.into()),
+    }
+}
+
+#[cfg(unix)]
+fn signal_number(signal: &Sig) -> Option<libc::c_int> {
+    Some(match signal {
+        Sig::ABRT => libc::SIGABRT,
+        Sig::ALRM => libc::SIGALRM,
+        Sig::FPE => libc::SIGFPE,
+        Sig::HUP => libc::SIGHUP,
+        Sig::ILL => libc::SIGILL,
+        Sig::INT => libc::SIGINT,
+        Sig::KILL => libc::SIGKILL,
+        Sig::PIPE => libc::SIGPIPE,
+        Sig::QUIT => libc::SIGQUIT,
+        Sig::SEGV => libc::SIGSEGV,
+        Sig::TERM => libc::SIGTERM,
+        Sig::USR1 => libc::SIGUSR1,
+        Sig::Custom(name) => match name.as_str() {
+            "USR2" => libc::SIGUSR2,
+            "CONT" => libc::SIGCONT,
+            "STOP" => libc::SIGSTOP,
+            "TSTP" => libc::SIGTSTP,
+            "WINCH" => libc::SIGWINCH,
+            _ => return None,
+        },
+    })
+}
+
+#[cfg(unix)]
+fn signal_name(number: libc::c_int) -> Sig {
+    match number {
+        libc::SIGABRT => Sig::ABRT,
+        libc::SIGALRM => Sig::ALRM,
+        libc::SIGFPE => Sig::FPE,
+        libc::SIGHUP => Sig::HUP,
+        libc::SIGILL => Sig::ILL,
+        libc::SIGINT => Sig::INT,
+        libc::SIGKILL => Sig::KILL,
+        libc::SIGPIPE => Sig::PIPE,
+        libc::SIGQUIT => Sig::QUIT,
+        libc::SIGSEGV => Sig::SEGV,
+        libc::SIGTERM => Sig::TERM,
+        libc::SIGUSR1 => Sig::USR1,
+        libc::SIGUSR2 => Sig::Custom("USR2".into()),
+        // The name OpenSSH sends for the signals RFC 4254 does not list.
+        _ => Sig::Custom("SIG@openssh.com".into()),
+    }
+}
+
+#[cfg(unix)]
+mod terminal {
+    use std::fs::File;
+    use std::io::{self, Read, Write};
+    use std::mem::MaybeUninit;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{ready, Context, Poll};
+
+    use log::debug;
+    use tokio::io::unix::AsyncFd;
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+    use tokio::process::Command;
+
+    use crate::{Pty, PtyRequest, TerminalModes};
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const VDISABLE: libc::cc_t = 0;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const VDISABLE: libc::cc_t = 0xff;
+
+    /// The master side of a pseudo-terminal.
+    pub(super) struct Terminal {
+        master: Arc<AsyncFd<File>>,
+    }
+
+    /// A reader or writer of the master side of a pseudo-terminal.
+    pub(super) struct TerminalIo(Arc<AsyncFd<File>>);
+
+    impl Terminal {
+        /// Open a pseudo-terminal of the requested size and modes, and
+        /// return its slave side.
+        pub(super) fn open(request: &PtyRequest) -> io::Result<(Self, OwnedFd)> {
+            let (mut master, mut slave) = (-1, -1);
+            let mut size = window_size(
+                request.col_width,
+                request.row_height,
+                request.pix_width,
+                request.pix_height,
+            );
+            // SAFETY: the pointers are valid for the duration of the call,
+            // and `openpty` accepts null names and attributes.
+            if unsafe {
+                libc::openpty(
+                    &mut master,
+                    &mut slave,
+                    std::ptr::null_mut(),
+                    std::ptr::null_mut(),
+                    // Some systems take a mutable pointer.
+                    std::ptr::addr_of_mut!(size),
+                )
+            } < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: `openpty` succeeded, and these fds are ours.
+            let (master, slave) =
+                unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+            for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+                fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC)?;
+            }
+            let flags = fcntl(master.as_raw_fd(), libc::F_GETFL, 0)?;
+            fcntl(master.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK)?;
+            set_modes(slave.as_raw_fd(), &request.modes)?;
+            Ok((
+                Terminal {
+                    master: Arc::new(AsyncFd::new(master)?),
+                },
+                slave,
+            ))
+        }
+
+        pub(super) fn io(&self) -> TerminalIo {
+            TerminalIo(self.master.clone())
+        }
+
+        pub(super) fn resize(
+            &self,
+            col_width: u32,
+            row_height: u32,
+            pix_width: u32,
+            pix_height: u32,
+        ) -> io::Result<()> {
+            let size = window_size(col_width, row_height, pix_width, pix_height);
+            // SAFETY: `TIOCSWINSZ` reads a `winsize`.
+            if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    /// Start the process in a new session, with the slave of the
+    /// pseudo-terminal, its standard input, as controlling terminal.
+    pub(super) fn set_controlling(command: &mut Command) {
+        // SAFETY: `setsid` and `ioctl` are async-signal-safe.
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    fn window_size(
+        col_width: u32,
+        row_height: u32,
+        pix_width: u32,
+        pix_height: u32,
+    ) -> libc::winsize {
+        let short = |n: u32| u16::try_from(n).unwrap_or(u16::MAX);
+        libc::winsize {
+            ws_row: short(row_height),
+            ws_col: short(col_width),
+            ws_xpixel: short(pix_width),
+            ws_ypixel: short(pix_height),
+        }
+    }
+
+    fn fcntl(fd: RawFd, command: libc::c_int, arg: libc::c_int) -> io::Result<libc::c_int> {
+        // SAFETY: the commands used here take an integer argument.
+        let r = unsafe { libc::fcntl(fd, command, arg) };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(r)
+    }
+
+    /// Apply the terminal modes of a `pty-req`, ignoring the ones this
+    /// system does not have.
+    fn set_modes(fd: RawFd, modes: &TerminalModes) -> io::Result<()> {
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: `tcgetattr` fills the `termios` when it succeeds.
+        let mut termios = unsafe {
+            if libc::tcgetattr(fd, termios.as_mut_ptr()) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios.assume_init()
+        };
+        for &(mode, value) in modes.iter() {
+            if let Some(c) = control_char(mode).and_then(|i| termios.c_cc.get_mut(i)) {
+                *c = match value {
+                    255 => VDISABLE,
+                    v => v as libc::cc_t,
+                };
+                continue;
+            }
+            let size = match mode {
+                Pty::CS7 => Some(libc::CS7),
+                Pty::CS8 => Some(libc::CS8),
+                _ => None,
+            };
+            if let Some(size) = size {
+                if value != 0 {
+                    termios.c_cflag = (termios.c_cflag & !libc::CSIZE) | size;
+                }
+                continue;
+            }
+            match flag(&mut termios, mode) {
+                Some((flags, flag)) if value != 0 => *flags |= flag,
+                Some((flags, flag)) => *flags &= !flag,
+                None => debug!("unsupported terminal mode {mode:?}"),
+            }
+        }
+        // SAFETY: `termios` is initialized.
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn control_char(mode: Pty) -> Option<usize> {
+        Some(match mode {
+            Pty::VINTR => libc::VINTR,
+            Pty::VQUIT => libc::VQUIT,
+            Pty::VERASE => libc::VERASE,
+            Pty::VKILL => libc::VKILL,
+            Pty::VEOF => libc::VEOF,
+            Pty::VEOL => libc::VEOL,
+            Pty::VEOL2 => libc::VEOL2,
+            Pty::VSTART => libc::VSTART,
+            Pty::VSTOP => libc::VSTOP,
+            Pty::VSUSP => libc::VSUSP,
+            Pty::VREPRINT => libc::VREPRINT,
+            Pty::VWERASE => libc::VWERASE,
+            Pty::VLNEXT => libc::VLNEXT,
+            Pty::VDISCARD => libc::VDISCARD,
+            _ => return None,
+        })
+    }
+
+    fn flag(
+        termios: &mut libc::termios,
+        mode: Pty,
+    ) -> Option<(&mut libc::tcflag_t, libc::tcflag_t)> {
+        let flags = match mode {
+            Pty::IGNPAR => (&mut termios.c_iflag, libc::IGNPAR),
+            Pty::PARMRK => (&mut termios.c_iflag, libc::PARMRK),
+            Pty::INPCK => (&mut termios.c_iflag, libc::INPCK),
+            Pty::ISTRIP => (&mut termios.c_iflag, libc::ISTRIP),
+            Pty::INLCR => (&mut termios.c_iflag, libc::INLCR),
+            Pty::IGNCR => (&mut termios.c_iflag, libc::IGNCR),
+            Pty::ICRNL => (&mut termios.c_iflag, libc::ICRNL),
+            Pty::IXON => (&mut termios.c_iflag, libc::IXON),
+            Pty::IXANY => (&mut termios.c_iflag, libc::IXANY),
+            Pty::IXOFF => (&mut termios.c_iflag, libc::IXOFF),
+            Pty::IMAXBEL => (&mut termios.c_iflag, libc::IMAXBEL),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Pty::IUTF8 => (&mut termios.c_iflag, libc::IUTF8),
+            Pty::ISIG => (&mut termios.c_lflag, libc::ISIG),
+            Pty::ICANON => (&mut termios.c_lflag, libc::ICANON),
+            Pty::ECHO => (&mut termios.c_lflag, libc::ECHO),
+            Pty::ECHOE => (&mut termios.c_lflag, libc::ECHOE),
+            Pty::ECHOK => (&mut termios.c_lflag, libc::ECHOK),
+            Pty::ECHONL => (&mut termios.c_lflag, libc::ECHONL),
+            Pty::NOFLSH => (&mut termios.c_lflag, libc::NOFLSH),
+            Pty::TOSTOP => (&mut termios.c_lflag, libc::TOSTOP),
+            Pty::IEXTEN => (&mut termios.c_lflag, libc::IEXTEN),
+            Pty::ECHOCTL => (&mut termios.c_lflag, libc::ECHOCTL),
+            Pty::ECHOKE => (&mut termios.c_lflag, libc::ECHOKE),
+            Pty::PENDIN => (&mut termios.c_lflag, libc::PENDIN),
+            Pty::OPOST => (&mut termios.c_oflag, libc::OPOST),
+            Pty::ONLCR => (&mut termios.c_oflag, libc::ONLCR),
+            Pty::OCRNL => (&mut termios.c_oflag, libc::OCRNL),
+            Pty::ONOCR => (&mut termios.c_oflag, libc::ONOCR),
+            Pty::ONLRET => (&mut termios.c_oflag, libc::ONLRET),
+            Pty::PARENB => (&mut termios.c_cflag, libc::PARENB),
+            Pty::PARODD => (&mut termios.c_cflag, libc::PARODD),
+            _ => return None,
+        };
+        Some(flags)
+    }
+
+    impl AsyncRead for TerminalIo {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            loop {
+                let mut guard = ready!(self.0.poll_read_ready(cx))?;
+                let unfilled = buf.initialize_unfilled();
+                match guard.try_io(|master| master.get_ref().read(unfilled)) {
+                    Ok(Ok(n)) => {
+                        buf.advance(n);
+                        return Poll::Ready(Ok(()));
+                    }
+                    Ok(Err(e)) => return Poll::Ready(Err(e)),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+    }
+
+    impl AsyncWrite for TerminalIo {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            loop {
+                let mut guard = ready!(self.0.poll_write_ready(cx))?;
+                match guard.try_io(|master| master.get_ref().write(buf)) {
+                    Ok(result) => return Poll::Ready(result),
+                    Err(_would_block) => continue,
+                }
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use tokio::sync::mpsc::{self, Receiver};
+
+    use super::*;
+    use crate::channels::ChannelRef;
+    use crate::server::Msg;
+
+    fn channel() -> (Channel<Msg>, ChannelRef, Receiver<Msg>) {
+        let (sender, receiver) = mpsc::channel(16);
+        let (channel, reference) =
+            Channel::new(ChannelId(0), sender, 32768, 1 << 20, 16, Default::default());
+        (channel, reference, receiver)
+    }
+
+    /// The messages sent on the channel until it is closed, with the
+    /// data of each stream concatenated.
+    async fn replies(mut receiver: Receiver<Msg>) -> (Vec<u8>, Vec<u8>, Vec<ChannelMsg>) {
+        let (mut stdout, mut stderr, mut others) = (Vec::new(), Vec::new(), Vec::new());
+        while let Some(msg) = receiver.recv().await {
+            match msg {
+                Msg::Channel(_, ChannelMsg::Data { data }) => stdout.extend_from_slice(&data),
+                Msg::Channel(_, ChannelMsg::ExtendedData { data, ext: 1 }) => {
+                    stderr.extend_from_slice(&data)
+                }
+                Msg::Channel(_, ChannelMsg::Close) => break,
+                Msg::Channel(_, msg) => others.push(msg),
+                _ => {}
+            }
+        }
+        (stdout, stderr, others)
+    }
+
+    #[tokio::test]
+    async fn exec() {
+        let (channel, reference, receiver) = channel();
+        let task = Process::new()
+            .env("NAME", "world")
+            .spawn_exec(
+                channel,
+                b"read line; echo $line $NAME; echo err >&2; exit 3",
+            )
+            .unwrap();
+        reference
+            .send(ChannelMsg::Data {
+                data: crate::CryptoVec::from_slice(b"hello\n"),
+            })
+            .await
+            .unwrap();
+        let (stdout, stderr, others) = replies(receiver).await;
+        assert_eq!(stdout, b"hello world\n");
+        assert_eq!(stderr, b"err\n");
+        assert!(matches!(
+            others.as_slice(),
+            [ChannelMsg::ExitStatus { exit_status: 3 }, ChannelMsg::Eof]
+        ));
+        assert_eq!(task.await.unwrap().unwrap().code(), Some(3));
+    }
+
+    #[tokio::test]
+    async fn signal() {
+        let (channel, reference, receiver) = channel();
+        Process::new()
+            .spawn_exec(channel, b"exec sleep 10")
+            .unwrap();
+        reference
+            .send(ChannelMsg::Signal { signal: Sig::TERM })
+            .await
+            .unwrap();
+        let (_, _, others) = replies(receiver).await;
+        assert!(matches!(
+            others.as_slice(),
+            [
+                ChannelMsg::ExitSignal {
+                    signal_name: Sig::TERM,
+                    core_dumped: false,
+                    ..
+                },
+                ChannelMsg::Eof
+            ]
+        ));
+    }
+
+    #[tokio::test]
+    async fn pty() {
+        let (channel, reference, receiver) = channel();
+        Process::new()
+            .pty(PtyRequest::new("vt100").size(80, 24))
+            .spawn_exec(channel, b"read line; echo $TERM; stty size; test -t 2")
+            .unwrap();
+        reference
+            .send(ChannelMsg::WindowChange {
+                col_width: 100,
+                row_height: 30,
+                pix_width: 0,
+                pix_height: 0,
+            })
+            .await
+            .unwrap();
+        reference
+            .send(ChannelMsg::Data {
+                data: crate::CryptoVec::from_slice(b"\n"),
+            })
+            .await
+            .unwrap();
+        let (stdout, stderr, others) = replies(receiver).await;
+        // The input is echoed by the terminal, which translates newlines.
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            "\r\nvt100\r\n30 100\r\n"
+        );
+        assert!(stderr.is_empty());
+        assert!(matches!(
+            others.as_slice(),
+            [ChannelMsg::ExitStatus { exit_status: 0 }, ChannelMsg::Eof]
+        ));
+    }
+}
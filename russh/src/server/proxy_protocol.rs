@@ -0,0 +1,404 @@
+//! The [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt)
+//! of HAProxy, versions 1 and 2, with which load balancers tell the
+//! server the address of the client before the data of each connection.
+//!
+//! [`ProxyProtocolListener`] reads the header of the connections
+//! accepted by another [`Listener`], and returns the address of the
+//! client it contains as the peer address of the connection, which is
+//! then passed to [`Server::new_client`](super::Server::new_client) and
+//! used for [`Config::per_source_penalties`](super::Config::per_source_penalties):
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use russh::server::proxy_protocol::ProxyProtocolListener;
+//! # use russh::server::Server;
+//! # async fn run<S: Server + Send>(mut server: S, config: Arc<russh::server::Config>) -> std::io::Result<()> {
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:2222").await?;
+//! server
+//!     .run_on_listener(config, ProxyProtocolListener::new(listener))
+//!     .await
+//! # }
+//! ```
+//!
+//! Connections without a valid header are dropped. Since the header is
+//! trusted, the server must only be reachable through the load
+//! balancer, or clients could pretend to connect from any address.
+//!
+//! Sessions started with [`run_stream`](super::run_stream) can call
+//! [`read_header`] on their stream first.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::debug;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::transport::Listener;
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// The longest header of version 1, including the final CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// The addresses of a connection relayed by a proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// The address of the client.
+    pub source: SocketAddr,
+    /// The address the client connected to, on the proxy.
+    pub destination: SocketAddr,
+}
+
+/// Read a PROXY protocol header of version 1 or 2 from `stream`, and
+/// nothing more. Returns `None` if the header does not relay a TCP
+/// connection, such as for the health checks of the proxy, in which
+/// case the address of the connection itself should be used.
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<Option<ProxyHeader>> {
+    // Both the signature of version 2 and the shortest header of
+    // version 1 are at least 12 bytes long.
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+    if &start == V2_SIGNATURE {
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).await?;
+        let [version_command, family, len @ ..] = header;
+        let mut addresses = vec![0; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut addresses).await?;
+        parse_v2(version_command, family, &addresses)
+    } else if start.starts_with(b"PROXY ") {
+        // Read byte by byte, not to read past the header.
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(invalid("header too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        parse_v1(&line)
+    } else {
+        Err(invalid("missing header"))
+    }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid PROXY protocol header: {reason}"),
+    )
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    let line = std::str::from_utf8(line)
+        .ok()
+        .and_then(|l| l.strip_suffix("\r\n"))
+        .ok_or_else(|| invalid("not text"))?;
+    let mut fields = line.split(' ').skip(1);
+    let ipv6 = match fields.next() {
+        Some("TCP4") => false,
+        Some("TCP6") => true,
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("unknown protocol")),
+    };
+    let mut next = || fields.next().ok_or_else(|| invalid("missing address"));
+    let (source, destination) = (next()?, next()?);
+    let (source_port, destination_port) = (next()?, next()?);
+    let address = |ip: &str, port: &str| -> io::Result<SocketAddr> {
+        let ip: IpAddr = ip.parse().map_err(|_| invalid("bad address"))?;
+        let port = port.parse().map_err(|_| invalid("bad port"))?;
+        if ip.is_ipv6() != ipv6 {
+            return Err(invalid("wrong address family"));
+        }
+        Ok(SocketAddr::new(ip, port))
+    };
+    let header = ProxyHeader {
+        source: address(source, source_port)?,
+        destination: address(destination, destination_port)?,
+    };
+    if fields.next().is_some() {
+        return Err(invalid("trailing data"));
+    }
+    Ok(Some(header))
+}
+
+fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> io::Result<Option<ProxyHeader>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("unknown version"));
+    }
+    match version_command & 0xf {
+        // LOCAL, sent by the proxy on its own behalf.
+        0 => return Ok(None),
+        // PROXY
+        1 => {}
+        _ => return Err(invalid("unknown command")),
+    }
+    let header = match family >> 4 {
+        // AF_INET
+        1 => {
+            let a: &[u8; 12] = addresses
+                .get(..12)
+                .and_then(|a| a.try_into().ok())
+                .ok_or_else(|| invalid("truncated address"))?;
+            let [s0, s1, s2, s3, d0, d1, d2, d3, sp0, sp1, dp0, dp1] = *a;
+            ProxyHeader {
+                source: SocketAddr::new(
+                    Ipv4Addr::new(s0, s1, s2, s3).into(),
+                    u16::from_be_bytes([sp0, sp1]),
+                ),
+                destination: SocketAddr::new(
+                    Ipv4Addr::new(d0, d1, d2, d3).into(),
+                    u16::from_be_bytes([dp0, dp1]),
+                ),
+            }
+        }
+        // AF_INET6
+        2 => {
+            let a: &[u8; 36] = addresses
+                .get(..36)
+                .and_then(|a| a.try_into().ok())
+                .ok_or_else(|| invalid("truncated address"))?;
+            let (source, rest) = a.split_at(16);
+            let (destination, ports) = rest.split_at(16);
+            let ip = |b: &[u8]| -> io::Result<IpAddr> {
+                let b: [u8; 16] = b.try_into().map_err(|_| invalid("truncated address"))?;
+                Ok(Ipv6Addr::from(b).into())
+            };
+            let [sp0, sp1, dp0, dp1] =
+                ports.try_into().map_err(|_| invalid("truncated address"))?;
+            ProxyHeader {
+                source: SocketAddr::new(ip(source)?, u16::from_be_bytes([sp0, sp1])),
+                destination: SocketAddr::new(ip(destination)?, u16::from_be_bytes([dp0, dp1])),
+            }
+        }
+        // AF_UNSPEC or AF_UNIX, without an IP address.
+        _ => return Ok(None),
+    };
+    Ok(Some(header))
+}
+
+type Reading<S> = BoxFuture<'static, (Option<SocketAddr>, io::Result<(S, Option<SocketAddr>)>)>;
+
+/// A [`Listener`] reading the PROXY protocol header of the connections
+/// accepted by another one, and returning the address of the client as
+/// their peer address.
+///
+/// Headers are read concurrently, so that slow connections do not
+/// delay the others, and connections are dropped if their header is
+/// invalid or not received within the timeout.
+pub struct ProxyProtocolListener<L: Listener> {
+    listener: L,
+    timeout: Duration,
+    reading: FuturesUnordered<Reading<L::Stream>>,
+}
+
+impl<L: Listener> std::fmt::Debug for ProxyProtocolListener<L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyProtocolListener")
+            .field("timeout", &self.timeout)
+            .field("reading", &self.reading.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<L: Listener> ProxyProtocolListener<L> {
+    /// Read the headers of the connections of `listener`, within 10
+    /// seconds.
+    pub fn new(listener: L) -> Self {
+        ProxyProtocolListener {
+            listener,
+            timeout: Duration::from_secs(10),
+            reading: FuturesUnordered::new(),
+        }
+    }
+
+    /// Drop the connections whose header is not received after
+    /// `timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The listener whose connections are accepted.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+}
+
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+impl<L: Listener> Listener for ProxyProtocolListener<L> {
+    type Stream = L::Stream;
+
+    #[allow(clippy::manual_async_fn)]
+    fn accept(
+        &mut self,
+    ) -> impl std::future::Future<Output = io::Result<(Self::Stream, Option<SocketAddr>)>> + Send
+    {
+        async move {
+            loop {
+                tokio::select! {
+                    accepted = self.listener.accept() => {
+                        let (mut stream, peer_addr) = accepted?;
+                        let timeout = self.timeout;
+                        self.reading.push(Box::pin(async move {
+                            let header = match tokio::time::timeout(timeout, read_header(&mut stream)).await {
+                                Ok(header) => header,
+                                Err(_) => Err(io::ErrorKind::TimedOut.into()),
+                            };
+                            let accepted = header.map(|header| match header {
+                                Some(header) => {
+                                    debug!("connection from {peer_addr:?} relays {}", header.source);
+                                    (stream, Some(header.source))
+                                }
+                                None => (stream, peer_addr),
+                            });
+                            (peer_addr, accepted)
+                        }));
+                    }
+                    Some((peer_addr, accepted)) = self.reading.next() => match accepted {
+                        Ok(accepted) => return Ok(accepted),
+                        Err(e) => debug!("dropping connection from {peer_addr:?}: {e}"),
+                    },
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    async fn read(data: &[u8]) -> (io::Result<Option<ProxyHeader>>, Vec<u8>) {
+        let mut data = data;
+        let header = read_header(&mut data).await;
+        (header, data.to_vec())
+    }
+
+    #[tokio::test]
+    async fn v1() {
+        let (header, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 22\r\nSSH-2.0-").await;
+        assert_eq!(
+            header.unwrap(),
+            Some(ProxyHeader {
+                source: "192.0.2.1:56324".parse().unwrap(),
+                destination: "198.51.100.2:22".parse().unwrap(),
+            })
+        );
+        assert_eq!(rest, b"SSH-2.0-");
+
+        let (header, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 1024 22\r\n").await;
+        assert_eq!(
+            header.unwrap().unwrap().source,
+            "[2001:db8::1]:1024".parse().unwrap()
+        );
+
+        let (header, rest) = read(b"PROXY UNKNOWN\r\nSSH-2.0-").await;
+        assert_eq!(header.unwrap(), None);
+        assert_eq!(rest, b"SSH-2.0-");
+
+        for invalid in [
+            &b"SSH-2.0-OpenSSH_9.9\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 1\r\n",
+            b"PROXY TCP4 192.0.2.1 192.0.2.2 1 65536\r\n",
+        ] {
+            let (header, _) = read(invalid).await;
+            assert_eq!(header.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+        let mut long = b"PROXY UNKNOWN ".to_vec();
+        long.resize(200, b'a');
+        assert!(read(&long).await.0.is_err());
+    }
+
+    #[tokio::test]
+    async fn v2() {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([
+            0x21, 0x11, 0, 12, 192, 0, 2, 1, 198, 51, 100, 2, 0xdc, 0x04, 0, 22,
+        ]);
+        data.extend(b"SSH-2.0-");
+        let (header, rest) = read(&data).await;
+        assert_eq!(
+            header.unwrap(),
+            Some(ProxyHeader {
+                source: "192.0.2.1:56324".parse().unwrap(),
+                destination: "198.51.100.2:22".parse().unwrap(),
+            })
+        );
+        assert_eq!(rest, b"SSH-2.0-");
+
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x21, 0x21, 0, 39]);
+        data.extend("2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        data.extend("2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        // Ports, followed by a TLV which is skipped.
+        data.extend([4, 0, 0, 22, 0x04, 0, 0]);
+        let (header, rest) = read(&data).await;
+        assert_eq!(
+            header.unwrap().unwrap().source,
+            "[2001:db8::1]:1024".parse().unwrap()
+        );
+        assert!(rest.is_empty());
+
+        // LOCAL
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x20, 0x00, 0, 0]);
+        assert_eq!(read(&data).await.0.unwrap(), None);
+
+        // A truncated address.
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x21, 0x11, 0, 4, 192, 0, 2, 1]);
+        assert!(read(&data).await.0.is_err());
+    }
+
+    struct Pipes(tokio::sync::mpsc::UnboundedReceiver<tokio::io::DuplexStream>);
+
+    impl Listener for Pipes {
+        type Stream = tokio::io::DuplexStream;
+
+        async fn accept(&mut self) -> io::Result<(Self::Stream, Option<SocketAddr>)> {
+            match self.0.recv().await {
+                Some(stream) => Ok((stream, None)),
+                None => Err(io::ErrorKind::NotConnected.into()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn listener() {
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        let mut listener =
+            ProxyProtocolListener::new(Pipes(pipes_recv)).with_timeout(Duration::from_millis(100));
+
+        // A connection sending its header slowly does not delay the
+        // others.
+        let (mut slow, server_end) = tokio::io::duplex(1024);
+        pipes.send(server_end).unwrap();
+        slow.write_all(b"PROXY TCP4 192.0.2.1").await.unwrap();
+        // An invalid header is dropped.
+        let (mut invalid, server_end) = tokio::io::duplex(1024);
+        pipes.send(server_end).unwrap();
+        invalid.write_all(b"SSH-2.0-client\r\n").await.unwrap();
+        let (mut fast, server_end) = tokio::io::duplex(1024);
+        pipes.send(server_end).unwrap();
+        fast.write_all(b"PROXY TCP4 192.0.2.2 192.0.2.3 1024 22\r\n")
+            .await
+            .unwrap();
+
+        let (_, addr) = listener.accept().await.unwrap();
+        assert_eq!(addr, Some("192.0.2.2:1024".parse().unwrap()));
+        // The slow one is dropped at the timeout.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(300), listener.accept())
+                .await
+                .is_err()
+        );
+        let mut buf = [0; 1];
+        assert_eq!(slow.read(&mut buf).await.unwrap(), 0);
+        assert_eq!(invalid.read(&mut buf).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,484 @@
+//! Recording of the terminal sessions of a server, for audit.
+//!
+//! A [`Recorder`] set in [`Config::recorder`](super::Config::recorder)
+//! is asked for a [`Recording`] of each session channel, once the client
+//! requests a terminal, a shell, a command or a subsystem on it. The
+//! recording then receives these requests, the data sent to the client
+//! and, if it wants them, the data received from the client, with the
+//! time elapsed since the recording started.
+//!
+//! [`Asciicast`] writes recordings in the
+//! [asciicast v2](https://docs.asciinema.org/manual/asciicast/v2/)
+//! format, which `asciinema play` replays:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use russh::server::recording::{Asciicast, Recorder, Recording};
+//! # use russh::ChannelId;
+//! struct Files;
+//!
+//! impl Recorder for Files {
+//!     fn start(&self, user: &str, channel: ChannelId) -> Option<Box<dyn Recording>> {
+//!         let path = format!("/var/log/ssh/{user}-{channel}.cast");
+//!         let file = std::fs::File::create(path).ok()?;
+//!         Some(Box::new(Asciicast::new(file)))
+//!     }
+//! }
+//!
+//! let config = russh::server::Config {
+//!     recorder: Some(Arc::new(Files)),
+//!     ..Default::default()
+//! };
+//! ```
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::ChannelId;
+
+/// What happens on a recorded channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// The client requested a terminal.
+    Pty {
+        term: &'a str,
+        cols: u32,
+        rows: u32,
+    },
+    /// The terminal of the client was resized.
+    WindowChange {
+        cols: u32,
+        rows: u32,
+    },
+    Shell,
+    Exec(&'a [u8]),
+    Subsystem(&'a str),
+    /// Data received from the client.
+    Input(&'a [u8]),
+    /// Data sent to the client, on the extended stream `ext` if it is
+    /// set, usually standard error.
+    Output {
+        ext: Option<u32>,
+        data: &'a [u8],
+    },
+    /// The client closed the channel, or the session ended.
+    Close,
+}
+
+/// Creates the recordings of the channels of a server.
+pub trait Recorder: Send + Sync {
+    /// Start recording `channel`, opened by `user`, or return `None` to
+    /// leave it unrecorded.
+    fn start(&self, user: &str, channel: ChannelId) -> Option<Box<dyn Recording>>;
+}
+
+/// The recording of a channel.
+pub trait Recording: Send {
+    /// Record `event`, which happened `time` after the recording
+    /// started.
+    fn record(&mut self, time: Duration, event: &Event<'_>);
+
+    /// Whether to record the data received from the client, which
+    /// includes the passwords typed in the terminal. Not by default.
+    fn records_input(&self) -> bool {
+        false
+    }
+}
+
+/// A recording in the asciicast v2 format, written to `W`.
+///
+/// The header is written once the client starts a shell, a command or
+/// a subsystem, with the size and type of its terminal if it requested
+/// one. Commands and subsystems are recorded as the `command` of the
+/// header, resizes as `r` events, and the data of both standard output
+/// and standard error as `o` events.
+pub struct Asciicast<W: Write + Send> {
+    writer: W,
+    input: bool,
+    term: Option<String>,
+    size: (u32, u32),
+    started: bool,
+    failed: bool,
+    /// The incomplete UTF-8 sequences at the end of the output and the
+    /// input.
+    pending_output: Vec<u8>,
+    pending_input: Vec<u8>,
+}
+
+impl<W: Write + Send> std::fmt::Debug for Asciicast<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Asciicast")
+            .field("input", &self.input)
+            .field("term", &self.term)
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W: Write + Send> Asciicast<W> {
+    pub fn new(writer: W) -> Self {
+        Asciicast {
+            writer,
+            input: false,
+            term: None,
+            size: (80, 24),
+            started: false,
+            failed: false,
+            pending_output: Vec::new(),
+            pending_input: Vec::new(),
+        }
+    }
+
+    /// Also record the data received from the client, as `i` events.
+    pub fn with_input(mut self) -> Self {
+        self.input = true;
+        self
+    }
+
+    /// The writer, once the recording is over.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_header(&mut self, command: Option<&str>) {
+        self.started = true;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut header = format!(
+            "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {timestamp}",
+            self.size.0, self.size.1
+        );
+        if let Some(command) = command {
+            header.push_str(", \"command\": ");
+            json_string(&mut header, command);
+        }
+        if let Some(ref term) = self.term {
+            header.push_str(", \"env\": {\"TERM\": ");
+            json_string(&mut header, term);
+            header.push('}');
+        }
+        header.push('}');
+        self.write_line(&header);
+    }
+
+    fn write_event(&mut self, time: Duration, code: &str, data: &str) {
+        if !self.started {
+            self.write_header(None);
+        }
+        let mut line = format!("[{:.6}, \"{code}\", ", time.as_secs_f64());
+        json_string(&mut line, data);
+        line.push(']');
+        self.write_line(&line);
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = writeln!(self.writer, "{line}") {
+            debug!("could not write recording: {e}");
+            self.failed = true;
+        }
+    }
+}
+
+impl<W: Write + Send> Recording for Asciicast<W> {
+    fn record(&mut self, time: Duration, event: &Event<'_>) {
+        match *event {
+            Event::Pty { term, cols, rows } => {
+                self.term = Some(term.to_string());
+                self.size = (cols, rows);
+            }
+            Event::WindowChange { cols, rows } => {
+                self.size = (cols, rows);
+                if self.started {
+                    self.write_event(time, "r", &format!("{cols}x{rows}"));
+                }
+            }
+            Event::Shell if !self.started => self.write_header(None),
+            Event::Exec(command) if !self.started => {
+                self.write_header(Some(&String::from_utf8_lossy(command)))
+            }
+            Event::Subsystem(name) if !self.started => self.write_header(Some(name)),
+            Event::Shell | Event::Exec(_) | Event::Subsystem(_) => {}
+            Event::Input(data) => {
+                let data = decode_utf8(&mut self.pending_input, data);
+                if !data.is_empty() {
+                    self.write_event(time, "i", &data)
+                }
+            }
+            Event::Output { data, .. } => {
+                let data = decode_utf8(&mut self.pending_output, data);
+                if !data.is_empty() {
+                    self.write_event(time, "o", &data)
+                }
+            }
+            Event::Close => {
+                if let Err(e) = self.writer.flush() {
+                    debug!("could not write recording: {e}");
+                }
+            }
+        }
+    }
+
+    fn records_input(&self) -> bool {
+        self.input
+    }
+}
+
+/// Decode `data` after the incomplete sequence of the previous call in
+/// `pending`, keeping the incomplete sequence at its end for the next
+/// one.
+fn decode_utf8(pending: &mut Vec<u8>, data: &[u8]) -> String {
+    pending.extend_from_slice(data);
+    let mut decoded = String::new();
+    let mut rest = pending.as_slice();
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(s) => {
+                decoded.push_str(s);
+                rest = &[];
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                decoded.push_str(&String::from_utf8_lossy(valid));
+                match e.error_len() {
+                    Some(len) => {
+                        decoded.push(char::REPLACEMENT_CHARACTER);
+                        rest = after.get(len..).unwrap_or_default();
+                    }
+                    None => {
+                        rest = after;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    let rest = rest.to_vec();
+    *pending = rest;
+    decoded
+}
+
+/// Append `s` to `out` as a JSON string.
+fn json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' || c == '\u{7f}' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+struct ChannelRecording {
+    started: Instant,
+    recording: Box<dyn Recording>,
+}
+
+/// The recordings of the channels of a session, closed when dropped.
+pub(crate) struct Recordings {
+    recorder: Arc<dyn Recorder>,
+    channels: HashMap<ChannelId, ChannelRecording>,
+}
+
+impl std::fmt::Debug for Recordings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recordings")
+            .field("channels", &self.channels.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Recordings {
+    pub(crate) fn new(recorder: Arc<dyn Recorder>) -> Self {
+        Recordings {
+            recorder,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Record a request of `user` on `channel`, starting its recording
+    /// if needed.
+    pub(crate) fn request(&mut self, user: &str, channel: ChannelId, event: Event<'_>) {
+        if let Entry::Vacant(entry) = self.channels.entry(channel) {
+            let Some(recording) = self.recorder.start(user, channel) else {
+                return;
+            };
+            entry.insert(ChannelRecording {
+                started: Instant::now(),
+                recording,
+            });
+        }
+        self.record(channel, event)
+    }
+
+    /// Record an event of `channel`, if it is recorded.
+    pub(crate) fn record(&mut self, channel: ChannelId, event: Event<'_>) {
+        if let Some(c) = self.channels.get_mut(&channel) {
+            if matches!(event, Event::Input(_)) && !c.recording.records_input() {
+                return;
+            }
+            c.recording.record(c.started.elapsed(), &event);
+        }
+    }
+
+    pub(crate) fn close(&mut self, channel: ChannelId) {
+        if let Some(mut c) = self.channels.remove(&channel) {
+            c.recording.record(c.started.elapsed(), &Event::Close);
+        }
+    }
+}
+
+impl Drop for Recordings {
+    fn drop(&mut self) {
+        for (_, mut c) in self.channels.drain() {
+            c.recording.record(c.started.elapsed(), &Event::Close);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn asciicast() {
+        let mut cast = Asciicast::new(Vec::new()).with_input();
+        let t = Duration::from_millis(1500);
+        cast.record(
+            t,
+            &Event::Pty {
+                term: "xterm",
+                cols: 100,
+                rows: 30,
+            },
+        );
+        cast.record(t, &Event::Exec(b"ls \"-l\""));
+        cast.record(t, &Event::Input(b"y\r"));
+        // A character split between two packets.
+        cast.record(
+            t,
+            &Event::Output {
+                ext: None,
+                data: b"\xc3",
+            },
+        );
+        cast.record(
+            t,
+            &Event::Output {
+                ext: Some(1),
+                data: b"\xa9\x1b[0m\n",
+            },
+        );
+        cast.record(t, &Event::WindowChange { cols: 80, rows: 24 });
+        cast.record(t, &Event::Close);
+
+        let cast = String::from_utf8(cast.into_inner()).unwrap();
+        let mut lines = cast.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with("{\"version\": 2, \"width\": 100, \"height\": 30, "));
+        assert!(
+            header.ends_with(", \"command\": \"ls \\\"-l\\\"\", \"env\": {\"TERM\": \"xterm\"}}")
+        );
+        assert_eq!(
+            lines.collect::<Vec<_>>(),
+            [
+                "[1.500000, \"i\", \"y\\r\"]",
+                "[1.500000, \"o\", \"é\\u001b[0m\\n\"]",
+                "[1.500000, \"r\", \"80x24\"]",
+            ]
+        );
+    }
+
+    #[test]
+    fn decode() {
+        let mut pending = Vec::new();
+        assert_eq!(decode_utf8(&mut pending, b"a\xe2\x82"), "a");
+        assert_eq!(decode_utf8(&mut pending, b"\xacb\xffc"), "€b\u{fffd}c");
+        assert!(pending.is_empty());
+    }
+
+    #[derive(Clone, Default)]
+    struct Events(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl Recorder for Events {
+        fn start(&self, user: &str, channel: ChannelId) -> Option<Box<dyn Recording>> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("start {user} {channel}"));
+            Some(Box::new(self.clone()))
+        }
+    }
+
+    impl Recording for Events {
+        fn record(&mut self, _: Duration, event: &Event<'_>) {
+            self.0.lock().unwrap().push(format!("{event:?}"));
+        }
+    }
+
+    #[test]
+    fn recordings() {
+        let events = Events::default();
+        let mut recordings = Recordings::new(Arc::new(events.clone()));
+        let channel = ChannelId(1);
+        // Channels are only recorded once they start a session.
+        recordings.record(
+            channel,
+            Event::Output {
+                ext: None,
+                data: b"a",
+            },
+        );
+        recordings.request("alice", channel, Event::Shell);
+        recordings.record(channel, Event::Input(b"b"));
+        recordings.record(
+            channel,
+            Event::Output {
+                ext: None,
+                data: b"c",
+            },
+        );
+        recordings.request("alice", ChannelId(2), Event::Subsystem("sftp"));
+        recordings.close(channel);
+        recordings.record(
+            channel,
+            Event::Output {
+                ext: None,
+                data: b"d",
+            },
+        );
+        drop(recordings);
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            [
+                "start alice 1",
+                "Shell",
+                "Output { ext: None, data: [99] }",
+                "start alice 2",
+                "Subsystem(\"sftp\")",
+                "Close",
+                "Close",
+            ]
+        );
+    }
+}
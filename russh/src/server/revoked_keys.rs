@@ -0,0 +1,464 @@
+//! Revoked client keys, as the `RevokedKeys` option of `sshd_config`.
+//!
+//! A [`Krl`] is parsed from an OpenSSH key revocation list, as
+//! generated by `ssh-keygen -k` and described in `PROTOCOL.krl`, or from
+//! a list of public keys, one per line. It revokes plain keys, by
+//! value or fingerprint, and certificates by serial number or key ID
+//! for their certificate authority. A certificate is also revoked when
+//! its key or the key of its authority is.
+//!
+//! With [`Config::revoked_keys`](super::Config::revoked_keys), the
+//! public keys and certificates revoked are rejected before reaching
+//! the handler. The list is shared with the server, and can be replaced
+//! while it runs, for instance when the file changes:
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use russh::server::revoked_keys::RevokedKeys;
+//! # fn run() -> Result<(), russh::keys::Error> {
+//! let revoked_keys = Arc::new(RevokedKeys::from_path("/etc/ssh/revoked_keys")?);
+//! let config = russh::server::Config {
+//!     revoked_keys: Some(revoked_keys.clone()),
+//!     ..Default::default()
+//! };
+//! // Later, on SIGHUP:
+//! revoked_keys.reload("/etc/ssh/revoked_keys")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The signatures of KRLs are not verified.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+use log::debug;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use ssh_encoding::Decode;
+use ssh_key::public::KeyData;
+use ssh_key::{Certificate, PublicKey};
+
+use crate::keys::{parse_public_key_base64, Error};
+
+const KRL_MAGIC: &[u8] = b"SSHKRL\n\0";
+const KRL_FORMAT_VERSION: u32 = 1;
+
+const SECTION_CERTIFICATES: u8 = 1;
+const SECTION_EXPLICIT_KEY: u8 = 2;
+const SECTION_FINGERPRINT_SHA1: u8 = 3;
+const SECTION_SIGNATURE: u8 = 4;
+const SECTION_FINGERPRINT_SHA256: u8 = 5;
+
+const SECTION_CERT_SERIAL_LIST: u8 = 0x20;
+const SECTION_CERT_SERIAL_RANGE: u8 = 0x21;
+const SECTION_CERT_SERIAL_BITMAP: u8 = 0x22;
+const SECTION_CERT_KEY_ID: u8 = 0x23;
+
+/// The certificates revoked for a certificate authority.
+#[derive(Debug, Clone, Default)]
+struct RevokedCertificates {
+    /// The authority, or `None` for the certificates of any authority.
+    ca: Option<Vec<u8>>,
+    /// Inclusive ranges of serial numbers.
+    serials: Vec<(u64, u64)>,
+    key_ids: BTreeSet<String>,
+}
+
+/// A key revocation list.
+#[derive(Debug, Clone, Default)]
+pub struct Krl {
+    /// The version number of the list, increasing with each update.
+    pub version: u64,
+    pub comment: String,
+    /// Key blobs.
+    keys: HashSet<Vec<u8>>,
+    sha1: HashSet<Vec<u8>>,
+    sha256: HashSet<Vec<u8>>,
+    certificates: Vec<RevokedCertificates>,
+}
+
+fn decode<T: Decode>(r: &mut &[u8]) -> Result<T, Error> {
+    T::decode(r).map_err(|_| Error::KeyIsCorrupt)
+}
+
+fn key_blob(key: &KeyData) -> Option<Vec<u8>> {
+    PublicKey::from(key.clone()).to_bytes().ok()
+}
+
+impl Krl {
+    /// An empty list, revoking nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a binary KRL, or a list of public keys in the format of
+    /// `authorized_keys` files, without options. Unparsable lines of the
+    /// latter are skipped.
+    pub fn parse(contents: &[u8]) -> Result<Self, Error> {
+        if contents.starts_with(KRL_MAGIC) {
+            return Self::parse_krl(contents);
+        }
+        let contents = std::str::from_utf8(contents).map_err(|_| Error::KeyIsCorrupt)?;
+        let mut krl = Krl::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (Some(_algorithm), Some(key)) = (fields.next(), fields.next()) else {
+                debug!("revoked keys:{}: missing fields", n + 1);
+                continue;
+            };
+            match parse_public_key_base64(key) {
+                Ok(key) => krl.revoke_key(&key),
+                Err(e) => debug!("revoked keys:{}: {e:?}", n + 1),
+            }
+        }
+        Ok(krl)
+    }
+
+    fn parse_krl(contents: &[u8]) -> Result<Self, Error> {
+        #[allow(clippy::indexing_slicing)] // length checked
+        let mut r = &contents[KRL_MAGIC.len()..];
+        if decode::<u32>(&mut r)? != KRL_FORMAT_VERSION {
+            return Err(Error::KeyIsCorrupt);
+        }
+        let mut krl = Krl {
+            version: decode(&mut r)?,
+            ..Default::default()
+        };
+        let _generated_date: u64 = decode(&mut r)?;
+        let _flags: u64 = decode(&mut r)?;
+        let _reserved: Vec<u8> = decode(&mut r)?;
+        krl.comment = decode(&mut r)?;
+        while !r.is_empty() {
+            let section_type: u8 = decode(&mut r)?;
+            let section: Vec<u8> = decode(&mut r)?;
+            let mut s = section.as_slice();
+            match section_type {
+                SECTION_CERTIFICATES => krl.certificates.push(parse_certificates(&mut s)?),
+                SECTION_EXPLICIT_KEY => {
+                    while !s.is_empty() {
+                        let blob: Vec<u8> = decode(&mut s)?;
+                        krl.keys.insert(blob);
+                    }
+                }
+                SECTION_FINGERPRINT_SHA1 | SECTION_FINGERPRINT_SHA256 => {
+                    let (fingerprints, len) = if section_type == SECTION_FINGERPRINT_SHA1 {
+                        (&mut krl.sha1, 20)
+                    } else {
+                        (&mut krl.sha256, 32)
+                    };
+                    while !s.is_empty() {
+                        let fingerprint: Vec<u8> = decode(&mut s)?;
+                        if fingerprint.len() != len {
+                            return Err(Error::KeyIsCorrupt);
+                        }
+                        fingerprints.insert(fingerprint);
+                    }
+                }
+                // Only the signatures follow.
+                SECTION_SIGNATURE => break,
+                t => debug!("KRL: unsupported section {t}"),
+            }
+        }
+        Ok(krl)
+    }
+
+    /// Revoke `key`, and all the certificates of the key.
+    pub fn revoke_key(&mut self, key: &PublicKey) {
+        if let Ok(blob) = key.to_bytes() {
+            self.keys.insert(blob);
+        }
+    }
+
+    /// Whether `key` is revoked.
+    pub fn is_revoked(&self, key: &PublicKey) -> bool {
+        self.is_key_data_revoked(key.key_data())
+    }
+
+    fn is_key_data_revoked(&self, key: &KeyData) -> bool {
+        let Some(blob) = key_blob(key) else {
+            return false;
+        };
+        self.keys.contains(&blob)
+            || (!self.sha1.is_empty() && self.sha1.contains(Sha1::digest(&blob).as_slice()))
+            || (!self.sha256.is_empty() && self.sha256.contains(Sha256::digest(&blob).as_slice()))
+    }
+
+    /// Whether `certificate` is revoked, by its serial number or key ID,
+    /// or because its key or the key of its authority is.
+    pub fn is_certificate_revoked(&self, certificate: &Certificate) -> bool {
+        if self.is_key_data_revoked(certificate.public_key())
+            || self.is_key_data_revoked(certificate.signature_key())
+        {
+            return true;
+        }
+        let ca = key_blob(certificate.signature_key());
+        self.certificates
+            .iter()
+            .filter(|revoked| revoked.ca.is_none() || revoked.ca == ca)
+            .any(|revoked| {
+                revoked.key_ids.contains(certificate.key_id())
+                    // Certificates without serial number are only
+                    // revoked by key ID.
+                    || (certificate.serial() != 0
+                        && revoked
+                            .serials
+                            .iter()
+                            .any(|&(min, max)| (min..=max).contains(&certificate.serial())))
+            })
+    }
+}
+
+fn parse_certificates(r: &mut &[u8]) -> Result<RevokedCertificates, Error> {
+    let ca: Vec<u8> = decode(r)?;
+    let _reserved: Vec<u8> = decode(r)?;
+    let mut revoked = RevokedCertificates {
+        ca: (!ca.is_empty()).then_some(ca),
+        ..Default::default()
+    };
+    while !r.is_empty() {
+        let section_type: u8 = decode(r)?;
+        let section: Vec<u8> = decode(r)?;
+        let mut s = section.as_slice();
+        match section_type {
+            SECTION_CERT_SERIAL_LIST => {
+                while !s.is_empty() {
+                    let serial: u64 = decode(&mut s)?;
+                    revoked.serials.push((serial, serial));
+                }
+            }
+            SECTION_CERT_SERIAL_RANGE => {
+                let min: u64 = decode(&mut s)?;
+                let max: u64 = decode(&mut s)?;
+                revoked.serials.push((min, max));
+            }
+            SECTION_CERT_SERIAL_BITMAP => {
+                let offset: u64 = decode(&mut s)?;
+                let bitmap: Vec<u8> = decode(&mut s)?;
+                // The bitmap is a big-endian mpint, whose bit `i` revokes
+                // the serial number `offset + i`.
+                for (i, byte) in bitmap.iter().rev().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (1 << bit) != 0 {
+                            let serial = offset.saturating_add(i as u64 * 8 + bit);
+                            revoked.serials.push((serial, serial));
+                        }
+                    }
+                }
+            }
+            SECTION_CERT_KEY_ID => {
+                while !s.is_empty() {
+                    revoked.key_ids.insert(decode(&mut s)?);
+                }
+            }
+            t => debug!("KRL: unsupported certificate section {t}"),
+        }
+    }
+    Ok(revoked)
+}
+
+/// A [`Krl`] shared with running servers, which can be replaced at any
+/// time.
+#[derive(Debug, Default)]
+pub struct RevokedKeys {
+    krl: RwLock<Arc<Krl>>,
+}
+
+impl RevokedKeys {
+    pub fn new(krl: Krl) -> Self {
+        RevokedKeys {
+            krl: RwLock::new(Arc::new(krl)),
+        }
+    }
+
+    /// Load the list at `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::new(Krl::parse(&std::fs::read(path)?)?))
+    }
+
+    /// The current list.
+    pub fn krl(&self) -> Arc<Krl> {
+        match self.krl.read() {
+            Ok(krl) => krl.clone(),
+            Err(e) => e.into_inner().clone(),
+        }
+    }
+
+    /// Replace the list, for the authentication requests received from
+    /// now on.
+    pub fn replace(&self, krl: Krl) {
+        let mut current = match self.krl.write() {
+            Ok(current) => current,
+            Err(e) => e.into_inner(),
+        };
+        *current = Arc::new(krl);
+    }
+
+    /// Replace the list with the one at `path`. The current list is
+    /// kept if it cannot be loaded.
+    pub fn reload<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.replace(Krl::parse(&std::fs::read(path)?)?);
+        Ok(())
+    }
+
+    /// Whether `key` is revoked by the current list.
+    pub fn is_revoked(&self, key: &PublicKey) -> bool {
+        self.krl().is_revoked(key)
+    }
+
+    /// Whether `certificate` is revoked by the current list.
+    pub fn is_certificate_revoked(&self, certificate: &Certificate) -> bool {
+        self.krl().is_certificate_revoked(certificate)
+    }
+}
+
+impl From<Krl> for RevokedKeys {
+    fn from(krl: Krl) -> Self {
+        Self::new(krl)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use rand_core::OsRng;
+    use ssh_encoding::Encode;
+    use ssh_key::certificate::{Builder, CertType};
+    use ssh_key::{Algorithm, PrivateKey};
+
+    use super::*;
+
+    fn key() -> PrivateKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap()
+    }
+
+    fn certificate(ca: &PrivateKey, key: &PrivateKey, serial: u64, key_id: &str) -> Certificate {
+        let mut builder =
+            Builder::new_with_random_nonce(&mut OsRng, key.public_key(), 0, u64::MAX >> 2).unwrap();
+        builder.serial(serial).unwrap();
+        builder.key_id(key_id).unwrap();
+        builder.cert_type(CertType::User).unwrap();
+        builder.all_principals_valid().unwrap();
+        builder.sign(ca).unwrap()
+    }
+
+    fn section(krl: &mut Vec<u8>, section_type: u8, contents: &[u8]) {
+        section_type.encode(krl).unwrap();
+        contents.encode(krl).unwrap();
+    }
+
+    #[test]
+    fn key_list() {
+        let revoked = key();
+        let other = key();
+        let contents = format!(
+            "# revoked\n{}\n\nnot a key\n",
+            revoked.public_key().to_openssh().unwrap()
+        );
+        let krl = Krl::parse(contents.as_bytes()).unwrap();
+        assert!(krl.is_revoked(revoked.public_key()));
+        assert!(!krl.is_revoked(other.public_key()));
+        // The certificates of a revoked key, or signed by one, are revoked.
+        assert!(krl.is_certificate_revoked(&certificate(&other, &revoked, 1, "id")));
+        assert!(krl.is_certificate_revoked(&certificate(&revoked, &other, 1, "id")));
+        assert!(!krl.is_certificate_revoked(&certificate(&other, &other, 1, "id")));
+    }
+
+    #[test]
+    fn krl() {
+        let ca = key();
+        let other_ca = key();
+        let explicit = key();
+        let sha256 = key();
+        let user = key();
+
+        let mut serials = Vec::new();
+        3u64.encode(&mut serials).unwrap();
+        let mut range = Vec::new();
+        10u64.encode(&mut range).unwrap();
+        20u64.encode(&mut range).unwrap();
+        // Revokes 100 and 109.
+        let mut bitmap = Vec::new();
+        100u64.encode(&mut bitmap).unwrap();
+        [0x02u8, 0x01].as_slice().encode(&mut bitmap).unwrap();
+        let mut key_ids = Vec::new();
+        "stolen".encode(&mut key_ids).unwrap();
+        let mut certificates = Vec::new();
+        ca.public_key()
+            .to_bytes()
+            .unwrap()
+            .as_slice()
+            .encode(&mut certificates)
+            .unwrap();
+        [].as_slice().encode(&mut certificates).unwrap();
+        section(&mut certificates, SECTION_CERT_SERIAL_LIST, &serials);
+        section(&mut certificates, SECTION_CERT_SERIAL_RANGE, &range);
+        section(&mut certificates, SECTION_CERT_SERIAL_BITMAP, &bitmap);
+        section(&mut certificates, SECTION_CERT_KEY_ID, &key_ids);
+
+        let mut keys = Vec::new();
+        explicit
+            .public_key()
+            .to_bytes()
+            .unwrap()
+            .as_slice()
+            .encode(&mut keys)
+            .unwrap();
+        let mut fingerprints = Vec::new();
+        Sha256::digest(sha256.public_key().to_bytes().unwrap())
+            .as_slice()
+            .encode(&mut fingerprints)
+            .unwrap();
+
+        let mut contents = KRL_MAGIC.to_vec();
+        KRL_FORMAT_VERSION.encode(&mut contents).unwrap();
+        7u64.encode(&mut contents).unwrap();
+        0u64.encode(&mut contents).unwrap();
+        0u64.encode(&mut contents).unwrap();
+        [].as_slice().encode(&mut contents).unwrap();
+        "test".encode(&mut contents).unwrap();
+        section(&mut contents, SECTION_CERTIFICATES, &certificates);
+        section(&mut contents, SECTION_EXPLICIT_KEY, &keys);
+        section(&mut contents, SECTION_FINGERPRINT_SHA256, &fingerprints);
+
+        let krl = Krl::parse(&contents).unwrap();
+        assert_eq!(krl.version, 7);
+        assert_eq!(krl.comment, "test");
+        assert!(krl.is_revoked(explicit.public_key()));
+        assert!(krl.is_revoked(sha256.public_key()));
+        assert!(!krl.is_revoked(user.public_key()));
+        for serial in [3, 10, 15, 20, 100, 109] {
+            assert!(
+                krl.is_certificate_revoked(&certificate(&ca, &user, serial, "id")),
+                "{serial}"
+            );
+        }
+        for serial in [0, 2, 21, 101] {
+            assert!(
+                !krl.is_certificate_revoked(&certificate(&ca, &user, serial, "id")),
+                "{serial}"
+            );
+        }
+        assert!(krl.is_certificate_revoked(&certificate(&ca, &user, 0, "stolen")));
+        // The serial numbers are those of the certificates of `ca`.
+        assert!(!krl.is_certificate_revoked(&certificate(&other_ca, &user, 3, "stolen")));
+
+        assert!(Krl::parse(contents.split_last().unwrap().1).is_err());
+    }
+
+    #[test]
+    fn reload() {
+        let revoked = key();
+        let revoked_keys = RevokedKeys::default();
+        assert!(!revoked_keys.is_revoked(revoked.public_key()));
+        let mut krl = Krl::new();
+        krl.revoke_key(revoked.public_key());
+        revoked_keys.replace(krl);
+        assert!(revoked_keys.is_revoked(revoked.public_key()));
+    }
+}
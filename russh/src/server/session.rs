@@ -1,20 +1,24 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::ErrorKind;
 use std::sync::Arc;
 
 use channels::WindowSizeRef;
 use kex::ServerKex;
 use log::debug;
-use negotiation::parse_kex_algo_list;
+use negotiation::kex_init_lists;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 
+use super::penalties::Progress;
+use super::recording::Event;
 use super::*;
-use crate::channels::{Channel, ChannelMsg, ChannelReadHalf, ChannelRef, ChannelWriteHalf};
-use crate::helpers::NameList;
+use crate::channels::{
+    Channel, ChannelMsg, ChannelReadHalf, ChannelRef, ChannelWriteHalf, ConnectionLimiters,
+};
+use crate::ext_info::write_ext_info;
 use crate::kex::{KexCause, SessionKexState, EXTENSION_SUPPORT_AS_CLIENT};
-use crate::{map_err, msg};
+use crate::{map_err, msg, CloseReason, ConnectionInfo, ExtensionInfo, RemoteDisconnectInfo};
 
 /// A connected server session. This type is unique to a client.
 #[derive(Debug)]
@@ -28,6 +32,35 @@ pub struct Session {
     pub(crate) channels: HashMap<ChannelId, ChannelRef>,
     pub(crate) open_global_requests: VecDeque<GlobalRequestResponse>,
     pub(crate) kex: SessionKexState<ServerKex>,
+    pub(crate) extension_info: ExtensionInfo,
+    /// Whether the next packet may be the client's `SSH_MSG_EXT_INFO`,
+    /// which is only allowed right after the first `SSH_MSG_NEWKEYS`.
+    pub(crate) ext_info_expected: bool,
+    pub(crate) no_more_sessions: bool,
+    /// Only the `sftp` subsystem is allowed, see [`Config::sftp_only`].
+    pub(crate) sftp_only: bool,
+    /// Whether data was sent or received on a channel, for
+    /// [`Config::idle_timeout`].
+    pub(crate) channel_activity: bool,
+    /// The session channels opened by the client, for
+    /// [`Config::max_sessions`]. Some of them may be closed.
+    pub(crate) session_channels: HashSet<ChannelId>,
+    /// The [`Config::preauth_limits`] left, until the client
+    /// authenticates.
+    pub(crate) preauth: Option<PreAuthBudget>,
+    /// Counts the connection for [`Config::max_startups`] until it
+    /// authenticates.
+    pub(crate) startup: Option<super::Startup>,
+    /// Tracks the authentication of the connection for
+    /// [`Config::per_source_penalties`].
+    pub(crate) auth_progress: Option<super::penalties::AuthProgress>,
+    /// Reports the activity of the session to [`Config::metrics`].
+    pub(crate) metrics: Option<super::metrics::SessionMetrics>,
+    /// Records the session channels to [`Config::recorder`].
+    pub(crate) recordings: Option<super::recording::Recordings>,
+    /// The algorithms of the connection, from
+    /// [`Handler::preferred_algorithms`] or [`Config::preferred`].
+    pub(crate) preferred: Preferred,
 }
 
 #[derive(Debug)]
@@ -61,6 +94,11 @@ pub enum Msg {
         originator_port: u32,
         channel_ref: ChannelRef,
     },
+    ChannelOpenCustom {
+        channel_type: String,
+        data: Vec<u8>,
+        channel_ref: ChannelRef,
+    },
     TcpIpForward {
         /// Provide a channel for the reply result to request a reply from the server
         reply_channel: Option<oneshot::Sender<Option<u32>>>,
@@ -78,6 +116,16 @@ pub enum Msg {
         description: String,
         language_tag: String,
     },
+    GlobalRequest {
+        name: String,
+        payload: Vec<u8>,
+        /// Provide a channel for the reply result to request a reply from the other side
+        reply_channel: Option<oneshot::Sender<Option<Vec<u8>>>>,
+    },
+    Rekey {
+        /// Notified when the rekey completes
+        reply_channel: oneshot::Sender<()>,
+    },
     Channel(ChannelId, ChannelMsg),
 }
 
@@ -93,6 +141,8 @@ impl From<(ChannelId, ChannelMsg)> for Msg {
 pub struct Handle {
     pub(crate) sender: Sender<Msg>,
     pub(crate) channel_buffer_size: usize,
+    pub(crate) limiters: ConnectionLimiters,
+    pub(crate) closed: watch::Receiver<Option<CloseReason>>,
 }
 
 impl Handle {
@@ -167,12 +217,45 @@ impl Handle {
 
     /// Send the exit status of a program.
     pub async fn exit_status_request(&self, id: ChannelId, exit_status: u32) -> Result<(), ()> {
+        self.exit_status(id, exit_status).await
+    }
+
+    /// Send the exit status of a program, when it exited normally. See
+    /// [`Handle::exit_signal`] for programs killed by a signal.
+    pub async fn exit_status(&self, id: ChannelId, exit_status: u32) -> Result<(), ()> {
         self.sender
             .send(Msg::Channel(id, ChannelMsg::ExitStatus { exit_status }))
             .await
             .map_err(|_| ())
     }
 
+    /// Tell the client that the program was killed by `signal`, instead
+    /// of sending an exit status, like OpenSSH does. `lang_tag` is the
+    /// language of `error_message`
+    /// ([RFC3066](https://tools.ietf.org/html/rfc3066)), and is usually
+    /// empty.
+    pub async fn exit_signal<A: Into<String>, B: Into<String>>(
+        &self,
+        id: ChannelId,
+        signal: Sig,
+        core_dumped: bool,
+        error_message: A,
+        lang_tag: B,
+    ) -> Result<(), ()> {
+        self.sender
+            .send(Msg::Channel(
+                id,
+                ChannelMsg::ExitSignal {
+                    signal_name: signal,
+                    core_dumped,
+                    error_message: error_message.into(),
+                    lang_tag: lang_tag.into(),
+                },
+            ))
+            .await
+            .map_err(|_| ())
+    }
+
     /// Notifies the client that it can open TCP/IP forwarding channels for a port.
     pub async fn forward_tcpip(&self, address: String, port: u32) -> Result<u32, ()> {
         let (reply_send, reply_recv) = oneshot::channel();
@@ -282,6 +365,15 @@ impl Handle {
             .await
     }
 
+    /// Open a `forwarded-tcpip` channel to the client, for a connection
+    /// from `originator_address` and `originator_port` to
+    /// `connected_address` and `connected_port`, a port that the client
+    /// asked to forward with [`Handler::tcpip_forward`](super::Handler::tcpip_forward).
+    /// Clients reject the channels of ports they did not ask for, with
+    /// [`Error::ChannelOpenFailure`]. See
+    /// [RFC4254](https://tools.ietf.org/html/rfc4254#section-7.2), and
+    /// [`forward::RemoteForwards`](super::forward::RemoteForwards) to
+    /// listen on these ports.
     pub async fn channel_open_forwarded_tcpip<A: Into<String>, B: Into<String>>(
         &self,
         connected_address: A,
@@ -307,6 +399,29 @@ impl Handle {
             .await
     }
 
+    /// Open a `forwarded-tcpip` channel to the client, as
+    /// [`Handle::channel_open_forwarded_tcpip`], for a connection from
+    /// `originator`.
+    pub async fn channel_open_forwarded_tcpip_from<A: Into<String>>(
+        &self,
+        connected_address: A,
+        connected_port: u32,
+        originator: std::net::SocketAddr,
+    ) -> Result<Channel<Msg>, Error> {
+        self.channel_open_forwarded_tcpip(
+            connected_address,
+            connected_port,
+            originator.ip().to_string(),
+            originator.port().into(),
+        )
+        .await
+    }
+
+    /// Open a `forwarded-streamlocal@openssh.com` channel to the client,
+    /// for a connection to `server_socket_path`, a Unix socket that the
+    /// client asked to forward with
+    /// [`Handler::streamlocal_forward`](super::Handler::streamlocal_forward).
+    /// The protocol carries no originator for Unix sockets.
     pub async fn channel_open_forwarded_streamlocal<A: Into<String>>(
         &self,
         server_socket_path: A,
@@ -326,6 +441,10 @@ impl Handle {
             .await
     }
 
+    /// Open an `x11` channel to the client, for a connection from
+    /// `originator_address` and `originator_port` to the X11 display
+    /// forwarded after [`Handler::x11_request`](super::Handler::x11_request).
+    /// See [`x11`](super::x11) to listen on a display.
     pub async fn channel_open_x11<A: Into<String>>(
         &self,
         originator_address: A,
@@ -347,13 +466,39 @@ impl Handle {
             .await
     }
 
+    /// Open a channel of type `channel_type`, which this crate does not
+    /// know about, for protocol extensions. `data` is sent after the
+    /// standard fields of `SSH_MSG_CHANNEL_OPEN`, and must be encoded
+    /// as the channel type requires.
+    pub async fn channel_open_custom<A: Into<String>>(
+        &self,
+        channel_type: A,
+        data: Vec<u8>,
+    ) -> Result<Channel<Msg>, Error> {
+        let (sender, receiver) = channel(self.channel_buffer_size);
+        let channel_ref = ChannelRef::new(sender);
+        let window_size_ref = channel_ref.window_size().clone();
+
+        self.sender
+            .send(Msg::ChannelOpenCustom {
+                channel_type: channel_type.into(),
+                data,
+                channel_ref,
+            })
+            .await
+            .map_err(|_| Error::SendError)?;
+        self.wait_channel_confirmation(receiver, window_size_ref)
+            .await
+    }
+
     async fn wait_channel_confirmation(
         &self,
-        mut receiver: Receiver<ChannelMsg>,
+        receiver: Receiver<ChannelMsg>,
         window_size_ref: WindowSizeRef,
     ) -> Result<Channel<Msg>, Error> {
+        let mut read_half = ChannelReadHalf::new(receiver, self.limiters.receive.clone());
         loop {
-            match receiver.recv().await {
+            match read_half.receiver.recv().await {
                 Some(ChannelMsg::Open {
                     id,
                     max_packet_size,
@@ -367,8 +512,9 @@ impl Handle {
                             sender: self.sender.clone(),
                             max_packet_size,
                             window_size: window_size_ref,
+                            send_limiters: self.limiters.send.clone(),
                         },
-                        read_half: ChannelReadHalf { receiver },
+                        read_half,
                     });
                 }
                 Some(ChannelMsg::OpenFailure(reason)) => {
@@ -393,18 +539,8 @@ impl Handle {
         error_message: String,
         lang_tag: String,
     ) -> Result<(), ()> {
-        self.sender
-            .send(Msg::Channel(
-                id,
-                ChannelMsg::ExitSignal {
-                    signal_name,
-                    core_dumped,
-                    error_message,
-                    lang_tag,
-                },
-            ))
+        self.exit_signal(id, signal_name, core_dumped, error_message, lang_tag)
             .await
-            .map_err(|_| ())
     }
 
     /// Allows a server to disconnect a client session
@@ -423,11 +559,70 @@ impl Handle {
             .await
             .map_err(|_| Error::SendError)
     }
+
+    /// Send a global request named `name`, followed by `payload`. This
+    /// is meant for extensions that this crate does not implement.
+    ///
+    /// If `want_reply` is set, this waits for the reply and returns the
+    /// data that follows `SSH_MSG_REQUEST_SUCCESS`, or
+    /// [`Error::RequestDenied`] if the request failed. Otherwise, this
+    /// returns `None` as soon as the request is sent.
+    pub async fn global_request<A: Into<String>>(
+        &self,
+        name: A,
+        want_reply: bool,
+        payload: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (reply_send, reply_recv) = oneshot::channel();
+        self.sender
+            .send(Msg::GlobalRequest {
+                name: name.into(),
+                payload,
+                reply_channel: want_reply.then_some(reply_send),
+            })
+            .await
+            .map_err(|_| Error::SendError)?;
+        if !want_reply {
+            return Ok(None);
+        }
+        match reply_recv.await {
+            Ok(Some(data)) => Ok(Some(data)),
+            Ok(None) => Err(Error::RequestDenied),
+            Err(e) => {
+                error!("Unable to receive GlobalRequest result: {e:?}");
+                Err(Error::Disconnect)
+            }
+        }
+    }
+
+    /// Whether the connection has ended. See [`Handle::closed`].
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed() || self.closed.borrow().is_some()
+    }
+
+    /// Wait for the connection to end, and return why.
+    pub async fn closed(&self) -> CloseReason {
+        crate::session::wait_closed(self.closed.clone()).await
+    }
+
+    /// Re-exchange keys now, and wait for the new keys to be in use.
+    /// Keys are also re-exchanged automatically according to
+    /// [`Config::limits`](super::Config::limits).
+    pub async fn rekey(&self) -> Result<(), Error> {
+        let (reply_channel, receiver) = oneshot::channel();
+        self.sender
+            .send(Msg::Rekey { reply_channel })
+            .await
+            .map_err(|_| Error::SendError)?;
+        receiver.await.map_err(|_| Error::Disconnect)
+    }
 }
 
 impl Session {
     fn maybe_decompress(&mut self, buffer: &SSHBuffer) -> Result<IncomingSshPacket, Error> {
         if let Some(ref mut enc) = self.common.encrypted {
+            enc.read_bytes += buffer.buffer.len();
+            enc.read_packets += 1;
             let mut decomp = CryptoVec::new();
             Ok(IncomingSshPacket {
                 #[allow(clippy::indexing_slicing)] // length checked
@@ -450,14 +645,18 @@ impl Session {
         mut self,
         mut stream: SshRead<R>,
         mut handler: H,
-    ) -> Result<(), H::Error>
+    ) -> Result<CloseReason, H::Error>
     where
         H: Handler + Send + 'static,
         R: AsyncRead + AsyncWrite + Unpin + Send + 'static,
     {
         self.flush()?;
 
+        let sent = self.common.packet_writer.buffer().buffer.len();
         map_err!(self.common.packet_writer.flush_into(&mut stream).await)?;
+        if let Some(ref metrics) = self.metrics {
+            metrics.bytes_sent(sent);
+        }
 
         let (stream_read, mut stream_write) = stream.split();
         let buffer = SSHBuffer::new();
@@ -474,21 +673,52 @@ impl Session {
             future_or_pending(self.common.config.inactivity_timeout, tokio::time::sleep);
         pin!(inactivity_timer);
 
-        let reading = start_reading(stream_read, buffer, opening_cipher);
+        let login_grace_timer =
+            future_or_pending(self.common.config.login_grace_time, tokio::time::sleep);
+        pin!(login_grace_timer);
+
+        let idle_timer = future_or_pending(
+            self.common.config.idle_timeout.as_ref().map(|t| t.duration),
+            tokio::time::sleep,
+        );
+        pin!(idle_timer);
+
+        let reading = start_reading(
+            stream_read,
+            buffer,
+            opening_cipher,
+            self.maximum_packet_len(),
+        );
         pin!(reading);
         let mut is_reading = None;
+        let mut close_reason = CloseReason::Local;
 
         #[allow(clippy::panic)] // false positive in macro
         while !self.common.disconnected {
             self.common.received_data = false;
+            self.channel_activity = false;
             let mut sent_keepalive = false;
+            let mut idle_expired = false;
             tokio::select! {
                 r = &mut reading => {
                     let (stream_read, mut buffer, mut opening_cipher) = match r {
-                        Ok((_, stream_read, buffer, opening_cipher)) => (stream_read, buffer, opening_cipher),
+                        Ok((n, stream_read, buffer, opening_cipher)) => {
+                            if let Some(ref metrics) = self.metrics {
+                                metrics.bytes_received(n);
+                            }
+                            if let Some(Err(reason)) = self.preauth.as_mut().map(|b| b.packet(n)) {
+                                info!("Disconnecting the client: {reason}");
+                                self.common.disconnect(Disconnect::ByApplication, reason, "")?;
+                                self.flush()?;
+                                map_err!(self.common.packet_writer.flush_into(&mut stream_write).await)?;
+                                return Err(crate::Error::PreAuthLimitExceeded.into());
+                            }
+                            (stream_read, buffer, opening_cipher)
+                        }
                         Err(e) => return Err(e.into())
                     };
                     if buffer.buffer.len() < 5 {
+                        close_reason = CloseReason::Error;
                         is_reading = Some((stream_read, buffer, opening_cipher));
                         break
                     }
@@ -499,6 +729,9 @@ impl Session {
                         None => (),
                         Some(&crate::msg::DISCONNECT) => {
                             debug!("break");
+                            if let Ok(info) = RemoteDisconnectInfo::parse(&pkt.buffer) {
+                                close_reason = CloseReason::Remote(info);
+                            }
                             is_reading = Some((stream_read, buffer, opening_cipher));
                             break;
                         }
@@ -512,11 +745,14 @@ impl Session {
                                 Err(e) => return Err(e),
                             }
                             buffer.seqn = pkt.seqn; // TODO reply changes seqn internall, find cleaner way
+                            if self.preauth.is_some() && self.is_authenticated() {
+                                self.preauth = None;
+                            }
 
                             std::mem::swap(&mut opening_cipher, &mut self.common.remote_to_local);
                         }
                     }
-                    reading.set(start_reading(stream_read, buffer, opening_cipher));
+                    reading.set(start_reading(stream_read, buffer, opening_cipher, self.maximum_packet_len()));
                 }
                 () = &mut keepalive_timer => {
                     if self.common.config.keepalive_max != 0 && self.common.alive_timeouts > self.common.config.keepalive_max {
@@ -531,6 +767,15 @@ impl Session {
                     debug!("timeout");
                     return Err(crate::Error::InactivityTimeout.into());
                 }
+                () = &mut login_grace_timer, if !self.is_authenticated() => {
+                    info!("Timeout before authentication");
+                    self.advance_auth(Progress::GraceExceeded);
+                    self.common.disconnect(Disconnect::ByApplication, "Timeout before authentication", "")?;
+                }
+                () = &mut idle_timer => {
+                    idle_expired = true;
+                    self.idle_timeout()?;
+                }
                 msg = self.receiver.recv(), if !self.kex.active() => {
                     match msg {
                         Some(Msg::Channel(id, ChannelMsg::Data { data })) => {
@@ -587,6 +832,10 @@ impl Session {
                             let id = self.channel_open_x11(&originator_address, originator_port)?;
                             self.channels.insert(id, channel_ref);
                         }
+                        Some(Msg::ChannelOpenCustom { channel_type, data, channel_ref }) => {
+                            let id = self.channel_open_custom(&channel_type, &data)?;
+                            self.channels.insert(id, channel_ref);
+                        }
                         Some(Msg::TcpIpForward { address, port, reply_channel }) => {
                             self.tcpip_forward(&address, port, reply_channel)?;
                         }
@@ -596,6 +845,12 @@ impl Session {
                         Some(Msg::Disconnect {reason, description, language_tag}) => {
                             self.common.disconnect(reason, &description, &language_tag)?;
                         }
+                        Some(Msg::GlobalRequest { name, payload, reply_channel }) => {
+                            self.global_request(&name, &payload, reply_channel)?;
+                        }
+                        Some(Msg::Rekey { reply_channel }) => {
+                            self.initiate_rekey(Some(reply_channel))?;
+                        }
                         Some(_) => {
                             // should be unreachable, since the receiver only gets
                             // messages from methods implemented within russh
@@ -609,12 +864,16 @@ impl Session {
             }
             self.flush()?;
 
+            let sent = self.common.packet_writer.buffer().buffer.len();
             map_err!(
                 self.common
                     .packet_writer
                     .flush_into(&mut stream_write)
                     .await
             )?;
+            if let Some(ref metrics) = self.metrics {
+                metrics.bytes_sent(sent);
+            }
 
             if self.common.received_data {
                 // Reset the number of failed keepalive attempts. We don't
@@ -640,13 +899,30 @@ impl Session {
                     sleep.as_mut().reset(tokio::time::Instant::now() + d);
                 }
             }
+            if self.channel_activity || idle_expired {
+                if let (futures::future::Either::Right(ref mut sleep), Some(t)) = (
+                    idle_timer.as_mut().as_pin_mut(),
+                    &self.common.config.idle_timeout,
+                ) {
+                    sleep
+                        .as_mut()
+                        .reset(tokio::time::Instant::now() + t.duration);
+                }
+            }
         }
         debug!("disconnected");
         // Shutdown
-        map_err!(stream_write.shutdown().await)?;
+        if let Err(e) = stream_write.shutdown().await {
+            debug!("could not shut down the transport: {e:?}");
+        }
         loop {
             if let Some((stream_read, buffer, opening_cipher)) = is_reading.take() {
-                reading.set(start_reading(stream_read, buffer, opening_cipher));
+                reading.set(start_reading(
+                    stream_read,
+                    buffer,
+                    opening_cipher,
+                    cipher::MAXIMUM_PACKET_LEN,
+                ));
             }
             match (&mut reading).await {
                 Ok((0, _, _, _)) => break,
@@ -659,9 +935,50 @@ impl Session {
             }
         }
 
+        Ok(close_reason)
+    }
+
+    /// Take the [`IdleAction`] of [`Config::idle_timeout`], once no data
+    /// was sent or received on the channels for its duration.
+    fn idle_timeout(&mut self) -> Result<(), Error> {
+        let Some(timeout) = self.common.config.idle_timeout.clone() else {
+            return Ok(());
+        };
+        info!("Session idle for {:?}", timeout.duration);
+        match timeout.action {
+            IdleAction::Warn(message) => {
+                if let Some(ref mut enc) = self.common.encrypted {
+                    let channels: Vec<_> = enc.channels.keys().copied().collect();
+                    for id in channels {
+                        let message = CryptoVec::from_slice(message.as_bytes());
+                        enc.extended_data(id, 1, message, self.kex.active())?;
+                    }
+                }
+            }
+            IdleAction::CloseChannels => {
+                if let Some(ref enc) = self.common.encrypted {
+                    let channels: Vec<_> = enc.channels.keys().copied().collect();
+                    for id in channels {
+                        self.close(id)?;
+                    }
+                }
+            }
+            IdleAction::Disconnect => {
+                self.common
+                    .disconnect(Disconnect::ByApplication, "Idle timeout", "")?;
+            }
+        }
         Ok(())
     }
 
+    /// The maximal length of the next packet read, lower until the
+    /// client authenticates with [`Config::preauth_limits`].
+    fn maximum_packet_len(&self) -> usize {
+        self.preauth
+            .as_ref()
+            .map_or(cipher::MAXIMUM_PACKET_LEN, |b| b.max_packet_len())
+    }
+
     /// Get a handle to this session.
     pub fn handle(&self) -> Handle {
         self.sender.clone()
@@ -698,12 +1015,17 @@ impl Session {
 
     /// Flush the session, i.e. encrypt the pending buffer.
     pub fn flush(&mut self) -> Result<(), Error> {
+        // Only key exchange messages may be sent until the new keys are
+        // in use, the others are sent after the key exchange.
+        if self.kex.active() {
+            return Ok(());
+        }
         if let Some(ref mut enc) = self.common.encrypted {
             if enc.flush(
-                &self.common.config.as_ref().limits,
+                &self.common.config.limits,
+                self.common.config.rekey_packet_limit,
                 &mut self.common.packet_writer,
-            )? && self.kex == SessionKexState::Idle
-            {
+            )? {
                 debug!("starting rekeying");
                 if enc.exchange.take().is_some() {
                     self.begin_rekey()?;
@@ -713,6 +1035,20 @@ impl Session {
         Ok(())
     }
 
+    /// Re-exchange keys after flushing the pending packets. The
+    /// waiter, if any, is notified when the new keys are in use.
+    pub(crate) fn initiate_rekey(
+        &mut self,
+        waiter: Option<oneshot::Sender<()>>,
+    ) -> Result<(), Error> {
+        if let Some(ref mut enc) = self.common.encrypted {
+            enc.rekey_waiters.extend(waiter);
+            enc.rekey_wanted = true;
+            self.flush()?
+        }
+        Ok(())
+    }
+
     pub fn flush_pending(&mut self, channel: ChannelId) -> Result<usize, Error> {
         if let Some(ref mut enc) = self.common.encrypted {
             enc.flush_pending(channel)
@@ -847,6 +1183,16 @@ impl Session {
     /// The number of bytes added to the "sending pipeline" (to be
     /// processed by the event loop) is returned.
     pub fn data(&mut self, channel: ChannelId, data: CryptoVec) -> Result<(), Error> {
+        self.channel_activity = true;
+        if let Some(ref mut recordings) = self.recordings {
+            recordings.record(
+                channel,
+                Event::Output {
+                    ext: None,
+                    data: &data,
+                },
+            );
+        }
         if let Some(ref mut enc) = self.common.encrypted {
             enc.data(channel, data, self.kex.active())
         } else {
@@ -866,6 +1212,16 @@ impl Session {
         extended: u32,
         data: CryptoVec,
     ) -> Result<(), Error> {
+        self.channel_activity = true;
+        if let Some(ref mut recordings) = self.recordings {
+            recordings.record(
+                channel,
+                Event::Output {
+                    ext: Some(extended),
+                    data: &data,
+                },
+            );
+        }
         if let Some(ref mut enc) = self.common.encrypted {
             enc.extended_data(channel, extended, data, self.kex.active())
         } else {
@@ -897,6 +1253,50 @@ impl Session {
         Ok(())
     }
 
+    /// Announce the host keys of the server with
+    /// `hostkeys-00@openssh.com`, so that clients can learn new keys
+    /// before the current one is retired (OpenSSH's `UpdateHostKeys`).
+    /// OpenSSH servers send this after authentication.
+    pub fn announce_host_keys(&mut self) -> Result<(), Error> {
+        if let Some(ref mut enc) = self.common.encrypted {
+            push_packet!(enc.write, {
+                msg::GLOBAL_REQUEST.encode(&mut enc.write)?;
+                "hostkeys-00@openssh.com".encode(&mut enc.write)?;
+                0u8.encode(&mut enc.write)?;
+                for key in &self.common.config.keys {
+                    key.public_key().to_bytes()?.encode(&mut enc.write)?;
+                }
+            })
+        }
+        Ok(())
+    }
+
+    /// Send a global request named `name`, followed by `payload`. If
+    /// `reply_channel` is provided, a reply is requested, and its data
+    /// (or `None` if the request failed) is sent on it.
+    pub fn global_request(
+        &mut self,
+        name: &str,
+        payload: &[u8],
+        reply_channel: Option<oneshot::Sender<Option<Vec<u8>>>>,
+    ) -> Result<(), Error> {
+        if let Some(ref mut enc) = self.common.encrypted {
+            let want_reply = reply_channel.is_some();
+            if let Some(reply_channel) = reply_channel {
+                self.open_global_requests.push_back(
+                    crate::session::GlobalRequestResponse::Generic(reply_channel),
+                );
+            }
+            push_packet!(enc.write, {
+                msg::GLOBAL_REQUEST.encode(&mut enc.write)?;
+                name.encode(&mut enc.write)?;
+                (want_reply as u8).encode(&mut enc.write)?;
+                enc.write.extend(payload);
+            });
+        }
+        Ok(())
+    }
+
     /// Ping the client to verify there is still connectivity.
     pub fn keepalive_request(&mut self) -> Result<(), Error> {
         let want_reply = u8::from(true);
@@ -1031,11 +1431,38 @@ impl Session {
         })
     }
 
+    /// Opens a channel of any type, followed by `data`.
+    pub fn channel_open_custom(
+        &mut self,
+        channel_type: &str,
+        data: &[u8],
+    ) -> Result<ChannelId, Error> {
+        self.channel_open_generic(channel_type.as_bytes(), |write| {
+            write.extend(data);
+            Ok(())
+        })
+    }
+
     /// Opens a new agent channel on the client.
     pub fn channel_open_agent(&mut self) -> Result<ChannelId, Error> {
         self.channel_open_generic(b"auth-agent@openssh.com", |_| Ok(()))
     }
 
+    pub(crate) fn advance_auth(&self, progress: Progress) {
+        if let Some(ref auth_progress) = self.auth_progress {
+            auth_progress.advance(progress);
+        }
+    }
+
+    fn is_authenticated(&self) -> bool {
+        self.common.encrypted.as_ref().is_some_and(|enc| {
+            matches!(
+                enc.state,
+                EncryptedState::Authenticated | EncryptedState::InitCompression
+            )
+        })
+    }
+
     fn channel_open_generic<F>(&mut self, kind: &[u8], write_suffix: F) -> Result<ChannelId, Error>
     where
         F: FnOnce(&mut CryptoVec) -> Result<(), Error>,
@@ -1147,46 +1574,77 @@ impl Session {
         &self.common.remote_sshid
     }
 
+    /// The session identifier: the exchange hash `H` of the first key
+    /// exchange, which stays the same after rekeys. It is what
+    /// signatures bind to in authentication, and can be used for channel
+    /// binding. `None` before the first key exchange.
+    pub fn session_id(&self) -> Option<&[u8]> {
+        self.common.session_id()
+    }
+
+    /// The algorithms negotiated with the client in the last key
+    /// exchange, and its identification string. `None` before the first
+    /// key exchange.
+    pub fn connection_info(&self) -> Option<ConnectionInfo> {
+        self.common.connection_info()
+    }
+
+    /// The extensions received from the client so far.
+    pub fn extension_info(&self) -> &ExtensionInfo {
+        &self.extension_info
+    }
+
+    /// Whether the client has sent `no-more-sessions@openssh.com`. If
+    /// so, every new session channel is rejected.
+    pub fn no_more_sessions(&self) -> bool {
+        self.no_more_sessions
+    }
+
+    /// Whether only the `sftp` subsystem is allowed, see
+    /// [`Config::sftp_only`].
+    pub fn sftp_only(&self) -> bool {
+        self.sftp_only
+    }
+
+    /// Only allow the `sftp` subsystem from now on, or not, such as in
+    /// [`Handler::auth_succeeded`](super::Handler::auth_succeeded) for
+    /// the users whose [`Policy`](super::sshd_config::Policy) is
+    /// [`sftp_only`](super::sshd_config::Policy::sftp_only). This
+    /// overrides [`Config::sftp_only`].
+    pub fn set_sftp_only(&mut self, sftp_only: bool) {
+        self.sftp_only = sftp_only;
+    }
+
     pub(crate) fn maybe_send_ext_info(&mut self) -> Result<(), Error> {
         if let Some(ref mut enc) = self.common.encrypted {
             // If client sent a ext-info-c message in the kex list, it supports RFC 8308 extension negotiation.
-            let mut key_extension_client = false;
-            if let Some(e) = &enc.exchange {
-                let Some(mut r) = &e.client_kex_init.as_ref().get(17..) else {
-                    return Ok(());
-                };
-                if let Ok(kex_string) = String::decode(&mut r) {
-                    use super::negotiation::Select;
-                    key_extension_client = super::negotiation::Server::select(
-                        &[EXTENSION_SUPPORT_AS_CLIENT],
-                        &parse_kex_algo_list(&kex_string),
-                        AlgorithmKind::Kex,
-                    )
-                    .is_ok();
-                }
-            }
-
+            let key_extension_client = enc
+                .exchange
+                .as_ref()
+                .is_some_and(|e| kex_init_lists(&e.client_kex_init, &EXTENSION_SUPPORT_AS_CLIENT));
             if !key_extension_client {
                 debug!("RFC 8308 Extension Negotiation not supported by client");
                 return Ok(());
             }
 
-            push_packet!(enc.write, {
-                msg::EXT_INFO.encode(&mut enc.write)?;
-                1u32.encode(&mut enc.write)?;
-                "server-sig-algs".encode(&mut enc.write)?;
-
-                NameList(
-                    self.common
-                        .config
-                        .preferred
-                        .key
+            let config = &self.common.config;
+            let server_sig_algs = self
+                .preferred
+                .key
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            let extensions = std::iter::once(("server-sig-algs", server_sig_algs.as_bytes()))
+                .chain(std::iter::once(("ping@openssh.com", &b"0"[..])))
+                .chain(
+                    config
+                        .extensions
                         .iter()
-                        .map(|x| x.to_string())
-                        .collect(),
+                        .map(|(n, v)| (n.as_str(), v.as_slice())),
                 )
-                .encode(&mut enc.write)?;
-            });
+                .collect::<Vec<_>>();
+            write_ext_info(&mut enc.write, extensions.into_iter())?;
         }
         Ok(())
     }
@@ -1195,6 +1653,7 @@ impl Session {
         debug!("beginning re-key");
         let mut kex = ServerKex::new(
             self.common.config.clone(),
+            self.preferred.clone(),
             &self.common.remote_sshid,
             &self.common.config.server_id,
             match self.common.encrypted {
@@ -1208,6 +1667,9 @@ impl Session {
 
         kex.kexinit(&mut self.common.packet_writer)?;
         self.kex = SessionKexState::InProgress(kex);
+        if let Some(ref mut metrics) = self.metrics {
+            metrics.kex_started();
+        }
         Ok(())
     }
 }
@@ -0,0 +1,150 @@
+//! Graceful shutdown of a server.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use super::Handle;
+use crate::Disconnect;
+
+#[derive(Debug, Clone)]
+pub(crate) enum State {
+    Running,
+    /// The server stopped accepting connections, and waits for its
+    /// sessions to end until `deadline`.
+    Draining {
+        reason: Option<String>,
+        deadline: Duration,
+    },
+    /// The remaining sessions are closed.
+    Closing,
+}
+
+/// A handle to shut a server down. All the clones control the same
+/// server.
+///
+/// A server run by
+/// [`Server::run_on_listener_with_shutdown`](super::Server::run_on_listener_with_shutdown)
+/// stops when [`Shutdown::shutdown`] is called on one of the clones of
+/// its [`Shutdown`]: it stops accepting connections, disconnects the
+/// clients if a reason is given, and waits for the current sessions to
+/// end until a deadline, after which the remaining ones are closed.
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// # use russh::server::{Server, Shutdown};
+/// # async fn run<S: Server + Send>(
+/// #     mut server: S,
+/// #     config: Arc<russh::server::Config>,
+/// #     stop: impl std::future::Future<Output = ()> + Send + 'static,
+/// # ) -> std::io::Result<()> {
+/// let listener = tokio::net::TcpListener::bind("0.0.0.0:2222").await?;
+/// let shutdown = Shutdown::new();
+/// let signal = shutdown.clone();
+/// tokio::spawn(async move {
+///     stop.await;
+///     signal.shutdown(Some("Server shutting down".into()), Duration::from_secs(10));
+/// });
+/// server
+///     .run_on_listener_with_shutdown(config, listener, shutdown)
+///     .await
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    state: Arc<watch::Sender<State>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            state: Arc::new(watch::channel(State::Running).0),
+        }
+    }
+
+    /// Stop accepting connections and wait, for at most `deadline`, for
+    /// the current sessions to end before closing them. If `reason` is
+    /// set, clients are disconnected first, with `reason` as the
+    /// description of the disconnection.
+    ///
+    /// This does nothing if the server is already shutting down.
+    pub fn shutdown(&self, reason: Option<String>, deadline: Duration) {
+        self.state.send_modify(|state| {
+            if let State::Running = state {
+                *state = State::Draining { reason, deadline }
+            }
+        })
+    }
+
+    /// Whether [`Shutdown::shutdown`] was called.
+    pub fn is_shutting_down(&self) -> bool {
+        !matches!(*self.state.borrow(), State::Running)
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<State> {
+        self.state.subscribe()
+    }
+
+    pub(crate) fn close(&self) {
+        self.state.send_replace(State::Closing);
+    }
+}
+
+/// Wait until the server starts draining, and return the deadline of
+/// its sessions.
+pub(crate) async fn draining(state: &mut watch::Receiver<State>) -> Duration {
+    loop {
+        if let State::Draining { deadline, .. } = *state.borrow_and_update() {
+            return deadline;
+        }
+        if state.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+}
+
+/// Wait until the remaining sessions must be closed.
+pub(crate) async fn closing(mut state: watch::Receiver<State>) {
+    loop {
+        if let State::Closing = *state.borrow_and_update() {
+            return;
+        }
+        if state.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+}
+
+/// Follow the shutdown of the server for a session: disconnect the
+/// client if a reason is given, and return once the session must be
+/// closed. Never returns if the server is dropped without shutting
+/// down.
+pub(crate) async fn session(mut state: watch::Receiver<State>, handle: Handle) {
+    let mut disconnected = false;
+    loop {
+        let current = state.borrow_and_update().clone();
+        match current {
+            State::Running => {}
+            State::Draining { reason, .. } => {
+                if let (Some(reason), false) = (reason, disconnected) {
+                    disconnected = true;
+                    let _ = handle
+                        .disconnect(Disconnect::ByApplication, reason, String::new())
+                        .await;
+                }
+            }
+            State::Closing => return,
+        }
+        if state.changed().await.is_err() {
+            return std::future::pending().await;
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Listening on the sockets passed by systemd
+//! ([socket activation](https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html)).
+//!
+//! With a `.socket` unit such as
+//!
+//! ```text
+//! [Socket]
+//! ListenStream=22
+//! ```
+//!
+//! systemd binds the socket itself, even on a privileged port, and
+//! starts the service with the socket when the first client connects.
+//! The server then only has to call
+//! [`Server::run_on_activated_sockets`](super::Server::run_on_activated_sockets),
+//! or to use an [`ActivatedListener`] with
+//! [`Server::run_on_listener`](super::Server::run_on_listener).
+//! Both TCP and Unix stream sockets are accepted, and connections are
+//! accepted on all the sockets of the unit.
+
+use std::io;
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+/// The first file descriptor passed by systemd.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// A socket passed by systemd.
+#[derive(Debug)]
+pub enum ActivatedSocket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+/// A [`Listener`](crate::transport::Listener) accepting connections on
+/// all the sockets passed by systemd.
+#[derive(Debug)]
+pub struct ActivatedListener {
+    sockets: Vec<(String, ActivatedSocket)>,
+    nodelay: bool,
+}
+
+impl ActivatedListener {
+    /// Take the sockets passed to this process by systemd, or return
+    /// `None` if there are none, such as when the process was not
+    /// started by socket activation.
+    ///
+    /// As `sd_listen_fds` when asked to, this removes the `LISTEN_*`
+    /// variables from the environment, so that the sockets are not
+    /// passed on to child processes, and can only be taken once. It
+    /// should then be called early, before other threads read the
+    /// environment. This must be called from a Tokio runtime.
+    pub fn from_env() -> io::Result<Option<Self>> {
+        let fds = listen_fds(
+            std::env::var("LISTEN_PID").ok().as_deref(),
+            std::env::var("LISTEN_FDS").ok().as_deref(),
+            std::env::var("LISTEN_FDNAMES").ok().as_deref(),
+            std::process::id(),
+        );
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            std::env::remove_var(name);
+        }
+        let Some(fds) = fds else {
+            return Ok(None);
+        };
+        let mut sockets = Vec::with_capacity(fds.len());
+        for (fd, name) in fds {
+            // SAFETY: systemd passes these descriptors to the process,
+            // and the environment variables naming them were removed,
+            // so that they are only taken here.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            sockets.push((name, socket(fd)?));
+        }
+        Ok(Some(ActivatedListener {
+            sockets,
+            nodelay: false,
+        }))
+    }
+
+    /// Invoke `set_nodelay(true)` on the TCP connections, as
+    /// [`Config::nodelay`](super::Config::nodelay).
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// The sockets, with their names from the `FileDescriptorName=`
+    /// option of the socket unit, which default to the name of the
+    /// unit.
+    pub fn sockets(&self) -> &[(String, ActivatedSocket)] {
+        &self.sockets
+    }
+}
+
+/// The descriptors and names passed to the process `pid` in the
+/// `LISTEN_*` variables of its environment, if any.
+fn listen_fds(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    names: Option<&str>,
+    pid: u32,
+) -> Option<Vec<(RawFd, String)>> {
+    // The variables may have been inherited from the parent process.
+    if listen_pid?.parse::<u32>().ok()? != pid {
+        return None;
+    }
+    let n: RawFd = listen_fds?.parse().ok()?;
+    if n <= 0 {
+        return None;
+    }
+    let mut names = names.into_iter().flat_map(|names| names.split(':'));
+    Some(
+        (LISTEN_FDS_START..LISTEN_FDS_START.checked_add(n)?)
+            .map(|fd| (fd, names.next().unwrap_or("unknown").to_string()))
+            .collect(),
+    )
+}
+
+/// A listener for `fd`, according to its address family.
+fn socket(fd: OwnedFd) -> io::Result<ActivatedSocket> {
+    let raw = fd.as_raw_fd();
+    // SAFETY: the options are written to valid integers and structures
+    // of the given sizes.
+    let (kind, family) = unsafe {
+        let mut kind: libc::c_int = 0;
+        let mut len = std::mem::size_of_val(&kind) as libc::socklen_t;
+        if libc::getsockopt(
+            raw,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            std::ptr::addr_of_mut!(kind).cast(),
+            &mut len,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let mut address: libc::sockaddr_storage = std::mem::zeroed();
+        let mut len = std::mem::size_of_val(&address) as libc::socklen_t;
+        if libc::getsockname(raw, std::ptr::addr_of_mut!(address).cast(), &mut len) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        (kind, libc::c_int::from(address.ss_family))
+    };
+    if kind != libc::SOCK_STREAM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("socket {raw} is not a stream socket"),
+        ));
+    }
+    // SAFETY: a file descriptor flag is set on a descriptor we own.
+    if unsafe { libc::fcntl(raw, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    match family {
+        libc::AF_INET | libc::AF_INET6 => {
+            let listener = std::net::TcpListener::from(fd);
+            listener.set_nonblocking(true)?;
+            Ok(ActivatedSocket::Tcp(TcpListener::from_std(listener)?))
+        }
+        libc::AF_UNIX => {
+            let listener = std::os::unix::net::UnixListener::from(fd);
+            listener.set_nonblocking(true)?;
+            Ok(ActivatedSocket::Unix(UnixListener::from_std(listener)?))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("socket {raw} has an unknown address family"),
+        )),
+    }
+}
+
+impl ActivatedSocket {
+    async fn accept(&self, nodelay: bool) -> io::Result<(ActivatedStream, Option<SocketAddr>)> {
+        match self {
+            ActivatedSocket::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                if nodelay {
+                    if let Err(e) = stream.set_nodelay(true) {
+                        log::warn!("set_nodelay() failed: {e:?}");
+                    }
+                }
+                Ok((ActivatedStream::Tcp(stream), Some(addr)))
+            }
+            ActivatedSocket::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((ActivatedStream::Unix(stream), None))
+            }
+        }
+    }
+}
+
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+impl crate::transport::Listener for ActivatedListener {
+    type Stream = ActivatedStream;
+
+    #[allow(clippy::manual_async_fn)]
+    fn accept(
+        &mut self,
+    ) -> impl std::future::Future<Output = io::Result<(Self::Stream, Option<SocketAddr>)>> + Send
+    {
+        async move {
+            let nodelay = self.nodelay;
+            let accepting = self
+                .sockets
+                .iter()
+                .map(|(_, socket)| Box::pin(socket.accept(nodelay)));
+            let accepting: Vec<_> = accepting.collect();
+            if accepting.is_empty() {
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+            futures::future::select_all(accepting).await.0
+        }
+    }
+}
+
+/// A connection accepted by an [`ActivatedListener`].
+#[derive(Debug)]
+pub enum ActivatedStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for ActivatedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ActivatedStream::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            ActivatedStream::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ActivatedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ActivatedStream::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            ActivatedStream::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ActivatedStream::Tcp(s) => Pin::new(s).poll_flush(cx),
+            ActivatedStream::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ActivatedStream::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            ActivatedStream::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::transport::Listener;
+
+    #[test]
+    fn environment() {
+        assert_eq!(
+            listen_fds(Some("42"), Some("2"), Some("ssh:admin"), 42).unwrap(),
+            [(3, "ssh".to_string()), (4, "admin".to_string())]
+        );
+        assert_eq!(
+            listen_fds(Some("42"), Some("1"), None, 42).unwrap(),
+            [(3, "unknown".to_string())]
+        );
+        // Variables inherited from another process.
+        assert!(listen_fds(Some("41"), Some("1"), None, 42).is_none());
+        assert!(listen_fds(None, Some("1"), None, 42).is_none());
+        assert!(listen_fds(Some("42"), Some("0"), None, 42).is_none());
+    }
+
+    #[tokio::test]
+    async fn sockets() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ssh.sock");
+        let unix = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let mut listener = ActivatedListener {
+            sockets: vec![
+                ("tcp".into(), socket(OwnedFd::from(tcp)).unwrap()),
+                ("unix".into(), socket(OwnedFd::from(unix)).unwrap()),
+            ],
+            nodelay: true,
+        };
+        assert!(matches!(
+            listener.sockets(),
+            [(_, ActivatedSocket::Tcp(_)), (_, ActivatedSocket::Unix(_))]
+        ));
+
+        let mut client = UnixStream::connect(&path).await.unwrap();
+        let (mut stream, peer) = listener.accept().await.unwrap();
+        assert!(matches!(stream, ActivatedStream::Unix(_)) && peer.is_none());
+        client.write_all(b"SSH").await.unwrap();
+        let mut buf = [0; 3];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"SSH");
+
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (_, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer, Some(client.local_addr().unwrap()));
+
+        let (datagram, _) = std::os::unix::net::UnixDatagram::pair().unwrap();
+        assert!(socket(OwnedFd::from(datagram)).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Filtering of the connections by source address, before anything is
+//! sent to them.
+//!
+//! With [`Config::source_filter`](super::Config::source_filter),
+//! [`Server::run_on_listener`](super::Server::run_on_listener) closes
+//! the connections from the addresses which are not allowed right after
+//! accepting them, before sending the SSH identification string, and
+//! without starting a session or a key exchange:
+//!
+//! ```
+//! # use std::sync::Arc;
+//! # use russh::server::source_filter::SourceFilter;
+//! let config = russh::server::Config {
+//!     source_filter: Some(
+//!         SourceFilter::new()
+//!             .allow("10.0.0.0/8".parse().unwrap())
+//!             .allow("fd00::/8".parse().unwrap())
+//!             .deny("10.0.66.0/24".parse().unwrap())
+//!             .check(|source| source.to_string() != "10.0.0.13"),
+//!     ),
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! The connections without a source address, such as those accepted on
+//! Unix sockets, are not filtered.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// A range of IP addresses, in CIDR notation such as `192.168.0.0/16`
+/// or `2001:db8::/32`. A single address is a range of one address.
+///
+/// ```
+/// # use russh::server::source_filter::IpRange;
+/// let range: IpRange = "192.168.0.0/16".parse().unwrap();
+/// assert!(range.contains("192.168.1.2".parse().unwrap()));
+/// assert!(!range.contains("192.169.1.2".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// The addresses starting with the first `prefix_len` bits of
+    /// `network`, or `None` if `prefix_len` is longer than the address.
+    pub fn new(network: IpAddr, prefix_len: u8) -> Option<Self> {
+        let range = IpRange {
+            network,
+            prefix_len,
+        };
+        (prefix_len <= range.max_prefix_len()).then_some(range)
+    }
+
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    fn max_prefix_len(&self) -> u8 {
+        match self.network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// Whether `address` is in this range. IPv4 addresses mapped to
+    /// IPv6, as accepted on dual-stack sockets, are matched as IPv4
+    /// addresses.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let (address, network, len) = match (canonical(address), canonical(self.network)) {
+            (IpAddr::V4(a), IpAddr::V4(n)) => (u32::from(a) as u128, u32::from(n) as u128, 32),
+            (IpAddr::V6(a), IpAddr::V6(n)) => (u128::from(a), u128::from(n), 128),
+            _ => return false,
+        };
+        // A mapped network keeps the same bits in its IPv4 form.
+        let prefix_len =
+            u32::from(self.prefix_len).saturating_sub(self.max_prefix_len() as u32 - len);
+        let mask =
+            u128::MAX.checked_shl(len - prefix_len).unwrap_or(0) & (u128::MAX >> (128 - len));
+        address & mask == network & mask
+    }
+}
+
+/// `address`, or its IPv4 form if it is an IPv4 address mapped to IPv6.
+pub(super) fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        IpAddr::V4(_) => address,
+    }
+}
+
+impl From<IpAddr> for IpRange {
+    fn from(address: IpAddr) -> Self {
+        let mut range = IpRange {
+            network: address,
+            prefix_len: 0,
+        };
+        range.prefix_len = range.max_prefix_len();
+        range
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || crate::Error::InvalidConfig(format!("invalid address range: {s:?}"));
+        match s.split_once('/') {
+            None => Ok(IpRange::from(s.parse::<IpAddr>().map_err(|_| invalid())?)),
+            Some((network, prefix_len)) => IpRange::new(
+                network.parse().map_err(|_| invalid())?,
+                prefix_len.parse().map_err(|_| invalid())?,
+            )
+            .ok_or_else(invalid),
+        }
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+/// Which source addresses may connect. An address is allowed if it is
+/// in one of the [`allow`](SourceFilter::allow) ranges, or if there are
+/// none, and it is in none of the [`deny`](SourceFilter::deny) ranges,
+/// and the [`check`](SourceFilter::check) function, if any, accepts it.
+/// Everything is allowed by default.
+#[derive(Clone, Default)]
+pub struct SourceFilter {
+    allow: Vec<IpRange>,
+    deny: Vec<IpRange>,
+    check: Option<Arc<dyn Fn(IpAddr) -> bool + Send + Sync>>,
+}
+
+impl fmt::Debug for SourceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SourceFilter")
+            .field("allow", &self.allow)
+            .field("deny", &self.deny)
+            .field("check", &self.check.is_some())
+            .finish()
+    }
+}
+
+impl SourceFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only allow the addresses in `range`, and in the other ranges
+    /// allowed.
+    pub fn allow(mut self, range: IpRange) -> Self {
+        self.allow.push(range);
+        self
+    }
+
+    /// Refuse the addresses in `range`, even if they are in an allowed
+    /// range.
+    pub fn deny(mut self, range: IpRange) -> Self {
+        self.deny.push(range);
+        self
+    }
+
+    /// Also refuse the addresses for which `check` returns `false`, such
+    /// as those of a block list updated while the server runs. It is
+    /// only called for the addresses allowed by the ranges, on the task
+    /// accepting the connections, and must not block.
+    pub fn check<F>(mut self, check: F) -> Self
+    where
+        F: Fn(IpAddr) -> bool + Send + Sync + 'static,
+    {
+        self.check = Some(Arc::new(check));
+        self
+    }
+
+    /// Whether connections from `source` are allowed.
+    pub fn allows(&self, source: IpAddr) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|r| r.contains(source)))
+            && !self.deny.iter().any(|r| r.contains(source))
+            && self.check.as_ref().map_or(true, |check| check(source))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    fn addr(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn range() {
+        let range: IpRange = "192.168.0.0/16".parse().unwrap();
+        assert!(range.contains(addr("192.168.255.1")));
+        assert!(range.contains(addr("::ffff:192.168.0.1")));
+        assert!(!range.contains(addr("192.169.0.1")));
+        assert!(!range.contains(addr("2001:db8::1")));
+        assert_eq!(range.to_string(), "192.168.0.0/16");
+
+        let range: IpRange = "2001:db8::/32".parse().unwrap();
+        assert!(range.contains(addr("2001:db8:1::1")));
+        assert!(!range.contains(addr("2001:db9::1")));
+
+        let range: IpRange = "::ffff:10.0.0.0/104".parse().unwrap();
+        assert!(range.contains(addr("10.1.2.3")));
+        assert!(!range.contains(addr("11.1.2.3")));
+
+        let range: IpRange = "10.0.0.1".parse().unwrap();
+        assert!(range.contains(addr("10.0.0.1")));
+        assert!(!range.contains(addr("10.0.0.2")));
+
+        assert!("0.0.0.0/0"
+            .parse::<IpRange>()
+            .unwrap()
+            .contains(addr("1.2.3.4")));
+        for invalid in [
+            "",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "host/8",
+            "10.0.0.0/a",
+        ] {
+            assert!(invalid.parse::<IpRange>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn filter() {
+        assert!(SourceFilter::new().allows(addr("1.2.3.4")));
+
+        let filter = SourceFilter::new()
+            .allow("10.0.0.0/8".parse().unwrap())
+            .allow("fd00::/8".parse().unwrap())
+            .deny("10.0.66.0/24".parse().unwrap())
+            .check(|source| source != addr("10.0.0.13"));
+        assert!(filter.allows(addr("10.1.2.3")));
+        assert!(filter.allows(addr("fd12::1")));
+        assert!(!filter.allows(addr("192.168.1.1")));
+        assert!(!filter.allows(addr("10.0.66.1")));
+        assert!(!filter.allows(addr("10.0.0.13")));
+
+        let filter = SourceFilter::new().deny("::ffff:10.0.0.0/104".parse().unwrap());
+        assert!(!filter.allows(addr("10.0.0.1")));
+        assert!(filter.allows(addr("11.0.0.1")));
+    }
+}
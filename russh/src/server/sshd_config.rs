@@ -0,0 +1,1196 @@
+//! Loading server settings from OpenSSH `sshd_config` files.
+//!
+//! [`SshdConfig`] parses a configuration file, with its `Match` blocks
+//! and `Include` directives. The global algorithm, host key and
+//! authentication settings make a server [`Config`], and the settings
+//! that can depend on the connection make a [`Policy`], resolved with
+//! [`SshdConfig::policy`] once the user is known:
+//!
+//! ```no_run
+//! # use russh::server::sshd_config::{MatchContext, SshdConfig};
+//! # fn run() -> Result<(), russh::Error> {
+//! let sshd_config = SshdConfig::from_path("/etc/ssh/sshd_config")?;
+//! let config = std::sync::Arc::new(sshd_config.server_config()?);
+//! let policy = sshd_config.policy(&MatchContext {
+//!     user: "alice".into(),
+//!     address: "192.0.2.7".parse().ok(),
+//!     ..Default::default()
+//! })?;
+//! assert!(!policy.permits_login("root", russh::MethodKind::Password, false));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! As in `sshd(8)`, the first value obtained for each option wins, and
+//! the options of matching `Match` blocks override the global ones.
+//! Unsupported options are ignored.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::debug;
+use ssh_key::{Algorithm, Certificate};
+
+use super::authorized_keys::{match_address, AuthorizedPrincipals, KeyOptions, TrustedUserCaKeys};
+use super::penalties::{MemoryPenaltyStore, PerSourcePenalties};
+use super::revoked_keys::RevokedKeys;
+use super::Config;
+use crate::config_file::{
+    algorithm_list, match_patterns, parse_bool, split_args, split_line, MAX_INCLUDE_DEPTH,
+};
+use crate::helpers::wildcard_match;
+use crate::{cipher, kex, mac, Error, MethodKind};
+
+/// The default `LoginGraceTime` of `sshd`.
+const DEFAULT_LOGIN_GRACE_TIME: Duration = Duration::from_secs(120);
+
+/// The default `MaxSessions` of `sshd`.
+const DEFAULT_MAX_SESSIONS: usize = 10;
+
+/// Relative `Include` paths are looked up here.
+const SSHD_CONFIG_DIR: &str = "/etc/ssh";
+
+/// The options allowed in `Match` blocks. They make the [`Policy`].
+const POLICY_KEYWORDS: &[&str] = &[
+    "allowagentforwarding",
+    "allowtcpforwarding",
+    "authorizedkeysfile",
+    "authorizedprincipalsfile",
+    "forcecommand",
+    "kbdinteractiveauthentication",
+    "challengeresponseauthentication",
+    "chrootdirectory",
+    "maxauthtries",
+    "passwordauthentication",
+    "permitrootlogin",
+    "permittty",
+    "pubkeyauthentication",
+    "subsystem",
+    "trustedusercakeys",
+    "x11forwarding",
+];
+
+#[derive(Debug, Clone)]
+enum Criterion {
+    All,
+    User(String),
+    Group(String),
+    Host(String),
+    Address(String),
+    LocalAddress(String),
+    LocalPort(String),
+}
+
+#[derive(Debug, Clone)]
+struct Directive {
+    line: usize,
+    keyword: String,
+    args: Vec<String>,
+}
+
+impl Directive {
+    fn invalid(&self) -> Error {
+        Error::InvalidConfig(format!(
+            "invalid {} at line {}: {:?}",
+            self.keyword, self.line, self.args
+        ))
+    }
+
+    fn first(&self) -> Result<&str, Error> {
+        self.args
+            .first()
+            .map(|a| a.as_str())
+            .ok_or_else(|| self.invalid())
+    }
+
+    fn bool(&self) -> Result<bool, Error> {
+        parse_bool(self.first()?).ok_or_else(|| self.invalid())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct MatchBlock {
+    criteria: Vec<Criterion>,
+    directives: Vec<Directive>,
+}
+
+/// A parsed `sshd_config` file.
+#[derive(Debug, Clone, Default)]
+pub struct SshdConfig {
+    global: Vec<Directive>,
+    matches: Vec<MatchBlock>,
+}
+
+/// What is known of a connection when resolving its [`Policy`], to
+/// evaluate `Match` blocks. Criteria about unknown values never match.
+#[derive(Debug, Clone, Default)]
+pub struct MatchContext {
+    pub user: String,
+    /// The groups of the user.
+    pub groups: Vec<String>,
+    /// The host name of the client.
+    pub host: Option<String>,
+    /// The address of the client.
+    pub address: Option<IpAddr>,
+    /// The address and port the client connected to.
+    pub local_address: Option<IpAddr>,
+    pub local_port: Option<u16>,
+}
+
+/// Value of the `PermitRootLogin` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PermitRootLogin {
+    Yes,
+    /// `prohibit-password`, or its alias `without-password`: root can
+    /// only log in with public keys.
+    #[default]
+    ProhibitPassword,
+    /// Root can only log in with public keys that have a forced
+    /// command.
+    ForcedCommandsOnly,
+    No,
+}
+
+/// Value of the `AllowTcpForwarding` option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AllowTcpForwarding {
+    #[default]
+    All,
+    Local,
+    Remote,
+    No,
+}
+
+/// The settings of `sshd_config` that apply to one connection, for
+/// the server handler to enforce.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    pub permit_root_login: PermitRootLogin,
+    pub allow_tcp_forwarding: AllowTcpForwarding,
+    pub allow_agent_forwarding: bool,
+    pub x11_forwarding: bool,
+    pub permit_tty: bool,
+    pub max_auth_tries: usize,
+    pub force_command: Option<String>,
+    pub password_authentication: bool,
+    pub pubkey_authentication: bool,
+    pub kbd_interactive_authentication: bool,
+    /// `AuthorizedKeysFile`, as written in the file. See
+    /// [`Policy::authorized_keys_files`].
+    pub authorized_keys_file: Vec<String>,
+    /// `AuthorizedPrincipalsFile`, as written in the file, unless it is
+    /// `none`. See [`Policy::authorize_certificate`].
+    pub authorized_principals_file: Option<String>,
+    /// `TrustedUserCAKeys`, unless it is `none`.
+    pub trusted_user_ca_keys: Option<String>,
+    /// The `Subsystem` commands, by name.
+    pub subsystems: Vec<(String, String)>,
+    /// `ChrootDirectory`, as written in the file, unless it is `none`.
+    /// See [`Policy::chroot_path`].
+    pub chroot_directory: Option<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            permit_root_login: PermitRootLogin::default(),
+            allow_tcp_forwarding: AllowTcpForwarding::default(),
+            allow_agent_forwarding: true,
+            x11_forwarding: false,
+            permit_tty: true,
+            max_auth_tries: 6,
+            force_command: None,
+            password_authentication: true,
+            pubkey_authentication: true,
+            kbd_interactive_authentication: true,
+            authorized_keys_file: vec![
+                ".ssh/authorized_keys".into(),
+                ".ssh/authorized_keys2".into(),
+            ],
+            authorized_principals_file: None,
+            trusted_user_ca_keys: None,
+            subsystems: Vec::new(),
+            chroot_directory: None,
+        }
+    }
+}
+
+impl Policy {
+    /// Whether `user` may log in with `method`. `forced_command` tells
+    /// whether the key used has a forced command, for
+    /// `PermitRootLogin forced-commands-only`.
+    pub fn permits_login(&self, user: &str, method: MethodKind, forced_command: bool) -> bool {
+        let enabled = match method {
+            MethodKind::Password => self.password_authentication,
+            MethodKind::PublicKey => self.pubkey_authentication,
+            MethodKind::KeyboardInteractive => self.kbd_interactive_authentication,
+            MethodKind::None | MethodKind::HostBased => true,
+            #[cfg(feature = "gssapi")]
+            MethodKind::GssapiWithMic => true,
+        };
+        if !enabled {
+            return false;
+        }
+        if user != "root" {
+            return true;
+        }
+        match self.permit_root_login {
+            PermitRootLogin::Yes => true,
+            PermitRootLogin::ProhibitPassword => !matches!(
+                method,
+                MethodKind::None | MethodKind::Password | MethodKind::KeyboardInteractive
+            ),
+            PermitRootLogin::ForcedCommandsOnly => {
+                method == MethodKind::PublicKey && forced_command
+            }
+            PermitRootLogin::No => false,
+        }
+    }
+
+    /// Whether `direct-tcpip` channels are allowed.
+    pub fn permits_local_forwarding(&self) -> bool {
+        matches!(
+            self.allow_tcp_forwarding,
+            AllowTcpForwarding::All | AllowTcpForwarding::Local
+        )
+    }
+
+    /// Whether `tcpip-forward` requests are allowed.
+    pub fn permits_remote_forwarding(&self) -> bool {
+        matches!(
+            self.allow_tcp_forwarding,
+            AllowTcpForwarding::All | AllowTcpForwarding::Remote
+        )
+    }
+
+    /// The command of the subsystem `name`, if it is configured.
+    pub fn subsystem(&self, name: &str) -> Option<&str> {
+        self.subsystems
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, command)| command.as_str())
+    }
+
+    /// Whether the user may only use SFTP, with `ForceCommand
+    /// internal-sftp`, which
+    /// [`Session::set_sftp_only`](super::Session::set_sftp_only)
+    /// enforces.
+    pub fn sftp_only(&self) -> bool {
+        self.force_command
+            .as_deref()
+            .is_some_and(|command| command.split_whitespace().next() == Some("internal-sftp"))
+    }
+
+    /// The directory that the files of `user`, whose home directory is
+    /// `home`, are served from, with the tokens of `ChrootDirectory`
+    /// expanded as [`Policy::authorized_keys_files`], such as to serve
+    /// an SFTP-only user with a `LocalFs` of the `sftp` feature.
+    pub fn chroot_path(&self, user: &str, home: &Path) -> Option<PathBuf> {
+        self.chroot_directory
+            .as_ref()
+            .map(|dir| expand_path(dir, user, home))
+    }
+
+    /// The `authorized_keys` files of `user`, whose home directory is
+    /// `home`, with the `%%`, `%h` and `%u` tokens expanded. Relative
+    /// paths are relative to `home`.
+    pub fn authorized_keys_files(&self, user: &str, home: &Path) -> Vec<PathBuf> {
+        self.authorized_keys_file
+            .iter()
+            .filter(|f| !f.eq_ignore_ascii_case("none"))
+            .map(|f| expand_path(f, user, home))
+            .collect()
+    }
+
+    /// The `authorized_principals` file of `user`, expanded as
+    /// [`Policy::authorized_keys_files`].
+    pub fn authorized_principals_path(&self, user: &str, home: &Path) -> Option<PathBuf> {
+        self.authorized_principals_file
+            .as_ref()
+            .map(|f| expand_path(f, user, home))
+    }
+
+    /// The options authorizing `certificate` for `user`, whose home
+    /// directory is `home`, connecting from `client`, or `None` if it is
+    /// not authorized, as `sshd` does for `TrustedUserCAKeys`. The
+    /// certificate must be signed by one of the trusted keys, and list
+    /// one of the principals of the `AuthorizedPrincipalsFile` of the
+    /// user if there is one, or the user itself otherwise.
+    pub fn authorize_certificate(
+        &self,
+        certificate: &Certificate,
+        user: &str,
+        home: &Path,
+        client: Option<IpAddr>,
+    ) -> Result<Option<KeyOptions>, Error> {
+        let Some(ref trusted_user_ca_keys) = self.trusted_user_ca_keys else {
+            return Ok(None);
+        };
+        let trusted = TrustedUserCaKeys::from_path(trusted_user_ca_keys)?;
+        Ok(match self.authorized_principals_path(user, home) {
+            Some(path) => {
+                let principals = AuthorizedPrincipals::from_path(path)?;
+                trusted.authorize_principals(certificate, &principals, client)
+            }
+            None => trusted.authorize(certificate, user, client),
+        })
+    }
+
+    fn apply(&mut self, directive: &Directive, seen: &mut HashSet<String>) -> Result<(), Error> {
+        let keyword = directive.keyword.as_str();
+        if keyword == "subsystem" {
+            // Subsystems are looked up by name. The first one wins.
+            let [name, command @ ..] = directive.args.as_slice() else {
+                return Err(directive.invalid());
+            };
+            if command.is_empty() {
+                return Err(directive.invalid());
+            }
+            if self.subsystem(name).is_none() {
+                self.subsystems.push((name.clone(), command.join(" ")));
+            }
+            return Ok(());
+        }
+        let keyword = match keyword {
+            "challengeresponseauthentication" => "kbdinteractiveauthentication",
+            k => k,
+        };
+        if !seen.insert(keyword.to_string()) {
+            return Ok(());
+        }
+        let first = directive.first()?;
+        match keyword {
+            "allowagentforwarding" => self.allow_agent_forwarding = directive.bool()?,
+            "allowtcpforwarding" => {
+                self.allow_tcp_forwarding = match first.to_ascii_lowercase().as_str() {
+                    "yes" | "all" => AllowTcpForwarding::All,
+                    "local" => AllowTcpForwarding::Local,
+                    "remote" => AllowTcpForwarding::Remote,
+                    "no" => AllowTcpForwarding::No,
+                    _ => return Err(directive.invalid()),
+                }
+            }
+            "authorizedkeysfile" => self.authorized_keys_file = directive.args.clone(),
+            "authorizedprincipalsfile" => {
+                self.authorized_principals_file =
+                    (!first.eq_ignore_ascii_case("none")).then(|| first.to_string())
+            }
+            "chrootdirectory" => {
+                self.chroot_directory =
+                    (!first.eq_ignore_ascii_case("none")).then(|| first.to_string())
+            }
+            "forcecommand" => {
+                self.force_command =
+                    (!first.eq_ignore_ascii_case("none")).then(|| directive.args.join(" "))
+            }
+            "kbdinteractiveauthentication" => {
+                self.kbd_interactive_authentication = directive.bool()?
+            }
+            "maxauthtries" => {
+                self.max_auth_tries = first.parse().map_err(|_| directive.invalid())?
+            }
+            "passwordauthentication" => self.password_authentication = directive.bool()?,
+            "permitrootlogin" => {
+                self.permit_root_login = match first.to_ascii_lowercase().as_str() {
+                    "yes" => PermitRootLogin::Yes,
+                    "prohibit-password" | "without-password" => PermitRootLogin::ProhibitPassword,
+                    "forced-commands-only" => PermitRootLogin::ForcedCommandsOnly,
+                    "no" => PermitRootLogin::No,
+                    _ => return Err(directive.invalid()),
+                }
+            }
+            "permittty" => self.permit_tty = directive.bool()?,
+            "pubkeyauthentication" => self.pubkey_authentication = directive.bool()?,
+            "trustedusercakeys" => {
+                self.trusted_user_ca_keys =
+                    (!first.eq_ignore_ascii_case("none")).then(|| first.to_string())
+            }
+            "x11forwarding" => self.x11_forwarding = directive.bool()?,
+            _ => debug!("sshd_config: unsupported option {keyword:?}"),
+        }
+        Ok(())
+    }
+}
+
+/// Expand the `%%`, `%h` and `%u` tokens of `path`, relative to `home`.
+fn expand_path(path: &str, user: &str, home: &Path) -> PathBuf {
+    let path = path
+        .replace("%%", "\0")
+        .replace("%h", &home.to_string_lossy())
+        .replace("%u", user)
+        .replace('\0', "%");
+    home.join(path)
+}
+
+impl SshdConfig {
+    /// Parse the contents of a configuration file. Relative `Include`
+    /// paths are looked up in `/etc/ssh`.
+    ///
+    /// Like `sshd -t`, this checks the values of the options, and that
+    /// `Match` blocks only contain options that can depend on the
+    /// connection.
+    pub fn parse(contents: &str) -> Result<Self, Error> {
+        let mut config = SshdConfig::default();
+        config.parse_into(contents, None, 0)?;
+        config.check()?;
+        Ok(config)
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)?;
+        Self::parse(&contents)
+    }
+
+    /// Parse `contents` into the `Match` block at index `outer`, or
+    /// into the global section.
+    fn parse_into(
+        &mut self,
+        contents: &str,
+        outer: Option<usize>,
+        depth: usize,
+    ) -> Result<(), Error> {
+        let mut current = outer;
+        for (n, line) in contents.lines().enumerate() {
+            let line_number = n + 1;
+            let Some((keyword, args)) = split_line(line) else {
+                continue;
+            };
+            let args = split_args(args);
+            let invalid =
+                |what: &str| Error::InvalidConfig(format!("invalid {what} at line {line_number}"));
+            match keyword.as_str() {
+                "match" => {
+                    if outer.is_some() {
+                        return Err(invalid("Match (in a file included from a Match block)"));
+                    }
+                    let criteria = parse_match(&args).ok_or_else(|| invalid("Match"))?;
+                    current = Some(self.matches.len());
+                    self.matches.push(MatchBlock {
+                        criteria,
+                        directives: Vec::new(),
+                    });
+                }
+                "include" => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(invalid("Include (too deeply nested)"));
+                    }
+                    for path in args {
+                        for path in expand_wildcards(Path::new(SSHD_CONFIG_DIR).join(path))? {
+                            debug!("sshd_config: including {path:?}");
+                            let contents = std::fs::read_to_string(&path)?;
+                            self.parse_into(&contents, current, depth + 1)?;
+                        }
+                    }
+                }
+                _ => {
+                    let directive = Directive {
+                        line: line_number,
+                        keyword,
+                        args,
+                    };
+                    match current.and_then(|i| self.matches.get_mut(i)) {
+                        Some(block) => block.directives.push(directive),
+                        None => self.global.push(directive),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let mut config = Config::default();
+        let mut seen = HashSet::new();
+        for directive in &self.global {
+            if !POLICY_KEYWORDS.contains(&directive.keyword.as_str()) {
+                apply_global(&mut config, directive, &mut seen, false)?;
+            }
+        }
+        for block in &self.matches {
+            for directive in &block.directives {
+                if !POLICY_KEYWORDS.contains(&directive.keyword.as_str()) {
+                    return Err(Error::InvalidConfig(format!(
+                        "{} is not allowed in a Match block, at line {}",
+                        directive.keyword, directive.line
+                    )));
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        let mut policy = Policy::default();
+        for directive in self.policy_directives(None) {
+            policy.apply(directive, &mut seen)?;
+            // Every occurrence must be valid, not only the first.
+            seen.clear();
+        }
+        Ok(())
+    }
+
+    /// The policy directives that apply in `context`, or all of them if
+    /// it is `None`, in order of precedence.
+    fn policy_directives<'a>(
+        &'a self,
+        context: Option<&'a MatchContext>,
+    ) -> impl Iterator<Item = &'a Directive> {
+        self.matches
+            .iter()
+            .filter(move |block| {
+                context.map_or(true, |context| {
+                    block.criteria.iter().all(|c| c.matches(context))
+                })
+            })
+            .flat_map(|block| &block.directives)
+            .chain(
+                self.global
+                    .iter()
+                    .filter(|d| POLICY_KEYWORDS.contains(&d.keyword.as_str())),
+            )
+    }
+
+    /// A server [`Config`] with the global `Ciphers`, `MACs`,
+    /// `KexAlgorithms`, `HostKeyAlgorithms`, `HostKey`,
+    /// `HostCertificate`, `MaxAuthTries`, `MaxSessions`, `MaxStartups`,
+    /// `PerSourcePenalties`, `LoginGraceTime`, `ClientAliveInterval`,
+    /// `ClientAliveCountMax` and `AcceptEnv` options, and the
+    /// authentication methods enabled globally. The host keys and
+    /// certificates are loaded, and the keys must not be encrypted.
+    pub fn server_config(&self) -> Result<Config, Error> {
+        let mut config = Config {
+            login_grace_time: Some(DEFAULT_LOGIN_GRACE_TIME),
+            max_sessions: Some(DEFAULT_MAX_SESSIONS),
+            per_source_penalties: Some(PerSourcePenalties::default()),
+            ..Default::default()
+        };
+        let mut seen = HashSet::new();
+        for directive in &self.global {
+            apply_global(&mut config, directive, &mut seen, true)?;
+        }
+        let policy = self.global_policy()?;
+        config.max_auth_attempts = policy.max_auth_tries;
+        for (enabled, method) in [
+            (policy.password_authentication, MethodKind::Password),
+            (policy.pubkey_authentication, MethodKind::PublicKey),
+            (
+                policy.kbd_interactive_authentication,
+                MethodKind::KeyboardInteractive,
+            ),
+        ] {
+            if !enabled {
+                config.methods.remove(method)
+            }
+        }
+        Ok(config)
+    }
+
+    /// The policy of the global section, ignoring `Match` blocks.
+    pub fn global_policy(&self) -> Result<Policy, Error> {
+        let mut policy = Policy::default();
+        let mut seen = HashSet::new();
+        for directive in self
+            .global
+            .iter()
+            .filter(|d| POLICY_KEYWORDS.contains(&d.keyword.as_str()))
+        {
+            policy.apply(directive, &mut seen)?;
+        }
+        Ok(policy)
+    }
+
+    /// The policy for a connection, with the options of the `Match`
+    /// blocks that apply to it.
+    pub fn policy(&self, context: &MatchContext) -> Result<Policy, Error> {
+        let mut policy = Policy::default();
+        let mut seen = HashSet::new();
+        for directive in self.policy_directives(Some(context)) {
+            policy.apply(directive, &mut seen)?;
+        }
+        Ok(policy)
+    }
+}
+
+/// Apply a global option to `config`. Host keys and the banner are only
+/// loaded if `load_keys` is set.
+fn apply_global(
+    config: &mut Config,
+    directive: &Directive,
+    seen: &mut HashSet<String>,
+    load_keys: bool,
+) -> Result<(), Error> {
+    let keyword = directive.keyword.as_str();
+    let first = directive.first()?;
+    match keyword {
+        // Options that accumulate.
+        "hostkey" => {
+            if load_keys {
+                config.keys.push(crate::keys::load_secret_key(first, None)?);
+            }
+            return Ok(());
+        }
+        "hostcertificate" => {
+            if load_keys {
+                config
+                    .host_certificates
+                    .push(crate::keys::load_openssh_certificate(first)?);
+            }
+            return Ok(());
+        }
+        "acceptenv" => {
+            config
+                .accept_env
+                .get_or_insert_with(Vec::new)
+                .extend(directive.args.iter().cloned());
+            return Ok(());
+        }
+        _ => {}
+    }
+    if !seen.insert(directive.keyword.clone()) {
+        return Ok(());
+    }
+    let preferred = &mut config.preferred;
+    match keyword {
+        "ciphers" => {
+            preferred.cipher = Cow::Owned(algorithm_list(&preferred.cipher, first, |n| {
+                cipher::Name::try_from(n).ok()
+            })?)
+        }
+        "macs" => {
+            preferred.mac = Cow::Owned(algorithm_list(&preferred.mac, first, |n| {
+                mac::Name::try_from(n).ok()
+            })?)
+        }
+        "kexalgorithms" => {
+            let mut kex = algorithm_list(&preferred.kex, first, |n| kex::Name::try_from(n).ok())?;
+            // The extension pseudo-algorithms are not part of the list,
+            // but must still be advertised.
+            for ext in [
+                kex::EXTENSION_SUPPORT_AS_SERVER,
+                kex::EXTENSION_OPENSSH_STRICT_KEX_AS_SERVER,
+            ] {
+                if !kex.contains(&ext) {
+                    kex.push(ext)
+                }
+            }
+            preferred.kex = Cow::Owned(kex);
+        }
+        "hostkeyalgorithms" => {
+            preferred.key = Cow::Owned(algorithm_list(&preferred.key, first, |n| {
+                Algorithm::new(n).ok()
+            })?)
+        }
+        "clientaliveinterval" => {
+            let interval = parse_time(first).ok_or_else(|| directive.invalid())?;
+            config.keepalive_interval = (!interval.is_zero()).then_some(interval);
+        }
+        "clientalivecountmax" => {
+            config.keepalive_max = first.parse().map_err(|_| directive.invalid())?
+        }
+        "logingracetime" => {
+            let grace_time = parse_time(first).ok_or_else(|| directive.invalid())?;
+            config.login_grace_time = (!grace_time.is_zero()).then_some(grace_time);
+        }
+        "persourcepenalties" => config.per_source_penalties = per_source_penalties(directive)?,
+        "authenticationmethods" => {
+            config.authentication_methods = if first == "any" {
+                Vec::new()
+            } else {
+                directive
+                    .args
+                    .iter()
+                    .map(|list| authentication_methods(directive, list))
+                    .collect::<Result<_, _>>()?
+            }
+        }
+        "maxsessions" => {
+            config.max_sessions = Some(first.parse().map_err(|_| directive.invalid())?)
+        }
+        "maxstartups" => {
+            config.max_startups = Some(first.parse().map_err(|_| directive.invalid())?)
+        }
+        "banner" => {
+            if load_keys && first != "none" {
+                config.auth_banner = Some(std::fs::read_to_string(first)?);
+            }
+        }
+        "revokedkeys" => {
+            if load_keys && first != "none" {
+                config.revoked_keys = Some(Arc::new(RevokedKeys::from_path(first)?));
+            }
+        }
+        _ => debug!("sshd_config: unsupported option {keyword:?}"),
+    }
+    Ok(())
+}
+
+/// Parse a comma-separated list of `AuthenticationMethods`. The
+/// submethods, as in `keyboard-interactive:pam`, are left to the
+/// handler.
+fn authentication_methods(directive: &Directive, list: &str) -> Result<Vec<MethodKind>, Error> {
+    list.split(',')
+        .map(|method| {
+            let name = method.split_once(':').map_or(method, |(name, _)| name);
+            name.parse().map_err(|_| directive.invalid())
+        })
+        .collect()
+}
+
+/// Parse the arguments of `PerSourcePenalties`, which disable the
+/// penalties with `no`.
+fn per_source_penalties(directive: &Directive) -> Result<Option<PerSourcePenalties>, Error> {
+    let mut penalties = PerSourcePenalties::default();
+    let mut max_sources = None;
+    for arg in &directive.args {
+        match parse_bool(arg) {
+            Some(false) => return Ok(None),
+            Some(true) => continue,
+            None => {}
+        }
+        let (key, value) = arg.split_once(':').ok_or_else(|| directive.invalid())?;
+        let time = || parse_time(value).ok_or_else(|| directive.invalid());
+        match key {
+            "crash" => penalties.crash = time()?,
+            "authfail" => penalties.auth_failure = time()?,
+            "noauth" => penalties.no_auth = time()?,
+            "grace-exceeded" => penalties.grace_exceeded = time()?,
+            "max" => penalties.max = time()?,
+            "min" => penalties.min = time()?,
+            // A single store holds both address families.
+            "max-sources4" | "max-sources6" => {
+                let n: usize = value.parse().map_err(|_| directive.invalid())?;
+                max_sources = max_sources.max(Some(n));
+            }
+            "overflow" | "overflow6" => debug!("sshd_config: unsupported penalty {key:?}"),
+            _ => return Err(directive.invalid()),
+        }
+    }
+    if let Some(max_sources) = max_sources {
+        penalties.store = Arc::new(MemoryPenaltyStore::new(max_sources));
+    }
+    Ok(Some(penalties))
+}
+
+/// Parse a time in the format of `sshd_config(5)`, a number of seconds
+/// or a sequence of numbers with units, such as `1h30m`.
+fn parse_time(s: &str) -> Option<Duration> {
+    if s.is_empty() {
+        return None;
+    }
+    let mut seconds = 0u64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (n, tail) = rest.split_at(digits);
+        let n: u64 = n.parse().ok()?;
+        let mut chars = tail.chars();
+        let unit = match chars.next() {
+            None | Some('s' | 'S') => 1,
+            Some('m' | 'M') => 60,
+            Some('h' | 'H') => 60 * 60,
+            Some('d' | 'D') => 24 * 60 * 60,
+            Some('w' | 'W') => 7 * 24 * 60 * 60,
+            Some(_) => return None,
+        };
+        seconds = seconds.checked_add(n.checked_mul(unit)?)?;
+        rest = chars.as_str();
+    }
+    Some(Duration::from_secs(seconds))
+}
+
+impl Criterion {
+    fn matches(&self, context: &MatchContext) -> bool {
+        match self {
+            Criterion::All => true,
+            Criterion::User(patterns) => match_list(&context.user, patterns),
+            Criterion::Group(patterns) => context.groups.iter().any(|g| match_list(g, patterns)),
+            Criterion::Host(patterns) => context.host.as_ref().is_some_and(|host| {
+                match_list(&host.to_ascii_lowercase(), &patterns.to_ascii_lowercase())
+            }),
+            Criterion::Address(patterns) => context
+                .address
+                .is_some_and(|address| match_address(address, patterns)),
+            Criterion::LocalAddress(patterns) => context
+                .local_address
+                .is_some_and(|address| match_address(address, patterns)),
+            Criterion::LocalPort(patterns) => context
+                .local_port
+                .is_some_and(|port| match_list(&port.to_string(), patterns)),
+        }
+    }
+}
+
+fn parse_match(args: &[String]) -> Option<Vec<Criterion>> {
+    let mut criteria = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let name = arg.to_ascii_lowercase();
+        let criterion = if name == "all" {
+            Criterion::All
+        } else {
+            let value = args.next()?.clone();
+            match name.as_str() {
+                "user" => Criterion::User(value),
+                "group" => Criterion::Group(value),
+                "host" => Criterion::Host(value),
+                "address" => Criterion::Address(value),
+                "localaddress" => Criterion::LocalAddress(value),
+                "localport" => Criterion::LocalPort(value),
+                _ => return None,
+            }
+        };
+        criteria.push(criterion);
+    }
+    if criteria.is_empty() {
+        return None;
+    }
+    Some(criteria)
+}
+
+/// [`match_patterns`] with the `*` and `?` wildcards of `sshd_config`.
+fn match_list(s: &str, patterns: &str) -> bool {
+    match_patterns(patterns, |pattern| {
+        wildcard_match(s.as_bytes(), pattern.as_bytes())
+    })
+}
+
+/// The files matching `path`, sorted, if its file name contains
+/// wildcards, or `path` itself.
+fn expand_wildcards(path: PathBuf) -> Result<Vec<PathBuf>, Error> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(vec![path]);
+    };
+    let name = name.to_string_lossy();
+    if !name.contains(['*', '?']) {
+        return Ok(vec![path]);
+    }
+    // Only the file name may contain wildcards.
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if wildcard_match(
+            entry.file_name().to_string_lossy().as_bytes(),
+            name.as_bytes(),
+        ) {
+            paths.push(entry.path())
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    const CONFIG: &str = r#"
+# A hardened baseline.
+Ciphers chacha20-poly1305@openssh.com,aes256-gcm@openssh.com
+KexAlgorithms curve25519-sha256
+MACs -hmac-sha1
+PermitRootLogin no
+PasswordAuthentication no
+MaxAuthTries 3
+AuthenticationMethods publickey,keyboard-interactive:pam publickey,password
+MaxSessions 2
+MaxStartups 10:30:100
+LoginGraceTime 1m30s
+PerSourcePenalties authfail:10 max:1h
+AllowTcpForwarding no
+ClientAliveInterval 30
+Subsystem sftp internal-sftp -l INFO
+AcceptEnv LANG LC_*
+
+Match User admin Address 10.0.0.0/8
+    AllowTcpForwarding local
+    PasswordAuthentication yes
+
+Match Group sftponly
+    ForceCommand internal-sftp
+    ChrootDirectory /srv/sftp/%u
+    PermitTTY no
+    AllowTcpForwarding yes
+"#;
+
+    #[test]
+    fn server_config() {
+        let config = SshdConfig::parse(CONFIG).unwrap().server_config().unwrap();
+        assert_eq!(
+            &config.preferred.cipher[..],
+            &[cipher::CHACHA20_POLY1305, cipher::AES_256_GCM]
+        );
+        assert_eq!(
+            &config.preferred.kex[..],
+            &[
+                kex::CURVE25519,
+                kex::EXTENSION_SUPPORT_AS_SERVER,
+                kex::EXTENSION_OPENSSH_STRICT_KEX_AS_SERVER
+            ]
+        );
+        assert!(!config.preferred.mac.contains(&mac::HMAC_SHA1));
+        assert_eq!(config.max_auth_attempts, 3);
+        assert_eq!(
+            config.authentication_methods,
+            [
+                [MethodKind::PublicKey, MethodKind::KeyboardInteractive],
+                [MethodKind::PublicKey, MethodKind::Password]
+            ]
+        );
+        assert_eq!(
+            config.max_startups,
+            Some(crate::server::MaxStartups {
+                start: 10,
+                rate: 30,
+                full: 100
+            })
+        );
+        assert_eq!(config.max_sessions, Some(2));
+        assert_eq!(config.keepalive_interval, Some(Duration::from_secs(30)));
+        assert_eq!(config.login_grace_time, Some(Duration::from_secs(90)));
+        let penalties = config.per_source_penalties.as_ref().unwrap();
+        assert_eq!(penalties.auth_failure, Duration::from_secs(10));
+        assert_eq!(penalties.max, Duration::from_secs(3600));
+        assert_eq!(penalties.crash, Duration::from_secs(90));
+        assert!(!config.methods.contains(&MethodKind::Password));
+        assert!(config.methods.contains(&MethodKind::PublicKey));
+        assert!(config.accepts_env("LC_ALL"));
+        assert!(!config.accepts_env("PATH"));
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_time("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_time("1W2d"), Some(Duration::from_secs(9 * 86400)));
+        assert_eq!(parse_time(""), None);
+        assert_eq!(parse_time("m"), None);
+        assert_eq!(parse_time("10y"), None);
+
+        let grace_time = |contents: &str| {
+            SshdConfig::parse(contents)
+                .unwrap()
+                .server_config()
+                .unwrap()
+                .login_grace_time
+        };
+        assert_eq!(grace_time(""), Some(DEFAULT_LOGIN_GRACE_TIME));
+        assert_eq!(grace_time("LoginGraceTime 0\n"), None);
+
+        let penalties = |contents: &str| {
+            SshdConfig::parse(contents)
+                .unwrap()
+                .server_config()
+                .unwrap()
+                .per_source_penalties
+        };
+        assert!(penalties("").is_some());
+        assert!(penalties("PerSourcePenalties no\n").is_none());
+    }
+
+    #[test]
+    fn host_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ssh_host_ed25519_key");
+        let key = ssh_key::PrivateKey::random(&mut rand_core::OsRng, Algorithm::Ed25519).unwrap();
+        std::fs::write(&path, key.to_openssh(ssh_key::LineEnding::LF).unwrap()).unwrap();
+        let ca = ssh_key::PrivateKey::random(&mut rand_core::OsRng, Algorithm::Ed25519).unwrap();
+        let mut builder = ssh_key::certificate::Builder::new_with_random_nonce(
+            &mut rand_core::OsRng,
+            key.public_key().key_data().clone(),
+            0,
+            u64::MAX >> 2,
+        )
+        .unwrap();
+        builder
+            .cert_type(ssh_key::certificate::CertType::Host)
+            .unwrap();
+        builder.all_principals_valid().unwrap();
+        let cert = builder.sign(&ca).unwrap();
+        let cert_path = dir.path().join("ssh_host_ed25519_key-cert.pub");
+        std::fs::write(&cert_path, cert.to_openssh().unwrap()).unwrap();
+        let config = SshdConfig::parse(&format!(
+            "HostKey {}\nHostCertificate {}\n",
+            path.display(),
+            cert_path.display()
+        ))
+        .unwrap()
+        .server_config()
+        .unwrap();
+        assert_eq!(config.keys.len(), 1);
+        assert_eq!(config.keys.first().unwrap().public_key(), key.public_key());
+        assert_eq!(config.host_certificates, vec![cert]);
+    }
+
+    #[test]
+    fn banner() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("issue.net");
+        std::fs::write(&path, "Authorized use only\n").unwrap();
+        let config = SshdConfig::parse(&format!("Banner {}\n", path.display()))
+            .unwrap()
+            .server_config()
+            .unwrap();
+        assert_eq!(config.auth_banner.as_deref(), Some("Authorized use only\n"));
+
+        let config = SshdConfig::parse("Banner none\n")
+            .unwrap()
+            .server_config()
+            .unwrap();
+        assert_eq!(config.auth_banner, None);
+    }
+
+    #[test]
+    fn revoked_keys() {
+        let key = ssh_key::PrivateKey::random(&mut rand_core::OsRng, Algorithm::Ed25519).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("revoked_keys");
+        std::fs::write(&path, key.public_key().to_openssh().unwrap()).unwrap();
+        let config = SshdConfig::parse(&format!("RevokedKeys {}\n", path.display()))
+            .unwrap()
+            .server_config()
+            .unwrap();
+        assert!(config.revoked_keys.unwrap().is_revoked(key.public_key()));
+    }
+
+    #[test]
+    fn policy() {
+        let config = SshdConfig::parse(CONFIG).unwrap();
+        let context = |user: &str, address: &str, groups: &[&str]| MatchContext {
+            user: user.into(),
+            address: address.parse().ok(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+            ..Default::default()
+        };
+
+        let policy = config.policy(&context("bob", "192.0.2.1", &[])).unwrap();
+        assert_eq!(policy, config.global_policy().unwrap());
+        assert!(!policy.permits_local_forwarding());
+        assert!(!policy.permits_login("bob", MethodKind::Password, false));
+        assert!(policy.permits_login("bob", MethodKind::PublicKey, false));
+        assert!(!policy.permits_login("root", MethodKind::PublicKey, true));
+        assert_eq!(policy.subsystem("sftp"), Some("internal-sftp -l INFO"));
+
+        let policy = config
+            .policy(&context("admin", "10.1.2.3", &["sftponly"]))
+            .unwrap();
+        assert!(policy.permits_local_forwarding());
+        assert!(!policy.permits_remote_forwarding());
+        assert!(policy.permits_login("admin", MethodKind::Password, false));
+        assert_eq!(policy.force_command.as_deref(), Some("internal-sftp"));
+        assert!(policy.sftp_only());
+        assert_eq!(
+            policy.chroot_path("admin", Path::new("/home/admin")),
+            Some(PathBuf::from("/srv/sftp/admin"))
+        );
+        assert!(!policy.permit_tty);
+
+        let policy = config.policy(&context("admin", "192.0.2.1", &[])).unwrap();
+        assert!(!policy.permits_local_forwarding());
+
+        assert_eq!(
+            Policy::default().authorized_keys_files("alice", Path::new("/home/alice")),
+            vec![
+                PathBuf::from("/home/alice/.ssh/authorized_keys"),
+                PathBuf::from("/home/alice/.ssh/authorized_keys2")
+            ]
+        );
+        let root = Policy::default();
+        assert!(root.permits_login("root", MethodKind::PublicKey, false));
+        assert!(!root.permits_login("root", MethodKind::Password, false));
+    }
+
+    #[test]
+    fn trusted_user_ca_keys() {
+        use rand_core::OsRng;
+        use ssh_key::certificate::{Builder, CertType};
+        use ssh_key::PrivateKey;
+
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let ca = PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap();
+        let mut builder =
+            Builder::new_with_random_nonce(&mut OsRng, key.public_key(), 0, u64::MAX >> 2).unwrap();
+        builder.cert_type(CertType::User).unwrap();
+        builder.valid_principal("admins").unwrap();
+        let cert = builder.sign(&ca).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let trusted = dir.path().join("trusted_user_ca_keys");
+        std::fs::write(&trusted, ca.public_key().to_openssh().unwrap()).unwrap();
+        std::fs::create_dir(dir.path().join(".ssh")).unwrap();
+        std::fs::write(dir.path().join(".ssh/principals_alice"), "admins\n").unwrap();
+        let config = SshdConfig::parse(&format!(
+            "TrustedUserCAKeys {}\nMatch User alice\n  AuthorizedPrincipalsFile .ssh/principals_%u\n",
+            trusted.display()
+        ))
+        .unwrap();
+        let context = |user: &str| MatchContext {
+            user: user.into(),
+            ..Default::default()
+        };
+
+        let policy = config.policy(&context("alice")).unwrap();
+        assert_eq!(
+            policy.authorized_principals_path("alice", dir.path()),
+            Some(dir.path().join(".ssh/principals_alice"))
+        );
+        assert!(policy
+            .authorize_certificate(&cert, "alice", dir.path(), None)
+            .unwrap()
+            .is_some());
+        // Without principals file, the certificate must list the user.
+        let policy = config.policy(&context("admins")).unwrap();
+        assert!(policy
+            .authorize_certificate(&cert, "admins", dir.path(), None)
+            .unwrap()
+            .is_some());
+        let policy = config.policy(&context("bob")).unwrap();
+        assert!(policy
+            .authorize_certificate(&cert, "bob", dir.path(), None)
+            .unwrap()
+            .is_none());
+        assert!(Policy::default()
+            .authorize_certificate(&cert, "admins", dir.path(), None)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(SshdConfig::parse("Match\n").is_err());
+        assert!(SshdConfig::parse("Match Color red\n").is_err());
+        assert!(SshdConfig::parse("Match User bob\nCiphers aes128-ctr\n").is_err());
+        assert!(SshdConfig::parse("PermitRootLogin maybe\n").is_err());
+        assert!(SshdConfig::parse("Match all\nMaxAuthTries many\n").is_err());
+        assert!(SshdConfig::parse("Ciphers unknown\n").is_err());
+        assert!(SshdConfig::parse("Subsystem sftp\n").is_err());
+        assert!(SshdConfig::parse("MaxStartups 10:30\n").is_err());
+        assert!(SshdConfig::parse("LoginGraceTime 2x\n").is_err());
+        assert!(SshdConfig::parse("PerSourcePenalties crash\n").is_err());
+        assert!(SshdConfig::parse("PerSourcePenalties speeding:10\n").is_err());
+        assert!(SshdConfig::parse("AuthenticationMethods publickey,magic\n").is_err());
+        assert!(SshdConfig::parse("UnknownOption yes\n").is_ok());
+    }
+
+    #[test]
+    fn include() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("10-a.conf"), "MaxAuthTries 2\n").unwrap();
+        std::fs::write(
+            dir.path().join("20-b.conf"),
+            "MaxAuthTries 4\nX11Forwarding yes\n",
+        )
+        .unwrap();
+        let config = SshdConfig::parse(&format!(
+            "Include {}/*.conf\nMaxAuthTries 5\n",
+            dir.path().display()
+        ))
+        .unwrap();
+        let policy = config.global_policy().unwrap();
+        assert_eq!(policy.max_auth_tries, 2);
+        assert!(policy.x11_forwarding);
+    }
+}
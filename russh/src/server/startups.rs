@@ -0,0 +1,176 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use rand::Rng;
+
+/// A limit on the connections which have not authenticated yet, as the
+/// `MaxStartups` option of `sshd_config`. Once there are `start` of
+/// them, new connections are dropped with a probability of `rate`
+/// percent, increasing linearly to 100% when there are `full` of them.
+///
+/// It can be parsed from the `start:rate:full` syntax of `sshd_config`,
+/// or from a single number to drop all the connections past it:
+///
+/// ```
+/// # use russh::server::MaxStartups;
+/// let limit: MaxStartups = "10:30:100".parse().unwrap();
+/// assert_eq!(limit, MaxStartups { start: 10, rate: 30, full: 100 });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxStartups {
+    /// The number of unauthenticated connections from which new
+    /// connections may be dropped.
+    pub start: usize,
+    /// The percentage of new connections dropped when there are `start`
+    /// unauthenticated connections.
+    pub rate: u32,
+    /// The number of unauthenticated connections from which all new
+    /// connections are dropped.
+    pub full: usize,
+}
+
+impl MaxStartups {
+    /// Drop all the new connections once there are `full`
+    /// unauthenticated connections.
+    pub fn new(full: usize) -> Self {
+        MaxStartups {
+            start: full,
+            rate: 100,
+            full,
+        }
+    }
+
+    /// The percentage of new connections dropped when there are
+    /// `unauthenticated` connections.
+    pub fn drop_rate(&self, unauthenticated: usize) -> u32 {
+        if unauthenticated >= self.full {
+            100
+        } else if unauthenticated < self.start {
+            0
+        } else {
+            let rate = self.rate.min(100) as usize;
+            let range = self.full - self.start;
+            (rate + (100 - rate) * (unauthenticated - self.start) / range) as u32
+        }
+    }
+
+    /// Whether to drop a new connection when there are
+    /// `unauthenticated` connections.
+    pub fn should_drop(&self, unauthenticated: usize) -> bool {
+        match self.drop_rate(unauthenticated) {
+            0 => false,
+            100.. => true,
+            rate => rand::thread_rng().gen_range(0..100) < rate,
+        }
+    }
+}
+
+impl FromStr for MaxStartups {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || crate::Error::InvalidConfig(format!("invalid MaxStartups: {s:?}"));
+        let mut fields = s.split(':');
+        let start = fields
+            .next()
+            .and_then(|start| start.parse().ok())
+            .ok_or_else(invalid)?;
+        let limit = match (fields.next(), fields.next(), fields.next()) {
+            (None, _, _) => MaxStartups::new(start),
+            (Some(rate), Some(full), None) => MaxStartups {
+                start,
+                rate: rate.parse().map_err(|_| invalid())?,
+                full: full.parse().map_err(|_| invalid())?,
+            },
+            _ => return Err(invalid()),
+        };
+        if limit.start > limit.full || limit.rate > 100 {
+            return Err(invalid());
+        }
+        Ok(limit)
+    }
+}
+
+/// The number of connections of a server which have not authenticated
+/// yet.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Startups(Arc<AtomicUsize>);
+
+impl Startups {
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Count a new connection until the returned guard is dropped.
+    pub(crate) fn begin(&self) -> Startup {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Startup(self.0.clone())
+    }
+}
+
+/// An unauthenticated connection, counted in [`Startups`] until this is
+/// dropped, once it authenticates or closes.
+#[derive(Debug)]
+pub(crate) struct Startup(Arc<AtomicUsize>);
+
+impl Drop for Startup {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            "10:30:100".parse::<MaxStartups>().unwrap(),
+            MaxStartups {
+                start: 10,
+                rate: 30,
+                full: 100
+            }
+        );
+        assert_eq!("5".parse::<MaxStartups>().unwrap(), MaxStartups::new(5));
+        for invalid in ["", "a", "10:30", "10:30:100:1", "10:101:100", "20:30:10"] {
+            assert!(invalid.parse::<MaxStartups>().is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn drop_rate() {
+        let limit = MaxStartups {
+            start: 10,
+            rate: 30,
+            full: 20,
+        };
+        assert_eq!(limit.drop_rate(0), 0);
+        assert_eq!(limit.drop_rate(9), 0);
+        assert_eq!(limit.drop_rate(10), 30);
+        assert_eq!(limit.drop_rate(15), 65);
+        assert_eq!(limit.drop_rate(20), 100);
+        assert_eq!(limit.drop_rate(25), 100);
+        assert!(!limit.should_drop(9));
+        assert!(limit.should_drop(20));
+
+        let limit = MaxStartups::new(3);
+        assert_eq!(limit.drop_rate(2), 0);
+        assert_eq!(limit.drop_rate(3), 100);
+    }
+
+    #[test]
+    fn count() {
+        let startups = Startups::default();
+        let first = startups.begin();
+        let second = startups.begin();
+        assert_eq!(startups.count(), 2);
+        drop(first);
+        assert_eq!(startups.count(), 1);
+        drop(second);
+        assert_eq!(startups.count(), 0);
+    }
+}
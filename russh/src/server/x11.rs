@@ -0,0 +1,256 @@
+//! X11 forwarding, as done by `sshd` with `X11Forwarding yes`.
+//!
+//! When a client sends an `x11-req`, [`X11Forward::listen`] opens a fake
+//! display on the loopback interface, and sends each connection to it
+//! to the client in an `x11` channel, where it reaches the real display.
+//! The X clients run by the session authenticate with the cookie of the
+//! request, which [`X11Forward::write_xauthority`] adds to their
+//! Xauthority file. The SSH client checks it, and replaces it with the
+//! cookie of the real display, which never leaves the client:
+//!
+//! ```no_run
+//! # use russh::server::process::Process;
+//! # use russh::server::x11::{X11Forward, X11Request, DEFAULT_DISPLAY_OFFSET};
+//! # use russh::server::{Handler, Session};
+//! # use russh::ChannelId;
+//! struct Client {
+//!     xauthority: std::path::PathBuf,
+//!     process: Process,
+//!     x11: Option<X11Forward>,
+//! }
+//!
+//! impl Handler for Client {
+//!     type Error = russh::Error;
+//!
+//!     async fn x11_request(
+//!         &mut self,
+//!         channel: ChannelId,
+//!         single_connection: bool,
+//!         x11_auth_protocol: &str,
+//!         x11_auth_cookie: &str,
+//!         x11_screen_number: u32,
+//!         session: &mut Session,
+//!     ) -> Result<(), Self::Error> {
+//!         let request = X11Request {
+//!             single_connection,
+//!             auth_protocol: x11_auth_protocol.into(),
+//!             auth_cookie: x11_auth_cookie.into(),
+//!             screen_number: x11_screen_number,
+//!         };
+//!         let forward =
+//!             X11Forward::listen(session.handle(), request, DEFAULT_DISPLAY_OFFSET).await?;
+//!         forward.write_xauthority(&self.xauthority)?;
+//!         self.process = std::mem::take(&mut self.process)
+//!             .env("DISPLAY", forward.display())
+//!             .env("XAUTHORITY", &self.xauthority);
+//!         self.x11 = Some(forward);
+//!         session.channel_success(channel)?;
+//!         Ok(())
+//!     }
+//! }
+//! ```
+
+use std::io::{self, Write};
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+use log::debug;
+use tokio::net::TcpListener;
+
+use super::Handle;
+use crate::client::x11::{read_string, read_u16};
+
+/// The first display number used by `sshd`, see `X11DisplayOffset` in
+/// `sshd_config(5)`.
+pub const DEFAULT_DISPLAY_OFFSET: u32 = 10;
+
+/// The number of displays tried after the offset, as in `sshd`.
+const MAX_DISPLAYS: u32 = 1000;
+
+/// The first TCP port of X displays.
+const X11_BASE_PORT: u32 = 6000;
+
+/// Xauthority entries of this family match any address.
+const FAMILY_WILD: u16 = 0xffff;
+
+/// The parameters of an `x11-req`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct X11Request {
+    /// Only forward the first connection.
+    pub single_connection: bool,
+    /// Usually `MIT-MAGIC-COOKIE-1`.
+    pub auth_protocol: String,
+    /// The cookie, in hexadecimal, the X clients have to present.
+    pub auth_cookie: String,
+    pub screen_number: u32,
+}
+
+/// A fake display, forwarding its connections to the client for as
+/// long as this guard is alive.
+#[derive(Debug)]
+pub struct X11Forward {
+    request: X11Request,
+    number: u32,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for X11Forward {
+    fn drop(&mut self) {
+        debug!("stopping X11 forward on display {}", self.number);
+        self.task.abort();
+    }
+}
+
+impl X11Forward {
+    /// Listen on the first free display from `offset` on the loopback
+    /// interface, and open an `x11` channel on `handle` for each
+    /// connection, or only the first one if the request asks for a
+    /// single connection.
+    pub async fn listen(handle: Handle, request: X11Request, offset: u32) -> io::Result<Self> {
+        for number in offset..offset.saturating_add(MAX_DISPLAYS) {
+            let Some(port) = number
+                .checked_add(X11_BASE_PORT)
+                .and_then(|port| u16::try_from(port).ok())
+            else {
+                break;
+            };
+            let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await {
+                Ok(listener) => listener,
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(e),
+            };
+            debug!("X11 forward on display {number}");
+            let task = tokio::spawn(accept(listener, handle, request.single_connection));
+            return Ok(X11Forward {
+                request,
+                number,
+                task,
+            });
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "no free X11 display",
+        ))
+    }
+
+    /// The number of the fake display.
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// The `DISPLAY` of the processes of the session, such as
+    /// `localhost:10.0`.
+    pub fn display(&self) -> String {
+        format!("localhost:{}.{}", self.number, self.request.screen_number)
+    }
+
+    /// Add the cookie of the request for the fake display to the
+    /// Xauthority file at `path`, replacing any previous entry for a
+    /// display with the same number, or create the file, readable only
+    /// by its owner.
+    pub fn write_xauthority(&self, path: &Path) -> io::Result<()> {
+        let cookie = data_encoding::HEXLOWER_PERMISSIVE
+            .decode(self.request.auth_cookie.as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        write_xauthority(path, self.number, &self.request.auth_protocol, &cookie)
+    }
+}
+
+fn write_xauthority(path: &Path, number: u32, protocol: &str, cookie: &[u8]) -> io::Result<()> {
+    let number = number.to_string();
+    let mut file = Vec::new();
+    match std::fs::read(path) {
+        Ok(existing) => {
+            let mut r = &existing[..];
+            while !r.is_empty() {
+                let start = r;
+                let _family = read_u16(&mut r)?;
+                let _address = read_string(&mut r)?;
+                let entry_number = read_string(&mut r)?;
+                let _name = read_string(&mut r)?;
+                let _cookie = read_string(&mut r)?;
+                if entry_number != number.as_bytes() {
+                    let (entry, _) = start.split_at(start.len() - r.len());
+                    file.extend(entry);
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    file.extend(FAMILY_WILD.to_be_bytes());
+    let fields = [&b""[..], number.as_bytes(), protocol.as_bytes(), cookie];
+    for field in fields {
+        let len = u16::try_from(field.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "field too long"))?;
+        file.extend(len.to_be_bytes());
+        file.extend(field);
+    }
+
+    // Replace the file at once, for X clients not to read half of it.
+    let mut new_path = path.as_os_str().to_owned();
+    new_path.push("-n");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&new_path)?.write_all(&file)?;
+    std::fs::rename(new_path, path)
+}
+
+async fn accept(listener: TcpListener, handle: Handle, single_connection: bool) {
+    loop {
+        let (mut stream, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                debug!("X11 forward: {e:?}");
+                return;
+            }
+        };
+        let handle = handle.clone();
+        tokio::spawn(async move {
+            let channel = match handle
+                .channel_open_x11(address.ip().to_string(), address.port() as u32)
+                .await
+            {
+                Ok(channel) => channel,
+                Err(e) => {
+                    debug!("X11 forward: could not open a channel: {e:?}");
+                    return;
+                }
+            };
+            let mut channel_stream = channel.into_stream();
+            if let Err(e) = tokio::io::copy_bidirectional(&mut stream, &mut channel_stream).await {
+                debug!("X11 forward: connection from {address} ended: {e:?}");
+            }
+        });
+        if single_connection {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use crate::client::x11::{xauthority_cookie, MIT_MAGIC_COOKIE};
+
+    #[test]
+    fn xauthority() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("Xauthority");
+        write_xauthority(&path, 10, MIT_MAGIC_COOKIE, &[1; 16]).unwrap();
+        write_xauthority(&path, 11, MIT_MAGIC_COOKIE, &[2; 16]).unwrap();
+        write_xauthority(&path, 10, MIT_MAGIC_COOKIE, &[3; 16]).unwrap();
+        assert_eq!(xauthority_cookie(&path, 10).unwrap(), Some(vec![3; 16]));
+        assert_eq!(xauthority_cookie(&path, 11).unwrap(), Some(vec![2; 16]));
+        assert_eq!(xauthority_cookie(&path, 12).unwrap(), None);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
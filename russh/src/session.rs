@@ -21,7 +21,7 @@ use std::num::Wrapping;
 use byteorder::{BigEndian, ByteOrder};
 use log::{debug, trace};
 use ssh_encoding::Encode;
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 
 use crate::cipher::OpeningKey;
 use crate::client::GexParams;
@@ -29,7 +29,8 @@ use crate::kex::dh::groups::DhGroup;
 use crate::kex::{KexAlgorithm, KexAlgorithmImplementor};
 use crate::sshbuffer::PacketWriter;
 use crate::{
-    auth, cipher, mac, msg, negotiation, ChannelId, ChannelParams, CryptoVec, Disconnect, Limits,
+    auth, cipher, mac, msg, negotiation, ChannelId, ChannelParams, CloseReason, CryptoVec,
+    Disconnect, Limits,
 };
 
 #[derive(Debug)]
@@ -42,6 +43,7 @@ pub(crate) struct Encrypted {
     pub key: usize,
     pub client_mac: mac::Name,
     pub server_mac: mac::Name,
+    pub names: negotiation::Names,
     pub session_id: CryptoVec,
     pub channels: HashMap<ChannelId, ChannelParams>,
     pub last_channel_id: Wrapping<u32>,
@@ -52,6 +54,11 @@ pub(crate) struct Encrypted {
     pub client_compression: crate::compression::Compression,
     pub decompress: crate::compression::Decompress,
     pub rekey_wanted: bool,
+    /// Bytes and packets received since the last rekey.
+    pub read_bytes: usize,
+    pub read_packets: usize,
+    /// Notified when the next rekey completes.
+    pub rekey_waiters: Vec<oneshot::Sender<()>>,
     pub received_extensions: Vec<String>,
     pub extension_info_awaiters: HashMap<String, Vec<oneshot::Sender<()>>>,
 }
@@ -128,10 +135,10 @@ impl<C> CommonSession<C> {
             enc.key = newkeys.key;
             enc.client_mac = newkeys.names.client_mac;
             enc.server_mac = newkeys.names.server_mac;
+            enc.names = newkeys.names;
             self.remote_to_local = newkeys.cipher.remote_to_local;
             self.packet_writer
                 .set_cipher(newkeys.cipher.local_to_remote);
-            self.strict_kex = self.strict_kex || newkeys.names.strict_kex;
         }
     }
 
@@ -142,6 +149,7 @@ impl<C> CommonSession<C> {
             key: newkeys.key,
             client_mac: newkeys.names.client_mac,
             server_mac: newkeys.names.server_mac,
+            names: newkeys.names.clone(),
             session_id: newkeys.session_id,
             state,
             channels: HashMap::new(),
@@ -149,10 +157,17 @@ impl<C> CommonSession<C> {
             write: CryptoVec::new(),
             write_cursor: 0,
             last_rekey: russh_util::time::Instant::now(),
-            server_compression: newkeys.names.server_compression,
-            client_compression: newkeys.names.client_compression,
+            server_compression: crate::compression::Compression::new(
+                &newkeys.names.server_compression,
+            ),
+            client_compression: crate::compression::Compression::new(
+                &newkeys.names.client_compression,
+            ),
             decompress: crate::compression::Decompress::None,
             rekey_wanted: false,
+            read_bytes: 0,
+            read_packets: 0,
+            rekey_waiters: Vec::new(),
             received_extensions: Vec::new(),
             extension_info_awaiters: HashMap::new(),
         });
@@ -162,6 +177,18 @@ impl<C> CommonSession<C> {
         self.strict_kex = newkeys.names.strict_kex;
     }
 
+    pub fn session_id(&self) -> Option<&[u8]> {
+        self.encrypted.as_ref().map(|enc| &enc.session_id[..])
+    }
+
+    pub fn connection_info(&self) -> Option<negotiation::ConnectionInfo> {
+        let enc = self.encrypted.as_ref()?;
+        Some(negotiation::ConnectionInfo::new(
+            &enc.names,
+            &self.remote_sshid,
+        ))
+    }
+
     /// Send a disconnect message.
     pub fn disconnect(
         &mut self,
@@ -451,6 +478,7 @@ impl Encrypted {
     pub fn flush(
         &mut self,
         limits: &Limits,
+        packet_limit: usize,
         writer: &mut PacketWriter,
     ) -> Result<bool, crate::Error> {
         // If there are pending packets (and we've not started to rekey), flush them.
@@ -479,11 +507,32 @@ impl Encrypted {
 
         let now = russh_util::time::Instant::now();
         let dur = now.duration_since(self.last_rekey);
+        let max_bytes = self.names.cipher.rekey_bytes();
+        let write_limit = limits.rekey_write_limit.min(max_bytes);
+        let read_limit = limits.rekey_read_limit.min(max_bytes);
+        let buffer = writer.buffer();
         Ok(replace(&mut self.rekey_wanted, false)
-            || writer.buffer().bytes >= limits.rekey_write_limit
+            || buffer.bytes >= write_limit
+            || self.read_bytes >= read_limit
+            || buffer.packets >= packet_limit
+            || self.read_packets >= packet_limit
             || dur >= limits.rekey_time_limit)
     }
 
+    /// Reset the counters of [`Limits`] after a rekey, and notify the
+    /// tasks waiting for it.
+    pub fn rekey_done(&mut self, writer: &mut PacketWriter) {
+        self.last_rekey = russh_util::time::Instant::now();
+        self.read_bytes = 0;
+        self.read_packets = 0;
+        let buffer = writer.buffer();
+        buffer.bytes = 0;
+        buffer.packets = 0;
+        for waiter in self.rekey_waiters.drain(..) {
+            let _ = waiter.send(());
+        }
+    }
+
     pub fn new_channel_id(&mut self) -> ChannelId {
         self.last_channel_id += Wrapping(1);
         while self
@@ -569,4 +618,39 @@ pub(crate) enum GlobalRequestResponse {
     /// request was for StreamLocalForward, sends true for success or false for failure
     StreamLocalForward(oneshot::Sender<bool>),
     CancelStreamLocalForward(oneshot::Sender<bool>),
+    /// request was for HostKeysProve, with the keys to be proven
+    HostKeysProve(Vec<ssh_key::PublicKey>),
+    /// request was sent with `global_request`, sends Some(reply data) for
+    /// success or None for failure
+    Generic(oneshot::Sender<Option<Vec<u8>>>),
+}
+
+/// Reply to a global request with its data if it succeeded.
+pub(crate) fn push_global_request_reply(
+    write: &mut CryptoVec,
+    reply: Option<Vec<u8>>,
+) -> Result<(), crate::Error> {
+    push_packet!(write, {
+        match reply {
+            Some(data) => {
+                write.push(msg::REQUEST_SUCCESS);
+                write.extend(&data);
+            }
+            None => write.push(msg::REQUEST_FAILURE),
+        }
+    });
+    Ok(())
+}
+
+/// Wait until the session sets why it closed. If it is gone without a
+/// reason, it failed.
+pub(crate) async fn wait_closed(mut closed: watch::Receiver<Option<CloseReason>>) -> CloseReason {
+    loop {
+        if let Some(reason) = closed.borrow_and_update().clone() {
+            return reason;
+        }
+        if closed.changed().await.is_err() {
+            return closed.borrow().clone().unwrap_or(CloseReason::Error);
+        }
+    }
 }
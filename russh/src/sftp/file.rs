@@ -0,0 +1,347 @@
+use std::future::Future;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use bytes::Bytes;
+use ssh_encoding::Encode;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use super::protocol::*;
+use super::{encode, Inner};
+
+/// The size of the chunks read and written in a single request. Every
+/// server accepts packets of this size.
+const CHUNK_SIZE: usize = 32768;
+
+/// How to open a file, in the style of [`std::fs::OpenOptions`].
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    create: bool,
+    create_new: bool,
+    truncate: bool,
+    mode: Option<u32>,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Write at the end of the file. Implies `write`.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Create the file if it does not exist.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Create the file, failing if it exists.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// The permissions of the file, if it is created.
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub(super) fn flags(&self) -> u32 {
+        let mut flags = 0;
+        if self.read {
+            flags |= OPEN_READ
+        }
+        if self.write || self.append {
+            flags |= OPEN_WRITE
+        }
+        if self.append {
+            flags |= OPEN_APPEND
+        }
+        if self.create || self.create_new {
+            flags |= OPEN_CREAT
+        }
+        if self.create_new {
+            flags |= OPEN_EXCL
+        }
+        if self.truncate {
+            flags |= OPEN_TRUNC
+        }
+        flags
+    }
+
+    pub(super) fn attributes(&self) -> FileAttributes {
+        FileAttributes {
+            permissions: self.mode,
+            ..Default::default()
+        }
+    }
+}
+
+type OpFuture = Pin<Box<dyn Future<Output = io::Result<Done>> + Send>>;
+
+enum Done {
+    Read(Bytes),
+    Wrote(usize),
+    Seeked(u64),
+}
+
+/// An open file on the server.
+///
+/// Only one read, write or seek is in flight at a time. The file is
+/// closed in the background when dropped; use [`File::close`] to wait
+/// for it and get errors.
+pub struct File {
+    inner: Arc<Inner>,
+    handle: Option<Vec<u8>>,
+    /// The offset of the next request.
+    offset: u64,
+    /// Data read ahead of the position of the file.
+    buffer: Bytes,
+    op: Option<OpFuture>,
+}
+
+impl std::fmt::Debug for File {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("File")
+            .field("position", &self.position())
+            .finish_non_exhaustive()
+    }
+}
+
+impl File {
+    pub(super) fn new(inner: Arc<Inner>, handle: Vec<u8>) -> Self {
+        File {
+            inner,
+            handle: Some(handle),
+            offset: 0,
+            buffer: Bytes::new(),
+            op: None,
+        }
+    }
+
+    fn handle(&self) -> io::Result<&Vec<u8>> {
+        self.handle
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "file closed"))
+    }
+
+    fn position(&self) -> u64 {
+        self.offset - self.buffer.len() as u64
+    }
+
+    /// The attributes of the file.
+    pub async fn metadata(&self) -> io::Result<FileAttributes> {
+        fstat(self.inner.clone(), self.handle()?.clone()).await
+    }
+
+    /// Wait for pending writes, and close the file.
+    pub async fn close(mut self) -> io::Result<()> {
+        std::future::poll_fn(|cx| Pin::new(&mut self).poll_flush(cx)).await?;
+        match self.handle.take() {
+            Some(handle) => self.inner.close_handle(handle).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Run the pending operation to completion, and account for its
+    /// result.
+    fn poll_op(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<Done>>> {
+        let Some(op) = self.op.as_mut() else {
+            return Poll::Ready(Ok(None));
+        };
+        let result = ready!(op.as_mut().poll(cx));
+        self.op = None;
+        let done = result?;
+        match &done {
+            Done::Read(data) => {
+                self.offset += data.len() as u64;
+                self.buffer = data.clone();
+            }
+            Done::Wrote(n) => self.offset += *n as u64,
+            Done::Seeked(offset) => {
+                self.offset = *offset;
+                self.buffer = Bytes::new();
+            }
+        }
+        Poll::Ready(Ok(Some(done)))
+    }
+
+    fn start_read(&mut self, len: usize) -> io::Result<()> {
+        let inner = self.inner.clone();
+        let handle = self.handle()?;
+        let mut payload = Vec::new();
+        encode(&mut payload, |w| {
+            handle.encode(w)?;
+            self.offset.encode(w)?;
+            (len.min(CHUNK_SIZE) as u32).encode(w)
+        })?;
+        self.op = Some(Box::pin(async move {
+            let data = inner.request(READ, payload).await?.into_data()?;
+            Ok(Done::Read(data.unwrap_or_default().into()))
+        }));
+        Ok(())
+    }
+
+    fn start_write(&mut self, buf: &[u8]) -> io::Result<()> {
+        // Writes happen at the position of the file, not after the data
+        // read ahead.
+        self.offset = self.position();
+        self.buffer = Bytes::new();
+        let inner = self.inner.clone();
+        let n = buf.len().min(CHUNK_SIZE);
+        let handle = self.handle()?;
+        let mut payload = Vec::new();
+        encode(&mut payload, |w| {
+            handle.encode(w)?;
+            self.offset.encode(w)?;
+            buf.get(..n).unwrap_or_default().encode(w)
+        })?;
+        self.op = Some(Box::pin(async move {
+            inner.request(WRITE, payload).await?.into_status()?;
+            Ok(Done::Wrote(n))
+        }));
+        Ok(())
+    }
+}
+
+async fn fstat(inner: Arc<Inner>, handle: Vec<u8>) -> io::Result<FileAttributes> {
+    let mut payload = Vec::new();
+    encode(&mut payload, |w| handle.encode(w))?;
+    inner.request(FSTAT, payload).await?.into_attrs()
+}
+
+impl AsyncRead for File {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if !this.buffer.is_empty() {
+                let n = buf.remaining().min(this.buffer.len());
+                buf.put_slice(&this.buffer.split_to(n));
+                return Poll::Ready(Ok(()));
+            }
+            if this.op.is_none() {
+                if buf.remaining() == 0 {
+                    return Poll::Ready(Ok(()));
+                }
+                this.start_read(buf.remaining())?;
+            }
+            if let Some(Done::Read(data)) = ready!(this.poll_op(cx))? {
+                if data.is_empty() {
+                    // End of file.
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        loop {
+            if this.op.is_none() {
+                if buf.is_empty() {
+                    return Poll::Ready(Ok(0));
+                }
+                this.start_write(buf)?;
+            }
+            if let Some(Done::Wrote(n)) = ready!(this.poll_op(cx))? {
+                return Poll::Ready(Ok(n));
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_op(cx))?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl AsyncSeek for File {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        if self.op.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "another operation is pending",
+            ));
+        }
+        let out_of_range = || io::Error::new(io::ErrorKind::InvalidInput, "invalid seek");
+        let op: OpFuture = match position {
+            SeekFrom::Start(offset) => Box::pin(std::future::ready(Ok(Done::Seeked(offset)))),
+            SeekFrom::Current(delta) => {
+                let offset = self
+                    .position()
+                    .checked_add_signed(delta)
+                    .ok_or_else(out_of_range)?;
+                Box::pin(std::future::ready(Ok(Done::Seeked(offset))))
+            }
+            SeekFrom::End(delta) => {
+                let attrs = fstat(self.inner.clone(), self.handle()?.clone());
+                Box::pin(async move {
+                    let size = attrs.await?.size.ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::Unsupported, "unknown file size")
+                    })?;
+                    let offset = size.checked_add_signed(delta).ok_or_else(out_of_range)?;
+                    Ok(Done::Seeked(offset))
+                })
+            }
+        };
+        self.op = Some(op);
+        Ok(())
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        ready!(self.poll_op(cx))?;
+        Poll::Ready(Ok(self.position()))
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if let (Some(handle), Ok(runtime)) =
+            (self.handle.take(), tokio::runtime::Handle::try_current())
+        {
+            let inner = self.inner.clone();
+            runtime.spawn(async move {
+                let _ = inner.close_handle(handle).await;
+            });
+        }
+    }
+}
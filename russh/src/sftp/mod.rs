@@ -0,0 +1,747 @@
+//! An SFTP (version 3) client, enabled by the `sftp` feature. The
+//! [`server`] module has the server side.
+//!
+//! [`SftpSession`] runs the protocol on a session channel with the `sftp`
+//! subsystem, or on any byte stream. Requests can be made concurrently
+//! from several tasks; files are read and written through [`File`],
+//! which implements [`AsyncRead`](tokio::io::AsyncRead),
+//! [`AsyncWrite`](tokio::io::AsyncWrite) and
+//! [`AsyncSeek`](tokio::io::AsyncSeek).
+//!
+//! Errors are [`io::Error`]s. When the server refuses a request, the
+//! inner error is a [`StatusError`] and the kind is derived from its
+//! status code, so that a missing file is [`io::ErrorKind::NotFound`].
+//!
+//! ```no_run
+//! # async fn run<H: russh::client::Handler>(session: russh::client::Handle<H>) -> std::io::Result<()> {
+//! use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//!
+//! let sftp = session.sftp().await?;
+//! for entry in sftp.read_dir(".").await? {
+//!     println!("{}", entry.long_name);
+//! }
+//!
+//! let mut file = sftp.create("hello.txt").await?;
+//! file.write_all(b"hello").await?;
+//! file.close().await?;
+//!
+//! let mut contents = String::new();
+//! sftp.open("hello.txt").await?.read_to_string(&mut contents).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::debug;
+use ssh_encoding::{Decode, Encode};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::{client, Channel, ChannelId, ChannelMsg};
+
+mod file;
+mod protocol;
+pub mod server;
+
+pub use file::{File, OpenOptions};
+use protocol::*;
+pub use protocol::{DirEntry, FileAttributes, StatusCode, StatusError};
+
+/// The largest packet accepted from the server. Servers must accept
+/// packets of at least 34000 bytes, and may send larger ones.
+const MAX_PACKET_SIZE: u32 = 256 * 1024 + 1024;
+
+/// An SFTP session.
+pub struct SftpSession {
+    inner: Arc<Inner>,
+    version: u32,
+    reader: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for SftpSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SftpSession")
+            .field("version", &self.version)
+            .finish_non_exhaustive()
+    }
+}
+
+type Pending = HashMap<u32, oneshot::Sender<Response>>;
+
+struct Inner {
+    writer: tokio::sync::Mutex<Box<dyn AsyncWrite + Send + Unpin>>,
+    /// The requests waiting for a reply, `None` once the session has
+    /// ended.
+    pending: Mutex<Option<Pending>>,
+    next_id: AtomicU32,
+}
+
+impl<H: client::Handler> client::Handle<H> {
+    /// Open a session channel and start an SFTP session on it.
+    pub async fn sftp(&self) -> io::Result<SftpSession> {
+        let channel = self
+            .channel_open_session()
+            .await
+            .map_err(io::Error::other)?;
+        SftpSession::new(channel).await
+    }
+}
+
+impl SftpSession {
+    /// Request the `sftp` subsystem on `channel`, and start a session on
+    /// it.
+    pub async fn new<S>(mut channel: Channel<S>) -> io::Result<Self>
+    where
+        S: From<(ChannelId, ChannelMsg)> + Send + Sync + 'static,
+    {
+        channel
+            .request_subsystem(true, "sftp")
+            .await
+            .map_err(io::Error::other)?;
+        loop {
+            match channel.wait().await {
+                Some(ChannelMsg::Success) => break,
+                Some(ChannelMsg::Failure) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "the sftp subsystem was refused",
+                    ))
+                }
+                Some(_) => {}
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            }
+        }
+        Self::from_stream(channel.into_stream()).await
+    }
+
+    /// Start a session on a stream connected to an SFTP server, such as
+    /// a channel on which the `sftp` subsystem was already requested.
+    pub async fn from_stream<T>(stream: T) -> io::Result<Self>
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let mut init = Vec::new();
+        VERSION.encode(&mut init).map_err(invalid_data)?;
+        write_packet(&mut writer, INIT, &init).await?;
+
+        let packet = read_packet(&mut reader).await?;
+        let mut r = &packet[..];
+        let version = match u8::decode(&mut r).map_err(invalid_data)? {
+            VERSION_REPLY => u32::decode(&mut r).map_err(invalid_data)?,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "expected SSH_FXP_VERSION",
+                ))
+            }
+        };
+        debug!("SFTP version {version}");
+
+        let inner = Arc::new(Inner {
+            writer: tokio::sync::Mutex::new(Box::new(writer)),
+            pending: Mutex::new(Some(HashMap::new())),
+            next_id: AtomicU32::new(0),
+        });
+        let reader = tokio::spawn(read_responses(reader, inner.clone()));
+        Ok(SftpSession {
+            inner,
+            version,
+            reader,
+        })
+    }
+
+    /// The protocol version of the server.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Open a file for reading.
+    pub async fn open(&self, path: &str) -> io::Result<File> {
+        self.open_with(path, OpenOptions::new().read(true)).await
+    }
+
+    /// Open a file for writing, creating it or truncating it.
+    pub async fn create(&self, path: &str) -> io::Result<File> {
+        self.open_with(
+            path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )
+        .await
+    }
+
+    pub async fn open_with(&self, path: &str, options: &OpenOptions) -> io::Result<File> {
+        let mut payload = Vec::new();
+        encode(&mut payload, |w| {
+            path.encode(w)?;
+            options.flags().encode(w)?;
+            options.attributes().encode(w)
+        })?;
+        let handle = self.inner.request(OPEN, payload).await?.into_handle()?;
+        Ok(File::new(self.inner.clone(), handle))
+    }
+
+    /// Read a whole file.
+    pub async fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let mut file = self.open(path).await?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).await?;
+        file.close().await?;
+        Ok(contents)
+    }
+
+    /// Create or replace a file with `contents`.
+    pub async fn write(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let mut file = self.create(path).await?;
+        file.write_all(contents).await?;
+        file.close().await
+    }
+
+    /// List the entries of a directory, except `.` and `..`.
+    pub async fn read_dir(&self, path: &str) -> io::Result<Vec<DirEntry>> {
+        let handle = self
+            .inner
+            .request(OPENDIR, encode_path(path)?)
+            .await?
+            .into_handle()?;
+        let mut entries = Vec::new();
+        let result = loop {
+            let mut payload = Vec::new();
+            encode(&mut payload, |w| handle.encode(w))?;
+            match self.inner.request(READDIR, payload).await {
+                Ok(r) => match r.into_names() {
+                    Ok(Some(names)) => entries.extend(
+                        names
+                            .into_iter()
+                            .filter(|e| e.file_name != "." && e.file_name != ".."),
+                    ),
+                    Ok(None) => break Ok(entries),
+                    Err(e) => break Err(e),
+                },
+                Err(e) => break Err(e),
+            }
+        };
+        self.inner.close_handle(handle).await?;
+        result
+    }
+
+    /// The attributes of a file, following symbolic links.
+    pub async fn metadata(&self, path: &str) -> io::Result<FileAttributes> {
+        self.inner
+            .request(STAT, encode_path(path)?)
+            .await?
+            .into_attrs()
+    }
+
+    /// The attributes of a file, without following symbolic links.
+    pub async fn symlink_metadata(&self, path: &str) -> io::Result<FileAttributes> {
+        self.inner
+            .request(LSTAT, encode_path(path)?)
+            .await?
+            .into_attrs()
+    }
+
+    /// Change the attributes of a file. Only the attributes that are set
+    /// are changed.
+    pub async fn set_metadata(&self, path: &str, attributes: &FileAttributes) -> io::Result<()> {
+        let mut payload = Vec::new();
+        encode(&mut payload, |w| {
+            path.encode(w)?;
+            attributes.encode(w)
+        })?;
+        self.inner.request(SETSTAT, payload).await?.into_status()
+    }
+
+    /// Rename a file. Most servers fail if `to` already exists.
+    pub async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut payload = Vec::new();
+        encode(&mut payload, |w| {
+            from.encode(w)?;
+            to.encode(w)
+        })?;
+        self.inner.request(RENAME, payload).await?.into_status()
+    }
+
+    pub async fn remove_file(&self, path: &str) -> io::Result<()> {
+        self.inner
+            .request(REMOVE, encode_path(path)?)
+            .await?
+            .into_status()
+    }
+
+    pub async fn create_dir(&self, path: &str) -> io::Result<()> {
+        let mut payload = encode_path(path)?;
+        encode(&mut payload, |w| FileAttributes::default().encode(w))?;
+        self.inner.request(MKDIR, payload).await?.into_status()
+    }
+
+    pub async fn remove_dir(&self, path: &str) -> io::Result<()> {
+        self.inner
+            .request(RMDIR, encode_path(path)?)
+            .await?
+            .into_status()
+    }
+
+    /// The absolute path of `path` on the server. `"."` gives the
+    /// current directory.
+    pub async fn canonicalize(&self, path: &str) -> io::Result<String> {
+        self.single_name(REALPATH, path).await
+    }
+
+    /// The target of a symbolic link.
+    pub async fn read_link(&self, path: &str) -> io::Result<String> {
+        self.single_name(READLINK, path).await
+    }
+
+    async fn single_name(&self, kind: u8, path: &str) -> io::Result<String> {
+        let names = self.inner.request(kind, encode_path(path)?).await?;
+        match names
+            .into_names()?
+            .and_then(|names| names.into_iter().next())
+        {
+            Some(entry) => Ok(entry.file_name),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "empty SSH_FXP_NAME reply",
+            )),
+        }
+    }
+}
+
+impl Drop for SftpSession {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl Inner {
+    async fn request(&self, kind: u8, payload: Vec<u8>) -> io::Result<Response> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        match self.pending.lock() {
+            Ok(mut pending) => match pending.as_mut() {
+                Some(pending) => pending.insert(id, sender),
+                None => return Err(closed()),
+            },
+            Err(_) => return Err(closed()),
+        };
+        let mut packet = Vec::with_capacity(4 + payload.len());
+        encode(&mut packet, |w| id.encode(w))?;
+        packet.extend(payload);
+        {
+            let mut writer = self.writer.lock().await;
+            write_packet(&mut *writer, kind, &packet).await?;
+        }
+        receiver.await.map_err(|_| closed())
+    }
+
+    async fn close_handle(&self, handle: Vec<u8>) -> io::Result<()> {
+        let mut payload = Vec::new();
+        encode(&mut payload, |w| handle.encode(w))?;
+        self.request(CLOSE, payload).await?.into_status()
+    }
+}
+
+async fn read_responses<R: AsyncRead>(mut reader: ReadHalf<R>, inner: Arc<Inner>) {
+    loop {
+        let packet = match read_packet(&mut reader).await {
+            Ok(packet) => packet,
+            Err(e) => {
+                debug!("SFTP session ended: {e}");
+                break;
+            }
+        };
+        let mut r = &packet[..];
+        let response = (|| {
+            let kind = u8::decode(&mut r)?;
+            let id = u32::decode(&mut r)?;
+            Ok::<_, ssh_encoding::Error>((id, Response::decode(kind, &mut r)?))
+        })();
+        let (id, response) = match response {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("invalid SFTP response: {e}");
+                break;
+            }
+        };
+        let sender = inner
+            .pending
+            .lock()
+            .ok()
+            .and_then(|mut pending| pending.as_mut()?.remove(&id));
+        match sender {
+            Some(sender) => {
+                let _ = sender.send(response);
+            }
+            None => debug!("SFTP response to unknown request {id}"),
+        }
+    }
+    if let Ok(mut pending) = inner.pending.lock() {
+        pending.take();
+    }
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = reader.read_u32().await?;
+    if len == 0 || len > MAX_PACKET_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid SFTP packet length {len}"),
+        ));
+    }
+    let mut packet = vec![0; len as usize];
+    reader.read_exact(&mut packet).await?;
+    Ok(packet)
+}
+
+async fn write_packet<W: AsyncWrite + Unpin + ?Sized>(
+    writer: &mut W,
+    kind: u8,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(5 + payload.len());
+    encode(&mut packet, |w| {
+        (1 + payload.len() as u32).encode(w)?;
+        kind.encode(w)
+    })?;
+    packet.extend_from_slice(payload);
+    writer.write_all(&packet).await?;
+    writer.flush().await
+}
+
+fn encode(
+    w: &mut Vec<u8>,
+    f: impl FnOnce(&mut Vec<u8>) -> Result<(), ssh_encoding::Error>,
+) -> io::Result<()> {
+    f(w).map_err(invalid_data)
+}
+
+fn encode_path(path: &str) -> io::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    encode(&mut payload, |w| path.encode(w))?;
+    Ok(payload)
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "the SFTP session ended")
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used, clippy::indexing_slicing, clippy::panic)]
+    use std::io::SeekFrom;
+
+    use tokio::io::{AsyncSeekExt, DuplexStream};
+
+    use super::*;
+
+    enum Open {
+        File(String),
+        Dir { listed: bool },
+    }
+
+    /// An SFTP server with files in a single directory, `/`.
+    #[derive(Default)]
+    struct FakeServer {
+        files: HashMap<String, Vec<u8>>,
+        handles: HashMap<Vec<u8>, Open>,
+        next_handle: u32,
+    }
+
+    impl FakeServer {
+        async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut self, mut stream: S) {
+            let init = read_packet(&mut stream).await.unwrap();
+            assert_eq!(init[0], INIT);
+            let mut version = Vec::new();
+            VERSION.encode(&mut version).unwrap();
+            write_packet(&mut stream, VERSION_REPLY, &version)
+                .await
+                .unwrap();
+            while let Ok(packet) = read_packet(&mut stream).await {
+                let mut r = &packet[..];
+                let kind = u8::decode(&mut r).unwrap();
+                let id = u32::decode(&mut r).unwrap();
+                let (kind, reply) = self.handle(kind, &mut r);
+                let mut payload = Vec::new();
+                id.encode(&mut payload).unwrap();
+                payload.extend(reply);
+                write_packet(&mut stream, kind, &payload).await.unwrap();
+            }
+        }
+
+        fn handle(&mut self, kind: u8, r: &mut &[u8]) -> (u8, Vec<u8>) {
+            let mut w = Vec::new();
+            let string = |r: &mut &[u8]| String::decode(r).unwrap();
+            let status = |code: StatusCode| {
+                let mut w = Vec::new();
+                u32::from(code).encode(&mut w).unwrap();
+                "".encode(&mut w).unwrap();
+                "".encode(&mut w).unwrap();
+                (STATUS, w)
+            };
+            match kind {
+                OPEN => {
+                    let path = string(r);
+                    let flags = u32::decode(r).unwrap();
+                    if flags & OPEN_CREAT != 0 {
+                        self.files.entry(path.clone()).or_default();
+                    }
+                    let Some(file) = self.files.get_mut(&path) else {
+                        return status(StatusCode::NoSuchFile);
+                    };
+                    if flags & OPEN_TRUNC != 0 {
+                        file.clear();
+                    }
+                    self.new_handle(Open::File(path))
+                }
+                OPENDIR => self.new_handle(Open::Dir { listed: false }),
+                CLOSE => {
+                    self.handles.remove(&Vec::decode(r).unwrap());
+                    status(StatusCode::Ok)
+                }
+                READ => {
+                    let path = self.file(r);
+                    let offset = u64::decode(r).unwrap() as usize;
+                    let len = u32::decode(r).unwrap() as usize;
+                    let file = &self.files[&path];
+                    if offset >= file.len() {
+                        return status(StatusCode::Eof);
+                    }
+                    file[offset..(offset + len).min(file.len())]
+                        .encode(&mut w)
+                        .unwrap();
+                    (DATA, w)
+                }
+                WRITE => {
+                    let path = self.file(r);
+                    let offset = u64::decode(r).unwrap() as usize;
+                    let data = Vec::decode(r).unwrap();
+                    let file = self.files.get_mut(&path).unwrap();
+                    file.resize(file.len().max(offset + data.len()), 0);
+                    file[offset..offset + data.len()].copy_from_slice(&data);
+                    status(StatusCode::Ok)
+                }
+                READDIR => {
+                    let handle = Vec::decode(r).unwrap();
+                    let Some(Open::Dir { listed }) = self.handles.get_mut(&handle) else {
+                        return status(StatusCode::Failure);
+                    };
+                    if std::mem::replace(listed, true) {
+                        return status(StatusCode::Eof);
+                    }
+                    let mut names: Vec<_> = self.files.keys().cloned().collect();
+                    names.sort();
+                    ((names.len() + 1) as u32).encode(&mut w).unwrap();
+                    for name in [".".to_owned()].into_iter().chain(names) {
+                        let name = name.trim_start_matches('/');
+                        name.encode(&mut w).unwrap();
+                        name.encode(&mut w).unwrap();
+                        FileAttributes::default().encode(&mut w).unwrap();
+                    }
+                    (NAME, w)
+                }
+                STAT | FSTAT => {
+                    let path = match kind {
+                        STAT => string(r),
+                        _ => self.file(r),
+                    };
+                    let Some(file) = self.files.get(&path) else {
+                        return status(StatusCode::NoSuchFile);
+                    };
+                    FileAttributes {
+                        size: Some(file.len() as u64),
+                        permissions: Some(0o100644),
+                        ..Default::default()
+                    }
+                    .encode(&mut w)
+                    .unwrap();
+                    (ATTRS, w)
+                }
+                RENAME => {
+                    let (from, to) = (string(r), string(r));
+                    match self.files.remove(&from) {
+                        Some(file) => {
+                            self.files.insert(to, file);
+                            status(StatusCode::Ok)
+                        }
+                        None => status(StatusCode::NoSuchFile),
+                    }
+                }
+                REMOVE => match self.files.remove(&string(r)) {
+                    Some(_) => status(StatusCode::Ok),
+                    None => status(StatusCode::NoSuchFile),
+                },
+                _ => status(StatusCode::OpUnsupported),
+            }
+        }
+
+        fn new_handle(&mut self, open: Open) -> (u8, Vec<u8>) {
+            self.next_handle += 1;
+            let handle = self.next_handle.to_be_bytes().to_vec();
+            let mut w = Vec::new();
+            handle.encode(&mut w).unwrap();
+            self.handles.insert(handle, open);
+            (HANDLE, w)
+        }
+
+        fn file(&self, r: &mut &[u8]) -> String {
+            match &self.handles[&Vec::decode(r).unwrap()] {
+                Open::File(path) => path.clone(),
+                Open::Dir { .. } => panic!("not a file"),
+            }
+        }
+    }
+
+    struct Client {}
+
+    impl client::Handler for Client {
+        type Error = crate::Error;
+
+        async fn check_server_key(
+            &mut self,
+            _server_public_key: &ssh_key::PublicKey,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    /// Runs a [`FakeServer`] on the channels requesting the `sftp`
+    /// subsystem.
+    #[derive(Default)]
+    struct Server {
+        channels: HashMap<ChannelId, Channel<crate::server::Msg>>,
+    }
+
+    impl crate::server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_none(&mut self, _: &str) -> Result<crate::server::Auth, Self::Error> {
+            Ok(crate::server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            channel: Channel<crate::server::Msg>,
+            _session: &mut crate::server::Session,
+        ) -> Result<bool, Self::Error> {
+            self.channels.insert(channel.id(), channel);
+            Ok(true)
+        }
+
+        async fn subsystem_request(
+            &mut self,
+            channel: ChannelId,
+            name: &str,
+            session: &mut crate::server::Session,
+        ) -> Result<(), Self::Error> {
+            match self.channels.remove(&channel) {
+                Some(stream) if name == "sftp" => {
+                    session.channel_success(channel)?;
+                    tokio::spawn(FakeServer::default().serve(stream.into_stream()));
+                }
+                _ => session.channel_failure(channel)?,
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn subsystem() {
+        let config = Arc::new(crate::server::Config {
+            inactivity_timeout: None,
+            keys: vec![ssh_key::PrivateKey::random(
+                &mut rand_core::OsRng,
+                ssh_key::Algorithm::Ed25519,
+            )
+            .unwrap()],
+            ..Default::default()
+        });
+        let (client_end, server_end) = tokio::io::duplex(65536);
+        tokio::spawn(crate::server::run_stream(
+            config,
+            server_end,
+            Server::default(),
+        ));
+        let config = Arc::new(client::Config::default());
+        let mut session = client::connect_stream(config, client_end, Client {})
+            .await
+            .unwrap();
+        assert!(session.authenticate_none("user").await.unwrap().success());
+
+        let sftp = session.sftp().await.unwrap();
+        sftp.write("/a", b"hello").await.unwrap();
+        assert_eq!(sftp.read("/a").await.unwrap(), b"hello");
+    }
+
+    async fn session() -> SftpSession {
+        let (client, server): (DuplexStream, DuplexStream) = tokio::io::duplex(65536);
+        tokio::spawn(FakeServer::default().serve(server));
+        SftpSession::from_stream(client).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn files() {
+        let sftp = session().await;
+        assert_eq!(sftp.version(), 3);
+        sftp.write("/a", b"hello world").await.unwrap();
+        assert_eq!(sftp.read("/a").await.unwrap(), b"hello world");
+
+        let mut file = sftp
+            .open_with("/a", OpenOptions::new().read(true).write(true))
+            .await
+            .unwrap();
+        let mut buf = [0; 6];
+        file.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello ");
+        file.write_all(b"there").await.unwrap();
+        assert_eq!(file.seek(SeekFrom::End(-5)).await.unwrap(), 6);
+        let mut end = String::new();
+        file.read_to_string(&mut end).await.unwrap();
+        assert_eq!(end, "there");
+        file.close().await.unwrap();
+
+        // A large file takes several requests, made concurrently.
+        let large: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        sftp.write("/large", &large).await.unwrap();
+        let (a, b) = tokio::join!(sftp.read("/a"), sftp.read("/large"));
+        assert_eq!(a.unwrap(), b"hello there");
+        assert_eq!(b.unwrap(), large);
+
+        let attrs = sftp.metadata("/a").await.unwrap();
+        assert_eq!(attrs.size, Some(11));
+        assert!(attrs.is_file());
+    }
+
+    #[tokio::test]
+    async fn directories() {
+        let sftp = session().await;
+        sftp.write("/a", b"").await.unwrap();
+        sftp.write("/b", b"").await.unwrap();
+        let names: Vec<_> = sftp
+            .read_dir("/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.file_name)
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+
+        sftp.rename("/a", "/c").await.unwrap();
+        let error = sftp.metadata("/a").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        let status = error.get_ref().unwrap().downcast_ref::<StatusError>();
+        assert_eq!(status.unwrap().code, StatusCode::NoSuchFile);
+
+        sftp.remove_file("/b").await.unwrap();
+        sftp.remove_file("/c").await.unwrap();
+        assert!(sftp.read_dir("/").await.unwrap().is_empty());
+
+        let error = sftp.create_dir("/d").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,337 @@
+//! Packets of the SFTP version 3 protocol
+//! ([draft-ietf-secsh-filexfer-02](https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-02)).
+
+use std::io;
+
+use ssh_encoding::{Decode, Encode, Reader};
+
+pub(super) const VERSION: u32 = 3;
+
+pub(super) const INIT: u8 = 1;
+pub(super) const VERSION_REPLY: u8 = 2;
+pub(super) const OPEN: u8 = 3;
+pub(super) const CLOSE: u8 = 4;
+pub(super) const READ: u8 = 5;
+pub(super) const WRITE: u8 = 6;
+pub(super) const LSTAT: u8 = 7;
+pub(super) const FSTAT: u8 = 8;
+pub(super) const SETSTAT: u8 = 9;
+pub(super) const OPENDIR: u8 = 11;
+pub(super) const READDIR: u8 = 12;
+pub(super) const REMOVE: u8 = 13;
+pub(super) const MKDIR: u8 = 14;
+pub(super) const RMDIR: u8 = 15;
+pub(super) const REALPATH: u8 = 16;
+pub(super) const STAT: u8 = 17;
+pub(super) const RENAME: u8 = 18;
+pub(super) const READLINK: u8 = 19;
+
+pub(super) const STATUS: u8 = 101;
+pub(super) const HANDLE: u8 = 102;
+pub(super) const DATA: u8 = 103;
+pub(super) const NAME: u8 = 104;
+pub(super) const ATTRS: u8 = 105;
+
+pub(super) const OPEN_READ: u32 = 0x01;
+pub(super) const OPEN_WRITE: u32 = 0x02;
+pub(super) const OPEN_APPEND: u32 = 0x04;
+pub(super) const OPEN_CREAT: u32 = 0x08;
+pub(super) const OPEN_TRUNC: u32 = 0x10;
+pub(super) const OPEN_EXCL: u32 = 0x20;
+
+const ATTR_SIZE: u32 = 0x01;
+const ATTR_UIDGID: u32 = 0x02;
+const ATTR_PERMISSIONS: u32 = 0x04;
+const ATTR_ACMODTIME: u32 = 0x08;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// The status codes of `SSH_FXP_STATUS` replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusCode {
+    Ok,
+    Eof,
+    NoSuchFile,
+    PermissionDenied,
+    Failure,
+    BadMessage,
+    NoConnection,
+    ConnectionLost,
+    OpUnsupported,
+    Other(u32),
+}
+
+impl From<u32> for StatusCode {
+    fn from(code: u32) -> Self {
+        match code {
+            0 => StatusCode::Ok,
+            1 => StatusCode::Eof,
+            2 => StatusCode::NoSuchFile,
+            3 => StatusCode::PermissionDenied,
+            4 => StatusCode::Failure,
+            5 => StatusCode::BadMessage,
+            6 => StatusCode::NoConnection,
+            7 => StatusCode::ConnectionLost,
+            8 => StatusCode::OpUnsupported,
+            code => StatusCode::Other(code),
+        }
+    }
+}
+
+impl From<StatusCode> for u32 {
+    fn from(code: StatusCode) -> Self {
+        match code {
+            StatusCode::Ok => 0,
+            StatusCode::Eof => 1,
+            StatusCode::NoSuchFile => 2,
+            StatusCode::PermissionDenied => 3,
+            StatusCode::Failure => 4,
+            StatusCode::BadMessage => 5,
+            StatusCode::NoConnection => 6,
+            StatusCode::ConnectionLost => 7,
+            StatusCode::OpUnsupported => 8,
+            StatusCode::Other(code) => code,
+        }
+    }
+}
+
+/// An error status returned by the server. This is the inner error of
+/// the [`io::Error`]s returned by the SFTP client when the server
+/// refuses a request.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("SFTP error {code:?}: {message}")]
+pub struct StatusError {
+    pub code: StatusCode,
+    pub message: String,
+}
+
+impl From<StatusError> for io::Error {
+    fn from(e: StatusError) -> Self {
+        let kind = match e.code {
+            StatusCode::Eof => io::ErrorKind::UnexpectedEof,
+            StatusCode::NoSuchFile => io::ErrorKind::NotFound,
+            StatusCode::PermissionDenied => io::ErrorKind::PermissionDenied,
+            StatusCode::BadMessage => io::ErrorKind::InvalidData,
+            StatusCode::NoConnection | StatusCode::ConnectionLost => {
+                io::ErrorKind::ConnectionAborted
+            }
+            StatusCode::OpUnsupported => io::ErrorKind::Unsupported,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// The attributes of a file. Servers may leave any of them out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub size: Option<u64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// The file type and mode bits, as in `st_mode`.
+    pub permissions: Option<u32>,
+    /// Access time, in seconds since the Unix epoch.
+    pub atime: Option<u32>,
+    /// Modification time, in seconds since the Unix epoch.
+    pub mtime: Option<u32>,
+}
+
+impl FileAttributes {
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == Some(S_IFDIR)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type() == Some(S_IFREG)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == Some(S_IFLNK)
+    }
+
+    fn file_type(&self) -> Option<u32> {
+        self.permissions.map(|p| p & S_IFMT)
+    }
+
+    pub(super) fn encode(&self, w: &mut Vec<u8>) -> Result<(), ssh_encoding::Error> {
+        let mut flags = 0;
+        if self.size.is_some() {
+            flags |= ATTR_SIZE
+        }
+        if self.uid.is_some() && self.gid.is_some() {
+            flags |= ATTR_UIDGID
+        }
+        if self.permissions.is_some() {
+            flags |= ATTR_PERMISSIONS
+        }
+        if self.atime.is_some() && self.mtime.is_some() {
+            flags |= ATTR_ACMODTIME
+        }
+        flags.encode(w)?;
+        if let Some(size) = self.size {
+            size.encode(w)?;
+        }
+        if let (Some(uid), Some(gid)) = (self.uid, self.gid) {
+            uid.encode(w)?;
+            gid.encode(w)?;
+        }
+        if let Some(permissions) = self.permissions {
+            permissions.encode(w)?;
+        }
+        if let (Some(atime), Some(mtime)) = (self.atime, self.mtime) {
+            atime.encode(w)?;
+            mtime.encode(w)?;
+        }
+        Ok(())
+    }
+
+    pub(super) fn decode(r: &mut impl Reader) -> Result<Self, ssh_encoding::Error> {
+        let flags = u32::decode(r)?;
+        let mut attrs = FileAttributes::default();
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(u64::decode(r)?);
+        }
+        if flags & ATTR_UIDGID != 0 {
+            attrs.uid = Some(u32::decode(r)?);
+            attrs.gid = Some(u32::decode(r)?);
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(u32::decode(r)?);
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            attrs.atime = Some(u32::decode(r)?);
+            attrs.mtime = Some(u32::decode(r)?);
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..u32::decode(r)? {
+                Vec::<u8>::decode(r)?;
+                Vec::<u8>::decode(r)?;
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+/// An entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub file_name: String,
+    /// The line of `ls -l` describing the entry, for display only.
+    pub long_name: String,
+    pub attributes: FileAttributes,
+}
+
+/// A reply of the server.
+#[derive(Debug)]
+pub(super) enum Response {
+    Status { code: StatusCode, message: String },
+    Handle(Vec<u8>),
+    Data(Vec<u8>),
+    Name(Vec<DirEntry>),
+    Attrs(FileAttributes),
+}
+
+impl Response {
+    pub(super) fn decode(kind: u8, r: &mut impl Reader) -> Result<Self, ssh_encoding::Error> {
+        Ok(match kind {
+            STATUS => Response::Status {
+                code: u32::decode(r)?.into(),
+                // Servers speaking version 2 or older omit the message.
+                message: if r.is_finished() {
+                    String::new()
+                } else {
+                    lossy_string(r)?
+                },
+            },
+            HANDLE => Response::Handle(Vec::decode(r)?),
+            DATA => Response::Data(Vec::decode(r)?),
+            NAME => {
+                let count = u32::decode(r)?;
+                let mut entries = Vec::new();
+                for _ in 0..count {
+                    entries.push(DirEntry {
+                        file_name: lossy_string(r)?,
+                        long_name: lossy_string(r)?,
+                        attributes: FileAttributes::decode(r)?,
+                    });
+                }
+                Response::Name(entries)
+            }
+            ATTRS => Response::Attrs(FileAttributes::decode(r)?),
+            _ => return Err(ssh_encoding::Error::Length),
+        })
+    }
+
+    pub(super) fn into_status(self) -> io::Result<()> {
+        match self {
+            Response::Status {
+                code: StatusCode::Ok,
+                ..
+            } => Ok(()),
+            Response::Status { code, message } => Err(StatusError { code, message }.into()),
+            _ => Err(unexpected()),
+        }
+    }
+
+    pub(super) fn into_handle(self) -> io::Result<Vec<u8>> {
+        match self {
+            Response::Handle(handle) => Ok(handle),
+            r => Err(r.into_error()),
+        }
+    }
+
+    pub(super) fn into_attrs(self) -> io::Result<FileAttributes> {
+        match self {
+            Response::Attrs(attrs) => Ok(attrs),
+            r => Err(r.into_error()),
+        }
+    }
+
+    /// The entries of a `NAME` reply, or `None` at the end of a
+    /// directory.
+    pub(super) fn into_names(self) -> io::Result<Option<Vec<DirEntry>>> {
+        match self {
+            Response::Name(entries) => Ok(Some(entries)),
+            Response::Status {
+                code: StatusCode::Eof,
+                ..
+            } => Ok(None),
+            r => Err(r.into_error()),
+        }
+    }
+
+    /// The data of a `DATA` reply, or `None` at the end of a file.
+    pub(super) fn into_data(self) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Response::Data(data) => Ok(Some(data)),
+            Response::Status {
+                code: StatusCode::Eof,
+                ..
+            } => Ok(None),
+            r => Err(r.into_error()),
+        }
+    }
+
+    fn into_error(self) -> io::Error {
+        match self {
+            Response::Status { code, message } => StatusError { code, message }.into(),
+            _ => unexpected(),
+        }
+    }
+}
+
+fn lossy_string(r: &mut impl Reader) -> Result<String, ssh_encoding::Error> {
+    Ok(String::from_utf8_lossy(&Vec::<u8>::decode(r)?).into_owned())
+}
+
+fn unexpected() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "unexpected SFTP response")
+}
+
+pub(super) fn invalid_data(e: ssh_encoding::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
@@ -0,0 +1,260 @@
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use super::{normalize_path, OpenFlags, SftpBackend};
+use crate::sftp::{DirEntry, FileAttributes};
+
+/// The number of entries sent in a single `NAME` reply.
+const READ_DIR_CHUNK: usize = 100;
+
+/// Serves a directory of the local filesystem, which appears to the
+/// client as `/`.
+///
+/// Paths cannot leave the root directory, with `..` or with symbolic
+/// links pointing outside of it. Clients cannot create symbolic links,
+/// but other users of the directory could swap a directory for a link
+/// between the check of a path and its use: the root should only be
+/// writable by the clients.
+#[derive(Debug, Clone)]
+pub struct LocalFs {
+    root: PathBuf,
+}
+
+/// The entries of a directory, read when it is opened.
+#[derive(Debug)]
+pub struct LocalDir {
+    entries: std::vec::IntoIter<DirEntry>,
+}
+
+impl LocalFs {
+    /// Serve `root`, which must be an existing directory.
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let root = std::fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the root of the SFTP server is not a directory",
+            ));
+        }
+        Ok(LocalFs { root })
+    }
+
+    /// The local path of `path`, following the final symbolic link if
+    /// `follow` is set. This fails if the result is outside of the
+    /// root.
+    async fn resolve(&self, path: &str, follow: bool) -> io::Result<PathBuf> {
+        let mut local = self.root.clone();
+        local.extend(normalize_path(path).split('/').filter(|c| !c.is_empty()));
+        let resolved = match fs::canonicalize(&local).await {
+            Ok(resolved) if follow => resolved,
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            // The file itself may not exist yet, or is not followed.
+            _ => match (local.parent(), local.file_name()) {
+                (Some(parent), Some(name)) if local != self.root => {
+                    let resolved = fs::canonicalize(parent).await?.join(name);
+                    // A dangling link, which could be followed when
+                    // creating the file.
+                    if follow && fs::symlink_metadata(&resolved).await.is_ok() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            "dangling symbolic link",
+                        ));
+                    }
+                    resolved
+                }
+                _ => local,
+            },
+        };
+        if !resolved.starts_with(&self.root) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "outside of the root directory",
+            ));
+        }
+        Ok(resolved)
+    }
+}
+
+impl SftpBackend for LocalFs {
+    type File = fs::File;
+    type Dir = LocalDir;
+
+    async fn open(
+        &mut self,
+        path: &str,
+        flags: OpenFlags,
+        attributes: &FileAttributes,
+    ) -> io::Result<fs::File> {
+        let path = self.resolve(path, true).await?;
+        let mut options = fs::OpenOptions::new();
+        options
+            .read(flags.read || !flags.write)
+            .write(flags.write)
+            .append(flags.append)
+            .truncate(flags.truncate);
+        if flags.create && flags.exclusive {
+            options.create_new(true);
+        } else {
+            options.create(flags.create);
+        }
+        #[cfg(unix)]
+        if let Some(mode) = attributes.permissions {
+            options.mode(mode & 0o7777);
+        }
+        #[cfg(not(unix))]
+        let _ = attributes;
+        options.open(path).await
+    }
+
+    async fn read(&mut self, file: &mut fs::File, offset: u64, len: u32) -> io::Result<Vec<u8>> {
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut data = Vec::with_capacity(len as usize);
+        file.take(len as u64).read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    async fn write(&mut self, file: &mut fs::File, offset: u64, data: &[u8]) -> io::Result<()> {
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(data).await?;
+        file.flush().await
+    }
+
+    async fn fstat(&mut self, file: &mut fs::File) -> io::Result<FileAttributes> {
+        Ok(attributes(&file.metadata().await?))
+    }
+
+    async fn open_dir(&mut self, path: &str) -> io::Result<LocalDir> {
+        let path = self.resolve(path, true).await?;
+        let mut dir = fs::read_dir(path).await?;
+        let mut entries = Vec::new();
+        while let Some(entry) = dir.next_entry().await? {
+            let metadata = fs::symlink_metadata(entry.path()).await?;
+            entries.push(DirEntry::new(
+                entry.file_name().to_string_lossy(),
+                attributes(&metadata),
+            ));
+        }
+        Ok(LocalDir {
+            entries: entries.into_iter(),
+        })
+    }
+
+    async fn read_dir(&mut self, dir: &mut LocalDir) -> io::Result<Vec<DirEntry>> {
+        Ok(dir.entries.by_ref().take(READ_DIR_CHUNK).collect())
+    }
+
+    async fn stat(&mut self, path: &str) -> io::Result<FileAttributes> {
+        let path = self.resolve(path, true).await?;
+        Ok(attributes(&fs::metadata(path).await?))
+    }
+
+    async fn lstat(&mut self, path: &str) -> io::Result<FileAttributes> {
+        let path = self.resolve(path, false).await?;
+        Ok(attributes(&fs::symlink_metadata(path).await?))
+    }
+
+    async fn set_stat(&mut self, path: &str, attributes: &FileAttributes) -> io::Result<()> {
+        let path = self.resolve(path, true).await?;
+        if let Some(size) = attributes.size {
+            let file = fs::OpenOptions::new().write(true).open(&path).await?;
+            file.set_len(size).await?;
+        }
+        #[cfg(unix)]
+        if let Some(mode) = attributes.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, std::fs::Permissions::from_mode(mode & 0o7777)).await?;
+        }
+        if let (Some(atime), Some(mtime)) = (attributes.atime, attributes.mtime) {
+            let file = std::fs::File::options().write(true).open(&path)?;
+            let time = |t: u32| UNIX_EPOCH + Duration::from_secs(t as u64);
+            file.set_times(
+                std::fs::FileTimes::new()
+                    .set_accessed(time(atime))
+                    .set_modified(time(mtime)),
+            )?;
+        }
+        Ok(())
+    }
+
+    async fn rename(&mut self, from: &str, to: &str) -> io::Result<()> {
+        let from = self.resolve(from, false).await?;
+        let to = self.resolve(to, false).await?;
+        // Unlike `rename(2)`, SFTP does not replace existing files.
+        if fs::symlink_metadata(&to).await.is_ok() {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        fs::rename(from, to).await
+    }
+
+    async fn remove(&mut self, path: &str) -> io::Result<()> {
+        fs::remove_file(self.resolve(path, false).await?).await
+    }
+
+    async fn create_dir(&mut self, path: &str, attributes: &FileAttributes) -> io::Result<()> {
+        let path = self.resolve(path, false).await?;
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        if let Some(mode) = attributes.permissions {
+            builder.mode(mode & 0o7777);
+        }
+        #[cfg(not(unix))]
+        let _ = attributes;
+        builder.create(path).await
+    }
+
+    async fn remove_dir(&mut self, path: &str) -> io::Result<()> {
+        fs::remove_dir(self.resolve(path, false).await?).await
+    }
+
+    async fn read_link(&mut self, path: &str) -> io::Result<String> {
+        let path = self.resolve(path, false).await?;
+        Ok(fs::read_link(path).await?.to_string_lossy().into_owned())
+    }
+}
+
+fn attributes(metadata: &std::fs::Metadata) -> FileAttributes {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        FileAttributes {
+            size: Some(metadata.size()),
+            uid: Some(metadata.uid()),
+            gid: Some(metadata.gid()),
+            permissions: Some(metadata.mode()),
+            atime: Some(metadata.atime() as u32),
+            mtime: Some(metadata.mtime() as u32),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let (file_type, mode) = if metadata.is_dir() {
+            (0o040000, 0o755)
+        } else if metadata.is_symlink() {
+            (0o120000, 0o777)
+        } else {
+            (0o100000, 0o644)
+        };
+        let mode = if metadata.permissions().readonly() {
+            mode & !0o222
+        } else {
+            mode
+        };
+        let time = |t: io::Result<std::time::SystemTime>| {
+            t.ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as u32)
+        };
+        FileAttributes {
+            size: Some(metadata.len()),
+            uid: None,
+            gid: None,
+            permissions: Some(file_type | mode),
+            atime: time(metadata.accessed()),
+            mtime: time(metadata.modified()),
+        }
+    }
+}
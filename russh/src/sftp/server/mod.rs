@@ -0,0 +1,578 @@
+//! An SFTP (version 3) server, for the `sftp` subsystem.
+//!
+//! [`serve`] runs the protocol on a channel, or on any byte stream, and
+//! delegates the requests to an [`SftpBackend`]. [`LocalFs`] serves a
+//! directory of the local filesystem:
+//!
+//! ```no_run
+//! # use russh::server::{Msg, Session};
+//! # use russh::{Channel, ChannelId};
+//! # use russh::sftp::server::{serve, LocalFs};
+//! # fn run(channel: Channel<Msg>, name: &str, session: &mut Session) -> Result<(), russh::Error> {
+//! // In `Handler::subsystem_request`:
+//! if name == "sftp" {
+//!     let backend = LocalFs::new("/srv/files")?;
+//!     session.channel_success(channel.id())?;
+//!     tokio::spawn(serve(channel.into_stream(), backend));
+//! } else {
+//!     session.channel_failure(channel.id())?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests are handled one at a time, in order. The errors of the
+//! backend are sent to the client as status codes: a [`StatusError`]
+//! is sent as is, and other errors are mapped from their kind, so that
+//! [`io::ErrorKind::NotFound`] is sent as [`StatusCode::NoSuchFile`].
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+
+use log::debug;
+use ssh_encoding::{Decode, Encode};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::protocol::*;
+use super::{encode, read_packet, write_packet, DirEntry, FileAttributes, StatusCode, StatusError};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod local;
+#[cfg(not(target_arch = "wasm32"))]
+pub use local::{LocalDir, LocalFs};
+
+/// The largest read answered in a single `DATA` reply.
+const MAX_READ_LEN: u32 = 64 * 1024;
+
+/// How the client wants a file to be opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpenFlags {
+    pub read: bool,
+    pub write: bool,
+    /// Writes are made at the end of the file.
+    pub append: bool,
+    /// Create the file if it does not exist.
+    pub create: bool,
+    pub truncate: bool,
+    /// With `create`, fail if the file exists.
+    pub exclusive: bool,
+}
+
+impl OpenFlags {
+    fn from_bits(flags: u32) -> Self {
+        OpenFlags {
+            read: flags & OPEN_READ != 0,
+            write: flags & OPEN_WRITE != 0,
+            append: flags & OPEN_APPEND != 0,
+            create: flags & OPEN_CREAT != 0,
+            truncate: flags & OPEN_TRUNC != 0,
+            exclusive: flags & OPEN_EXCL != 0,
+        }
+    }
+}
+
+/// The filesystem served by [`serve`]. Paths are the strings sent by
+/// the client, which usually uses `/` as a separator.
+///
+/// Only the operations on files and directory listings are required.
+/// The others are refused by default, with
+/// [`io::ErrorKind::Unsupported`].
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+pub trait SftpBackend: Send {
+    /// An open file.
+    type File: Send;
+    /// An open directory.
+    type Dir: Send;
+
+    /// Open a file. `attributes` are those of the file, if it is
+    /// created.
+    fn open(
+        &mut self,
+        path: &str,
+        flags: OpenFlags,
+        attributes: &FileAttributes,
+    ) -> impl Future<Output = io::Result<Self::File>> + Send;
+
+    /// Read at most `len` bytes at `offset`. An empty result means the
+    /// end of the file.
+    fn read(
+        &mut self,
+        file: &mut Self::File,
+        offset: u64,
+        len: u32,
+    ) -> impl Future<Output = io::Result<Vec<u8>>> + Send;
+
+    fn write(
+        &mut self,
+        file: &mut Self::File,
+        offset: u64,
+        data: &[u8],
+    ) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// The attributes of an open file.
+    fn fstat(
+        &mut self,
+        file: &mut Self::File,
+    ) -> impl Future<Output = io::Result<FileAttributes>> + Send;
+
+    /// Close a file. By default, it is dropped.
+    #[allow(unused_variables)]
+    fn close(&mut self, file: Self::File) -> impl Future<Output = io::Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    fn open_dir(&mut self, path: &str) -> impl Future<Output = io::Result<Self::Dir>> + Send;
+
+    /// The next entries of a directory. An empty result means the end
+    /// of the directory.
+    fn read_dir(
+        &mut self,
+        dir: &mut Self::Dir,
+    ) -> impl Future<Output = io::Result<Vec<DirEntry>>> + Send;
+
+    /// The attributes of a file, following symbolic links.
+    fn stat(&mut self, path: &str) -> impl Future<Output = io::Result<FileAttributes>> + Send;
+
+    /// The attributes of a file, without following symbolic links. By
+    /// default, this is [`SftpBackend::stat`].
+    fn lstat(&mut self, path: &str) -> impl Future<Output = io::Result<FileAttributes>> + Send {
+        self.stat(path)
+    }
+
+    /// Change the attributes of a file that are set in `attributes`.
+    #[allow(unused_variables)]
+    fn set_stat(
+        &mut self,
+        path: &str,
+        attributes: &FileAttributes,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    /// Rename a file. This should fail if `to` exists.
+    fn rename(&mut self, from: &str, to: &str) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Remove a file.
+    fn remove(&mut self, path: &str) -> impl Future<Output = io::Result<()>> + Send;
+
+    #[allow(unused_variables)]
+    fn create_dir(
+        &mut self,
+        path: &str,
+        attributes: &FileAttributes,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    #[allow(unused_variables)]
+    fn remove_dir(&mut self, path: &str) -> impl Future<Output = io::Result<()>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    /// The absolute path of `path`. By default, `.` and `..` are
+    /// resolved, and relative paths are relative to `/`.
+    fn real_path(&mut self, path: &str) -> impl Future<Output = io::Result<String>> + Send {
+        let path = normalize_path(path);
+        async { Ok(path) }
+    }
+
+    /// The target of a symbolic link.
+    #[allow(unused_variables)]
+    fn read_link(&mut self, path: &str) -> impl Future<Output = io::Result<String>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+}
+
+impl DirEntry {
+    /// An entry with a `long_name` in the style of `ls -l`.
+    pub fn new<N: Into<String>>(file_name: N, attributes: FileAttributes) -> Self {
+        let file_name = file_name.into();
+        let mode = attributes.permissions.unwrap_or(0);
+        let mut long_name = String::from(if attributes.is_dir() {
+            'd'
+        } else if attributes.is_symlink() {
+            'l'
+        } else {
+            '-'
+        });
+        for shift in [6, 3, 0] {
+            let bits = (mode >> shift) & 0o7;
+            long_name.push(if bits & 4 != 0 { 'r' } else { '-' });
+            long_name.push(if bits & 2 != 0 { 'w' } else { '-' });
+            long_name.push(if bits & 1 != 0 { 'x' } else { '-' });
+        }
+        long_name.push_str(&format!(
+            " {:>4} {:<8} {:<8} {:>8} {}",
+            1,
+            attributes.uid.unwrap_or(0),
+            attributes.gid.unwrap_or(0),
+            attributes.size.unwrap_or(0),
+            file_name
+        ));
+        DirEntry {
+            file_name,
+            long_name,
+            attributes,
+        }
+    }
+}
+
+enum Open<F, D> {
+    File(F),
+    Dir(D),
+}
+
+/// The state of a session with one client.
+struct Session<B: SftpBackend> {
+    backend: B,
+    handles: HashMap<Vec<u8>, Open<B::File, B::Dir>>,
+    next_handle: u64,
+}
+
+/// Run an SFTP server on `stream` until the client closes it.
+pub async fn serve<S, B>(mut stream: S, backend: B) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+    B: SftpBackend,
+{
+    let init = read_packet(&mut stream).await?;
+    if init.first() != Some(&INIT) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "expected SSH_FXP_INIT",
+        ));
+    }
+    let mut version = Vec::new();
+    encode(&mut version, |w| VERSION.encode(w))?;
+    write_packet(&mut stream, VERSION_REPLY, &version).await?;
+
+    let mut session = Session {
+        backend,
+        handles: HashMap::new(),
+        next_handle: 0,
+    };
+    loop {
+        let packet = match read_packet(&mut stream).await {
+            Ok(packet) => packet,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut r = &packet[..];
+        let (kind, id) =
+            (|| Ok::<_, ssh_encoding::Error>((u8::decode(&mut r)?, u32::decode(&mut r)?)))()
+                .map_err(invalid_data)?;
+        let (reply_kind, reply) = match session.handle(kind, &mut r).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                debug!("SFTP request {kind} failed: {e}");
+                status_reply(&e)?
+            }
+        };
+        let mut payload = Vec::new();
+        encode(&mut payload, |w| id.encode(w))?;
+        payload.extend(reply);
+        write_packet(&mut stream, reply_kind, &payload).await?;
+    }
+}
+
+impl<B: SftpBackend> Session<B> {
+    async fn handle(&mut self, kind: u8, r: &mut &[u8]) -> io::Result<(u8, Vec<u8>)> {
+        let mut w = Vec::new();
+        match kind {
+            OPEN => {
+                let path = decode_string(r)?;
+                let flags = OpenFlags::from_bits(u32::decode(r).map_err(invalid_data)?);
+                let attributes = FileAttributes::decode(r).map_err(invalid_data)?;
+                let file = self.backend.open(&path, flags, &attributes).await?;
+                self.new_handle(Open::File(file))
+            }
+            OPENDIR => {
+                let dir = self.backend.open_dir(&decode_string(r)?).await?;
+                self.new_handle(Open::Dir(dir))
+            }
+            CLOSE => {
+                match self.handles.remove(&decode_bytes(r)?) {
+                    Some(Open::File(file)) => self.backend.close(file).await?,
+                    Some(Open::Dir(_)) => {}
+                    None => return Err(invalid_handle()),
+                }
+                ok()
+            }
+            READ => {
+                let handle = decode_bytes(r)?;
+                let offset = u64::decode(r).map_err(invalid_data)?;
+                let len = u32::decode(r).map_err(invalid_data)?.min(MAX_READ_LEN);
+                let Some(Open::File(file)) = self.handles.get_mut(&handle) else {
+                    return Err(invalid_handle());
+                };
+                let data = self.backend.read(file, offset, len).await?;
+                if data.is_empty() {
+                    return Err(StatusError {
+                        code: StatusCode::Eof,
+                        message: String::new(),
+                    }
+                    .into());
+                }
+                encode(&mut w, |w| data.encode(w))?;
+                Ok((DATA, w))
+            }
+            WRITE => {
+                let handle = decode_bytes(r)?;
+                let offset = u64::decode(r).map_err(invalid_data)?;
+                let data = decode_bytes(r)?;
+                let Some(Open::File(file)) = self.handles.get_mut(&handle) else {
+                    return Err(invalid_handle());
+                };
+                self.backend.write(file, offset, &data).await?;
+                ok()
+            }
+            FSTAT => {
+                let Some(Open::File(file)) = self.handles.get_mut(&decode_bytes(r)?) else {
+                    return Err(invalid_handle());
+                };
+                attrs(self.backend.fstat(file).await?)
+            }
+            STAT => attrs(self.backend.stat(&decode_string(r)?).await?),
+            LSTAT => attrs(self.backend.lstat(&decode_string(r)?).await?),
+            SETSTAT => {
+                let path = decode_string(r)?;
+                let attributes = FileAttributes::decode(r).map_err(invalid_data)?;
+                self.backend.set_stat(&path, &attributes).await?;
+                ok()
+            }
+            READDIR => {
+                let Some(Open::Dir(dir)) = self.handles.get_mut(&decode_bytes(r)?) else {
+                    return Err(invalid_handle());
+                };
+                let entries = self.backend.read_dir(dir).await?;
+                if entries.is_empty() {
+                    return Err(StatusError {
+                        code: StatusCode::Eof,
+                        message: String::new(),
+                    }
+                    .into());
+                }
+                names(&entries)
+            }
+            REMOVE => {
+                self.backend.remove(&decode_string(r)?).await?;
+                ok()
+            }
+            MKDIR => {
+                let path = decode_string(r)?;
+                let attributes = FileAttributes::decode(r).map_err(invalid_data)?;
+                self.backend.create_dir(&path, &attributes).await?;
+                ok()
+            }
+            RMDIR => {
+                self.backend.remove_dir(&decode_string(r)?).await?;
+                ok()
+            }
+            RENAME => {
+                let from = decode_string(r)?;
+                let to = decode_string(r)?;
+                self.backend.rename(&from, &to).await?;
+                ok()
+            }
+            REALPATH => {
+                let path = self.backend.real_path(&decode_string(r)?).await?;
+                names(&[DirEntry {
+                    long_name: path.clone(),
+                    file_name: path,
+                    attributes: FileAttributes::default(),
+                }])
+            }
+            READLINK => {
+                let target = self.backend.read_link(&decode_string(r)?).await?;
+                names(&[DirEntry {
+                    long_name: target.clone(),
+                    file_name: target,
+                    attributes: FileAttributes::default(),
+                }])
+            }
+            _ => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    fn new_handle(&mut self, open: Open<B::File, B::Dir>) -> io::Result<(u8, Vec<u8>)> {
+        self.next_handle += 1;
+        let handle = self.next_handle.to_be_bytes().to_vec();
+        let mut w = Vec::new();
+        encode(&mut w, |w| handle.encode(w))?;
+        self.handles.insert(handle, open);
+        Ok((HANDLE, w))
+    }
+}
+
+/// The absolute form of `path`, with `.` and `..` resolved. Relative
+/// paths are relative to `/`, and `..` stops at `/`.
+pub(super) fn normalize_path(path: &str) -> String {
+    let mut components = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            c => components.push(c),
+        }
+    }
+    format!("/{}", components.join("/"))
+}
+
+fn decode_string(r: &mut &[u8]) -> io::Result<String> {
+    String::decode(r).map_err(invalid_data)
+}
+
+fn decode_bytes(r: &mut &[u8]) -> io::Result<Vec<u8>> {
+    Vec::decode(r).map_err(invalid_data)
+}
+
+fn invalid_handle() -> io::Error {
+    StatusError {
+        code: StatusCode::Failure,
+        message: "invalid handle".into(),
+    }
+    .into()
+}
+
+fn ok() -> io::Result<(u8, Vec<u8>)> {
+    status(StatusCode::Ok, "")
+}
+
+fn status(code: StatusCode, message: &str) -> io::Result<(u8, Vec<u8>)> {
+    let mut w = Vec::new();
+    encode(&mut w, |w| {
+        u32::from(code).encode(w)?;
+        message.encode(w)?;
+        // Language tag.
+        "".encode(w)
+    })?;
+    Ok((STATUS, w))
+}
+
+fn status_reply(e: &io::Error) -> io::Result<(u8, Vec<u8>)> {
+    if let Some(e) = e.get_ref().and_then(|e| e.downcast_ref::<StatusError>()) {
+        return status(e.code, &e.message);
+    }
+    let code = match e.kind() {
+        io::ErrorKind::NotFound => StatusCode::NoSuchFile,
+        io::ErrorKind::PermissionDenied => StatusCode::PermissionDenied,
+        io::ErrorKind::UnexpectedEof => StatusCode::Eof,
+        io::ErrorKind::InvalidData => StatusCode::BadMessage,
+        io::ErrorKind::Unsupported => StatusCode::OpUnsupported,
+        _ => StatusCode::Failure,
+    };
+    status(code, &e.to_string())
+}
+
+fn attrs(attributes: FileAttributes) -> io::Result<(u8, Vec<u8>)> {
+    let mut w = Vec::new();
+    encode(&mut w, |w| attributes.encode(w))?;
+    Ok((ATTRS, w))
+}
+
+fn names(entries: &[DirEntry]) -> io::Result<(u8, Vec<u8>)> {
+    let mut w = Vec::new();
+    encode(&mut w, |w| {
+        (entries.len() as u32).encode(w)?;
+        for entry in entries {
+            entry.file_name.encode(w)?;
+            entry.long_name.encode(w)?;
+            entry.attributes.encode(w)?;
+        }
+        Ok(())
+    })?;
+    Ok((NAME, w))
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+    use crate::sftp::{OpenOptions, SftpSession};
+
+    async fn session(root: &std::path::Path) -> SftpSession {
+        let (client, server) = tokio::io::duplex(65536);
+        tokio::spawn(serve(server, LocalFs::new(root).unwrap()));
+        SftpSession::from_stream(client).await.unwrap()
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(normalize_path(""), "/");
+        assert_eq!(normalize_path("a/./b/"), "/a/b");
+        assert_eq!(normalize_path("/../a/../../b"), "/b");
+    }
+
+    #[tokio::test]
+    async fn local_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let sftp = session(dir.path()).await;
+        assert_eq!(sftp.canonicalize(".").await.unwrap(), "/");
+
+        let large: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        sftp.write("/large", &large).await.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("large")).unwrap(), large);
+        assert_eq!(sftp.read("large").await.unwrap(), large);
+        assert_eq!(sftp.metadata("/large").await.unwrap().size, Some(200_000));
+
+        let error = sftp
+            .open_with("/large", OpenOptions::new().write(true).create_new(true))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Other);
+
+        sftp.create_dir("/d").await.unwrap();
+        sftp.write("/d/a", b"a").await.unwrap();
+        sftp.rename("/d/a", "/d/b").await.unwrap();
+        assert!(sftp.rename("/d/b", "/large").await.is_err());
+        let mut names: Vec<_> = sftp
+            .read_dir("/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| (e.file_name, e.attributes.is_dir()))
+            .collect();
+        names.sort();
+        assert_eq!(names, [("d".into(), true), ("large".into(), false)]);
+        let entries = sftp.read_dir("/d").await.unwrap();
+        assert!(entries.first().unwrap().long_name.starts_with("-rw"));
+
+        let error = sftp.read("/d/a").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        sftp.remove_file("/d/b").await.unwrap();
+        sftp.remove_dir("/d").await.unwrap();
+        assert!(!dir.path().join("d").exists());
+    }
+
+    #[tokio::test]
+    async fn local_confinement() {
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret"), b"secret").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(dir.path().join("sibling"), b"sibling").unwrap();
+        let sftp = session(&root).await;
+
+        // `..` stops at the root.
+        assert_eq!(sftp.canonicalize("/../..").await.unwrap(), "/");
+        let error = sftp.read("/../sibling").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(outside.path(), root.join("out")).unwrap();
+            std::os::unix::fs::symlink(outside.path().join("new"), root.join("dangling")).unwrap();
+            let error = sftp.read("/out/secret").await.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+            assert!(sftp.write("/dangling", b"x").await.is_err());
+            assert!(!outside.path().join("new").exists());
+            // The links themselves can be seen and removed.
+            assert!(sftp.symlink_metadata("/out").await.unwrap().is_symlink());
+            sftp.remove_file("/out").await.unwrap();
+        }
+    }
+}
@@ -70,8 +70,9 @@ fn test_ssh_id() {
 #[derive(Debug, Default)]
 pub struct SSHBuffer {
     pub buffer: CryptoVec,
-    pub len: usize,   // next packet length.
-    pub bytes: usize, // total bytes written since the last rekey
+    pub len: usize,     // next packet length.
+    pub bytes: usize,   // total bytes written since the last rekey
+    pub packets: usize, // total packets written since the last rekey
     // Sequence numbers are on 32 bits and wrap.
     // https://tools.ietf.org/html/rfc4253#section-6.4
     pub seqn: Wrapping<u32>,
@@ -83,6 +84,7 @@ impl SSHBuffer {
             buffer: CryptoVec::new(),
             len: 0,
             bytes: 0,
+            packets: 0,
             seqn: Wrapping(0),
         }
     }
@@ -1,9 +1,90 @@
 #![allow(clippy::unwrap_used, clippy::expect_used, clippy::panic)] // Allow unwraps, expects and panics in the test suite
 
+use std::sync::Arc;
+
 use futures::Future;
+use keys::PrivateKeyWithHashAlg;
+use rand_core::OsRng;
+use ssh_key::PrivateKey;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
 
 use super::*;
 
+/// A client accepting any server key.
+struct Client {}
+
+impl client::Handler for Client {
+    type Error = crate::Error;
+
+    async fn check_server_key(
+        &mut self,
+        _server_public_key: &crate::keys::ssh_key::PublicKey,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+fn random_key() -> PrivateKey {
+    PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap()
+}
+
+/// A server configuration with a random Ed25519 host key.
+fn server_config() -> server::Config {
+    server::Config {
+        inactivity_timeout: None,
+        keys: vec![random_key()],
+        ..Default::default()
+    }
+}
+
+/// Run `handler` on one end of a TCP socket pair, and return the other
+/// end with the task of the server.
+async fn serve<H: server::Handler + Send + 'static>(
+    config: server::Config,
+    handler: H,
+) -> (TcpStream, JoinHandle<Result<(), H::Error>>) {
+    let _ = env_logger::try_init();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap());
+    let (client, accepted) = tokio::join!(client, listener.accept());
+    let (socket, _) = accepted.unwrap();
+    let config = Arc::new(config);
+    let server =
+        tokio::spawn(async move { server::run_stream(config, socket, handler).await?.await });
+    (client.unwrap(), server)
+}
+
+/// Connect `client` to `handler` over a TCP socket pair.
+async fn connect_with<H, C>(
+    server_config: server::Config,
+    handler: H,
+    client_config: client::Config,
+    client: C,
+) -> client::Handle<C>
+where
+    H: server::Handler + Send + 'static,
+    C: client::Handler + Send + 'static,
+{
+    let (socket, _) = serve(server_config, handler).await;
+    client::connect_stream(Arc::new(client_config), socket, client)
+        .await
+        .unwrap()
+}
+
+/// Authenticate as `user` with a random key, which the server must accept.
+async fn authenticate<C: client::Handler>(session: &mut client::Handle<C>) {
+    let authenticated = session
+        .authenticate_publickey(
+            "user",
+            PrivateKeyWithHashAlg::new(Arc::new(random_key()), None),
+        )
+        .await
+        .unwrap();
+    assert!(authenticated.success());
+}
+
 mod compress {
     use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
@@ -389,6 +470,102 @@ mod channels {
         .await;
     }
 
+    #[tokio::test]
+    async fn test_split_streams() {
+        #[derive(Debug)]
+        struct Client {}
+
+        impl client::Handler for Client {
+            type Error = crate::Error;
+
+            async fn check_server_key(
+                &mut self,
+                _server_public_key: &crate::keys::ssh_key::PublicKey,
+            ) -> Result<bool, Self::Error> {
+                Ok(true)
+            }
+        }
+
+        struct ServerHandle {
+            channel: Option<tokio::sync::oneshot::Sender<Channel<server::Msg>>>,
+        }
+
+        impl server::Handler for ServerHandle {
+            type Error = crate::Error;
+
+            async fn auth_publickey(
+                &mut self,
+                _: &str,
+                _: &crate::keys::ssh_key::PublicKey,
+            ) -> Result<server::Auth, Self::Error> {
+                Ok(server::Auth::Accept)
+            }
+
+            async fn channel_open_session(
+                &mut self,
+                channel: Channel<server::Msg>,
+                _session: &mut server::Session,
+            ) -> Result<bool, Self::Error> {
+                if let Some(a) = self.channel.take() {
+                    a.send(channel).unwrap();
+                }
+                Ok(true)
+            }
+        }
+
+        let (tx, scw) = tokio::sync::oneshot::channel();
+        let sh = ServerHandle { channel: Some(tx) };
+
+        test_session(
+            Client {},
+            sh,
+            |client| async move {
+                let ch = client.channel_open_session().await.unwrap();
+                let mut streams = ch.split_streams();
+                streams.stdin.write_all(&b"input"[..]).await.unwrap();
+                streams.stdin.shutdown().await.unwrap();
+
+                let mut stdout = Vec::new();
+                let mut stderr = Vec::new();
+                let (out, err) = tokio::join!(
+                    streams.stdout.read_to_end(&mut stdout),
+                    streams.stderr.read_to_end(&mut stderr),
+                );
+                out.unwrap();
+                err.unwrap();
+                assert_eq!(&stdout, &b"out 0 out 1 out 2 "[..]);
+                assert_eq!(&stderr, &b"err 0 err 1 err 2 "[..]);
+
+                client
+            },
+            |server| async move {
+                let mut channel = scw.await.unwrap();
+
+                let mut input = Vec::new();
+                while let Some(msg) = channel.wait().await {
+                    match msg {
+                        ChannelMsg::Data { data } => input.extend_from_slice(&data),
+                        ChannelMsg::Eof => break,
+                        _ => {}
+                    }
+                }
+                assert_eq!(&input, &b"input"[..]);
+
+                for i in 0..3 {
+                    channel.data(format!("out {i} ").as_bytes()).await.unwrap();
+                    channel
+                        .extended_data(1, format!("err {i} ").as_bytes())
+                        .await
+                        .unwrap();
+                }
+                channel.eof().await.unwrap();
+
+                server
+            },
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn test_channel_objects() {
         #[derive(Debug)]
@@ -564,3 +741,4573 @@ mod channels {
         .await;
     }
 }
+
+mod forwarding {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Echoes everything written to `direct-tcpip` channels.
+    struct EchoServer {
+        /// Receives what the client answered on the agent and X11
+        /// channels opened by the server.
+        replies: Option<tokio::sync::mpsc::UnboundedSender<Vec<u8>>>,
+    }
+
+    impl server::Handler for EchoServer {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_direct_tcpip(
+            &mut self,
+            channel: Channel<server::Msg>,
+            host_to_connect: &str,
+            port_to_connect: u32,
+            _originator_address: &str,
+            _originator_port: u32,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            assert_eq!((host_to_connect, port_to_connect), ("example.com", 80));
+            tokio::spawn(async move {
+                let (mut read, mut write) = tokio::io::split(channel.into_stream());
+                tokio::io::copy(&mut read, &mut write).await.unwrap();
+                write.shutdown().await.unwrap();
+            });
+            Ok(true)
+        }
+
+        async fn tcpip_forward(
+            &mut self,
+            address: &str,
+            port: &mut u32,
+            session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            if *port == 0 {
+                *port = 2222;
+            }
+            let (address, port) = (address.to_string(), *port);
+            let handle = session.handle();
+            tokio::spawn(async move {
+                let channel = handle
+                    .channel_open_forwarded_tcpip(address, port, "10.0.0.1", 4444)
+                    .await
+                    .unwrap();
+                channel.data(&b"forwarded"[..]).await.unwrap();
+                channel.eof().await.unwrap();
+            });
+            Ok(true)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn agent_request(
+            &mut self,
+            _channel: ChannelId,
+            session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            let Some(replies) = self.replies.clone() else {
+                return Ok(false);
+            };
+            let handle = session.handle();
+            tokio::spawn(async move {
+                let channel = handle.channel_open_agent().await.unwrap();
+                let mut stream = channel.into_stream();
+                stream.write_all(b"request").await.unwrap();
+                let mut buf = Vec::new();
+                stream.read_to_end(&mut buf).await.unwrap();
+                replies.send(buf).unwrap();
+            });
+            Ok(true)
+        }
+
+        async fn streamlocal_forward(
+            &mut self,
+            socket_path: &str,
+            session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            let socket_path = socket_path.to_string();
+            let handle = session.handle();
+            tokio::spawn(async move {
+                let channel = handle
+                    .channel_open_forwarded_streamlocal(socket_path)
+                    .await
+                    .unwrap();
+                channel.data(&b"forwarded"[..]).await.unwrap();
+                channel.eof().await.unwrap();
+            });
+            Ok(true)
+        }
+
+        async fn channel_open_tun(
+            &mut self,
+            channel: Channel<server::Msg>,
+            mode: tun::TunMode,
+            remote_unit: u32,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            assert_eq!(remote_unit, tun::TUN_UNIT_ANY);
+            tokio::spawn(async move {
+                let mut tun = tun::TunChannel::new(channel, mode);
+                while let Some(packet) = tun.recv().await {
+                    tun.send(&packet).await.unwrap();
+                }
+            });
+            Ok(true)
+        }
+
+        async fn x11_request(
+            &mut self,
+            _channel: ChannelId,
+            _single_connection: bool,
+            x11_auth_protocol: &str,
+            x11_auth_cookie: &str,
+            _x11_screen_number: u32,
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            assert_eq!(x11_auth_protocol, "MIT-MAGIC-COOKIE-1");
+            let cookie = data_encoding::HEXLOWER
+                .decode(x11_auth_cookie.as_bytes())
+                .unwrap();
+            let Some(replies) = self.replies.clone() else {
+                return Ok(());
+            };
+            let handle = session.handle();
+            tokio::spawn(async move {
+                let channel = handle.channel_open_x11("127.0.0.1", 6010).await.unwrap();
+                let mut stream = channel.into_stream();
+                let mut setup = vec![b'l', 0, 11, 0, 0, 0, 18, 0, 16, 0, 0, 0];
+                setup.extend(b"MIT-MAGIC-COOKIE-1\0\0");
+                setup.extend(cookie);
+                stream.write_all(&setup).await.unwrap();
+                let mut buf = Vec::new();
+                stream.read_to_end(&mut buf).await.unwrap();
+                replies.send(buf).unwrap();
+            });
+            Ok(())
+        }
+    }
+
+    async fn connect() -> client::Handle<Client> {
+        connect_to(EchoServer { replies: None }).await
+    }
+
+    async fn connect_to<H: server::Handler<Error = crate::Error> + Send + 'static>(
+        server: H,
+    ) -> client::Handle<Client> {
+        let _ = env_logger::try_init();
+
+        let mut session =
+            connect_with(server_config(), server, Default::default(), Client {}).await;
+        authenticate(&mut session).await;
+        session
+    }
+
+    #[tokio::test]
+    async fn test_forward_local() {
+        let session = connect().await;
+        let forward = session
+            .forward_local("127.0.0.1:0", "example.com", 80)
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            let mut stream = tokio::net::TcpStream::connect(forward.local_addr())
+                .await
+                .unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            stream.shutdown().await.unwrap();
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"hello");
+        }
+
+        let addr = forward.local_addr();
+        drop(forward);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_socks() {
+        use client::proxy::socks;
+
+        let session = connect().await;
+        let originator = "127.0.0.1:4000".parse().unwrap();
+        let (mut stream, socks_end) = tokio::io::duplex(4096);
+        let (result, ()) = tokio::join!(
+            socks::handle_connection(&session, socks_end, originator),
+            async {
+                stream.write_all(&[5, 1, 0]).await.unwrap();
+                let mut method = [0; 2];
+                stream.read_exact(&mut method).await.unwrap();
+                assert_eq!(method, [5, 0]);
+                stream
+                    .write_all(b"\x05\x01\x00\x03\x0bexample.com\x00\x50")
+                    .await
+                    .unwrap();
+                let mut reply = [0; 10];
+                stream.read_exact(&mut reply).await.unwrap();
+                assert_eq!(reply[..2], [5, 0]);
+
+                stream.write_all(b"hello").await.unwrap();
+                stream.shutdown().await.unwrap();
+                let mut buf = Vec::new();
+                stream.read_to_end(&mut buf).await.unwrap();
+                assert_eq!(buf, b"hello");
+            }
+        );
+        result.unwrap();
+
+        // Once the connection ends, requests fail and the listener stops.
+        let session = Arc::new(session);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let serving = tokio::spawn(socks::serve(session.clone(), listener));
+        session
+            .disconnect(Disconnect::ByApplication, "", "")
+            .await
+            .unwrap();
+        session.closed().await;
+        let (mut stream, socks_end) = tokio::io::duplex(4096);
+        let (result, ()) = tokio::join!(
+            socks::handle_connection(&session, socks_end, originator),
+            async {
+                stream
+                    .write_all(b"\x05\x01\x00\x05\x01\x00\x01\x7f\x00\x00\x01\x00\x50")
+                    .await
+                    .unwrap();
+                let mut reply = [0; 12];
+                stream.read_exact(&mut reply).await.unwrap();
+                assert_eq!(reply[2..4], [5, 1]);
+            }
+        );
+        assert!(result.is_err());
+        assert!(serving.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let session = connect().await;
+        let mut channel = session
+            .channel_open_direct_tcpip("example.com", 80, "127.0.0.1", 0)
+            .await
+            .unwrap();
+        channel.set_rate_limit(
+            Some(RateLimiter::new(RateLimit {
+                bytes_per_second: 50_000,
+                burst: 10_000,
+            })),
+            None,
+        );
+        let mut stream = channel.into_stream();
+        let data = vec![7u8; 35_000];
+        let start = std::time::Instant::now();
+        stream.write_all(&data).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+        // The first 10 KB go through at once, the rest takes 0.5s.
+        assert!(start.elapsed() >= std::time::Duration::from_millis(400));
+    }
+
+    async fn echo_through(addr: std::net::SocketAddr) {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_resilient_handle() {
+        let resilient = client::ResilientHandle::new(
+            || async { Ok(connect().await) },
+            client::Backoff {
+                initial: std::time::Duration::from_millis(10),
+                ..Default::default()
+            },
+        );
+        let (events, mut events_recv) = tokio::sync::mpsc::unbounded_channel();
+        resilient.on_event(move |event| {
+            let _ = events.send(format!("{event:?}"));
+        });
+        let forward = resilient
+            .forward_local("127.0.0.1:0", "example.com", 80)
+            .await
+            .unwrap();
+
+        let first = resilient.handle().await.unwrap();
+        echo_through(forward.local_addr()).await;
+
+        first
+            .disconnect(Disconnect::ByApplication, "", "")
+            .await
+            .unwrap();
+        while events_recv.recv().await.unwrap() != "Disconnected" {}
+        assert_eq!(
+            events_recv.recv().await.unwrap(),
+            "Reconnecting { attempt: 1, delay: 10ms }"
+        );
+        assert_eq!(events_recv.recv().await.unwrap(), "Connected");
+        let second = resilient.handle().await.unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        echo_through(forward.local_addr()).await;
+
+        resilient
+            .disconnect(Disconnect::ByApplication, "", "")
+            .await
+            .unwrap();
+        assert!(resilient.try_handle().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_forward_remote() {
+        let session = connect().await;
+        let mut forward = session.forward_remote("127.0.0.1", 0).await.unwrap();
+        assert_eq!(forward.port(), 2222);
+
+        let (channel, originator) = forward.accept().await.unwrap();
+        assert_eq!(
+            originator,
+            client::OriginatorInfo {
+                address: "10.0.0.1".into(),
+                port: 4444,
+            }
+        );
+        let mut buf = Vec::new();
+        channel.into_stream().read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"forwarded");
+    }
+
+    /// Opens a `forwarded-tcpip` channel for another bind address on the
+    /// same port before the one for the requested address.
+    struct SamePortServer {}
+
+    impl server::Handler for SamePortServer {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn tcpip_forward(
+            &mut self,
+            address: &str,
+            port: &mut u32,
+            session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            let (address, port) = (address.to_string(), *port);
+            let handle = session.handle();
+            tokio::spawn(async move {
+                for address in ["127.0.0.2", &address] {
+                    let channel = handle
+                        .channel_open_forwarded_tcpip(address, port, "10.0.0.1", 4444)
+                        .await
+                        .unwrap();
+                    channel.data(address.as_bytes()).await.unwrap();
+                    channel.eof().await.unwrap();
+                }
+            });
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward_remote_same_port() {
+        let session = connect_to(SamePortServer {}).await;
+        let mut forward = session.forward_remote("127.0.0.1", 2222).await.unwrap();
+        let (channel, _) = forward.accept().await.unwrap();
+        let mut buf = Vec::new();
+        channel.into_stream().read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"127.0.0.1");
+    }
+
+    /// Listens for the `tcpip-forward` requests of the client.
+    #[derive(Default)]
+    struct ListeningServer {
+        forwards: server::forward::RemoteForwards,
+    }
+
+    impl server::Handler for ListeningServer {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn tcpip_forward(
+            &mut self,
+            address: &str,
+            port: &mut u32,
+            session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(self
+                .forwards
+                .listen(session.handle(), address, port)
+                .await
+                .is_ok())
+        }
+
+        async fn cancel_tcpip_forward(
+            &mut self,
+            address: &str,
+            port: u32,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(self.forwards.cancel(address, port))
+        }
+
+        #[cfg(unix)]
+        async fn streamlocal_forward(
+            &mut self,
+            socket_path: &str,
+            session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(self
+                .forwards
+                .listen_unix(session.handle(), socket_path)
+                .is_ok())
+        }
+
+        async fn cancel_streamlocal_forward(
+            &mut self,
+            socket_path: &str,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(self.forwards.cancel_unix(socket_path))
+        }
+
+        #[cfg(unix)]
+        async fn channel_open_direct_streamlocal(
+            &mut self,
+            channel: Channel<server::Msg>,
+            socket_path: &str,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            if !socket_path.ends_with("allowed.sock") {
+                return Ok(false);
+            }
+            let mut stream = tokio::net::UnixStream::connect(socket_path).await?;
+            tokio::spawn(async move {
+                let mut channel = channel.into_stream();
+                tokio::io::copy_bidirectional(&mut stream, &mut channel)
+                    .await
+                    .unwrap();
+            });
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forward_remote_listener() {
+        let session = connect_to(ListeningServer::default()).await;
+        let mut forward = session.forward_remote("localhost", 0).await.unwrap();
+        let port = forward.port() as u16;
+        assert_ne!(port, 0);
+
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let (channel, originator) = forward.accept().await.unwrap();
+        assert_eq!(originator.address, "127.0.0.1");
+        assert_eq!(originator.port, stream.local_addr().unwrap().port() as u32);
+        let mut channel = channel.into_stream();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        channel.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        channel.write_all(b"pong").await.unwrap();
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        // Dropping the forward cancels it.
+        drop(forward);
+        while tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .is_ok()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_forward_remote_unix_listener() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("forwarded.sock");
+        let session = connect_to(ListeningServer::default()).await;
+        let mut forward = session
+            .forward_remote_unix(socket_path.to_str().unwrap())
+            .await
+            .unwrap();
+
+        let mut stream = tokio::net::UnixStream::connect(&socket_path).await.unwrap();
+        let mut channel = forward.accept().await.unwrap().into_stream();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        channel.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // Dropping the forward cancels it, which removes the socket.
+        drop(forward);
+        while socket_path.exists() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_direct_streamlocal() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("allowed.sock");
+        let listener = tokio::net::UnixListener::bind(&socket_path).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+        });
+
+        let session = connect_to(ListeningServer::default()).await;
+        let channel = session
+            .channel_open_direct_streamlocal(socket_path.to_str().unwrap())
+            .await
+            .unwrap();
+        let mut buf = [0; 5];
+        channel.into_stream().read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let denied = dir.path().join("denied.sock");
+        assert!(session
+            .channel_open_direct_streamlocal(denied.to_str().unwrap())
+            .await
+            .is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_forward_remote_unix() {
+        let session = connect().await;
+        let mut forward = session
+            .forward_remote_unix("/run/forwarded.sock")
+            .await
+            .unwrap();
+        let channel = forward.accept().await.unwrap();
+        let mut buf = Vec::new();
+        channel.into_stream().read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"forwarded");
+    }
+
+    #[tokio::test]
+    async fn test_tun() {
+        let session = connect().await;
+        let channel = session
+            .channel_open_tun(tun::TunMode::PointToPoint, tun::TUN_UNIT_ANY)
+            .await
+            .unwrap();
+        let mut tun = tun::TunChannel::new(channel, tun::TunMode::PointToPoint);
+        let packets = [vec![0x45; 20], vec![0x60; 40], vec![0x45; 1500]];
+        for packet in &packets {
+            tun.send(packet).await.unwrap();
+        }
+        for packet in &packets {
+            assert_eq!(&tun.recv().await.unwrap(), packet);
+        }
+        assert!(tun.send(&[0x10; 20]).await.is_err());
+    }
+
+    /// Answers a single request of the forwarded agent.
+    async fn fake_agent<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(mut stream: S) {
+        // The client first binds the agent to the session, which
+        // this agent does not support.
+        let len = stream.read_u32().await.unwrap();
+        let mut bind = vec![0; len as usize];
+        stream.read_exact(&mut bind).await.unwrap();
+        assert!(bind.starts_with(b"\x1b\0\0\0\x18session-bind@openssh.com"));
+        assert_eq!(bind.last(), Some(&1));
+        stream.write_all(&[0, 0, 0, 1, 5]).await.unwrap();
+
+        let mut buf = [0; 7];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"request");
+        stream.write_all(b"response").await.unwrap();
+        stream.shutdown().await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_forward_agent() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent_path = dir.path().join("agent.sock");
+        let agent = tokio::net::UnixListener::bind(&agent_path).unwrap();
+        tokio::spawn(async move { fake_agent(agent.accept().await.unwrap().0).await });
+
+        let (replies, mut replies_recv) = tokio::sync::mpsc::unbounded_channel();
+        let session = connect_to(EchoServer {
+            replies: Some(replies),
+        })
+        .await;
+        let channel = session.channel_open_session().await.unwrap();
+        let _forward = session
+            .forward_agent_to(&channel, &agent_path)
+            .await
+            .unwrap();
+        assert_eq!(replies_recv.recv().await.unwrap(), b"response");
+    }
+
+    #[tokio::test]
+    async fn test_forward_agent_with() {
+        let agent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let agent_addr = agent.local_addr().unwrap();
+        tokio::spawn(async move { fake_agent(agent.accept().await.unwrap().0).await });
+
+        let (replies, mut replies_recv) = tokio::sync::mpsc::unbounded_channel();
+        let session = connect_to(EchoServer {
+            replies: Some(replies),
+        })
+        .await;
+        let channel = session.channel_open_session().await.unwrap();
+        let _forward = session
+            .forward_agent_with(&channel, move || tokio::net::TcpStream::connect(agent_addr))
+            .await
+            .unwrap();
+        assert_eq!(replies_recv.recv().await.unwrap(), b"response");
+    }
+
+    /// Bridges the forwarded agent to a Unix socket.
+    #[cfg(unix)]
+    struct AgentSocketServer {
+        sockets: tokio::sync::mpsc::UnboundedSender<std::path::PathBuf>,
+        forward: Option<server::agent_forward::AgentForward>,
+    }
+
+    #[cfg(unix)]
+    impl server::Handler for AgentSocketServer {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn agent_request(
+            &mut self,
+            _channel: ChannelId,
+            session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            let forward = server::agent_forward::AgentForward::listen(session.handle())?;
+            self.sockets.send(forward.path().to_path_buf()).unwrap();
+            self.forward = Some(forward);
+            Ok(true)
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_forward_agent_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let agent_path = dir.path().join("agent.sock");
+        let agent = tokio::net::UnixListener::bind(&agent_path).unwrap();
+        tokio::spawn(async move { fake_agent(agent.accept().await.unwrap().0).await });
+
+        let (sockets, mut sockets_recv) = tokio::sync::mpsc::unbounded_channel();
+        let session = connect_to(AgentSocketServer {
+            sockets,
+            forward: None,
+        })
+        .await;
+        let channel = session.channel_open_session().await.unwrap();
+        let _forward = session
+            .forward_agent_to(&channel, &agent_path)
+            .await
+            .unwrap();
+
+        let socket = sockets_recv.recv().await.unwrap();
+        let mut stream = tokio::net::UnixStream::connect(&socket).await.unwrap();
+        stream.write_all(b"request").await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"response");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_forward_x11() {
+        let dir = tempfile::tempdir().unwrap();
+        let display = format!("{}/x11:0", dir.path().display());
+        let x11_server = tokio::net::UnixListener::bind(&display).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = x11_server.accept().await.unwrap();
+            let mut buf = [0; 48];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf[6..10], [18, 0, 16, 0]);
+            assert_eq!(&buf[12..30], b"MIT-MAGIC-COOKIE-1");
+            assert_eq!(&buf[32..], [7; 16]);
+            stream.write_all(b"ok").await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+
+        let (replies, mut replies_recv) = tokio::sync::mpsc::unbounded_channel();
+        let session = connect_to(EchoServer {
+            replies: Some(replies),
+        })
+        .await;
+        let channel = session.channel_open_session().await.unwrap();
+        let display = client::x11::X11Display::parse(&display).unwrap();
+        let _forward = session
+            .forward_x11_to(&channel, display, Some(vec![7; 16]))
+            .await
+            .unwrap();
+        assert_eq!(replies_recv.recv().await.unwrap(), b"ok");
+    }
+}
+
+mod jump {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use client::proxy::jump::{Hop, JumpHost};
+
+    use super::*;
+
+    /// Accepts sessions, and connects `direct-tcpip` channels to their
+    /// destination.
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn channel_open_direct_tcpip(
+            &mut self,
+            channel: Channel<server::Msg>,
+            host_to_connect: &str,
+            port_to_connect: u32,
+            _originator_address: &str,
+            _originator_port: u32,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            let port = u16::try_from(port_to_connect).unwrap();
+            let mut stream = TcpStream::connect((host_to_connect, port)).await?;
+            tokio::spawn(async move {
+                let mut channel = channel.into_stream();
+                tokio::io::copy_bidirectional(&mut channel, &mut stream).await
+            });
+            Ok(true)
+        }
+    }
+
+    /// Serve one connection on a new TCP listener.
+    async fn listen() -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            server::run_stream(Arc::new(server_config()), socket, Server {})
+                .await?
+                .await
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_jump_host() {
+        let _ = env_logger::try_init();
+
+        let hop = listen().await;
+        let destination = listen().await;
+        let config = Arc::new(client::Config::default());
+        let stream = JumpHost::new()
+            .hop(Hop::new("127.0.0.1", hop.port()), config.clone(), Client {})
+            .connect(
+                "127.0.0.1",
+                destination.port(),
+                |_, mut handle| async move {
+                    authenticate(&mut handle).await;
+                    Ok(handle)
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut session = client::connect_stream(config, stream, Client {})
+            .await
+            .unwrap();
+        authenticate(&mut session).await;
+        let channel = session.channel_open_session().await.unwrap();
+        channel.close().await.unwrap();
+    }
+}
+
+mod banner {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Client {
+        banners: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl client::Handler for Client {
+        type Error = crate::Error;
+
+        async fn auth_banner(
+            &mut self,
+            banner: &str,
+            language_tag: &str,
+            _session: &mut client::Session,
+        ) -> Result<(), Self::Error> {
+            self.banners
+                .lock()
+                .unwrap()
+                .push((banner.to_owned(), language_tag.to_owned()));
+            Ok(())
+        }
+
+        async fn check_server_key(
+            &mut self,
+            _server_public_key: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn authentication_banner(&mut self) -> Result<Option<String>, Self::Error> {
+            Ok(Some("Authorized use only\r\n".to_owned()))
+        }
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth_banner() {
+        let _ = env_logger::try_init();
+
+        let (socket, _) = serve(server_config(), Server {}).await;
+
+        let banners = Arc::new(Mutex::new(Vec::new()));
+        let client = Client {
+            banners: banners.clone(),
+        };
+        let config = Arc::new(client::Config::default());
+        let mut session = client::connect_stream(config, socket, client)
+            .await
+            .unwrap();
+        assert!(session.auth_banners().is_empty());
+        authenticate(&mut session).await;
+
+        let expected = client::AuthBanner {
+            message: "Authorized use only\r\n".to_owned(),
+            language_tag: String::new(),
+        };
+        assert_eq!(session.auth_banners(), [expected]);
+        assert_eq!(
+            *banners.lock().unwrap(),
+            [("Authorized use only\r\n".to_owned(), String::new())]
+        );
+    }
+
+    struct Unannounced {}
+
+    impl server::Handler for Unannounced {
+        type Error = crate::Error;
+    }
+
+    #[tokio::test]
+    async fn test_config_banner() {
+        let _ = env_logger::try_init();
+
+        let (socket, _) = serve(
+            server::Config {
+                auth_banner: Some("Connections are logged\r\n".to_owned()),
+                ..server_config()
+            },
+            Unannounced {},
+        )
+        .await;
+
+        let banners = Arc::new(Mutex::new(Vec::new()));
+        let client = Client {
+            banners: banners.clone(),
+        };
+        let mut session = client::connect_stream(Arc::new(Default::default()), socket, client)
+            .await
+            .unwrap();
+        assert!(!session.authenticate_none("user").await.unwrap().success());
+        assert_eq!(
+            *banners.lock().unwrap(),
+            [("Connections are logged\r\n".to_owned(), String::new())]
+        );
+    }
+}
+
+mod host_keys {
+    use std::sync::Arc;
+
+    use rand_core::OsRng;
+    use ssh_key::{PrivateKey, PublicKey};
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+    use super::*;
+
+    #[derive(Debug)]
+    enum Event {
+        Announced(Vec<PublicKey>),
+        Proven(Vec<PublicKey>),
+    }
+
+    struct Client {
+        events: UnboundedSender<Event>,
+    }
+
+    impl client::Handler for Client {
+        type Error = crate::Error;
+
+        async fn check_server_key(
+            &mut self,
+            _server_public_key: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn openssh_ext_host_keys_announced(
+            &mut self,
+            keys: Vec<PublicKey>,
+            _session: &mut client::Session,
+        ) -> Result<(), Self::Error> {
+            let _ = self.events.send(Event::Announced(keys));
+            Ok(())
+        }
+
+        async fn openssh_ext_host_keys_proven(
+            &mut self,
+            keys: Vec<PublicKey>,
+            _session: &mut client::Session,
+        ) -> Result<(), Self::Error> {
+            let _ = self.events.send(Event::Proven(keys));
+            Ok(())
+        }
+    }
+
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn auth_succeeded(
+            &mut self,
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            session.announce_host_keys()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_keys_proven() {
+        let _ = env_logger::try_init();
+
+        let host_keys = vec![
+            PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap(),
+            PrivateKey::random(
+                &mut OsRng,
+                ssh_key::Algorithm::Ecdsa {
+                    curve: ssh_key::EcdsaCurve::NistP256,
+                },
+            )
+            .unwrap(),
+        ];
+        let public_keys: Vec<_> = host_keys.iter().map(|k| k.public_key().clone()).collect();
+        let (socket, _) = serve(
+            server::Config {
+                keys: host_keys,
+                ..server_config()
+            },
+            Server {},
+        )
+        .await;
+
+        let (events, mut received) = unbounded_channel();
+        let config = Arc::new(client::Config {
+            update_host_keys: true,
+            ..Default::default()
+        });
+        let mut session = client::connect_stream(config, socket, Client { events })
+            .await
+            .unwrap();
+        authenticate(&mut session).await;
+
+        match received.recv().await.unwrap() {
+            Event::Announced(keys) => assert_eq!(keys, public_keys),
+            e => panic!("unexpected {e:?}"),
+        }
+        match received.recv().await.unwrap() {
+            Event::Proven(keys) => assert_eq!(keys, public_keys),
+            e => panic!("unexpected {e:?}"),
+        }
+    }
+
+    struct KeyRecorder {
+        keys: UnboundedSender<PublicKey>,
+    }
+
+    impl client::Handler for KeyRecorder {
+        type Error = crate::Error;
+
+        async fn check_server_key(
+            &mut self,
+            server_public_key: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<bool, Self::Error> {
+            let _ = self.keys.send(server_public_key.clone());
+            Ok(true)
+        }
+    }
+
+    struct VirtualHost {
+        key: PrivateKey,
+    }
+
+    impl server::Handler for VirtualHost {
+        type Error = crate::Error;
+
+        async fn select_host_key(
+            &mut self,
+            algorithm: &ssh_key::Algorithm,
+            certificate: bool,
+            client_algorithms: &[String],
+        ) -> Result<Option<PrivateKey>, Self::Error> {
+            assert_eq!(*algorithm, ssh_key::Algorithm::Ed25519);
+            assert!(!certificate);
+            assert!(client_algorithms.iter().any(|a| a == "ssh-ed25519"));
+            Ok(Some(self.key.clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_select_host_key() {
+        let _ = env_logger::try_init();
+
+        let default_key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let selected_key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let selected = selected_key.public_key().clone();
+        let handler = VirtualHost { key: selected_key };
+        let (socket, _) = serve(
+            server::Config {
+                keys: vec![default_key],
+                ..server_config()
+            },
+            handler,
+        )
+        .await;
+
+        let (keys, mut received) = unbounded_channel();
+        let _session =
+            client::connect_stream(Arc::new(Default::default()), socket, KeyRecorder { keys })
+                .await
+                .unwrap();
+        assert_eq!(received.recv().await.unwrap(), selected);
+    }
+}
+
+mod ext_info {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    type Received = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+    struct Client {
+        received: Received,
+    }
+
+    impl client::Handler for Client {
+        type Error = crate::Error;
+
+        async fn extension_received(
+            &mut self,
+            name: &str,
+            value: &[u8],
+            _session: &mut client::Session,
+        ) -> Result<(), Self::Error> {
+            self.received
+                .lock()
+                .unwrap()
+                .push((name.to_owned(), value.to_owned()));
+            Ok(())
+        }
+
+        async fn check_server_key(
+            &mut self,
+            _server_public_key: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    struct Server {
+        received: Received,
+    }
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn extension_received(
+            &mut self,
+            name: &str,
+            value: &[u8],
+            _session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            self.received
+                .lock()
+                .unwrap()
+                .push((name.to_owned(), value.to_owned()));
+            Ok(())
+        }
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn auth_succeeded(
+            &mut self,
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            // The extensions of the client arrive before authentication.
+            assert_eq!(
+                session.extension_info().get("client@example.com"),
+                Some(&b"1"[..])
+            );
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extension_info() {
+        let _ = env_logger::try_init();
+
+        let server_received = Received::default();
+        let server = Server {
+            received: server_received.clone(),
+        };
+        let (socket, _) = serve(
+            server::Config {
+                extensions: vec![("server@example.com".into(), b"2".to_vec())],
+                ..server_config()
+            },
+            server,
+        )
+        .await;
+
+        let config = Arc::new(client::Config {
+            extensions: vec![("client@example.com".into(), b"1".to_vec())],
+            ..Default::default()
+        });
+        let client_received = Received::default();
+        let client = Client {
+            received: client_received.clone(),
+        };
+        let mut session = client::connect_stream(config, socket, client)
+            .await
+            .unwrap();
+        authenticate(&mut session).await;
+
+        let info = session.extension_info().await.unwrap();
+        assert!(info.contains("server-sig-algs"));
+        let algs = info.server_sig_algs.as_ref().unwrap();
+        assert!(algs.contains(&ssh_key::Algorithm::Ed25519));
+        assert_eq!(info.get("server@example.com"), Some(&b"2"[..]));
+        assert_eq!(info.ping().as_deref(), Some("0"));
+
+        let names: Vec<_> = client_received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(n, _)| n.clone())
+            .collect();
+        assert_eq!(
+            names,
+            ["server-sig-algs", "ping@openssh.com", "server@example.com"]
+        );
+        assert_eq!(
+            *server_received.lock().unwrap(),
+            [("client@example.com".to_owned(), b"1".to_vec())]
+        );
+    }
+}
+
+mod no_more_sessions {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+
+    struct Server {
+        opened: Arc<AtomicUsize>,
+    }
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            self.opened.fetch_add(1, Ordering::SeqCst);
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_no_more_sessions() {
+        let _ = env_logger::try_init();
+
+        let opened = Arc::new(AtomicUsize::new(0));
+        let server = Server {
+            opened: opened.clone(),
+        };
+        let mut session =
+            connect_with(server_config(), server, Default::default(), Client {}).await;
+        authenticate(&mut session).await;
+
+        let _channel = session.channel_open_session().await.unwrap();
+        session.no_more_sessions().await.unwrap();
+        assert!(matches!(
+            session.channel_open_session().await,
+            Err(Error::ChannelOpenFailure(
+                ChannelOpenFailure::AdministrativelyProhibited
+            ))
+        ));
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_sessions() {
+        let _ = env_logger::try_init();
+
+        let opened = Arc::new(AtomicUsize::new(0));
+        let server = Server {
+            opened: opened.clone(),
+        };
+        let mut session = connect_with(
+            server::Config {
+                max_sessions: Some(2),
+                ..server_config()
+            },
+            server,
+            Default::default(),
+            Client {},
+        )
+        .await;
+        authenticate(&mut session).await;
+
+        let first = session.channel_open_session().await.unwrap();
+        let _second = session.channel_open_session().await.unwrap();
+        assert!(matches!(
+            session.channel_open_session().await,
+            Err(Error::ChannelOpenFailure(
+                ChannelOpenFailure::AdministrativelyProhibited
+            ))
+        ));
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+
+        // Closing a session makes room for another one.
+        first.close().await.unwrap();
+        session.channel_open_session().await.unwrap();
+        assert_eq!(opened.load(Ordering::SeqCst), 3);
+    }
+}
+
+mod ping {
+
+    use super::*;
+
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let _ = env_logger::try_init();
+
+        let server = Server {};
+        let mut session =
+            connect_with(server_config(), server, Default::default(), Client {}).await;
+        authenticate(&mut session).await;
+
+        assert!(session.ping().await.unwrap() < std::time::Duration::from_secs(10));
+        // Pings can be in flight together.
+        let (a, b) = tokio::join!(session.ping(), session.ping());
+        a.unwrap();
+        b.unwrap();
+    }
+}
+
+mod metrics {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::server::metrics::ServerMetrics;
+
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_password(
+            &mut self,
+            _: &str,
+            password: &str,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(if password == "secret" {
+                server::Auth::Accept
+            } else {
+                server::Auth::reject()
+            })
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _: Channel<server::Msg>,
+            _: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<String>>,
+        bytes: Mutex<(usize, usize)>,
+    }
+
+    impl Recorder {
+        fn record(&self, event: String) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    impl ServerMetrics for Recorder {
+        fn connection_opened(&self) {
+            self.record("opened".into())
+        }
+
+        fn connection_closed(&self, _: Duration) {
+            self.record("closed".into())
+        }
+
+        fn auth_succeeded(&self, method: MethodKind) {
+            self.record(format!("success {method:?}"))
+        }
+
+        fn auth_failed(&self, method: MethodKind) {
+            self.record(format!("failure {method:?}"))
+        }
+
+        fn channel_opened(&self) {
+            self.record("channel opened".into())
+        }
+
+        fn channel_closed(&self) {
+            self.record("channel closed".into())
+        }
+
+        fn bytes_received(&self, bytes: usize) {
+            self.bytes.lock().unwrap().0 += bytes;
+        }
+
+        fn bytes_sent(&self, bytes: usize) {
+            self.bytes.lock().unwrap().1 += bytes;
+        }
+
+        fn kex_completed(&self, _: kex::Name, _: Duration) {
+            self.record("kex".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let _ = env_logger::try_init();
+
+        let recorder = Arc::new(Recorder::default());
+        let (socket, server) = serve(
+            server::Config {
+                auth_rejection_time: Duration::from_millis(10),
+                metrics: Some(recorder.clone()),
+                ..server_config()
+            },
+            Server {},
+        )
+        .await;
+
+        let mut session = client::connect_stream(Arc::new(Default::default()), socket, Client {})
+            .await
+            .unwrap();
+        assert!(!session
+            .authenticate_password("alice", "wrong")
+            .await
+            .unwrap()
+            .success());
+        assert!(session
+            .authenticate_password("alice", "secret")
+            .await
+            .unwrap()
+            .success());
+        let channel = session.channel_open_session().await.unwrap();
+        channel.close().await.unwrap();
+        session
+            .disconnect(Disconnect::ByApplication, "", "")
+            .await
+            .unwrap();
+        let _ = server.await.unwrap();
+
+        assert_eq!(
+            *recorder.events.lock().unwrap(),
+            [
+                "opened",
+                "kex",
+                "failure Password",
+                "success Password",
+                "channel opened",
+                "channel closed",
+                "closed"
+            ]
+        );
+        let (received, sent) = *recorder.bytes.lock().unwrap();
+        assert!(received > 0 && sent > 0);
+    }
+}
+
+mod recording {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::server::recording::{Asciicast, Recorder, Recording};
+
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_none(&mut self, _: &str) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _: Channel<server::Msg>,
+            _: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn exec_request(
+            &mut self,
+            channel: ChannelId,
+            _: &[u8],
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            session.data(channel, CryptoVec::from_slice(b"hello\n"))?;
+            session.extended_data(channel, 1, CryptoVec::from_slice(b"done\n"))?;
+            session.close(channel)?;
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Casts(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Casts {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Recorder for Casts {
+        fn start(&self, user: &str, _: ChannelId) -> Option<Box<dyn Recording>> {
+            assert_eq!(user, "alice");
+            Some(Box::new(Asciicast::new(self.clone())))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recording() {
+        let _ = env_logger::try_init();
+
+        let casts = Casts::default();
+        let (socket, server) = serve(
+            server::Config {
+                auth_rejection_time: Duration::from_millis(10),
+                recorder: Some(Arc::new(casts.clone())),
+                ..server_config()
+            },
+            Server {},
+        )
+        .await;
+
+        let mut session = client::connect_stream(Arc::new(Default::default()), socket, Client {})
+            .await
+            .unwrap();
+        assert!(session.authenticate_none("alice").await.unwrap().success());
+        let mut channel = session.channel_open_session().await.unwrap();
+        channel
+            .request_pty(false, "vt100", 132, 43, 0, 0, &[])
+            .await
+            .unwrap();
+        channel.exec(false, "uptime").await.unwrap();
+        while let Some(msg) = channel.wait().await {
+            if let ChannelMsg::Close = msg {
+                break;
+            }
+        }
+        session
+            .disconnect(Disconnect::ByApplication, "", "")
+            .await
+            .unwrap();
+        let _ = server.await.unwrap();
+
+        let cast = String::from_utf8(casts.0.lock().unwrap().clone()).unwrap();
+        let mut lines = cast.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with("{\"version\": 2, \"width\": 132, \"height\": 43, "));
+        assert!(header.ends_with(", \"command\": \"uptime\", \"env\": {\"TERM\": \"vt100\"}}"));
+        let events: Vec<_> = lines.map(|line| line.split_once(", ").unwrap().1).collect();
+        assert_eq!(events, ["\"o\", \"hello\\n\"]", "\"o\", \"done\\n\"]"]);
+    }
+}
+
+mod global_request {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    struct Client {}
+
+    impl client::Handler for Client {
+        type Error = crate::Error;
+
+        async fn check_server_key(
+            &mut self,
+            _server_public_key: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn global_request(
+            &mut self,
+            name: &str,
+            want_reply: bool,
+            payload: &[u8],
+            _session: &mut client::Session,
+        ) -> Result<Option<Vec<u8>>, Self::Error> {
+            assert!(want_reply);
+            Ok((name == "client@example.com").then(|| payload.to_vec()))
+        }
+    }
+
+    type Reply = Arc<Mutex<Option<oneshot::Sender<Result<Option<Vec<u8>>, Error>>>>>;
+
+    struct Server {
+        reply: Reply,
+    }
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn auth_succeeded(
+            &mut self,
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            let handle = session.handle();
+            let reply = self.reply.lock().unwrap().take().unwrap();
+            tokio::spawn(async move {
+                let result = handle
+                    .global_request("client@example.com", true, b"from server".to_vec())
+                    .await;
+                let _ = reply.send(result);
+            });
+            Ok(())
+        }
+
+        async fn global_request(
+            &mut self,
+            name: &str,
+            _want_reply: bool,
+            payload: &[u8],
+            _session: &mut server::Session,
+        ) -> Result<Option<Vec<u8>>, Self::Error> {
+            Ok((name == "server@example.com").then(|| [payload, b"!"].concat()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_global_request() {
+        let _ = env_logger::try_init();
+
+        let (reply_send, reply_recv) = oneshot::channel();
+        let server = Server {
+            reply: Arc::new(Mutex::new(Some(reply_send))),
+        };
+        let mut session = connect_with(
+            server::Config {
+                global_request_replies: [
+                    ("keepalive@openssh.com".to_string(), false),
+                    ("fixed@example.com".to_string(), true),
+                ]
+                .into(),
+                ..server_config()
+            },
+            server,
+            Default::default(),
+            Client {},
+        )
+        .await;
+        authenticate(&mut session).await;
+
+        assert_eq!(
+            session
+                .global_request("server@example.com", true, b"hi".to_vec())
+                .await
+                .unwrap(),
+            Some(b"hi!".to_vec())
+        );
+        assert!(matches!(
+            session
+                .global_request("unknown@example.com", true, vec![])
+                .await,
+            Err(Error::RequestDenied)
+        ));
+        assert_eq!(
+            session
+                .global_request("unknown@example.com", false, vec![])
+                .await
+                .unwrap(),
+            None
+        );
+        // Requests without a reply don't shift the replies of later ones.
+        assert_eq!(
+            session
+                .global_request("server@example.com", true, vec![])
+                .await
+                .unwrap(),
+            Some(b"!".to_vec())
+        );
+
+        // Fixed replies are sent without calling the handler.
+        assert!(matches!(
+            session
+                .global_request("keepalive@openssh.com", true, vec![])
+                .await,
+            Err(Error::RequestDenied)
+        ));
+        assert_eq!(
+            session
+                .global_request("fixed@example.com", true, vec![])
+                .await
+                .unwrap(),
+            Some(vec![])
+        );
+
+        assert_eq!(
+            reply_recv.await.unwrap().unwrap(),
+            Some(b"from server".to_vec())
+        );
+    }
+}
+
+mod custom_channel {
+    use std::sync::Arc;
+
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    struct Client {
+        opened: mpsc::UnboundedSender<(String, Vec<u8>)>,
+    }
+
+    impl client::Handler for Client {
+        type Error = crate::Error;
+
+        async fn check_server_key(
+            &mut self,
+            _server_public_key: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn should_accept_unknown_server_channel(
+            &mut self,
+            _id: ChannelId,
+            channel_type: &str,
+        ) -> bool {
+            channel_type == "client@example.com"
+        }
+
+        async fn server_channel_open_unknown(
+            &mut self,
+            _channel: Channel<client::Msg>,
+            channel_type: &str,
+            data: &[u8],
+            _session: &mut client::Session,
+        ) -> Result<(), Self::Error> {
+            let _ = self.opened.send((channel_type.to_owned(), data.to_owned()));
+            Ok(())
+        }
+    }
+
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_unknown(
+            &mut self,
+            channel: Channel<server::Msg>,
+            channel_type: &str,
+            data: &[u8],
+            session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            if channel_type != "echo@example.com" {
+                return Ok(false);
+            }
+            // Echo the type-specific data, and open a channel back.
+            let data = data.to_vec();
+            let handle = session.handle();
+            tokio::spawn(async move {
+                channel.data(&data[..]).await.unwrap();
+                channel.eof().await.unwrap();
+                handle
+                    .channel_open_custom("client@example.com", b"back".to_vec())
+                    .await
+                    .unwrap();
+            });
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_channel() {
+        let _ = env_logger::try_init();
+
+        let (socket, _) = serve(server_config(), Server {}).await;
+
+        let config = Arc::new(client::Config::default());
+        let (opened, mut opened_recv) = mpsc::unbounded_channel();
+        let mut session = client::connect_stream(config, socket, Client { opened })
+            .await
+            .unwrap();
+        authenticate(&mut session).await;
+
+        assert!(matches!(
+            session
+                .channel_open_custom("unknown@example.com", vec![])
+                .await,
+            Err(Error::ChannelOpenFailure(
+                ChannelOpenFailure::UnknownChannelType
+            ))
+        ));
+
+        let mut channel = session
+            .channel_open_custom("echo@example.com", b"hello".to_vec())
+            .await
+            .unwrap();
+        let mut echoed = Vec::new();
+        while let Some(msg) = channel.wait().await {
+            match msg {
+                ChannelMsg::Data { data } => echoed.extend_from_slice(&data),
+                ChannelMsg::Eof => break,
+                _ => {}
+            }
+        }
+        assert_eq!(echoed, b"hello");
+
+        assert_eq!(
+            opened_recv.recv().await.unwrap(),
+            ("client@example.com".to_owned(), b"back".to_vec())
+        );
+    }
+}
+
+mod auth_result {
+    use std::sync::Arc;
+
+    use keys::PrivateKeyWithHashAlg;
+    use rand_core::OsRng;
+    use ssh_key::PrivateKey;
+
+    use super::*;
+
+    /// Requires a key, then a password.
+    struct TwoFactorServer {}
+
+    impl server::Handler for TwoFactorServer {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Reject {
+                proceed_with_methods: Some(MethodSet::from(&[MethodKind::Password][..])),
+                partial_success: true,
+            })
+        }
+
+        async fn auth_password(
+            &mut self,
+            _: &str,
+            password: &str,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(if password == "secret" {
+                server::Auth::Accept
+            } else {
+                server::Auth::reject()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_partial_success() {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server::Config {
+                methods: MethodSet::from(&[MethodKind::PublicKey, MethodKind::Password][..]),
+                ..server_config()
+            },
+            TwoFactorServer {},
+            Default::default(),
+            Client {},
+        )
+        .await;
+
+        let result = session
+            .authenticate_password("user", "wrong")
+            .await
+            .unwrap();
+        assert!(!result.success());
+        assert!(!result.partial_success());
+        // The server no longer offers the method that failed.
+        assert_eq!(
+            result.remaining_methods().map(|m| m.to_vec()),
+            Some(vec![MethodKind::PublicKey])
+        );
+
+        let client_key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let result = session
+            .authenticate_publickey(
+                "user",
+                PrivateKeyWithHashAlg::new(Arc::new(client_key), None),
+            )
+            .await
+            .unwrap();
+        assert!(result.partial_success());
+        assert_eq!(
+            result.remaining_methods().map(|m| m.to_vec()),
+            Some(vec![MethodKind::Password])
+        );
+
+        let result = session
+            .authenticate_password("user", "secret")
+            .await
+            .unwrap();
+        assert!(result.success());
+        assert_eq!(result.remaining_methods(), None);
+    }
+
+    /// Accepts any public key, and the "secret" password.
+    struct AnyMethodServer {}
+
+    impl server::Handler for AnyMethodServer {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn auth_password(
+            &mut self,
+            _: &str,
+            password: &str,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(if password == "secret" {
+                server::Auth::Accept
+            } else {
+                server::Auth::reject()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authentication_methods() {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server::Config {
+                auth_rejection_time: std::time::Duration::ZERO,
+                authentication_methods: vec![vec![MethodKind::PublicKey, MethodKind::Password]],
+                ..server_config()
+            },
+            AnyMethodServer {},
+            Default::default(),
+            Client {},
+        )
+        .await;
+
+        // The password alone is not enough, and must come second.
+        let result = session
+            .authenticate_password("user", "secret")
+            .await
+            .unwrap();
+        assert!(!result.success());
+        assert!(!result.partial_success());
+        assert_eq!(
+            result.remaining_methods().map(|m| m.to_vec()),
+            Some(vec![MethodKind::PublicKey])
+        );
+
+        let client_key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let result = session
+            .authenticate_publickey(
+                "user",
+                PrivateKeyWithHashAlg::new(Arc::new(client_key), None),
+            )
+            .await
+            .unwrap();
+        assert!(result.partial_success());
+        assert_eq!(
+            result.remaining_methods().map(|m| m.to_vec()),
+            Some(vec![MethodKind::Password])
+        );
+
+        let result = session
+            .authenticate_password("user", "secret")
+            .await
+            .unwrap();
+        assert!(result.success());
+    }
+
+    #[tokio::test]
+    async fn test_max_auth_attempts() {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server::Config {
+                auth_rejection_time: std::time::Duration::ZERO,
+                max_auth_attempts: 2,
+                methods: MethodSet::from(&[MethodKind::Password][..]),
+                ..server_config()
+            },
+            TwoFactorServer {},
+            Default::default(),
+            Client {},
+        )
+        .await;
+        // Probing with "none" does not count.
+        assert!(!session.authenticate_none("user").await.unwrap().success());
+        let result = session.authenticate_password("user", "wrong").await;
+        assert!(!result.unwrap().success());
+        // The client is disconnected after the second failure.
+        let result = session.authenticate_password("user", "wrong").await;
+        assert!(result.map_or(true, |r| !r.success()));
+        assert!(session
+            .authenticate_password("user", "secret")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_max_auth_attempts_none() {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server::Config {
+                auth_rejection_time: std::time::Duration::ZERO,
+                max_auth_attempts: 2,
+                methods: MethodSet::from(&[MethodKind::Password][..]),
+                ..server_config()
+            },
+            TwoFactorServer {},
+            Default::default(),
+            Client {},
+        )
+        .await;
+        // Only the first "none" is free: the client is disconnected after
+        // the third.
+        for _ in 0..3 {
+            let result = session.authenticate_none("user").await;
+            assert!(result.map_or(true, |r| !r.success()));
+        }
+        assert!(session
+            .authenticate_password("user", "secret")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_login_grace_time() {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server::Config {
+                login_grace_time: Some(std::time::Duration::from_millis(100)),
+                methods: MethodSet::from(&[MethodKind::Password][..]),
+                ..server_config()
+            },
+            TwoFactorServer {},
+            Default::default(),
+            Client {},
+        )
+        .await;
+        tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+        assert!(session
+            .authenticate_password("user", "secret")
+            .await
+            .is_err());
+    }
+
+    impl server::Server for TwoFactorServer {
+        type Handler = Self;
+
+        fn new_client(&mut self, _: Option<std::net::SocketAddr>) -> Self {
+            TwoFactorServer {}
+        }
+    }
+
+    #[tokio::test]
+    async fn test_per_source_penalties() {
+        use server::Server as _;
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server::Config {
+            auth_rejection_time: std::time::Duration::ZERO,
+            per_source_penalties: Some(server::penalties::PerSourcePenalties {
+                auth_failure: std::time::Duration::from_secs(60),
+                min: std::time::Duration::from_secs(60),
+                ..Default::default()
+            }),
+            methods: MethodSet::from(&[MethodKind::Password][..]),
+            ..server_config()
+        });
+        let socket = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move { TwoFactorServer {}.run_on_socket(config, &socket).await });
+
+        let client_config = Arc::new(client::Config::default());
+        let mut session = client::connect(client_config.clone(), addr, Client {})
+            .await
+            .unwrap();
+        let result = session.authenticate_password("user", "wrong").await;
+        assert!(!result.unwrap().success());
+        drop(session);
+
+        // The penalty applies once the server notices the disconnection.
+        let mut refused = false;
+        for _ in 0..50 {
+            if client::connect(client_config.clone(), addr, Client {})
+                .await
+                .is_err()
+            {
+                refused = true;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(refused);
+    }
+
+    #[tokio::test]
+    async fn test_source_filter() {
+        use server::source_filter::SourceFilter;
+        use server::Server as _;
+        use tokio::io::AsyncReadExt;
+        let _ = env_logger::try_init();
+
+        let run = |filter: SourceFilter| async move {
+            let config = Arc::new(server::Config {
+                source_filter: Some(filter),
+                ..server_config()
+            });
+            let socket = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = socket.local_addr().unwrap();
+            tokio::spawn(async move { TwoFactorServer {}.run_on_socket(config, &socket).await });
+            addr
+        };
+
+        let addr = run(SourceFilter::new().deny("127.0.0.0/8".parse().unwrap())).await;
+        // The connection is closed before the server sends its banner.
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+
+        let addr = run(SourceFilter::new().check(|source| !source.is_loopback())).await;
+        let client_config = Arc::new(client::Config::default());
+        assert!(client::connect(client_config.clone(), addr, Client {})
+            .await
+            .is_err());
+
+        let addr = run(SourceFilter::new().allow("127.0.0.1".parse().unwrap())).await;
+        client::connect(client_config, addr, Client {})
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_probe_with_none() {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server::Config {
+                methods: MethodSet::from(&[MethodKind::PublicKey, MethodKind::Password][..]),
+                ..server_config()
+            },
+            TwoFactorServer {},
+            Default::default(),
+            Client {},
+        )
+        .await;
+        let result = session.authenticate_none("user").await.unwrap();
+        assert!(!result.success());
+        assert!(!result.partial_success());
+        assert_eq!(
+            result.remaining_methods().map(|m| m.to_vec()),
+            Some(vec![MethodKind::PublicKey, MethodKind::Password])
+        );
+        assert!(session
+            .authenticate_password("user", "secret")
+            .await
+            .unwrap()
+            .success());
+    }
+
+    /// Accepts a single key, on its own or with a certificate.
+    struct OneKeyServer {
+        key: ssh_key::PublicKey,
+        signed: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl server::Handler for OneKeyServer {
+        type Error = crate::Error;
+
+        async fn auth_publickey_offered(
+            &mut self,
+            _: &str,
+            key: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(if key.key_data() == self.key.key_data() {
+                server::Auth::Accept
+            } else {
+                server::Auth::reject()
+            })
+        }
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            key: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            self.signed
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.auth_publickey_offered("", key).await
+        }
+
+        async fn auth_openssh_certificate(
+            &mut self,
+            _: &str,
+            cert: &ssh_key::Certificate,
+        ) -> Result<server::Auth, Self::Error> {
+            self.signed
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(if cert.public_key() == self.key.key_data() {
+                server::Auth::Accept
+            } else {
+                server::Auth::reject()
+            })
+        }
+    }
+
+    async fn connect_one_key(
+        key: ssh_key::PublicKey,
+    ) -> (client::Handle<Client>, Arc<std::sync::atomic::AtomicUsize>) {
+        let signed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server = OneKeyServer {
+            key,
+            signed: signed.clone(),
+        };
+
+        let (socket, _) = serve(server_config(), server).await;
+
+        let config = Arc::new(client::Config::default());
+        let session = client::connect_stream(config, socket, Client {})
+            .await
+            .unwrap();
+        (session, signed)
+    }
+
+    #[tokio::test]
+    async fn test_identities() {
+        let _ = env_logger::try_init();
+
+        let wrong = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let right = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let (mut session, signed) = connect_one_key(right.public_key().clone()).await;
+        let result = session
+            .authenticate_identities(
+                "user",
+                [
+                    client::Identity::new(Arc::new(wrong)),
+                    client::Identity::new(Arc::new(right)),
+                ],
+            )
+            .await
+            .unwrap();
+        assert!(result.success());
+        // Only the accepted key was asked to sign.
+        assert_eq!(signed.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_identity_with_certificate() {
+        let _ = env_logger::try_init();
+
+        let key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let ca = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let mut builder = ssh_key::certificate::Builder::new_with_random_nonce(
+            &mut OsRng,
+            key.public_key().key_data().clone(),
+            0,
+            u64::MAX >> 2,
+        )
+        .unwrap();
+        builder.valid_principal("user").unwrap();
+        let cert = builder.sign(&ca).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("id_ed25519");
+        std::fs::write(&path, key.to_openssh(ssh_key::LineEnding::LF).unwrap()).unwrap();
+        let alone = client::Identity::load(&path, None).unwrap();
+        assert!(alone.certificate.is_none());
+        std::fs::write(
+            dir.path().join("id_ed25519-cert.pub"),
+            cert.to_openssh().unwrap(),
+        )
+        .unwrap();
+        let identity = client::Identity::load(&path, None).unwrap();
+        assert_eq!(identity.certificate, Some(cert));
+
+        let (mut session, signed) = connect_one_key(key.public_key().clone()).await;
+        assert!(session
+            .authenticate_identities("user", [identity])
+            .await
+            .unwrap()
+            .success());
+        assert_eq!(signed.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[derive(Clone)]
+    struct Agent {}
+
+    impl crate::keys::agent::server::Agent for Agent {}
+
+    #[tokio::test]
+    async fn test_agent_with_filter() {
+        let _ = env_logger::try_init();
+
+        let (client_stream, agent_stream) = tokio::io::duplex(65536);
+        tokio::spawn(crate::keys::agent::server::serve(
+            futures::stream::iter([Ok::<_, std::io::Error>(agent_stream)]),
+            Agent {},
+        ));
+        let mut agent = crate::keys::agent::client::AgentClient::connect(client_stream);
+        let wrong = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let right = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        agent.add_identity(&wrong, &[]).await.unwrap();
+        agent.add_identity(&right, &[]).await.unwrap();
+
+        let (mut session, signed) = connect_one_key(right.public_key().clone()).await;
+        let only_wrong = client::AgentFilter {
+            fingerprint: Some(wrong.public_key().fingerprint(ssh_key::HashAlg::Sha256)),
+            ..Default::default()
+        };
+        let result = session
+            .authenticate_with_agent("user", &mut agent, &only_wrong)
+            .await
+            .unwrap();
+        assert!(!result.success());
+        let only_certificates = client::AgentFilter {
+            certificates: Some(true),
+            ..Default::default()
+        };
+        let result = session
+            .authenticate_with_agent("user", &mut agent, &only_certificates)
+            .await
+            .unwrap();
+        assert!(!result.success());
+        assert_eq!(signed.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        let result = session
+            .authenticate_with_agent("user", &mut agent, &client::AgentFilter::default())
+            .await
+            .unwrap();
+        assert!(result.success());
+        assert_eq!(signed.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_agent_session_bind() {
+        let _ = env_logger::try_init();
+
+        let dir = tempfile::tempdir().unwrap();
+        let agent_path = dir.path().join("agent");
+        let mut ssh_agent = tokio::process::Command::new("ssh-agent")
+            .arg("-a")
+            .arg(&agent_path)
+            .arg("-D")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        // The socket may exist before ssh-agent listens on it.
+        let mut agent = loop {
+            match crate::keys::agent::client::AgentClient::connect_uds(&agent_path).await {
+                Ok(agent) => break agent,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        let key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        agent.add_identity(&key, &[]).await.unwrap();
+
+        let (mut session, _) = connect_one_key(key.public_key().clone()).await;
+        let bind = session.session_bind().await.unwrap();
+        assert_eq!(bind.session_id, session.session_id().await.unwrap());
+        // The agent checks the signature of the session identifier.
+        let mut forged = bind.clone();
+        *forged.session_id.first_mut().unwrap() ^= 1;
+        assert!(agent.bind_session(&forged, false).await.is_err());
+        agent.bind_session(&bind, false).await.unwrap();
+
+        assert!(session
+            .authenticate_with_agent("user", &mut agent, &client::AgentFilter::default())
+            .await
+            .unwrap()
+            .success());
+
+        ssh_agent.kill().await.unwrap();
+        ssh_agent.wait().await.unwrap();
+    }
+
+    /// Signs like an agent would, with a key held outside of the
+    /// session.
+    struct KeySigner(PrivateKey);
+
+    impl auth::Signer for KeySigner {
+        type Error = auth::AgentAuthError;
+
+        async fn auth_publickey_sign(
+            &mut self,
+            _: &ssh_key::PublicKey,
+            _: Option<ssh_key::HashAlg>,
+            mut to_sign: CryptoVec,
+        ) -> Result<CryptoVec, Self::Error> {
+            let signature: ssh_key::Signature =
+                signature::Signer::try_sign(&self.0, &to_sign).unwrap();
+            let signature = crate::helpers::EncodedExt::encoded(&signature).unwrap();
+            ssh_encoding::Encode::encode(signature.as_slice(), &mut to_sign).unwrap();
+            Ok(to_sign)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_certificate_with_signer() {
+        let _ = env_logger::try_init();
+
+        let key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let ca = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let mut builder = ssh_key::certificate::Builder::new_with_random_nonce(
+            &mut OsRng,
+            key.public_key().key_data().clone(),
+            0,
+            u64::MAX >> 2,
+        )
+        .unwrap();
+        builder.valid_principal("user").unwrap();
+        let cert = builder.sign(&ca).unwrap();
+
+        let (mut session, signed) = connect_one_key(key.public_key().clone()).await;
+        let result = session
+            .authenticate_certificate_with("user", cert, &mut KeySigner(key))
+            .await
+            .unwrap();
+        assert!(result.success());
+        assert_eq!(signed.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    struct Credentials {
+        calls: Vec<&'static str>,
+    }
+
+    impl client::Authenticator for Credentials {
+        async fn keys(&mut self) -> Vec<PrivateKeyWithHashAlg> {
+            self.calls.push("keys");
+            let key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+            vec![PrivateKeyWithHashAlg::new(Arc::new(key), None)]
+        }
+
+        async fn password(&mut self) -> Option<String> {
+            self.calls.push("password");
+            Some("secret".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server::Config {
+                methods: MethodSet::from(&[MethodKind::PublicKey, MethodKind::Password][..]),
+                ..server_config()
+            },
+            TwoFactorServer {},
+            Default::default(),
+            Client {},
+        )
+        .await;
+        let mut credentials = Credentials { calls: Vec::new() };
+        let result = session
+            .authenticate("user", &mut credentials)
+            .await
+            .unwrap();
+        assert!(result.success());
+        // The key first, then the password once the key is accepted.
+        assert_eq!(credentials.calls, ["keys", "password"]);
+    }
+}
+
+mod keyboard_interactive {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    /// Asks for a password, then for a one-time code.
+    struct TwoFactorServer {
+        round: usize,
+    }
+
+    impl server::Handler for TwoFactorServer {
+        type Error = crate::Error;
+
+        async fn auth_keyboard_interactive<'a>(
+            &'a mut self,
+            _user: &str,
+            _submethods: &str,
+            response: Option<server::Response<'a>>,
+        ) -> Result<server::Auth, Self::Error> {
+            let responses = response
+                .map(|r| r.map(|b| b.to_vec()).collect::<Vec<_>>())
+                .unwrap_or_default();
+            self.round += 1;
+            Ok(match (self.round, &responses[..]) {
+                (1, []) => server::Auth::Partial {
+                    name: Cow::Borrowed("login"),
+                    instructions: Cow::Borrowed("Enter your password"),
+                    prompts: Cow::Owned(vec![(Cow::Borrowed("Password: "), false)]),
+                },
+                (2, [password]) if password == b"secret" => server::Auth::Partial {
+                    name: Cow::Borrowed("otp"),
+                    instructions: Cow::Borrowed(""),
+                    prompts: Cow::Owned(vec![(Cow::Borrowed("Code: "), true)]),
+                },
+                (3, [code]) if code == b"123456" => server::Auth::Accept,
+                _ => server::Auth::reject(),
+            })
+        }
+    }
+
+    async fn authenticate(password: &'static str) -> (client::AuthResult, Vec<client::Prompt>) {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server_config(),
+            TwoFactorServer { round: 0 },
+            Default::default(),
+            Client {},
+        )
+        .await;
+        let mut seen = Vec::new();
+        let result = session
+            .authenticate_keyboard_interactive("user", None, |_, _, prompts| {
+                seen.extend(prompts.iter().cloned());
+                let responses = prompts
+                    .iter()
+                    .map(|p| match p.prompt.as_str() {
+                        "Password: " => password.to_string(),
+                        _ => "123456".to_string(),
+                    })
+                    .collect();
+                async move { Ok::<_, crate::Error>(responses) }
+            })
+            .await
+            .unwrap();
+        (result, seen)
+    }
+
+    #[tokio::test]
+    async fn test_multiple_rounds() {
+        let (result, prompts) = authenticate("secret").await;
+        assert!(result.success());
+        assert_eq!(
+            prompts,
+            vec![
+                client::Prompt {
+                    prompt: "Password: ".into(),
+                    echo: false,
+                },
+                client::Prompt {
+                    prompt: "Code: ".into(),
+                    echo: true,
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejected() {
+        let (result, prompts) = authenticate("wrong").await;
+        assert!(!result.success());
+        assert_eq!(prompts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_number_of_responses() {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server_config(),
+            TwoFactorServer { round: 0 },
+            Default::default(),
+            Client {},
+        )
+        .await;
+        let response = session
+            .authenticate_keyboard_interactive_start("user", None)
+            .await
+            .unwrap();
+        assert!(matches!(
+            response,
+            client::KeyboardInteractiveAuthResponse::InfoRequest { ref prompts, .. }
+                if prompts.len() == 1
+        ));
+        let response = session
+            .authenticate_keyboard_interactive_respond(vec!["secret".into(), "secret".into()])
+            .await
+            .unwrap();
+        assert!(matches!(
+            response,
+            client::KeyboardInteractiveAuthResponse::Failure { .. }
+        ));
+    }
+}
+
+mod hostbased {
+    use std::sync::Arc;
+
+    use keys::PrivateKeyWithHashAlg;
+    use rand_core::OsRng;
+    use ssh_key::{PrivateKey, PublicKey};
+
+    use super::*;
+    use crate::client::known_hosts::{HostKeyStatus, KnownHosts};
+
+    struct Server {
+        known_hosts: KnownHosts,
+    }
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_hostbased(
+            &mut self,
+            user: &str,
+            client_host: &str,
+            client_user: &str,
+            key: &PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            let known = matches!(
+                self.known_hosts.check(client_host, 22, key),
+                HostKeyStatus::Known { .. }
+            );
+            Ok(if known && user == client_user {
+                server::Auth::Accept
+            } else {
+                server::Auth::reject()
+            })
+        }
+    }
+
+    async fn authenticate(host_key: PrivateKey, known_key: &PublicKey) -> bool {
+        let _ = env_logger::try_init();
+
+        let known_hosts = KnownHosts::parse(&format!(
+            "client.example.com {}\n",
+            known_key.to_openssh().unwrap()
+        ));
+        let mut session = connect_with(
+            server_config(),
+            Server { known_hosts },
+            Default::default(),
+            Client {},
+        )
+        .await;
+        session
+            .authenticate_hostbased(
+                "alice",
+                PrivateKeyWithHashAlg::new(Arc::new(host_key), None),
+                "client.example.com.",
+                "alice",
+            )
+            .await
+            .unwrap()
+            .success()
+    }
+
+    #[tokio::test]
+    async fn test_hostbased() {
+        let host_key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let public_key = host_key.public_key().clone();
+        assert!(authenticate(host_key, &public_key).await);
+    }
+
+    #[tokio::test]
+    async fn test_hostbased_unknown_key() {
+        let host_key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let other = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        assert!(!authenticate(host_key, other.public_key()).await);
+    }
+}
+
+mod revoked_keys {
+    use std::sync::Arc;
+
+    use keys::PrivateKeyWithHashAlg;
+    use rand_core::OsRng;
+    use ssh_key::PrivateKey;
+
+    use super::*;
+    use crate::server::revoked_keys::{Krl, RevokedKeys};
+
+    /// Accepts any key.
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+    }
+
+    async fn authenticate(revoked_keys: Arc<RevokedKeys>, key: &PrivateKey) -> bool {
+        let _ = env_logger::try_init();
+
+        let mut session = connect_with(
+            server::Config {
+                revoked_keys: Some(revoked_keys),
+                ..server_config()
+            },
+            Server {},
+            Default::default(),
+            Client {},
+        )
+        .await;
+        session
+            .authenticate_publickey(
+                "alice",
+                PrivateKeyWithHashAlg::new(Arc::new(key.clone()), None),
+            )
+            .await
+            .unwrap()
+            .success()
+    }
+
+    #[tokio::test]
+    async fn test_revoked_keys() {
+        let key = PrivateKey::random(&mut OsRng, ssh_key::Algorithm::Ed25519).unwrap();
+        let revoked_keys = Arc::new(RevokedKeys::default());
+        assert!(authenticate(revoked_keys.clone(), &key).await);
+
+        // The list is replaced while the server runs.
+        let mut krl = Krl::new();
+        krl.revoke_key(key.public_key());
+        revoked_keys.replace(krl);
+        assert!(!authenticate(revoked_keys, &key).await);
+    }
+}
+
+#[cfg(feature = "gssapi")]
+mod gssapi {
+    use super::*;
+    use crate::client::gssapi::{GssapiContext, GssapiStep};
+    use crate::server::gssapi::GssapiAcceptor;
+
+    const MECHANISM: &[u8] = &[0x06, 0x01, 0x2a];
+
+    /// Integrity codes of a test mechanism, keyed by a shared secret.
+    fn integrity_code(key: u8, message: &[u8]) -> Vec<u8> {
+        message.iter().map(|b| b ^ key).collect()
+    }
+
+    struct Initiator {
+        key: u8,
+    }
+
+    impl GssapiContext for Initiator {
+        fn mechanisms(&self) -> Vec<Vec<u8>> {
+            vec![vec![0x06, 0x01, 0x2b], MECHANISM.to_vec()]
+        }
+
+        fn step(
+            &mut self,
+            mechanism: &[u8],
+            input: Option<&[u8]>,
+        ) -> Result<GssapiStep, crate::Error> {
+            assert_eq!(mechanism, MECHANISM);
+            Ok(match input {
+                None => GssapiStep {
+                    token: Some(b"alice@EXAMPLE.COM".to_vec()),
+                    complete: false,
+                },
+                Some(b"established") => GssapiStep {
+                    token: None,
+                    complete: true,
+                },
+                Some(_) => return Err(crate::Error::Gssapi("unexpected token".into())),
+            })
+        }
+
+        fn get_mic(&mut self, message: &[u8]) -> Result<Vec<u8>, crate::Error> {
+            Ok(integrity_code(self.key, message))
+        }
+    }
+
+    #[derive(Default)]
+    struct Acceptor {
+        principal: Option<String>,
+    }
+
+    impl GssapiAcceptor for Acceptor {
+        fn mechanisms(&self) -> Vec<Vec<u8>> {
+            vec![MECHANISM.to_vec()]
+        }
+
+        fn step(&mut self, mechanism: &[u8], input: &[u8]) -> Result<GssapiStep, crate::Error> {
+            assert_eq!(mechanism, MECHANISM);
+            self.principal = Some(String::from_utf8_lossy(input).into_owned());
+            Ok(GssapiStep {
+                token: Some(b"established".to_vec()),
+                complete: true,
+            })
+        }
+
+        fn verify_mic(&mut self, message: &[u8], mic: &[u8]) -> Result<bool, crate::Error> {
+            Ok(integrity_code(7, message) == mic)
+        }
+
+        fn source_name(&self) -> Option<String> {
+            self.principal.clone()
+        }
+    }
+
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn gssapi_acceptor(
+            &mut self,
+            _user: &str,
+        ) -> Result<Option<Box<dyn GssapiAcceptor>>, Self::Error> {
+            Ok(Some(Box::new(Acceptor::default())))
+        }
+
+        async fn auth_gssapi_with_mic(
+            &mut self,
+            user: &str,
+            principal: &str,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(if user == "alice" && principal == "alice@EXAMPLE.COM" {
+                server::Auth::Accept
+            } else {
+                server::Auth::reject()
+            })
+        }
+    }
+
+    async fn authenticate(user: &str, key: u8) -> client::AuthResult {
+        let _ = env_logger::try_init();
+
+        let mut methods = MethodSet::empty();
+        methods.push(MethodKind::GssapiWithMic);
+        let mut session = connect_with(
+            server::Config {
+                methods,
+                ..server_config()
+            },
+            Server {},
+            Default::default(),
+            Client {},
+        )
+        .await;
+        session
+            .authenticate_gssapi_with_mic(user, Box::new(Initiator { key }))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_gssapi_with_mic() {
+        assert!(authenticate("alice", 7).await.success());
+    }
+
+    #[tokio::test]
+    async fn test_gssapi_rejected() {
+        // A wrong integrity code.
+        assert!(!authenticate("alice", 8).await.success());
+        // A principal not allowed for the user.
+        assert!(!authenticate("bob", 7).await.success());
+    }
+}
+
+mod exec {
+
+    use super::*;
+    use crate::client::{ExecOptions, ExitStatus};
+
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn exec_request(
+            &mut self,
+            channel: ChannelId,
+            data: &[u8],
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            session.channel_success(channel)?;
+            match data {
+                b"hang" => return Ok(()),
+                b"big" => session.data(channel, CryptoVec::from_slice(&[0; 1000]))?,
+                b"crash" => {
+                    let handle = session.handle();
+                    tokio::spawn(async move {
+                        handle
+                            .exit_signal(channel, Sig::SEGV, true, "Segmentation fault", "en")
+                            .await
+                            .unwrap();
+                        handle.eof(channel).await.unwrap();
+                        handle.close(channel).await.unwrap();
+                    });
+                    return Ok(());
+                }
+                _ => {
+                    // The exit status comes before the rest of the output.
+                    session.exit_status_request(channel, 3)?;
+                    session.data(channel, CryptoVec::from_slice(data))?;
+                    session.extended_data(channel, 1, CryptoVec::from_slice(b"warning"))?;
+                }
+            }
+            session.eof(channel)?;
+            session.close(channel)?;
+            Ok(())
+        }
+    }
+
+    async fn connect() -> client::Handle<Client> {
+        let _ = env_logger::try_init();
+
+        let mut session =
+            connect_with(server_config(), Server {}, Default::default(), Client {}).await;
+        authenticate(&mut session).await;
+        session
+    }
+
+    #[tokio::test]
+    async fn test_exec_collect() {
+        let session = connect().await;
+        let output = session.exec_collect("echo").await.unwrap();
+        assert_eq!(output.stdout, b"echo");
+        assert_eq!(output.stderr, b"warning");
+        assert!(matches!(output.exit, ExitStatus::Code(3)));
+    }
+
+    #[tokio::test]
+    async fn test_exec_collect_signal() {
+        let session = connect().await;
+        let output = session.exec_collect("crash").await.unwrap();
+        assert!(matches!(
+            output.exit,
+            ExitStatus::Signal {
+                signal_name: Sig::SEGV,
+                core_dumped: true,
+                ref error_message,
+            } if error_message == "Segmentation fault"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_exec_collect_limits() {
+        let session = connect().await;
+        let result = session
+            .exec_collect_with(
+                "hang",
+                ExecOptions {
+                    timeout: Some(std::time::Duration::from_millis(100)),
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Elapsed(_))));
+
+        let result = session
+            .exec_collect_with(
+                "big",
+                ExecOptions {
+                    max_output: Some(100),
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert!(matches!(result, Err(Error::OutputLimitExceeded)));
+
+        // The session is still usable.
+        let output = session.exec_collect("echo").await.unwrap();
+        assert_eq!(output.stdout, b"echo");
+    }
+}
+
+mod scp {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncWriteExt, BufReader};
+
+    use super::*;
+    use crate::client::scp::{receive, send, Options};
+
+    /// Runs `scp -t` and `scp -f` with the implementation of the client.
+    struct Server {
+        channels: Arc<Mutex<HashMap<ChannelId, Channel<server::Msg>>>>,
+    }
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            self.channels.lock().unwrap().insert(channel.id(), channel);
+            Ok(true)
+        }
+
+        async fn exec_request(
+            &mut self,
+            channel: ChannelId,
+            data: &[u8],
+            _session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            let command = String::from_utf8_lossy(data).into_owned();
+            let Some(channel) = self.channels.lock().unwrap().remove(&channel) else {
+                return Ok(());
+            };
+            let args: Vec<_> = command.split(' ').collect();
+            let options = Options {
+                recursive: args.contains(&"-r"),
+                preserve: args.contains(&"-p"),
+            };
+            let path = args.last().unwrap().trim_matches('\'').to_owned();
+            let sink = args.contains(&"-t");
+            tokio::spawn(async move {
+                let mut stream = BufReader::new(channel.into_stream());
+                if sink {
+                    receive(&mut stream, Path::new(&path), options)
+                        .await
+                        .unwrap();
+                } else {
+                    send(&mut stream, Path::new(&path), None, options)
+                        .await
+                        .unwrap();
+                }
+                stream.shutdown().await.unwrap();
+            });
+            Ok(())
+        }
+    }
+
+    async fn connect() -> client::Handle<Client> {
+        let _ = env_logger::try_init();
+
+        let server = Server {
+            channels: Default::default(),
+        };
+        let mut session =
+            connect_with(server_config(), server, Default::default(), Client {}).await;
+        authenticate(&mut session).await;
+        session
+    }
+
+    fn mtime(path: &Path) -> std::time::SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_scp_file() {
+        let session = connect().await;
+        let dir = tempfile::tempdir().unwrap();
+        let local = dir.path().join("local");
+        std::fs::write(&local, vec![7; 100_000]).unwrap();
+
+        let remote = dir.path().join("remote");
+        session
+            .scp()
+            .upload(&local, remote.to_str().unwrap(), Some(0o600))
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&remote).unwrap(), vec![7; 100_000]);
+
+        // Downloading into a directory keeps the remote name.
+        let into = dir.path().join("into");
+        std::fs::create_dir(&into).unwrap();
+        session
+            .scp()
+            .download(remote.to_str().unwrap(), &into)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(into.join("remote")).unwrap(),
+            vec![7; 100_000]
+        );
+
+        // Directories need `recursive`.
+        assert!(matches!(
+            session
+                .scp()
+                .upload(&into, remote.to_str().unwrap(), None)
+                .await,
+            Err(Error::Scp(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_scp_recursive() {
+        let session = connect().await;
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        std::fs::create_dir_all(tree.join("a/b")).unwrap();
+        std::fs::write(tree.join("top"), b"top").unwrap();
+        std::fs::write(tree.join("a/b/deep"), b"deep").unwrap();
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000_000);
+        std::fs::File::options()
+            .write(true)
+            .open(tree.join("top"))
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+
+        let uploaded = dir.path().join("uploaded");
+        std::fs::create_dir(&uploaded).unwrap();
+        session
+            .scp()
+            .recursive(true)
+            .preserve(true)
+            .upload(&tree, uploaded.to_str().unwrap(), None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(uploaded.join("tree/top")).unwrap(), b"top");
+        assert_eq!(
+            std::fs::read(uploaded.join("tree/a/b/deep")).unwrap(),
+            b"deep"
+        );
+        assert_eq!(mtime(&uploaded.join("tree/top")), time);
+
+        let downloaded = dir.path().join("downloaded");
+        session
+            .scp()
+            .recursive(true)
+            .preserve(true)
+            .download(uploaded.join("tree").to_str().unwrap(), &downloaded)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(downloaded.join("a/b/deep")).unwrap(), b"deep");
+        assert_eq!(mtime(&downloaded.join("top")), time);
+    }
+}
+
+mod env {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Server {
+        accepted: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn env_request(
+            &mut self,
+            channel: ChannelId,
+            variable_name: &str,
+            variable_value: &str,
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            self.accepted
+                .lock()
+                .unwrap()
+                .push((variable_name.to_owned(), variable_value.to_owned()));
+            session.channel_success(channel)?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_accept_env() {
+        let _ = env_logger::try_init();
+
+        let accepted = Arc::new(Mutex::new(Vec::new()));
+        let server = Server {
+            accepted: accepted.clone(),
+        };
+        let mut session = connect_with(
+            server::Config {
+                accept_env: Some(vec!["LANG".into(), "LC_*".into()]),
+                ..server_config()
+            },
+            server,
+            Default::default(),
+            Client {},
+        )
+        .await;
+        authenticate(&mut session).await;
+
+        let mut channel = session.channel_open_session().await.unwrap();
+        channel
+            .set_envs(
+                true,
+                [("LANG", "C"), ("LD_PRELOAD", "evil.so"), ("LC_ALL", "C")],
+            )
+            .await
+            .unwrap();
+        let mut replies = Vec::new();
+        while replies.len() < 3 {
+            match channel.wait().await.unwrap() {
+                ChannelMsg::Success => replies.push(true),
+                ChannelMsg::Failure => replies.push(false),
+                _ => {}
+            }
+        }
+        assert_eq!(replies, [true, false, true]);
+        assert_eq!(
+            *accepted.lock().unwrap(),
+            [
+                ("LANG".to_owned(), "C".to_owned()),
+                ("LC_ALL".to_owned(), "C".to_owned())
+            ]
+        );
+    }
+}
+
+mod terminal_requests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        WindowChange(WindowSize),
+        Signal(String),
+        Break(Duration),
+    }
+
+    struct Server {
+        events: Arc<Mutex<Vec<Event>>>,
+    }
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn window_change(
+            &mut self,
+            _channel: ChannelId,
+            size: WindowSize,
+            _session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            self.events.lock().unwrap().push(Event::WindowChange(size));
+            Ok(())
+        }
+
+        async fn signal(
+            &mut self,
+            _channel: ChannelId,
+            signal: Sig,
+            _session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            self.events
+                .lock()
+                .unwrap()
+                .push(Event::Signal(format!("{signal:?}")));
+            Ok(())
+        }
+
+        async fn break_request(
+            &mut self,
+            _channel: ChannelId,
+            length: Duration,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            self.events.lock().unwrap().push(Event::Break(length));
+            Ok(length <= Duration::from_secs(1))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_terminal_requests() {
+        let _ = env_logger::try_init();
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let server = Server {
+            events: events.clone(),
+        };
+        let mut session =
+            connect_with(server_config(), server, Default::default(), Client {}).await;
+        authenticate(&mut session).await;
+
+        let mut channel = session.channel_open_session().await.unwrap();
+        channel.window_change(120, 40, 960, 640).await.unwrap();
+        channel.signal(Sig::TERM).await.unwrap();
+        channel.send_break(true, 500).await.unwrap();
+        channel.send_break(true, 5000).await.unwrap();
+        let mut replies = Vec::new();
+        while replies.len() < 2 {
+            match channel.wait().await.unwrap() {
+                ChannelMsg::Success => replies.push(true),
+                ChannelMsg::Failure => replies.push(false),
+                _ => {}
+            }
+        }
+        assert_eq!(replies, [true, false]);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                Event::WindowChange(WindowSize {
+                    col_width: 120,
+                    row_height: 40,
+                    pix_width: 960,
+                    pix_height: 640,
+                }),
+                Event::Signal("TERM".into()),
+                Event::Break(Duration::from_millis(500)),
+                Event::Break(Duration::from_secs(5)),
+            ]
+        );
+    }
+}
+
+mod sftp_only {
+
+    use super::*;
+
+    /// Accepts everything, and leaves the restrictions to the session.
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn auth_succeeded(
+            &mut self,
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            session.set_sftp_only(true);
+            Ok(())
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn channel_open_direct_tcpip(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _host_to_connect: &str,
+            _port_to_connect: u32,
+            _originator_address: &str,
+            _originator_port: u32,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn tcpip_forward(
+            &mut self,
+            _address: &str,
+            _port: &mut u32,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn pty_request(
+            &mut self,
+            channel: ChannelId,
+            _term: &str,
+            _col_width: u32,
+            _row_height: u32,
+            _pix_width: u32,
+            _pix_height: u32,
+            _modes: &[(Pty, u32)],
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            session.channel_success(channel)?;
+            Ok(())
+        }
+
+        async fn exec_request(
+            &mut self,
+            channel: ChannelId,
+            _data: &[u8],
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            session.channel_success(channel)?;
+            Ok(())
+        }
+
+        async fn subsystem_request(
+            &mut self,
+            channel: ChannelId,
+            _name: &str,
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            session.channel_success(channel)?;
+            Ok(())
+        }
+    }
+
+    async fn reply(channel: &mut Channel<client::Msg>) -> bool {
+        loop {
+            match channel.wait().await.unwrap() {
+                ChannelMsg::Success => return true,
+                ChannelMsg::Failure => return false,
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sftp_only() {
+        let _ = env_logger::try_init();
+
+        let mut session =
+            connect_with(server_config(), Server {}, Default::default(), Client {}).await;
+        authenticate(&mut session).await;
+
+        let mut channel = session.channel_open_session().await.unwrap();
+        channel
+            .request_pty(true, "xterm", 80, 24, 0, 0, &[])
+            .await
+            .unwrap();
+        assert!(!reply(&mut channel).await);
+        channel.exec(true, "id").await.unwrap();
+        assert!(!reply(&mut channel).await);
+        channel.request_subsystem(true, "other").await.unwrap();
+        assert!(!reply(&mut channel).await);
+        channel.request_subsystem(true, "sftp").await.unwrap();
+        assert!(reply(&mut channel).await);
+
+        assert!(matches!(
+            session
+                .channel_open_direct_tcpip("example.com", 80, "127.0.0.1", 1234)
+                .await,
+            Err(Error::ChannelOpenFailure(
+                ChannelOpenFailure::AdministrativelyProhibited
+            ))
+        ));
+        assert!(session.tcpip_forward("127.0.0.1", 0).await.is_err());
+    }
+}
+
+#[cfg(unix)]
+mod mux {
+
+    use super::*;
+    use crate::client::mux::MuxClient;
+
+    /// Echoes the commands it is asked to run.
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn exec_request(
+            &mut self,
+            channel: ChannelId,
+            data: &[u8],
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            session.channel_success(channel)?;
+            session.data(channel, CryptoVec::from_slice(data))?;
+            session.exit_status_request(channel, 3)?;
+            session.eof(channel)?;
+            session.close(channel)?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_control_socket() {
+        let _ = env_logger::try_init();
+
+        let mut session =
+            connect_with(server_config(), Server {}, Default::default(), Client {}).await;
+        authenticate(&mut session).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mux.sock");
+        let master = session.serve_control_socket(&path).await.unwrap();
+        let client = MuxClient::new(&path);
+
+        for command in ["first", "second"] {
+            let mut channel = client.channel_open_session().await.unwrap();
+            channel.exec(true, command).await.unwrap();
+            let mut output = Vec::new();
+            let mut exit_status = None;
+            let mut success = false;
+            while let Some(msg) = channel.wait().await {
+                match msg {
+                    ChannelMsg::Success => success = true,
+                    ChannelMsg::Data { data } => output.extend_from_slice(&data),
+                    ChannelMsg::ExitStatus { exit_status: s } => exit_status = Some(s),
+                    ChannelMsg::Close => break,
+                    _ => {}
+                }
+            }
+            assert!(success);
+            assert_eq!(output, command.as_bytes());
+            assert_eq!(exit_status, Some(3));
+        }
+
+        // The server does not accept `direct-tcpip` channels.
+        assert!(matches!(
+            client
+                .channel_open_direct_tcpip("example.com", 80, "127.0.0.1", 1234)
+                .await,
+            Err(Error::ChannelOpenFailure(_))
+        ));
+
+        drop(master);
+        assert!(!path.exists());
+        assert!(client.channel_open_session().await.is_err());
+    }
+}
+
+mod keepalive {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::mpsc::UnboundedSender;
+
+    use super::*;
+
+    struct Client {
+        disconnected: UnboundedSender<String>,
+    }
+
+    impl client::Handler for Client {
+        type Error = crate::Error;
+
+        async fn check_server_key(
+            &mut self,
+            _server_public_key: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+
+        async fn disconnected(
+            &mut self,
+            reason: client::DisconnectReason<Self::Error>,
+        ) -> Result<(), Self::Error> {
+            let reason = match reason {
+                client::DisconnectReason::Error(e) => format!("{e:?}"),
+                client::DisconnectReason::ReceivedDisconnect(_) => "received".into(),
+            };
+            self.disconnected.send(reason).unwrap();
+            Ok(())
+        }
+    }
+
+    struct Server {}
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            _session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_keepalives() {
+        let _ = env_logger::try_init();
+
+        let (server, _) = serve(server_config(), Server {}).await;
+
+        // A proxy that stops relaying the server's packets once `frozen`
+        // is set, so that keepalives go unanswered.
+        let frozen = Arc::new(AtomicBool::new(false));
+        let proxy = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = proxy.local_addr().unwrap();
+        let frozen_ = frozen.clone();
+        tokio::spawn(async move {
+            let (client, _) = proxy.accept().await.unwrap();
+            let (mut client_read, mut client_write) = client.into_split();
+            let (mut server_read, mut server_write) = server.into_split();
+            tokio::spawn(async move { tokio::io::copy(&mut client_read, &mut server_write).await });
+            let mut buf = [0; 4096];
+            loop {
+                let n = server_read.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                if !frozen_.load(Ordering::SeqCst) {
+                    client_write.write_all(buf.get(..n).unwrap()).await.unwrap();
+                }
+            }
+        });
+
+        let config = Arc::new(client::Config {
+            keepalive_interval: Some(Duration::from_millis(50)),
+            keepalive_max: 2,
+            ..Default::default()
+        });
+        let (disconnected, mut disconnected_recv) = tokio::sync::mpsc::unbounded_channel();
+        let mut session = client::connect(config, addr, Client { disconnected })
+            .await
+            .unwrap();
+        authenticate(&mut session).await;
+
+        // Keepalives are answered while the server is reachable.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(!session.is_closed());
+
+        frozen.store(true, Ordering::SeqCst);
+        let reason = tokio::time::timeout(Duration::from_secs(5), disconnected_recv.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reason, "KeepaliveTimeout");
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let _ = env_logger::try_init();
+
+        let connect = |action: server::IdleAction| async move {
+            let (socket, _) = serve(
+                server::Config {
+                    idle_timeout: Some(server::IdleTimeout {
+                        duration: Duration::from_millis(300),
+                        action,
+                    }),
+                    ..server_config()
+                },
+                Server {},
+            )
+            .await;
+
+            // The keepalives do not keep the session from being idle.
+            let config = Arc::new(client::Config {
+                keepalive_interval: Some(Duration::from_millis(50)),
+                ..Default::default()
+            });
+            let (disconnected, disconnected_recv) = tokio::sync::mpsc::unbounded_channel();
+            let mut session = client::connect_stream(config, socket, Client { disconnected })
+                .await
+                .unwrap();
+            authenticate(&mut session).await;
+            let channel = session.channel_open_session().await.unwrap();
+            (session, channel, disconnected_recv)
+        };
+
+        let (_session, mut channel, _) = connect(server::IdleAction::Warn("idle\r\n".into())).await;
+        // Data resets the timer.
+        for _ in 0..4 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            channel.data(&b"x"[..]).await.unwrap();
+        }
+        let msg = tokio::time::timeout(Duration::from_secs(5), channel.wait())
+            .await
+            .unwrap();
+        assert!(
+            matches!(msg, Some(ChannelMsg::ExtendedData { ext: 1, ref data }) if &data[..] == b"idle\r\n")
+        );
+
+        let (_session, mut channel, _) = connect(server::IdleAction::CloseChannels).await;
+        let msg = tokio::time::timeout(Duration::from_secs(5), channel.wait())
+            .await
+            .unwrap();
+        // The channel ends once the server closes it.
+        assert!(msg.is_none());
+
+        let (session, _channel, mut disconnected_recv) =
+            connect(server::IdleAction::Disconnect).await;
+        let reason = tokio::time::timeout(Duration::from_secs(5), disconnected_recv.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reason, "received");
+        assert!(session.is_closed());
+    }
+}
+
+mod transport {
+    use std::borrow::Cow;
+    use std::sync::Arc;
+
+    use tokio::io::DuplexStream;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    use super::server::Server as _;
+    use super::*;
+
+    #[derive(Clone)]
+    struct Server {}
+
+    impl server::Server for Server {
+        type Handler = Self;
+
+        fn new_client(&mut self, peer_addr: Option<std::net::SocketAddr>) -> Self {
+            assert!(peer_addr.is_none());
+            self.clone()
+        }
+    }
+
+    impl server::Handler for Server {
+        type Error = crate::Error;
+
+        async fn auth_publickey(
+            &mut self,
+            _: &str,
+            _: &crate::keys::ssh_key::PublicKey,
+        ) -> Result<server::Auth, Self::Error> {
+            Ok(server::Auth::Accept)
+        }
+
+        async fn channel_open_session(
+            &mut self,
+            _channel: Channel<server::Msg>,
+            session: &mut server::Session,
+        ) -> Result<bool, Self::Error> {
+            let info = session.connection_info().unwrap();
+            assert!(info.remote_sshid.starts_with("SSH-2.0-"));
+            Ok(true)
+        }
+
+        async fn exec_request(
+            &mut self,
+            channel: ChannelId,
+            _data: &[u8],
+            session: &mut server::Session,
+        ) -> Result<(), Self::Error> {
+            // Send the session identifier back.
+            let id = CryptoVec::from_slice(session.session_id().unwrap());
+            session.channel_success(channel)?;
+            session.data(channel, id)?;
+            session.close(channel)?;
+            Ok(())
+        }
+    }
+
+    /// Hands out in-memory pipes.
+    struct PipeListener(UnboundedReceiver<DuplexStream>);
+
+    impl crate::transport::Listener for PipeListener {
+        type Stream = DuplexStream;
+
+        async fn accept(
+            &mut self,
+        ) -> std::io::Result<(DuplexStream, Option<std::net::SocketAddr>)> {
+            match self.0.recv().await {
+                Some(stream) => Ok((stream, None)),
+                None => Err(std::io::ErrorKind::NotConnected.into()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_transport() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server_config());
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            Server {}
+                .run_on_listener(config, PipeListener(pipes_recv))
+                .await
+        });
+
+        for _ in 0..2 {
+            let (client_end, server_end) = tokio::io::duplex(4096);
+            pipes.send(server_end).unwrap();
+            let config = Arc::new(client::Config::default());
+            let mut session = client::connect_stream(config, client_end, Client {})
+                .await
+                .unwrap();
+            authenticate(&mut session).await;
+            session.channel_open_session().await.unwrap();
+            session
+                .disconnect(Disconnect::ByApplication, "", "")
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server_config());
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        let shutdown = server::Shutdown::new();
+        let server = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                Server {}
+                    .run_on_listener_with_shutdown(config, PipeListener(pipes_recv), shutdown)
+                    .await
+            }
+        });
+        let (client_end, server_end) = tokio::io::duplex(4096);
+        pipes.send(server_end).unwrap();
+        let session =
+            client::connect_stream(Arc::new(client::Config::default()), client_end, Client {})
+                .await
+                .unwrap();
+
+        shutdown.shutdown(
+            Some("maintenance".into()),
+            std::time::Duration::from_secs(60),
+        );
+        assert!(shutdown.is_shutting_down());
+        // The client is disconnected, so the server ends before the
+        // deadline.
+        match session.closed().await {
+            CloseReason::Remote(info) => assert_eq!(info.message, "maintenance"),
+            reason => panic!("unexpected {reason:?}"),
+        }
+        server.await.unwrap().unwrap();
+        // The listener was dropped.
+        assert!(pipes.send(tokio::io::duplex(4096).1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_deadline() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server_config());
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        let shutdown = server::Shutdown::new();
+        let server = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                Server {}
+                    .run_on_listener_with_shutdown(config, PipeListener(pipes_recv), shutdown)
+                    .await
+            }
+        });
+        let (client_end, server_end) = tokio::io::duplex(4096);
+        pipes.send(server_end).unwrap();
+        let session =
+            client::connect_stream(Arc::new(client::Config::default()), client_end, Client {})
+                .await
+                .unwrap();
+
+        // Without a reason, the session is left to end by itself, and
+        // closed at the deadline.
+        shutdown.shutdown(None, std::time::Duration::from_millis(100));
+        server.await.unwrap().unwrap();
+        assert!(!matches!(session.closed().await, CloseReason::Remote(_)));
+    }
+
+    #[tokio::test]
+    async fn test_max_startups() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server::Config {
+            max_startups: Some(server::MaxStartups::new(1)),
+            ..server_config()
+        });
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            Server {}
+                .run_on_listener(config, PipeListener(pipes_recv))
+                .await
+        });
+        let connect = || {
+            let (client_end, server_end) = tokio::io::duplex(4096);
+            pipes.send(server_end).unwrap();
+            client::connect_stream(Arc::new(client::Config::default()), client_end, Client {})
+        };
+
+        let mut session = connect().await.unwrap();
+        // The first connection has not authenticated yet.
+        assert!(connect().await.is_err());
+
+        authenticate(&mut session).await;
+        connect().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_preauth_limits() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server::Config {
+            auth_rejection_time: std::time::Duration::ZERO,
+            preauth_limits: Some(server::PreAuthLimits {
+                max_packet_len: 4096,
+                max_bytes: 16 * 1024,
+                ..Default::default()
+            }),
+            ..server_config()
+        });
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            Server {}
+                .run_on_listener(config, PipeListener(pipes_recv))
+                .await
+        });
+        let connect = || {
+            let (client_end, server_end) = tokio::io::duplex(4096);
+            pipes.send(server_end).unwrap();
+            client::connect_stream(Arc::new(client::Config::default()), client_end, Client {})
+        };
+
+        let closed = |session: client::Handle<Client>| async move {
+            tokio::time::timeout(std::time::Duration::from_secs(5), session.closed())
+                .await
+                .unwrap();
+        };
+
+        // Too large a packet.
+        let mut session = connect().await.unwrap();
+        let result = session
+            .authenticate_password("user", "x".repeat(8192))
+            .await;
+        assert!(!result.is_ok_and(|r| r.success()));
+        closed(session).await;
+
+        // Too much data.
+        let mut session = connect().await.unwrap();
+        for _ in 0..8 {
+            let result = session
+                .authenticate_password("user", "x".repeat(2048))
+                .await;
+            assert!(!result.is_ok_and(|r| r.success()));
+        }
+        closed(session).await;
+
+        // The limits are lifted once the client authenticates.
+        let mut session = connect().await.unwrap();
+        authenticate(&mut session).await;
+        for _ in 0..10 {
+            let mut channel = session.channel_open_session().await.unwrap();
+            channel.exec(true, vec![b'x'; 8192]).await.unwrap();
+            while let Some(msg) = channel.wait().await {
+                if let ChannelMsg::Close = msg {
+                    break;
+                }
+            }
+        }
+        assert!(!session.is_closed());
+    }
+
+    #[derive(Default)]
+    struct Gauge(std::sync::atomic::AtomicUsize);
+
+    impl server::metrics::ServerMetrics for Gauge {
+        fn active_connections(&self, count: usize) {
+            self.0.store(count, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let _ = env_logger::try_init();
+
+        let run = |overflow: server::Overflow| {
+            let gauge = Arc::new(Gauge::default());
+            let config = Arc::new(server::Config {
+                max_connections: Some(server::MaxConnections { limit: 1, overflow }),
+                metrics: Some(gauge.clone()),
+                ..server_config()
+            });
+            let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+            tokio::spawn(async move {
+                Server {}
+                    .run_on_listener(config, PipeListener(pipes_recv))
+                    .await
+            });
+            let connect = move || {
+                let (client_end, server_end) = tokio::io::duplex(4096);
+                pipes.send(server_end).unwrap();
+                client::connect_stream(Arc::new(client::Config::default()), client_end, Client {})
+            };
+            (connect, gauge)
+        };
+        let count = |gauge: &Gauge| gauge.0.load(std::sync::atomic::Ordering::SeqCst);
+
+        let (connect, gauge) = run(server::Overflow::Refuse);
+        let session = connect().await.unwrap();
+        assert_eq!(count(&gauge), 1);
+        assert!(connect().await.is_err());
+        session
+            .disconnect(Disconnect::ByApplication, "", "")
+            .await
+            .unwrap();
+        session.closed().await;
+        let mut connected = false;
+        for _ in 0..50 {
+            if connect().await.is_ok() {
+                connected = true;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert!(connected);
+
+        let (connect, gauge) = run(server::Overflow::Queue);
+        let session = connect().await.unwrap();
+        let mut queued = tokio::spawn(connect());
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(!queued.is_finished());
+        assert_eq!(count(&gauge), 1);
+        session
+            .disconnect(Disconnect::ByApplication, "", "")
+            .await
+            .unwrap();
+        let _queued = tokio::time::timeout(std::time::Duration::from_secs(5), &mut queued)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(count(&gauge), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connection_info() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server::Config {
+            server_id: SshId::Standard("SSH-2.0-test_server".into()),
+            ..server_config()
+        });
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            Server {}
+                .run_on_listener(config, PipeListener(pipes_recv))
+                .await
+        });
+
+        let config = Arc::new(client::Config {
+            preferred: Preferred {
+                kex: Cow::Borrowed(&[kex::CURVE25519]),
+                cipher: Cow::Borrowed(&[cipher::AES_256_GCM]),
+                compression: Cow::Borrowed(&[compression::NONE]),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let (client_end, server_end) = tokio::io::duplex(4096);
+        pipes.send(server_end).unwrap();
+        let mut session = client::connect_stream(config, client_end, Client {})
+            .await
+            .unwrap();
+        let info = session.connection_info().await.unwrap();
+        assert_eq!(info.kex, kex::CURVE25519);
+        assert_eq!(info.host_key, ssh_key::Algorithm::Ed25519);
+        assert_eq!(info.cipher, cipher::AES_256_GCM);
+        assert_eq!(info.client_compression, compression::NONE);
+        assert_eq!(info.server_compression, compression::NONE);
+        assert_eq!(info.remote_sshid, "SSH-2.0-test_server");
+
+        authenticate(&mut session).await;
+        session.channel_open_session().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_kex_algorithms() {
+        let _ = env_logger::try_init();
+
+        let mut preferred = Preferred::default();
+        preferred.kex = Cow::Owned(
+            [kex::MLKEM1024NISTP384_SHA384]
+                .into_iter()
+                .chain(preferred.kex.iter().copied())
+                .collect(),
+        );
+        let config = Arc::new(server::Config {
+            preferred,
+            ..server_config()
+        });
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            Server {}
+                .run_on_listener(config, PipeListener(pipes_recv))
+                .await
+        });
+
+        // mlkem768x25519-sha256 is preferred by default.
+        for (kexes, expected) in [
+            (None, kex::MLKEM768X25519_SHA256),
+            (
+                Some(&[kex::MLKEM1024NISTP384_SHA384][..]),
+                kex::MLKEM1024NISTP384_SHA384,
+            ),
+            (Some(&[kex::CURVE448][..]), kex::CURVE448),
+            (Some(&[kex::DH_GEX_SHA256][..]), kex::DH_GEX_SHA256),
+        ] {
+            let mut config = client::Config::default();
+            if let Some(kexes) = kexes {
+                config.preferred.kex = Cow::Borrowed(kexes);
+            }
+            let (client_end, server_end) = tokio::io::duplex(4096);
+            pipes.send(server_end).unwrap();
+            let mut session = client::connect_stream(Arc::new(config), client_end, Client {})
+                .await
+                .unwrap();
+            let info = session.connection_info().await.unwrap();
+            assert_eq!(info.kex, expected);
+
+            authenticate(&mut session).await;
+            session.channel_open_session().await.unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server_config());
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ssh.sock");
+        tokio::spawn({
+            let path = path.clone();
+            async move { Server {}.run_on_unix_socket(config, path).await }
+        });
+
+        let stream = loop {
+            match tokio::net::UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+            }
+        };
+        let mut session = client::connect_stream(Default::default(), stream, Client {})
+            .await
+            .unwrap();
+        authenticate(&mut session).await;
+        session.channel_open_session().await.unwrap();
+    }
+
+    /// Offers only `aes128-ctr`, as to a legacy client.
+    struct Legacy {}
+
+    impl server::Handler for Legacy {
+        type Error = crate::Error;
+
+        async fn preferred_algorithms(
+            &mut self,
+            config: &server::Config,
+        ) -> Result<Option<Preferred>, Self::Error> {
+            Ok(Some(Preferred {
+                cipher: Cow::Borrowed(&[cipher::AES_128_CTR]),
+                ..config.preferred.clone()
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preferred_algorithms() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server_config());
+        let (client_end, server_end) = tokio::io::duplex(4096);
+        let server = server::run_stream(config.clone(), server_end, Legacy {});
+        let client = client::connect_stream(Default::default(), client_end, Client {});
+        let (server, client) = tokio::join!(server, client);
+        let _server = server.unwrap();
+        let info = client.unwrap().connection_info().await.unwrap();
+        assert_eq!(info.cipher, cipher::AES_128_CTR);
+
+        // The other connections keep the configured algorithms.
+        let (client_end, server_end) = tokio::io::duplex(4096);
+        let server = server::run_stream(config, server_end, Server {});
+        let client = client::connect_stream(Default::default(), client_end, Client {});
+        let (server, client) = tokio::join!(server, client);
+        let _server = server.unwrap();
+        let info = client.unwrap().connection_info().await.unwrap();
+        assert_ne!(info.cipher, cipher::AES_128_CTR);
+    }
+
+    #[tokio::test]
+    async fn test_session_id() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server_config());
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            Server {}
+                .run_on_listener(config, PipeListener(pipes_recv))
+                .await
+        });
+
+        let (client_end, server_end) = tokio::io::duplex(4096);
+        pipes.send(server_end).unwrap();
+        let config = Arc::new(client::Config::default());
+        let mut session = client::connect_stream(config, client_end, Client {})
+            .await
+            .unwrap();
+        let id = session.session_id().await.unwrap();
+        assert!(!id.is_empty());
+
+        authenticate(&mut session).await;
+        session.rekey_soon().await.unwrap();
+
+        // Both sides agree, and rekeys don't change it.
+        let output = session.exec_collect("").await.unwrap();
+        assert_eq!(output.stdout, id);
+        assert_eq!(session.session_id().await.unwrap(), id);
+    }
+
+    /// An authenticated client, over a pipe, with both sides limited to
+    /// the `preferred` algorithms.
+    async fn connect_transport(
+        config: client::Config,
+        preferred: Preferred,
+    ) -> client::Handle<Client> {
+        let server_config = Arc::new(server::Config {
+            preferred: preferred.clone(),
+            ..server_config()
+        });
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            Server {}
+                .run_on_listener(server_config, PipeListener(pipes_recv))
+                .await
+        });
+
+        let (client_end, server_end) = tokio::io::duplex(4096);
+        pipes.send(server_end).unwrap();
+        let config = Arc::new(client::Config {
+            preferred,
+            ..config
+        });
+        let mut session = client::connect_stream(config, client_end, Client {})
+            .await
+            .unwrap();
+        authenticate(&mut session).await;
+        session
+    }
+
+    #[tokio::test]
+    async fn test_rekey() {
+        let _ = env_logger::try_init();
+
+        let session = connect_transport(client::Config::default(), Preferred::default()).await;
+        let id = session.session_id().await.unwrap();
+        session.rekey().await.unwrap();
+        session.rekey().await.unwrap();
+        let output = session.exec_collect("").await.unwrap();
+        assert_eq!(output.stdout, id);
+    }
+
+    #[tokio::test]
+    async fn test_rekey_limits() {
+        let _ = env_logger::try_init();
+
+        // Rekey every few hundred bytes, or every few packets.
+        for config in [
+            client::Config {
+                limits: Limits {
+                    rekey_write_limit: 256,
+                    rekey_read_limit: 256,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            client::Config {
+                rekey_packet_limit: 4,
+                ..Default::default()
+            },
+        ] {
+            let session = connect_transport(config, Preferred::default()).await;
+            let id = session.session_id().await.unwrap();
+            for _ in 0..5 {
+                let output = session.exec_collect("").await.unwrap();
+                assert_eq!(output.stdout, id);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cipher_rekey_bytes() {
+        assert_eq!(cipher::CHACHA20_POLY1305.rekey_bytes(), 1 << 30);
+        assert!(cipher::AES_256_GCM.rekey_bytes() > 1 << 30);
+    }
+
+    #[cfg(feature = "legacy-ciphers")]
+    #[tokio::test]
+    async fn test_legacy_ciphers() {
+        let _ = env_logger::try_init();
+        // 64-bit blocks.
+        assert_eq!(cipher::TRIPLE_DES_CBC.rekey_bytes(), 1 << 30);
+
+        let preferred = Preferred {
+            cipher: Cow::Borrowed(&[cipher::TRIPLE_DES_CBC]),
+            mac: Cow::Borrowed(&[mac::HMAC_SHA256_ETM]),
+            ..Default::default()
+        };
+        let session = connect_transport(client::Config::default(), preferred).await;
+        let info = session.connection_info().await.unwrap();
+        assert_eq!(info.cipher, cipher::TRIPLE_DES_CBC);
+        session.channel_open_session().await.unwrap();
+    }
+
+    /// A server session and an authenticated client, over a pipe.
+    async fn connect_running() -> (server::RunningSession<Server>, client::Handle<Client>) {
+        let config = Arc::new(server_config());
+        let (client_end, server_end) = tokio::io::duplex(4096);
+        let (running, session) = tokio::join!(
+            server::run_stream(config, server_end, Server {}),
+            client::connect_stream(Arc::new(client::Config::default()), client_end, Client {}),
+        );
+        let mut session = session.unwrap();
+        authenticate(&mut session).await;
+        (running.unwrap(), session)
+    }
+
+    #[tokio::test]
+    async fn test_closed_by_server() {
+        let _ = env_logger::try_init();
+
+        let (running, session) = connect_running().await;
+        assert!(!session.is_closed());
+        let server = running.handle();
+        server
+            .disconnect(Disconnect::TooManyConnections, "bye".into(), "".into())
+            .await
+            .unwrap();
+        assert_eq!(
+            session.closed().await,
+            CloseReason::Remote(RemoteDisconnectInfo {
+                reason_code: Disconnect::TooManyConnections,
+                message: "bye".into(),
+                lang_tag: "".into(),
+            })
+        );
+        assert!(session.is_closed());
+        assert_eq!(server.closed().await, CloseReason::Local);
+    }
+
+    #[tokio::test]
+    async fn test_closed_by_client() {
+        let _ = env_logger::try_init();
+
+        let (running, session) = connect_running().await;
+        let server = running.handle();
+        session
+            .disconnect(Disconnect::ByApplication, "done", "")
+            .await
+            .unwrap();
+        assert_eq!(session.closed().await, CloseReason::Local);
+        match server.closed().await {
+            CloseReason::Remote(info) => {
+                assert_eq!(info.reason_code, Disconnect::ByApplication);
+                assert_eq!(info.message, "done");
+            }
+            reason => panic!("unexpected close reason {reason:?}"),
+        }
+        assert!(server.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_rekey_strict_kex() {
+        let _ = env_logger::try_init();
+
+        let config = Arc::new(server_config());
+        let (pipes, pipes_recv) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            Server {}
+                .run_on_listener(config, PipeListener(pipes_recv))
+                .await
+        });
+
+        // With and without strict kex: sequence numbers are reset at every
+        // NEWKEYS in the first case only.
+        for strict in [true, false] {
+            let mut preferred = Preferred::default();
+            if !strict {
+                preferred.kex = preferred
+                    .kex
+                    .iter()
+                    .filter(|k| **k != kex::EXTENSION_OPENSSH_STRICT_KEX_AS_CLIENT)
+                    .cloned()
+                    .collect();
+            }
+            let config = Arc::new(client::Config {
+                preferred,
+                ..Default::default()
+            });
+            let (client_end, server_end) = tokio::io::duplex(4096);
+            pipes.send(server_end).unwrap();
+            let mut session = client::connect_stream(config, client_end, Client {})
+                .await
+                .unwrap();
+            authenticate(&mut session).await;
+            for _ in 0..2 {
+                session.rekey_soon().await.unwrap();
+                session.channel_open_session().await.unwrap();
+            }
+            session
+                .disconnect(Disconnect::ByApplication, "", "")
+                .await
+                .unwrap();
+        }
+    }
+}
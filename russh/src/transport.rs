@@ -0,0 +1,74 @@
+//! The byte streams that sessions run over.
+//!
+//! The protocol only needs an ordered, reliable byte stream: anything that
+//! implements [`Transport`] can carry a session, through
+//! [`client::connect_stream`](crate::client::connect_stream) and
+//! [`server::run_stream`](crate::server::run_stream). This includes serial
+//! ports, vsock or QUIC streams and in-memory pipes such as
+//! [`tokio::io::duplex`]. Nothing is assumed about socket semantics: there
+//! is no peer address, and failing to shut the stream down at the end of
+//! a session is not an error.
+//!
+//! Servers accepting connections from something else than a TCP socket
+//! can implement [`Listener`] and use
+//! [`Server::run_on_listener`](crate::server::Server::run_on_listener).
+//! Unix domain sockets are supported directly, with
+//! [`Server::run_on_unix_socket`](crate::server::Server::run_on_unix_socket).
+
+use std::future::Future;
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A byte stream that can carry an SSH session. Implemented for every
+/// `AsyncRead + AsyncWrite + Unpin + Send + 'static` type.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Transport for T {}
+
+/// A source of incoming connections for a server.
+///
+/// Note: this is an async trait. The trait functions return `impl Future`,
+/// and you can simply define them as `async fn` instead.
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+pub trait Listener: Send {
+    type Stream: Transport;
+
+    /// Wait for the next connection, and return it with the address of
+    /// the peer if the transport has one. An error stops the server.
+    fn accept(
+        &mut self,
+    ) -> impl Future<Output = std::io::Result<(Self::Stream, Option<SocketAddr>)>> + Send;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+impl Listener for tokio::net::TcpListener {
+    type Stream = tokio::net::TcpStream;
+
+    #[allow(clippy::manual_async_fn)]
+    fn accept(
+        &mut self,
+    ) -> impl Future<Output = std::io::Result<(Self::Stream, Option<SocketAddr>)>> + Send {
+        async move {
+            let (stream, addr) = tokio::net::TcpListener::accept(self).await?;
+            Ok((stream, Some(addr)))
+        }
+    }
+}
+
+#[cfg(unix)]
+#[cfg_attr(feature = "async-trait", async_trait::async_trait)]
+impl Listener for tokio::net::UnixListener {
+    type Stream = tokio::net::UnixStream;
+
+    #[allow(clippy::manual_async_fn)]
+    fn accept(
+        &mut self,
+    ) -> impl Future<Output = std::io::Result<(Self::Stream, Option<SocketAddr>)>> + Send {
+        async move {
+            let (stream, _) = tokio::net::UnixListener::accept(self).await?;
+            Ok((stream, None))
+        }
+    }
+}
@@ -0,0 +1,515 @@
+//! SSH over WebSocket ([RFC 6455](https://tools.ietf.org/html/rfc6455)),
+//! to reach servers behind HTTP-only ingress such as reverse proxies,
+//! browser gateways or Cloudflare tunnels. Requires the `websocket`
+//! feature.
+//!
+//! [`connect`] and [`accept`] perform the HTTP upgrade on any byte
+//! stream (a [`TcpStream`](tokio::net::TcpStream), or a TLS stream for
+//! `wss://`), and return a [`WebSocketStream`] that carries the SSH
+//! byte stream in binary messages:
+//!
+//! ```no_run
+//! # async fn run<H: russh::client::Handler + 'static>(handler: H) -> Result<(), Box<dyn std::error::Error>> {
+//! let tcp = tokio::net::TcpStream::connect("gateway.example.com:80").await?;
+//! let ws = russh::websocket::connect(tcp, "gateway.example.com", "/ssh").await?;
+//! let config = std::sync::Arc::new(russh::client::Config::default());
+//! let session = russh::client::connect_stream(config, ws, handler).await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Message boundaries carry no meaning: SSH packets can be split across
+//! messages, and messages can hold several packets.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use data_encoding::BASE64;
+use log::debug;
+use rand::RngCore;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Maximal size of the HTTP request or response head.
+const MAX_HEAD: usize = 16384;
+/// Maximal payload size of a received frame.
+const MAX_FRAME: u64 = 1 << 24;
+/// Maximal payload size of a sent frame.
+const MAX_WRITE_FRAME: usize = 1 << 16;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+const CLOSE_NORMAL: u16 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Client,
+    Server,
+}
+
+/// A byte stream carried in the binary messages of a WebSocket
+/// connection. Pings are answered, and a close frame is sent on
+/// shutdown.
+///
+/// Writes are buffered until the next write or flush.
+#[derive(Debug)]
+pub struct WebSocketStream<S> {
+    inner: S,
+    role: Role,
+    path: Option<String>,
+    /// Bytes received from `inner` that do not form a complete frame yet.
+    read_buf: Vec<u8>,
+    /// Payload received and not yet read.
+    data: Vec<u8>,
+    data_pos: usize,
+    /// Frames waiting to be written to `inner`.
+    write_buf: Vec<u8>,
+    read_closed: bool,
+    close_sent: bool,
+}
+
+/// Open a WebSocket connection on `stream`, by requesting `path` from
+/// `host`.
+pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    host: &str,
+    path: &str,
+) -> io::Result<WebSocketStream<S>> {
+    let mut key = [0; 16];
+    rand::thread_rng().fill_bytes(&mut key);
+    let key = BASE64.encode(&key);
+    let request = format!(
+        "GET {path} HTTP/1.1\r\n\
+         Host: {host}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {key}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let (head, rest) = read_head(&mut stream).await?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default();
+    if status.split(' ').nth(1) != Some("101") {
+        return Err(handshake_error(format!("unexpected response: {status}")));
+    }
+    let headers = parse_headers(lines);
+    if !has_token(&headers, "upgrade", "websocket") {
+        return Err(handshake_error("missing Upgrade header"));
+    }
+    if header(&headers, "sec-websocket-accept") != Some(accept_key(&key).as_str()) {
+        return Err(handshake_error("invalid Sec-WebSocket-Accept"));
+    }
+    Ok(WebSocketStream::new(stream, Role::Client, None, rest))
+}
+
+/// Accept a WebSocket connection on `stream`, whatever the requested
+/// path is. The path is available from [`WebSocketStream::path`].
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+) -> io::Result<WebSocketStream<S>> {
+    let (head, rest) = read_head(&mut stream).await?;
+    match parse_request(&head) {
+        Ok((path, key)) => {
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key)
+            );
+            stream.write_all(response.as_bytes()).await?;
+            stream.flush().await?;
+            Ok(WebSocketStream::new(stream, Role::Server, Some(path), rest))
+        }
+        Err(e) => {
+            stream
+                .write_all(
+                    b"HTTP/1.1 400 Bad Request\r\n\
+                      Sec-WebSocket-Version: 13\r\n\
+                      Content-Length: 0\r\n\r\n",
+                )
+                .await?;
+            stream.shutdown().await?;
+            Err(e)
+        }
+    }
+}
+
+fn parse_request(head: &str) -> io::Result<(String, String)> {
+    let mut lines = head.split("\r\n");
+    let request = lines.next().unwrap_or_default();
+    let mut request = request.split(' ');
+    let (Some("GET"), Some(path)) = (request.next(), request.next()) else {
+        return Err(handshake_error("not a GET request"));
+    };
+    let headers = parse_headers(lines);
+    if !has_token(&headers, "upgrade", "websocket") || !has_token(&headers, "connection", "upgrade")
+    {
+        return Err(handshake_error("not a WebSocket upgrade"));
+    }
+    if header(&headers, "sec-websocket-version") != Some("13") {
+        return Err(handshake_error("unsupported WebSocket version"));
+    }
+    let Some(key) = header(&headers, "sec-websocket-key") else {
+        return Err(handshake_error("missing Sec-WebSocket-Key"));
+    };
+    Ok((path.to_string(), key.to_string()))
+}
+
+fn handshake_error<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(GUID.as_bytes());
+    BASE64.encode(&hasher.finalize())
+}
+
+/// Read the HTTP head, returning it without the final empty line, along
+/// with the bytes read after it.
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<(String, Vec<u8>)> {
+    let mut buf = Vec::new();
+    loop {
+        if let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(end + 4);
+            buf.truncate(end);
+            let head = String::from_utf8(buf).map_err(|_| handshake_error("invalid HTTP head"))?;
+            return Ok((head, rest));
+        }
+        if buf.len() > MAX_HEAD {
+            return Err(handshake_error("HTTP head too long"));
+        }
+        let mut chunk = [0; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend(chunk.iter().take(n));
+    }
+}
+
+/// Header names are lowercased.
+fn parse_headers<'a, I: Iterator<Item = &'a str>>(lines: I) -> Vec<(String, &'a str)> {
+    lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim()))
+        .collect()
+}
+
+fn header<'a>(headers: &[(String, &'a str)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(n, _)| n == name).map(|(_, v)| *v)
+}
+
+fn has_token(headers: &[(String, &str)], name: &str, token: &str) -> bool {
+    headers
+        .iter()
+        .filter(|(n, _)| n == name)
+        .flat_map(|(_, v)| v.split(','))
+        .any(|t| t.trim().eq_ignore_ascii_case(token))
+}
+
+impl<S> WebSocketStream<S> {
+    fn new(inner: S, role: Role, path: Option<String>, read_buf: Vec<u8>) -> Self {
+        WebSocketStream {
+            inner,
+            role,
+            path,
+            read_buf,
+            data: Vec::new(),
+            data_pos: 0,
+            write_buf: Vec::new(),
+            read_closed: false,
+            close_sent: false,
+        }
+    }
+
+    /// The path requested by the client, on the server side.
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn push_frame(&mut self, opcode: u8, payload: &[u8]) {
+        let out = &mut self.write_buf;
+        out.push(0x80 | opcode);
+        let mask_bit = if self.role == Role::Client { 0x80 } else { 0 };
+        let len = payload.len();
+        if len < 126 {
+            out.push(mask_bit | len as u8);
+        } else if let Ok(len) = u16::try_from(len) {
+            out.push(mask_bit | 126);
+            out.extend(len.to_be_bytes());
+        } else {
+            out.push(mask_bit | 127);
+            out.extend((len as u64).to_be_bytes());
+        }
+        if self.role == Role::Client {
+            let mut mask = [0; 4];
+            rand::thread_rng().fill_bytes(&mut mask);
+            out.extend(mask);
+            out.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+        } else {
+            out.extend(payload);
+        }
+    }
+
+    /// Parse the frame at the start of `read_buf`, if it is complete.
+    fn next_frame(&mut self) -> io::Result<Option<(u8, Vec<u8>)>> {
+        let buf = &self.read_buf;
+        let (Some(&b0), Some(&b1)) = (buf.first(), buf.get(1)) else {
+            return Ok(None);
+        };
+        let masked = b1 & 0x80 != 0;
+        if masked != (self.role == Role::Server) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid WebSocket frame masking",
+            ));
+        }
+        let (len, mut pos) = match b1 & 0x7f {
+            126 => match buf.get(2..4).map(<[u8; 2]>::try_from) {
+                Some(Ok(len)) => (u16::from_be_bytes(len) as u64, 4),
+                _ => return Ok(None),
+            },
+            127 => match buf.get(2..10).map(<[u8; 8]>::try_from) {
+                Some(Ok(len)) => (u64::from_be_bytes(len), 10),
+                _ => return Ok(None),
+            },
+            len => (len as u64, 2),
+        };
+        if len > MAX_FRAME {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "WebSocket frame too large",
+            ));
+        }
+        let mask = if masked {
+            let Some(mask) = buf.get(pos..pos + 4) else {
+                return Ok(None);
+            };
+            pos += 4;
+            mask.to_vec()
+        } else {
+            vec![0]
+        };
+        let len = len as usize;
+        let Some(payload) = buf.get(pos..pos + len) else {
+            return Ok(None);
+        };
+        let payload = payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(b, m)| b ^ m)
+            .collect();
+        self.read_buf.drain(..pos + len);
+        Ok(Some((b0 & 0x0f, payload)))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> WebSocketStream<S> {
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_buf.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_buf.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Send the answers to pings and close frames from the reading side,
+    /// so that they get out even if nothing else is written. Write errors
+    /// are left for the next write.
+    fn poll_control_frames(&mut self, cx: &mut Context<'_>) {
+        if let Poll::Ready(Err(e)) = self.poll_write_buf(cx) {
+            debug!("could not answer WebSocket control frame: {e:?}");
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(data) = this.data.get(this.data_pos..).filter(|d| !d.is_empty()) {
+                let n = data.len().min(buf.remaining());
+                buf.put_slice(data.get(..n).unwrap_or_default());
+                this.data_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            if this.read_closed {
+                return Poll::Ready(Ok(()));
+            }
+            if let Some((opcode, payload)) = this.next_frame()? {
+                match opcode {
+                    OP_BINARY | OP_CONTINUATION => {
+                        this.data = payload;
+                        this.data_pos = 0;
+                    }
+                    OP_PING => {
+                        this.push_frame(OP_PONG, &payload);
+                        this.poll_control_frames(cx);
+                    }
+                    OP_PONG => {}
+                    OP_CLOSE => {
+                        this.read_closed = true;
+                        if !this.close_sent {
+                            this.close_sent = true;
+                            let status = payload.get(..2).unwrap_or_default().to_vec();
+                            this.push_frame(OP_CLOSE, &status);
+                            this.poll_control_frames(cx);
+                        }
+                    }
+                    _ => {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "unexpected WebSocket frame",
+                        )))
+                    }
+                }
+                continue;
+            }
+            this.poll_control_frames(cx);
+            let mut chunk = [0; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                if this.read_buf.is_empty() {
+                    this.read_closed = true;
+                    continue;
+                }
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            this.read_buf.extend_from_slice(chunk.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for WebSocketStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.close_sent {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(this.poll_write_buf(cx))?;
+        let n = buf.len().min(MAX_WRITE_FRAME);
+        this.push_frame(OP_BINARY, buf.get(..n).unwrap_or_default());
+        if let Poll::Ready(Err(e)) = this.poll_write_buf(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.close_sent {
+            this.close_sent = true;
+            this.push_frame(OP_CLOSE, &CLOSE_NORMAL.to_be_bytes());
+        }
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #![allow(clippy::unwrap_used)]
+    use super::*;
+
+    #[test]
+    fn accept_key_example() {
+        // RFC 6455, section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let (client, server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(async move {
+            let mut ws = accept(server).await.unwrap();
+            assert_eq!(ws.path(), Some("/ssh"));
+            let mut buf = vec![0; 100_000];
+            ws.read_exact(&mut buf).await.unwrap();
+            ws.write_all(&buf).await.unwrap();
+            ws.shutdown().await.unwrap();
+        });
+        let mut ws = connect(client, "example.com", "/ssh").await.unwrap();
+        let data: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+        ws.write_all(&data).await.unwrap();
+        ws.flush().await.unwrap();
+        let mut buf = Vec::new();
+        ws.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn answers_pings() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut ws = WebSocketStream::new(client, Role::Client, None, Vec::new());
+        let mut server = WebSocketStream::new(server, Role::Server, None, Vec::new());
+        // Unmasked frames, as sent by a server.
+        server
+            .inner
+            .write_all(&[0x89, 2, b'h', b'i', 0x82, 1, 7])
+            .await
+            .unwrap();
+        let mut buf = [0];
+        ws.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [7]);
+        let mut frame = [0; 8];
+        server.inner.read_exact(&mut frame).await.unwrap();
+        server.read_buf.extend(frame);
+        assert_eq!(
+            server.next_frame().unwrap(),
+            Some((OP_PONG, b"hi".to_vec()))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        assert!(parse_request("GET / HTTP/1.1\r\nHost: a").is_err());
+        let (path, key) = parse_request(
+            "GET /x HTTP/1.1\r\nHost: a\r\nUpgrade: WebSocket\r\nConnection: keep-alive, Upgrade\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: abc",
+        )
+        .unwrap();
+        assert_eq!((path.as_str(), key.as_str()), ("/x", "abc"));
+    }
+}